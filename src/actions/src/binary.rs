@@ -1,16 +1,74 @@
-use super::{error_result, get_stream_error, ActionOptions, ActionResult};
-use config::workflow::BinaryAttributes;
-use log::debug;
+use super::{
+    error_result, get_stream_error, output,
+    sandbox::{self, Sandboxed},
+    stream::{self, OutputStream, Stdout},
+    usage, ActionOptions, ActionResult, ErrorCategory,
+};
+use config::workflow::{platform_keys, BinaryAttributes, BinaryPath};
+use log::{debug, error};
 use process_wrap::tokio::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::fs::File;
-use tokio::process::Command;
+use tokio::io::AsyncWrite;
 use tokio::time::timeout;
+use utils::fail_injection::{self, SPAWN};
 use utils::process::{print_stream, read_stream};
 pub struct Binary {}
 
+/// Resolves the binary for the platform. A relative path is searched in the custom_files directory.
+/// If the path is a directory, the file named after the platform is used (e.g. winpmem/windows_x86_64.exe)
+pub fn resolve_binary_path(
+    path: &BinaryPath,
+    custom_files_dir: &Path,
+    os: &str,
+    arch: &str,
+) -> Result<PathBuf, String> {
+    let keys = platform_keys(os, arch);
+    let path = match path.select(os, arch) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            return Err(format!(
+                "No binary for this platform in {}, tried: {}",
+                path,
+                keys.join(", ")
+            ))
+        }
+    };
+    let path = match path.is_absolute() {
+        true => path,
+        false => custom_files_dir.join(path),
+    };
+    if !path.is_dir() {
+        return match path.exists() {
+            true => Ok(path),
+            false => Err(format!("File not found: {:?}", path)),
+        };
+    }
+
+    let entries: Vec<PathBuf> = std::fs::read_dir(&path)
+        .map_err(|e| format!("Error reading directory {:?}: {}", path, e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|entry| entry.is_file())
+        .collect();
+    keys.iter()
+        .find_map(|key| {
+            entries.iter().find(|entry| {
+                entry
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_lowercase() == *key)
+                    .unwrap_or(false)
+            })
+        })
+        .cloned()
+        .ok_or(format!(
+            "No binary for this platform in {:?}, tried: {}",
+            path,
+            keys.join(", ")
+        ))
+}
+
 impl Binary {
     pub async fn run(
         bin: BinaryAttributes,
@@ -18,20 +76,19 @@ impl Binary {
         out_file: Option<PathBuf>,
         custom_files_dir: PathBuf,
     ) -> ActionResult {
-        // Case distinction:
-        // 1. If bin.path is relative, search in the custom_files directory
-        // 2. If bin.path is absolute, directly use it
-
-        // first, convert to PathBuf
-        let bin_path = PathBuf::from(&bin.path);
-        let bin_path = match bin_path.is_absolute() {
-            true => bin_path,
-            false => custom_files_dir.join(bin_path),
+        let bin_path = match resolve_binary_path(
+            &bin.path,
+            &custom_files_dir,
+            &system::get_os(),
+            &system::get_arch(),
+        ) {
+            Ok(bin_path) => bin_path,
+            Err(e) => return error_result!(e).with_category(ErrorCategory::NotFound),
         };
-
-        // check if file exists
-        if !bin_path.exists() {
-            return error_result!(format!("File not found: {:?}", bin_path));
+        if let Some(integrity) = &options.custom_files_integrity {
+            if let Err(e) = integrity.check(&custom_files_dir, &bin_path) {
+                return error_result!(e, options.start_time);
+            }
         }
 
         if bin.args.is_empty() {
@@ -45,22 +102,58 @@ impl Binary {
         }
 
         //TODO: print checksum of binary or version
-        let mut cmd = Command::new(&bin_path);
-        cmd.args(&bin.args);
+        let mut cmd = sandbox::command(&bin_path, &bin.args, &bin.sandbox, options.run_as.as_ref());
 
         let output_to_console = !bin.log_to_file && !options.parallel;
+        let log_file = out_file.clone().filter(|_| bin.log_to_file);
 
-        if out_file.is_some() && bin.log_to_file {
+        // stdout is passed through the collector to stream it to local subscribers
+        let output_stream = match bin.stream_to.is_empty() {
+            true => None,
+            false => match OutputStream::bind(&bin.stream_to) {
+                Ok(output_stream) => Some(output_stream),
+                Err(e) => return error_result!(e, options.start_time),
+            },
+        };
+        let mut stdout_writer: Option<Box<dyn AsyncWrite + Unpin + Send>> = None;
+
+        // the output is encrypted before it touches the disk
+        let mut encrypted_output = None;
+        if let (true, Some(out_file), Some(key)) = (bin.log_to_file, &out_file, &options.output_key)
+        {
+            match output::create(out_file, key) {
+                Ok(writer) => encrypted_output = Some(writer),
+                Err(e) => return error_result!(e, options.start_time),
+            }
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+        } else if out_file.is_some() && bin.log_to_file {
             let out_file = out_file.unwrap();
             let std_out_file = File::create(&out_file).await.unwrap();
-            cmd.stdout(std_out_file.into_std().await);
+            match output_stream.is_some() {
+                true => {
+                    cmd.stdout(Stdio::piped());
+                    stdout_writer = Some(Box::new(std_out_file));
+                }
+                false => {
+                    cmd.stdout(std_out_file.into_std().await);
+                }
+            }
             let std_err_file = File::create(&out_file).await.unwrap();
             cmd.stderr(std_err_file.into_std().await);
         } else if output_to_console {
             cmd.stdout(Stdio::piped());
             cmd.stderr(Stdio::piped());
         } else {
-            cmd.stdout(Stdio::inherit());
+            match output_stream.is_some() {
+                true => {
+                    cmd.stdout(Stdio::piped());
+                    stdout_writer = Some(Box::new(tokio::io::stdout()));
+                }
+                false => {
+                    cmd.stdout(Stdio::inherit());
+                }
+            }
             cmd.stderr(Stdio::inherit());
         }
 
@@ -70,19 +163,42 @@ impl Binary {
         child.wrap(JobObject);
         #[cfg(unix)]
         child.wrap(ProcessGroup::leader());
+        if bin.sandbox.enabled {
+            debug!("Running in a sandbox: {:?}", bin.sandbox);
+            child.wrap(Sandboxed::new(bin.sandbox.clone()));
+        }
 
-        let mut child = match child.spawn() {
+        let mut child = match fail_injection::check(SPAWN).and_then(|_| child.spawn()) {
             Ok(child) => child,
-            Err(e) => return error_result!(e.to_string()),
+            Err(e) => {
+                return error_result!(e.to_string()).with_category(ErrorCategory::from_io_error(&e))
+            }
+        };
+
+        let mut stdout = child
+            .inner_mut()
+            .stdout
+            .take()
+            .map(|stdout| Box::new(stdout) as Stdout);
+        if let Some(output_stream) = output_stream {
+            stdout = output_stream.tee(stdout);
+        }
+
+        let output_task = match (encrypted_output, stdout_writer) {
+            (Some(writer), _) => {
+                let stderr = child.inner_mut().stderr.take();
+                Some(output::spawn_writer(writer, stdout.take(), stderr))
+            }
+            (None, Some(writer)) => Some(stream::spawn_copy(stdout.take(), writer)),
+            (None, None) => None,
         };
 
         let stderr_task: Option<tokio::task::JoinHandle<String>> = match output_to_console {
             true => {
                 // run command in parallel and print output to console
-                let stdout = child.inner_mut().stdout.take();
                 let stderr = child.inner_mut().stderr.take();
 
-                tokio::spawn(print_stream(stdout));
+                tokio::spawn(print_stream(stdout.take()));
                 Some(tokio::spawn(read_stream(stderr, true)))
             }
             false => None,
@@ -91,22 +207,33 @@ impl Binary {
         let output = if options.timeout > 0 {
             timeout(
                 Duration::from_secs(options.timeout as u64),
-                Box::into_pin(child.wait()),
+                usage::wait(&mut child),
             )
             .await
         } else {
-            Ok(Box::into_pin(child.wait()).await)
+            Ok(usage::wait(&mut child).await)
         };
 
-        let output = match output {
-            Ok(Ok(output)) => output,
-            Ok(Err(e)) => return error_result!(e.to_string(), options.start_time),
+        let (output, resource_usage) = match output {
+            Ok((Ok(output), resource_usage)) => (output, resource_usage),
+            Ok((Err(e), _)) => {
+                return error_result!(e.to_string(), options.start_time)
+                    .with_category(ErrorCategory::from_io_error(&e))
+            }
             Err(_) => {
                 Box::into_pin(child.kill()).await.unwrap();
-                return error_result!("Process timed out", options.start_time);
+                if let Err(e) = output::wait(output_task).await {
+                    error!("{}", e);
+                }
+                return error_result!("Process timed out", options.start_time)
+                    .with_category(ErrorCategory::Timeout);
             }
         };
 
+        if let Err(e) = output::wait(output_task).await {
+            return error_result!(e, options.start_time);
+        }
+
         let mut action_result = ActionResult::default();
         action_result.execution_time = options.start_time.elapsed();
         action_result.parallel = options.parallel;
@@ -114,10 +241,13 @@ impl Binary {
         action_result.success = output.success();
         action_result.exit_code = output.code();
         if !output.success() {
+            action_result.error_category = Some(ErrorCategory::NonzeroExit);
             action_result.error_message = get_stream_error!(stderr_task, "Process failed");
         }
 
-        return action_result;
+        action_result
+            .with_resource_usage(resource_usage)
+            .with_artifacts(log_file)
     }
 }
 
@@ -146,9 +276,11 @@ mod tests {
         cleanup.add(out_file.clone());
 
         let bin = BinaryAttributes {
-            path: bin_path,
+            path: BinaryPath::Single(bin_path),
             args: vec![],
             log_to_file: true,
+            stream_to: String::new(),
+            sandbox: Default::default(),
         };
 
         let system_vars = SystemVariables::new();
@@ -191,9 +323,11 @@ mod tests {
         }
 
         let bin = BinaryAttributes {
-            path: binary.to_str().unwrap().to_string(),
+            path: BinaryPath::Single(binary.to_str().unwrap().to_string()),
             args: vec![],
             log_to_file: false,
+            stream_to: String::new(),
+            sandbox: Default::default(),
         };
 
         let system_vars = SystemVariables::new();
@@ -210,4 +344,45 @@ mod tests {
         // check if error message is not empty
         assert_eq!(result.error_message.is_none(), false);
     }
+
+    #[test]
+    fn test_resolve_binary_path() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_resolve_binary_path");
+        let tool_dir = dir.join("winpmem");
+        std::fs::create_dir(&tool_dir).unwrap();
+        for name in ["windows_x86_64.exe", "windows.exe", "linux_aarch64"] {
+            std::fs::write(tool_dir.join(name), "").unwrap();
+        }
+
+        // directory convention
+        let path = BinaryPath::Single("winpmem".to_string());
+        assert_eq!(
+            resolve_binary_path(&path, &dir, "windows", "amd64").unwrap(),
+            tool_dir.join("windows_x86_64.exe")
+        );
+        assert_eq!(
+            resolve_binary_path(&path, &dir, "windows", "x86").unwrap(),
+            tool_dir.join("windows.exe")
+        );
+        assert!(resolve_binary_path(&path, &dir, "macos", "aarch64")
+            .unwrap_err()
+            .contains("macos_aarch64, macos, default"));
+
+        // map per platform
+        let path = BinaryPath::PerPlatform(std::collections::BTreeMap::from([
+            (
+                "linux_arm64".to_string(),
+                "winpmem/linux_aarch64".to_string(),
+            ),
+            ("default".to_string(), "missing".to_string()),
+        ]));
+        assert_eq!(
+            resolve_binary_path(&path, &dir, "linux", "aarch64").unwrap(),
+            tool_dir.join("linux_aarch64")
+        );
+        assert!(resolve_binary_path(&path, &dir, "linux", "x86_64")
+            .unwrap_err()
+            .contains("File not found"));
+    }
 }
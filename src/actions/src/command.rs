@@ -1,13 +1,19 @@
-use super::{error_result, get_stream_error, ActionOptions, ActionResult};
+use super::{
+    error_result, get_stream_error, output,
+    sandbox::{self, Sandboxed},
+    stream::{self, OutputStream, Stdout},
+    usage, ActionOptions, ActionResult, ErrorCategory,
+};
 use config::workflow::CommandAttributes;
-use log::debug;
+use log::{debug, error};
 use process_wrap::tokio::*;
 use std::path::PathBuf;
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::fs::File;
-use tokio::process::Command;
+use tokio::io::AsyncWrite;
 use tokio::time::timeout;
+use utils::fail_injection::{self, SPAWN};
 use utils::process::{print_stream, read_stream};
 
 pub struct ShellCommand {}
@@ -28,8 +34,12 @@ impl ShellCommand {
             );
         };
 
-        let mut cmd = Command::new(&command.cmd);
-        cmd.args(&command.args);
+        let mut cmd = sandbox::command(
+            &command.cmd,
+            &command.args,
+            &command.sandbox,
+            options.run_as.as_ref(),
+        );
 
         // check if cwd is set (not empty String)
         if !command.cwd.is_empty() {
@@ -39,24 +49,61 @@ impl ShellCommand {
             if !cwd.exists() {
                 return error_result!(
                     format!("Specified cwd does not exist: {:?}", command.cwd).to_string()
-                );
+                )
+                .with_category(ErrorCategory::NotFound);
             }
             cmd.current_dir(cwd);
         }
 
         let output_to_console = !command.log_to_file && !options.parallel;
 
-        if out_file.is_some() {
+        // stdout is passed through the collector to stream it to local subscribers
+        let output_stream = match command.stream_to.is_empty() {
+            true => None,
+            false => match OutputStream::bind(&command.stream_to) {
+                Ok(output_stream) => Some(output_stream),
+                Err(e) => return error_result!(e, options.start_time),
+            },
+        };
+        let mut stdout_writer: Option<Box<dyn AsyncWrite + Unpin + Send>> = None;
+
+        // the output is encrypted before it touches the disk
+        let mut encrypted_output = None;
+        let log_file = out_file.clone();
+        if let (Some(out_file), Some(key)) = (&out_file, &options.output_key) {
+            match output::create(out_file, key) {
+                Ok(writer) => encrypted_output = Some(writer),
+                Err(e) => return error_result!(e, options.start_time),
+            }
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+        } else if out_file.is_some() {
             let out_file = out_file.unwrap();
             let std_out_file = File::create(&out_file).await.unwrap();
-            cmd.stdout(std_out_file.into_std().await);
+            match output_stream.is_some() {
+                true => {
+                    cmd.stdout(Stdio::piped());
+                    stdout_writer = Some(Box::new(std_out_file));
+                }
+                false => {
+                    cmd.stdout(std_out_file.into_std().await);
+                }
+            }
             let std_err_file = File::create(&out_file).await.unwrap();
             cmd.stderr(std_err_file.into_std().await);
         } else if output_to_console {
             cmd.stdout(Stdio::piped());
             cmd.stderr(Stdio::piped());
         } else {
-            cmd.stdout(Stdio::inherit());
+            match output_stream.is_some() {
+                true => {
+                    cmd.stdout(Stdio::piped());
+                    stdout_writer = Some(Box::new(tokio::io::stdout()));
+                }
+                false => {
+                    cmd.stdout(Stdio::inherit());
+                }
+            }
             cmd.stderr(Stdio::inherit());
         }
 
@@ -68,19 +115,42 @@ impl ShellCommand {
         child.wrap(JobObject);
         #[cfg(unix)]
         child.wrap(ProcessGroup::leader());
+        if command.sandbox.enabled {
+            debug!("Running in a sandbox: {:?}", command.sandbox);
+            child.wrap(Sandboxed::new(command.sandbox.clone()));
+        }
 
-        let mut child = match child.spawn() {
+        let mut child = match fail_injection::check(SPAWN).and_then(|_| child.spawn()) {
             Ok(child) => child,
-            Err(e) => return error_result!(e.to_string()),
+            Err(e) => {
+                return error_result!(e.to_string()).with_category(ErrorCategory::from_io_error(&e))
+            }
+        };
+
+        let mut stdout = child
+            .inner_mut()
+            .stdout
+            .take()
+            .map(|stdout| Box::new(stdout) as Stdout);
+        if let Some(output_stream) = output_stream {
+            stdout = output_stream.tee(stdout);
+        }
+
+        let output_task = match (encrypted_output, stdout_writer) {
+            (Some(writer), _) => {
+                let stderr = child.inner_mut().stderr.take();
+                Some(output::spawn_writer(writer, stdout.take(), stderr))
+            }
+            (None, Some(writer)) => Some(stream::spawn_copy(stdout.take(), writer)),
+            (None, None) => None,
         };
 
         let stderr_task: Option<tokio::task::JoinHandle<String>> = match output_to_console {
             true => {
                 // run command in parallel and print output to console
-                let stdout = child.inner_mut().stdout.take();
                 let stderr = child.inner_mut().stderr.take();
 
-                tokio::spawn(print_stream(stdout));
+                tokio::spawn(print_stream(stdout.take()));
                 Some(tokio::spawn(read_stream(stderr, true)))
             }
             false => None,
@@ -89,22 +159,33 @@ impl ShellCommand {
         let output = if options.timeout > 0 {
             timeout(
                 Duration::from_secs(options.timeout as u64),
-                Box::into_pin(child.wait()),
+                usage::wait(&mut child),
             )
             .await
         } else {
-            Ok(Box::into_pin(child.wait()).await)
+            Ok(usage::wait(&mut child).await)
         };
 
-        let output = match output {
-            Ok(Ok(output)) => output,
-            Ok(Err(e)) => return error_result!(e.to_string(), options.start_time),
+        let (output, resource_usage) = match output {
+            Ok((Ok(output), resource_usage)) => (output, resource_usage),
+            Ok((Err(e), _)) => {
+                return error_result!(e.to_string(), options.start_time)
+                    .with_category(ErrorCategory::from_io_error(&e))
+            }
             Err(_) => {
                 Box::into_pin(child.kill()).await.unwrap();
-                return error_result!("Command timed out", options.start_time);
+                if let Err(e) = output::wait(output_task).await {
+                    error!("{}", e);
+                }
+                return error_result!("Command timed out", options.start_time)
+                    .with_category(ErrorCategory::Timeout);
             }
         };
 
+        if let Err(e) = output::wait(output_task).await {
+            return error_result!(e, options.start_time);
+        }
+
         let mut action_result = ActionResult::default();
         action_result.execution_time = options.start_time.elapsed();
         action_result.parallel = options.parallel;
@@ -112,10 +193,13 @@ impl ShellCommand {
         action_result.success = output.success();
         action_result.exit_code = output.code();
         if !output.success() {
+            action_result.error_category = Some(ErrorCategory::NonzeroExit);
             action_result.error_message = get_stream_error!(stderr_task, "Command failed");
         }
 
-        return action_result;
+        action_result
+            .with_resource_usage(resource_usage)
+            .with_artifacts(log_file)
     }
 }
 
@@ -136,6 +220,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["/c".to_string(), "echo".to_string(), "Hello".to_string()],
                 log_to_file: false,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         } else {
             CommandAttributes {
@@ -143,6 +229,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["Hello".to_string()],
                 log_to_file: false,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         };
 
@@ -168,6 +256,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["/c".to_string(), "echo".to_string(), "Hello".to_string()],
                 log_to_file: true,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         } else {
             CommandAttributes {
@@ -175,6 +265,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["Hello".to_string()],
                 log_to_file: true,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         };
 
@@ -198,6 +290,46 @@ mod tests {
         assert_eq!(content.contains("Hello"), true);
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command_with_stream() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::UnixStream;
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_run_command_with_stream");
+        let socket = dir.join("output.sock");
+        let out_file = dir.join("output.txt");
+        let command = CommandAttributes {
+            cmd: "sh".to_string(),
+            cwd: "".to_string(),
+            // give the subscriber time to connect
+            args: vec!["-c".to_string(), "sleep 1; echo Hello".to_string()],
+            log_to_file: true,
+            stream_to: socket.to_string_lossy().to_string(),
+            sandbox: Default::default(),
+        };
+
+        let subscribe = async {
+            while !socket.exists() {
+                tokio::time::sleep(time::Duration::from_millis(10)).await;
+            }
+            let mut subscriber = UnixStream::connect(&socket).await.unwrap();
+            let mut streamed = String::new();
+            subscriber.read_to_string(&mut streamed).await.unwrap();
+            streamed
+        };
+        let (result, streamed) = tokio::join!(
+            ShellCommand::run(command, ActionOptions::default(), Some(out_file.clone())),
+            subscribe
+        );
+        assert!(result.success, "Command failed: {:?}", result.error_message);
+        assert_eq!(streamed, "Hello\n");
+        // still logged by the collector
+        assert_eq!(std::fs::read_to_string(out_file).unwrap(), "Hello\n");
+        assert!(!socket.exists());
+    }
+
     #[tokio::test]
     async fn test_run_command_with_error() {
         let command = if cfg!(target_os = "windows") {
@@ -206,6 +338,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["/ccc".to_string(), "echo".to_string(), "Hello".to_string()],
                 log_to_file: false,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         } else {
             CommandAttributes {
@@ -213,6 +347,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["Hello".to_string()],
                 log_to_file: false,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         };
 
@@ -222,6 +358,11 @@ mod tests {
         assert_eq!(result.success, false);
         assert_ne!(result.exit_code, Some(0));
         assert_eq!(result.error_message.is_some(), true);
+        let category = match cfg!(target_os = "windows") {
+            true => ErrorCategory::NonzeroExit,
+            false => ErrorCategory::NotFound,
+        };
+        assert_eq!(result.error_category, Some(category));
     }
 
     #[tokio::test]
@@ -232,12 +373,18 @@ mod tests {
             cwd: invalid_cwd.to_string(),
             args: vec!["Hello".to_string()],
             log_to_file: false,
+            stream_to: String::new(),
+            sandbox: Default::default(),
         };
 
         let options = ActionOptions {
             timeout: 0,
             parallel: false,
             start_time: time::Instant::now(),
+            run_as: None,
+            output_key: None,
+            custom_files_integrity: None,
+            path_cache: None,
         };
 
         let result = ShellCommand::run(command, options, None).await;
@@ -246,6 +393,7 @@ mod tests {
         // assert that error message contains the cwd
         let error_message = result.error_message.unwrap();
         assert_eq!(error_message.contains(invalid_cwd), true);
+        assert_eq!(result.error_category, Some(ErrorCategory::NotFound));
     }
 
     #[tokio::test]
@@ -262,6 +410,8 @@ mod tests {
                     "127.0.0.1".to_string(),
                 ],
                 log_to_file: false,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         } else {
             CommandAttributes {
@@ -269,6 +419,8 @@ mod tests {
                 cwd: "".to_string(),
                 args: vec!["-c".to_string(), "sleep 10".to_string()],
                 log_to_file: false,
+                stream_to: String::new(),
+                sandbox: Default::default(),
             }
         };
 
@@ -276,6 +428,10 @@ mod tests {
             timeout: 1,
             parallel: false,
             start_time: time::Instant::now(),
+            run_as: None,
+            output_key: None,
+            custom_files_integrity: None,
+            path_cache: None,
         };
 
         let result = ShellCommand::run(command, options, None).await;
@@ -283,5 +439,41 @@ mod tests {
         assert_eq!(result.success, false, "Expected a timeout",);
         assert_ne!(result.exit_code, Some(0));
         assert_eq!(result.error_message, Some("Command timed out".to_string()));
+        assert_eq!(result.error_category, Some(ErrorCategory::Timeout));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command_as_user() {
+        // dropping privileges requires root
+        if !privileges::is_elevated() {
+            return;
+        }
+        let run_as = privileges::resolve_user("nobody").unwrap();
+        assert_ne!(run_as.uid, 0);
+
+        let command = CommandAttributes {
+            cmd: "sh".to_string(),
+            cwd: "".to_string(),
+            args: vec![
+                "-c".to_string(),
+                format!(
+                    "test \"$(id -u)\" = {} && test \"$(id -G)\" = '{}' && test \"$HOME\" = '{}' && test \"$USER\" = nobody",
+                    run_as.uid,
+                    run_as.groups.iter().map(|group| group.to_string()).collect::<Vec<_>>().join(" "),
+                    run_as.home.display()
+                ),
+            ],
+            log_to_file: false,
+            stream_to: String::new(),
+            sandbox: Default::default(),
+        };
+        let options = ActionOptions {
+            run_as: Some(run_as),
+            ..Default::default()
+        };
+
+        let result = ShellCommand::run(command, options, None).await;
+        assert!(result.success, "{:?}", result.error_message);
     }
 }
@@ -0,0 +1,379 @@
+use config::workflow::DefenderAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use super::{error_result, ActionOptions, ActionResult};
+
+// split up, so the toolkit itself is not detected
+const EICAR: [&str; 2] = [
+    r"X5O!P%@AP[4\PZX54(P^)7CC)7}$",
+    r"EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*",
+];
+const EICAR_FILE: &str = "ir_toolkit_eicar.txt";
+// time for the real-time protection to react to the test file
+const EICAR_WAIT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Serialize, Default)]
+pub struct DefenderStatus {
+    // false if Defender is not installed or could not be queried
+    pub available: bool,
+    pub antivirus_enabled: bool,
+    pub real_time_protection: bool,
+    // only readable by administrators
+    pub exclusions_readable: bool,
+    pub exclusion_paths: Vec<String>,
+    // set if the EICAR test file was written
+    pub eicar_quarantined: Option<bool>,
+    pub added_exclusions: Vec<String>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Whether the path is covered by one of the exclusions (case-insensitive, as on Windows)
+pub fn is_excluded(path: &Path, exclusions: &[String]) -> bool {
+    let path = path.to_string_lossy().to_lowercase().replace('/', "\\");
+    exclusions.iter().any(|exclusion| {
+        let exclusion = exclusion
+            .to_lowercase()
+            .replace('/', "\\")
+            .trim_end_matches('\\')
+            .to_string();
+        !exclusion.is_empty()
+            && (path == exclusion || path.starts_with(&format!("{}\\", exclusion)))
+    })
+}
+
+/// Warnings for binaries run from the directory, if real-time protection may quarantine them
+pub fn check_directory(status: &DefenderStatus, dir: &Path) -> Vec<String> {
+    let mut warnings = vec![];
+    if !status.available || !status.antivirus_enabled || !status.real_time_protection {
+        return warnings;
+    }
+    if !status.exclusions_readable {
+        warnings.push(format!(
+            "Windows Defender real-time protection is enabled and the exclusions can't be read without elevation. Binaries in {:?} may be quarantined",
+            dir.display()
+        ));
+    } else if !is_excluded(dir, &status.exclusion_paths) {
+        warnings.push(format!(
+            "Windows Defender real-time protection is enabled and {:?} is not excluded. Binaries may be quarantined, consider a defender action with exclude: true",
+            dir.display()
+        ));
+    }
+    if status.eicar_quarantined == Some(true) {
+        warnings.push(format!(
+            "The EICAR test file in {:?} was quarantined",
+            dir.display()
+        ));
+    }
+    warnings
+}
+
+/// Writes the EICAR test file and checks if it is still readable after a few seconds
+fn eicar_quarantined(dir: &Path) -> Result<bool, String> {
+    let path = dir.join(EICAR_FILE);
+    fs::write(&path, EICAR.concat())
+        .map_err(|e| format!("Error writing EICAR test file {:?}: {}", path.display(), e))?;
+    thread::sleep(EICAR_WAIT);
+    let quarantined =
+        !matches!(fs::read(&path), Ok(content) if content == EICAR.concat().as_bytes());
+    if path.exists() {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Error removing EICAR test file {:?}: {}", path.display(), e);
+        }
+    }
+    Ok(quarantined)
+}
+
+/// Logs a warning if binaries in the custom_files directory may be quarantined
+pub fn warn_custom_files(custom_files_dir: &Path) {
+    for warning in check_directory(&query_status(), custom_files_dir) {
+        warn!("{}", warning);
+    }
+}
+
+/// Removes the exclusions added by defender actions
+pub fn remove_exclusions(paths: &[String]) {
+    for path in paths {
+        match set_exclusion(path, false) {
+            Ok(_) => info!("Removed Windows Defender exclusion: {}", path),
+            Err(e) => error!(
+                "Error removing Windows Defender exclusion {:?}: {}. Remove it manually",
+                path, e
+            ),
+        }
+    }
+}
+
+pub struct Defender {}
+
+impl Defender {
+    pub fn run(
+        attributes: DefenderAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        custom_files_dir: &Path,
+        exclusions: &mut Vec<String>,
+    ) -> ActionResult {
+        if !cfg!(target_os = "windows") {
+            return error_result!(
+                "Windows Defender is only available on Windows",
+                options.start_time
+            );
+        }
+
+        let mut status = query_status();
+        if !status.available {
+            let message = status
+                .errors
+                .first()
+                .cloned()
+                .unwrap_or("Windows Defender is not available".to_string());
+            return error_result!(message, options.start_time);
+        }
+
+        if attributes.eicar_test {
+            match eicar_quarantined(custom_files_dir) {
+                Ok(quarantined) => status.eicar_quarantined = Some(quarantined),
+                Err(e) => status.errors.push(e),
+            }
+        }
+
+        let paths: Vec<String> = match attributes.paths.is_empty() {
+            true => vec![custom_files_dir.to_string_lossy().to_string()],
+            false => attributes.paths.clone(),
+        };
+
+        if attributes.exclude {
+            if !privileges::is_elevated() {
+                status
+                    .errors
+                    .push("Adding Windows Defender exclusions requires elevation".to_string());
+            } else {
+                for path in &paths {
+                    if status.exclusions_readable
+                        && is_excluded(Path::new(path), &status.exclusion_paths)
+                    {
+                        debug!("Already excluded from Windows Defender: {}", path);
+                        continue;
+                    }
+                    match set_exclusion(path, true) {
+                        Ok(_) => {
+                            info!("Added temporary Windows Defender exclusion: {}", path);
+                            status.added_exclusions.push(path.clone());
+                            exclusions.push(path.clone());
+                        }
+                        Err(e) => status.errors.push(format!(
+                            "Error adding Windows Defender exclusion {:?}: {}",
+                            path, e
+                        )),
+                    }
+                }
+            }
+        }
+
+        // the added exclusions are not visible in the status queried before
+        if status.added_exclusions.is_empty() {
+            for path in &paths {
+                status
+                    .warnings
+                    .extend(check_directory(&status, Path::new(path)));
+            }
+        }
+        for warning in &status.warnings {
+            warn!("{}", warning);
+        }
+        for error in &status.errors {
+            error!("{}", error);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        if let Err(e) = serde_json::to_writer_pretty(BufWriter::new(file), &status) {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+
+        let result = ActionResult {
+            success: status.errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match status.errors.is_empty() {
+                true => None,
+                false => Some(status.errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result.with_artifact(out_file)
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn query_status() -> DefenderStatus {
+    DefenderStatus::default()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn set_exclusion(_path: &str, _add: bool) -> Result<(), String> {
+    Err("Windows Defender is only available on Windows".to_string())
+}
+
+/// Queries the status and exclusions of Windows Defender via WMI
+#[cfg(target_os = "windows")]
+pub fn query_status() -> DefenderStatus {
+    // COM has to be initialized on the thread issuing the queries
+    match thread::spawn(native::query_status).join() {
+        Ok(status) => status,
+        Err(_) => DefenderStatus {
+            errors: vec!["Querying Windows Defender panicked".to_string()],
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn set_exclusion(path: &str, add: bool) -> Result<(), String> {
+    let cmdlet = match add {
+        true => "Add-MpPreference",
+        false => "Remove-MpPreference",
+    };
+    // single quotes are escaped by doubling them
+    let script = format!("{} -ExclusionPath '{}'", cmdlet, path.replace('\'', "''"));
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .map_err(|e| e.to_string())?;
+    match output.status.success() && output.stderr.is_empty() {
+        true => Ok(()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod native {
+    use super::DefenderStatus;
+    use crate::wmi_persistence::native::variant_to_string;
+    use std::collections::HashMap;
+    use wmi::{COMLibrary, Variant, WMIConnection};
+
+    const NAMESPACE: &str = "root\\Microsoft\\Windows\\Defender";
+
+    fn query(connection: &WMIConnection, query: &str) -> Result<HashMap<String, Variant>, String> {
+        let rows: Vec<HashMap<String, Variant>> =
+            connection.raw_query(query).map_err(|e| e.to_string())?;
+        rows.into_iter()
+            .next()
+            .ok_or(format!("No result for query: {}", query))
+    }
+
+    pub fn query_status() -> DefenderStatus {
+        let mut status = DefenderStatus::default();
+        let connection = match COMLibrary::new()
+            .and_then(|com_lib| WMIConnection::with_namespace_path(NAMESPACE, com_lib))
+        {
+            Ok(connection) => connection,
+            Err(e) => {
+                status
+                    .errors
+                    .push(format!("Windows Defender is not available: {}", e));
+                return status;
+            }
+        };
+
+        match query(
+            &connection,
+            "SELECT AntivirusEnabled, RealTimeProtectionEnabled FROM MSFT_MpComputerStatus",
+        ) {
+            Ok(row) => {
+                status.available = true;
+                let enabled = |key: &str| matches!(row.get(key), Some(Variant::Bool(true)));
+                status.antivirus_enabled = enabled("AntivirusEnabled");
+                status.real_time_protection = enabled("RealTimeProtectionEnabled");
+            }
+            Err(e) => {
+                status
+                    .errors
+                    .push(format!("Error querying Windows Defender status: {}", e));
+                return status;
+            }
+        }
+
+        // non-administrators get a placeholder instead of the exclusions
+        if let Ok(row) = query(&connection, "SELECT ExclusionPath FROM MSFT_MpPreference") {
+            let paths: Vec<String> = match row.get("ExclusionPath") {
+                Some(Variant::Array(values)) => values.iter().map(variant_to_string).collect(),
+                Some(value) => vec![variant_to_string(value)],
+                None => vec![],
+            };
+            status.exclusions_readable = !paths.iter().any(|path| path.starts_with("N/A"));
+            if status.exclusions_readable {
+                status.exclusion_paths = paths.into_iter().filter(|p| !p.is_empty()).collect();
+            }
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_excluded() {
+        let exclusions = vec![
+            "C:\\IR\\custom_files\\".to_string(),
+            "D:\\Tools".to_string(),
+        ];
+        assert!(is_excluded(Path::new("C:\\IR\\custom_files"), &exclusions));
+        assert!(is_excluded(
+            Path::new("c:/ir/custom_files/winpmem.exe"),
+            &exclusions
+        ));
+        assert!(is_excluded(Path::new("D:\\Tools\\bin"), &exclusions));
+        assert!(!is_excluded(Path::new("D:\\Tools2"), &exclusions));
+        assert!(!is_excluded(Path::new("C:\\IR"), &exclusions));
+    }
+
+    #[test]
+    fn test_check_directory() {
+        let dir = Path::new("C:\\IR\\custom_files");
+        let mut status = DefenderStatus {
+            available: true,
+            antivirus_enabled: true,
+            real_time_protection: true,
+            exclusions_readable: true,
+            ..Default::default()
+        };
+        assert_eq!(check_directory(&status, dir).len(), 1);
+
+        status.exclusion_paths = vec!["C:\\IR".to_string()];
+        assert!(check_directory(&status, dir).is_empty());
+        status.eicar_quarantined = Some(true);
+        assert!(check_directory(&status, dir)[0].contains("EICAR"));
+
+        // no warnings without real-time protection
+        status.exclusion_paths.clear();
+        status.real_time_protection = false;
+        assert!(check_directory(&status, dir).is_empty());
+        assert!(check_directory(&DefenderStatus::default(), dir).is_empty());
+    }
+}
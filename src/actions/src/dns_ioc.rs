@@ -0,0 +1,435 @@
+// Checks whether the endpoint resolved the domains and IP addresses of an IOC list: the DNS cache
+// and the hosts file are read and, if enabled, the domains are resolved. A hit in the DNS cache
+// means the endpoint contacted the domain recently, a quick signal for the containment
+use super::{
+    error_result,
+    grep::read_entries,
+    network_config::{hosts_path, parse_hosts, run_tool},
+    yara::resolve_rules_paths,
+    ActionOptions, ActionResult,
+};
+use config::workflow::DnsIocAttributes;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs,
+    net::{IpAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ioc {
+    Domain(String),
+    Ip(IpAddr),
+}
+
+impl Ioc {
+    /// Parses a domain or IP address, defanged notations like evil[.]com are accepted
+    pub fn parse(entry: &str) -> Option<Ioc> {
+        let entry = entry
+            .trim()
+            .to_lowercase()
+            .replace("[.]", ".")
+            .replace("(.)", ".")
+            .replace("[:]", ":");
+        let entry = entry.trim_start_matches("*.").trim_end_matches('.');
+        if let Ok(ip) = entry.parse::<IpAddr>() {
+            return Some(Ioc::Ip(ip));
+        }
+        let valid = !entry.is_empty()
+            && entry
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        valid.then(|| Ioc::Domain(entry.to_string()))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Ioc::Domain(_) => "domain",
+            Ioc::Ip(_) => "ip",
+        }
+    }
+
+    pub fn value(&self) -> String {
+        match self {
+            Ioc::Domain(domain) => domain.clone(),
+            Ioc::Ip(ip) => ip.to_string(),
+        }
+    }
+
+    // a domain also matches its subdomains
+    fn matches(&self, value: &str) -> bool {
+        let value = value.trim().trim_end_matches('.').to_lowercase();
+        match self {
+            Ioc::Domain(domain) => value == *domain || value.ends_with(&format!(".{}", domain)),
+            Ioc::Ip(ip) => value.parse::<IpAddr>().is_ok_and(|value| value == *ip),
+        }
+    }
+}
+
+/// A resolved name, e.g. an entry of the DNS cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub record_type: String,
+    // address or name the record points to
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct DnsIocHit {
+    pub ioc: String,
+    // domain or ip
+    pub kind: String,
+    // dns_cache, hosts_file or lookup
+    pub source: String,
+    pub name: String,
+    pub record_type: String,
+    pub data: String,
+    // the hit was found by a query sent to the DNS servers
+    pub active: bool,
+}
+
+fn record_type_name(number: &str) -> String {
+    match number {
+        "1" => "A",
+        "2" => "NS",
+        "5" => "CNAME",
+        "6" => "SOA",
+        "12" => "PTR",
+        "15" => "MX",
+        "16" => "TXT",
+        "28" => "AAAA",
+        "33" => "SRV",
+        other => other,
+    }
+    .to_string()
+}
+
+fn address_record_type(address: &str) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => "A".to_string(),
+        Ok(IpAddr::V6(_)) => "AAAA".to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Parses the output of Get-DnsClientCache | Select-Object Entry,Type,Data | ConvertTo-Csv
+pub fn parse_dns_client_cache(text: &str) -> Vec<DnsRecord> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Row {
+        entry: String,
+        #[serde(rename = "Type")]
+        record_type: String,
+        data: String,
+    }
+    csv::Reader::from_reader(text.as_bytes())
+        .deserialize::<Row>()
+        .filter_map(Result::ok)
+        .map(|row| DnsRecord {
+            name: row.entry,
+            record_type: record_type_name(&row.record_type),
+            data: row.data,
+        })
+        .collect()
+}
+
+/// Parses the output of resolvectl show-cache, e.g. "example.com IN A 93.184.216.34"
+pub fn parse_resolvectl_cache(text: &str) -> Vec<DnsRecord> {
+    text.lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [name, "IN", record_type, data, ..] => Some(DnsRecord {
+                    name: name.trim_end_matches('.').to_string(),
+                    record_type: record_type.to_string(),
+                    data: data.trim_end_matches('.').to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn read_dns_cache(warnings: &mut Vec<String>) -> Vec<DnsRecord> {
+    if cfg!(target_os = "windows") {
+        let command =
+            "Get-DnsClientCache | Select-Object Entry,Type,Data | ConvertTo-Csv -NoTypeInformation";
+        run_tool(
+            "powershell",
+            &["-NoProfile", "-NonInteractive", "-Command", command],
+            warnings,
+        )
+        .map(|output| parse_dns_client_cache(&output))
+        .unwrap_or_default()
+    } else if cfg!(target_os = "linux") {
+        // only systemd-resolved keeps a cache that can be listed
+        match run_tool("resolvectl", &["show-cache"], warnings) {
+            Some(output) => parse_resolvectl_cache(&output),
+            None => {
+                warnings.push("The DNS cache can't be read without systemd-resolved".to_string());
+                vec![]
+            }
+        }
+    } else {
+        warnings.push("The DNS cache of macOS can't be read".to_string());
+        vec![]
+    }
+}
+
+fn read_hosts_file(path: &Path, warnings: &mut Vec<String>) -> Vec<DnsRecord> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            warnings.push(format!("Failed to read {}: {}", path.display(), e));
+            return vec![];
+        }
+    };
+    parse_hosts(&content)
+        .into_iter()
+        .flat_map(|entry| {
+            let record_type = address_record_type(&entry.address);
+            entry.hostnames.into_iter().map(move |hostname| DnsRecord {
+                name: hostname,
+                record_type: record_type.clone(),
+                data: entry.address.clone(),
+            })
+        })
+        .collect()
+}
+
+// the standard library has no timeout for lookups, so each lookup runs on its own thread
+fn lookup(domain: &str, timeout: Duration) -> Result<Vec<DnsRecord>, String> {
+    let (sender, receiver) = mpsc::channel();
+    let host = format!("{}:0", domain);
+    std::thread::spawn(move || {
+        let addresses = host
+            .to_socket_addrs()
+            .map(|addresses| addresses.map(|address| address.ip()).collect::<Vec<_>>());
+        let _ = sender.send(addresses);
+    });
+    let addresses: BTreeSet<IpAddr> = match receiver.recv_timeout(timeout) {
+        Ok(Ok(addresses)) => addresses.into_iter().collect(),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("timed out".to_string()),
+    };
+    Ok(addresses
+        .into_iter()
+        .map(|address| DnsRecord {
+            name: domain.to_string(),
+            record_type: address_record_type(&address.to_string()),
+            data: address.to_string(),
+        })
+        .collect())
+}
+
+/// The IOCs matching the name or the data of the records
+pub fn find_hits(
+    iocs: &[Ioc],
+    source: &str,
+    records: &[DnsRecord],
+    active: bool,
+) -> Vec<DnsIocHit> {
+    let mut hits = vec![];
+    for record in records {
+        for ioc in iocs {
+            if ioc.matches(&record.name) || ioc.matches(&record.data) {
+                hits.push(DnsIocHit {
+                    ioc: ioc.value(),
+                    kind: ioc.kind().to_string(),
+                    source: source.to_string(),
+                    name: record.name.clone(),
+                    record_type: record.record_type.clone(),
+                    data: record.data.clone(),
+                    active,
+                });
+            }
+        }
+    }
+    hits
+}
+
+pub struct DnsIoc {}
+
+impl DnsIoc {
+    pub fn run(
+        attributes: DnsIocAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        custom_files_dir: &Path,
+    ) -> ActionResult {
+        let ioc_files = resolve_rules_paths(&attributes.ioc_files, custom_files_dir);
+        let entries = match read_entries(&attributes.iocs, &ioc_files) {
+            Ok(entries) => entries,
+            Err(e) => return error_result!(e, options.start_time),
+        };
+        let mut warnings = vec![];
+        let mut iocs: Vec<Ioc> = vec![];
+        for entry in entries {
+            match Ioc::parse(&entry) {
+                Some(ioc) if !iocs.contains(&ioc) => iocs.push(ioc),
+                Some(_) => {}
+                None => warnings.push(format!("Skipping invalid IOC {:?}", entry)),
+            }
+        }
+        if iocs.is_empty() {
+            return error_result!("No IOCs provided", options.start_time);
+        }
+
+        // the cache is read first, as the lookups add their results to it
+        let mut hits = vec![];
+        if attributes.dns_cache {
+            let records = read_dns_cache(&mut warnings);
+            debug!("Read {} records of the DNS cache", records.len());
+            hits.extend(find_hits(&iocs, "dns_cache", &records, false));
+        }
+        if attributes.hosts_file {
+            let records = read_hosts_file(&hosts_path(), &mut warnings);
+            hits.extend(find_hits(&iocs, "hosts_file", &records, false));
+        }
+        if attributes.lookups {
+            let domains: Vec<&String> = iocs
+                .iter()
+                .filter_map(|ioc| match ioc {
+                    Ioc::Domain(domain) => Some(domain),
+                    Ioc::Ip(_) => None,
+                })
+                .collect();
+            info!(
+                "Resolving {} domains, the queries are sent to the DNS servers",
+                domains.len()
+            );
+            let timeout = Duration::from_secs(attributes.lookup_timeout.max(1) as u64);
+            for domain in domains {
+                match lookup(domain, timeout) {
+                    Ok(records) => hits.extend(find_hits(&iocs, "lookup", &records, true)),
+                    Err(e) => debug!("{} doesn't resolve: {}", domain, e),
+                }
+            }
+        }
+
+        let mut writer = match csv::Writer::from_path(&out_file) {
+            Ok(writer) => writer,
+            Err(e) => return error_result!(format!("Failed to create output file: {}", e)),
+        };
+        for hit in &hits {
+            if let Err(e) = writer.serialize(hit) {
+                return error_result!(
+                    format!("Failed to write output file: {}", e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Failed to write output file: {}", e),
+                options.start_time
+            );
+        }
+
+        let found: BTreeSet<&String> = hits.iter().map(|hit| &hit.ioc).collect();
+        for ioc in &found {
+            warn!("IOC {} was resolved on this endpoint", ioc);
+        }
+        info!("{} of {} IOCs found", found.len(), iocs.len());
+
+        let result = ActionResult {
+            success: true,
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: None,
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(iocs.len() as u64)
+            .with_artifact(out_file)
+            .with_warnings(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_parse_ioc() {
+        assert_eq!(
+            Ioc::parse(" Evil[.]COM. "),
+            Some(Ioc::Domain("evil.com".to_string()))
+        );
+        assert_eq!(
+            Ioc::parse("*.c2.example"),
+            Some(Ioc::Domain("c2.example".to_string()))
+        );
+        assert_eq!(
+            Ioc::parse("185.220.101(.)5"),
+            Some(Ioc::Ip("185.220.101.5".parse().unwrap()))
+        );
+        assert_eq!(
+            Ioc::parse("2001:db8::1"),
+            Some(Ioc::Ip("2001:db8::1".parse().unwrap()))
+        );
+        assert_eq!(Ioc::parse("http://evil.com/payload"), None);
+        assert_eq!(Ioc::parse(""), None);
+    }
+
+    #[test]
+    fn test_find_hits() {
+        let windows = "\"Entry\",\"Type\",\"Data\"\r\n\
+                       \"cdn.evil.com\",\"5\",\"edge.fastcdn.net\"\r\n\
+                       \"cdn.evil.com\",\"1\",\"203.0.113.7\"\r\n\
+                       \"notevil.com\",\"1\",\"198.51.100.1\"\r\n";
+        let records = parse_dns_client_cache(windows);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].record_type, "CNAME");
+
+        let linux = "Scope protocol=dns interface=eth0:\n\
+                     KEY                                   TTL\n\
+                     update.example.org. IN AAAA 2001:db8::1 120\n\
+                     beacon.evil.com IN A 203.0.113.7 3000\n";
+        let records_linux = parse_resolvectl_cache(linux);
+        assert_eq!(records_linux.len(), 2);
+        assert_eq!(records_linux[0].name, "update.example.org");
+
+        let iocs = vec![
+            Ioc::parse("evil.com").unwrap(),
+            Ioc::parse("203.0.113.7").unwrap(),
+            Ioc::parse("2001:db8:0::1").unwrap(),
+        ];
+        let hits = find_hits(&iocs, "dns_cache", &records, false);
+        // a subdomain matches, a domain that only ends with the same letters doesn't
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].ioc, "evil.com");
+        assert_eq!(hits[0].data, "edge.fastcdn.net");
+        assert_eq!(hits[2].kind, "ip");
+        let hits = find_hits(&iocs, "dns_cache", &records_linux, false);
+        assert_eq!(
+            hits.iter().map(|hit| hit.ioc.as_str()).collect::<Vec<_>>(),
+            vec!["2001:db8::1", "evil.com", "203.0.113.7"]
+        );
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_dns_ioc_hosts");
+        fs::write(
+            dir.join("hosts"),
+            "127.0.0.1 localhost\n0.0.0.0 update.av-vendor.com # blocked\n203.0.113.7 login.evil.com\n",
+        )
+        .unwrap();
+        let mut warnings = vec![];
+        let records = read_hosts_file(&dir.join("hosts"), &mut warnings);
+        assert_eq!(records.len(), 3);
+        let hits = find_hits(&iocs, "hosts_file", &records, false);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].record_type, "A");
+        read_hosts_file(&dir.join("missing"), &mut warnings);
+        assert_eq!(warnings.len(), 1);
+    }
+}
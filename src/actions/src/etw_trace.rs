@@ -0,0 +1,223 @@
+use config::workflow::{EtwTraceAttributes, EtwTraceMode};
+use log::{debug, error, info};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+use storage::FileProcessor;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+// Commonly used providers that can be referenced by a short name
+const PROVIDER_PRESETS: [(&str, &str); 6] = [
+    ("dns", "Microsoft-Windows-DNS-Client"),
+    ("process", "Microsoft-Windows-Kernel-Process"),
+    ("network", "Microsoft-Windows-Kernel-Network"),
+    ("file", "Microsoft-Windows-Kernel-File"),
+    ("registry", "Microsoft-Windows-Kernel-Registry"),
+    ("powershell", "Microsoft-Windows-PowerShell"),
+];
+
+/// Resolve a preset to its provider name, anything else is passed as is
+pub fn resolve_provider(provider: &str) -> String {
+    let provider = provider.trim();
+    PROVIDER_PRESETS
+        .iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(provider))
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| provider.to_string())
+}
+
+/// Build the logman invocations to start the session. logman only accepts
+/// a single provider on start, so all others are added with an update.
+pub fn start_commands(attributes: &EtwTraceAttributes, trace_file: &Path) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    for (i, provider) in attributes.providers.iter().enumerate() {
+        let provider = resolve_provider(provider);
+        let mut args: Vec<String> = match i {
+            0 => vec![
+                "start".to_string(),
+                attributes.session_name.clone(),
+                "-ets".to_string(),
+                "-o".to_string(),
+                trace_file.to_string_lossy().to_string(),
+                "-max".to_string(),
+                attributes.max_size.to_string(),
+            ],
+            _ => vec![
+                "update".to_string(),
+                "trace".to_string(),
+                attributes.session_name.clone(),
+                "-ets".to_string(),
+            ],
+        };
+        args.push("-p".to_string());
+        args.push(provider);
+        commands.push(args);
+    }
+    commands
+}
+
+pub fn stop_command(attributes: &EtwTraceAttributes) -> Vec<String> {
+    vec![
+        "stop".to_string(),
+        attributes.session_name.clone(),
+        "-ets".to_string(),
+    ]
+}
+
+fn run_logman(args: &[String]) -> Result<(), String> {
+    debug!("Running logman {}", args.join(" "));
+    let output = Command::new("logman")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run logman: {}", e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    // logman reports errors on stdout
+    let mut message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if message.is_empty() {
+        message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    }
+    message.truncate(200);
+    Err(format!(
+        "logman {} failed ({}): {}",
+        args[0], output.status, message
+    ))
+}
+
+fn start_session(attributes: &EtwTraceAttributes, trace_file: &Path) -> Result<(), String> {
+    if attributes.providers.is_empty() {
+        return Err("No providers specified".to_string());
+    }
+    if let Some(parent) = trace_file.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {:?}: {}", parent.display(), e))?;
+    }
+
+    let commands = start_commands(attributes, trace_file);
+    for (i, args) in commands.iter().enumerate() {
+        if let Err(e) = run_logman(args) {
+            // don't leave a half configured session behind
+            if i > 0 {
+                let _ = run_logman(&stop_command(attributes));
+            }
+            return Err(e);
+        }
+    }
+    info!(
+        "Started ETW session {} with {} providers",
+        attributes.session_name,
+        commands.len()
+    );
+    Ok(())
+}
+
+fn stop_session(
+    attributes: &EtwTraceAttributes,
+    trace_file: &Path,
+    file_processor: &mut FileProcessor,
+) -> Result<(), String> {
+    run_logman(&stop_command(attributes))?;
+    info!("Stopped ETW session {}", attributes.session_name);
+
+    if !trace_file.exists() {
+        return Err(format!("Trace file {:?} not found", trace_file.display()));
+    }
+    file_processor
+        .store(trace_file, None)
+        .map_err(|e| format!("Error storing file {:?}: {}", trace_file.display(), e))
+}
+
+pub struct EtwTrace {}
+
+impl EtwTrace {
+    pub fn run(
+        attributes: EtwTraceAttributes,
+        options: ActionOptions,
+        trace_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        if !cfg!(target_os = "windows") {
+            return error_result!("etw_trace is only supported on Windows", options.start_time);
+        }
+
+        let result = match attributes.mode {
+            EtwTraceMode::Capture => start_session(&attributes, &trace_file).and_then(|_| {
+                info!("Capturing for {} seconds", attributes.duration);
+                thread::sleep(Duration::from_secs(attributes.duration));
+                stop_session(&attributes, &trace_file, file_processor)
+            }),
+            EtwTraceMode::Start => start_session(&attributes, &trace_file),
+            EtwTraceMode::Stop => stop_session(&attributes, &trace_file, file_processor),
+        };
+
+        match result {
+            Ok(_) => ActionResult {
+                success: true,
+                exit_code: Some(0),
+                execution_time: options.start_time.elapsed(),
+                error_message: None,
+                parallel: false,
+                finished: true,
+                error_category: None,
+                ..Default::default()
+            }
+            .with_artifact(trace_file),
+            Err(e) => {
+                error!("{}", e);
+                error_result!(e, options.start_time)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(providers: Vec<&str>) -> EtwTraceAttributes {
+        EtwTraceAttributes {
+            mode: EtwTraceMode::Capture,
+            session_name: "irt_test".to_string(),
+            providers: providers.into_iter().map(String::from).collect(),
+            duration: 1,
+            max_size: 64,
+        }
+    }
+
+    #[test]
+    fn test_resolve_provider() {
+        assert_eq!(resolve_provider("DNS"), "Microsoft-Windows-DNS-Client");
+        assert_eq!(
+            resolve_provider(" process "),
+            "Microsoft-Windows-Kernel-Process"
+        );
+        assert_eq!(
+            resolve_provider("{22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716}"),
+            "{22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716}"
+        );
+    }
+
+    #[test]
+    fn test_start_commands() {
+        let trace_file = PathBuf::from("loot").join("irt_test.etl");
+        let commands = start_commands(&attributes(vec!["dns", "network"]), &trace_file);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0][0], "start");
+        assert!(commands[0].contains(&"-max".to_string()));
+        assert!(commands[0].contains(&trace_file.to_string_lossy().to_string()));
+        assert_eq!(commands[0].last().unwrap(), "Microsoft-Windows-DNS-Client");
+        assert_eq!(commands[1][..3], ["update", "trace", "irt_test"]);
+        assert_eq!(
+            commands[1].last().unwrap(),
+            "Microsoft-Windows-Kernel-Network"
+        );
+
+        assert!(start_commands(&attributes(vec![]), &trace_file).is_empty());
+    }
+}
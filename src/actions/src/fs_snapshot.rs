@@ -0,0 +1,291 @@
+use config::workflow::FsSnapshotAttributes;
+use crypto::get_file_sha1;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use utils::misc::iter_files_by_pattern_limited;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub size: u64,
+    // seconds since the unix epoch
+    pub modified: Option<u64>,
+    pub sha1: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Snapshot {
+    pub version: String,
+    pub files: BTreeMap<String, SnapshotEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModifiedEntry {
+    pub path: String,
+    pub before: SnapshotEntry,
+    pub after: SnapshotEntry,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct SnapshotDiff {
+    pub baseline: String,
+    pub added: Vec<String>,
+    pub modified: Vec<ModifiedEntry>,
+    pub deleted: Vec<String>,
+}
+
+impl SnapshotEntry {
+    pub fn from_file(path: &PathBuf, checksums: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let metadata = path.metadata()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+        let sha1 = match checksums {
+            true => Some(get_file_sha1(path)?),
+            false => None,
+        };
+
+        Ok(SnapshotEntry {
+            size: metadata.len(),
+            modified,
+            sha1,
+        })
+    }
+
+    // Prefer the checksums, if both snapshots have them. Otherwise fall back to size and mtime
+    pub fn differs_from(&self, other: &SnapshotEntry) -> bool {
+        match (&self.sha1, &other.sha1) {
+            (Some(a), Some(b)) => a != b || self.size != other.size,
+            _ => self.size != other.size || self.modified != other.modified,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn capture(
+        patterns: &[&str],
+        case_sensitive: bool,
+        checksums: bool,
+        max_files: usize,
+    ) -> Self {
+        let mut snapshot = Snapshot {
+            version: "1.0".to_string(),
+            files: BTreeMap::new(),
+        };
+
+        for pattern in patterns {
+            let files = match iter_files_by_pattern_limited(pattern, case_sensitive, max_files) {
+                Ok(files) => files,
+                Err(e) => {
+                    error!("Invalid pattern {:?}: {}", pattern, e);
+                    continue;
+                }
+            };
+            for file in files {
+                match SnapshotEntry::from_file(&file, checksums) {
+                    Ok(entry) => {
+                        snapshot
+                            .files
+                            .insert(file.to_string_lossy().to_string(), entry);
+                    }
+                    Err(e) => warn!("Unable to read file {:?}: {}", file.display(), e),
+                }
+            }
+        }
+
+        snapshot
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn diff(&self, baseline: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        for (path, entry) in &self.files {
+            match baseline.files.get(path) {
+                None => diff.added.push(path.clone()),
+                Some(before) if before.differs_from(entry) => diff.modified.push(ModifiedEntry {
+                    path: path.clone(),
+                    before: before.clone(),
+                    after: entry.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+
+        diff.deleted = baseline
+            .files
+            .keys()
+            .filter(|path| !self.files.contains_key(*path))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+pub struct FsSnapshot {}
+
+impl FsSnapshot {
+    pub fn run(
+        attributes: FsSnapshotAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        snapshot_file: PathBuf,
+        custom_files_dir: &Path,
+    ) -> ActionResult {
+        // Step 1: Split pattern string into Vec<String>
+        let patterns: Vec<&str> = attributes
+            .patterns
+            .split('\n')
+            .filter(|x| !x.is_empty())
+            .collect();
+
+        // Step 2: Capture the current state and save it, so it can be used as baseline later on
+        let snapshot = Snapshot::capture(
+            &patterns,
+            attributes.case_sensitive,
+            attributes.checksums,
+            attributes.max_files,
+        );
+        info!("Captured snapshot of {} files", snapshot.files.len());
+        if let Err(e) = snapshot.save(&snapshot_file) {
+            return error_result!(
+                format!(
+                    "Error writing snapshot {:?}: {}",
+                    snapshot_file.display(),
+                    e
+                ),
+                options.start_time
+            );
+        }
+
+        // Step 3: Compare against the baseline (if any)
+        if !attributes.baseline.is_empty() {
+            let baseline_path = match PathBuf::from(&attributes.baseline).is_absolute() {
+                true => PathBuf::from(&attributes.baseline),
+                false => custom_files_dir.join(&attributes.baseline),
+            };
+            let baseline = match Snapshot::load(&baseline_path) {
+                Ok(baseline) => baseline,
+                Err(e) => {
+                    return error_result!(
+                        format!(
+                            "Error reading baseline {:?}: {}",
+                            baseline_path.display(),
+                            e
+                        ),
+                        options.start_time
+                    );
+                }
+            };
+
+            let mut diff = snapshot.diff(&baseline);
+            diff.baseline = baseline_path.to_string_lossy().to_string();
+            info!(
+                "Compared against baseline: {} added, {} modified, {} deleted",
+                diff.added.len(),
+                diff.modified.len(),
+                diff.deleted.len()
+            );
+
+            let result = File::create(&out_file)
+                .map_err(|e| e.to_string())
+                .and_then(|file| {
+                    serde_json::to_writer_pretty(BufWriter::new(file), &diff)
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = result {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+            debug!("Wrote snapshot diff to {:?}", out_file.display());
+        }
+
+        let result = ActionResult {
+            success: true,
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: None,
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(snapshot.files.len() as u64)
+            .with_artifact(snapshot_file)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_snapshot_diff");
+        cleanup.create_files(&tmp_dir, vec!["keep.txt", "change.txt", "remove.txt"]);
+
+        let pattern = tmp_dir.join("*.txt").to_str().unwrap().to_string();
+        let baseline = Snapshot::capture(&[&pattern], true, true, 0);
+        assert_eq!(baseline.files.len(), 3);
+
+        // save and reload the baseline like it would be shipped in custom_files
+        let baseline_path = tmp_dir.join("baseline.json");
+        baseline.save(&baseline_path).unwrap();
+        let baseline = Snapshot::load(&baseline_path).unwrap();
+
+        std::fs::write(tmp_dir.join("change.txt"), b"modified").unwrap();
+        std::fs::remove_file(tmp_dir.join("remove.txt")).unwrap();
+        std::fs::write(tmp_dir.join("new.txt"), b"new").unwrap();
+
+        let current = Snapshot::capture(&[&pattern], true, true, 0);
+        let diff = current.diff(&baseline);
+
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.added[0].ends_with("new.txt"));
+        assert_eq!(diff.modified.len(), 1);
+        assert!(diff.modified[0].path.ends_with("change.txt"));
+        assert_eq!(diff.deleted.len(), 1);
+        assert!(diff.deleted[0].ends_with("remove.txt"));
+    }
+
+    #[test]
+    fn test_entry_differs_without_checksums() {
+        let before = SnapshotEntry {
+            size: 10,
+            modified: Some(100),
+            sha1: None,
+        };
+        let mut after = before.clone();
+        assert!(!after.differs_from(&before));
+
+        after.modified = Some(200);
+        assert!(after.differs_from(&before));
+    }
+}
@@ -0,0 +1,332 @@
+// Searches files for keywords and regular expressions (e.g. the IOCs of a threat report),
+// a lighter-weight complement to YARA which doesn't require rules to be written
+use super::{error_result, yara::resolve_rules_paths, ActionOptions, ActionResult};
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use config::workflow::GrepAttributes;
+use log::{debug, error, info};
+use memmap2::Mmap;
+use regex::bytes::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use storage::FileProcessor;
+use utils::misc::iter_files_by_pattern_cached;
+
+/// One hit of a keyword or regular expression inside a file
+#[derive(Debug, Serialize, PartialEq)]
+pub struct GrepHit {
+    pub file: String,
+    pub offset: usize,
+    // keyword or regex
+    pub kind: String,
+    pub pattern: String,
+    pub matched: String,
+    // bytes around the hit, non-printable bytes are replaced by a dot
+    pub context: String,
+}
+
+/// Entries of the attribute and of the IOC packs, empty lines and lines starting with # are ignored
+pub(crate) fn read_entries(inline: &str, files: &[PathBuf]) -> Result<Vec<String>, String> {
+    let mut content = inline.to_string();
+    for file in files {
+        let pack = fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        content.push('\n');
+        content.push_str(&pack);
+    }
+    let mut seen = HashSet::new();
+    Ok(content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter(|line| seen.insert(line.to_string()))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b' '..=b'~' => byte as char,
+            _ => '.',
+        })
+        .collect()
+}
+
+/// Multi-pattern search over the content of a file
+pub struct Matcher {
+    keywords: Vec<String>,
+    searcher: Option<AhoCorasick>,
+    regexes: Vec<Regex>,
+    context: usize,
+    max_hits: usize,
+}
+
+impl Matcher {
+    pub fn new(
+        keywords: Vec<String>,
+        regexes: &[String],
+        case_sensitive: bool,
+        context: usize,
+        max_hits: usize,
+    ) -> Result<Self, String> {
+        let searcher = match keywords.is_empty() {
+            true => None,
+            false => Some(
+                AhoCorasickBuilder::new()
+                    .ascii_case_insensitive(!case_sensitive)
+                    .build(&keywords)
+                    .map_err(|e| format!("Failed to build keyword searcher: {}", e))?,
+            ),
+        };
+        let regexes = regexes
+            .iter()
+            .map(|regex| {
+                RegexBuilder::new(regex)
+                    .case_insensitive(!case_sensitive)
+                    .build()
+                    .map_err(|e| format!("Invalid regex {}: {}", regex, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            keywords,
+            searcher,
+            regexes,
+            context,
+            max_hits,
+        })
+    }
+
+    fn hit(
+        &self,
+        file: &str,
+        content: &[u8],
+        kind: &str,
+        pattern: &str,
+        range: Range<usize>,
+    ) -> GrepHit {
+        let start = range.start.saturating_sub(self.context);
+        let end = (range.end + self.context).min(content.len());
+        GrepHit {
+            file: file.to_string(),
+            offset: range.start,
+            kind: kind.to_string(),
+            pattern: pattern.to_string(),
+            matched: printable(&content[range]),
+            context: printable(&content[start..end]),
+        }
+    }
+
+    /// Hits ordered by their offset, at most max_hits (0 = unlimited)
+    pub fn search(&self, file: &str, content: &[u8]) -> Vec<GrepHit> {
+        let limit = match self.max_hits {
+            0 => usize::MAX,
+            max_hits => max_hits,
+        };
+        let mut hits = vec![];
+        if let Some(searcher) = &self.searcher {
+            // overlapping, so a keyword inside another keyword is reported as well
+            for found in searcher.find_overlapping_iter(content).take(limit) {
+                let keyword = &self.keywords[found.pattern().as_usize()];
+                hits.push(self.hit(file, content, "keyword", keyword, found.range()));
+            }
+        }
+        for regex in &self.regexes {
+            for found in regex.find_iter(content).take(limit) {
+                hits.push(self.hit(file, content, "regex", regex.as_str(), found.range()));
+            }
+        }
+        hits.sort_by_key(|hit| hit.offset);
+        hits.truncate(limit);
+        hits
+    }
+}
+
+// memory-mapped, so large files are not read into memory as a whole
+fn search_file(matcher: &Matcher, path: &Path) -> Result<Vec<GrepHit>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    // SAFETY: the mapping is only read, a file truncated by another process while being searched may yield incomplete hits
+    let content = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+    Ok(matcher.search(&path.to_string_lossy(), &content))
+}
+
+pub struct Grep {}
+
+impl Grep {
+    pub fn run(
+        attributes: GrepAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+        custom_files_dir: &Path,
+    ) -> ActionResult {
+        let keyword_files = resolve_rules_paths(&attributes.keyword_files, custom_files_dir);
+        let regex_files = resolve_rules_paths(&attributes.regex_files, custom_files_dir);
+        let (keywords, regexes) = match (
+            read_entries(&attributes.keywords, &keyword_files),
+            read_entries(&attributes.regexes, &regex_files),
+        ) {
+            (Ok(keywords), Ok(regexes)) => (keywords, regexes),
+            (Err(e), _) | (_, Err(e)) => return error_result!(e, options.start_time),
+        };
+        if keywords.is_empty() && regexes.is_empty() {
+            return error_result!("No keywords or regexes provided", options.start_time);
+        }
+        let matcher = match Matcher::new(
+            keywords,
+            &regexes,
+            attributes.case_sensitive,
+            attributes.context,
+            attributes.max_hits_per_file,
+        ) {
+            Ok(matcher) => matcher,
+            Err(e) => return error_result!(e, options.start_time),
+        };
+
+        let files: HashSet<PathBuf> = attributes
+            .patterns
+            .split('\n')
+            .filter(|pattern| !pattern.trim().is_empty())
+            .flat_map(|pattern| {
+                iter_files_by_pattern_cached(
+                    pattern,
+                    false,
+                    attributes.max_files,
+                    options.path_cache.as_ref(),
+                )
+                .into_iter()
+                .flatten()
+            })
+            // the lines of a hit would reveal the content of excluded files
+            .filter(|file| !file_processor.is_excluded(file))
+            .collect();
+        if files.is_empty() {
+            return error_result!("No files to search provided", options.start_time);
+        }
+        debug!(
+            "Searching {} files for {} keywords and {} regexes",
+            files.len(),
+            matcher.keywords.len(),
+            matcher.regexes.len()
+        );
+
+        let mut writer = match csv::Writer::from_path(&out_file) {
+            Ok(writer) => writer,
+            Err(e) => return error_result!(format!("Failed to create output file: {}", e)),
+        };
+        let mut warnings = vec![];
+        let mut searched = 0;
+        let mut matched_files = 0;
+        for file in &files {
+            let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            if size == 0 {
+                continue;
+            }
+            if attributes.size_limit > 0 && size > attributes.size_limit {
+                debug!("Skipping {}: {} bytes", file.display(), size);
+                continue;
+            }
+            let hits = match search_file(&matcher, file) {
+                Ok(hits) => hits,
+                Err(e) => {
+                    warnings.push(format!("Failed to search {}: {}", file.display(), e));
+                    continue;
+                }
+            };
+            searched += 1;
+            if hits.is_empty() {
+                continue;
+            }
+            matched_files += 1;
+            for hit in &hits {
+                if let Err(e) = writer.serialize(hit) {
+                    return error_result!(
+                        format!("Failed to write output file: {}", e),
+                        options.start_time
+                    );
+                }
+            }
+            if attributes.store_on_match {
+                if let Err(e) = file_processor.store(
+                    file,
+                    Some("Matched by grep: Access time may have changed".to_string()),
+                ) {
+                    error!("Error storing file: {}", e);
+                    warnings.push(format!("Failed to store {}: {}", file.display(), e));
+                }
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Failed to write output file: {}", e),
+                options.start_time
+            );
+        }
+        info!("Searched {} files, {} with hits", searched, matched_files);
+
+        let result = ActionResult {
+            success: true,
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: None,
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(searched)
+            .with_artifact(out_file)
+            .with_warnings(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_matcher() {
+        let matcher = Matcher::new(
+            vec!["evil.com".to_string(), "EVIL".to_string()],
+            &[r"\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}".to_string()],
+            false,
+            4,
+            0,
+        )
+        .unwrap();
+        let content = b"GET http://Evil.com/\x00 from 10.0.0.1";
+        let hits = matcher.search("access.log", content);
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].offset, 11);
+        assert_eq!(hits[0].matched, "Evil");
+        assert_eq!(hits[1].pattern, "evil.com");
+        assert_eq!(hits[1].context, "p://Evil.com/. f");
+        assert_eq!(hits[2].kind, "regex");
+        assert_eq!(hits[2].matched, "10.0.0.1");
+
+        // case sensitive and capped
+        let matcher = Matcher::new(vec!["EVIL".to_string()], &[], true, 0, 1).unwrap();
+        assert!(matcher.search("", b"evil").is_empty());
+        assert_eq!(matcher.search("", b"EVIL EVIL").len(), 1);
+        assert!(Matcher::new(vec![], &["(".to_string()], true, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_grep_read_entries");
+        let pack = dir.join("iocs.txt");
+        fs::write(&pack, "# C2 domains\nevil.com\n\nbad.org\n").unwrap();
+
+        let entries = read_entries("evil.com\n  mimikatz ", &[pack]).unwrap();
+        assert_eq!(entries, vec!["evil.com", "mimikatz", "bad.org"]);
+        assert!(read_entries("", &[dir.join("missing.txt")]).is_err());
+    }
+}
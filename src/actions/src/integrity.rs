@@ -0,0 +1,215 @@
+// Checksums of the tools in the custom_files directory, so a tampered toolkit doesn't run
+// modified binaries on the evidence. custom_files/custom_files.manifest lists one file per line
+// in the format of sha256sum, with the path relative to custom_files:
+//
+//   9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  winpmem/windows_x86_64.exe
+//
+// e.g. created with: cd custom_files && find . -type f ! -name custom_files.manifest -exec sha256sum {} + > custom_files.manifest
+use config::config::OnMismatch;
+use crypto::{Digest, HashingReader};
+use log::{error, warn};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    path::Path,
+};
+
+pub const CUSTOM_FILES_MANIFEST: &str = "custom_files.manifest";
+
+#[derive(Debug, Default)]
+pub struct CustomFilesManifest {
+    // SHA-256 by normalized relative path
+    entries: BTreeMap<String, String>,
+}
+
+// separated by "/", lowercase on Windows where paths are case-insensitive
+fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let path = path.trim_start_matches("./");
+    match cfg!(target_os = "windows") {
+        true => path.to_lowercase(),
+        false => path.to_string(),
+    }
+}
+
+fn sha256_file(path: &Path) -> Result<String, Box<dyn Error>> {
+    let digests = HashingReader::new(File::open(path)?, &[Digest::Sha256])?.digest_remaining()?;
+    Ok(digests.hex(Digest::Sha256))
+}
+
+impl CustomFilesManifest {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entries = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (hash, path) = line
+                .split_once(char::is_whitespace)
+                .map(|(hash, path)| (hash, path.trim_start().trim_start_matches('*')))
+                .filter(|(hash, path)| {
+                    hash.len() == 64
+                        && hash.chars().all(|c| c.is_ascii_hexdigit())
+                        && !path.is_empty()
+                })
+                .ok_or_else(|| format!("Line {}: expected \"<sha256>  <path>\"", number + 1))?;
+            entries.insert(normalize_path(path), hash.to_lowercase());
+        }
+        Ok(Self { entries })
+    }
+
+    /// Loads the manifest of the custom_files directory, None if there is none
+    pub fn load(custom_files_dir: &Path) -> Result<Option<Self>, String> {
+        let path = custom_files_dir.join(CUSTOM_FILES_MANIFEST);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text)
+            .map(Some)
+            .map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    /// Checks the file against the manifest. Files outside of the custom_files directory aren't checked
+    pub fn verify_file(&self, custom_files_dir: &Path, file: &Path) -> Result<(), String> {
+        let Ok(relative) = file.strip_prefix(custom_files_dir) else {
+            return Ok(());
+        };
+        let relative = normalize_path(&relative.to_string_lossy());
+        let expected = self
+            .entries
+            .get(&relative)
+            .ok_or_else(|| format!("{} is not listed in {}", relative, CUSTOM_FILES_MANIFEST))?;
+        let actual =
+            sha256_file(file).map_err(|e| format!("Failed to hash {}: {}", relative, e))?;
+        match &actual == expected {
+            true => Ok(()),
+            false => Err(format!(
+                "{} doesn't match {} (expected SHA-256 {}, got {})",
+                relative, CUSTOM_FILES_MANIFEST, expected, actual
+            )),
+        }
+    }
+
+    /// Checks all files listed in the manifest
+    pub fn verify_all(&self, custom_files_dir: &Path) -> Vec<String> {
+        self.entries
+            .keys()
+            .filter_map(|relative| {
+                let file = custom_files_dir.join(relative);
+                match file.is_file() {
+                    true => self.verify_file(custom_files_dir, &file).err(),
+                    false => Some(format!("{} is missing", relative)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The manifest of the custom_files directory and what happens on a mismatch
+#[derive(Debug, Default)]
+pub struct CustomFilesIntegrity {
+    pub manifest: Option<CustomFilesManifest>,
+    pub on_mismatch: OnMismatch,
+}
+
+impl CustomFilesIntegrity {
+    /// Loads the manifest and checks all listed files once at startup. An invalid manifest
+    /// blocks all binaries of the custom_files directory, unless mismatches only warn
+    pub fn load(custom_files_dir: &Path, on_mismatch: OnMismatch) -> Self {
+        let manifest = match CustomFilesManifest::load(custom_files_dir) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error!("{}", e);
+                match on_mismatch {
+                    OnMismatch::Block => Some(CustomFilesManifest::default()),
+                    OnMismatch::Warn => None,
+                }
+            }
+        };
+        if let Some(manifest) = &manifest {
+            for problem in manifest.verify_all(custom_files_dir) {
+                match on_mismatch {
+                    OnMismatch::Block => error!("Integrity check of custom_files: {}", problem),
+                    OnMismatch::Warn => warn!("Integrity check of custom_files: {}", problem),
+                }
+            }
+        }
+        Self {
+            manifest,
+            on_mismatch,
+        }
+    }
+
+    /// Checks the binary before it is executed. Fails if it doesn't match and mismatches are blocked
+    pub fn check(&self, custom_files_dir: &Path, binary: &Path) -> Result<(), String> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        match (
+            manifest.verify_file(custom_files_dir, binary),
+            self.on_mismatch,
+        ) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), OnMismatch::Warn) => {
+                warn!("{}", e);
+                Ok(())
+            }
+            (Err(e), OnMismatch::Block) => Err(format!("Refusing to execute the binary: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_custom_files_integrity() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_custom_files_integrity");
+        fs::create_dir_all(dir.join("tools")).unwrap();
+        fs::write(dir.join("tools").join("tool.exe"), "test").unwrap();
+        fs::write(dir.join("other.exe"), "other").unwrap();
+
+        // no manifest, nothing is checked
+        let integrity = CustomFilesIntegrity::load(&dir, OnMismatch::Block);
+        assert!(integrity.manifest.is_none());
+        assert!(integrity.check(&dir, &dir.join("other.exe")).is_ok());
+
+        fs::write(
+            dir.join(CUSTOM_FILES_MANIFEST),
+            "# tools of the engagement\n\
+             9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  ./tools/tool.exe\n\
+             0000000000000000000000000000000000000000000000000000000000000000 *missing.exe\n",
+        )
+        .unwrap();
+        let integrity = CustomFilesIntegrity::load(&dir, OnMismatch::Block);
+        let manifest = integrity.manifest.as_ref().unwrap();
+        assert_eq!(manifest.verify_all(&dir), vec!["missing.exe is missing"]);
+        assert!(integrity
+            .check(&dir, &dir.join("tools").join("tool.exe"))
+            .is_ok());
+        // binaries outside of custom_files are not checked
+        assert!(integrity.check(&dir, Path::new("/usr/bin/ls")).is_ok());
+        let e = integrity.check(&dir, &dir.join("other.exe")).unwrap_err();
+        assert!(e.contains("other.exe is not listed"));
+
+        fs::write(dir.join("tools").join("tool.exe"), "tampered").unwrap();
+        assert!(integrity
+            .check(&dir, &dir.join("tools").join("tool.exe"))
+            .unwrap_err()
+            .contains("doesn't match"));
+        let integrity = CustomFilesIntegrity::load(&dir, OnMismatch::Warn);
+        assert!(integrity
+            .check(&dir, &dir.join("tools").join("tool.exe"))
+            .is_ok());
+
+        assert!(CustomFilesManifest::parse("abc tool.exe").is_err());
+        assert!(CustomFilesManifest::parse(&"a".repeat(64)).is_err());
+    }
+}
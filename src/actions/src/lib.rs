@@ -1,15 +1,53 @@
 pub mod binary;
 pub mod command;
+pub mod defender;
+pub mod dns_ioc;
+pub mod etw_trace;
+pub mod fs_snapshot;
+pub mod grep;
+pub mod integrity;
+pub mod memory_files;
+pub mod memory_image;
+pub mod netwatch;
+pub mod network_config;
+pub mod output;
+pub mod parsers;
+pub mod pcap;
+pub mod recycle_bin;
+pub mod registry;
+pub mod sandbox;
+pub mod shell_artifacts;
+pub mod ssh_artifacts;
 pub mod store;
+pub mod stream;
 pub mod terminal;
+pub mod usage;
+pub mod usb_history;
+pub mod wmi_persistence;
 pub mod yara;
 
+pub use config::workflow::ErrorCategory;
 use core::fmt;
+use crypto::SessionKey;
+use integrity::CustomFilesIntegrity;
+use privileges::RunAs;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{self, Duration};
+use usage::ResourceUsage;
+use utils::path_cache::PathCache;
 pub struct ActionOptions {
     pub timeout: i32,
     pub parallel: bool,
     pub start_time: time::Instant,
+    // started processes run as this user instead of the collector's user
+    pub run_as: Option<RunAs>,
+    // stdout and stderr written to a file are encrypted with this key
+    pub output_key: Option<Arc<SessionKey>>,
+    // binaries of the custom_files directory are checked against its manifest before they run
+    pub custom_files_integrity: Option<Arc<CustomFilesIntegrity>>,
+    // expansions of the glob patterns, shared by the store, yara and grep actions of the run
+    pub path_cache: Option<Arc<PathCache>>,
 }
 
 impl Default for ActionOptions {
@@ -18,6 +56,10 @@ impl Default for ActionOptions {
             timeout: 0,
             parallel: false,
             start_time: time::Instant::now(),
+            run_as: None,
+            output_key: None,
+            custom_files_integrity: None,
+            path_cache: None,
         }
     }
 }
@@ -30,6 +72,18 @@ pub struct ActionResult {
     pub error_message: Option<String>,
     pub parallel: bool,
     pub finished: bool,
+    // why the action failed, selects the on_error handler of the step
+    pub error_category: Option<ErrorCategory>,
+    // e.g. the number of stored files or observed connections
+    pub items_processed: Option<u64>,
+    // e.g. the size of the stored files
+    pub bytes: Option<u64>,
+    // files written by the action, e.g. action_output/[output_name].csv
+    pub produced_artifacts: Vec<PathBuf>,
+    // problems which didn't fail the action, e.g. a file that could not be read
+    pub warnings: Vec<String>,
+    // CPU time, peak memory and I/O of the started processes
+    pub resource_usage: Option<ResourceUsage>,
 }
 
 impl Default for ActionResult {
@@ -41,6 +95,12 @@ impl Default for ActionResult {
             error_message: None,
             parallel: false,
             finished: false,
+            error_category: None,
+            items_processed: None,
+            bytes: None,
+            produced_artifacts: vec![],
+            warnings: vec![],
+            resource_usage: None,
         }
     }
 }
@@ -65,11 +125,116 @@ impl fmt::Display for ActionResult {
                 self.error_message.as_ref().unwrap_or(&"None".to_string())
             )?;
         }
+        if let Some(category) = &self.error_category {
+            write!(f, "\nError category: {}", category)?;
+        }
+        if let Some(items) = self.items_processed {
+            write!(f, "\nItems processed: {}", items)?;
+        }
+        if let Some(bytes) = self.bytes {
+            write!(f, "\nBytes: {}", bytes)?;
+        }
+        if let Some(usage) = &self.resource_usage {
+            write!(f, "\nResource usage: {}", usage)?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {}", warning)?;
+        }
 
         Ok(())
     }
 }
 
+impl ActionResult {
+    /// Merge the results of a step running once per element (e.g. for each user profile).
+    /// The step succeeds only if every run succeeded.
+    pub fn combine(mut results: Vec<ActionResult>, start_time: time::Instant) -> ActionResult {
+        // parallel runs are handled one by one once they have finished
+        if results.len() == 1 || results.iter().any(|result| !result.finished) {
+            return results.remove(0);
+        }
+
+        let failed: Vec<&ActionResult> = results.iter().filter(|result| !result.success).collect();
+        let errors: Vec<String> = failed
+            .iter()
+            .filter_map(|result| result.error_message.clone())
+            .collect();
+        ActionResult {
+            success: failed.is_empty(),
+            exit_code: match failed.first() {
+                Some(result) => result.exit_code,
+                None => results.last().and_then(|result| result.exit_code),
+            },
+            execution_time: start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("\n")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: failed.first().and_then(|result| result.error_category),
+            items_processed: sum_counts(results.iter().map(|result| result.items_processed)),
+            bytes: sum_counts(results.iter().map(|result| result.bytes)),
+            produced_artifacts: results
+                .iter()
+                .flat_map(|result| result.produced_artifacts.clone())
+                .collect(),
+            warnings: results
+                .iter()
+                .flat_map(|result| result.warnings.clone())
+                .collect(),
+            resource_usage: results
+                .iter()
+                .filter_map(|result| result.resource_usage)
+                .reduce(ResourceUsage::merge),
+        }
+    }
+
+    pub fn with_category(mut self, category: ErrorCategory) -> ActionResult {
+        self.error_category = Some(category);
+        self
+    }
+
+    pub fn with_items(mut self, items: u64) -> ActionResult {
+        self.items_processed = Some(items);
+        self
+    }
+
+    pub fn with_bytes(mut self, bytes: u64) -> ActionResult {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Adds a file written by the action, if it exists (e.g. a log which was enabled)
+    pub fn with_artifact<P: Into<PathBuf>>(self, path: P) -> ActionResult {
+        self.with_artifacts([path.into()])
+    }
+
+    pub fn with_artifacts<I: IntoIterator<Item = PathBuf>>(mut self, paths: I) -> ActionResult {
+        self.produced_artifacts
+            .extend(paths.into_iter().filter(|path| path.is_file()));
+        self
+    }
+
+    pub fn with_resource_usage(mut self, usage: Option<ResourceUsage>) -> ActionResult {
+        self.resource_usage = usage;
+        self
+    }
+
+    pub fn with_warnings(mut self, warnings: Vec<String>) -> ActionResult {
+        self.warnings.extend(warnings);
+        self
+    }
+}
+
+// None if no run reported the count
+fn sum_counts<I: Iterator<Item = Option<u64>>>(counts: I) -> Option<u64> {
+    counts.fold(None, |sum, count| match (sum, count) {
+        (None, None) => None,
+        (sum, count) => Some(sum.unwrap_or(0) + count.unwrap_or(0)),
+    })
+}
+
 #[macro_export]
 macro_rules! error_result {
     ($msg:expr) => {
@@ -80,6 +245,8 @@ macro_rules! error_result {
             error_message: Some($msg.to_string()),
             parallel: false,
             finished: true,
+            error_category: Some($crate::ErrorCategory::Other),
+            ..Default::default()
         }
     };
     ($msg:expr, $start_time:expr) => {
@@ -90,6 +257,8 @@ macro_rules! error_result {
             error_message: Some($msg.to_string()),
             parallel: false,
             finished: true,
+            error_category: Some($crate::ErrorCategory::Other),
+            ..Default::default()
         }
     };
 }
@@ -104,6 +273,8 @@ macro_rules! waiting_result {
             error_message: None,
             parallel: true,
             finished: false,
+            error_category: None,
+            ..Default::default()
         }
     };
 }
@@ -124,3 +295,53 @@ macro_rules! get_stream_error {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_results() {
+        let start_time = time::Instant::now();
+        let success = || ActionResult {
+            success: true,
+            exit_code: Some(0),
+            finished: true,
+            ..Default::default()
+        };
+
+        let result = ActionResult::combine(vec![success(), success()], start_time);
+        assert!(result.success && result.finished);
+        assert_eq!(result.exit_code, Some(0));
+
+        let result = ActionResult::combine(
+            vec![success(), error_result!("profile john"), success()],
+            start_time,
+        );
+        assert!(!result.success);
+        assert_eq!(result.exit_code, Some(-1));
+        assert_eq!(result.error_message, Some("profile john".to_string()));
+
+        // the counts and warnings of all runs are kept
+        let result = ActionResult::combine(
+            vec![
+                success().with_items(2).with_bytes(10),
+                success().with_warnings(vec!["locked".to_string()]),
+                success().with_items(3),
+            ],
+            start_time,
+        );
+        assert_eq!(result.items_processed, Some(5));
+        assert_eq!(result.bytes, Some(10));
+        assert_eq!(result.warnings, vec!["locked".to_string()]);
+        let result = ActionResult::combine(vec![success(), success()], start_time);
+        assert_eq!(result.items_processed, None);
+
+        // nothing to iterate over
+        let result = ActionResult::combine(vec![], start_time);
+        assert!(result.success && result.finished);
+
+        let result = ActionResult::combine(vec![waiting_result!(), waiting_result!()], start_time);
+        assert!(!result.finished);
+    }
+}
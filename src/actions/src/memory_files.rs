@@ -0,0 +1,669 @@
+use config::workflow::MemoryFilesAttributes;
+use flate2::{write::GzEncoder, Compression};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use storage::{exclusions::EXCLUDED, FileProcessor};
+use utils::sanitize::sanitize_dirname;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemorySource {
+    pub path: PathBuf,
+    // pagefile, hiberfil, swap or custom
+    pub kind: String,
+    // swap partitions report a size of 0, so the size is taken from /proc/swaps
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct MemoryFileResult {
+    pub source: String,
+    pub kind: String,
+    pub size: u64,
+    // copy or raw (read from the volume as the file is locked)
+    pub method: String,
+    pub compressed: bool,
+    pub file: String,
+    pub error: Option<String>,
+}
+
+/// Parse /proc/swaps, the size is given in KiB
+pub fn parse_proc_swaps(text: &str) -> Vec<MemorySource> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 3 {
+                return None;
+            }
+            Some(MemorySource {
+                // spaces in the path are escaped as \040
+                path: PathBuf::from(columns[0].replace("\\040", " ")),
+                kind: "swap".to_string(),
+                size: columns[2].parse::<u64>().ok().map(|size| size * 1024),
+            })
+        })
+        .collect()
+}
+
+/// Parse the PagingFiles registry value, e.g. "C:\pagefile.sys 0 0" per line.
+/// A drive of "?" means the page file is managed by the system on the system drive.
+pub fn parse_paging_files(value: &str, system_drive: &str) -> Vec<PathBuf> {
+    value
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            // the sizes follow the path, which may contain spaces
+            let path = match line.to_lowercase().find(".sys") {
+                Some(end) => &line[..end + 4],
+                None => line.split_whitespace().next()?,
+            };
+            match path.strip_prefix("?:") {
+                Some(rest) => Some(PathBuf::from(format!("{}{}", system_drive, rest))),
+                None => Some(PathBuf::from(path)),
+            }
+        })
+        .collect()
+}
+
+fn source(path: PathBuf, kind: &str) -> MemorySource {
+    MemorySource {
+        path,
+        kind: kind.to_string(),
+        size: None,
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn default_sources(types: &[String]) -> Vec<MemorySource> {
+    let system_drive = std::env::var("SystemDrive").unwrap_or("C:".to_string());
+    let mut sources = vec![];
+    for kind in types {
+        match kind.as_str() {
+            "pagefile" => {
+                let key =
+                    "HKLM\\SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Memory Management";
+                let mut paths = system::checks::registry_value(key, "PagingFiles")
+                    .map(|value| parse_paging_files(&value, &system_drive))
+                    .unwrap_or_default();
+                let default = PathBuf::from(format!("{}\\pagefile.sys", system_drive));
+                if !paths.iter().any(|path| {
+                    path.to_string_lossy()
+                        .eq_ignore_ascii_case(&default.to_string_lossy())
+                }) {
+                    paths.push(default);
+                }
+                sources.extend(paths.into_iter().map(|path| source(path, "pagefile")));
+            }
+            "hiberfil" => sources.push(source(
+                PathBuf::from(format!("{}\\hiberfil.sys", system_drive)),
+                "hiberfil",
+            )),
+            // used for modern apps since Windows 8
+            "swap" => sources.push(source(
+                PathBuf::from(format!("{}\\swapfile.sys", system_drive)),
+                "swap",
+            )),
+            _ => warn!("Unknown memory file type: {}", kind),
+        }
+    }
+    sources
+}
+
+#[cfg(target_os = "linux")]
+fn default_sources(types: &[String]) -> Vec<MemorySource> {
+    let mut sources = vec![];
+    for kind in types {
+        match kind.as_str() {
+            "swap" => match fs::read_to_string("/proc/swaps") {
+                Ok(swaps) => sources.extend(parse_proc_swaps(&swaps)),
+                Err(e) => warn!("Failed to read /proc/swaps: {}", e),
+            },
+            // there is no page or hibernation file, hibernation uses the swap space
+            "pagefile" | "hiberfil" => debug!("No {} on Linux", kind),
+            _ => warn!("Unknown memory file type: {}", kind),
+        }
+    }
+    sources
+}
+
+#[cfg(target_os = "macos")]
+fn default_sources(types: &[String]) -> Vec<MemorySource> {
+    let vm_dir = Path::new("/private/var/vm");
+    let mut sources = vec![];
+    for kind in types {
+        match kind.as_str() {
+            "swap" => {
+                let mut swapfiles: Vec<PathBuf> = fs::read_dir(vm_dir)
+                    .map(|entries| {
+                        entries
+                            .flatten()
+                            .map(|entry| entry.path())
+                            .filter(|path| {
+                                path.file_name()
+                                    .map(|name| name.to_string_lossy().starts_with("swapfile"))
+                                    .unwrap_or(false)
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                swapfiles.sort();
+                sources.extend(swapfiles.into_iter().map(|path| source(path, "swap")));
+            }
+            "hiberfil" => sources.push(source(vm_dir.join("sleepimage"), "hiberfil")),
+            "pagefile" => debug!("No pagefile on macOS"),
+            _ => warn!("Unknown memory file type: {}", kind),
+        }
+    }
+    sources
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+fn default_sources(_types: &[String]) -> Vec<MemorySource> {
+    vec![]
+}
+
+/// A contiguous run of clusters of a file on the volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extent {
+    pub vcn: u64,
+    pub clusters: u64,
+    // None for sparse runs
+    pub lcn: Option<u64>,
+}
+
+/// Parse the RETRIEVAL_POINTERS_BUFFER returned by FSCTL_GET_RETRIEVAL_POINTERS.
+/// Returns the extents and the VCN to continue with.
+pub fn parse_retrieval_pointers(buffer: &[u8]) -> (Vec<Extent>, u64) {
+    let read_i64 = |offset: usize| -> Option<i64> {
+        Some(i64::from_le_bytes(
+            buffer.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+    let count = match buffer.get(0..4) {
+        Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap()) as usize,
+        None => return (vec![], 0),
+    };
+    let mut vcn = read_i64(8).unwrap_or(0) as u64;
+    let mut extents = vec![];
+    for i in 0..count {
+        let (next_vcn, lcn) = match (read_i64(16 + i * 16), read_i64(24 + i * 16)) {
+            (Some(next_vcn), Some(lcn)) => (next_vcn as u64, lcn),
+            _ => break,
+        };
+        extents.push(Extent {
+            vcn,
+            clusters: next_vcn.saturating_sub(vcn),
+            lcn: if lcn < 0 { None } else { Some(lcn as u64) },
+        });
+        vcn = next_vcn;
+    }
+    (extents, vcn)
+}
+
+/// Reads a file by its clusters directly from the volume. Used for files that are
+/// locked by the system, e.g. pagefile.sys
+pub struct RawReader<V: Read + Seek> {
+    volume: V,
+    extents: Vec<Extent>,
+    cluster_size: u64,
+    size: u64,
+    position: u64,
+    pending: Vec<u8>,
+    pending_position: usize,
+}
+
+impl<V: Read + Seek> RawReader<V> {
+    pub fn new(volume: V, extents: Vec<Extent>, cluster_size: u64, size: u64) -> Self {
+        Self {
+            volume,
+            extents,
+            cluster_size,
+            size,
+            position: 0,
+            pending: vec![],
+            pending_position: 0,
+        }
+    }
+
+    // Volumes can only be read in whole sectors, so whole clusters are read at once
+    fn fill(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        self.pending_position = 0;
+        if self.position >= self.size {
+            return Ok(());
+        }
+
+        let cluster = self.position / self.cluster_size;
+        let extent = self
+            .extents
+            .iter()
+            .find(|extent| cluster >= extent.vcn && cluster < extent.vcn + extent.clusters)
+            .ok_or_else(|| io::Error::other(format!("No extent for cluster {}", cluster)))?;
+        let max_clusters = (BUFFER_SIZE as u64 / self.cluster_size).max(1);
+        let clusters = (extent.vcn + extent.clusters - cluster).min(max_clusters);
+        let length = (clusters * self.cluster_size) as usize;
+
+        self.pending.resize(length, 0);
+        if let Some(lcn) = extent.lcn {
+            let offset = (lcn + cluster - extent.vcn) * self.cluster_size;
+            self.volume.seek(SeekFrom::Start(offset))?;
+            self.volume.read_exact(&mut self.pending)?;
+        }
+
+        // the last cluster is only partially used by the file
+        let valid = (self.size - self.position).min(length as u64) as usize;
+        self.pending.truncate(valid);
+        self.position += length as u64;
+        Ok(())
+    }
+}
+
+impl<V: Read + Seek> Read for RawReader<V> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_position >= self.pending.len() {
+            self.fill()?;
+            if self.pending.is_empty() {
+                return Ok(0);
+            }
+        }
+        let count = buf.len().min(self.pending.len() - self.pending_position);
+        buf[..count]
+            .copy_from_slice(&self.pending[self.pending_position..self.pending_position + count]);
+        self.pending_position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod raw {
+    use super::{parse_retrieval_pointers, Extent, RawReader};
+    use std::{fs::File, io, os::windows::ffi::OsStrExt, path::Path, ptr::null_mut};
+    use winapi::{
+        shared::winerror::ERROR_MORE_DATA,
+        um::{
+            errhandlingapi::GetLastError,
+            fileapi::{CreateFileW, GetDiskFreeSpaceW, OPEN_EXISTING},
+            handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+            ioapiset::DeviceIoControl,
+            winbase::FILE_FLAG_BACKUP_SEMANTICS,
+            winioctl::FSCTL_GET_RETRIEVAL_POINTERS,
+            winnt::{FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE},
+        },
+    };
+
+    fn to_wide(s: &std::ffi::OsStr) -> Vec<u16> {
+        s.encode_wide().chain(std::iter::once(0)).collect()
+    }
+
+    fn extents(path: &Path) -> io::Result<Vec<Extent>> {
+        let path = to_wide(path.as_os_str());
+        // reading the attributes is allowed for locked files
+        let handle = unsafe {
+            CreateFileW(
+                path.as_ptr(),
+                FILE_READ_ATTRIBUTES,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut extents = vec![];
+        let mut vcn: u64 = 0;
+        let mut buffer = vec![0u8; 64 * 1024];
+        let result = loop {
+            let input = (vcn as i64).to_le_bytes();
+            let mut returned = 0;
+            let ok = unsafe {
+                DeviceIoControl(
+                    handle,
+                    FSCTL_GET_RETRIEVAL_POINTERS,
+                    input.as_ptr() as *mut _,
+                    input.len() as u32,
+                    buffer.as_mut_ptr() as *mut _,
+                    buffer.len() as u32,
+                    &mut returned,
+                    null_mut(),
+                )
+            };
+            let more_data = ok == 0 && unsafe { GetLastError() } == ERROR_MORE_DATA;
+            if ok == 0 && !more_data {
+                break Err(io::Error::last_os_error());
+            }
+            let (mut parsed, next_vcn) = parse_retrieval_pointers(&buffer[..returned as usize]);
+            extents.append(&mut parsed);
+            vcn = next_vcn;
+            if !more_data {
+                break Ok(extents);
+            }
+        };
+        unsafe { CloseHandle(handle) };
+        result
+    }
+
+    fn cluster_size(root: &str) -> io::Result<u64> {
+        let root = to_wide(std::ffi::OsStr::new(root));
+        let (mut sectors, mut bytes, mut free, mut total) = (0, 0, 0, 0);
+        let ok = unsafe {
+            GetDiskFreeSpaceW(
+                root.as_ptr(),
+                &mut sectors,
+                &mut bytes,
+                &mut free,
+                &mut total,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sectors as u64 * bytes as u64)
+    }
+
+    pub fn open(path: &Path, size: u64) -> io::Result<RawReader<File>> {
+        let drive = path
+            .to_string_lossy()
+            .get(..2)
+            .filter(|drive| drive.ends_with(':'))
+            .map(|drive| drive.to_string())
+            .ok_or_else(|| io::Error::other("Raw reads require a path with a drive letter"))?;
+        let cluster_size = cluster_size(&format!("{}\\", drive))?;
+        let extents = extents(path)?;
+        let volume = File::open(format!("\\\\.\\{}", drive))?;
+        Ok(RawReader::new(volume, extents, cluster_size, size))
+    }
+}
+
+// Returns the reader and the method used
+fn open_source(path: &Path, size: u64) -> io::Result<(Box<dyn Read>, &'static str)> {
+    match File::open(path) {
+        Ok(file) => Ok((Box::new(file), "copy")),
+        #[cfg(target_os = "windows")]
+        Err(e) => {
+            debug!("Failed to open {:?} ({}): reading from volume", path, e);
+            let reader = raw::open(path, size)?;
+            Ok((Box::new(reader), "raw"))
+        }
+        #[cfg(not(target_os = "windows"))]
+        Err(e) => {
+            let _ = size;
+            Err(e)
+        }
+    }
+}
+
+/// Copy (and optionally gzip) the reader into the destination file. Returns the number of bytes read
+pub fn copy_to(mut reader: impl Read, dest: &Path, compress: bool) -> io::Result<u64> {
+    let file = BufWriter::new(File::create(dest)?);
+    let mut writer: Box<dyn Write> = match compress {
+        true => Box::new(GzEncoder::new(file, Compression::fast())),
+        false => Box::new(file),
+    };
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut total = 0;
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..count])?;
+        total += count as u64;
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+fn collect(
+    source: &MemorySource,
+    attributes: &MemoryFilesAttributes,
+    out_dir: &Path,
+    result: &mut MemoryFileResult,
+    file_processor: &mut FileProcessor,
+) -> Result<(), String> {
+    let metadata_size = fs::metadata(&source.path)
+        .map_err(|e| format!("Failed to access {:?}: {}", source.path.display(), e))?
+        .len();
+    // block devices report a size of 0
+    let size = match metadata_size {
+        0 => source.size.unwrap_or(0),
+        size => size,
+    };
+    result.size = size;
+
+    if attributes.size_limit > 0 && size > attributes.size_limit {
+        return Err(format!(
+            "Skipping {:?}: size of {} bytes exceeds the size limit",
+            source.path.display(),
+            size
+        ));
+    }
+    if attributes.size_warning > 0 && size > attributes.size_warning {
+        warn!(
+            "{:?} has a size of {} bytes: make sure there is enough disk space",
+            source.path.display(),
+            size
+        );
+    }
+
+    let mut file_name = sanitize_dirname(&source.path.to_string_lossy());
+    if attributes.compress {
+        file_name.push_str(".gz");
+    }
+    let dest = out_dir.join(file_name);
+    let (reader, method) = open_source(&source.path, size)
+        .map_err(|e| format!("Failed to open {:?}: {}", source.path.display(), e))?;
+    result.method = method.to_string();
+
+    info!(
+        "Collecting {:?} ({} bytes, {})",
+        source.path.display(),
+        size,
+        method
+    );
+    let copied = copy_to(reader, &dest, attributes.compress)
+        .map_err(|e| format!("Failed to copy {:?}: {}", source.path.display(), e))?;
+    if copied != size && size > 0 {
+        // swap files may change while they are copied
+        warn!(
+            "Copied {} of {} bytes of {:?}",
+            copied,
+            size,
+            source.path.display()
+        );
+    }
+    result.compressed = attributes.compress;
+    result.file = dest.to_string_lossy().to_string();
+
+    let comment = format!(
+        "source: {}; type: {}; size: {}; method: {}; compressed: {}",
+        result.source, result.kind, size, method, attributes.compress
+    );
+    file_processor
+        .store(&dest, Some(comment))
+        .map_err(|e| format!("Error storing file {:?}: {}", dest.display(), e))
+}
+
+pub struct MemoryFiles {}
+
+impl MemoryFiles {
+    pub fn run(
+        attributes: MemoryFilesAttributes,
+        options: ActionOptions,
+        out_dir: PathBuf,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        let mut sources = default_sources(&attributes.types);
+        sources.extend(
+            attributes
+                .paths
+                .iter()
+                .map(|path| source(PathBuf::from(path), "custom")),
+        );
+        if sources.is_empty() {
+            warn!("No memory files found");
+        }
+
+        if let Err(e) = fs::create_dir_all(&out_dir) {
+            return error_result!(
+                format!("Failed to create {:?}: {}", out_dir.display(), e),
+                options.start_time
+            );
+        }
+
+        let mut results: Vec<MemoryFileResult> = vec![];
+        let mut errors: Vec<String> = vec![];
+        for source in &sources {
+            let mut result = MemoryFileResult {
+                source: source.path.to_string_lossy().to_string(),
+                kind: source.kind.clone(),
+                ..Default::default()
+            };
+            // files that don't exist (e.g. hibernation is disabled) are not an error
+            if !source.path.exists() {
+                debug!("{:?} does not exist", source.path.display());
+                result.error = Some("Not found".to_string());
+                results.push(result);
+                continue;
+            }
+            // the source is copied into the report before it is stored, so the exclusions
+            // have to be checked against the source
+            if file_processor.is_excluded(&source.path) {
+                result.error = Some(EXCLUDED.to_string());
+                results.push(result);
+                continue;
+            }
+            if let Err(e) = collect(source, &attributes, &out_dir, &mut result, file_processor) {
+                error!("{}", e);
+                errors.push(e.clone());
+                result.error = Some(e);
+            }
+            results.push(result);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        if let Err(e) = serde_json::to_writer_pretty(BufWriter::new(file), &results) {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+
+        let collected: Vec<&MemoryFileResult> = results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .collect();
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(collected.len() as u64)
+            .with_bytes(collected.iter().map(|result| result.size).sum())
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Cursor;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_parse_proc_swaps() {
+        let swaps = "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n\
+            /swap\\040file                               file\t\t2097148\t\t0\t\t-2\n\
+            /dev/sda2                               partition\t1000\t\t0\t\t-3\n";
+        let sources = parse_proc_swaps(swaps);
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].path, PathBuf::from("/swap file"));
+        assert_eq!(sources[0].size, Some(2097148 * 1024));
+        assert_eq!(sources[1].path, PathBuf::from("/dev/sda2"));
+    }
+
+    #[test]
+    fn test_parse_paging_files() {
+        let paths = parse_paging_files(
+            "?:\\pagefile.sys\nD:\\Page Files\\pagefile.sys 1024 4096\n",
+            "C:",
+        );
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("C:\\pagefile.sys"),
+                PathBuf::from("D:\\Page Files\\pagefile.sys")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_raw_reader() {
+        // volume with 8 clusters of 4 bytes, the file uses clusters 5-6, a sparse cluster and cluster 1
+        let volume: Vec<u8> = (0..32).collect();
+        let mut buffer = vec![0u8; 16 + 16 * 3];
+        buffer[0..4].copy_from_slice(&3u32.to_le_bytes());
+        let extents = [(2i64, 5i64), (3, -1), (4, 1)];
+        for (i, (next_vcn, lcn)) in extents.iter().enumerate() {
+            buffer[16 + i * 16..24 + i * 16].copy_from_slice(&next_vcn.to_le_bytes());
+            buffer[24 + i * 16..32 + i * 16].copy_from_slice(&lcn.to_le_bytes());
+        }
+        let (extents, next_vcn) = parse_retrieval_pointers(&buffer);
+        assert_eq!(next_vcn, 4);
+        assert_eq!(extents[1].lcn, None);
+
+        let mut reader = RawReader::new(Cursor::new(volume), extents, 4, 14);
+        let mut data = vec![];
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![20, 21, 22, 23, 24, 25, 26, 27, 0, 0, 0, 0, 4, 5]);
+    }
+
+    #[test]
+    fn test_copy_to_compressed() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_memory_files_copy_to");
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 7) as u8).collect();
+        let dest = tmp_dir.join("swapfile.gz");
+
+        let copied = copy_to(Cursor::new(data.clone()), &dest, true).unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert!(fs::metadata(&dest).unwrap().len() < data.len() as u64);
+
+        let mut decompressed = vec![];
+        GzDecoder::new(File::open(&dest).unwrap())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -0,0 +1,543 @@
+use config::workflow::MemoryImageAttributes;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use storage::FileProcessor;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+const PAGE_SIZE: usize = 4096;
+// "EMiL" in little endian, see https://github.com/504ensicsLabs/LiME/blob/master/doc/README.md
+const LIME_MAGIC: u32 = 0x4C69_4D45;
+const LIME_VERSION: u32 = 1;
+const PT_LOAD: u32 = 1;
+
+/// A range of physical memory (end is inclusive, like in /proc/iomem)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MemoryRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl MemoryRange {
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// A physical range and its offset in the source (/proc/kcore or /dev/crash)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryBlock {
+    pub range: MemoryRange,
+    pub offset: u64,
+}
+
+/// A loadable segment of the ELF core file /proc/kcore
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub offset: u64,
+    pub paddr: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct ImageSummary {
+    pub source: String,
+    pub file: String,
+    pub ranges: Vec<MemoryRange>,
+    pub size: u64,
+    // pages that couldn't be read are written as zeros
+    pub unreadable: u64,
+}
+
+/// Parse the "System RAM" ranges of /proc/iomem. Without root privileges all addresses are 0
+pub fn parse_iomem(text: &str) -> Vec<MemoryRange> {
+    text.lines()
+        // nested entries (e.g. Kernel code) are part of a top level range
+        .filter(|line| !line.starts_with(' '))
+        .filter_map(|line| {
+            let (range, name) = line.split_once(" : ")?;
+            if name.trim() != "System RAM" {
+                return None;
+            }
+            let (start, end) = range.trim().split_once('-')?;
+            Some(MemoryRange {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        buffer.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        buffer.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        buffer.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Parse the program headers of a 64-bit little endian ELF core file. Only loadable
+/// segments with a physical address (memory of /proc/kcore) are returned
+pub fn parse_elf_segments<R: Read + Seek>(reader: &mut R) -> Result<Vec<Segment>, String> {
+    let mut header = [0u8; 64];
+    reader
+        .seek(SeekFrom::Start(0))
+        .and_then(|_| reader.read_exact(&mut header))
+        .map_err(|e| format!("Failed to read ELF header: {}", e))?;
+    // ELFCLASS64 and ELFDATA2LSB
+    if &header[0..4] != b"\x7fELF" || header[4] != 2 || header[5] != 1 {
+        return Err("Not a 64-bit little endian ELF file".to_string());
+    }
+
+    let phoff = read_u64(&header, 0x20).unwrap_or(0);
+    let phentsize = read_u16(&header, 0x36).unwrap_or(0) as usize;
+    let phnum = read_u16(&header, 0x38).unwrap_or(0) as usize;
+    if phentsize < 56 {
+        return Err(format!("Invalid program header size: {}", phentsize));
+    }
+
+    let mut headers = vec![0u8; phentsize * phnum];
+    reader
+        .seek(SeekFrom::Start(phoff))
+        .and_then(|_| reader.read_exact(&mut headers))
+        .map_err(|e| format!("Failed to read program headers: {}", e))?;
+
+    Ok(headers
+        .chunks_exact(phentsize)
+        .filter_map(|ph| {
+            if read_u32(ph, 0)? != PT_LOAD {
+                return None;
+            }
+            let paddr = read_u64(ph, 24)?;
+            // vmalloc and module areas have no physical address
+            if paddr == u64::MAX {
+                return None;
+            }
+            Some(Segment {
+                offset: read_u64(ph, 8)?,
+                paddr,
+                size: read_u64(ph, 32)?,
+            })
+        })
+        .collect())
+}
+
+/// Map the physical ranges to the segments of /proc/kcore. Parts of a range
+/// without a segment can't be read and are left out of the image
+pub fn map_ranges(ranges: &[MemoryRange], segments: &[Segment]) -> Vec<MemoryBlock> {
+    let mut blocks = vec![];
+    for range in ranges {
+        let mut address = range.start;
+        while address <= range.end {
+            let segment = segments
+                .iter()
+                .find(|segment| address >= segment.paddr && address - segment.paddr < segment.size);
+            match segment {
+                Some(segment) => {
+                    let end = range.end.min(segment.paddr + segment.size - 1);
+                    blocks.push(MemoryBlock {
+                        range: MemoryRange {
+                            start: address,
+                            end,
+                        },
+                        offset: segment.offset + (address - segment.paddr),
+                    });
+                    match end.checked_add(1) {
+                        Some(next) => address = next,
+                        None => break,
+                    }
+                }
+                // continue with the next segment inside the range
+                None => match segments
+                    .iter()
+                    .map(|segment| segment.paddr)
+                    .filter(|paddr| *paddr > address && *paddr <= range.end)
+                    .min()
+                {
+                    Some(next) => address = next,
+                    None => break,
+                },
+            }
+        }
+    }
+    blocks
+}
+
+/// Header written in front of each range of a LiME image
+pub fn lime_header(range: &MemoryRange) -> [u8; 32] {
+    let mut header = [0u8; 32];
+    header[0..4].copy_from_slice(&LIME_MAGIC.to_le_bytes());
+    header[4..8].copy_from_slice(&LIME_VERSION.to_le_bytes());
+    header[8..16].copy_from_slice(&range.start.to_le_bytes());
+    header[16..24].copy_from_slice(&range.end.to_le_bytes());
+    header
+}
+
+// Some sources (e.g. /dev/crash) return at most one page per read
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            count => filled += count,
+        }
+    }
+    Ok(())
+}
+
+/// Write the blocks of the source as LiME image. Returns the number of bytes that
+/// couldn't be read, they are written as zeros to keep the offsets intact
+pub fn write_lime<R: Read + Seek, W: Write>(
+    source: &mut R,
+    blocks: &[MemoryBlock],
+    writer: &mut W,
+    pb: &ProgressBar,
+) -> io::Result<u64> {
+    let mut unreadable = 0;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    for block in blocks {
+        writer.write_all(&lime_header(&block.range))?;
+        let mut position = 0;
+        while position < block.range.size() {
+            let size = (block.range.size() - position).min(BUFFER_SIZE as u64) as usize;
+            let chunk = &mut buffer[..size];
+            let offset = block.offset + position;
+            let result = source
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| read_full(source, chunk));
+            // retry page by page, so only the unreadable pages are lost
+            if result.is_err() {
+                for (i, page) in chunk.chunks_mut(PAGE_SIZE).enumerate() {
+                    let result = source
+                        .seek(SeekFrom::Start(offset + (i * PAGE_SIZE) as u64))
+                        .and_then(|_| read_full(source, page));
+                    if result.is_err() {
+                        page.fill(0);
+                        unreadable += page.len() as u64;
+                    }
+                }
+            }
+            writer.write_all(chunk)?;
+            position += size as u64;
+            pb.inc(size as u64);
+        }
+    }
+    writer.flush()?;
+    Ok(unreadable)
+}
+
+#[cfg(all(target_os = "linux", feature = "memory_image"))]
+fn open_source(source: &str) -> Result<(String, File, Vec<MemoryBlock>), String> {
+    let iomem = fs::read_to_string("/proc/iomem")
+        .map_err(|e| format!("Failed to read /proc/iomem: {}", e))?;
+    let ranges = parse_iomem(&iomem);
+    if ranges.is_empty() || ranges.iter().all(|range| range.end == 0) {
+        return Err("No physical memory ranges found: root privileges are required".to_string());
+    }
+
+    let sources = match source {
+        "auto" => vec!["crash", "kcore"],
+        "kcore" | "crash" => vec![source],
+        _ => return Err(format!("Unknown memory source: {}", source)),
+    };
+    let mut errors = vec![];
+    for source in sources {
+        let result = match source {
+            // the physical address is the offset in /dev/crash
+            "crash" => File::open("/dev/crash").map(|file| {
+                let blocks = ranges
+                    .iter()
+                    .map(|range| MemoryBlock {
+                        range: range.clone(),
+                        offset: range.start,
+                    })
+                    .collect();
+                (file, blocks)
+            }),
+            _ => File::open("/proc/kcore").and_then(|mut file| {
+                let segments = parse_elf_segments(&mut file).map_err(io::Error::other)?;
+                Ok((file, map_ranges(&ranges, &segments)))
+            }),
+        };
+        match result {
+            Ok((file, blocks)) if !blocks.is_empty() => {
+                return Ok((source.to_string(), file, blocks))
+            }
+            Ok(_) => errors.push(format!("{}: no readable memory ranges", source)),
+            Err(e) => errors.push(format!("{}: {}", source, e)),
+        }
+    }
+    Err(format!(
+        "No memory source available ({})",
+        errors.join("; ")
+    ))
+}
+
+#[cfg(not(all(target_os = "linux", feature = "memory_image")))]
+fn open_source(_source: &str) -> Result<(String, File, Vec<MemoryBlock>), String> {
+    match cfg!(target_os = "linux") {
+        true => Err("The collector was built without the memory_image feature".to_string()),
+        false => Err("Memory acquisition is only supported on Linux".to_string()),
+    }
+}
+
+fn acquire(
+    attributes: &MemoryImageAttributes,
+    out_dir: &Path,
+    output_name: &str,
+    file_processor: &mut FileProcessor,
+) -> Result<ImageSummary, String> {
+    let (source, mut reader, blocks) = open_source(&attributes.source)?;
+
+    // the size of the image without the LiME headers
+    let size: u64 = blocks.iter().map(|block| block.range.size()).sum();
+    info!(
+        "Acquiring {} bytes of physical memory in {} ranges from {}",
+        size,
+        blocks.len(),
+        source
+    );
+    if attributes.size_limit > 0 && size > attributes.size_limit {
+        return Err(format!(
+            "Physical memory of {} bytes exceeds the size limit",
+            size
+        ));
+    }
+
+    fs::create_dir_all(out_dir)
+        .map_err(|e| format!("Failed to create {:?}: {}", out_dir.display(), e))?;
+    let image_path = out_dir.join(format!("{}.lime", output_name));
+    let file = File::create(&image_path)
+        .map_err(|e| format!("Error creating file {:?}: {}", image_path.display(), e))?;
+
+    let pb = ProgressBar::new(size);
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+        )
+        .unwrap()
+        .progress_chars("=>-"),
+    );
+    let unreadable = write_lime(&mut reader, &blocks, &mut BufWriter::new(file), &pb)
+        .map_err(|e| format!("Error writing image {:?}: {}", image_path.display(), e))?;
+    pb.finish_and_clear();
+    if unreadable > 0 {
+        warn!("{} bytes of physical memory could not be read", unreadable);
+    }
+
+    let summary = ImageSummary {
+        source: source.clone(),
+        file: image_path.to_string_lossy().to_string(),
+        ranges: blocks.into_iter().map(|block| block.range).collect(),
+        size,
+        unreadable,
+    };
+    let comment = format!(
+        "source: {}; format: lime; size: {}; unreadable: {}",
+        source, size, unreadable
+    );
+    file_processor
+        .store(&image_path, Some(comment))
+        .map_err(|e| format!("Error storing file {:?}: {}", image_path.display(), e))?;
+    Ok(summary)
+}
+
+pub struct MemoryImage {}
+
+impl MemoryImage {
+    pub fn run(
+        attributes: MemoryImageAttributes,
+        options: ActionOptions,
+        out_dir: PathBuf,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        let output_name = out_file
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or("memory".to_string());
+        let summary = match acquire(&attributes, &out_dir, &output_name, file_processor) {
+            Ok(summary) => summary,
+            Err(e) => return error_result!(e, options.start_time),
+        };
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        if let Err(e) = serde_json::to_writer_pretty(BufWriter::new(file), &summary) {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+
+        let mut warnings = vec![];
+        if summary.unreadable > 0 {
+            warnings.push(format!(
+                "{} bytes could not be read and were written as zeros",
+                summary.unreadable
+            ));
+        }
+        let result = ActionResult {
+            success: true,
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: None,
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_bytes(summary.size)
+            .with_artifact(out_file)
+            .with_warnings(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_iomem() {
+        let iomem = "00000000-00000fff : Reserved\n\
+            00001000-0009fbff : System RAM\n\
+            00100000-bffdffff : System RAM\n\
+            \x20 01000000-01e0315f : Kernel code\n\
+            fec00000-fec003ff : IOAPIC 0\n";
+        assert_eq!(
+            parse_iomem(iomem),
+            vec![
+                MemoryRange {
+                    start: 0x1000,
+                    end: 0x9fbff
+                },
+                MemoryRange {
+                    start: 0x100000,
+                    end: 0xbffdffff
+                },
+            ]
+        );
+    }
+
+    fn elf_core(segments: &[(u32, u64, u64, u64)]) -> Vec<u8> {
+        let mut elf = vec![0u8; 64 + 56 * segments.len()];
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for (i, (p_type, offset, paddr, size)) in segments.iter().enumerate() {
+            let ph = &mut elf[64 + i * 56..64 + (i + 1) * 56];
+            ph[0..4].copy_from_slice(&p_type.to_le_bytes());
+            ph[8..16].copy_from_slice(&offset.to_le_bytes());
+            ph[24..32].copy_from_slice(&paddr.to_le_bytes());
+            ph[32..40].copy_from_slice(&size.to_le_bytes());
+        }
+        elf
+    }
+
+    #[test]
+    fn test_parse_elf_segments_and_map_ranges() {
+        // a note, vmalloc (no physical address) and two RAM segments
+        let elf = elf_core(&[
+            (4, 0, 0, 0),
+            (PT_LOAD, 0x1000, u64::MAX, 0x100),
+            (PT_LOAD, 0x2000, 0x0, 0x100),
+            (PT_LOAD, 0x3000, 0x200, 0x100),
+        ]);
+        let segments = parse_elf_segments(&mut Cursor::new(elf)).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].paddr, 0x200);
+
+        // the gap between both segments is not part of the image
+        let blocks = map_ranges(
+            &[MemoryRange {
+                start: 0x80,
+                end: 0x27f,
+            }],
+            &segments,
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                MemoryBlock {
+                    range: MemoryRange {
+                        start: 0x80,
+                        end: 0xff
+                    },
+                    offset: 0x2080
+                },
+                MemoryBlock {
+                    range: MemoryRange {
+                        start: 0x200,
+                        end: 0x27f
+                    },
+                    offset: 0x3000
+                },
+            ]
+        );
+
+        assert!(parse_elf_segments(&mut Cursor::new(vec![0u8; 64])).is_err());
+    }
+
+    #[test]
+    fn test_write_lime() {
+        let source: Vec<u8> = (0..=255).collect();
+        let blocks = vec![
+            MemoryBlock {
+                range: MemoryRange { start: 0, end: 3 },
+                offset: 16,
+            },
+            // beyond the end of the source
+            MemoryBlock {
+                range: MemoryRange {
+                    start: 0x1000,
+                    end: 0x1001,
+                },
+                offset: 1000,
+            },
+        ];
+        let mut image = vec![];
+        let unreadable = write_lime(
+            &mut Cursor::new(source),
+            &blocks,
+            &mut image,
+            &ProgressBar::hidden(),
+        )
+        .unwrap();
+        assert_eq!(unreadable, 2);
+        assert_eq!(image.len(), 32 + 4 + 32 + 2);
+        assert_eq!(&image[0..4], b"EMiL");
+        assert_eq!(&image[16..24], &3u64.to_le_bytes());
+        assert_eq!(&image[32..36], &[16, 17, 18, 19]);
+        assert_eq!(&image[44..52], &0x1000u64.to_le_bytes());
+        assert_eq!(&image[68..70], &[0, 0]);
+    }
+}
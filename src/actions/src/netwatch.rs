@@ -0,0 +1,664 @@
+// Samples the network connections of all processes over an interval, so short-lived
+// connections (e.g. a beacon calling home every few seconds) are caught, which a
+// single netstat snapshot most likely misses
+use chrono::{DateTime, Utc};
+use config::workflow::NetwatchAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use super::{error_result, ActionOptions, ActionResult};
+
+/// A connection as seen in a single sample
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Connection {
+    pub protocol: String,
+    pub local_address: String,
+    pub local_port: u16,
+    pub remote_address: String,
+    pub remote_port: u16,
+    pub state: String,
+    pub pid: Option<u32>,
+    pub process: String,
+}
+
+// the state and the process name may change between samples without being a new connection
+type ConnectionKey = (String, String, u16, String, u16, Option<u32>);
+
+impl Connection {
+    fn key(&self) -> ConnectionKey {
+        (
+            self.protocol.clone(),
+            self.local_address.clone(),
+            self.local_port,
+            self.remote_address.clone(),
+            self.remote_port,
+            self.pid,
+        )
+    }
+}
+
+/// One row of the resulting csv file
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ObservedConnection {
+    pub protocol: String,
+    pub local_address: String,
+    pub local_port: u16,
+    pub remote_address: String,
+    pub remote_port: u16,
+    // state in the last sample the connection was seen in
+    pub state: String,
+    pub pid: Option<u32>,
+    pub process: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    // number of samples the connection was seen in
+    pub samples: usize,
+    // neither present in the first nor in the last sample
+    pub short_lived: bool,
+}
+
+#[derive(Debug)]
+struct Observation {
+    connection: Connection,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    first_sample: usize,
+    last_sample: usize,
+    samples: usize,
+}
+
+/// Merges the samples into one record per connection
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+    samples: usize,
+    observations: BTreeMap<ConnectionKey, Observation>,
+}
+
+impl ConnectionTracker {
+    pub fn add_sample(&mut self, connections: Vec<Connection>, time: DateTime<Utc>) {
+        let sample = self.samples;
+        self.samples += 1;
+        for connection in connections {
+            let observation = self
+                .observations
+                .entry(connection.key())
+                .or_insert_with(|| Observation {
+                    connection: connection.clone(),
+                    first_seen: time,
+                    last_seen: time,
+                    first_sample: sample,
+                    last_sample: sample,
+                    samples: 0,
+                });
+            // a connection may be listed twice within a sample (e.g. by netstat for dual-stack sockets)
+            if observation.samples > 0 && observation.last_sample == sample {
+                continue;
+            }
+            observation.last_seen = time;
+            observation.last_sample = sample;
+            observation.samples += 1;
+            if !connection.state.is_empty() {
+                observation.connection.state = connection.state;
+            }
+            if !connection.process.is_empty() {
+                observation.connection.process = connection.process;
+            }
+        }
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The observed connections, short-lived ones first
+    pub fn finish(self) -> Vec<ObservedConnection> {
+        let last = self.samples.saturating_sub(1);
+        let mut observed: Vec<ObservedConnection> = self
+            .observations
+            .into_values()
+            .map(|observation| ObservedConnection {
+                protocol: observation.connection.protocol,
+                local_address: observation.connection.local_address,
+                local_port: observation.connection.local_port,
+                remote_address: observation.connection.remote_address,
+                remote_port: observation.connection.remote_port,
+                state: observation.connection.state,
+                pid: observation.connection.pid,
+                process: observation.connection.process,
+                first_seen: observation.first_seen.to_rfc3339(),
+                last_seen: observation.last_seen.to_rfc3339(),
+                samples: observation.samples,
+                short_lived: observation.first_sample > 0 && observation.last_sample < last,
+            })
+            .collect();
+        // stable sort, so the connections stay ordered by protocol and address otherwise
+        observed.sort_by_key(|connection| !connection.short_lived);
+        observed
+    }
+}
+
+// Splits an endpoint like 10.0.0.1:443, [::1]:631 or *:* into address and port
+fn split_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    let (address, port) = endpoint.rsplit_once(':')?;
+    let address = address.trim_start_matches('[').trim_end_matches(']');
+    let port = match port {
+        "*" => 0,
+        port => port.parse().ok()?,
+    };
+    Some((address.to_string(), port))
+}
+
+fn tcp_state(code: &str) -> &'static str {
+    match code {
+        "01" => "ESTABLISHED",
+        "02" => "SYN_SENT",
+        "03" => "SYN_RECV",
+        "04" => "FIN_WAIT1",
+        "05" => "FIN_WAIT2",
+        "06" => "TIME_WAIT",
+        "07" => "CLOSE",
+        "08" => "CLOSE_WAIT",
+        "09" => "LAST_ACK",
+        "0A" => "LISTEN",
+        "0B" => "CLOSING",
+        _ => "UNKNOWN",
+    }
+}
+
+// The kernel prints the address as 32 bit words in host byte order
+fn parse_proc_address(hex: &str) -> Option<String> {
+    let mut bytes = vec![];
+    for index in (0..hex.len()).step_by(8) {
+        let word = u32::from_str_radix(hex.get(index..index + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    match bytes.len() {
+        4 => Some(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).to_string()),
+        16 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+fn parse_proc_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    let (address, port) = endpoint.split_once(':')?;
+    Some((
+        parse_proc_address(address)?,
+        u16::from_str_radix(port, 16).ok()?,
+    ))
+}
+
+/// Parses /proc/net/{tcp,tcp6,udp,udp6} into connections and the inodes of their sockets
+pub fn parse_proc_net(protocol: &str, text: &str) -> Vec<(Connection, u64)> {
+    let mut connections = vec![];
+    for line in text.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            continue;
+        }
+        let (Some(local), Some(remote)) = (
+            parse_proc_endpoint(fields[1]),
+            parse_proc_endpoint(fields[2]),
+        ) else {
+            continue;
+        };
+        let state = match (protocol.starts_with("udp"), fields[3]) {
+            // unconnected udp socket
+            (true, "07") => "",
+            _ => tcp_state(fields[3]),
+        };
+        let connection = Connection {
+            protocol: protocol.to_string(),
+            local_address: local.0,
+            local_port: local.1,
+            remote_address: remote.0,
+            remote_port: remote.1,
+            state: state.to_string(),
+            ..Default::default()
+        };
+        connections.push((connection, fields[9].parse().unwrap_or(0)));
+    }
+    connections
+}
+
+/// Parses the output of netstat -ano (Windows)
+pub fn parse_netstat(text: &str) -> Vec<Connection> {
+    let mut connections = vec![];
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // udp sockets have no state
+        let (state, pid) = match fields.len() {
+            4 => ("", fields[3]),
+            5 => (fields[3], fields[4]),
+            _ => continue,
+        };
+        let protocol = fields[0].to_lowercase();
+        if protocol != "tcp" && protocol != "udp" {
+            continue;
+        }
+        let (Some(local), Some(remote)) = (split_endpoint(fields[1]), split_endpoint(fields[2]))
+        else {
+            continue;
+        };
+        let protocol = match local.0.contains(':') {
+            true => format!("{}6", protocol),
+            false => protocol,
+        };
+        connections.push(Connection {
+            protocol,
+            local_address: local.0,
+            local_port: local.1,
+            remote_address: remote.0,
+            remote_port: remote.1,
+            state: state.to_string(),
+            pid: pid.parse().ok(),
+            process: "".to_string(),
+        });
+    }
+    connections
+}
+
+/// Parses the output of lsof -nP -i (macOS)
+pub fn parse_lsof(text: &str) -> Vec<Connection> {
+    let mut connections = vec![];
+    for line in text.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // the node column is followed by the endpoints and optionally the state
+        let Some(node) = fields
+            .iter()
+            .skip(4)
+            .position(|field| *field == "TCP" || *field == "UDP")
+            .map(|position| position + 4)
+        else {
+            continue;
+        };
+        let Some(name) = fields.get(node + 1) else {
+            continue;
+        };
+        let (local, remote) = name.split_once("->").unwrap_or((name, "*:*"));
+        let (Some(local), Some(remote)) = (split_endpoint(local), split_endpoint(remote)) else {
+            continue;
+        };
+        let ip_version = match fields.get(4) {
+            Some(&"IPv6") => "6",
+            _ => "",
+        };
+        let state = fields
+            .get(node + 2)
+            .map(|state| state.trim_matches(|c| c == '(' || c == ')'))
+            .unwrap_or_default();
+        connections.push(Connection {
+            protocol: format!("{}{}", fields[node].to_lowercase(), ip_version),
+            local_address: local.0,
+            local_port: local.1,
+            remote_address: remote.0,
+            remote_port: remote.1,
+            state: state.to_string(),
+            pid: fields[1].parse().ok(),
+            // lsof escapes spaces in the command name
+            process: fields[0].replace("\\x20", " "),
+        });
+    }
+    connections
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_tool(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    // lsof exits with 1 if no connection matches
+    if !output.status.success() && output.stdout.is_empty() {
+        let stderr: String = String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .chars()
+            .take(200)
+            .collect();
+        return Err(format!("{} {} failed: {}", program, args.join(" "), stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Lists the current connections of all processes
+#[derive(Debug, Default)]
+struct Sampler {
+    // process id and name by socket inode (Linux) or process name by id (Windows)
+    #[cfg(target_os = "linux")]
+    sockets: std::collections::HashMap<u64, (u32, String)>,
+    #[cfg(target_os = "windows")]
+    processes: std::collections::HashMap<u32, String>,
+}
+
+impl Sampler {
+    #[cfg(target_os = "linux")]
+    fn refresh_sockets(&mut self) {
+        self.sockets.clear();
+        for entry in std::fs::read_dir("/proc").into_iter().flatten().flatten() {
+            let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            let process = std::fs::read_to_string(entry.path().join("comm"))
+                .map(|comm| comm.trim().to_string())
+                .unwrap_or_default();
+            for fd in std::fs::read_dir(entry.path().join("fd"))
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let Ok(target) = std::fs::read_link(fd.path()) else {
+                    continue;
+                };
+                let target = target.to_string_lossy();
+                if let Some(inode) = target
+                    .strip_prefix("socket:[")
+                    .and_then(|inode| inode.strip_suffix(']'))
+                    .and_then(|inode| inode.parse().ok())
+                {
+                    self.sockets.insert(inode, (pid, process.clone()));
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sample(&mut self) -> Result<Vec<Connection>, String> {
+        let mut sockets = vec![];
+        for protocol in ["tcp", "tcp6", "udp", "udp6"] {
+            // ipv6 may be disabled
+            if let Ok(text) = std::fs::read_to_string(format!("/proc/net/{}", protocol)) {
+                sockets.extend(parse_proc_net(protocol, &text));
+            }
+        }
+        if sockets.is_empty() {
+            return Err("Failed to read /proc/net".to_string());
+        }
+        // the processes are only looked up again if a socket is new, sockets in TIME_WAIT have no inode
+        if sockets
+            .iter()
+            .any(|(_, inode)| *inode != 0 && !self.sockets.contains_key(inode))
+        {
+            self.refresh_sockets();
+        }
+        Ok(sockets
+            .into_iter()
+            .map(|(mut connection, inode)| {
+                if let Some((pid, process)) = self.sockets.get(&inode) {
+                    connection.pid = Some(*pid);
+                    connection.process = process.clone();
+                }
+                connection
+            })
+            .collect())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn sample(&mut self) -> Result<Vec<Connection>, String> {
+        let mut connections = parse_netstat(&run_tool("netstat", &["-ano"])?);
+        let unknown = connections.iter().any(|connection| {
+            connection
+                .pid
+                .is_some_and(|pid| !self.processes.contains_key(&pid))
+        });
+        if unknown {
+            match run_tool("tasklist", &["/fo", "csv", "/nh"]) {
+                Ok(text) => {
+                    let mut reader = csv::ReaderBuilder::new()
+                        .has_headers(false)
+                        .from_reader(text.as_bytes());
+                    for record in reader.records().flatten() {
+                        if let (Some(name), Some(Ok(pid))) =
+                            (record.get(0), record.get(1).map(|pid| pid.parse()))
+                        {
+                            self.processes.insert(pid, name.to_string());
+                        }
+                    }
+                }
+                Err(e) => debug!("Failed to list processes: {}", e),
+            }
+        }
+        for connection in &mut connections {
+            if let Some(process) = connection.pid.and_then(|pid| self.processes.get(&pid)) {
+                connection.process = process.clone();
+            }
+        }
+        Ok(connections)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn sample(&mut self) -> Result<Vec<Connection>, String> {
+        Ok(parse_lsof(&run_tool("lsof", &["-nP", "-i"])?))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+    fn sample(&mut self) -> Result<Vec<Connection>, String> {
+        Err("Listing network connections is not supported on this platform".to_string())
+    }
+}
+
+pub struct Netwatch {}
+
+impl Netwatch {
+    pub fn run(
+        attributes: NetwatchAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+    ) -> ActionResult {
+        if attributes.interval == 0 {
+            return error_result!("The interval must be at least 1 second", options.start_time);
+        }
+        info!(
+            "Sampling network connections every {} seconds for {} seconds",
+            attributes.interval, attributes.duration
+        );
+
+        let interval = Duration::from_secs(attributes.interval);
+        let duration = Duration::from_secs(attributes.duration);
+        let mut sampler = Sampler::default();
+        let mut tracker = ConnectionTracker::default();
+        let mut errors: Vec<String> = vec![];
+        let start = Instant::now();
+        let mut next_sample = start;
+        loop {
+            match sampler.sample() {
+                Ok(connections) => tracker.add_sample(connections, Utc::now()),
+                Err(e) => errors.push(e),
+            }
+            // the last sample is taken at the end of the duration
+            next_sample += interval;
+            if next_sample.duration_since(start) > duration {
+                break;
+            }
+            std::thread::sleep(next_sample.saturating_duration_since(Instant::now()));
+        }
+
+        let samples = tracker.samples();
+        errors.dedup();
+        for error in &errors {
+            warn!("{}", error);
+        }
+        if samples == 0 {
+            return error_result!(errors.join("; "), options.start_time);
+        }
+        let connections = tracker.finish();
+        info!(
+            "Observed {} connections in {} samples, {} of them short-lived",
+            connections.len(),
+            samples,
+            connections.iter().filter(|c| c.short_lived).count()
+        );
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        let mut writer = csv::Writer::from_writer(file);
+        for connection in &connections {
+            if let Err(e) = writer.serialize(connection) {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote network connections to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(connections.len() as u64)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(remote: &str, remote_port: u16, pid: u32) -> Connection {
+        Connection {
+            protocol: "tcp".to_string(),
+            local_address: "10.0.0.2".to_string(),
+            local_port: 50000 + remote_port,
+            remote_address: remote.to_string(),
+            remote_port,
+            state: "ESTABLISHED".to_string(),
+            pid: Some(pid),
+            process: "svchost.exe".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_connection_tracker() {
+        let listener = Connection {
+            state: "LISTEN".to_string(),
+            ..connection("0.0.0.0", 0, 4)
+        };
+        let beacon = connection("203.0.113.7", 443, 1234);
+        let late = connection("198.51.100.1", 80, 99);
+        let time = Utc::now();
+
+        let mut tracker = ConnectionTracker::default();
+        tracker.add_sample(vec![listener.clone()], time);
+        tracker.add_sample(vec![listener.clone(), beacon.clone(), beacon.clone()], time);
+        tracker.add_sample(
+            vec![
+                listener.clone(),
+                Connection {
+                    state: "TIME_WAIT".to_string(),
+                    ..beacon.clone()
+                },
+            ],
+            time,
+        );
+        tracker.add_sample(vec![listener.clone(), late.clone()], time);
+        assert_eq!(tracker.samples(), 4);
+
+        let observed = tracker.finish();
+        assert_eq!(observed.len(), 3);
+        // the beacon is neither in the first nor in the last sample
+        assert_eq!(observed[0].remote_address, "203.0.113.7");
+        assert!(observed[0].short_lived);
+        assert_eq!(observed[0].samples, 2);
+        assert_eq!(observed[0].state, "TIME_WAIT");
+        assert!(observed[1..].iter().all(|c| !c.short_lived));
+        assert_eq!(
+            observed.iter().find(|c| c.pid == Some(4)).unwrap().samples,
+            4
+        );
+    }
+
+    #[test]
+    fn test_parse_proc_net() {
+        let text = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0277 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21334 1 0000000000000000 100 0 0 10 0
+   1: 0200000A:C350 0771CBCB:01BB 01 00000000:00000000 02:000A7C8B 00000000  1000        0 43210 2 0000000000000000 20 4 30 10 -1
+";
+        let connections = parse_proc_net("tcp", text);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].0.local_address, "127.0.0.1");
+        assert_eq!(connections[0].0.local_port, 631);
+        assert_eq!(connections[0].0.state, "LISTEN");
+        assert_eq!(connections[0].1, 21334);
+        assert_eq!(connections[1].0.remote_address, "203.203.113.7");
+        assert_eq!(connections[1].0.remote_port, 443);
+        assert_eq!(connections[1].0.state, "ESTABLISHED");
+
+        let text = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000001000000:0016 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1234 1
+";
+        let connections = parse_proc_net("tcp6", text);
+        assert_eq!(connections[0].0.local_address, "::1");
+        assert_eq!(connections[0].0.local_port, 22);
+
+        let text = "header\n 0: 00000000:14E9 00000000:0000 07 00000000:00000000 00:00000000 00000000 0 0 555 2\n";
+        assert_eq!(parse_proc_net("udp", text)[0].0.state, "");
+    }
+
+    #[test]
+    fn test_parse_netstat_and_lsof() {
+        let text = "
+Active Connections
+
+  Proto  Local Address          Foreign Address        State           PID
+  TCP    0.0.0.0:135            0.0.0.0:0              LISTENING       1044
+  TCP    10.0.0.2:49712         203.0.113.7:443        ESTABLISHED     6120
+  TCP    [::]:445               [::]:0                 LISTENING       4
+  UDP    0.0.0.0:5353           *:*                                    2784
+";
+        let connections = parse_netstat(text);
+        assert_eq!(connections.len(), 4);
+        assert_eq!(connections[1].remote_address, "203.0.113.7");
+        assert_eq!(connections[1].pid, Some(6120));
+        assert_eq!(connections[2].protocol, "tcp6");
+        assert_eq!(connections[2].local_address, "::");
+        assert_eq!(connections[3].protocol, "udp");
+        assert_eq!(connections[3].state, "");
+        assert_eq!(connections[3].remote_port, 0);
+
+        let text = "COMMAND     PID   USER   FD   TYPE             DEVICE SIZE/OFF NODE NAME
+launchd       1   root   10u  IPv6 0x1a2b3c4d5e6f7a8b      0t0  TCP *:22 (LISTEN)
+Google\\x20C  912  alice   25u  IPv4 0x1a2b3c4d5e6f7a8c      0t0  TCP 10.0.0.2:50123->203.0.113.7:443 (ESTABLISHED)
+mDNSRespo   200 _mdns    8u  IPv4 0x1a2b3c4d5e6f7a8d      0t0  UDP *:5353
+";
+        let connections = parse_lsof(text);
+        assert_eq!(connections.len(), 3);
+        assert_eq!(connections[0].protocol, "tcp6");
+        assert_eq!(connections[0].local_address, "*");
+        assert_eq!(connections[0].state, "LISTEN");
+        assert_eq!(connections[1].process, "Google C");
+        assert_eq!(connections[1].pid, Some(912));
+        assert_eq!(connections[1].remote_port, 443);
+        assert_eq!(connections[2].protocol, "udp");
+        assert_eq!(connections[2].state, "");
+    }
+}
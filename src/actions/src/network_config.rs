@@ -0,0 +1,1049 @@
+use config::workflow::NetworkConfigAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use storage::FileProcessor;
+use utils::misc::iter_files_by_pattern;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+// Environment variables commonly used to configure a proxy
+const PROXY_VARIABLES: [&str; 5] = [
+    "http_proxy",
+    "https_proxy",
+    "ftp_proxy",
+    "all_proxy",
+    "no_proxy",
+];
+
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct FirewallRule {
+    // iptables, ip6tables, nftables, pf or windows
+    pub backend: String,
+    pub table: String,
+    pub chain: String,
+    pub name: String,
+    pub direction: String,
+    pub action: String,
+    pub enabled: bool,
+    pub protocol: String,
+    pub source: String,
+    pub destination: String,
+    pub ports: String,
+    pub raw: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct FirewallProfile {
+    pub name: String,
+    pub enabled: bool,
+    pub default_inbound: String,
+    pub default_outbound: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub mac: String,
+    pub state: String,
+    pub mtu: Option<u32>,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct HostsEntry {
+    pub address: String,
+    pub hostnames: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct NetworkReport {
+    pub firewall_profiles: Vec<FirewallProfile>,
+    pub firewall_rules: Vec<FirewallRule>,
+    pub interfaces: Vec<NetworkInterface>,
+    pub dns_servers: Vec<String>,
+    pub gateways: Vec<String>,
+    pub proxy: BTreeMap<String, String>,
+    pub hosts: Vec<HostsEntry>,
+    pub raw_files: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+// Runs a tool and returns its output. Missing tools are not treated as an error,
+// as most systems only ship one of the firewall frontends
+pub(crate) fn run_tool(program: &str, args: &[&str], errors: &mut Vec<String>) -> Option<String> {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        }
+        Ok(output) => {
+            let stderr: String = String::from_utf8_lossy(&output.stderr)
+                .trim()
+                .chars()
+                .take(200)
+                .collect();
+            errors.push(format!("{} {} failed: {}", program, args.join(" "), stderr));
+            None
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            debug!("{} is not available", program);
+            None
+        }
+        Err(e) => {
+            errors.push(format!("Failed to run {}: {}", program, e));
+            None
+        }
+    }
+}
+
+fn direction_from_chain(chain: &str) -> String {
+    match chain.to_lowercase().as_str() {
+        "input" => "in".to_string(),
+        "output" => "out".to_string(),
+        "forward" => "forward".to_string(),
+        _ => String::new(),
+    }
+}
+
+// Splits a rule into arguments, keeping quoted strings (e.g. comments) together
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+/// Parses the output of iptables-save or ip6tables-save
+pub fn parse_iptables_save(backend: &str, text: &str) -> Vec<FirewallRule> {
+    let mut rules = vec![];
+    let mut table = String::new();
+
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('*') {
+            table = name.to_string();
+        } else if let Some(policy) = line.strip_prefix(':') {
+            // chain policy, e.g. ":INPUT ACCEPT [0:0]"
+            let parts: Vec<&str> = policy.split_whitespace().collect();
+            if parts.len() < 2 || parts[1] == "-" {
+                continue;
+            }
+            rules.push(FirewallRule {
+                backend: backend.to_string(),
+                table: table.clone(),
+                chain: parts[0].to_string(),
+                name: "policy".to_string(),
+                direction: direction_from_chain(parts[0]),
+                action: parts[1].to_string(),
+                enabled: true,
+                raw: line.to_string(),
+                ..Default::default()
+            });
+        } else if line.starts_with("-A ") {
+            let args = split_args(line);
+            let mut rule = FirewallRule {
+                backend: backend.to_string(),
+                table: table.clone(),
+                chain: args.get(1).cloned().unwrap_or_default(),
+                enabled: true,
+                raw: line.to_string(),
+                ..Default::default()
+            };
+            rule.direction = direction_from_chain(&rule.chain);
+
+            let mut negate = false;
+            let mut iter = args.iter().skip(2);
+            while let Some(arg) = iter.next() {
+                if arg == "!" {
+                    negate = true;
+                    continue;
+                }
+                let field = match arg.as_str() {
+                    "-p" | "--protocol" => &mut rule.protocol,
+                    "-s" | "--source" => &mut rule.source,
+                    "-d" | "--destination" => &mut rule.destination,
+                    "--dport" | "--dports" | "--destination-port" => &mut rule.ports,
+                    "-j" | "--jump" | "-g" | "--goto" => &mut rule.action,
+                    "--comment" => &mut rule.name,
+                    _ => {
+                        negate = false;
+                        continue;
+                    }
+                };
+                if let Some(value) = iter.next() {
+                    *field = match negate {
+                        true => format!("!{}", value),
+                        false => value.clone(),
+                    };
+                }
+                negate = false;
+            }
+            rules.push(rule);
+        }
+    }
+
+    rules
+}
+
+fn nft_value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Object(map) => {
+            if let Some(Value::Array(values)) = map.get("set") {
+                values
+                    .iter()
+                    .map(nft_value_to_string)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            } else if let Some(prefix) = map.get("prefix") {
+                format!(
+                    "{}/{}",
+                    nft_value_to_string(&prefix["addr"]),
+                    nft_value_to_string(&prefix["len"])
+                )
+            } else if let Some(Value::Array(range)) = map.get("range") {
+                range
+                    .iter()
+                    .map(nft_value_to_string)
+                    .collect::<Vec<String>>()
+                    .join("-")
+            } else {
+                value.to_string()
+            }
+        }
+        other => other.to_string(),
+    }
+}
+
+/// Parses the output of "nft -j list ruleset"
+pub fn parse_nft_json(text: &str) -> Result<Vec<FirewallRule>, serde_json::Error> {
+    let value: Value = serde_json::from_str(text)?;
+    let mut rules = vec![];
+
+    for item in value["nftables"].as_array().into_iter().flatten() {
+        if let Some(chain) = item.get("chain") {
+            let policy = match chain["policy"].as_str() {
+                Some(policy) => policy,
+                None => continue,
+            };
+            rules.push(FirewallRule {
+                backend: "nftables".to_string(),
+                table: format!(
+                    "{} {}",
+                    chain["family"].as_str().unwrap_or_default(),
+                    chain["table"].as_str().unwrap_or_default()
+                ),
+                chain: chain["name"].as_str().unwrap_or_default().to_string(),
+                name: "policy".to_string(),
+                direction: direction_from_chain(chain["hook"].as_str().unwrap_or_default()),
+                action: policy.to_string(),
+                enabled: true,
+                raw: chain.to_string(),
+                ..Default::default()
+            });
+        } else if let Some(entry) = item.get("rule") {
+            let mut rule = FirewallRule {
+                backend: "nftables".to_string(),
+                table: format!(
+                    "{} {}",
+                    entry["family"].as_str().unwrap_or_default(),
+                    entry["table"].as_str().unwrap_or_default()
+                ),
+                chain: entry["chain"].as_str().unwrap_or_default().to_string(),
+                name: entry["comment"].as_str().unwrap_or_default().to_string(),
+                enabled: true,
+                raw: entry["expr"].to_string(),
+                ..Default::default()
+            };
+            rule.direction = direction_from_chain(&rule.chain);
+
+            for expr in entry["expr"].as_array().into_iter().flatten() {
+                if let Some(condition) = expr.get("match") {
+                    let right = nft_value_to_string(&condition["right"]);
+                    let payload = &condition["left"]["payload"];
+                    match payload["field"].as_str() {
+                        Some("dport") => {
+                            rule.protocol =
+                                payload["protocol"].as_str().unwrap_or_default().to_string();
+                            rule.ports = right;
+                        }
+                        Some("saddr") => rule.source = right,
+                        Some("daddr") => rule.destination = right,
+                        _ => {
+                            if condition["left"]["meta"]["key"].as_str() == Some("l4proto") {
+                                rule.protocol = right;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                for verdict in [
+                    "accept",
+                    "drop",
+                    "reject",
+                    "return",
+                    "masquerade",
+                    "snat",
+                    "dnat",
+                    "queue",
+                ] {
+                    if expr.get(verdict).is_some() {
+                        rule.action = verdict.to_string();
+                    }
+                }
+                for verdict in ["jump", "goto"] {
+                    if let Some(target) = expr.get(verdict) {
+                        rule.action = format!(
+                            "{} {}",
+                            verdict,
+                            target["target"].as_str().unwrap_or_default()
+                        );
+                    }
+                }
+            }
+            rules.push(rule);
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Parses the output of "pfctl -sr"
+pub fn parse_pf_rules(text: &str) -> Vec<FirewallRule> {
+    let mut rules = vec![];
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let mut rule = FirewallRule {
+            backend: "pf".to_string(),
+            action: args[0].to_string(),
+            enabled: true,
+            raw: line.to_string(),
+            ..Default::default()
+        };
+
+        let mut iter = args.iter().skip(1).peekable();
+        while let Some(arg) = iter.next() {
+            let field = match *arg {
+                "in" | "out" => {
+                    rule.direction = arg.to_string();
+                    continue;
+                }
+                "on" => &mut rule.chain,
+                "proto" => &mut rule.protocol,
+                "from" => &mut rule.source,
+                "to" => &mut rule.destination,
+                "port" => {
+                    // skip the comparison operator, e.g. "port = 22"
+                    iter.next_if(|value| **value == "=");
+                    &mut rule.ports
+                }
+                "label" => &mut rule.name,
+                _ => continue,
+            };
+            if let Some(value) = iter.next() {
+                *field = value.trim_matches('"').to_string();
+            }
+        }
+        rules.push(rule);
+    }
+
+    rules
+}
+
+/// Parses a hosts file, ignoring comments and empty lines
+pub fn parse_hosts(text: &str) -> Vec<HostsEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut parts = line.split_whitespace();
+            let address = parts.next()?.to_string();
+            let hostnames: Vec<String> = parts.map(str::to_string).collect();
+            match hostnames.is_empty() {
+                true => None,
+                false => Some(HostsEntry { address, hostnames }),
+            }
+        })
+        .collect()
+}
+
+/// Returns the name servers of a resolv.conf file
+pub fn parse_resolv_conf(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("nameserver") => parts.next().map(str::to_string),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Parses the output of "ip -o addr show" into (interface, address) pairs
+pub fn parse_ip_addr(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.get(2) {
+                Some(&"inet") | Some(&"inet6") => {
+                    Some((parts[1].to_string(), parts.get(3)?.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns the default gateways of /proc/net/route
+pub fn parse_proc_route(text: &str) -> Vec<String> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 3 || parts[1] != "00000000" {
+                return None;
+            }
+            // addresses are stored as little endian hex values
+            let gateway = u32::from_str_radix(parts[2], 16).ok()?;
+            let gateway = std::net::Ipv4Addr::from(gateway.to_le_bytes());
+            Some(format!("{} via {}", parts[0], gateway))
+        })
+        .collect()
+}
+
+/// Parses the output of "ifconfig -a"
+pub fn parse_ifconfig(text: &str) -> Vec<NetworkInterface> {
+    let mut interfaces: Vec<NetworkInterface> = vec![];
+
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            // e.g. "en0: flags=8863<UP,BROADCAST,RUNNING> mtu 1500"
+            let name = match line.split_once(':') {
+                Some((name, _)) => name.to_string(),
+                None => continue,
+            };
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let mtu = parts
+                .iter()
+                .position(|part| *part == "mtu")
+                .and_then(|index| parts.get(index + 1))
+                .and_then(|mtu| mtu.parse().ok());
+            interfaces.push(NetworkInterface {
+                name,
+                mtu,
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let interface = match interfaces.last_mut() {
+            Some(interface) => interface,
+            None => continue,
+        };
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["ether", mac, ..] => interface.mac = mac.to_string(),
+            ["inet", address, ..] | ["inet6", address, ..] => {
+                interface.addresses.push(address.to_string())
+            }
+            ["status:", state, ..] => interface.state = state.to_string(),
+            _ => {}
+        }
+    }
+
+    interfaces
+}
+
+fn read_file(path: &Path, errors: &mut Vec<String>) -> Option<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            errors.push(format!("Failed to read {:?}: {}", path.display(), e));
+            None
+        }
+    }
+}
+
+pub(crate) fn hosts_path() -> PathBuf {
+    match cfg!(target_os = "windows") {
+        true => PathBuf::from(std::env::var("SystemRoot").unwrap_or("C:\\Windows".to_string()))
+            .join("System32\\drivers\\etc\\hosts"),
+        false => PathBuf::from("/etc/hosts"),
+    }
+}
+
+// Configuration files which are stored as they are
+fn raw_file_patterns() -> Vec<String> {
+    match cfg!(target_os = "windows") {
+        true => {
+            let root = std::env::var("SystemRoot").unwrap_or("C:\\Windows".to_string());
+            vec![
+                format!("{}\\System32\\drivers\\etc\\hosts", root),
+                format!("{}\\System32\\LogFiles\\Firewall\\*.log", root),
+            ]
+        }
+        false => [
+            "/etc/hosts",
+            "/etc/resolv.conf",
+            "/etc/environment",
+            "/etc/nftables.conf",
+            "/etc/iptables/*",
+            "/etc/sysconfig/iptables",
+            "/etc/sysconfig/ip6tables",
+            "/etc/ufw/*.rules",
+            "/etc/firewalld/zones/*.xml",
+            "/etc/network/interfaces",
+            "/etc/netplan/*.yaml",
+            "/etc/pf.conf",
+        ]
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect(),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn collect_unix_firewall(report: &mut NetworkReport) {
+    for (backend, program) in [
+        ("iptables", "iptables-save"),
+        ("ip6tables", "ip6tables-save"),
+    ] {
+        if let Some(output) = run_tool(program, &[], &mut report.errors) {
+            report
+                .firewall_rules
+                .extend(parse_iptables_save(backend, &output));
+        }
+    }
+    if let Some(output) = run_tool("nft", &["-j", "list", "ruleset"], &mut report.errors) {
+        match parse_nft_json(&output) {
+            Ok(rules) => report.firewall_rules.extend(rules),
+            Err(e) => report
+                .errors
+                .push(format!("Failed to parse nftables ruleset: {}", e)),
+        }
+    }
+    if cfg!(target_os = "macos") {
+        if let Some(output) = run_tool("pfctl", &["-sr"], &mut report.errors) {
+            report.firewall_rules.extend(parse_pf_rules(&output));
+        }
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn collect_unix_interfaces(report: &mut NetworkReport) {
+    if let Some(content) = read_file(Path::new("/etc/resolv.conf"), &mut report.errors) {
+        report.dns_servers = parse_resolv_conf(&content);
+    }
+
+    if cfg!(target_os = "macos") {
+        if let Some(output) = run_tool("ifconfig", &["-a"], &mut report.errors) {
+            report.interfaces = parse_ifconfig(&output);
+        }
+        if let Some(output) = run_tool("route", &["-n", "get", "default"], &mut report.errors) {
+            report.gateways = output
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("gateway:"))
+                .map(|gateway| gateway.trim().to_string())
+                .collect();
+        }
+        return;
+    }
+
+    let mut interfaces: BTreeMap<String, NetworkInterface> = BTreeMap::new();
+    for entry in fs::read_dir("/sys/class/net")
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let read = |file: &str| {
+            fs::read_to_string(entry.path().join(file))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let interface = NetworkInterface {
+            name: name.clone(),
+            mac: read("address"),
+            state: read("operstate"),
+            mtu: read("mtu").parse().ok(),
+            addresses: vec![],
+        };
+        interfaces.insert(name, interface);
+    }
+    if let Some(output) = run_tool("ip", &["-o", "addr", "show"], &mut report.errors) {
+        for (name, address) in parse_ip_addr(&output) {
+            interfaces
+                .entry(name.clone())
+                .or_insert_with(|| NetworkInterface {
+                    name,
+                    ..Default::default()
+                })
+                .addresses
+                .push(address);
+        }
+    }
+    report.interfaces = interfaces.into_values().collect();
+
+    if let Some(content) = read_file(Path::new("/proc/net/route"), &mut report.errors) {
+        report.gateways = parse_proc_route(&content);
+    }
+}
+
+fn collect_proxy(report: &mut NetworkReport) {
+    for variable in PROXY_VARIABLES {
+        for name in [variable.to_string(), variable.to_uppercase()] {
+            if let Ok(value) = std::env::var(&name) {
+                report.proxy.insert(format!("env:{}", name), value);
+            }
+        }
+    }
+
+    if cfg!(target_os = "windows") {
+        let key = "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings";
+        for value in [
+            "ProxyEnable",
+            "ProxyServer",
+            "ProxyOverride",
+            "AutoConfigURL",
+        ] {
+            if let Some(data) = system::checks::registry_value(key, value) {
+                report.proxy.insert(format!("registry:{}", value), data);
+            }
+        }
+    } else if cfg!(target_os = "macos") {
+        // e.g. "  HTTPEnable : 1"
+        if let Some(output) = run_tool("scutil", &["--proxy"], &mut report.errors) {
+            for line in output.lines() {
+                if let Some((key, value)) = line.split_once(" : ") {
+                    report
+                        .proxy
+                        .insert(format!("scutil:{}", key.trim()), value.trim().to_string());
+                }
+            }
+        }
+    }
+}
+
+fn collect(attributes: &NetworkConfigAttributes) -> NetworkReport {
+    let mut report = NetworkReport::default();
+
+    #[cfg(target_os = "windows")]
+    let com_lib = match wmi::COMLibrary::new() {
+        Ok(com_lib) => Some(com_lib),
+        Err(e) => {
+            report
+                .errors
+                .push(format!("Failed to initialize COM library: {}", e));
+            None
+        }
+    };
+
+    if attributes.firewall {
+        #[cfg(target_os = "windows")]
+        if let Some(com_lib) = com_lib {
+            native::collect_firewall(com_lib, &mut report);
+        }
+        #[cfg(not(target_os = "windows"))]
+        collect_unix_firewall(&mut report);
+    }
+
+    if attributes.interfaces {
+        #[cfg(target_os = "windows")]
+        if let Some(com_lib) = com_lib {
+            native::collect_interfaces(com_lib, &mut report);
+        }
+        #[cfg(not(target_os = "windows"))]
+        collect_unix_interfaces(&mut report);
+    }
+
+    if attributes.proxy {
+        collect_proxy(&mut report);
+    }
+
+    if attributes.hosts {
+        if let Some(content) = read_file(&hosts_path(), &mut report.errors) {
+            report.hosts = parse_hosts(&content);
+        }
+    }
+
+    report
+}
+
+pub struct NetworkConfig {}
+
+impl NetworkConfig {
+    pub fn run(
+        attributes: NetworkConfigAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        // COM has to be initialized on the thread issuing the WMI queries,
+        // so we use a dedicated thread to not interfere with the async runtime
+        let store_raw = attributes.store_raw;
+        let handle = std::thread::spawn(move || collect(&attributes));
+        let mut report = match handle.join() {
+            Ok(report) => report,
+            Err(_) => {
+                return error_result!(
+                    "Network configuration collection panicked",
+                    options.start_time
+                )
+            }
+        };
+
+        // Store copies of the configuration files
+        if store_raw {
+            for pattern in raw_file_patterns() {
+                for file in iter_files_by_pattern(&pattern, !cfg!(target_os = "windows"))
+                    .into_iter()
+                    .flatten()
+                {
+                    match file_processor.store(&file, None) {
+                        Ok(_) => report.raw_files.push(file.to_string_lossy().to_string()),
+                        Err(e) => report.errors.push(format!(
+                            "Error storing file {:?}: {}",
+                            file.display(),
+                            e
+                        )),
+                    }
+                }
+            }
+        }
+
+        info!(
+            "Found {} firewall rules, {} interfaces, {} proxy settings and {} hosts entries",
+            report.firewall_rules.len(),
+            report.interfaces.len(),
+            report.proxy.len(),
+            report.hosts.len()
+        );
+        for error in &report.errors {
+            warn!("{}", error);
+        }
+
+        // Write the results as json to the action output directory
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        if let Err(e) = serde_json::to_writer_pretty(BufWriter::new(file), &report) {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote network configuration to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: report.errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match report.errors.is_empty() {
+                true => None,
+                false => Some(report.errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(
+                (report.firewall_rules.len()
+                    + report.interfaces.len()
+                    + report.proxy.len()
+                    + report.hosts.len()) as u64,
+            )
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod native {
+    use super::*;
+    use crate::wmi_persistence::native::variant_to_string;
+    use std::collections::HashMap;
+    use wmi::{COMLibrary, Variant, WMIConnection};
+
+    type Row = HashMap<String, Variant>;
+
+    fn get(row: &Row, key: &str) -> String {
+        row.get(key).map(variant_to_string).unwrap_or_default()
+    }
+
+    fn get_list(row: &Row, key: &str) -> Vec<String> {
+        match row.get(key) {
+            Some(Variant::Array(values)) => values.iter().map(variant_to_string).collect(),
+            Some(Variant::Empty) | Some(Variant::Null) | None => vec![],
+            Some(value) => vec![variant_to_string(value)],
+        }
+    }
+
+    fn query(connection: &WMIConnection, class: &str, report: &mut NetworkReport) -> Vec<Row> {
+        match connection.raw_query(format!("SELECT * FROM {}", class)) {
+            Ok(rows) => rows,
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("Failed to query {}: {}", class, e));
+                vec![]
+            }
+        }
+    }
+
+    fn firewall_action(value: &str) -> String {
+        match value {
+            "2" => "allow".to_string(),
+            "3" => "allow_bypass".to_string(),
+            "4" => "block".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn collect_firewall(com_lib: COMLibrary, report: &mut NetworkReport) {
+        let connection = match WMIConnection::with_namespace_path("root\\StandardCimv2", com_lib) {
+            Ok(connection) => connection,
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("Failed to connect to the firewall API: {}", e));
+                return;
+            }
+        };
+
+        for row in query(&connection, "MSFT_NetFirewallProfile", report) {
+            report.firewall_profiles.push(FirewallProfile {
+                name: get(&row, "Name"),
+                enabled: get(&row, "Enabled") == "1",
+                default_inbound: firewall_action(&get(&row, "DefaultInboundAction")),
+                default_outbound: firewall_action(&get(&row, "DefaultOutboundAction")),
+            });
+        }
+
+        // ports and addresses are stored in separate filter objects, referenced by the instance id
+        let port_filters: HashMap<String, Row> =
+            query(&connection, "MSFT_NetFirewallPortFilter", report)
+                .into_iter()
+                .map(|row| (get(&row, "InstanceID"), row))
+                .collect();
+        let address_filters: HashMap<String, Row> =
+            query(&connection, "MSFT_NetFirewallAddressFilter", report)
+                .into_iter()
+                .map(|row| (get(&row, "InstanceID"), row))
+                .collect();
+
+        for row in query(&connection, "MSFT_NetFirewallRule", report) {
+            let id = get(&row, "InstanceID");
+            let inbound = get(&row, "Direction") == "1";
+            let mut rule = FirewallRule {
+                backend: "windows".to_string(),
+                chain: get(&row, "Profiles"),
+                name: get(&row, "DisplayName"),
+                direction: match inbound {
+                    true => "in".to_string(),
+                    false => "out".to_string(),
+                },
+                action: firewall_action(&get(&row, "Action")),
+                enabled: get(&row, "Enabled") == "1",
+                raw: id.clone(),
+                ..Default::default()
+            };
+            if let Some(filter) = port_filters.get(&id) {
+                rule.protocol = get(filter, "Protocol");
+                rule.ports = match inbound {
+                    true => get_list(filter, "LocalPort").join(","),
+                    false => get_list(filter, "RemotePort").join(","),
+                };
+            }
+            if let Some(filter) = address_filters.get(&id) {
+                let local = get_list(filter, "LocalAddress").join(",");
+                let remote = get_list(filter, "RemoteAddress").join(",");
+                (rule.source, rule.destination) = match inbound {
+                    true => (remote, local),
+                    false => (local, remote),
+                };
+            }
+            report.firewall_rules.push(rule);
+        }
+    }
+
+    pub fn collect_interfaces(com_lib: COMLibrary, report: &mut NetworkReport) {
+        let connection = match WMIConnection::new(com_lib) {
+            Ok(connection) => connection,
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("Failed to connect to WMI: {}", e));
+                return;
+            }
+        };
+
+        let rows: Vec<Row> = match connection
+            .raw_query("SELECT * FROM Win32_NetworkAdapterConfiguration WHERE IPEnabled = TRUE")
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("Failed to query network adapters: {}", e));
+                return;
+            }
+        };
+
+        for row in rows {
+            let name = get(&row, "Description");
+            for gateway in get_list(&row, "DefaultIPGateway") {
+                report.gateways.push(format!("{} via {}", name, gateway));
+            }
+            for server in get_list(&row, "DNSServerSearchOrder") {
+                if !report.dns_servers.contains(&server) {
+                    report.dns_servers.push(server);
+                }
+            }
+            report.interfaces.push(NetworkInterface {
+                name,
+                mac: get(&row, "MACAddress"),
+                state: "up".to_string(),
+                mtu: get(&row, "MTU").parse().ok(),
+                addresses: get_list(&row, "IPAddress"),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iptables_save() {
+        let output = r#"
+# Generated by iptables-save
+*filter
+:INPUT DROP [0:0]
+:FORWARD ACCEPT [0:0]
+:DOCKER - [0:0]
+-A INPUT -i lo -j ACCEPT
+-A INPUT ! -s 10.0.0.0/8 -p tcp -m tcp --dport 22 -m comment --comment "allow ssh" -j ACCEPT
+COMMIT
+"#;
+        let rules = parse_iptables_save("iptables", output);
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0].action, "DROP");
+        assert_eq!(rules[0].direction, "in");
+        assert_eq!(rules[3].table, "filter");
+        assert_eq!(rules[3].source, "!10.0.0.0/8");
+        assert_eq!(rules[3].protocol, "tcp");
+        assert_eq!(rules[3].ports, "22");
+        assert_eq!(rules[3].name, "allow ssh");
+        assert_eq!(rules[3].action, "ACCEPT");
+    }
+
+    #[test]
+    fn test_parse_nft_and_pf() {
+        let output = r#"{"nftables": [
+            {"metainfo": {"version": "1.0.6"}},
+            {"chain": {"family": "inet", "table": "filter", "name": "input", "handle": 1,
+                "type": "filter", "hook": "input", "prio": 0, "policy": "drop"}},
+            {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 4,
+                "expr": [
+                    {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}},
+                        "right": {"set": [22, 443]}}},
+                    {"accept": null}
+                ]}}
+        ]}"#;
+        let rules = parse_nft_json(output).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].action, "drop");
+        assert_eq!(rules[1].table, "inet filter");
+        assert_eq!(rules[1].protocol, "tcp");
+        assert_eq!(rules[1].ports, "22,443");
+        assert_eq!(rules[1].action, "accept");
+
+        let rules = parse_pf_rules(
+            "block drop in all\npass out quick on en0 proto tcp from any to any port = 443\n",
+        );
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].action, "block");
+        assert_eq!(rules[0].direction, "in");
+        assert_eq!(rules[1].chain, "en0");
+        assert_eq!(rules[1].protocol, "tcp");
+        assert_eq!(rules[1].ports, "443");
+    }
+
+    #[test]
+    fn test_parse_network_files() {
+        let hosts =
+            parse_hosts("# comment\n127.0.0.1\tlocalhost\n\n10.0.0.1 evil.example.com evil # c2\n");
+        assert_eq!(
+            hosts,
+            vec![
+                HostsEntry {
+                    address: "127.0.0.1".to_string(),
+                    hostnames: vec!["localhost".to_string()],
+                },
+                HostsEntry {
+                    address: "10.0.0.1".to_string(),
+                    hostnames: vec!["evil.example.com".to_string(), "evil".to_string()],
+                },
+            ]
+        );
+
+        assert_eq!(
+            parse_resolv_conf("search lan\nnameserver 1.1.1.1\nnameserver ::1\n"),
+            vec!["1.1.1.1", "::1"]
+        );
+
+        let addresses = parse_ip_addr(
+            "1: lo    inet 127.0.0.1/8 scope host lo\\       valid_lft forever\n2: eth0    inet6 fe80::1/64 scope link\n",
+        );
+        assert_eq!(addresses[0], ("lo".to_string(), "127.0.0.1/8".to_string()));
+        assert_eq!(addresses[1], ("eth0".to_string(), "fe80::1/64".to_string()));
+
+        let route = "Iface\tDestination\tGateway\tFlags\neth0\t00000000\t0100A8C0\t0003\neth0\t0000A8C0\t00000000\t0001\n";
+        assert_eq!(parse_proc_route(route), vec!["eth0 via 192.168.0.1"]);
+
+        let interfaces = parse_ifconfig(
+            "en0: flags=8863<UP,BROADCAST> mtu 1500\n\tether aa:bb:cc:dd:ee:ff\n\tinet 192.168.1.2 netmask 0xffffff00\n\tstatus: active\n",
+        );
+        assert_eq!(interfaces.len(), 1);
+        assert_eq!(interfaces[0].name, "en0");
+        assert_eq!(interfaces[0].mtu, Some(1500));
+        assert_eq!(interfaces[0].mac, "aa:bb:cc:dd:ee:ff");
+        assert_eq!(interfaces[0].addresses, vec!["192.168.1.2"]);
+        assert_eq!(interfaces[0].state, "active");
+    }
+}
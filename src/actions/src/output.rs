@@ -0,0 +1,71 @@
+// Encrypted action outputs: stdout and stderr are encrypted before they are written to disk
+use crypto::{encrypted_output_path, EncryptingWriter, SessionKey};
+use log::debug;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use tokio::{io::AsyncRead, io::AsyncReadExt, sync::mpsc, task::JoinHandle};
+
+pub type EncryptedOutput = EncryptingWriter<BufWriter<File>>;
+
+/// Create the encrypted output file (the path with .enc appended)
+pub fn create(out_file: &Path, key: &SessionKey) -> Result<EncryptedOutput, String> {
+    let path = encrypted_output_path(out_file);
+    debug!("Encrypting output to {}", path.display());
+    let file =
+        File::create(&path).map_err(|e| format!("Failed to create {:?}: {}", path.display(), e))?;
+    EncryptingWriter::new(BufWriter::new(file), key)
+        .map_err(|e| format!("Failed to encrypt output {:?}: {}", path.display(), e))
+}
+
+async fn forward<R: AsyncRead + Unpin>(mut stream: R, tx: mpsc::UnboundedSender<Vec<u8>>) {
+    let mut buffer = vec![0u8; 8192];
+    while let Ok(n) = stream.read(&mut buffer).await {
+        if n == 0 || tx.send(buffer[..n].to_vec()).is_err() {
+            break;
+        }
+    }
+}
+
+/// Write stdout and stderr of a child process to the encrypted output.
+/// The task finishes (and writes the tag) once both streams are closed.
+pub fn spawn_writer<O, E>(
+    mut output: EncryptedOutput,
+    stdout: Option<O>,
+    stderr: Option<E>,
+) -> JoinHandle<Result<(), String>>
+where
+    O: AsyncRead + Unpin + Send + 'static,
+    E: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    if let Some(stdout) = stdout {
+        tokio::spawn(forward(stdout, tx.clone()));
+    }
+    if let Some(stderr) = stderr {
+        tokio::spawn(forward(stderr, tx.clone()));
+    }
+    drop(tx);
+
+    tokio::spawn(async move {
+        while let Some(chunk) = rx.recv().await {
+            output
+                .write_all(&chunk)
+                .map_err(|e| format!("Failed to write encrypted output: {}", e))?;
+        }
+        output
+            .finish()
+            .map_err(|e| format!("Failed to finish encrypted output: {}", e))?;
+        Ok(())
+    })
+}
+
+/// Wait until the encrypted output is complete
+pub async fn wait(task: Option<JoinHandle<Result<(), String>>>) -> Result<(), String> {
+    match task {
+        Some(task) => task.await.map_err(|e| e.to_string())?,
+        None => Ok(()),
+    }
+}
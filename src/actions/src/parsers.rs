@@ -0,0 +1,222 @@
+// Parser plugins extract findings from stored artifacts without recompiling the toolkit.
+// Each parser is described by a YAML file in custom_files/parsers, e.g. evtx.yaml:
+//
+//   cmd: evtx_parser.exe          (relative to custom_files/parsers, otherwise searched in PATH)
+//   args: ["--json", "${FILE}"]   (optional, the path of the file by default)
+//   types: ["application/x-ms-evtx"]
+//   timeout: 30                   (per file, 0 = no timeout)
+//
+// The parser prints {"findings": [{"name": ..., "severity": 0-100, "description": ...}]} on stdout
+// and exits with 0. The findings are merged into findings.json of the report
+use config::workflow::PerFileCommand;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+use storage::triage::ParserFinding;
+use utils::filetype::TypeFilter;
+
+use super::store::{run_command, FILE_VARIABLE};
+
+pub const PARSERS_DIR: &str = "parsers";
+
+fn default_parser_args() -> Vec<String> {
+    vec![FILE_VARIABLE.to_string()]
+}
+
+fn default_parser_timeout() -> i32 {
+    30
+}
+
+#[derive(Debug, Deserialize)]
+struct ParserManifest {
+    cmd: String,
+    #[serde(default = "default_parser_args")]
+    args: Vec<String>,
+    // MIME types detected by extension and content, e.g. "application/x-ms-evtx" or "text/*"
+    types: Vec<String>,
+    #[serde(default = "default_parser_timeout")]
+    timeout: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParserOutput {
+    #[serde(default)]
+    findings: Vec<ParserFinding>,
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    // file name of the manifest without extension
+    pub name: String,
+    command: PerFileCommand,
+    filter: TypeFilter,
+}
+
+impl Parser {
+    fn from_manifest(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let manifest: ParserManifest = serde_yaml::from_reader(File::open(path)?)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if manifest.types.is_empty() {
+            return Err(format!("Parser {:?} has no types", name).into());
+        }
+        let filter = TypeFilter::new(&manifest.types, &[]);
+        if let Some(invalid) = filter.invalid_filters().first() {
+            return Err(format!("Parser {:?} has an invalid type {:?}", name, invalid).into());
+        }
+        // shipped next to the manifest, or a tool like python3 from the PATH
+        let local_cmd = path.with_file_name(&manifest.cmd);
+        let cmd = match local_cmd.is_file() {
+            true => local_cmd.to_string_lossy().to_string(),
+            false => manifest.cmd,
+        };
+        Ok(Self {
+            name,
+            command: PerFileCommand {
+                cmd,
+                args: manifest.args,
+                timeout: manifest.timeout,
+            },
+            filter,
+        })
+    }
+
+    pub fn matches(&self, file: &Path) -> bool {
+        self.filter.allows(file)
+    }
+
+    /// Runs the parser for the file. Fails if the parser could not be started,
+    /// a parser which fails for a file or prints invalid output is only logged
+    pub fn run(&self, file: &Path) -> Result<Result<Vec<ParserFinding>, String>, String> {
+        let output = run_command(&self.command, file)
+            .map_err(|e| format!("Failed to run parser {:?}: {}", self.name, e))?;
+        Ok(parse_output(
+            &self.name,
+            output.exit_code,
+            output.error,
+            &output.stdout,
+        ))
+    }
+}
+
+fn parse_output(
+    name: &str,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    stdout: &[u8],
+) -> Result<Vec<ParserFinding>, String> {
+    if let Some(error) = error {
+        return Err(error);
+    }
+    if exit_code != Some(0) {
+        return Err(format!("Parser exited with {:?}", exit_code));
+    }
+    let output: ParserOutput =
+        serde_json::from_slice(stdout).map_err(|e| format!("Invalid output: {}", e))?;
+    let mut findings = output.findings;
+    for finding in &mut findings {
+        if finding.severity > 100 {
+            return Err(format!(
+                "Finding {:?}: severity must be between 0 and 100",
+                finding.name
+            ));
+        }
+        finding.parser = name.to_string();
+    }
+    Ok(findings)
+}
+
+/// Loads the parsers of custom_files/parsers, ordered by name. Empty if the directory doesn't exist
+pub fn load_parsers(custom_files_dir: &Path) -> Result<Vec<Parser>, Box<dyn Error>> {
+    let dir = custom_files_dir.join(PARSERS_DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut manifests: Vec<PathBuf> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "yaml" || extension == "yml")
+        })
+        .collect();
+    manifests.sort();
+    manifests
+        .iter()
+        .map(|path| {
+            Parser::from_manifest(path)
+                .map_err(|e| format!("Invalid parser {}: {}", path.display(), e).into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_parse_output() {
+        let findings = parse_output(
+            "evtx",
+            Some(0),
+            None,
+            br#"{"findings": [{"name": "Log cleared", "severity": 80}]}"#,
+        )
+        .unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].parser, "evtx");
+        assert_eq!(findings[0].description, "");
+
+        assert!(parse_output("evtx", Some(0), None, b"{}")
+            .unwrap()
+            .is_empty());
+        assert!(parse_output("evtx", Some(1), None, b"{}").is_err());
+        assert!(parse_output("evtx", Some(0), None, b"not json").is_err());
+        assert!(parse_output(
+            "evtx",
+            Some(0),
+            None,
+            br#"{"findings": [{"name": "x", "severity": 101}]}"#
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_and_run_parsers() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_load_and_run_parsers");
+        assert!(load_parsers(&dir).unwrap().is_empty());
+
+        let parsers_dir = dir.join(PARSERS_DIR);
+        fs::create_dir_all(&parsers_dir).unwrap();
+        fs::write(
+            parsers_dir.join("notes.yaml"),
+            r#"
+cmd: sh
+args: ["-c", "grep -q password \"$0\" && echo '{\"findings\": [{\"name\": \"Password\", \"severity\": 70}]}' || echo '{}'", "${FILE}"]
+types: ["text/plain"]
+"#,
+        )
+        .unwrap();
+        let parsers = load_parsers(&dir).unwrap();
+        assert_eq!(parsers.len(), 1);
+        assert_eq!(parsers[0].name, "notes");
+
+        let notes = dir.join("notes.txt");
+        fs::write(&notes, "password: hunter2").unwrap();
+        assert!(parsers[0].matches(&notes));
+        assert!(!parsers[0].matches(&dir.join("notes.yaml")));
+        let findings = parsers[0].run(&notes).unwrap().unwrap();
+        assert_eq!(findings[0].name, "Password");
+        assert_eq!(findings[0].parser, "notes");
+
+        fs::write(parsers_dir.join("broken.yaml"), "cmd: sh\ntypes: []\n").unwrap();
+        assert!(load_parsers(&dir).is_err());
+    }
+}
@@ -0,0 +1,572 @@
+use config::workflow::PcapAttributes;
+use libloading::{Library, Symbol};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    ffi::{CStr, CString},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    os::raw::{c_char, c_int, c_long, c_uint, c_void},
+    path::{Path, PathBuf},
+    ptr, thread,
+    time::{Duration, Instant},
+};
+use storage::FileProcessor;
+use utils::sanitize::sanitize_dirname;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+// libpcap is loaded at runtime, so the collector still works on systems without it
+#[cfg(target_os = "windows")]
+const LIBRARY_NAMES: [&str; 2] = ["wpcap.dll", "C:\\Windows\\System32\\Npcap\\wpcap.dll"];
+#[cfg(target_os = "macos")]
+const LIBRARY_NAMES: [&str; 2] = ["libpcap.A.dylib", "libpcap.dylib"];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARY_NAMES: [&str; 2] = ["libpcap.so.1", "libpcap.so"];
+
+const PCAP_ERRBUF_SIZE: usize = 256;
+const PCAP_NETMASK_UNKNOWN: c_uint = 0xffffffff;
+// read timeout in ms, so the capture duration is checked regularly on idle interfaces
+const READ_TIMEOUT: c_int = 500;
+
+#[repr(C)]
+struct PcapPkthdr {
+    tv_sec: c_long,
+    #[cfg(target_os = "macos")]
+    tv_usec: i32,
+    #[cfg(not(target_os = "macos"))]
+    tv_usec: c_long,
+    caplen: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct BpfProgram {
+    bf_len: c_uint,
+    bf_insns: *mut c_void,
+}
+
+#[repr(C)]
+struct PcapIf {
+    next: *mut PcapIf,
+    name: *mut c_char,
+    description: *mut c_char,
+    addresses: *mut c_void,
+    flags: c_uint,
+}
+
+type OpenLiveFn =
+    unsafe extern "C" fn(*const c_char, c_int, c_int, c_int, *mut c_char) -> *mut c_void;
+type CompileFn =
+    unsafe extern "C" fn(*mut c_void, *mut BpfProgram, *const c_char, c_int, c_uint) -> c_int;
+type SetFilterFn = unsafe extern "C" fn(*mut c_void, *mut BpfProgram) -> c_int;
+type FreeCodeFn = unsafe extern "C" fn(*mut BpfProgram);
+type NextExFn = unsafe extern "C" fn(*mut c_void, *mut *mut PcapPkthdr, *mut *const u8) -> c_int;
+type DatalinkFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type GetErrFn = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type CloseFn = unsafe extern "C" fn(*mut c_void);
+type FindAllDevsFn = unsafe extern "C" fn(*mut *mut PcapIf, *mut c_char) -> c_int;
+type FreeAllDevsFn = unsafe extern "C" fn(*mut PcapIf);
+
+/// Writes packets in the classic pcap format and starts a new file
+/// once the current one exceeds the rotation size
+pub struct PcapWriter {
+    dir: PathBuf,
+    prefix: String,
+    linktype: u32,
+    snaplen: u32,
+    rotate_size: u64,
+    current: Option<BufWriter<File>>,
+    current_size: u64,
+    pub files: Vec<PathBuf>,
+    pub total_size: u64,
+    pub packets: u64,
+}
+
+impl PcapWriter {
+    pub fn new(
+        dir: &Path,
+        prefix: &str,
+        linktype: u32,
+        snaplen: u32,
+        rotate_size: u64,
+    ) -> io::Result<Self> {
+        let mut writer = PcapWriter {
+            dir: dir.to_path_buf(),
+            prefix: prefix.to_string(),
+            linktype,
+            snaplen,
+            rotate_size,
+            current: None,
+            current_size: 0,
+            files: vec![],
+            total_size: 0,
+            packets: 0,
+        };
+        writer.rotate()?;
+        Ok(writer)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.finish()?;
+
+        let path = self
+            .dir
+            .join(format!("{}_{:03}.pcap", self.prefix, self.files.len() + 1));
+        let mut file = BufWriter::new(File::create(&path)?);
+
+        // global header: magic, version 2.4, utc offset, accuracy, snaplen, link type
+        file.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&4u16.to_le_bytes())?;
+        file.write_all(&0i32.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&self.snaplen.to_le_bytes())?;
+        file.write_all(&self.linktype.to_le_bytes())?;
+
+        debug!("Writing packets to {:?}", path.display());
+        self.current = Some(file);
+        self.current_size = 24;
+        self.total_size += 24;
+        self.files.push(path);
+        Ok(())
+    }
+
+    pub fn write_packet(
+        &mut self,
+        ts_sec: u32,
+        ts_usec: u32,
+        data: &[u8],
+        orig_len: u32,
+    ) -> io::Result<()> {
+        if self.rotate_size > 0 && self.current_size >= self.rotate_size {
+            self.rotate()?;
+        }
+        let file = match self.current.as_mut() {
+            Some(file) => file,
+            None => return Err(io::Error::other("Writer is closed")),
+        };
+
+        file.write_all(&ts_sec.to_le_bytes())?;
+        file.write_all(&ts_usec.to_le_bytes())?;
+        file.write_all(&(data.len() as u32).to_le_bytes())?;
+        file.write_all(&orig_len.to_le_bytes())?;
+        file.write_all(data)?;
+
+        let written = 16 + data.len() as u64;
+        self.current_size += written;
+        self.total_size += written;
+        self.packets += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.current.take() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Summary of the capture on a single interface
+#[derive(Debug, Serialize, Default)]
+pub struct InterfaceCapture {
+    pub interface: String,
+    pub description: String,
+    pub filter: String,
+    pub packets: u64,
+    pub bytes: u64,
+    pub files: Vec<String>,
+    pub error: Option<String>,
+}
+
+fn load_library() -> Result<Library, String> {
+    let mut errors = vec![];
+    for name in LIBRARY_NAMES {
+        #[cfg(target_os = "windows")]
+        let library = unsafe {
+            // dependencies (Packet.dll) are located next to the Npcap library
+            libloading::os::windows::Library::load_with_flags(
+                name,
+                libloading::os::windows::LOAD_WITH_ALTERED_SEARCH_PATH,
+            )
+            .map(Library::from)
+        };
+        #[cfg(not(target_os = "windows"))]
+        let library = unsafe { Library::new(name) };
+
+        match library {
+            Ok(library) => {
+                debug!("Loaded {}", name);
+                return Ok(library);
+            }
+            Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+    }
+    Err(format!(
+        "libpcap (or Npcap on Windows) is not available: {}",
+        errors.join("; ")
+    ))
+}
+
+fn to_string(ptr: *const c_char) -> String {
+    match ptr.is_null() {
+        true => String::new(),
+        false => unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string(),
+    }
+}
+
+fn symbol<'a, T>(library: &'a Library, name: &[u8]) -> Result<Symbol<'a, T>, String> {
+    unsafe { library.get(name) }.map_err(|e| format!("Failed to load symbol: {}", e))
+}
+
+/// Returns the name and description of all interfaces known to libpcap
+fn list_interfaces(library: &Library) -> Result<Vec<(String, String)>, String> {
+    let find_all_devs: Symbol<FindAllDevsFn> = symbol(library, b"pcap_findalldevs\0")?;
+    let free_all_devs: Symbol<FreeAllDevsFn> = symbol(library, b"pcap_freealldevs\0")?;
+
+    let mut errbuf = [0 as c_char; PCAP_ERRBUF_SIZE];
+    let mut devices: *mut PcapIf = ptr::null_mut();
+    if unsafe { find_all_devs(&mut devices, errbuf.as_mut_ptr()) } != 0 {
+        return Err(to_string(errbuf.as_ptr()));
+    }
+
+    let mut interfaces = vec![];
+    let mut current = devices;
+    while !current.is_null() {
+        let device = unsafe { &*current };
+        interfaces.push((to_string(device.name), to_string(device.description)));
+        current = device.next;
+    }
+    unsafe { free_all_devs(devices) };
+    Ok(interfaces)
+}
+
+fn capture_interface(
+    library: &Library,
+    interface: &str,
+    attributes: &PcapAttributes,
+    writer_dir: &Path,
+    prefix: &str,
+) -> Result<PcapWriter, String> {
+    let open_live: Symbol<OpenLiveFn> = symbol(library, b"pcap_open_live\0")?;
+    let compile: Symbol<CompileFn> = symbol(library, b"pcap_compile\0")?;
+    let set_filter: Symbol<SetFilterFn> = symbol(library, b"pcap_setfilter\0")?;
+    let free_code: Symbol<FreeCodeFn> = symbol(library, b"pcap_freecode\0")?;
+    let next_ex: Symbol<NextExFn> = symbol(library, b"pcap_next_ex\0")?;
+    let datalink: Symbol<DatalinkFn> = symbol(library, b"pcap_datalink\0")?;
+    let get_err: Symbol<GetErrFn> = symbol(library, b"pcap_geterr\0")?;
+    let close: Symbol<CloseFn> = symbol(library, b"pcap_close\0")?;
+
+    let c_interface = CString::new(interface).map_err(|e| e.to_string())?;
+    let mut errbuf = [0 as c_char; PCAP_ERRBUF_SIZE];
+    let handle = unsafe {
+        open_live(
+            c_interface.as_ptr(),
+            attributes.snaplen,
+            attributes.promiscuous as c_int,
+            READ_TIMEOUT,
+            errbuf.as_mut_ptr(),
+        )
+    };
+    if handle.is_null() {
+        return Err(format!(
+            "Failed to open interface {}: {}",
+            interface,
+            to_string(errbuf.as_ptr())
+        ));
+    }
+
+    let capture = || -> Result<PcapWriter, String> {
+        if !attributes.filter.is_empty() {
+            let c_filter = CString::new(attributes.filter.as_str()).map_err(|e| e.to_string())?;
+            let mut program = BpfProgram {
+                bf_len: 0,
+                bf_insns: ptr::null_mut(),
+            };
+            unsafe {
+                if compile(
+                    handle,
+                    &mut program,
+                    c_filter.as_ptr(),
+                    1,
+                    PCAP_NETMASK_UNKNOWN,
+                ) != 0
+                {
+                    return Err(format!("Invalid filter: {}", to_string(get_err(handle))));
+                }
+                let result = set_filter(handle, &mut program);
+                free_code(&mut program);
+                if result != 0 {
+                    return Err(format!(
+                        "Failed to set filter: {}",
+                        to_string(get_err(handle))
+                    ));
+                }
+            }
+        }
+
+        let linktype = unsafe { datalink(handle) } as u32;
+        let mut writer = PcapWriter::new(
+            writer_dir,
+            prefix,
+            linktype,
+            attributes.snaplen as u32,
+            attributes.rotate_size,
+        )
+        .map_err(|e| format!("Failed to create capture file: {}", e))?;
+
+        let start_time = Instant::now();
+        let duration = Duration::from_secs(attributes.duration);
+        while start_time.elapsed() < duration {
+            if attributes.size_limit > 0 && writer.total_size >= attributes.size_limit {
+                info!("Size limit reached on interface {}", interface);
+                break;
+            }
+
+            let mut header: *mut PcapPkthdr = ptr::null_mut();
+            let mut data: *const u8 = ptr::null();
+            match unsafe { next_ex(handle, &mut header, &mut data) } {
+                1 => {
+                    let header = unsafe { &*header };
+                    let packet =
+                        unsafe { std::slice::from_raw_parts(data, header.caplen as usize) };
+                    writer
+                        .write_packet(
+                            header.tv_sec as u32,
+                            header.tv_usec as u32,
+                            packet,
+                            header.len,
+                        )
+                        .map_err(|e| format!("Failed to write packet: {}", e))?;
+                }
+                // read timeout expired
+                0 => continue,
+                // no more packets (savefile) or pcap_breakloop
+                -2 => break,
+                _ => {
+                    return Err(format!(
+                        "Capture failed: {}",
+                        to_string(unsafe { get_err(handle) })
+                    ))
+                }
+            }
+        }
+
+        writer
+            .finish()
+            .map_err(|e| format!("Failed to write capture file: {}", e))?;
+        Ok(writer)
+    };
+
+    let result = capture();
+    unsafe { close(handle) };
+    result
+}
+
+pub struct Pcap {}
+
+impl Pcap {
+    pub fn run(
+        attributes: PcapAttributes,
+        options: ActionOptions,
+        out_dir: PathBuf,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        let library = match load_library() {
+            Ok(library) => library,
+            Err(e) => {
+                error!("{}", e);
+                return error_result!(e, options.start_time);
+            }
+        };
+
+        let known_interfaces = match list_interfaces(&library) {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                warn!("Failed to list interfaces: {}", e);
+                vec![]
+            }
+        };
+        let interfaces = match attributes.interfaces.is_empty() {
+            true => match known_interfaces.first() {
+                Some((name, _)) => vec![name.clone()],
+                None => {
+                    return error_result!(
+                        "No interface available for capturing",
+                        options.start_time
+                    )
+                }
+            },
+            false => attributes.interfaces.clone(),
+        };
+
+        if let Err(e) = fs::create_dir_all(&out_dir) {
+            return error_result!(
+                format!("Failed to create {:?}: {}", out_dir.display(), e),
+                options.start_time
+            );
+        }
+
+        // Step 1: Capture on all interfaces at the same time
+        info!(
+            "Capturing on {} for {} seconds",
+            interfaces.join(", "),
+            attributes.duration
+        );
+        let results: Vec<Result<PcapWriter, String>> = thread::scope(|scope| {
+            let handles: Vec<_> = interfaces
+                .iter()
+                .map(|interface| {
+                    let library = &library;
+                    let attributes = &attributes;
+                    let out_dir = &out_dir;
+                    scope.spawn(move || {
+                        capture_interface(
+                            library,
+                            interface,
+                            attributes,
+                            out_dir,
+                            &sanitize_dirname(interface),
+                        )
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("Capture thread panicked".to_string()))
+                })
+                .collect()
+        });
+
+        // Step 2: Store the capture files along with the interface they belong to
+        let mut captures: Vec<InterfaceCapture> = vec![];
+        let mut errors: Vec<String> = vec![];
+        for (interface, result) in interfaces.iter().zip(results) {
+            let mut capture = InterfaceCapture {
+                interface: interface.clone(),
+                description: known_interfaces
+                    .iter()
+                    .find(|(name, _)| name == interface)
+                    .map(|(_, description)| description.clone())
+                    .unwrap_or_default(),
+                filter: attributes.filter.clone(),
+                ..Default::default()
+            };
+            match result {
+                Ok(writer) => {
+                    capture.packets = writer.packets;
+                    capture.bytes = writer.total_size;
+                    for (i, file) in writer.files.iter().enumerate() {
+                        let comment = format!(
+                            "interface: {}; description: {}; filter: {}; part: {}/{}",
+                            capture.interface,
+                            capture.description,
+                            capture.filter,
+                            i + 1,
+                            writer.files.len()
+                        );
+                        if let Err(e) = file_processor.store(file, Some(comment)) {
+                            errors.push(format!("Error storing file {:?}: {}", file.display(), e));
+                        }
+                        capture.files.push(file.to_string_lossy().to_string());
+                    }
+                    info!(
+                        "Captured {} packets on {} into {} files",
+                        capture.packets,
+                        capture.interface,
+                        capture.files.len()
+                    );
+                }
+                Err(e) => {
+                    errors.push(format!("{}: {}", interface, e));
+                    capture.error = Some(e);
+                }
+            }
+            captures.push(capture);
+        }
+        for error in &errors {
+            warn!("{}", error);
+        }
+
+        // Step 3: Write the summary to the action output directory
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        if let Err(e) = serde_json::to_writer_pretty(BufWriter::new(file), &captures) {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(captures.iter().map(|capture| capture.packets).sum())
+            .with_bytes(captures.iter().map(|capture| capture.bytes).sum())
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_pcap_writer_rotation() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_pcap_writer_rotation");
+
+        // every file holds the header and two packets of 16 + 100 bytes
+        let mut writer = PcapWriter::new(&dir, "eth0", 1, 65535, 24 + 2 * 116).unwrap();
+        for i in 0..5 {
+            writer
+                .write_packet(1700000000 + i, 0, &[0u8; 100], 1500)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(writer.packets, 5);
+        assert_eq!(writer.files.len(), 3);
+        assert_eq!(writer.total_size, 3 * 24 + 5 * 116);
+        assert_eq!(
+            writer.files[0].file_name().unwrap().to_string_lossy(),
+            "eth0_001.pcap"
+        );
+
+        let content = fs::read(&writer.files[0]).unwrap();
+        assert_eq!(content.len(), 24 + 2 * 116);
+        assert_eq!(content[..4], 0xa1b2c3d4u32.to_le_bytes());
+        assert_eq!(content[20..24], 1u32.to_le_bytes());
+        // captured and original length of the first packet
+        assert_eq!(content[32..36], 100u32.to_le_bytes());
+        assert_eq!(content[36..40], 1500u32.to_le_bytes());
+
+        let content = fs::read(&writer.files[2]).unwrap();
+        assert_eq!(content.len(), 24 + 116);
+    }
+}
@@ -0,0 +1,580 @@
+// Deleted files of the Windows Recycle Bin, the freedesktop trash on Linux and the trash on macOS.
+// The records name the original path and the deletion time where the platform keeps them
+use chrono::{Local, NaiveDateTime, TimeZone};
+use config::workflow::RecycleBinAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use storage::FileProcessor;
+use system::profiles::{get_user_profiles, UserProfile};
+use utils::misc::iter_files_by_pattern;
+
+use super::registry::filetime_to_rfc3339;
+use super::{error_result, ActionOptions, ActionResult};
+
+const KIND_RECYCLE_BIN: &str = "recycle_bin";
+const KIND_TRASH: &str = "trash";
+const KIND_MACOS_TRASH: &str = "macos_trash";
+
+// metadata of the Finder, e.g. the location a file is put back to
+const DS_STORE: &str = ".DS_Store";
+const TRASH_INFO_EXTENSION: &str = "trashinfo";
+
+/// One row of the resulting csv file
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct DeletedFile {
+    // recycle_bin, trash or macos_trash
+    pub kind: String,
+    pub user: String,
+    // empty on macOS, which keeps it in .DS_Store
+    pub original_path: String,
+    // RFC 3339, empty on macOS
+    pub deleted: String,
+    // as recorded when the file was deleted, the current size on macOS
+    pub size: u64,
+    // $I file or .trashinfo
+    pub info_file: String,
+    // $R file or the file inside the trash, empty if it has been removed
+    pub content_file: String,
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn read_utf16(data: &[u8]) -> String {
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
+
+/// Parses a $I file of the Recycle Bin: version 1 (Vista to 8.1) has a fixed path of
+/// 260 characters, version 2 (Windows 10 and later) stores the length of the path
+pub fn parse_recycle_bin_info(data: &[u8]) -> Option<DeletedFile> {
+    let size = read_u64(data, 8)?;
+    let deleted = filetime_to_rfc3339(read_u64(data, 16)?);
+    let path = match read_u64(data, 0)? {
+        1 => data.get(24..)?,
+        2 => {
+            let length = u32::from_le_bytes(data.get(24..28)?.try_into().ok()?) as usize;
+            let end = (28 + length * 2).min(data.len());
+            data.get(28..end)?
+        }
+        _ => return None,
+    };
+    Some(DeletedFile {
+        kind: KIND_RECYCLE_BIN.to_string(),
+        original_path: read_utf16(path),
+        deleted,
+        size,
+        ..Default::default()
+    })
+}
+
+// Paths in .trashinfo are URL-encoded, e.g. %20 for a space
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Parses a .trashinfo file of the freedesktop trash. The deletion date is in local time
+pub fn parse_trash_info(content: &str) -> Option<DeletedFile> {
+    let mut entry = DeletedFile {
+        kind: KIND_TRASH.to_string(),
+        ..Default::default()
+    };
+    let mut section = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line == "[Trash Info]";
+            continue;
+        }
+        match line.split_once('=') {
+            Some(("Path", path)) if section => entry.original_path = percent_decode(path),
+            Some(("DeletionDate", date)) if section => {
+                entry.deleted = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+                    .ok()
+                    .and_then(|date| Local.from_local_datetime(&date).earliest())
+                    .map(|date| date.to_rfc3339())
+                    .unwrap_or_else(|| date.to_string());
+            }
+            _ => {}
+        }
+    }
+    match entry.original_path.is_empty() {
+        true => None,
+        false => Some(entry),
+    }
+}
+
+fn content_size(path: &Path) -> u64 {
+    match path.is_dir() {
+        true => iter_files_by_pattern(&format!("{}/**/*", path.to_string_lossy()), true)
+            .map(|files| {
+                files
+                    .filter_map(|file| file.metadata().ok())
+                    .map(|m| m.len())
+                    .sum()
+            })
+            .unwrap_or(0),
+        false => path.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+    }
+}
+
+/// Everything found in the recycle bins and trash directories
+#[derive(Debug, Default)]
+pub struct CollectedTrash {
+    pub entries: Vec<DeletedFile>,
+    // $I, .trashinfo and .DS_Store files
+    pub info_files: Vec<PathBuf>,
+    pub errors: Vec<String>,
+}
+
+impl CollectedTrash {
+    // A missing trash is expected, everything else is reported
+    fn read_dir(&mut self, dir: &Path) -> Vec<PathBuf> {
+        match fs::read_dir(dir) {
+            Ok(entries) => {
+                let mut paths: Vec<PathBuf> = entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .collect();
+                paths.sort();
+                paths
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => {
+                self.errors
+                    .push(format!("Failed to read {:?}: {}", dir.display(), e));
+                vec![]
+            }
+        }
+    }
+
+    fn read(&mut self, path: &Path) -> Option<Vec<u8>> {
+        match fs::read(path) {
+            Ok(content) => {
+                self.info_files.push(path.to_path_buf());
+                Some(content)
+            }
+            Err(e) => {
+                self.errors
+                    .push(format!("Failed to read {:?}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    /// Recycle Bin of a volume (e.g. C:\$Recycle.Bin) with a directory per SID
+    pub fn collect_recycle_bin(&mut self, recycle_bin: &Path, users: &[UserProfile]) {
+        for sid_dir in self.read_dir(recycle_bin) {
+            if !sid_dir.is_dir() {
+                continue;
+            }
+            let sid = sid_dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let user = users
+                .iter()
+                .find(|user| user.id.eq_ignore_ascii_case(&sid))
+                .map(|user| user.name.clone())
+                .unwrap_or(sid);
+            for info_file in self.read_dir(&sid_dir) {
+                let name = info_file
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                let suffix = match name.strip_prefix("$I") {
+                    Some(suffix) => suffix,
+                    None => continue,
+                };
+                let data = match self.read(&info_file) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                let mut entry = match parse_recycle_bin_info(&data) {
+                    Some(entry) => entry,
+                    None => {
+                        self.errors
+                            .push(format!("Invalid $I file {:?}", info_file.display()));
+                        continue;
+                    }
+                };
+                // the content was removed if the Recycle Bin was emptied partially
+                let content_file = sid_dir.join(format!("$R{}", suffix));
+                if content_file.exists() {
+                    entry.content_file = content_file.to_string_lossy().to_string();
+                }
+                entry.user = user.clone();
+                entry.info_file = info_file.to_string_lossy().to_string();
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Trash of the freedesktop specification (e.g. ~/.local/share/Trash) with the
+    /// directories info and files
+    pub fn collect_trash(&mut self, trash_dir: &Path, user: &str) {
+        for info_file in self.read_dir(&trash_dir.join("info")) {
+            if info_file
+                .extension()
+                .is_none_or(|ext| ext != TRASH_INFO_EXTENSION)
+            {
+                continue;
+            }
+            let data = match self.read(&info_file) {
+                Some(data) => data,
+                None => continue,
+            };
+            let mut entry = match parse_trash_info(&String::from_utf8_lossy(&data)) {
+                Some(entry) => entry,
+                None => {
+                    self.errors
+                        .push(format!("Invalid trash info {:?}", info_file.display()));
+                    continue;
+                }
+            };
+            let content_file = trash_dir
+                .join("files")
+                .join(info_file.file_stem().unwrap_or_default());
+            if content_file.exists() {
+                entry.size = content_size(&content_file);
+                entry.content_file = content_file.to_string_lossy().to_string();
+            }
+            entry.user = user.to_string();
+            entry.info_file = info_file.to_string_lossy().to_string();
+            self.entries.push(entry);
+        }
+    }
+
+    /// ~/.Trash on macOS, which only keeps the original location in its .DS_Store
+    pub fn collect_macos_trash(&mut self, trash_dir: &Path, user: &str) {
+        let ds_store = trash_dir.join(DS_STORE);
+        let info_file = match ds_store.exists() && self.read(&ds_store).is_some() {
+            true => ds_store.to_string_lossy().to_string(),
+            false => String::new(),
+        };
+        for content_file in self.read_dir(trash_dir) {
+            if content_file
+                .file_name()
+                .is_some_and(|name| name == DS_STORE)
+            {
+                continue;
+            }
+            self.entries.push(DeletedFile {
+                kind: KIND_MACOS_TRASH.to_string(),
+                user: user.to_string(),
+                size: content_size(&content_file),
+                info_file: info_file.clone(),
+                content_file: content_file.to_string_lossy().to_string(),
+                ..Default::default()
+            });
+        }
+    }
+
+    pub fn collect(users: &[UserProfile]) -> Self {
+        let mut trash = CollectedTrash::default();
+        #[cfg(target_os = "windows")]
+        for drive in b'A'..=b'Z' {
+            let recycle_bin = PathBuf::from(format!("{}:\\$Recycle.Bin", drive as char));
+            if recycle_bin.is_dir() {
+                trash.collect_recycle_bin(&recycle_bin, users);
+            }
+        }
+        #[cfg(target_os = "macos")]
+        for user in users {
+            trash.collect_macos_trash(&user.home.join(".Trash"), &user.name);
+        }
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        for user in users {
+            trash.collect_trash(&user.home.join(".local/share/Trash"), &user.name);
+        }
+        trash
+    }
+}
+
+pub struct RecycleBin {}
+
+impl RecycleBin {
+    pub fn run(
+        attributes: RecycleBinAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        let users = get_user_profiles();
+        let trash = CollectedTrash::collect(&users);
+        let mut errors = trash.errors;
+
+        // the content is stored under the path of the bin, so the exclusions are matched against
+        // the original path. Neither the record nor the info file of a held back file is kept
+        let mut entries = vec![];
+        let mut held_back: HashSet<&str> = HashSet::new();
+        for entry in &trash.entries {
+            match !entry.original_path.is_empty()
+                && file_processor.is_excluded(Path::new(&entry.original_path))
+            {
+                true => {
+                    held_back.insert(&entry.info_file);
+                }
+                false => entries.push(entry),
+            }
+        }
+        for file in trash
+            .info_files
+            .iter()
+            .filter(|file| !held_back.contains(file.to_string_lossy().as_ref()))
+        {
+            if let Err(e) = file_processor.store(file, None) {
+                errors.push(format!("Error storing file {:?}: {}", file.display(), e));
+            }
+        }
+        let mut skipped = 0;
+        if attributes.store_content {
+            for entry in entries
+                .iter()
+                .filter(|entry| !entry.content_file.is_empty())
+            {
+                // a deleted directory is stored with all its files
+                let content_file = Path::new(&entry.content_file);
+                let files: Vec<PathBuf> = match content_file.is_dir() {
+                    true => iter_files_by_pattern(&format!("{}/**/*", entry.content_file), true)
+                        .map(|files| files.collect())
+                        .unwrap_or_default(),
+                    false => vec![content_file.to_path_buf()],
+                };
+                for file in files {
+                    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                    if attributes.size_limit > 0 && size > attributes.size_limit {
+                        skipped += 1;
+                        continue;
+                    }
+                    // files of a deleted directory are matched by their path inside it
+                    let relative = file.strip_prefix(content_file).unwrap_or(Path::new(""));
+                    if !entry.original_path.is_empty()
+                        && !relative.as_os_str().is_empty()
+                        && file_processor
+                            .is_excluded(&Path::new(&entry.original_path).join(relative))
+                    {
+                        continue;
+                    }
+                    let comment = (!entry.original_path.is_empty())
+                        .then(|| format!("Deleted from {}", entry.original_path));
+                    if let Err(e) = file_processor.store(&file, comment) {
+                        errors.push(format!("Error storing file {:?}: {}", file.display(), e));
+                    }
+                }
+            }
+        }
+
+        info!("Found {} deleted files", trash.entries.len());
+        if trash.entries.len() > entries.len() {
+            info!(
+                "Held back {} deleted files matching the exclusions",
+                trash.entries.len() - entries.len()
+            );
+        }
+        if skipped > 0 {
+            info!("Skipped {} deleted files above the size limit", skipped);
+        }
+        for error in &errors {
+            warn!("{}", error);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        let mut writer = csv::Writer::from_writer(file);
+        for entry in &entries {
+            if let Err(e) = writer.serialize(entry) {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote deleted files to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(trash.entries.len() as u64)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use utils::tests::Cleanup;
+
+    fn filetime(date: &DateTime<Utc>) -> u64 {
+        (date.timestamp() as u64) * 10_000_000 + 116_444_736_000_000_000
+    }
+
+    fn info_file(version: u64, size: u64, deleted: &DateTime<Utc>, path: &str) -> Vec<u8> {
+        let path: Vec<u16> = path.encode_utf16().chain([0]).collect();
+        let mut data = vec![];
+        data.extend(version.to_le_bytes());
+        data.extend(size.to_le_bytes());
+        data.extend(filetime(deleted).to_le_bytes());
+        if version == 2 {
+            data.extend((path.len() as u32).to_le_bytes());
+        }
+        data.extend(path.iter().flat_map(|c| c.to_le_bytes()));
+        if version == 1 {
+            data.resize(24 + 520, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_info_files() {
+        let deleted = DateTime::parse_from_rfc3339("2024-03-01T12:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        for version in [1, 2] {
+            let data = info_file(version, 1234, &deleted, "C:\\Users\\alice\\secret.docx");
+            let entry = parse_recycle_bin_info(&data).unwrap();
+            assert_eq!(entry.original_path, "C:\\Users\\alice\\secret.docx");
+            assert_eq!(entry.size, 1234);
+            assert_eq!(entry.deleted, "2024-03-01T12:30:00Z");
+        }
+        assert!(parse_recycle_bin_info(&info_file(3, 0, &deleted, "C:\\x")).is_none());
+        assert!(parse_recycle_bin_info(b"short").is_none());
+
+        let entry = parse_trash_info(
+            "[Trash Info]\nPath=/home/alice/my%20notes.txt\nDeletionDate=2024-03-01T12:30:00\n",
+        )
+        .unwrap();
+        assert_eq!(entry.original_path, "/home/alice/my notes.txt");
+        assert!(entry.deleted.starts_with("2024-03-01T12:30:00"));
+        assert!(parse_trash_info("[Other]\nPath=/tmp/x\n").is_none());
+        assert_eq!(percent_decode("%C3%A4%2"), "ä%2");
+    }
+
+    #[test]
+    fn test_collect_trash() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_collect_trash");
+        let deleted = Utc::now();
+
+        // Recycle Bin with a removed $R file and a file which is no $I file
+        let sid_dir = dir.join("$Recycle.Bin").join("S-1-5-21-1001");
+        fs::create_dir_all(&sid_dir).unwrap();
+        fs::write(
+            sid_dir.join("$IAB12CD.txt"),
+            info_file(2, 5, &deleted, "C:\\Users\\alice\\a.txt"),
+        )
+        .unwrap();
+        fs::write(sid_dir.join("$RAB12CD.txt"), "hello").unwrap();
+        fs::write(
+            sid_dir.join("$IEF34GH.txt"),
+            info_file(2, 9, &deleted, "C:\\Users\\alice\\b.txt"),
+        )
+        .unwrap();
+        fs::write(sid_dir.join("desktop.ini"), "").unwrap();
+        let user = UserProfile {
+            name: "alice".to_string(),
+            home: dir.join("alice"),
+            id: "S-1-5-21-1001".to_string(),
+            last_logon: None,
+        };
+
+        let mut trash = CollectedTrash::default();
+        trash.collect_recycle_bin(&dir.join("$Recycle.Bin"), &[user]);
+        assert!(trash.errors.is_empty(), "{:?}", trash.errors);
+        assert_eq!(trash.entries.len(), 2);
+        assert_eq!(trash.entries[0].user, "alice");
+        assert!(trash.entries[0].content_file.ends_with("$RAB12CD.txt"));
+        assert_eq!(trash.entries[1].content_file, "");
+        assert_eq!(trash.info_files.len(), 2);
+
+        // freedesktop trash with a deleted directory
+        let trash_dir = dir.join("Trash");
+        fs::create_dir_all(trash_dir.join("info")).unwrap();
+        fs::create_dir_all(trash_dir.join("files").join("project")).unwrap();
+        fs::write(
+            trash_dir.join("info").join("project.trashinfo"),
+            "[Trash Info]\nPath=/home/bob/project\nDeletionDate=2024-03-01T12:30:00\n",
+        )
+        .unwrap();
+        fs::write(trash_dir.join("files").join("project").join("a"), "12345").unwrap();
+        fs::write(trash_dir.join("files").join("project").join("b"), "678").unwrap();
+        let mut trash = CollectedTrash::default();
+        trash.collect_trash(&trash_dir, "bob");
+        trash.collect_trash(&dir.join("missing"), "carol");
+        assert!(trash.errors.is_empty(), "{:?}", trash.errors);
+        assert_eq!(trash.entries.len(), 1);
+        assert_eq!(trash.entries[0].original_path, "/home/bob/project");
+        assert_eq!(trash.entries[0].size, 8);
+
+        // macOS trash without original paths
+        let mac_trash = dir.join(".Trash");
+        fs::create_dir_all(&mac_trash).unwrap();
+        fs::write(mac_trash.join(DS_STORE), "Bud1").unwrap();
+        fs::write(mac_trash.join("invoice.pdf"), "%PDF").unwrap();
+        let mut trash = CollectedTrash::default();
+        trash.collect_macos_trash(&mac_trash, "carol");
+        assert_eq!(trash.entries.len(), 1);
+        assert_eq!(trash.entries[0].kind, KIND_MACOS_TRASH);
+        assert!(trash.entries[0].info_file.ends_with(DS_STORE));
+        assert_eq!(trash.info_files.len(), 1);
+    }
+}
@@ -0,0 +1,684 @@
+// Queries registry keys of the machine and of each user. The hives of users who are not logged in
+// (NTUSER.DAT and UsrClass.dat) are loaded below HKEY_USERS for the query and unloaded afterwards
+use config::workflow::RegistryAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{fs::File, path::PathBuf};
+use system::checks::split_registry_key;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+// the hives are loaded below HKEY_USERS with this prefix and the SID of the user
+const MOUNT_PREFIX: &str = "IRT_";
+
+// keys of HKEY_LOCAL_MACHINE and the other machine-wide roots
+pub const HIVE_MACHINE: &str = "machine";
+// hive of a logged in user, already loaded by Windows
+pub const HIVE_LIVE: &str = "live";
+// hive of a user who is not logged in, loaded by the collector
+pub const HIVE_LOADED: &str = "loaded";
+
+// value types, see winnt.h
+const REG_NONE: u32 = 0;
+const REG_SZ: u32 = 1;
+const REG_EXPAND_SZ: u32 = 2;
+const REG_BINARY: u32 = 3;
+const REG_DWORD: u32 = 4;
+const REG_DWORD_BIG_ENDIAN: u32 = 5;
+const REG_LINK: u32 = 6;
+const REG_MULTI_SZ: u32 = 7;
+const REG_QWORD: u32 = 11;
+
+/// One row of the resulting csv file, a key without values is listed with an empty value
+#[derive(Debug, Serialize, Default, Clone, PartialEq)]
+pub struct RegistryEntry {
+    pub user: String,
+    pub sid: String,
+    pub hive: String,
+    pub key: String,
+    pub last_write: String,
+    pub value: String,
+    pub value_type: String,
+    pub data: String,
+}
+
+/// Where a key of the workflow is queried
+#[derive(Debug, PartialEq)]
+pub enum KeyScope {
+    // canonical name of the root key (e.g. HKEY_LOCAL_MACHINE) and the sub key
+    Machine(&'static str, String),
+    // sub key inside NTUSER.DAT of each user
+    User(String),
+    // sub key inside UsrClass.dat of each user, which is mapped to HKCU\Software\Classes
+    Classes(String),
+}
+
+impl KeyScope {
+    pub fn parse(key: &str) -> Option<Self> {
+        const CLASSES: &str = "Software\\Classes";
+        let (root, sub_key) = split_registry_key(key)?;
+        if root != "HKEY_CURRENT_USER" {
+            return Some(KeyScope::Machine(root, sub_key));
+        }
+        let is_classes = sub_key
+            .get(..CLASSES.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(CLASSES))
+            && (sub_key.len() == CLASSES.len() || sub_key[CLASSES.len()..].starts_with('\\'));
+        match is_classes {
+            true => Some(KeyScope::Classes(
+                sub_key[CLASSES.len()..]
+                    .trim_start_matches('\\')
+                    .to_string(),
+            )),
+            false => Some(KeyScope::User(sub_key)),
+        }
+    }
+}
+
+/// Name of the key below HKEY_USERS the hive of the user is loaded to
+pub fn mount_name(sid: &str, classes: bool) -> String {
+    match classes {
+        true => format!("{}{}_Classes", MOUNT_PREFIX, sid),
+        false => format!("{}{}", MOUNT_PREFIX, sid),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn join_key(parent: &str, child: &str) -> String {
+    match (parent.is_empty(), child.is_empty()) {
+        (_, true) => parent.to_string(),
+        (true, false) => child.to_string(),
+        (false, false) => format!("{}\\{}", parent, child),
+    }
+}
+
+/// Name of the value type and the data as text (strings as is, numbers in decimal, anything else in hex)
+pub fn format_data(value_type: u32, data: &[u8]) -> (String, String) {
+    let wide = || -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect()
+    };
+    let hex = || {
+        data.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let (name, text) = match value_type {
+        REG_SZ | REG_EXPAND_SZ | REG_LINK => {
+            let wide = wide();
+            let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+            let name = match value_type {
+                REG_SZ => "REG_SZ",
+                REG_EXPAND_SZ => "REG_EXPAND_SZ",
+                _ => "REG_LINK",
+            };
+            (name, String::from_utf16_lossy(&wide[..len]))
+        }
+        REG_MULTI_SZ => (
+            "REG_MULTI_SZ",
+            wide()
+                .split(|&c| c == 0)
+                .filter(|s| !s.is_empty())
+                .map(String::from_utf16_lossy)
+                .collect::<Vec<String>>()
+                .join("\n"),
+        ),
+        REG_DWORD if data.len() >= 4 => (
+            "REG_DWORD",
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]]).to_string(),
+        ),
+        REG_DWORD_BIG_ENDIAN if data.len() >= 4 => (
+            "REG_DWORD_BIG_ENDIAN",
+            u32::from_be_bytes([data[0], data[1], data[2], data[3]]).to_string(),
+        ),
+        REG_QWORD if data.len() >= 8 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[..8]);
+            ("REG_QWORD", u64::from_le_bytes(bytes).to_string())
+        }
+        REG_NONE => ("REG_NONE", hex()),
+        REG_BINARY => ("REG_BINARY", hex()),
+        _ => return (format!("REG_{}", value_type), hex()),
+    };
+    (name.to_string(), text)
+}
+
+/// Last write time of a key (100ns intervals since 1601-01-01) as RFC 3339
+pub fn filetime_to_rfc3339(filetime: u64) -> String {
+    filetime
+        .checked_sub(116_444_736_000_000_000)
+        .and_then(|unix| chrono::DateTime::from_timestamp((unix / 10_000_000) as i64, 0))
+        .map(|date| date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+pub struct Registry {}
+
+impl Registry {
+    pub fn run(
+        attributes: RegistryAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+    ) -> ActionResult {
+        if !cfg!(target_os = "windows") {
+            return error_result!(
+                "Registry collection is only supported on Windows",
+                options.start_time
+            );
+        }
+
+        let mut scopes = vec![];
+        for key in &attributes.keys {
+            match KeyScope::parse(key) {
+                Some(scope) => scopes.push(scope),
+                None => {
+                    return error_result!(
+                        format!(
+                            "Invalid registry key {:?}, expected e.g. HKLM\\SOFTWARE",
+                            key
+                        ),
+                        options.start_time
+                    )
+                }
+            }
+        }
+
+        let (entries, errors) = collect(&attributes, &scopes);
+        info!(
+            "Found {} registry values in {} keys",
+            entries
+                .iter()
+                .filter(|entry| !entry.value_type.is_empty())
+                .count(),
+            attributes.keys.len()
+        );
+        for error in &errors {
+            warn!("{}", error);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        let mut writer = csv::Writer::from_writer(file);
+        for entry in &entries {
+            if let Err(e) = writer.serialize(entry) {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote registry values to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(entries.len() as u64)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn collect(
+    _attributes: &RegistryAttributes,
+    _scopes: &[KeyScope],
+) -> (Vec<RegistryEntry>, Vec<String>) {
+    (vec![], vec![])
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn collect(
+    attributes: &RegistryAttributes,
+    scopes: &[KeyScope],
+) -> (Vec<RegistryEntry>, Vec<String>) {
+    use system::profiles::get_user_profiles;
+    use winapi::um::winreg::HKEY_USERS;
+
+    let mut entries = vec![];
+    let mut errors = vec![];
+
+    for scope in scopes {
+        if let KeyScope::Machine(root, sub_key) = scope {
+            let template = RegistryEntry {
+                hive: HIVE_MACHINE.to_string(),
+                ..Default::default()
+            };
+            unsafe {
+                native::query(
+                    native::root_key(root),
+                    sub_key,
+                    &join_key(root, sub_key),
+                    attributes.depth,
+                    &template,
+                    &mut entries,
+                    &mut errors,
+                )
+            };
+        }
+    }
+
+    let user_keys = |classes: bool| -> Vec<&String> {
+        scopes
+            .iter()
+            .filter_map(|scope| match (scope, classes) {
+                (KeyScope::User(key), false) | (KeyScope::Classes(key), true) => Some(key),
+                _ => None,
+            })
+            .collect()
+    };
+    if user_keys(false).is_empty() && user_keys(true).is_empty() {
+        return (entries, errors);
+    }
+
+    // loading a hive requires the backup and restore privileges, which admins hold but don't enable
+    let mut load_hives = attributes.load_hives;
+    if load_hives {
+        for privilege in ["SeBackupPrivilege", "SeRestorePrivilege"] {
+            if let Err(e) = privileges::enable_privilege(privilege) {
+                errors.push(format!(
+                    "Can't load the hives of users who are not logged in: {}",
+                    e
+                ));
+                load_hives = false;
+                break;
+            }
+        }
+    }
+
+    for profile in get_user_profiles() {
+        for classes in [false, true] {
+            let keys = user_keys(classes);
+            if keys.is_empty() {
+                continue;
+            }
+            let live = match classes {
+                true => format!("{}_Classes", profile.id),
+                false => profile.id.clone(),
+            };
+
+            // unloaded once the keys are queried
+            let mut _loaded = None;
+            let (hive, mount) = if unsafe { native::key_exists(HKEY_USERS, &live) } {
+                (HIVE_LIVE, live)
+            } else if load_hives {
+                let file = match classes {
+                    true => profile
+                        .home
+                        .join("AppData\\Local\\Microsoft\\Windows\\UsrClass.dat"),
+                    false => profile.home.join("NTUSER.DAT"),
+                };
+                if !file.is_file() {
+                    debug!("No hive {:?} for user {}", file.display(), profile.name);
+                    continue;
+                }
+                let mount = mount_name(&profile.id, classes);
+                match unsafe { native::LoadedHive::load(&mount, &file) } {
+                    Ok(loaded) => _loaded = Some(loaded),
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                }
+                (HIVE_LOADED, mount)
+            } else {
+                debug!(
+                    "Skipping the hive of user {}: not logged in and load_hives is disabled",
+                    profile.name
+                );
+                continue;
+            };
+
+            let template = RegistryEntry {
+                user: profile.name.clone(),
+                sid: profile.id.clone(),
+                hive: hive.to_string(),
+                ..Default::default()
+            };
+            for key in keys {
+                let display = match classes {
+                    true => join_key("HKEY_CURRENT_USER\\Software\\Classes", key),
+                    false => join_key("HKEY_CURRENT_USER", key),
+                };
+                unsafe {
+                    native::query(
+                        HKEY_USERS,
+                        &join_key(&mount, key),
+                        &display,
+                        attributes.depth,
+                        &template,
+                        &mut entries,
+                        &mut errors,
+                    )
+                };
+            }
+        }
+    }
+    (entries, errors)
+}
+
+#[cfg(target_os = "windows")]
+mod native {
+    use super::*;
+    use std::{io, os::windows::ffi::OsStrExt, path::Path, ptr::null_mut};
+    use winapi::{
+        shared::{
+            minwindef::{DWORD, FILETIME, HKEY},
+            winerror::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS},
+        },
+        um::{
+            winnt::KEY_READ,
+            winreg::{
+                RegCloseKey, RegEnumKeyExW, RegEnumValueW, RegLoadKeyW, RegOpenKeyExW,
+                RegQueryInfoKeyW, RegUnLoadKeyW, HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG,
+                HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS,
+            },
+        },
+    };
+
+    fn to_wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    pub fn root_key(root: &str) -> HKEY {
+        match root {
+            "HKEY_LOCAL_MACHINE" => HKEY_LOCAL_MACHINE,
+            "HKEY_CURRENT_USER" => HKEY_CURRENT_USER,
+            "HKEY_USERS" => HKEY_USERS,
+            "HKEY_CLASSES_ROOT" => HKEY_CLASSES_ROOT,
+            _ => HKEY_CURRENT_CONFIG,
+        }
+    }
+
+    pub unsafe fn key_exists(root: HKEY, path: &str) -> bool {
+        let mut key: HKEY = null_mut();
+        if RegOpenKeyExW(root, to_wide(path).as_ptr(), 0, KEY_READ, &mut key) as DWORD
+            != ERROR_SUCCESS
+        {
+            return false;
+        }
+        RegCloseKey(key);
+        true
+    }
+
+    /// A hive file loaded below HKEY_USERS, unloaded once dropped.
+    /// All keys opened inside the hive have to be closed before
+    pub struct LoadedHive {
+        name: String,
+    }
+
+    impl LoadedHive {
+        pub unsafe fn load(name: &str, file: &Path) -> Result<Self, String> {
+            let wide_name = to_wide(name);
+            // left behind by an aborted run
+            if key_exists(HKEY_USERS, name) {
+                RegUnLoadKeyW(HKEY_USERS, wide_name.as_ptr());
+            }
+            let wide_file: Vec<u16> = file
+                .as_os_str()
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect();
+            let status = RegLoadKeyW(HKEY_USERS, wide_name.as_ptr(), wide_file.as_ptr());
+            if status as DWORD != ERROR_SUCCESS {
+                return Err(format!(
+                    "Failed to load hive {:?}: {}",
+                    file.display(),
+                    io::Error::from_raw_os_error(status)
+                ));
+            }
+            debug!("Loaded hive {:?} to HKEY_USERS\\{}", file.display(), name);
+            Ok(Self {
+                name: name.to_string(),
+            })
+        }
+    }
+
+    impl Drop for LoadedHive {
+        fn drop(&mut self) {
+            let status = unsafe { RegUnLoadKeyW(HKEY_USERS, to_wide(&self.name).as_ptr()) };
+            match status as DWORD {
+                ERROR_SUCCESS => debug!("Unloaded hive HKEY_USERS\\{}", self.name),
+                _ => error!(
+                    "Failed to unload hive HKEY_USERS\\{}: {}",
+                    self.name,
+                    io::Error::from_raw_os_error(status)
+                ),
+            }
+        }
+    }
+
+    /// Adds the values of the key and its subkeys up to the depth. `display` is the path of the
+    /// key in the output, e.g. HKEY_CURRENT_USER\... for a key inside a loaded hive
+    pub unsafe fn query(
+        root: HKEY,
+        path: &str,
+        display: &str,
+        depth: u32,
+        template: &RegistryEntry,
+        entries: &mut Vec<RegistryEntry>,
+        errors: &mut Vec<String>,
+    ) {
+        let mut key: HKEY = null_mut();
+        let status = RegOpenKeyExW(root, to_wide(path).as_ptr(), 0, KEY_READ, &mut key);
+        match status as DWORD {
+            ERROR_SUCCESS => (),
+            // e.g. a Run key which was never created
+            ERROR_FILE_NOT_FOUND => {
+                debug!("Registry key {} does not exist", display);
+                return;
+            }
+            _ => {
+                errors.push(format!(
+                    "Failed to open registry key {}: {}",
+                    display,
+                    io::Error::from_raw_os_error(status)
+                ));
+                return;
+            }
+        }
+
+        let mut subkeys: DWORD = 0;
+        let mut max_subkey_len: DWORD = 0;
+        let mut values: DWORD = 0;
+        let mut max_value_name_len: DWORD = 0;
+        let mut max_value_len: DWORD = 0;
+        let mut last_write = FILETIME {
+            dwLowDateTime: 0,
+            dwHighDateTime: 0,
+        };
+        let status = RegQueryInfoKeyW(
+            key,
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            &mut subkeys,
+            &mut max_subkey_len,
+            null_mut(),
+            &mut values,
+            &mut max_value_name_len,
+            &mut max_value_len,
+            null_mut(),
+            &mut last_write,
+        );
+        if status as DWORD != ERROR_SUCCESS {
+            errors.push(format!(
+                "Failed to query registry key {}: {}",
+                display,
+                io::Error::from_raw_os_error(status)
+            ));
+            RegCloseKey(key);
+            return;
+        }
+
+        let entry = RegistryEntry {
+            key: display.to_string(),
+            last_write: filetime_to_rfc3339(
+                ((last_write.dwHighDateTime as u64) << 32) | last_write.dwLowDateTime as u64,
+            ),
+            ..template.clone()
+        };
+        // listed anyway, as the last write time tells when the values were removed
+        if values == 0 {
+            entries.push(entry.clone());
+        }
+        let mut name = vec![0u16; max_value_name_len as usize + 1];
+        let mut data = vec![0u8; max_value_len as usize + 1];
+        for index in 0..values {
+            let mut name_len = name.len() as DWORD;
+            let mut data_len = data.len() as DWORD;
+            let mut value_type: DWORD = 0;
+            let status = RegEnumValueW(
+                key,
+                index,
+                name.as_mut_ptr(),
+                &mut name_len,
+                null_mut(),
+                &mut value_type,
+                data.as_mut_ptr(),
+                &mut data_len,
+            );
+            if status as DWORD != ERROR_SUCCESS {
+                errors.push(format!(
+                    "Failed to read value {} of registry key {}: {}",
+                    index,
+                    display,
+                    io::Error::from_raw_os_error(status)
+                ));
+                continue;
+            }
+            let (value_type, data) = format_data(value_type, &data[..data_len as usize]);
+            entries.push(RegistryEntry {
+                value: String::from_utf16_lossy(&name[..name_len as usize]),
+                value_type,
+                data,
+                ..entry.clone()
+            });
+        }
+
+        let mut children = vec![];
+        if depth > 0 {
+            let mut buffer = vec![0u16; max_subkey_len as usize + 1];
+            for index in 0..subkeys {
+                let mut len = buffer.len() as DWORD;
+                let status = RegEnumKeyExW(
+                    key,
+                    index,
+                    buffer.as_mut_ptr(),
+                    &mut len,
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                );
+                if status as DWORD == ERROR_SUCCESS {
+                    children.push(String::from_utf16_lossy(&buffer[..len as usize]));
+                }
+            }
+        }
+        // closed before the subkeys are opened, so a loaded hive can be unloaded afterwards
+        RegCloseKey(key);
+        for child in children {
+            query(
+                root,
+                &join_key(path, &child),
+                &join_key(display, &child),
+                depth - 1,
+                template,
+                entries,
+                errors,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_scope() {
+        assert_eq!(
+            KeyScope::parse("HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run"),
+            Some(KeyScope::Machine(
+                "HKEY_LOCAL_MACHINE",
+                "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run".to_string()
+            ))
+        );
+        assert_eq!(
+            KeyScope::parse("HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Run"),
+            Some(KeyScope::User(
+                "Software\\Microsoft\\Windows\\CurrentVersion\\Run".to_string()
+            ))
+        );
+        assert_eq!(
+            KeyScope::parse("HKEY_CURRENT_USER\\SOFTWARE\\classes\\CLSID"),
+            Some(KeyScope::Classes("CLSID".to_string()))
+        );
+        assert_eq!(
+            KeyScope::parse("HKCU\\Software\\Classes"),
+            Some(KeyScope::Classes(String::new()))
+        );
+        assert_eq!(
+            KeyScope::parse("HKCU\\Software\\ClassesOther"),
+            Some(KeyScope::User("Software\\ClassesOther".to_string()))
+        );
+        assert_eq!(KeyScope::parse("HKXX\\Software"), None);
+        assert_eq!(mount_name("S-1-5-21-1", true), "IRT_S-1-5-21-1_Classes");
+    }
+
+    #[test]
+    fn test_format_data() {
+        let wide = |s: &str| -> Vec<u8> {
+            s.encode_utf16()
+                .chain(std::iter::once(0))
+                .flat_map(u16::to_le_bytes)
+                .collect()
+        };
+        assert_eq!(
+            format_data(REG_SZ, &wide("C:\\evil.exe")),
+            ("REG_SZ".to_string(), "C:\\evil.exe".to_string())
+        );
+        let mut multi = wide("a");
+        multi.extend(wide("b"));
+        multi.extend([0, 0]);
+        assert_eq!(format_data(REG_MULTI_SZ, &multi).1, "a\nb");
+        assert_eq!(
+            format_data(REG_DWORD, &[1, 1, 0, 0]),
+            ("REG_DWORD".to_string(), "257".to_string())
+        );
+        assert_eq!(
+            format_data(REG_BINARY, &[0xde, 0xad]),
+            ("REG_BINARY".to_string(), "dead".to_string())
+        );
+        assert_eq!(format_data(42, &[1]).0, "REG_42");
+        assert_eq!(
+            filetime_to_rfc3339(133_000_000_000_000_000),
+            "2022-06-18T04:26:40Z"
+        );
+    }
+}
@@ -0,0 +1,548 @@
+// Restricts the processes started by binary and command actions, so a third-party triage tool
+// can't touch more of the host than it needs to:
+//   Linux: Landlock denies writes outside of writable_paths, a seccomp filter denies network
+//          sockets and syscalls changing the system (kernel modules, mounts, time, ptrace, ...)
+//   Windows: a job object denies access to the desktop, the clipboard and the system settings.
+//            The network can't be blocked, so the tool is only started if it is allowed
+//   macOS: the tool is started by sandbox-exec with a profile denying network access and writes
+use config::workflow::Sandbox;
+use log::debug;
+use privileges::RunAs;
+use process_wrap::tokio::{TokioCommandWrap, TokioCommandWrapper};
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::process::Command;
+
+#[cfg(target_os = "macos")]
+const SANDBOX_EXEC: &str = "/usr/bin/sandbox-exec";
+
+/// The command of the tool, started as the run_as user. On macOS, the tool is started by sandbox-exec
+pub fn command<S: AsRef<OsStr>>(
+    program: S,
+    args: &[String],
+    sandbox: &Sandbox,
+    run_as: Option<&RunAs>,
+) -> Command {
+    // the collector starts itself as proxy, which starts the tool with the token of the user
+    #[cfg(windows)]
+    if let Some(run_as) = run_as {
+        debug!("Running as user {:?}", run_as.user);
+        let mut cmd = Command::new(std::env::current_exe().unwrap_or_default());
+        cmd.arg(privileges::RUN_AS_ARG)
+            .arg(run_as.session.to_string())
+            .arg(&run_as.user)
+            .arg(program)
+            .args(args);
+        return cmd;
+    }
+
+    let mut cmd = sandboxed_command(program, args, sandbox);
+
+    // drop the privileges of the collector for the started process
+    #[cfg(unix)]
+    if let Some(run_as) = run_as {
+        debug!("Running as user {:?}", run_as.user);
+        cmd.envs(run_as.env());
+        let run_as = run_as.clone();
+        // Command::uid would drop the supplementary groups, see RunAs::switch_user
+        unsafe {
+            cmd.pre_exec(move || run_as.switch_user());
+        }
+    }
+    cmd
+}
+
+fn sandboxed_command<S: AsRef<OsStr>>(program: S, args: &[String], sandbox: &Sandbox) -> Command {
+    #[cfg(target_os = "macos")]
+    if sandbox.enabled {
+        let mut cmd = Command::new(SANDBOX_EXEC);
+        cmd.arg("-p")
+            .arg(macos_profile(sandbox))
+            .arg(program)
+            .args(args);
+        return cmd;
+    }
+    #[cfg(not(target_os = "macos"))]
+    let _ = sandbox;
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd
+}
+
+// resolved, e.g. /tmp is /private/tmp on macOS
+fn writable_paths(sandbox: &Sandbox) -> Vec<PathBuf> {
+    sandbox
+        .writable_paths
+        .iter()
+        .filter(|path| !path.trim().is_empty())
+        .map(|path| fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)))
+        .collect()
+}
+
+fn profile_string(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Profile of sandbox-exec: everything is allowed, except for network access and writes
+/// outside of the writable paths and /dev (e.g. /dev/null)
+pub fn macos_profile(sandbox: &Sandbox) -> String {
+    let mut profile = vec!["(version 1)".to_string(), "(allow default)".to_string()];
+    if !sandbox.network {
+        profile.push("(deny network*)".to_string());
+        // local sockets, e.g. of system services
+        profile.push("(allow network* (remote unix-socket))".to_string());
+    }
+    let mut writable = vec![format!("(subpath {})", profile_string(Path::new("/dev")))];
+    writable.extend(
+        writable_paths(sandbox)
+            .iter()
+            .map(|path| format!("(subpath {})", profile_string(path))),
+    );
+    profile.push("(deny file-write*)".to_string());
+    profile.push(format!("(allow file-write* {})", writable.join(" ")));
+    profile.join("\n")
+}
+
+/// Wrapper restricting the started process on Linux and Windows
+#[derive(Debug)]
+pub struct Sandboxed {
+    sandbox: Sandbox,
+    // Landlock ruleset, inherited by the process until it restricts itself
+    #[cfg(target_os = "linux")]
+    ruleset: Option<std::os::fd::OwnedFd>,
+}
+
+impl Sandboxed {
+    pub fn new(sandbox: Sandbox) -> Self {
+        Self {
+            sandbox,
+            #[cfg(target_os = "linux")]
+            ruleset: None,
+        }
+    }
+}
+
+impl TokioCommandWrapper for Sandboxed {
+    #[cfg(target_os = "linux")]
+    fn pre_spawn(&mut self, command: &mut Command, _core: &TokioCommandWrap) -> io::Result<()> {
+        self.ruleset = linux::restrict(command, &self.sandbox)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn post_spawn(
+        &mut self,
+        _child: &mut tokio::process::Child,
+        _core: &TokioCommandWrap,
+    ) -> io::Result<()> {
+        self.ruleset = None;
+        Ok(())
+    }
+
+    // job objects can only throttle the network, which would leave it open to the tool
+    #[cfg(target_os = "windows")]
+    fn pre_spawn(&mut self, _command: &mut Command, _core: &TokioCommandWrap) -> io::Result<()> {
+        match self.sandbox.network {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The sandbox can't block the network on Windows: set sandbox.network to true to run the tool with network access",
+            )),
+        }
+    }
+
+    // the process is still suspended by the JobObject wrapper, which resumes it afterwards
+    #[cfg(target_os = "windows")]
+    fn post_spawn(
+        &mut self,
+        child: &mut tokio::process::Child,
+        _core: &TokioCommandWrap,
+    ) -> io::Result<()> {
+        windows::confine(child, &self.sandbox)
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub mod linux {
+    use super::*;
+    use log::warn;
+    use std::{
+        fs::OpenOptions,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::fs::OpenOptionsExt,
+        },
+        ptr,
+    };
+
+    // Landlock ABI 1, see https://docs.kernel.org/userspace-api/landlock.html
+    const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    // write_file, remove_dir, remove_file and make_char to make_sym (bits 4 to 12)
+    const ACCESS_FS_WRITE: u64 = ACCESS_FS_WRITE_FILE | (0x1ff << 4);
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Ruleset denying writes outside of the paths, None if the kernel doesn't support Landlock
+    pub fn landlock_ruleset(paths: &[PathBuf]) -> io::Result<Option<OwnedFd>> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                ptr::null::<RulesetAttr>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Ok(None);
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: ACCESS_FS_WRITE,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0 as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        for path in paths {
+            let file = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            // only the rights of files can be granted on a file
+            let rule = PathBeneathAttr {
+                allowed_access: match path.is_dir() {
+                    true => ACCESS_FS_WRITE,
+                    false => ACCESS_FS_WRITE_FILE,
+                },
+                parent_fd: file.as_raw_fd(),
+            };
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0 as libc::c_uint,
+                )
+            };
+            if result < 0 {
+                let e = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", path.display(), e),
+                ));
+            }
+        }
+        Ok(Some(ruleset))
+    }
+
+    const AUDIT_ARCH: Option<u32> = if cfg!(target_arch = "x86_64") {
+        Some(0xc000_003e)
+    } else if cfg!(target_arch = "aarch64") {
+        Some(0xc000_00b7)
+    } else {
+        None
+    };
+
+    // syscalls changing the system instead of reading it
+    const DENIED_SYSCALLS: [libc::c_long; 21] = [
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_writev,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_bpf,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_clock_adjtime,
+        libc::SYS_adjtimex,
+        libc::SYS_sethostname,
+        libc::SYS_setdomainname,
+        // its operations (e.g. creating sockets) are not seen by the filter
+        libc::SYS_io_uring_setup,
+    ];
+
+    fn statement(code: u32, k: u32) -> libc::sock_filter {
+        jump(code, k, 0, 0)
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    fn deny(errno: i32) -> libc::sock_filter {
+        statement(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA),
+        )
+    }
+
+    /// Seccomp filter denying the syscalls and, if the network is not allowed, sockets of the
+    /// internet protocols. None if the architecture is not supported
+    pub fn seccomp_filter(network: bool) -> Option<Vec<libc::sock_filter>> {
+        let arch = AUDIT_ARCH?;
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let equals = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+        // offsets of struct seccomp_data
+        let (nr, arch_offset, first_arg) = (0, 4, 16);
+
+        let mut filter = vec![
+            statement(load, arch_offset),
+            jump(equals, arch, 1, 0),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(load, nr),
+            // syscalls of the x32 ABI
+            jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                0x4000_0000,
+                0,
+                1,
+            ),
+            deny(libc::ENOSYS),
+        ];
+        for syscall in DENIED_SYSCALLS {
+            filter.push(jump(equals, syscall as u32, 0, 1));
+            filter.push(deny(libc::EPERM));
+        }
+        if !network {
+            let families = [libc::AF_INET, libc::AF_INET6, libc::AF_PACKET];
+            filter.push(jump(
+                equals,
+                libc::SYS_socket as u32,
+                0,
+                1 + 2 * families.len() as u8,
+            ));
+            // the lower 32 bits of the domain
+            filter.push(statement(load, first_arg));
+            for family in families {
+                filter.push(jump(equals, family as u32, 0, 1));
+                filter.push(deny(libc::EACCES));
+            }
+        }
+        filter.push(statement(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_ALLOW,
+        ));
+        Some(filter)
+    }
+
+    /// Restricts the process right before the tool is executed. Returns the Landlock ruleset,
+    /// which has to be kept open until the process has been started
+    pub fn restrict(command: &mut Command, sandbox: &Sandbox) -> io::Result<Option<OwnedFd>> {
+        let mut paths = vec![PathBuf::from("/dev")];
+        paths.extend(writable_paths(sandbox));
+        let ruleset = landlock_ruleset(&paths)?;
+        if ruleset.is_none() {
+            warn!("The kernel doesn't support Landlock: writes of the sandboxed tool are not restricted");
+        }
+        let filter = seccomp_filter(sandbox.network);
+        if filter.is_none() {
+            warn!("No seccomp filter for this architecture: syscalls and network access of the sandboxed tool are not restricted");
+        }
+
+        let ruleset_fd = ruleset.as_ref().map(|ruleset| ruleset.as_raw_fd());
+        unsafe {
+            command.pre_exec(move || {
+                // required to restrict an unprivileged process, also applies to its child processes
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Some(fd) = ruleset_fd {
+                    if libc::syscall(libc::SYS_landlock_restrict_self, fd, 0 as libc::c_uint) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(filter) = &filter {
+                    let program = libc::sock_fprog {
+                        len: filter.len() as libc::c_ushort,
+                        filter: filter.as_ptr() as *mut libc::sock_filter,
+                    };
+                    if libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER as libc::c_ulong,
+                        &program as *const libc::sock_fprog,
+                    ) != 0
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(ruleset)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows {
+    use super::*;
+    use std::{mem::size_of, os::windows::io::AsRawHandle, ptr};
+    use winapi::{
+        ctypes::c_void,
+        um::{
+            handleapi::CloseHandle,
+            jobapi2::{AssignProcessToJobObject, SetInformationJobObject},
+            winbase::CreateJobObjectW,
+            winnt::{
+                JobObjectBasicUIRestrictions, HANDLE, JOBOBJECT_BASIC_UI_RESTRICTIONS,
+                JOB_OBJECT_UILIMIT_DESKTOP, JOB_OBJECT_UILIMIT_DISPLAYSETTINGS,
+                JOB_OBJECT_UILIMIT_EXITWINDOWS, JOB_OBJECT_UILIMIT_GLOBALATOMS,
+                JOB_OBJECT_UILIMIT_HANDLES, JOB_OBJECT_UILIMIT_READCLIPBOARD,
+                JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS, JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+            },
+        },
+    };
+
+    fn set_information<T>(job: HANDLE, class: u32, information: &mut T) -> io::Result<()> {
+        let result = unsafe {
+            SetInformationJobObject(
+                job,
+                class,
+                information as *mut T as *mut c_void,
+                size_of::<T>() as u32,
+            )
+        };
+        match result {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Assigns the process to a job object with the restrictions. Jobs are nested,
+    /// so the job object of the KillOnDrop wrapper still terminates the process
+    pub fn confine(child: &tokio::process::Child, sandbox: &Sandbox) -> io::Result<()> {
+        let process = child
+            .raw_handle()
+            .ok_or_else(|| io::Error::other("The process has already exited"))?;
+        let job = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+        if job.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let result = (|| {
+            if !sandbox.ui {
+                let mut restrictions = JOBOBJECT_BASIC_UI_RESTRICTIONS {
+                    UIRestrictionsClass: JOB_OBJECT_UILIMIT_DESKTOP
+                        | JOB_OBJECT_UILIMIT_DISPLAYSETTINGS
+                        | JOB_OBJECT_UILIMIT_EXITWINDOWS
+                        | JOB_OBJECT_UILIMIT_GLOBALATOMS
+                        | JOB_OBJECT_UILIMIT_HANDLES
+                        | JOB_OBJECT_UILIMIT_READCLIPBOARD
+                        | JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS
+                        | JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+                };
+                set_information(job, JobObjectBasicUIRestrictions, &mut restrictions)?;
+            }
+            match unsafe { AssignProcessToJobObject(job, process as HANDLE) } {
+                0 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        })();
+        // the job exists as long as the process is assigned to it
+        unsafe { CloseHandle(job) };
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macos_profile() {
+        let sandbox = Sandbox {
+            enabled: true,
+            writable_paths: vec!["/nonexistent/out \"dir\"".to_string()],
+            ..Default::default()
+        };
+        let profile = macos_profile(&sandbox);
+        assert!(profile.contains("(deny network*)"));
+        assert!(profile.ends_with(
+            "(allow file-write* (subpath \"/dev\") (subpath \"/nonexistent/out \\\"dir\\\"\"))"
+        ));
+        let profile = macos_profile(&Sandbox {
+            network: true,
+            ..sandbox
+        });
+        assert!(!profile.contains("network"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sandboxed_command() {
+        use utils::tests::Cleanup;
+
+        let filter = linux::seccomp_filter(false).unwrap();
+        let allowed = linux::seccomp_filter(true).unwrap();
+        // the checks of the socket domain
+        assert_eq!(filter.len(), allowed.len() + 8);
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_sandboxed_command");
+        let writable = dir.join("writable");
+        fs::create_dir_all(&writable).unwrap();
+        let sandbox = Sandbox {
+            enabled: true,
+            writable_paths: vec![writable.to_string_lossy().to_string()],
+            ..Default::default()
+        };
+        let run = |script: String| {
+            let mut child =
+                TokioCommandWrap::from(command("sh", &["-c".to_string(), script], &sandbox, None));
+            child.wrap(Sandboxed::new(sandbox.clone()));
+            child.spawn().unwrap()
+        };
+
+        let status =
+            Box::into_pin(run(format!("echo test > {}/allowed", writable.display())).wait())
+                .await
+                .unwrap();
+        assert!(status.success());
+        assert!(writable.join("allowed").exists());
+
+        // only denied if the kernel supports Landlock
+        if linux::landlock_ruleset(&[]).unwrap().is_some() {
+            let status = Box::into_pin(run(format!("echo test > {}/denied", dir.display())).wait())
+                .await
+                .unwrap();
+            assert!(!status.success());
+            assert!(!dir.join("denied").exists());
+        }
+    }
+}
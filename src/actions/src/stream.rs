@@ -0,0 +1,194 @@
+// Streams the stdout of a command or binary to local subscribers (e.g. the capture of an EDR)
+// on a UNIX socket or a named pipe, while the collector still logs the output as usual
+use log::{debug, error};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+    task::JoinHandle,
+};
+
+type Subscriber = Box<dyn AsyncWrite + Unpin + Send>;
+pub type Stdout = Box<dyn AsyncRead + Unpin + Send>;
+
+// a subscriber which doesn't read its data is dropped, so it can't stall the action
+const SUBSCRIBER_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct OutputStream {
+    target: String,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl OutputStream {
+    /// Listens on the UNIX socket (Linux, macOS) or named pipe (Windows, e.g. \\.\pipe\ir-toolkit)
+    pub fn bind(target: &str) -> Result<Self, String> {
+        let subscribers: Arc<Mutex<Vec<Subscriber>>> = Arc::new(Mutex::new(vec![]));
+        let accept_task = accept(target, subscribers.clone())
+            .map_err(|e| format!("Failed to listen on {:?}: {}", target, e))?;
+        debug!("Streaming output to {:?}", target);
+        Ok(Self {
+            target: target.to_string(),
+            subscribers,
+            accept_task,
+        })
+    }
+
+    /// Sends the stdout to all subscribers and returns it to be logged like the stdout of the process
+    pub fn tee(self, stdout: Option<Stdout>) -> Option<Stdout> {
+        let mut stdout = stdout?;
+        // the logging of the collector reads from the other end
+        let (mut log, reader) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; 8192];
+            while let Ok(n) = stdout.read(&mut buffer).await {
+                if n == 0 {
+                    break;
+                }
+                self.send(&buffer[..n]).await;
+                if log.write_all(&buffer[..n]).await.is_err() {
+                    break;
+                }
+            }
+            // the subscribers and the socket are closed once the process exits
+        });
+        Some(Box::new(reader))
+    }
+
+    async fn send(&self, chunk: &[u8]) {
+        let mut subscribers = self.subscribers.lock().await;
+        let mut connected = Vec::with_capacity(subscribers.len());
+        for mut subscriber in subscribers.drain(..) {
+            match tokio::time::timeout(SUBSCRIBER_TIMEOUT, subscriber.write_all(chunk)).await {
+                Ok(Ok(_)) => connected.push(subscriber),
+                _ => debug!("Subscriber of {:?} disconnected", self.target),
+            }
+        }
+        *subscribers = connected;
+    }
+}
+
+impl Drop for OutputStream {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        #[cfg(unix)]
+        if let Err(e) = std::fs::remove_file(&self.target) {
+            error!("Failed to remove socket {:?}: {}", self.target, e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn accept(
+    target: &str,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+) -> std::io::Result<JoinHandle<()>> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+
+    // left behind by an aborted run, anything else is not overwritten
+    if let Ok(metadata) = std::fs::symlink_metadata(target) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(target)?;
+        }
+    }
+    let listener = UnixListener::bind(target)?;
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => subscribers.lock().await.push(Box::new(stream)),
+                Err(e) => {
+                    error!("Failed to accept subscriber: {}", e);
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(windows)]
+fn accept(
+    target: &str,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+) -> std::io::Result<JoinHandle<()>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(target)?;
+    let target = target.to_string();
+    Ok(tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                error!("Failed to accept subscriber: {}", e);
+                break;
+            }
+            // each subscriber is connected to its own instance of the pipe
+            let next = match ServerOptions::new().create(&target) {
+                Ok(next) => next,
+                Err(e) => {
+                    error!("Failed to create pipe {:?}: {}", target, e);
+                    break;
+                }
+            };
+            let connected = std::mem::replace(&mut server, next);
+            subscribers.lock().await.push(Box::new(connected));
+        }
+    }))
+}
+
+/// Copies the streamed stdout into the log file or the console, as the process can't write to it directly
+pub fn spawn_copy<W>(stdout: Option<Stdout>, mut writer: W) -> JoinHandle<Result<(), String>>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Some(mut stdout) = stdout {
+            tokio::io::copy(&mut stdout, &mut writer)
+                .await
+                .map_err(|e| format!("Failed to write output: {}", e))?;
+        }
+        writer
+            .flush()
+            .await
+            .map_err(|e| format!("Failed to write output: {}", e))
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+    use utils::tests::Cleanup;
+
+    #[tokio::test]
+    async fn test_output_stream() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_output_stream");
+        let socket = dir.join("output.sock");
+        let target = socket.to_string_lossy().to_string();
+
+        let stream = OutputStream::bind(&target).unwrap();
+        let mut subscriber = UnixStream::connect(&socket).await.unwrap();
+        // wait until the subscriber is accepted
+        while stream.subscribers.lock().await.is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        let stdout: Stdout = Box::new(&b"line 1\nline 2\n"[..]);
+        let mut logged = String::new();
+        stream
+            .tee(Some(stdout))
+            .unwrap()
+            .read_to_string(&mut logged)
+            .await
+            .unwrap();
+        assert_eq!(logged, "line 1\nline 2\n");
+
+        let mut streamed = String::new();
+        subscriber.read_to_string(&mut streamed).await.unwrap();
+        assert_eq!(streamed, logged);
+        assert!(!socket.exists());
+    }
+}
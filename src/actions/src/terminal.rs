@@ -1,16 +1,83 @@
-use super::{error_result, get_stream_error, ActionOptions, ActionResult};
-use config::workflow::TerminalAttributes;
+use super::{error_result, get_stream_error, usage, ActionOptions, ActionResult};
+use chrono::Utc;
+use config::workflow::{TerminalAttributes, TerminalAudit};
 use log::{debug, info, warn};
 use process_wrap::tokio::*;
-use std::{path::PathBuf, process::Stdio, time};
-use tokio::process::Command;
-use utils::process::read_stream;
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time,
+};
+use system::checks::process_name_matches;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt},
+    process::Command,
+};
+use utils::fail_injection::{self, SPAWN};
+use utils::messages::{self, TERMINAL_BLOCKED, TERMINAL_EXIT, TERMINAL_WARNED};
+use utils::process::{find_program, read_stream};
 
 pub struct Terminal {}
 
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    Warned(String),
+    Blocked(String),
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    command: &'a str,
+    verdict: &'a str,
+    matched: Option<&'a str>,
+}
+
+// Extracts the programs of a command line, e.g. "cat a.txt | /bin/rm -rf b" -> ["cat", "rm"]
+fn command_names(line: &str) -> Vec<String> {
+    line.split([';', '|', '&', '\n'])
+        .filter_map(|segment| segment.split_whitespace().next())
+        .map(|program| {
+            program
+                .rsplit(['/', '\\'])
+                .next()
+                .unwrap_or(program)
+                .to_lowercase()
+        })
+        .collect()
+}
+
+/// Checks a command line against the allow-, deny- and warnlist of the audit.
+/// "exit" is always allowed, so the session can be closed
+pub fn check_command(line: &str, audit: &TerminalAudit) -> Verdict {
+    let names: Vec<String> = command_names(line)
+        .into_iter()
+        .filter(|name| name != "exit")
+        .collect();
+    let contains =
+        |list: &[String], name: &str| list.iter().any(|entry| process_name_matches(name, entry));
+
+    for name in &names {
+        if contains(&audit.deny, name) {
+            return Verdict::Blocked(name.clone());
+        }
+        if !audit.allow.is_empty() && !contains(&audit.allow, name) {
+            return Verdict::Blocked(name.clone());
+        }
+    }
+    match names.into_iter().find(|name| contains(&audit.warn, name)) {
+        Some(name) => Verdict::Warned(name),
+        None => Verdict::Allowed,
+    }
+}
+
 #[cfg(windows)]
 fn get_windows_command(
-    shell: String,
+    shell: Vec<String>,
     out_file: Option<PathBuf>,
     terminal: &TerminalAttributes,
 ) -> Vec<String> {
@@ -26,10 +93,10 @@ fn get_windows_command(
             format!(
                 "Start-Transcript -Force -Path {}; {}",
                 out_file.unwrap().display(),
-                shell
+                shell.join(" ")
             ),
         ],
-        false => vec![shell],
+        false => shell,
     };
 
     base.append(&mut appendix);
@@ -38,15 +105,19 @@ fn get_windows_command(
 
 #[cfg(target_os = "macos")]
 fn get_macos_command(
-    shell: String,
+    shell: Vec<String>,
     out_file: Option<PathBuf>,
     terminal: &TerminalAttributes,
 ) -> Vec<String> {
     let base = if terminal.enable_transcript {
         // See: https://www.unix.com/man-page/osx/1/script/
-        format!("script -a {} {}", out_file.unwrap().display(), shell)
+        format!(
+            "script -a {} {}",
+            out_file.unwrap().display(),
+            shell.join(" ")
+        )
     } else {
-        shell.clone()
+        shell.join(" ")
     };
 
     match terminal.separate_window {
@@ -55,21 +126,25 @@ fn get_macos_command(
             "-e".to_string(),
             format!("'tell application \"Terminal\" to do script \"{}\"'", base),
         ],
-        false => vec![shell],
+        false => shell,
     }
 }
 
 #[cfg(all(unix, not(target_os = "macos")))]
 fn get_unix_command(
-    shell: String,
+    shell: Vec<String>,
     out_file: Option<PathBuf>,
     terminal: &TerminalAttributes,
 ) -> Vec<String> {
     let base_command = if terminal.enable_transcript {
         // See: https://man7.org/linux/man-pages/man1/script.1.html
-        format!("script -c '{}' {}", shell, out_file.unwrap().display())
+        format!(
+            "script -c '{}' {}",
+            shell.join(" "),
+            out_file.unwrap().display()
+        )
     } else {
-        shell
+        shell.join(" ")
     };
 
     let fallback = vec!["sh".to_string(), "-c".to_string(), base_command.clone()];
@@ -111,16 +186,62 @@ fn get_unix_command(
     }
 }
 
+// The shells tried in order if none is configured
+fn default_shells() -> Vec<String> {
+    if cfg!(windows) {
+        // PowerShell 7 is installed next to Windows PowerShell and preferred
+        return vec![
+            "pwsh".to_string(),
+            "powershell".to_string(),
+            "cmd".to_string(),
+        ];
+    }
+    // the shell of the user first, e.g. zsh on macOS since Catalina
+    let mut shells: Vec<String> = std::env::var("SHELL")
+        .into_iter()
+        .filter(|shell| !shell.is_empty())
+        .collect();
+    let fallbacks = match cfg!(target_os = "macos") {
+        true => vec!["zsh", "bash", "sh"],
+        false => vec!["bash", "zsh", "fish", "sh"],
+    };
+    shells.extend(fallbacks.into_iter().map(String::from));
+    shells
+}
+
+/// Arguments starting a login shell, which loads the profile of the user (e.g. .zprofile)
+fn login_args(shell: &str) -> Vec<String> {
+    let name = Path::new(shell)
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match name.as_str() {
+        "bash" | "zsh" | "fish" | "sh" | "dash" | "ksh" => vec!["-l".to_string()],
+        // a login shell is only supported on Linux and macOS
+        "pwsh" if !cfg!(windows) => vec!["-Login".to_string()],
+        _ => vec![],
+    }
+}
+
 impl Terminal {
     pub async fn run(
         terminal: TerminalAttributes,
         options: ActionOptions,
         out_file: Option<PathBuf>,
+        audit_file: Option<PathBuf>,
     ) -> ActionResult {
+        // Command audit requires the commands to be entered in the current terminal
+        let audit = terminal.audit.enabled && terminal.wait && !terminal.separate_window;
+        if terminal.audit.enabled && !audit {
+            warn!("Command audit is only supported for integrated terminals (wait: true, separate_window: false)");
+        }
+
         // Determine the shell to use
-        let shell = Terminal::get_shell(&terminal.shell);
+        let shell = Terminal::get_shell(&terminal.shell, &terminal.shells);
+        let shell = Terminal::shell_command(shell, terminal.login_shell);
 
         // Determine the command to run
+        let transcript = out_file.clone().filter(|_| terminal.enable_transcript);
         let cmd = Terminal::build_command(shell, out_file, &terminal);
 
         // error check
@@ -132,7 +253,7 @@ impl Terminal {
         };
 
         if !terminal.separate_window {
-            info!("Type 'exit' to exit the terminal session");
+            info!("{}", messages::text(TERMINAL_EXIT));
         }
 
         let mut child = TokioCommandWrap::from(cmd);
@@ -143,7 +264,7 @@ impl Terminal {
         child.wrap(JobObject);
         //#[cfg(unix)]
         //child.wrap(ProcessGroup::leader());
-        let mut child = match child.spawn() {
+        let mut child = match fail_injection::check(SPAWN).and_then(|_| child.spawn()) {
             Ok(child) => child,
             Err(e) => return error_result!(e.to_string()),
         };
@@ -157,20 +278,43 @@ impl Terminal {
                 error_message: None,
                 parallel: options.parallel,
                 finished: true,
+                error_category: None,
+                ..Default::default()
             };
         }
 
+        if let (true, Some(audit_file)) = (audit, &audit_file) {
+            if let Some(stdin) = child.inner_mut().stdin.take() {
+                let input = tokio::io::BufReader::new(tokio::io::stdin());
+                match Terminal::audit_session(input, stdin, &terminal.audit, audit_file).await {
+                    Ok(count) => info!("Audited {} commands", count),
+                    Err(e) => warn!("Command audit failed: {}", e),
+                }
+            }
+        }
+
         let stderr = child.inner_mut().stderr.take();
         let stderr_task: Option<tokio::task::JoinHandle<String>> =
             Some(tokio::spawn(read_stream(stderr, false)));
 
         // If wait is true, we wait for the command to finish
-        let output = match Box::into_pin(child.wait()).await {
-            Ok(output) => output,
-            Err(e) => return error_result!(e.to_string(), options.start_time),
+        let (output, resource_usage) = match usage::wait(&mut child).await {
+            (Ok(output), resource_usage) => (output, resource_usage),
+            (Err(e), _) => return error_result!(e.to_string(), options.start_time),
         };
 
-        ActionResult {
+        // secrets typed or printed during the session
+        if let Some(transcript) = transcript.as_ref().filter(|transcript| transcript.exists()) {
+            if let Err(e) = logging::redact_file(transcript) {
+                warn!(
+                    "Failed to redact secrets from transcript {:?}: {}",
+                    transcript.display(),
+                    e
+                );
+            }
+        }
+
+        let result = ActionResult {
             success: output.success(),
             exit_code: Some(output.code().unwrap()),
             execution_time: options.start_time.elapsed(),
@@ -180,43 +324,150 @@ impl Terminal {
             },
             parallel: options.parallel,
             finished: true,
-        }
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_resource_usage(resource_usage)
+            .with_artifacts(transcript.into_iter().chain(audit_file))
     }
 
-    pub fn get_shell(shell: &String) -> String {
-        // check if a shell is specified
-        if !shell.is_empty() {
-            return shell.clone();
-        }
+    /// Reads command lines from the input, logs them with a timestamp to the audit file
+    /// and passes the permitted ones to the shell. Returns the number of audited commands
+    pub async fn audit_session<R, W>(
+        input: R,
+        mut shell_stdin: W,
+        audit: &TerminalAudit,
+        audit_file: &Path,
+    ) -> Result<usize, io::Error>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(audit_file)?;
+        let mut lines = input.lines();
+        let mut count = 0;
+
+        loop {
+            print!("[audit] > ");
+            io::stdout().flush()?;
+
+            let line = match lines.next_line().await? {
+                Some(line) => line,
+                None => break,
+            };
+            let command = line.trim();
+            if command.is_empty() {
+                continue;
+            }
 
-        return if cfg!(windows) {
-            // check if powershell is available
-            match std::process::Command::new("powershell").arg("-?").output() {
-                Ok(output) if output.status.success() => "powershell".to_string(),
-                _ => "cmd".to_string(),
+            let verdict = check_command(command, audit);
+            let (verdict_name, matched) = match &verdict {
+                Verdict::Allowed => ("allowed", None),
+                Verdict::Warned(name) => ("warned", Some(name.as_str())),
+                Verdict::Blocked(name) => ("blocked", Some(name.as_str())),
+            };
+            let redacted = logging::redact(command);
+            let entry = AuditEntry {
+                timestamp: Utc::now().to_rfc3339(),
+                command: &redacted,
+                verdict: verdict_name,
+                matched,
+            };
+            writeln!(log, "{}", serde_json::to_string(&entry)?)?;
+            count += 1;
+
+            match &verdict {
+                Verdict::Blocked(name) => {
+                    warn!(
+                        "{}",
+                        messages::format(
+                            TERMINAL_BLOCKED,
+                            &[
+                                ("command", &format!("{:?}", command)),
+                                ("name", &format!("{:?}", name))
+                            ]
+                        )
+                    );
+                    continue;
+                }
+                Verdict::Warned(name) => {
+                    warn!(
+                        "{}",
+                        messages::format(
+                            TERMINAL_WARNED,
+                            &[
+                                ("command", &format!("{:?}", command)),
+                                ("name", &format!("{:?}", name))
+                            ]
+                        )
+                    )
+                }
+                Verdict::Allowed => debug!("Command: {:?}", command),
             }
-        } else if cfg!(target_os = "macos") {
-            // on macOS Catalina, the default shell is now zsh
-            // on older versions, it's bash
-            match std::env::var("SHELL") {
-                Ok(shell) if shell.contains("zsh") => shell,
-                Ok(shell) if shell.contains("bash") => shell,
-                _ => "sh".to_string(),
+
+            // the shell exited in the meantime
+            if let Err(e) = shell_stdin
+                .write_all(format!("{}\n", command).as_bytes())
+                .await
+            {
+                debug!("Failed to pass command to the shell: {}", e);
+                break;
             }
-        } else if cfg!(unix) {
-            // if on unix, check the SHELL environment variable
-            match std::env::var("SHELL") {
-                Ok(shell) => shell,
-                Err(_) => "bash".to_string(),
+            shell_stdin.flush().await?;
+
+            if command == "exit" || command.starts_with("exit ") {
+                break;
             }
-        } else {
-            warn!("Unknown OS, defaulting to sh");
-            "sh".to_string()
+        }
+
+        Ok(count)
+    }
+
+    /// The configured shell or the first of the candidates found on the PATH.
+    /// Without candidates, the defaults of the operating system are tried
+    pub fn get_shell(shell: &str, candidates: &[String]) -> String {
+        if !shell.is_empty() {
+            return shell.to_string();
+        }
+        let candidates = match candidates.is_empty() {
+            true => default_shells(),
+            false => candidates.to_vec(),
         };
+        match candidates
+            .iter()
+            .find(|shell| find_program(shell).is_some())
+        {
+            Some(shell) => shell.clone(),
+            None => {
+                let fallback = if cfg!(windows) { "cmd" } else { "sh" };
+                warn!(
+                    "None of the shells {:?} found, defaulting to {}",
+                    candidates, fallback
+                );
+                fallback.to_string()
+            }
+        }
+    }
+
+    /// The shell with its arguments
+    pub fn shell_command(shell: String, login_shell: bool) -> Vec<String> {
+        let mut command = vec![];
+        if login_shell {
+            command = login_args(&shell);
+            if command.is_empty() {
+                warn!("{} can't be started as a login shell", shell);
+            }
+        }
+        command.insert(0, shell);
+        command
     }
 
     fn build_command(
-        shell: String,
+        shell: Vec<String>,
         out_file: Option<PathBuf>,
         terminal: &TerminalAttributes,
     ) -> Option<Command> {
@@ -247,7 +498,11 @@ impl Terminal {
         } else if terminal.wait && !terminal.separate_window {
             cmd.stdout(Stdio::inherit());
             cmd.stderr(Stdio::inherit());
-            cmd.stdin(Stdio::inherit());
+            // when auditing, each command line is passed to the shell by us
+            cmd.stdin(match terminal.audit.enabled {
+                true => Stdio::piped(),
+                false => Stdio::inherit(),
+            });
         }
 
         Some(cmd)
@@ -259,22 +514,47 @@ mod tests {
 
     use crate::*;
 
-    use config::workflow::TerminalAttributes;
+    use config::workflow::{TerminalAttributes, TerminalAudit};
     use std::process::Stdio;
-    use terminal::Terminal;
+    use terminal::{check_command, Terminal, Verdict};
     use tokio::io::AsyncWriteExt;
     use tokio::process::Command;
     use utils::tests::Cleanup;
 
     #[tokio::test]
     async fn test_shell() {
-        let shell = Terminal::get_shell(&"".to_string());
+        let shell = Terminal::get_shell("", &[]);
         // execute the shell, and check if the process starts
         // if the shell is not found, the test will fail
         let success = Command::new(&shell).status().await.unwrap().success();
         assert_eq!(success, true);
     }
 
+    #[test]
+    fn test_get_shell_candidates() {
+        let available = if cfg!(windows) { "cmd" } else { "sh" };
+        let candidates = vec![
+            "ir-toolkit-missing-shell".to_string(),
+            available.to_string(),
+        ];
+        assert_eq!(Terminal::get_shell("", &candidates), available);
+        // a configured shell takes precedence
+        assert_eq!(Terminal::get_shell("zsh", &candidates), "zsh");
+
+        assert_eq!(
+            Terminal::shell_command("/usr/bin/fish".to_string(), true),
+            vec!["/usr/bin/fish", "-l"]
+        );
+        assert_eq!(
+            Terminal::shell_command("bash".to_string(), false),
+            vec!["bash"]
+        );
+        assert_eq!(
+            Terminal::shell_command("cmd".to_string(), true),
+            vec!["cmd"]
+        );
+    }
+
     #[tokio::test]
     async fn test_integrated_terminal() {
         let terminal = TerminalAttributes {
@@ -282,12 +562,15 @@ mod tests {
             separate_window: false,
             enable_transcript: false,
             wait: true,
+            audit: Default::default(),
+            shells: vec![],
+            login_shell: false,
         };
 
-        let shell = Terminal::get_shell(&terminal.shell);
+        let shell = Terminal::get_shell(&terminal.shell, &terminal.shells);
         assert_eq!(shell.is_empty(), false);
 
-        let cmd = Terminal::build_command(shell, None, &terminal);
+        let cmd = Terminal::build_command(vec![shell], None, &terminal);
 
         // run the command, send "echo hello world" to the shell and check if the output contains "hello world"
         let mut cmd = cmd.unwrap();
@@ -319,16 +602,19 @@ mod tests {
             separate_window: false,
             enable_transcript: true,
             wait: true,
+            audit: Default::default(),
+            shells: vec![],
+            login_shell: false,
         };
 
         let mut cleanup = Cleanup::new();
         let dir = cleanup.tmp_dir("test_integrated_terminal_transcript");
         let file_path = dir.join("transcript.log");
 
-        let shell = Terminal::get_shell(&terminal.shell);
+        let shell = Terminal::get_shell(&terminal.shell, &terminal.shells);
         assert_eq!(shell.is_empty(), false);
 
-        let cmd = Terminal::build_command(shell, Some(file_path.clone()), &terminal);
+        let cmd = Terminal::build_command(vec![shell], Some(file_path.clone()), &terminal);
 
         // run the command, send "echo hello world" to the shell and check if the output contains "hello world"
         let mut cmd = cmd.unwrap();
@@ -354,4 +640,69 @@ mod tests {
         let transcript = std::fs::read_to_string(file_path).unwrap();
         assert_eq!(transcript.is_empty(), false);
     }
+
+    #[test]
+    fn test_check_command() {
+        let audit = TerminalAudit {
+            enabled: true,
+            allow: vec![],
+            deny: vec!["del".to_string(), "format".to_string(), "rm".to_string()],
+            warn: vec!["mv".to_string()],
+        };
+
+        assert_eq!(check_command("ls -la", &audit), Verdict::Allowed);
+        assert_eq!(
+            check_command("cat a.txt | /bin/rm -rf /", &audit),
+            Verdict::Blocked("rm".to_string())
+        );
+        assert_eq!(
+            check_command("DEL.exe C:\\evidence.txt", &audit),
+            Verdict::Blocked("del.exe".to_string())
+        );
+        assert_eq!(
+            check_command("mv a b", &audit),
+            Verdict::Warned("mv".to_string())
+        );
+
+        let audit = TerminalAudit {
+            enabled: true,
+            allow: vec!["ls".to_string(), "cat".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(check_command("cat a && ls", &audit), Verdict::Allowed);
+        assert_eq!(check_command("exit", &audit), Verdict::Allowed);
+        assert_eq!(
+            check_command("ls; whoami", &audit),
+            Verdict::Blocked("whoami".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_session() {
+        let audit = TerminalAudit {
+            enabled: true,
+            deny: vec!["rm".to_string()],
+            ..Default::default()
+        };
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_audit_session");
+        let audit_file = dir.join("commands.jsonl");
+
+        let input: &[u8] = b"echo hello\n\nrm -rf /\nexit\necho never\n";
+        let mut shell_stdin: Vec<u8> = vec![];
+        let count = Terminal::audit_session(input, &mut shell_stdin, &audit, &audit_file)
+            .await
+            .unwrap();
+
+        // the blocked command is logged, but not passed to the shell
+        assert_eq!(count, 3);
+        assert_eq!(String::from_utf8_lossy(&shell_stdin), "echo hello\nexit\n");
+
+        let log = std::fs::read_to_string(audit_file).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("\"verdict\":\"blocked\""));
+        assert!(lines[1].contains("\"timestamp\""));
+    }
 }
@@ -0,0 +1,218 @@
+// Resources used by the processes an action started, so the heavy steps of a workflow
+// can be identified and the impact on the host can be justified afterwards
+use process_wrap::tokio::TokioChildWrapper;
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, process::ExitStatus};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub cpu_user_ms: u64,
+    pub cpu_system_ms: u64,
+    // highest resident set size (working set on Windows) of a single process
+    pub peak_rss_bytes: u64,
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+}
+
+impl ResourceUsage {
+    /// Usage of several runs, the peak is the highest of all runs
+    pub fn merge(self, other: ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            cpu_user_ms: self.cpu_user_ms + other.cpu_user_ms,
+            cpu_system_ms: self.cpu_system_ms + other.cpu_system_ms,
+            peak_rss_bytes: self.peak_rss_bytes.max(other.peak_rss_bytes),
+            io_read_bytes: self.io_read_bytes + other.io_read_bytes,
+            io_write_bytes: self.io_write_bytes + other.io_write_bytes,
+        }
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "CPU {} ms user, {} ms system, peak RSS {} bytes, read {} bytes, written {} bytes",
+            self.cpu_user_ms,
+            self.cpu_system_ms,
+            self.peak_rss_bytes,
+            self.io_read_bytes,
+            self.io_write_bytes
+        )
+    }
+}
+
+/// Waits for the child like TokioChildWrapper::wait and measures its usage. The exit is awaited
+/// in a blocking thread without reaping the child, as the counters are gone once it is reaped
+pub async fn wait(
+    child: &mut Box<dyn TokioChildWrapper>,
+) -> (io::Result<ExitStatus>, Option<ResourceUsage>) {
+    let usage = match child.id() {
+        Some(pid) => tokio::task::spawn_blocking(move || exited_usage(pid))
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    (Box::into_pin(child.wait()).await, usage)
+}
+
+// rusage of the exited child including its reaped children, I/O from /proc/[pid]/io
+#[cfg(target_os = "linux")]
+fn exited_usage(pid: u32) -> Option<ResourceUsage> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the raw syscall is used as the libc wrapper of waitid doesn't return the rusage.
+        // WNOWAIT leaves the child waitable, so it is still reaped by TokioChildWrapper::wait
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_waitid,
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info as *mut libc::siginfo_t,
+                libc::WEXITED | libc::WNOWAIT,
+                &mut rusage as *mut libc::rusage,
+            )
+        };
+        if result == 0 {
+            break;
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+
+    let millis = |time: libc::timeval| time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000;
+    let mut usage = ResourceUsage {
+        cpu_user_ms: millis(rusage.ru_utime),
+        cpu_system_ms: millis(rusage.ru_stime),
+        // in kilobytes
+        peak_rss_bytes: rusage.ru_maxrss as u64 * 1024,
+        ..Default::default()
+    };
+    // the zombie keeps its I/O accounting until it is reaped
+    if let Ok(content) = std::fs::read_to_string(format!("/proc/{}/io", pid)) {
+        for line in content.lines() {
+            match line.split_once(':') {
+                Some(("read_bytes", value)) => {
+                    usage.io_read_bytes = value.trim().parse().unwrap_or(0)
+                }
+                Some(("write_bytes", value)) => {
+                    usage.io_write_bytes = value.trim().parse().unwrap_or(0)
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(usage)
+}
+
+// counters of the process handle, which cover the started process only
+#[cfg(target_os = "windows")]
+fn exited_usage(pid: u32) -> Option<ResourceUsage> {
+    use winapi::{
+        shared::minwindef::FILETIME,
+        um::{
+            handleapi::CloseHandle,
+            processthreadsapi::{GetProcessTimes, OpenProcess},
+            psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+            synchapi::WaitForSingleObject,
+            winbase::{GetProcessIoCounters, INFINITE, WAIT_OBJECT_0},
+            winnt::{IO_COUNTERS, PROCESS_QUERY_LIMITED_INFORMATION, SYNCHRONIZE},
+        },
+    };
+
+    // in units of 100 nanoseconds
+    let millis =
+        |time: FILETIME| ((time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64) / 10_000;
+    unsafe {
+        // the process can't exit in the meantime, as it isn't waited for yet
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION | SYNCHRONIZE, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        let mut usage = None;
+        if WaitForSingleObject(handle, INFINITE) == WAIT_OBJECT_0 {
+            let mut creation: FILETIME = std::mem::zeroed();
+            let mut exit: FILETIME = std::mem::zeroed();
+            let mut kernel: FILETIME = std::mem::zeroed();
+            let mut user: FILETIME = std::mem::zeroed();
+            let mut memory: PROCESS_MEMORY_COUNTERS = std::mem::zeroed();
+            let mut io: IO_COUNTERS = std::mem::zeroed();
+            if GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) != 0 {
+                let mut result = ResourceUsage {
+                    cpu_user_ms: millis(user),
+                    cpu_system_ms: millis(kernel),
+                    ..Default::default()
+                };
+                if GetProcessMemoryInfo(
+                    handle,
+                    &mut memory,
+                    std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+                ) != 0
+                {
+                    result.peak_rss_bytes = memory.PeakWorkingSetSize as u64;
+                }
+                if GetProcessIoCounters(handle, &mut io) != 0 {
+                    result.io_read_bytes = io.ReadTransferCount;
+                    result.io_write_bytes = io.WriteTransferCount;
+                }
+                usage = Some(result);
+            }
+        }
+        CloseHandle(handle);
+        usage
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn exited_usage(_pid: u32) -> Option<ResourceUsage> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use process_wrap::tokio::TokioCommandWrap;
+    use tokio::process::Command;
+
+    #[test]
+    fn test_merge_usage() {
+        let first = ResourceUsage {
+            cpu_user_ms: 10,
+            cpu_system_ms: 5,
+            peak_rss_bytes: 4096,
+            io_read_bytes: 100,
+            io_write_bytes: 0,
+        };
+        let second = ResourceUsage {
+            peak_rss_bytes: 1024,
+            io_write_bytes: 50,
+            ..first
+        };
+        let total = first.merge(second);
+        assert_eq!(total.cpu_user_ms, 20);
+        assert_eq!(total.cpu_system_ms, 10);
+        assert_eq!(total.peak_rss_bytes, 4096);
+        assert_eq!(total.io_read_bytes, 200);
+        assert_eq!(total.io_write_bytes, 50);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_wait_with_usage() {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done; exit 3",
+        ]);
+        let mut child = TokioCommandWrap::from(command).spawn().unwrap();
+        let (status, usage) = wait(&mut child).await;
+        assert_eq!(status.unwrap().code(), Some(3));
+        let usage = usage.unwrap();
+        assert!(usage.peak_rss_bytes > 0);
+        assert!(usage.cpu_user_ms + usage.cpu_system_ms > 0);
+        // reaped, so it isn't measured a second time
+        assert!(exited_usage(child.id().unwrap_or(u32::MAX)).is_none());
+    }
+}
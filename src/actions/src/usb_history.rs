@@ -0,0 +1,910 @@
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
+use config::workflow::UsbHistoryAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    path::PathBuf,
+};
+use storage::FileProcessor;
+use utils::misc::iter_files_by_pattern;
+
+use super::{error_result, ActionOptions, ActionResult};
+
+/// A single sighting of a device in one of the sources
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UsbEvent {
+    pub source: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub device_id: String,
+    pub vendor_id: String,
+    pub product_id: String,
+    pub vendor: String,
+    pub product: String,
+    pub serial: String,
+    pub mount_point: String,
+    pub connected: bool,
+}
+
+/// One row of the resulting csv file
+#[derive(Debug, Default, Serialize)]
+pub struct UsbDevice {
+    pub device_id: String,
+    pub vendor_id: String,
+    pub product_id: String,
+    pub vendor: String,
+    pub product: String,
+    pub serial: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub connections: usize,
+    pub connected: bool,
+    pub mount_points: String,
+    pub sources: String,
+}
+
+impl UsbEvent {
+    fn key(&self) -> String {
+        match self.serial.is_empty() {
+            true => self.device_id.to_lowercase(),
+            false => self.serial.to_lowercase(),
+        }
+    }
+}
+
+fn set_if_empty(target: &mut String, value: &str) {
+    if target.is_empty() && !value.is_empty() {
+        *target = value.to_string();
+    }
+}
+
+/// Merges all sightings of the same device (by serial number or device id)
+pub fn aggregate(events: Vec<UsbEvent>) -> Vec<UsbDevice> {
+    struct Entry {
+        device: UsbDevice,
+        first_seen: Option<DateTime<Utc>>,
+        last_seen: Option<DateTime<Utc>>,
+        seen: BTreeSet<DateTime<Utc>>,
+        mount_points: BTreeSet<String>,
+        sources: BTreeSet<String>,
+    }
+
+    let mut entries: BTreeMap<String, Entry> = BTreeMap::new();
+    for event in events {
+        let entry = entries.entry(event.key()).or_insert_with(|| Entry {
+            device: UsbDevice::default(),
+            first_seen: None,
+            last_seen: None,
+            seen: BTreeSet::new(),
+            mount_points: BTreeSet::new(),
+            sources: BTreeSet::new(),
+        });
+
+        let device = &mut entry.device;
+        set_if_empty(&mut device.device_id, &event.device_id);
+        set_if_empty(&mut device.vendor_id, &event.vendor_id);
+        set_if_empty(&mut device.product_id, &event.product_id);
+        set_if_empty(&mut device.vendor, &event.vendor);
+        set_if_empty(&mut device.product, &event.product);
+        set_if_empty(&mut device.serial, &event.serial);
+        device.connected |= event.connected;
+
+        // the same connection might be logged by multiple sources (e.g. journal and syslog)
+        if let Some(timestamp) = event.timestamp {
+            entry.seen.insert(timestamp);
+            entry.first_seen = Some(entry.first_seen.map_or(timestamp, |t| t.min(timestamp)));
+            entry.last_seen = Some(entry.last_seen.map_or(timestamp, |t| t.max(timestamp)));
+        }
+        if !event.mount_point.is_empty() {
+            entry.mount_points.insert(event.mount_point);
+        }
+        entry.sources.insert(event.source);
+    }
+
+    entries
+        .into_values()
+        .map(|entry| {
+            let mut device = entry.device;
+            device.first_seen = entry.first_seen.map(|t| t.to_rfc3339()).unwrap_or_default();
+            device.last_seen = entry.last_seen.map(|t| t.to_rfc3339()).unwrap_or_default();
+            device.connections = entry.seen.len();
+            device.mount_points = entry.mount_points.into_iter().collect::<Vec<_>>().join(";");
+            device.sources = entry.sources.into_iter().collect::<Vec<_>>().join(";");
+            device
+        })
+        .collect()
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Parses the timestamp at the beginning of a log line.
+/// Supports ISO 8601 (journalctl short-iso, rsyslog, unified log) and the classic syslog format
+pub fn parse_log_timestamp(line: &str) -> Option<DateTime<Utc>> {
+    let mut parts = line.split_whitespace();
+    let first = parts.next()?;
+    if let Ok(t) = DateTime::parse_from_rfc3339(first) {
+        return Some(t.with_timezone(&Utc));
+    }
+    if let Ok(t) = DateTime::parse_from_str(first, "%Y-%m-%dT%H:%M:%S%z") {
+        return Some(t.with_timezone(&Utc));
+    }
+
+    // e.g. "2024-01-15 10:23:45.123456+0100" (macOS unified log)
+    let second = parts.next()?;
+    let joined = format!("{} {}", first, second);
+    if let Ok(t) = DateTime::parse_from_str(&joined, "%Y-%m-%d %H:%M:%S%.f%z") {
+        return Some(t.with_timezone(&Utc));
+    }
+
+    // e.g. "Jan 15 10:23:45", the year is not part of the timestamp
+    let third = parts.next()?;
+    let joined = format!("{} {} {} {}", Local::now().year(), first, second, third);
+    NaiveDateTime::parse_from_str(&joined, "%Y %b %d %H:%M:%S")
+        .ok()
+        .and_then(local_to_utc)
+}
+
+/// Splits a Windows device instance id (e.g. USBSTOR\Disk&Ven_SanDisk&Prod_Cruzer&Rev_1.00\4C53&0)
+/// into an event. Returns None for other buses
+pub fn parse_device_id(device_id: &str) -> Option<UsbEvent> {
+    // mounted devices are prefixed with \??\ and use # as separator
+    let device_id = device_id.trim_start_matches("\\??\\");
+    let parts: Vec<&str> = device_id.split(['\\', '#']).collect();
+    if parts.len() < 3 {
+        return None;
+    }
+    let bus = parts[0].to_uppercase();
+    let bus = bus.as_str();
+
+    // instance ids with an "&" as second character are generated by Windows
+    // if the device has no serial number
+    let instance = parts[2];
+    let serial = match instance.chars().nth(1) {
+        Some('&') | None => String::new(),
+        _ => match instance.rsplit_once('&') {
+            Some((serial, _)) => serial.to_string(),
+            None => instance.to_string(),
+        },
+    };
+
+    let mut event = UsbEvent {
+        device_id: format!("{}\\{}\\{}", parts[0], parts[1], instance),
+        serial,
+        ..Default::default()
+    };
+    for field in parts[1].split('&') {
+        let (key, value) = match field.split_once('_') {
+            Some((key, value)) => (key.to_uppercase(), value.replace('_', " ")),
+            None => continue,
+        };
+        match (bus, key.as_str()) {
+            ("USB", "VID") => event.vendor_id = value.to_lowercase(),
+            ("USB", "PID") => event.product_id = value.to_lowercase(),
+            ("USBSTOR", "VEN") => event.vendor = value,
+            ("USBSTOR", "PROD") => event.product = value,
+            _ => {}
+        }
+    }
+    match bus {
+        "USB" | "USBSTOR" => Some(event),
+        _ => None,
+    }
+}
+
+/// Parses the device installations of the SetupAPI log (setupapi.dev.log)
+pub fn parse_setupapi_log(text: &str) -> Vec<UsbEvent> {
+    let mut events = vec![];
+    let mut pending: Option<UsbEvent> = None;
+
+    for line in text.lines() {
+        // e.g. ">>>  [Device Install (Hardware initiated) - USBSTOR\Disk&Ven_X&Prod_Y\123&0]"
+        if line.starts_with(">>>  [Device Install") {
+            pending = line
+                .split_once(" - ")
+                .map(|(_, id)| id.trim_end_matches(']').trim())
+                .and_then(parse_device_id);
+        } else if let Some(timestamp) = line.trim().strip_prefix(">>>  Section start ") {
+            if let Some(mut event) = pending.take() {
+                event.timestamp =
+                    NaiveDateTime::parse_from_str(timestamp.trim(), "%Y/%m/%d %H:%M:%S%.f")
+                        .ok()
+                        .and_then(local_to_utc);
+                event.source = "setupapi".to_string();
+                events.push(event);
+            }
+        }
+    }
+
+    events
+}
+
+/// Parses the kernel messages about USB devices, e.g.
+/// "usb 1-1: New USB device found, idVendor=0781, idProduct=5567"
+pub fn parse_kernel_log(source: &str, text: &str) -> Vec<UsbEvent> {
+    let mut events = vec![];
+    // devices are identified by their port (e.g. 1-1) until they are disconnected
+    let mut pending: HashMap<String, UsbEvent> = HashMap::new();
+
+    for line in text.lines() {
+        let message = match line.find(": usb ").or_else(|| line.find("] usb ")) {
+            Some(index) => &line[index + 6..],
+            None => continue,
+        };
+        let (port, message) = match message.split_once(": ") {
+            Some((port, message)) => (port.to_string(), message.trim()),
+            None => continue,
+        };
+
+        if message.starts_with("New USB device found") {
+            if let Some(event) = pending.remove(&port) {
+                events.push(event);
+            }
+            let mut event = UsbEvent {
+                source: source.to_string(),
+                timestamp: parse_log_timestamp(line),
+                ..Default::default()
+            };
+            for field in message.split(", ") {
+                match field.split_once('=') {
+                    Some(("idVendor", value)) => event.vendor_id = value.trim().to_lowercase(),
+                    Some(("idProduct", value)) => event.product_id = value.trim().to_lowercase(),
+                    _ => {}
+                }
+            }
+            event.device_id = format!("usb:{}:{}", event.vendor_id, event.product_id);
+            pending.insert(port, event);
+        } else if message.starts_with("USB disconnect") {
+            if let Some(event) = pending.remove(&port) {
+                events.push(event);
+            }
+        } else if let Some(event) = pending.get_mut(&port) {
+            if let Some(product) = message.strip_prefix("Product: ") {
+                event.product = product.to_string();
+            } else if let Some(vendor) = message.strip_prefix("Manufacturer: ") {
+                event.vendor = vendor.to_string();
+            } else if let Some(serial) = message.strip_prefix("SerialNumber: ") {
+                event.serial = serial.to_string();
+            }
+        }
+    }
+
+    events.extend(pending.into_values());
+    events
+}
+
+/// Parses the mass storage messages of the macOS unified log, e.g.
+/// "USBMSC Identifier (non-unique): 4C530001230405115123 0x781 0x5567 0x100, 2"
+pub fn parse_unified_log(text: &str) -> Vec<UsbEvent> {
+    text.lines()
+        .filter_map(|line| {
+            let (_, identifier) = line.split_once("USBMSC Identifier (non-unique): ")?;
+            let parts: Vec<&str> = identifier.split([' ', ',']).collect();
+            let hex = |value: &str| {
+                u32::from_str_radix(value.trim_start_matches("0x"), 16)
+                    .map(|id| format!("{:04x}", id))
+                    .unwrap_or_default()
+            };
+            let event = UsbEvent {
+                source: "unified_log".to_string(),
+                timestamp: parse_log_timestamp(line),
+                serial: parts.first()?.to_string(),
+                vendor_id: hex(parts.get(1)?),
+                product_id: hex(parts.get(2)?),
+                ..Default::default()
+            };
+            Some(UsbEvent {
+                device_id: format!("usb:{}:{}", event.vendor_id, event.product_id),
+                ..event
+            })
+        })
+        .collect()
+}
+
+// Runs a tool and returns its output. Missing tools are only logged
+#[cfg(not(target_os = "windows"))]
+fn run_tool(program: &str, args: &[&str], errors: &mut Vec<String>) -> Option<String> {
+    match std::process::Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        }
+        Ok(output) => {
+            let stderr: String = String::from_utf8_lossy(&output.stderr)
+                .trim()
+                .chars()
+                .take(200)
+                .collect();
+            errors.push(format!("{} failed: {}", program, stderr));
+            None
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("{} is not available", program);
+            None
+        }
+        Err(e) => {
+            errors.push(format!("Failed to run {}: {}", program, e));
+            None
+        }
+    }
+}
+
+// Log files which are parsed (and stored as they are)
+fn log_file_patterns() -> Vec<String> {
+    if cfg!(target_os = "windows") {
+        let root = std::env::var("SystemRoot").unwrap_or("C:\\Windows".to_string());
+        vec![format!("{}\\INF\\setupapi.dev*.log", root)]
+    } else if cfg!(target_os = "macos") {
+        vec![]
+    } else {
+        vec![
+            "/var/log/kern.log*".to_string(),
+            "/var/log/syslog*".to_string(),
+            "/var/log/messages*".to_string(),
+        ]
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn collect_mounts(events: &mut Vec<UsbEvent>) {
+    // e.g. /dev/disk/by-id/usb-SanDisk_Cruzer_Blade_4C530001230405115123-0:0-part1 -> ../../sdb1
+    let mounts: HashMap<String, String> = fs::read_to_string("/proc/mounts")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.to_string()))
+        })
+        .collect();
+
+    for entry in fs::read_dir("/dev/disk/by-id")
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id = match name.strip_prefix("usb-") {
+            Some(id) => id.split("-0:").next().unwrap_or(id),
+            None => continue,
+        };
+        let device = match fs::canonicalize(entry.path()) {
+            Ok(device) => device.to_string_lossy().to_string(),
+            Err(_) => continue,
+        };
+        let (vendor_product, serial) = id.rsplit_once('_').unwrap_or(("", id));
+        events.push(UsbEvent {
+            source: "by-id".to_string(),
+            device_id: format!("usb-{}", id),
+            product: vendor_product.replace('_', " "),
+            serial: serial.to_string(),
+            mount_point: mounts.get(&device).cloned().unwrap_or_default(),
+            connected: true,
+            ..Default::default()
+        });
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn collect_native(
+    attributes: &UsbHistoryAttributes,
+    events: &mut Vec<UsbEvent>,
+    errors: &mut Vec<String>,
+) {
+    let since = format!("-{}d", attributes.log_days);
+    let mut args = vec!["-k", "--no-pager", "-o", "short-iso"];
+    if attributes.log_days > 0 {
+        args.extend(["--since", since.as_str()]);
+    }
+    if let Some(output) = run_tool("journalctl", &args, errors) {
+        events.extend(parse_kernel_log("journal", &output));
+    }
+
+    // currently connected devices
+    for entry in fs::read_dir("/sys/bus/usb/devices")
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let read = |file: &str| {
+            fs::read_to_string(entry.path().join(file))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let vendor_id = read("idVendor");
+        if vendor_id.is_empty() {
+            continue;
+        }
+        let product_id = read("idProduct");
+        events.push(UsbEvent {
+            source: "sysfs".to_string(),
+            device_id: format!("usb:{}:{}", vendor_id, product_id),
+            vendor_id,
+            product_id,
+            vendor: read("manufacturer"),
+            product: read("product"),
+            serial: read("serial"),
+            connected: true,
+            ..Default::default()
+        });
+    }
+
+    collect_mounts(events);
+}
+
+#[cfg(target_os = "macos")]
+fn collect_native(
+    attributes: &UsbHistoryAttributes,
+    events: &mut Vec<UsbEvent>,
+    errors: &mut Vec<String>,
+) {
+    let last = format!("{}d", attributes.log_days.max(1));
+    let args = [
+        "show",
+        "--style",
+        "syslog",
+        "--last",
+        last.as_str(),
+        "--predicate",
+        "eventMessage CONTAINS \"USBMSC Identifier\"",
+    ];
+    if let Some(output) = run_tool("log", &args, errors) {
+        events.extend(parse_unified_log(&output));
+    }
+
+    // currently connected devices
+    if let Some(output) = run_tool("system_profiler", &["SPUSBDataType", "-json"], errors) {
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap_or_default();
+        let mut stack: Vec<&serde_json::Value> = value["SPUSBDataType"]
+            .as_array()
+            .map(|items| items.iter().collect())
+            .unwrap_or_default();
+        while let Some(item) = stack.pop() {
+            if let Some(children) = item["_items"].as_array() {
+                stack.extend(children.iter());
+            }
+            let text = |key: &str| item[key].as_str().unwrap_or_default().to_string();
+            // e.g. "0x0781  (SanDisk Corporation)"
+            let id = |key: &str| {
+                text(key)
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .trim_start_matches("0x")
+                    .to_lowercase()
+            };
+            if text("vendor_id").is_empty() {
+                continue;
+            }
+            events.push(UsbEvent {
+                source: "system_profiler".to_string(),
+                device_id: format!("usb:{}:{}", id("vendor_id"), id("product_id")),
+                vendor_id: id("vendor_id"),
+                product_id: id("product_id"),
+                vendor: text("manufacturer"),
+                product: text("_name"),
+                serial: text("serial_num"),
+                connected: true,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn collect_native(
+    _attributes: &UsbHistoryAttributes,
+    events: &mut Vec<UsbEvent>,
+    errors: &mut Vec<String>,
+) {
+    events.extend(unsafe { native::collect_registry(errors) });
+}
+
+pub struct UsbHistory {}
+
+impl UsbHistory {
+    pub fn run(
+        attributes: UsbHistoryAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+        file_processor: &mut FileProcessor,
+    ) -> ActionResult {
+        let mut events: Vec<UsbEvent> = vec![];
+        let mut errors: Vec<String> = vec![];
+
+        // Step 1: Parse the log files
+        let case_sensitive = !cfg!(target_os = "windows");
+        for pattern in log_file_patterns() {
+            for file in iter_files_by_pattern(&pattern, case_sensitive)
+                .into_iter()
+                .flatten()
+            {
+                // rotated logs might be compressed
+                if file.extension().is_some_and(|ext| ext == "gz") {
+                    continue;
+                }
+                match fs::read(&file) {
+                    Ok(content) => {
+                        let content = String::from_utf8_lossy(&content);
+                        let source = file.file_name().unwrap_or_default().to_string_lossy();
+                        match cfg!(target_os = "windows") {
+                            true => events.extend(parse_setupapi_log(&content)),
+                            false => events.extend(parse_kernel_log(&source, &content)),
+                        }
+                    }
+                    Err(e) => {
+                        errors.push(format!("Failed to read {:?}: {}", file.display(), e));
+                        continue;
+                    }
+                }
+                if attributes.store_raw {
+                    if let Err(e) = file_processor.store(&file, None) {
+                        errors.push(format!("Error storing file {:?}: {}", file.display(), e));
+                    }
+                }
+            }
+        }
+
+        // Step 2: Query the platform specific sources
+        collect_native(&attributes, &mut events, &mut errors);
+
+        // Step 3: Merge the sightings of each device and write them to the csv file
+        let devices = aggregate(events);
+        info!("Found {} USB devices", devices.len());
+        for error in &errors {
+            warn!("{}", error);
+        }
+
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        let mut writer = csv::Writer::from_writer(file);
+        for device in &devices {
+            if let Err(e) = writer.serialize(device) {
+                return error_result!(
+                    format!("Error writing file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        }
+        if let Err(e) = writer.flush() {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote USB device history to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match errors.is_empty() {
+                true => None,
+                false => Some(errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(devices.len() as u64)
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod native {
+    use super::*;
+    use std::ptr::null_mut;
+    use winapi::{
+        shared::{
+            minwindef::{DWORD, HKEY},
+            winerror::ERROR_SUCCESS,
+        },
+        um::{
+            winnt::KEY_READ,
+            winreg::{
+                RegCloseKey, RegEnumKeyExW, RegEnumValueW, RegGetValueW, RegOpenKeyExW,
+                HKEY_LOCAL_MACHINE, RRF_RT_ANY,
+            },
+        },
+    };
+
+    const ENUM_KEY: &str = "SYSTEM\\CurrentControlSet\\Enum";
+    // DEVPKEY_Device_InstallDate, DEVPKEY_Device_LastArrivalDate and DEVPKEY_Device_LastRemovalDate
+    const PROPERTIES: &str = "Properties\\{83da6326-97a6-4088-9453-a1923f573b29}";
+    const DATE_PROPERTIES: [&str; 3] = ["0064", "0066", "0067"];
+
+    fn to_wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    fn from_wide(s: &[u16]) -> String {
+        let len = s.iter().position(|&c| c == 0).unwrap_or(s.len());
+        String::from_utf16_lossy(&s[..len])
+    }
+
+    fn filetime_to_utc(bytes: &[u8]) -> Option<DateTime<Utc>> {
+        let filetime = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+        // 100ns intervals since 1601-01-01
+        let unix = filetime.checked_sub(116_444_736_000_000_000)?;
+        DateTime::from_timestamp(
+            (unix / 10_000_000) as i64,
+            ((unix % 10_000_000) * 100) as u32,
+        )
+    }
+
+    unsafe fn open_key(path: &str) -> Option<HKEY> {
+        let mut key: HKEY = null_mut();
+        match RegOpenKeyExW(
+            HKEY_LOCAL_MACHINE,
+            to_wide(path).as_ptr(),
+            0,
+            KEY_READ,
+            &mut key,
+        ) {
+            status if status as DWORD == ERROR_SUCCESS => Some(key),
+            _ => None,
+        }
+    }
+
+    unsafe fn sub_keys(path: &str) -> Vec<String> {
+        let key = match open_key(path) {
+            Some(key) => key,
+            None => return vec![],
+        };
+        let mut names = vec![];
+        let mut buffer = [0u16; 256];
+        loop {
+            let mut len = buffer.len() as DWORD;
+            let status = RegEnumKeyExW(
+                key,
+                names.len() as DWORD,
+                buffer.as_mut_ptr(),
+                &mut len,
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+            );
+            if status as DWORD != ERROR_SUCCESS {
+                break;
+            }
+            names.push(from_wide(&buffer[..len as usize]));
+        }
+        RegCloseKey(key);
+        names
+    }
+
+    unsafe fn value_bytes(path: &str, name: &str) -> Option<Vec<u8>> {
+        let path = to_wide(path);
+        let name = to_wide(name);
+        let mut size: DWORD = 0;
+        let status = RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            path.as_ptr(),
+            name.as_ptr(),
+            RRF_RT_ANY,
+            null_mut(),
+            null_mut(),
+            &mut size,
+        );
+        if status as DWORD != ERROR_SUCCESS || size == 0 {
+            return None;
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let status = RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            path.as_ptr(),
+            name.as_ptr(),
+            RRF_RT_ANY,
+            null_mut(),
+            buffer.as_mut_ptr() as *mut _,
+            &mut size,
+        );
+        if status as DWORD != ERROR_SUCCESS {
+            return None;
+        }
+        buffer.truncate(size as usize);
+        Some(buffer)
+    }
+
+    fn bytes_to_string(bytes: &[u8]) -> String {
+        let wide: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        from_wide(&wide)
+    }
+
+    // Drive letters assigned to mass storage devices, e.g. \DosDevices\E: -> \??\USBSTOR#Disk&Ven_...#123&0#{...}
+    unsafe fn mounted_devices(errors: &mut Vec<String>) -> Vec<UsbEvent> {
+        let key = match open_key("SYSTEM\\MountedDevices") {
+            Some(key) => key,
+            None => {
+                errors.push("Failed to open SYSTEM\\MountedDevices".to_string());
+                return vec![];
+            }
+        };
+        let mut events = vec![];
+        let mut index = 0;
+        loop {
+            let mut name = [0u16; 256];
+            let mut name_len = name.len() as DWORD;
+            let mut data = [0u8; 1024];
+            let mut data_len = data.len() as DWORD;
+            let status = RegEnumValueW(
+                key,
+                index,
+                name.as_mut_ptr(),
+                &mut name_len,
+                null_mut(),
+                null_mut(),
+                data.as_mut_ptr(),
+                &mut data_len,
+            );
+            index += 1;
+            if status as DWORD != ERROR_SUCCESS {
+                break;
+            }
+            let name = from_wide(&name[..name_len as usize]);
+            let mount_point = match name.strip_prefix("\\DosDevices\\") {
+                Some(letter) => letter.to_string(),
+                None => continue,
+            };
+            let target = bytes_to_string(&data[..data_len as usize]);
+            if let Some(mut event) = parse_device_id(&target) {
+                event.source = "mounted_devices".to_string();
+                event.mount_point = mount_point;
+                events.push(event);
+            }
+        }
+        RegCloseKey(key);
+        events
+    }
+
+    pub unsafe fn collect_registry(errors: &mut Vec<String>) -> Vec<UsbEvent> {
+        let mut events = vec![];
+
+        for bus in ["USBSTOR", "USB"] {
+            let bus_path = format!("{}\\{}", ENUM_KEY, bus);
+            let classes = sub_keys(&bus_path);
+            if classes.is_empty() && bus == "USBSTOR" {
+                debug!("No USB mass storage devices found in the registry");
+            }
+            for class in classes {
+                let class_path = format!("{}\\{}", bus_path, class);
+                for instance in sub_keys(&class_path) {
+                    let mut event =
+                        match parse_device_id(&format!("{}\\{}\\{}", bus, class, instance)) {
+                            Some(event) => event,
+                            None => continue,
+                        };
+                    let instance_path = format!("{}\\{}", class_path, instance);
+                    if let Some(name) = value_bytes(&instance_path, "FriendlyName") {
+                        let name = bytes_to_string(&name);
+                        if event.product.is_empty() {
+                            event.product = name;
+                        }
+                    }
+                    event.source = format!("registry_{}", bus.to_lowercase());
+
+                    // the properties are only readable with SYSTEM privileges
+                    let mut found = false;
+                    for property in DATE_PROPERTIES {
+                        let path = format!("{}\\{}\\{}", instance_path, PROPERTIES, property);
+                        if let Some(bytes) = value_bytes(&path, "") {
+                            found = true;
+                            events.push(UsbEvent {
+                                timestamp: filetime_to_utc(&bytes),
+                                ..event.clone()
+                            });
+                        }
+                    }
+                    if !found {
+                        events.push(event);
+                    }
+                }
+            }
+        }
+
+        events.extend(mounted_devices(errors));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_setupapi_log() {
+        let log = r#"
+>>>  [Device Install (Hardware initiated) - USB\VID_0781&PID_5567\4C530001230405115123]
+>>>  Section start 2024/01/15 10:23:45.123
+     ump: Creating Install Process: DrvInst.exe 10:23:45.127
+<<<  Section end 2024/01/15 10:23:47.431
+>>>  [Device Install (Hardware initiated) - USBSTOR\Disk&Ven_SanDisk&Prod_Cruzer_Blade&Rev_1.00\4C530001230405115123&0]
+>>>  Section start 2024/01/15 10:23:46.001
+>>>  [Device Install (Hardware initiated) - SWD\WPDBUSENUM\_??_USBSTOR#Disk&Ven_SanDisk]
+>>>  Section start 2024/01/15 10:23:47.001
+"#;
+        let events = parse_setupapi_log(log);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].vendor_id, "0781");
+        assert_eq!(events[0].product_id, "5567");
+        assert_eq!(events[0].serial, "4C530001230405115123");
+        assert!(events[0].timestamp.is_some());
+        assert_eq!(events[1].vendor, "SanDisk");
+        assert_eq!(events[1].product, "Cruzer Blade");
+        assert_eq!(events[1].serial, "4C530001230405115123");
+
+        // both installations belong to the same device
+        let devices = aggregate(events);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].vendor_id, "0781");
+        assert_eq!(devices[0].vendor, "SanDisk");
+        assert_eq!(devices[0].connections, 2);
+        assert!(devices[0].first_seen < devices[0].last_seen);
+
+        // no serial number, the instance id is generated by Windows
+        let event = parse_device_id("USBSTOR\\Disk&Ven_Generic&Prod_Flash\\7&2a6f1c5b&0").unwrap();
+        assert_eq!(event.serial, "");
+
+        // value data of MountedDevices
+        let event = parse_device_id("\\??\\USBSTOR#Disk&Ven_SanDisk&Prod_Cruzer#4C53&0#{53f56307}")
+            .unwrap();
+        assert_eq!(
+            event.device_id,
+            "USBSTOR\\Disk&Ven_SanDisk&Prod_Cruzer\\4C53&0"
+        );
+        assert_eq!(event.serial, "4C53");
+    }
+
+    #[test]
+    fn test_parse_kernel_log() {
+        let log = "\
+2024-01-15T10:23:45+0100 host kernel: usb 1-1: new high-speed USB device number 5 using xhci_hcd
+2024-01-15T10:23:45+0100 host kernel: usb 1-1: New USB device found, idVendor=0781, idProduct=5567, bcdDevice= 1.00
+2024-01-15T10:23:45+0100 host kernel: usb 1-1: Product: Cruzer Blade
+2024-01-15T10:23:45+0100 host kernel: usb 1-1: Manufacturer: SanDisk
+2024-01-15T10:23:45+0100 host kernel: usb 1-1: SerialNumber: 4C530001230405115123
+2024-01-15T11:00:00+0100 host kernel: usb 1-1: USB disconnect, device number 5
+Jan 16 09:00:00 host kernel: [ 12.345] usb 2-1: New USB device found, idVendor=0781, idProduct=5567, bcdDevice= 1.00
+Jan 16 09:00:00 host kernel: [ 12.346] usb 2-1: SerialNumber: 4C530001230405115123
+";
+        let events = parse_kernel_log("kern.log", log);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].product, "Cruzer Blade");
+        assert_eq!(events[0].vendor, "SanDisk");
+        assert_eq!(
+            events[0].timestamp.unwrap().to_rfc3339(),
+            "2024-01-15T09:23:45+00:00"
+        );
+
+        let devices = aggregate(events);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].connections, 2);
+        assert_eq!(devices[0].device_id, "usb:0781:5567");
+
+        let events = parse_unified_log(
+            "2024-01-15 10:23:45.123456+0100  localhost kernel[0]: (IOUSBMassStorageDriver) USBMSC Identifier (non-unique): 4C530001230405115123 0x781 0x5567 0x100, 2",
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].vendor_id, "0781");
+        assert_eq!(events[0].serial, "4C530001230405115123");
+        assert!(events[0].timestamp.is_some());
+    }
+}
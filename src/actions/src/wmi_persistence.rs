@@ -0,0 +1,655 @@
+use config::workflow::WmiPersistenceAttributes;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::{collections::BTreeMap, fs::File, io::BufWriter, path::PathBuf};
+
+use super::{error_result, ActionOptions, ActionResult};
+
+// Executables that are commonly abused by script-based WMI consumers or BITS notify commands
+const SUSPICIOUS_BINARIES: [&str; 9] = [
+    "powershell",
+    "pwsh",
+    "cmd.exe",
+    "wscript",
+    "cscript",
+    "mshta",
+    "rundll32",
+    "regsvr32",
+    "certutil",
+];
+
+// Consumer classes of the standard WMI providers. The rows of a query don't contain the class of
+// an instance (__CLASS is a system property, which isn't returned), so each class is queried
+pub const CONSUMER_CLASSES: [&str; 5] = [
+    "ActiveScriptEventConsumer",
+    "CommandLineEventConsumer",
+    "LogFileEventConsumer",
+    "NTEventLogEventConsumer",
+    "SMTPEventConsumer",
+];
+// base class of all consumers, finds the consumers of other providers
+const EVENT_CONSUMER: &str = "__EventConsumer";
+const EVENT_FILTER: &str = "__EventFilter";
+const BINDING: &str = "__FilterToConsumerBinding";
+
+#[derive(Debug, Serialize, Default)]
+pub struct WmiObject {
+    pub namespace: String,
+    pub class: String,
+    pub name: String,
+    pub properties: BTreeMap<String, String>,
+    pub suspicious: bool,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct BitsFile {
+    pub remote_name: String,
+    pub local_name: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct BitsJob {
+    pub id: String,
+    pub display_name: String,
+    pub owner: String,
+    pub state: String,
+    pub notify_program: String,
+    pub notify_parameters: String,
+    pub files: Vec<BitsFile>,
+    pub suspicious: bool,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct PersistenceReport {
+    pub filters: Vec<WmiObject>,
+    pub consumers: Vec<WmiObject>,
+    pub bindings: Vec<WmiObject>,
+    pub bits_jobs: Vec<BitsJob>,
+    pub errors: Vec<String>,
+}
+
+fn find_suspicious_binary(command_line: &str) -> Option<&'static str> {
+    let lower = command_line.to_lowercase();
+    SUSPICIOUS_BINARIES
+        .iter()
+        .find(|binary| lower.contains(*binary))
+        .copied()
+}
+
+impl WmiObject {
+    /// An instance of the queried class
+    pub fn new(namespace: &str, class: &str, properties: BTreeMap<String, String>) -> Self {
+        let name = properties.get("Name").cloned().unwrap_or_default();
+        let mut object = WmiObject {
+            namespace: namespace.to_string(),
+            class: class.to_string(),
+            name,
+            properties,
+            ..Default::default()
+        };
+        object.flag();
+        object
+    }
+
+    // Consumers executing scripts or command lines are the usual way to achieve persistence
+    pub fn flag(&mut self) {
+        match self.class.as_str() {
+            "ActiveScriptEventConsumer" => {
+                let engine = self
+                    .properties
+                    .get("ScriptingEngine")
+                    .cloned()
+                    .unwrap_or_default();
+                self.reasons
+                    .push(format!("Script-based consumer (engine: {:?})", engine));
+            }
+            "CommandLineEventConsumer" => {
+                let command_line = format!(
+                    "{} {}",
+                    self.properties
+                        .get("ExecutablePath")
+                        .map(|s| s.as_str())
+                        .unwrap_or_default(),
+                    self.properties
+                        .get("CommandLineTemplate")
+                        .map(|s| s.as_str())
+                        .unwrap_or_default()
+                );
+                match find_suspicious_binary(&command_line) {
+                    Some(binary) => self.reasons.push(format!(
+                        "Command line consumer launches script host {:?}",
+                        binary
+                    )),
+                    None => self
+                        .reasons
+                        .push("Command line consumer executes a program".to_string()),
+                }
+            }
+            _ => {}
+        }
+        self.suspicious = !self.reasons.is_empty();
+    }
+}
+
+impl PersistenceReport {
+    /// Adds the rows of a query of the class. A consumer of __EventConsumer is only added if it
+    /// wasn't found by the query of one of the CONSUMER_CLASSES
+    pub fn add_rows(&mut self, namespace: &str, class: &str, rows: Vec<BTreeMap<String, String>>) {
+        for properties in rows {
+            let object = WmiObject::new(namespace, class, properties);
+            match class {
+                EVENT_FILTER => self.filters.push(object),
+                BINDING => self.bindings.push(object),
+                EVENT_CONSUMER => {
+                    let known = self.consumers.iter().any(|consumer| {
+                        consumer.namespace == object.namespace && consumer.name == object.name
+                    });
+                    if !known {
+                        self.consumers.push(object);
+                    }
+                }
+                _ => self.consumers.push(object),
+            }
+        }
+    }
+}
+
+impl BitsJob {
+    // A notify command line is executed by the BITS service once the job completes
+    pub fn flag(&mut self) {
+        if !self.notify_program.is_empty() || !self.notify_parameters.is_empty() {
+            let command_line = format!("{} {}", self.notify_program, self.notify_parameters);
+            match find_suspicious_binary(&command_line) {
+                Some(binary) => self.reasons.push(format!(
+                    "Notify command line launches script host {:?}",
+                    binary
+                )),
+                None => self
+                    .reasons
+                    .push("Job has a notify command line".to_string()),
+            }
+        }
+        self.suspicious = !self.reasons.is_empty();
+    }
+}
+
+pub struct WmiPersistence {}
+
+impl WmiPersistence {
+    pub fn run(
+        attributes: WmiPersistenceAttributes,
+        options: ActionOptions,
+        out_file: PathBuf,
+    ) -> ActionResult {
+        if !cfg!(target_os = "windows") {
+            return error_result!(
+                "WMI persistence collection is only supported on Windows",
+                options.start_time
+            );
+        }
+
+        // COM has to be initialized on the thread issuing the queries,
+        // so we use a dedicated thread to not interfere with the async runtime
+        let handle = std::thread::spawn(move || collect(&attributes));
+        let report = match handle.join() {
+            Ok(report) => report,
+            Err(_) => {
+                return error_result!("WMI persistence collection panicked", options.start_time)
+            }
+        };
+
+        let suspicious = report.consumers.iter().filter(|c| c.suspicious).count()
+            + report.bits_jobs.iter().filter(|j| j.suspicious).count();
+        info!(
+            "Found {} WMI filters, {} consumers, {} bindings and {} BITS jobs ({} suspicious)",
+            report.filters.len(),
+            report.consumers.len(),
+            report.bindings.len(),
+            report.bits_jobs.len(),
+            suspicious
+        );
+        for error in &report.errors {
+            warn!("{}", error);
+        }
+
+        // Write the results as json to the action output directory
+        let file = match File::create(&out_file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Error creating file {:?}: {}", out_file.display(), e);
+                return error_result!(
+                    format!("Error creating file {:?}: {}", out_file.display(), e),
+                    options.start_time
+                );
+            }
+        };
+        if let Err(e) = serde_json::to_writer_pretty(BufWriter::new(file), &report) {
+            return error_result!(
+                format!("Error writing file {:?}: {}", out_file.display(), e),
+                options.start_time
+            );
+        }
+        debug!("Wrote WMI persistence results to {:?}", out_file.display());
+
+        let result = ActionResult {
+            success: report.errors.is_empty(),
+            exit_code: Some(0),
+            execution_time: options.start_time.elapsed(),
+            error_message: match report.errors.is_empty() {
+                true => None,
+                false => Some(report.errors.join("; ")),
+            },
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(
+                (report.filters.len()
+                    + report.consumers.len()
+                    + report.bindings.len()
+                    + report.bits_jobs.len()) as u64,
+            )
+            .with_artifact(out_file)
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn collect(_attributes: &WmiPersistenceAttributes) -> PersistenceReport {
+    PersistenceReport::default()
+}
+
+#[cfg(target_os = "windows")]
+fn collect(attributes: &WmiPersistenceAttributes) -> PersistenceReport {
+    let mut report = PersistenceReport::default();
+
+    let com_lib = match wmi::COMLibrary::new() {
+        Ok(com_lib) => com_lib,
+        Err(e) => {
+            report
+                .errors
+                .push(format!("Failed to initialize COM library: {}", e));
+            return report;
+        }
+    };
+
+    for namespace in &attributes.namespaces {
+        native::query_namespace(namespace, com_lib, &mut report);
+    }
+
+    if attributes.collect_bits {
+        match unsafe { native::query_bits_jobs() } {
+            Ok(jobs) => report.bits_jobs = jobs,
+            Err(e) => report.errors.push(e),
+        }
+    }
+
+    report
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) mod native {
+    use super::*;
+    use std::{collections::HashMap, ptr::null_mut};
+    use winapi::{
+        shared::{
+            guiddef::GUID, minwindef::ULONG, winerror::FAILED, wtypesbase::CLSCTX_LOCAL_SERVER,
+        },
+        um::{
+            bits::{
+                BackgroundCopyManager, IBackgroundCopyFile, IBackgroundCopyJob,
+                IBackgroundCopyManager, IEnumBackgroundCopyFiles, IEnumBackgroundCopyJobs,
+                BG_JOB_ENUM_ALL_USERS, BG_JOB_STATE,
+            },
+            bits1_5::IBackgroundCopyJob2,
+            combaseapi::{CoCreateInstance, CoTaskMemFree},
+            winnt::LPWSTR,
+        },
+        Class, Interface,
+    };
+    use wmi::{COMLibrary, Variant, WMIConnection};
+
+    pub(crate) fn variant_to_string(variant: &Variant) -> String {
+        match variant {
+            Variant::Empty | Variant::Null => String::new(),
+            Variant::String(s) => s.clone(),
+            Variant::I1(n) => n.to_string(),
+            Variant::I2(n) => n.to_string(),
+            Variant::I4(n) => n.to_string(),
+            Variant::I8(n) => n.to_string(),
+            Variant::R4(n) => n.to_string(),
+            Variant::R8(n) => n.to_string(),
+            Variant::Bool(b) => b.to_string(),
+            Variant::UI1(n) => n.to_string(),
+            Variant::UI2(n) => n.to_string(),
+            Variant::UI4(n) => n.to_string(),
+            Variant::UI8(n) => n.to_string(),
+            Variant::Array(values) => values
+                .iter()
+                .map(variant_to_string)
+                .collect::<Vec<String>>()
+                .join(", "),
+            _ => String::new(),
+        }
+    }
+
+    pub fn query_namespace(namespace: &str, com_lib: COMLibrary, report: &mut PersistenceReport) {
+        let connection = match WMIConnection::with_namespace_path(namespace, com_lib) {
+            Ok(connection) => connection,
+            Err(e) => {
+                report.errors.push(format!(
+                    "Failed to connect to WMI namespace {:?}: {}",
+                    namespace, e
+                ));
+                return;
+            }
+        };
+
+        // the concrete consumer classes are queried before their base class
+        let classes = [EVENT_FILTER]
+            .into_iter()
+            .chain(CONSUMER_CLASSES)
+            .chain([EVENT_CONSUMER, BINDING]);
+        for class in classes {
+            let rows: Vec<HashMap<String, Variant>> =
+                match connection.raw_query(format!("SELECT * FROM {}", class)) {
+                    Ok(rows) => rows,
+                    // the provider of a consumer class isn't registered in every namespace
+                    Err(e) if CONSUMER_CLASSES.contains(&class) => {
+                        debug!("Skipping {} in namespace {:?}: {}", class, namespace, e);
+                        continue;
+                    }
+                    Err(e) => {
+                        report.errors.push(format!(
+                            "Failed to query {} in namespace {:?}: {}",
+                            class, namespace, e
+                        ));
+                        continue;
+                    }
+                };
+
+            let rows: Vec<BTreeMap<String, String>> = rows
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|(key, value)| (key.clone(), variant_to_string(value)))
+                        .collect::<BTreeMap<String, String>>()
+                })
+                .collect();
+            report.add_rows(namespace, class, rows);
+        }
+    }
+
+    unsafe fn take_string(ptr: LPWSTR) -> String {
+        if ptr.is_null() {
+            return String::new();
+        }
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        let value = String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len));
+        CoTaskMemFree(ptr as _);
+        value
+    }
+
+    fn format_guid(guid: &GUID) -> String {
+        format!(
+            "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+            guid.Data1,
+            guid.Data2,
+            guid.Data3,
+            guid.Data4[0],
+            guid.Data4[1],
+            guid.Data4[2],
+            guid.Data4[3],
+            guid.Data4[4],
+            guid.Data4[5],
+            guid.Data4[6],
+            guid.Data4[7]
+        )
+    }
+
+    fn state_name(state: BG_JOB_STATE) -> &'static str {
+        match state {
+            0 => "queued",
+            1 => "connecting",
+            2 => "transferring",
+            3 => "suspended",
+            4 => "error",
+            5 => "transient_error",
+            6 => "transferred",
+            7 => "acknowledged",
+            8 => "cancelled",
+            _ => "unknown",
+        }
+    }
+
+    pub unsafe fn query_bits_jobs() -> Result<Vec<BitsJob>, String> {
+        let mut manager: *mut IBackgroundCopyManager = null_mut();
+        let hr = CoCreateInstance(
+            &BackgroundCopyManager::uuidof(),
+            null_mut(),
+            CLSCTX_LOCAL_SERVER,
+            &IBackgroundCopyManager::uuidof(),
+            &mut manager as *mut _ as *mut _,
+        );
+        if FAILED(hr) {
+            return Err(format!("Failed to connect to BITS (HRESULT 0x{:08X})", hr));
+        }
+
+        // Enumerating the jobs of all users requires elevation,
+        // so fall back to the jobs of the current user
+        let mut jobs_enum: *mut IEnumBackgroundCopyJobs = null_mut();
+        if FAILED((*manager).EnumJobs(BG_JOB_ENUM_ALL_USERS, &mut jobs_enum)) {
+            warn!("Unable to enumerate BITS jobs of all users, falling back to current user");
+            let hr = (*manager).EnumJobs(0, &mut jobs_enum);
+            if FAILED(hr) {
+                (*manager).Release();
+                return Err(format!(
+                    "Failed to enumerate BITS jobs (HRESULT 0x{:08X})",
+                    hr
+                ));
+            }
+        }
+
+        let mut jobs = vec![];
+        loop {
+            let mut job: *mut IBackgroundCopyJob = null_mut();
+            let mut fetched: ULONG = 0;
+            if (*jobs_enum).Next(1, &mut job, &mut fetched) != 0 || fetched == 0 {
+                break;
+            }
+            jobs.push(read_job(job));
+            (*job).Release();
+        }
+
+        (*jobs_enum).Release();
+        (*manager).Release();
+        Ok(jobs)
+    }
+
+    unsafe fn read_job(job: *mut IBackgroundCopyJob) -> BitsJob {
+        let mut bits_job = BitsJob::default();
+
+        let mut id: GUID = std::mem::zeroed();
+        if !FAILED((*job).GetId(&mut id)) {
+            bits_job.id = format_guid(&id);
+        }
+
+        let mut value: LPWSTR = null_mut();
+        if !FAILED((*job).GetDisplayName(&mut value)) {
+            bits_job.display_name = take_string(value);
+        }
+        let mut value: LPWSTR = null_mut();
+        if !FAILED((*job).GetOwner(&mut value)) {
+            bits_job.owner = take_string(value);
+        }
+
+        let mut state: BG_JOB_STATE = 0;
+        if !FAILED((*job).GetState(&mut state)) {
+            bits_job.state = state_name(state).to_string();
+        }
+
+        // The notify command line is only available via IBackgroundCopyJob2
+        let mut job2: *mut IBackgroundCopyJob2 = null_mut();
+        if !FAILED((*job).QueryInterface(
+            &IBackgroundCopyJob2::uuidof(),
+            &mut job2 as *mut _ as *mut _,
+        )) {
+            let mut program: LPWSTR = null_mut();
+            let mut parameters: LPWSTR = null_mut();
+            if !FAILED((*job2).GetNotifyCmdLine(&mut program, &mut parameters)) {
+                bits_job.notify_program = take_string(program);
+                bits_job.notify_parameters = take_string(parameters);
+            }
+            (*job2).Release();
+        }
+
+        let mut files_enum: *mut IEnumBackgroundCopyFiles = null_mut();
+        if !FAILED((*job).EnumFiles(&mut files_enum)) {
+            loop {
+                let mut file: *mut IBackgroundCopyFile = null_mut();
+                let mut fetched: ULONG = 0;
+                if (*files_enum).Next(1, &mut file, &mut fetched) != 0 || fetched == 0 {
+                    break;
+                }
+                let mut remote: LPWSTR = null_mut();
+                let mut local: LPWSTR = null_mut();
+                let remote_name = match FAILED((*file).GetRemoteName(&mut remote)) {
+                    true => String::new(),
+                    false => take_string(remote),
+                };
+                let local_name = match FAILED((*file).GetLocalName(&mut local)) {
+                    true => String::new(),
+                    false => take_string(local),
+                };
+                bits_job.files.push(BitsFile {
+                    remote_name,
+                    local_name,
+                });
+                (*file).Release();
+            }
+            (*files_enum).Release();
+        }
+
+        bits_job.flag();
+        bits_job
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_flag_wmi_consumers() {
+        // the rows of the queries, without system properties like __CLASS
+        let namespace = "root\\subscription";
+        let mut report = PersistenceReport::default();
+        report.add_rows(
+            namespace,
+            EVENT_FILTER,
+            vec![properties(&[
+                ("Name", "Updater"),
+                ("Query", "SELECT * FROM ..."),
+            ])],
+        );
+        report.add_rows(
+            namespace,
+            "ActiveScriptEventConsumer",
+            vec![properties(&[
+                ("Name", "Updater"),
+                ("ScriptingEngine", "VBScript"),
+            ])],
+        );
+        report.add_rows(
+            namespace,
+            "CommandLineEventConsumer",
+            vec![properties(&[
+                ("Name", "Cleanup"),
+                ("CommandLineTemplate", "PowerShell.exe -enc AAAA"),
+            ])],
+        );
+        report.add_rows(
+            namespace,
+            "NTEventLogEventConsumer",
+            vec![properties(&[("Name", "SCM Event Log Consumer")])],
+        );
+        // the base class returns the instances of all consumer classes again
+        report.add_rows(
+            namespace,
+            EVENT_CONSUMER,
+            vec![
+                properties(&[("Name", "Updater")]),
+                properties(&[("Name", "Cleanup")]),
+                properties(&[("Name", "SCM Event Log Consumer")]),
+                properties(&[("Name", "Custom")]),
+            ],
+        );
+
+        assert_eq!(report.filters.len(), 1);
+        assert!(!report.filters[0].suspicious);
+        let consumers: Vec<(&str, &str, bool)> = report
+            .consumers
+            .iter()
+            .map(|c| (c.class.as_str(), c.name.as_str(), c.suspicious))
+            .collect();
+        assert_eq!(
+            consumers,
+            [
+                ("ActiveScriptEventConsumer", "Updater", true),
+                ("CommandLineEventConsumer", "Cleanup", true),
+                ("NTEventLogEventConsumer", "SCM Event Log Consumer", false),
+                (EVENT_CONSUMER, "Custom", false),
+            ]
+        );
+        assert!(report.consumers[1].reasons[0].contains("powershell"));
+    }
+
+    #[test]
+    fn test_flag_bits_jobs() {
+        let mut job = BitsJob {
+            display_name: "Updater".to_string(),
+            notify_program: "C:\\Windows\\System32\\cmd.exe".to_string(),
+            notify_parameters: "/c payload.bat".to_string(),
+            ..Default::default()
+        };
+        job.flag();
+        assert!(job.suspicious);
+
+        let mut job = BitsJob {
+            display_name: "Font Download".to_string(),
+            ..Default::default()
+        };
+        job.flag();
+        assert!(!job.suspicious);
+    }
+
+    #[test]
+    fn test_unsupported_platform() {
+        if cfg!(target_os = "windows") {
+            return;
+        }
+        let attributes = WmiPersistenceAttributes {
+            namespaces: vec!["root\\subscription".to_string()],
+            collect_bits: true,
+        };
+        let result = WmiPersistence::run(
+            attributes,
+            ActionOptions::default(),
+            PathBuf::from("wmi_persistence.json"),
+        );
+        assert!(!result.success);
+    }
+}
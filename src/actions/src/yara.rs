@@ -1,5 +1,5 @@
 use super::{error_result, ActionOptions, ActionResult};
-use config::workflow::YaraAttributes;
+use config::workflow::{ReportingYaraSweep, YaraAttributes};
 use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
 use log::{debug, error};
 use rayon::prelude::*;
@@ -9,11 +9,17 @@ use std::{
     fmt::Write,
     fs::File,
     io::BufWriter,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
 };
-use storage::FileProcessor;
-use utils::misc::get_files_by_pattern;
+use storage::{
+    sweep::{ContentMatch, ContentScanner},
+    FileProcessor,
+};
+use utils::{
+    age::AgeFilter,
+    misc::{iter_files_by_pattern, iter_files_by_pattern_cached},
+};
 use yara::{Compiler, Rules};
 
 #[derive(Serialize, Deserialize)]
@@ -37,6 +43,20 @@ fn compile_yara_rules(
     Ok(rules)
 }
 
+/// Rule files matching the patterns (one per line), relative patterns are resolved against custom_files
+pub(crate) fn resolve_rules_paths(patterns: &str, custom_files_dir: &Path) -> Vec<PathBuf> {
+    let rules_paths: HashSet<PathBuf> = patterns
+        .split('\n')
+        .filter(|pattern| !pattern.trim().is_empty())
+        .map(|pattern| match PathBuf::from(pattern).is_absolute() {
+            true => pattern.to_string(),
+            false => custom_files_dir.join(pattern).to_string_lossy().to_string(),
+        })
+        .flat_map(|pattern| iter_files_by_pattern(&pattern, false).into_iter().flatten())
+        .collect();
+    rules_paths.into_iter().collect()
+}
+
 fn scan_files_with_rules<'a>(
     rules: &'a Rules,
     files: &'a [PathBuf],
@@ -113,45 +133,48 @@ impl Yara {
             Some(writer)
         };
 
+        let age_filter = match AgeFilter::new(scan.modified_within, &scan.modified_after) {
+            Ok(age_filter) => age_filter,
+            Err(e) => return error_result!(e, options.start_time),
+        };
+
         // Step 1: Split pattern string into Vec<String>
         let files_to_scan_patterns = scan.files_to_scan.split('\n').collect::<Vec<&str>>();
-        let rules_paths_patterns = scan.rules_paths.split('\n').collect::<Vec<&str>>();
-
-        // Step 2: Check if rule paths are relative or absolute
-        let rules_paths_patterns: Vec<String> = rules_paths_patterns
-            .iter()
-            .map(|pattern| {
-                if PathBuf::from(pattern).is_absolute() {
-                    pattern.to_string()
-                } else {
-                    custom_files_dir.join(pattern).to_string_lossy().to_string()
-                }
-            })
-            .collect();
 
-        // Step 3: Get all unique files and rules paths matching the patterns
+        // Step 2: Get all unique files and rules paths matching the patterns
         let files_to_scan: HashSet<PathBuf> = files_to_scan_patterns
             .iter()
-            .flat_map(|pattern| get_files_by_pattern(pattern, false).unwrap_or_default())
-            .collect();
-
-        let rules_paths: HashSet<PathBuf> = rules_paths_patterns
-            .iter()
-            .flat_map(|pattern| get_files_by_pattern(pattern, false).unwrap_or_default())
+            .flat_map(|pattern| {
+                iter_files_by_pattern_cached(
+                    pattern,
+                    false,
+                    scan.max_files,
+                    options.path_cache.as_ref(),
+                )
+                .into_iter()
+                .flatten()
+            })
+            .filter(|file| age_filter.allows(file))
+            // the strings of a match would reveal the content of excluded files
+            .filter(|file| !file_processor.is_excluded(file))
             .collect();
 
         let files_to_scan: Vec<PathBuf> = files_to_scan.into_iter().collect();
-        let rules_paths: Vec<PathBuf> = rules_paths.into_iter().collect();
+        let rules_paths = resolve_rules_paths(&scan.rules_paths, custom_files_dir);
 
         // Both files_to_scan and rules should have at least one element
         if files_to_scan.is_empty() {
-            return error_result!("No files to scan provided", options.start_time);
+            let message = match age_filter.is_empty() {
+                true => "No files to scan provided",
+                false => "No files to scan modified within the time window",
+            };
+            return error_result!(message, options.start_time);
         }
         if rules_paths.is_empty() {
             return error_result!("No rules provided", options.start_time);
         }
 
-        // Step 4: Configure rayon with the number of threads
+        // Step 3: Configure rayon with the number of threads
         rayon::ThreadPoolBuilder::new()
             .num_threads(scan.num_threads as usize)
             .build_global()
@@ -189,7 +212,7 @@ impl Yara {
         );
         files_pb.set_message("Scanning files");
 
-        // Step 5: Scan files in batches
+        // Step 4: Scan files in batches
         let file_batch_size = 500;
         let rule_batch_size = 500;
         let total_hits = AtomicUsize::new(0);
@@ -234,6 +257,10 @@ impl Yara {
                 writer.serialize(result).unwrap();
             }
 
+            if result.error.is_none() {
+                file_processor.add_yara_match(&result.original_path, &result.namespace);
+            }
+
             // Check if the file has already been stored
             let original_path_str = result.original_path.to_string_lossy().to_string();
             if already_stored.contains_key(&original_path_str) {
@@ -255,13 +282,110 @@ impl Yara {
             already_stored.insert(original_path_str, true);
         }
 
-        ActionResult {
+        let mut warnings = vec![];
+        let scan_errors = total_errors.load(Ordering::Relaxed);
+        if scan_errors > 0 {
+            warnings.push(format!("{} files could not be scanned", scan_errors));
+        }
+        let result = ActionResult {
             success: true,
             exit_code: Some(0),
             execution_time: options.start_time.elapsed(),
             error_message: None,
             parallel: false,
             finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        result
+            .with_items(files_to_scan.len() as u64)
+            .with_artifact(out_file)
+            .with_warnings(warnings)
+    }
+}
+
+/// Compiled rules of the sweep over all collected files, see the yara_sweep report settings
+pub struct YaraSweep {
+    rules: Rules,
+    rules_count: usize,
+    timeout: i32,
+}
+
+impl std::fmt::Debug for YaraSweep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("YaraSweep")
+            .field("rules_count", &self.rules_count)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl YaraSweep {
+    pub fn new(
+        settings: &ReportingYaraSweep,
+        custom_files_dir: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rules_paths = resolve_rules_paths(&settings.rules, custom_files_dir);
+        if rules_paths.is_empty() {
+            return Err("No rules provided".into());
         }
+        Ok(Self {
+            rules: compile_yara_rules(&rules_paths, &ProgressBar::hidden())?,
+            rules_count: rules_paths.len(),
+            timeout: settings.timeout,
+        })
+    }
+
+    pub fn rules_count(&self) -> usize {
+        self.rules_count
+    }
+}
+
+impl ContentScanner for YaraSweep {
+    fn scan(&self, content: &[u8]) -> Result<Vec<ContentMatch>, String> {
+        let matches = self
+            .rules
+            .scan_mem(content, self.timeout)
+            .map_err(|e| e.to_string())?;
+        Ok(matches
+            .into_iter()
+            .map(|match_| ContentMatch {
+                rule: match_.identifier.to_string(),
+                namespace: match_.namespace.to_string(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_yara_sweep() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_yara_sweep");
+        fs::write(
+            dir.join("evil.yar"),
+            "rule evil_string { strings: $a = \"EVIL\" condition: $a }",
+        )
+        .unwrap();
+
+        let mut settings = ReportingYaraSweep {
+            rules: "*.yar".to_string(),
+            ..Default::default()
+        };
+        let sweep = YaraSweep::new(&settings, &dir).unwrap();
+        assert_eq!(sweep.rules_count(), 1);
+        let matches = sweep.scan(b"an EVIL file").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].rule, "evil_string");
+        assert_eq!(matches[0].namespace, "default");
+        assert!(sweep.scan(b"a clean file").unwrap().is_empty());
+
+        settings.rules = "missing/*.yar".to_string();
+        assert!(YaraSweep::new(&settings, &dir).is_err());
     }
 }
@@ -0,0 +1,148 @@
+use config::config::CONFIG_PATH;
+use report::REPORTS_DIR;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+use system::{base_path_of, CUSTOM_FILES_DIR};
+use workflow::handler::WORKFLOWS_DIR;
+
+const KEYS_DIR: &str = "keys";
+const BIN_DIR: &str = "bin";
+const LAYOUT_DIRS: [&str; 5] = [
+    BIN_DIR,
+    WORKFLOWS_DIR,
+    KEYS_DIR,
+    CUSTOM_FILES_DIR,
+    REPORTS_DIR,
+];
+
+// the shipped defaults are the templates, so they can't get out of sync
+const CONFIG_TEMPLATE: &str = include_str!("../../../output/config.yaml");
+
+#[cfg(target_os = "windows")]
+const EXAMPLE_WORKFLOW: (&str, &str) = (
+    "example_windows.yaml",
+    include_str!("../../../output/workflows/demo_windows.yaml"),
+);
+#[cfg(target_os = "macos")]
+const EXAMPLE_WORKFLOW: (&str, &str) = (
+    "example_macos.yaml",
+    include_str!("../../../output/workflows/example_macos.yaml"),
+);
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const EXAMPLE_WORKFLOW: (&str, &str) = (
+    "example_linux.yaml",
+    include_str!("../../../output/workflows/example_linux.yaml"),
+);
+
+/// Creates the directory layout and the templates inside the directory.
+/// Existing files are never overwritten. Returns what has been created
+pub fn init_layout(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut created = vec![];
+    for sub_dir in LAYOUT_DIRS {
+        let path = dir.join(sub_dir);
+        if !path.is_dir() {
+            fs::create_dir_all(&path)
+                .map_err(|e| format!("Error creating directory {:?}: {}", path.display(), e))?;
+            created.push(format!("{}/", sub_dir));
+        }
+    }
+
+    let (workflow_name, workflow) = EXAMPLE_WORKFLOW;
+    let templates = [
+        (PathBuf::from(CONFIG_PATH), CONFIG_TEMPLATE),
+        (PathBuf::from(WORKFLOWS_DIR).join(workflow_name), workflow),
+    ];
+    for (relative_path, content) in templates {
+        let path = dir.join(&relative_path);
+        if path.exists() {
+            continue;
+        }
+        fs::write(&path, content)
+            .map_err(|e| format!("Error writing file {:?}: {}", path.display(), e))?;
+        created.push(relative_path.to_string_lossy().replace('\\', "/"));
+    }
+    Ok(created)
+}
+
+/// Problems with the placement of the executable, which would make the collector fail to find its files
+pub fn check_binary_placement(exe: &Path, dir: &Path) -> Option<String> {
+    let file_name = exe
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let expected = dir.join(BIN_DIR).join(&file_name);
+    match base_path_of(exe) {
+        Ok(base_path) if same_path(&base_path, dir) => None,
+        Ok(base_path) => Some(format!(
+            "The collector resolves its base path to {:?} instead of {:?}. Move it to {:?}",
+            base_path.display(),
+            dir.display(),
+            expected.display()
+        )),
+        Err(_) => Some(format!(
+            "The collector is not inside a bin directory and will not find its files. Move it to {:?}",
+            expected.display()
+        )),
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_init_layout() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_init_layout");
+
+        let created = init_layout(&dir).unwrap();
+        for sub_dir in LAYOUT_DIRS {
+            assert!(dir.join(sub_dir).is_dir());
+        }
+        assert!(created.contains(&CONFIG_PATH.to_string()));
+        // the templates can be loaded
+        config::config::load_config(&dir.join(CONFIG_PATH), &[]).unwrap();
+        let workflow = dir.join(WORKFLOWS_DIR).join(EXAMPLE_WORKFLOW.0);
+        serde_yaml::from_str::<config::workflow::WorkflowRunner>(
+            &fs::read_to_string(&workflow).unwrap(),
+        )
+        .unwrap();
+
+        // existing files are kept
+        fs::write(dir.join(CONFIG_PATH), "elevate: true\n").unwrap();
+        assert!(init_layout(&dir).unwrap().is_empty());
+        assert_eq!(
+            fs::read_to_string(dir.join(CONFIG_PATH)).unwrap(),
+            "elevate: true\n"
+        );
+    }
+
+    #[test]
+    fn test_check_binary_placement() {
+        let dir = PathBuf::from("/opt/ir-toolkit");
+        assert!(check_binary_placement(&dir.join("bin").join("collector"), &dir).is_none());
+        assert!(
+            check_binary_placement(&dir.join("bin").join("linux").join("collector"), &dir)
+                .is_none()
+        );
+        assert!(check_binary_placement(&dir.join("collector"), &dir)
+            .unwrap()
+            .contains("not inside a bin directory"));
+        assert!(
+            check_binary_placement(&PathBuf::from("/tmp/bin/collector"), &dir)
+                .unwrap()
+                .contains("instead of")
+        );
+    }
+}
@@ -0,0 +1,200 @@
+// Prevents two collectors (e.g. a scheduled and a manual run) from writing
+// into the same reports directory at the same time
+use chrono::{SecondsFormat, Utc};
+use log::{info, warn};
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+pub const LOCK_FILE: &str = "collector.lock";
+
+/// Process id and start time of the collector holding the lock
+#[derive(Debug, PartialEq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub started: String,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            started: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let started = lines.next().unwrap_or_default().trim().to_string();
+        Some(Self { pid, started })
+    }
+}
+
+/// Lock of the base path, released when dropped
+#[derive(Debug)]
+pub struct CollectorLock {
+    path: PathBuf,
+    // locked by the OS (flock or LockFileEx) while the collector runs, so the lock is released
+    // even if the collector is killed. None if forced while another collector holds it
+    file: Option<File>,
+}
+
+fn read_holder(file: &mut File) -> Option<LockHolder> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    LockHolder::parse(&content)
+}
+
+// the previous holder removes the file once it has finished, possibly after it was opened
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(file), Ok(path)) => file.dev() == path.dev() && file.ino() == path.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+impl CollectorLock {
+    /// Acquires the lock of the base path. A lock left over by a collector which is no longer
+    /// running is taken over, as the OS released it. A running collector keeps its lock:
+    /// if forced, the collector runs without the lock
+    pub fn acquire(base_path: &Path, force: bool) -> Result<Self, String> {
+        let path = base_path.join(LOCK_FILE);
+        for _ in 0..3 {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map_err(|e| format!("Error opening {}: {}", path.display(), e))?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    // Windows doesn't allow reading the locked file
+                    let holder = match read_holder(&mut file) {
+                        Some(holder) => {
+                            format!("PID {}, started {}", holder.pid, holder.started)
+                        }
+                        None => "unknown PID".to_string(),
+                    };
+                    if !force {
+                        return Err(format!(
+                            "Another collector ({}) is already running on {}. Wait for it to finish, or use --force to run anyway",
+                            holder,
+                            base_path.display()
+                        ));
+                    }
+                    warn!(
+                        "Running without the lock {} held by another collector ({})",
+                        path.display(),
+                        holder
+                    );
+                    return Ok(Self { path, file: None });
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(format!("Error locking {}: {}", path.display(), e))
+                }
+            }
+            if !is_same_file(&file, &path) {
+                continue;
+            }
+
+            if let Some(holder) = read_holder(&mut file) {
+                warn!(
+                    "Taking over the lock of the collector with PID {} (started {}), which is no longer running",
+                    holder.pid, holder.started
+                );
+            }
+            let holder = LockHolder::current();
+            file.set_len(0)
+                .and_then(|_| file.rewind())
+                .and_then(|_| writeln!(file, "{}\n{}", holder.pid, holder.started))
+                .map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+            info!("Acquired lock: {}", path.display());
+            return Ok(Self {
+                path,
+                file: Some(file),
+            });
+        }
+        Err(format!(
+            "Another collector acquired the lock {} in the meantime",
+            path.display()
+        ))
+    }
+}
+
+impl Drop for CollectorLock {
+    fn drop(&mut self) {
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => return,
+        };
+        // the file is removed while it is still locked, unless it was replaced
+        let owned = is_same_file(&file, &self.path)
+            && read_holder(&mut file).is_some_and(|holder| holder.pid == std::process::id());
+        if !owned {
+            warn!("Lock {} was replaced, keeping it", self.path.display());
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Error removing lock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_collector_lock() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_collector_lock");
+        let path = dir.join(LOCK_FILE);
+
+        let lock = CollectorLock::acquire(&dir, false).unwrap();
+        let holder = LockHolder::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(holder.pid, std::process::id());
+
+        // the running collector keeps its lock, even if another one is forced
+        let error = CollectorLock::acquire(&dir, false).unwrap_err();
+        assert!(error.contains(&format!("PID {}", std::process::id())));
+        let forced = CollectorLock::acquire(&dir, true).unwrap();
+        drop(forced);
+        assert!(path.exists());
+        assert!(CollectorLock::acquire(&dir, false).is_err());
+        drop(lock);
+        assert!(!path.exists());
+
+        // locks of collectors which are no longer running are taken over
+        fs::write(&path, format!("{}\n2024-08-12T13:45:20Z\n", i32::MAX)).unwrap();
+        let lock = CollectorLock::acquire(&dir, false).unwrap();
+        drop(lock);
+        assert!(!path.exists());
+        fs::write(&path, "garbage").unwrap();
+        drop(CollectorLock::acquire(&dir, false).unwrap());
+        assert!(!path.exists());
+
+        // a replaced lock isn't removed
+        #[cfg(unix)]
+        {
+            let lock = CollectorLock::acquire(&dir, false).unwrap();
+            fs::remove_file(&path).unwrap();
+            fs::write(&path, "1\n2024-08-12T13:45:20Z\n").unwrap();
+            drop(lock);
+            assert!(path.exists());
+        }
+    }
+}
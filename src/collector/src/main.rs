@@ -1,60 +1,325 @@
 use clap::{Arg, Command};
-use config::config::{read_config_file, CONFIG_PATH};
-use log::{error, info, LevelFilter};
+use config::{
+    config::{load_config_with_secrets, load_language, CONFIG_PATH},
+    workflow::resolve_secrets,
+};
+use lock::CollectorLock;
+use log::{error, info, warn, LevelFilter};
 use logging::Logger;
-use privileges::{is_elevated, restart_elevated};
-use system::SystemVariables;
-use utils::misc::exit_after_user_input;
-use workflow::handler::WorkflowHandler;
+use privileges::{is_elevated, try_restart_elevated};
+use report::{
+    retention::{enforce_retention, list_reports},
+    REPORTS_DIR, TOOLKIT_VERSION,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use system::{set_base_path, try_get_base_path, SystemVariables, BASE_PATH_ENV};
+use utils::{
+    fail_injection,
+    messages::{self, PRESS_ANY_KEY_TO_EXIT},
+    misc::exit_after_user_input,
+};
+use workflow::{
+    failure::{remove_failure_report, FailureCategory, FailureReport, FAILURE_FILE},
+    handler::WorkflowHandler,
+    operator_audit::{
+        self, ELEVATION_DENIED, ELEVATION_GRANTED, ELEVATION_PROMPT, PENDING_AUDIT_FILE,
+    },
+};
+
+mod init;
+mod lock;
+mod plan;
+mod secrets;
 
 fn main() {
+    // started by a step with run_as on Windows, only to start its process as the user
+    if let Some(code) = privileges::run_as_proxy() {
+        std::process::exit(code);
+    }
+
+    let matches = get_command().get_matches();
+
+    // set up a new deployment, before the base path is resolved
+    if let Some(("init", sub_matches)) = matches.subcommand() {
+        // with an overridden base path, the binary doesn't have to be inside the toolkit
+        let base_path = matches
+            .get_one::<String>("base_path")
+            .cloned()
+            .or(std::env::var(BASE_PATH_ENV)
+                .ok()
+                .filter(|dir| !dir.is_empty()));
+        let check_placement = base_path.is_none();
+        let dir = sub_matches.get_one::<String>("dir").cloned().or(base_path);
+        std::process::exit(run_init(dir, check_placement));
+    }
+
     // Step 1: Initialize system variables
+    if let Some(base_path) = matches.get_one::<String>("base_path") {
+        set_base_path(PathBuf::from(base_path));
+    }
     let system_variables = SystemVariables::new();
 
-    // Step 2: Read the config file
+    // manage the encrypted secrets without loading the config
+    if let Some(("secrets", sub_matches)) = matches.subcommand() {
+        std::process::exit(secrets::run_secrets(
+            &system_variables.base_path,
+            sub_matches,
+        ));
+    }
+
+    // print the plan of the workflows without running them
+    if let Some(("plan", sub_matches)) = matches.subcommand() {
+        std::process::exit(plan::run_plan(&system_variables.base_path, sub_matches));
+    }
+
+    // Step 2: Read the config file, apply the encrypted secrets and the environment and command line overrides
+    let overrides: Vec<String> = matches
+        .get_many::<String>("set")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    // the collection doesn't depend on the credentials, so it runs without them
+    let (stored_secrets, secrets_error) = match secrets::unseal(&system_variables.base_path) {
+        Ok(values) => (values, None),
+        Err(e) => (BTreeMap::new(), Some(e.to_string())),
+    };
     let config_path = &system_variables.base_path.join(CONFIG_PATH);
-    let config = match read_config_file(config_path) {
+    let config = match load_config_with_secrets(config_path, &stored_secrets, &overrides) {
         Ok(config) => config,
         Err(e) => {
             error!("Error reading config file: {}", e);
-            return;
+            eprintln!("Error reading config file: {}", e);
+            write_failure(
+                &system_variables.base_path,
+                FailureReport::new(
+                    FailureCategory::Config,
+                    &format!("Error reading config file: {}", e),
+                    &system_variables.device_name,
+                ),
+            );
+            std::process::exit(1);
         }
     };
 
+    // print the effective config without running any workflow
+    if let Some(("config", sub_matches)) = matches.subcommand() {
+        if let Some(("show", _)) = sub_matches.subcommand() {
+            // the values of the encrypted secrets are hidden as well
+            let hidden: BTreeMap<String, String> = stored_secrets
+                .keys()
+                .map(|key| (key.clone(), "********".to_string()))
+                .collect();
+            match load_config_with_secrets(config_path, &hidden, &overrides)
+                .and_then(|config| config.to_yaml_redacted())
+            {
+                Ok(yaml) => print!("{}", yaml),
+                Err(e) => eprintln!("Error printing config: {}", e),
+            }
+        }
+        return;
+    }
+
+    // the credentials and secrets are redacted from the log
+    logging::add_secrets(config.credentials());
+    logging::add_secrets(stored_secrets.into_values());
+    // secrets that can't be resolved are reported by each workflow
+    let (secrets, _) = resolve_secrets(&config.secrets);
+    logging::add_secrets(secrets.into_values());
+
     // Step 3: Initialize the logger
-    let matches = get_command().get_matches();
     let logger = Logger::init()
         .set_file()
         .set_level(match matches.get_flag("verbose") {
             true => LevelFilter::Debug,
             false => LevelFilter::Info,
         })
-        .set_time_config(config.time)
+        .set_time_config(config.time.clone())
         .apply();
 
     logger.log_initial_info();
     info!("{}", system_variables);
+    if let Some(e) = secrets_error {
+        warn!("Failed to unseal the encrypted secrets: {}", e);
+    }
 
-    // Step 4: Elevate the process
-    if config.elevate && !is_elevated() {
-        restart_elevated();
+    // the prompts stay in English if the language can't be loaded
+    if let Err(e) = load_language(&system_variables.base_path, &config.language) {
+        warn!("Error loading language {:?}: {}", config.language, e);
     }
 
-    // Step 5: Initialize the workflow handler
+    // developer flag to test the error handling, see utils::fail_injection
+    if let Some(spec) = matches.get_one::<String>("fail_injection") {
+        if let Err(e) = fail_injection::set(spec) {
+            error!("{}", e);
+            eprintln!("{}", e);
+            write_failure(
+                &system_variables.base_path,
+                FailureReport::new(FailureCategory::Config, &e, &system_variables.device_name),
+            );
+            std::process::exit(1);
+        }
+        warn!("Fail injection enabled: {}", spec);
+    }
+
+    // Step 4: Elevate the process once if a workflow requires or prefers it
+    let base_path = system_variables.base_path.clone();
+    let device_name = system_variables.device_name.clone();
     let mut workflow_handler = WorkflowHandler::init(system_variables);
-    workflow_handler.run();
+    workflow_handler.set_elevate(config.elevate);
+    workflow_handler.set_time_zone(config.time.time_zone.clone());
+    workflow_handler.set_max_parallel_actions(config.max_parallel_actions);
+    workflow_handler.set_exclusions(config.exclusions.clone());
+    // the elevation prompt is documented in the operator audit log of the first workflow
+    let pending_audit = base_path.join(PENDING_AUDIT_FILE);
+    if operator_audit::restore_pending(&pending_audit) && is_elevated() {
+        operator_audit::record(ELEVATION_GRANTED, "Restarted as admin", None);
+    }
+    if !is_elevated() && workflow_handler.requests_elevation() {
+        operator_audit::record(ELEVATION_PROMPT, "Restart as admin", None);
+        operator_audit::persist_pending(&pending_audit);
+        if let Err(e) = try_restart_elevated() {
+            operator_audit::restore_pending(&pending_audit);
+            operator_audit::record(ELEVATION_DENIED, &e.to_string(), None);
+            warn!(
+                "Failed to restart as admin: {}. Workflows requiring elevation are skipped",
+                e
+            );
+        }
+    }
+
+    workflow_handler.check_full_disk_access(config.full_disk_access_prompt);
+
+    // Step 5: Make sure no other collector writes into the same base path
+    let lock = match CollectorLock::acquire(&base_path, matches.get_flag("force")) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", e);
+            write_failure(
+                &base_path,
+                FailureReport::new(FailureCategory::Lock, &e.to_string(), &device_name),
+            );
+            exit_after_user_input(&messages::text(PRESS_ANY_KEY_TO_EXIT), 1);
+        }
+    };
+    if let Err(e) = remove_failure_report(&base_path) {
+        warn!(
+            "Failed to remove {} of the previous run: {}",
+            FAILURE_FILE, e
+        );
+    }
+    report_interrupted_encryptions(&base_path);
+
+    // Step 6: Initialize the workflow handler
+    workflow_handler.set_notifications(config.notifications.clone());
+    workflow_handler.set_upload(config.upload.clone());
+    workflow_handler.set_secrets(config.secrets.clone());
+    workflow_handler.set_custom_files(config.custom_files.clone());
+    workflow_handler
+        .set_parallel_workflows(*matches.get_one::<usize>("parallel_workflows").unwrap());
+    let results = workflow_handler.run();
 
-    info!("Workflow finished successfully");
+    match FailureReport::from_results(&results, &device_name) {
+        Some(failure) => {
+            warn!("{}", failure.message);
+            write_failure(&base_path, failure);
+        }
+        None => info!("Workflow finished successfully"),
+    }
+
+    // Step 7: Apply the report retention policy, the reports just written are kept
+    let current_run: Vec<String> = results
+        .iter()
+        .filter_map(|result| result.report.clone())
+        .collect();
+    if let Err(e) = enforce_retention(&base_path, &config.reports.retention, &current_run) {
+        error!("Error applying report retention policy: {}", e);
+    }
 
+    drop(lock);
     logger.finish();
 
-    // Step 6: Wait for user input
-    exit_after_user_input("Press any key to exit...", 0);
+    // Step 8: Wait for user input
+    exit_after_user_input(&messages::text(PRESS_ANY_KEY_TO_EXIT), 0);
+}
+
+// A crash while the archive of a previous run was encrypted leaves it unencrypted. It can't be
+// encrypted now, as its session key is lost and the action outputs were encrypted with it
+fn report_interrupted_encryptions(base_path: &Path) {
+    let Ok(reports) = list_reports(&base_path.join(REPORTS_DIR)) else {
+        return;
+    };
+    for report in reports {
+        for file in crypto::interrupted_encryptions(&report.path) {
+            let progress = crypto::read_encryption_journal(&file)
+                .map(|journal| format!(" at {} of {} bytes", journal.offset, journal.file_size))
+                .unwrap_or_default();
+            error!(
+                "Encryption of {:?} was interrupted{} by a previous run: the file is still unencrypted. Remove the report once it has been secured",
+                file, progress
+            );
+        }
+    }
+}
+
+// Leaves the reason of the failure in the reports directory for remote orchestration
+fn write_failure(base_path: &Path, failure: FailureReport) {
+    if let Err(e) = failure.write(base_path) {
+        error!("Failed to write {}: {}", FAILURE_FILE, e);
+    }
+}
+
+/// Creates the directory layout in the directory (or the base path) and checks the placement of the binary
+fn run_init(dir: Option<String>, check_placement: bool) -> i32 {
+    let dir = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None => match try_get_base_path() {
+            Ok(base_path) => base_path,
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!("Use --dir to specify the root directory of the toolkit");
+                return 1;
+            }
+        },
+    };
+
+    match init::init_layout(&dir) {
+        Ok(created) if created.is_empty() => {
+            println!("Nothing to do, {} is already initialized", dir.display())
+        }
+        Ok(created) => {
+            println!("Initialized {}:", dir.display());
+            for path in created {
+                println!("  created {}", path);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error initializing {}: {}", dir.display(), e);
+            return 1;
+        }
+    }
+
+    if !check_placement {
+        return 0;
+    }
+    match std::env::current_exe() {
+        Ok(exe) => {
+            if let Some(warning) = init::check_binary_placement(&exe, &dir) {
+                eprintln!("Warning: {}", warning);
+                return 1;
+            }
+        }
+        Err(e) => eprintln!("Error getting current exe: {}", e),
+    }
+    0
 }
 
 fn get_command() -> Command {
     Command::new("Collector")
-        .version("1.0")
+        .version(TOOLKIT_VERSION)
         .about("Runs the defined workflows")
         .arg(
             Arg::new("verbose")
@@ -63,4 +328,108 @@ fn get_command() -> Command {
                 .help("Enables verbose logging")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Runs even if another collector holds the lock of the base path")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("base_path")
+                .long("base-path")
+                .value_name("DIR")
+                .global(true)
+                .help("Root directory of the toolkit (workflows, keys, reports, ...), overrides IRT_BASE_PATH"),
+        )
+        .arg(
+            Arg::new("set")
+                .long("set")
+                .value_name("KEY=VALUE")
+                .global(true)
+                .action(clap::ArgAction::Append)
+                .help("Overrides a config value, e.g. --set time.ntp_enabled=true"),
+        )
+        .arg(
+            Arg::new("parallel_workflows")
+                .long("parallel-workflows")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Runs up to N independent workflows at the same time, each with its own report"),
+        )
+        .arg(
+            Arg::new("fail_injection")
+                .long("fail-injection")
+                .value_name("SPEC")
+                .hide(true)
+                .help("Makes subsystems fail deterministically, e.g. zip_write=3,encryption,spawn=2"),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Inspects the configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("show")
+                        .about("Prints the effective configuration (defaults, config.yaml, IRT_* environment variables and --set overrides)"),
+                ),
+        )
+        .subcommand(
+            Command::new("plan")
+                .about("Prints the workflows, steps and settings the collector would run as JSON, without running them")
+                .arg(
+                    Arg::new("baseline")
+                        .long("baseline")
+                        .value_name("FILE")
+                        .help("Prints what changed compared to a previous plan instead, exits with 1 if anything changed"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Writes the plan to the file, e.g. as baseline of the next change"),
+                ),
+        )
+        .subcommand(
+            Command::new("secrets")
+                .about("Manages the encrypted credentials of the upload and notification targets")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("set")
+                        .about("Seals a value, read from stdin, under its config key (e.g. upload.targets.0.secret_key)")
+                        .arg(Arg::new("name").required(true).value_name("KEY"))
+                        .arg(
+                            Arg::new("public_key")
+                                .long("public-key")
+                                .value_name("PEM")
+                                .help("Seals new secrets to a public key instead of a passphrase"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("get")
+                        .about("Prints an unsealed value")
+                        .arg(Arg::new("name").required(true).value_name("KEY"))
+                        .arg(
+                            Arg::new("private_key")
+                                .long("private-key")
+                                .value_name("PEM")
+                                .help("Private key of secrets sealed to a public key, overrides IRT_SECRETS_KEY"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("Lists the keys of the secrets"))
+                .subcommand(
+                    Command::new("remove")
+                        .about("Removes a value")
+                        .arg(Arg::new("name").required(true).value_name("KEY")),
+                ),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Creates the directory layout, a template config.yaml and an example workflow")
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .value_name("DIR")
+                        .help("Root directory of the toolkit, defaults to the parent of the bin directory"),
+                ),
+        )
 }
@@ -0,0 +1,49 @@
+// Prints the plan of the workflows (collector plan) without running them, or what changed compared
+// to a previous plan, so a change of the workflows can be reviewed before it is shipped
+use clap::ArgMatches;
+use std::path::{Path, PathBuf};
+use workflow::plan::{diff_plans, Plan};
+
+/// Returns the exit code: 0 without changes, 1 if the plan differs from the baseline, 2 on errors
+pub fn run_plan(base_path: &PathBuf, matches: &ArgMatches) -> i32 {
+    let plan = Plan::create(base_path);
+    let json = match plan.to_json() {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Error creating the plan: {}", e);
+            return 2;
+        }
+    };
+    let baseline = matches.get_one::<String>("baseline");
+    match matches.get_one::<String>("output") {
+        Some(output) => {
+            if let Err(e) = std::fs::write(output, &json) {
+                eprintln!("Error writing {}: {}", output, e);
+                return 2;
+            }
+        }
+        // the diff is printed instead
+        None if baseline.is_none() => println!("{}", json),
+        None => {}
+    }
+
+    let Some(baseline) = baseline else {
+        return 0;
+    };
+    let baseline = match Plan::read(Path::new(baseline)) {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            eprintln!("Error reading the baseline {}: {}", baseline, e);
+            return 2;
+        }
+    };
+    let changes = diff_plans(&baseline, &plan);
+    if changes.is_empty() {
+        println!("No changes");
+        return 0;
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    1
+}
@@ -0,0 +1,157 @@
+// Manages the encrypted credentials of the upload and notification targets (collector secrets ...)
+// and unseals them for the config. The values are applied by their config key
+use clap::ArgMatches;
+use config::config::{SECRETS_KEY_ENV, SECRETS_PASSPHRASE_ENV};
+use console::Term;
+use crypto::{
+    load_private_key, load_public_key, SealKey, SealMode, SecretsStore, UnsealKey, SECRETS_PATH,
+};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{self, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+// the passphrase is read from the environment first, so it can be scripted
+fn passphrase() -> Result<String, Box<dyn Error>> {
+    if let Ok(passphrase) = std::env::var(SECRETS_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = prompt("Passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err("The passphrase must not be empty".into());
+    }
+    Ok(passphrase)
+}
+
+// the input isn't echoed on a terminal, piped input is read as is
+fn prompt(message: &str) -> Result<String, Box<dyn Error>> {
+    eprint!("{}", message);
+    io::stderr().flush()?;
+    if io::stdin().is_terminal() {
+        return Ok(Term::stderr().read_secure_line()?);
+    }
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn unseal_key(
+    store: &SecretsStore,
+    private_key: Option<PathBuf>,
+) -> Result<UnsealKey, Box<dyn Error>> {
+    match store.mode {
+        SealMode::Passphrase => Ok(UnsealKey::Passphrase(passphrase()?)),
+        SealMode::PublicKey => {
+            let path = private_key
+                .or_else(|| std::env::var(SECRETS_KEY_ENV).ok().map(PathBuf::from))
+                .ok_or_else(|| {
+                    format!(
+                        "The secrets are sealed to a public key, set {} to the private key",
+                        SECRETS_KEY_ENV
+                    )
+                })?;
+            Ok(UnsealKey::PrivateKey(load_private_key(path)?))
+        }
+    }
+}
+
+/// Unseals the secrets for the config, empty if there are none.
+/// Unlike the management commands, it never prompts for the passphrase
+pub fn unseal(base_path: &Path) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let path = base_path.join(SECRETS_PATH);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let store = SecretsStore::read(&path)?;
+    let key = match store.mode {
+        SealMode::Passphrase => UnsealKey::Passphrase(
+            std::env::var(SECRETS_PASSPHRASE_ENV)
+                .map_err(|_| format!("{} is not set", SECRETS_PASSPHRASE_ENV))?,
+        ),
+        SealMode::PublicKey => unseal_key(&store, None)?,
+    };
+    store.unseal(&key)
+}
+
+fn run(base_path: &Path, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = base_path.join(SECRETS_PATH);
+    let existing = match path.exists() {
+        true => Some(SecretsStore::read(&path)?),
+        false => None,
+    };
+    match matches.subcommand() {
+        Some(("set", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let public_key = sub_matches.get_one::<String>("public_key");
+            let (mut store, key) = match (existing, public_key) {
+                (Some(_), Some(_)) => {
+                    return Err(format!(
+                        "{} already exists, the public key can only be chosen for new secrets",
+                        path.display()
+                    )
+                    .into())
+                }
+                (Some(store), None) => {
+                    let key = match store.public_seal_key()? {
+                        Some(key) => key,
+                        None => SealKey::Passphrase(passphrase()?),
+                    };
+                    (store, key)
+                }
+                (None, Some(public_key)) => {
+                    let key = SealKey::PublicKey(load_public_key(PathBuf::from(public_key))?);
+                    (SecretsStore::new(&key)?, key)
+                }
+                (None, None) => {
+                    let key = SealKey::Passphrase(passphrase()?);
+                    (SecretsStore::new(&key)?, key)
+                }
+            };
+            // not an argument, so the value doesn't end up in the shell history
+            let value = prompt(&format!("Value of {}: ", name))?;
+            store.set(name, &value, &key)?;
+            store.write(&path)?;
+            println!("Stored {} in {}", name, path.display());
+        }
+        Some(("get", sub_matches)) => {
+            let store = existing.ok_or_else(|| format!("{} not found", path.display()))?;
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let private_key = sub_matches
+                .get_one::<String>("private_key")
+                .map(PathBuf::from);
+            let key = unseal_key(&store, private_key)?;
+            println!("{}", store.get(name, &key)?);
+        }
+        Some(("list", _)) => {
+            if let Some(store) = existing {
+                for name in store.names() {
+                    println!("{}", name);
+                }
+            }
+        }
+        Some(("remove", sub_matches)) => {
+            let mut store = existing.ok_or_else(|| format!("{} not found", path.display()))?;
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            if !store.remove(name) {
+                return Err(format!("Unknown secret {:?}", name).into());
+            }
+            store.write(&path)?;
+            println!("Removed {}", name);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Runs a secrets management command, returns the exit code
+pub fn run_secrets(base_path: &Path, matches: &ArgMatches) -> i32 {
+    match run(base_path, matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
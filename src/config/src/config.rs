@@ -1,10 +1,24 @@
-use log::error;
-use serde::Deserialize;
-use std::{error::Error, fs::File, io::BufReader, path::PathBuf};
+use crate::workflow::{
+    deserialize_size_limit, deserialize_timeout, serialize_size_limit, serialize_timeout, Secret,
+};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+use system::BASE_PATH_ENV;
+use utils::messages;
 
 pub const CONFIG_PATH: &str = "config.yaml";
+// catalogs of further languages, e.g. languages/fr.yaml
+pub const LANGUAGES_DIR: &str = "languages";
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Time {
     pub time_zone: String,
     pub ntp_enabled: bool,
@@ -12,10 +26,295 @@ pub struct Time {
     pub ntp_timeout: u64,
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            time_zone: "UTC".to_string(),
+            ntp_enabled: false,
+            ntp_servers: vec![
+                "0.pool.ntp.org:123".to_string(),
+                "1.pool.ntp.org:123".to_string(),
+            ],
+            ntp_timeout: 2,
+        }
+    }
+}
+
+fn default_max_count() -> usize {
+    0
+}
+
+fn default_max_age() -> i32 {
+    0
+}
+
+fn default_max_total_size() -> u64 {
+    0
+}
+
+fn default_archive_dir() -> String {
+    String::new()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Retention {
+    #[serde(default = "default_max_count")]
+    pub max_count: usize,
+    // in seconds
+    #[serde(default = "default_max_age")]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub max_age: i32,
+    #[serde(default = "default_max_total_size")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub max_total_size: u64,
+    #[serde(default = "default_archive_dir")]
+    pub archive_dir: String,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_count: default_max_count(),
+            max_age: default_max_age(),
+            max_total_size: default_max_total_size(),
+            archive_dir: default_archive_dir(),
+        }
+    }
+}
+
+/// What happens if a binary doesn't match custom_files.manifest
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnMismatch {
+    Warn,
+    #[default]
+    Block,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CustomFiles {
+    #[serde(default)]
+    pub on_mismatch: OnMismatch,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Reports {
+    #[serde(default)]
+    pub retention: Retention,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationEvent {
+    Start,
+    Success,
+    Failure,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_smtp_credential() -> String {
+    String::new()
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SmtpTarget {
+    pub server: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default = "default_smtp_credential")]
+    pub username: String,
+    #[serde(default = "default_smtp_credential")]
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotificationTarget {
+    Webhook { url: String },
+    Slack { url: String },
+    Smtp(SmtpTarget),
+}
+
+fn default_notification_events() -> Vec<NotificationEvent> {
+    vec![NotificationEvent::Success, NotificationEvent::Failure]
+}
+
+fn default_notification_targets() -> Vec<NotificationTarget> {
+    Vec::new()
+}
+
+fn default_notification_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notifications {
+    #[serde(default = "default_notification_events")]
+    pub events: Vec<NotificationEvent>,
+    #[serde(default = "default_notification_targets")]
+    pub targets: Vec<NotificationTarget>,
+    // in seconds
+    #[serde(default = "default_notification_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            events: default_notification_events(),
+            targets: default_notification_targets(),
+            timeout: default_notification_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectLockMode {
+    Governance,
+    Compliance,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(try_from = "ObjectLockSettings")]
+pub struct ObjectLock {
+    // no retention is set if empty
+    pub mode: Option<ObjectLockMode>,
+    pub retain_days: u32,
+    pub legal_hold: bool,
+}
+
+// object lock as written in the config, checked before it is used
+#[derive(Deserialize)]
+struct ObjectLockSettings {
+    #[serde(default)]
+    mode: Option<ObjectLockMode>,
+    #[serde(default)]
+    retain_days: u32,
+    #[serde(default)]
+    legal_hold: bool,
+}
+
+impl TryFrom<ObjectLockSettings> for ObjectLock {
+    type Error = String;
+
+    fn try_from(settings: ObjectLockSettings) -> Result<Self, Self::Error> {
+        // the retention would end as soon as the object is uploaded
+        if settings.mode.is_some() && settings.retain_days == 0 {
+            return Err("object_lock.retain_days must be at least 1 if a mode is set".to_string());
+        }
+        Ok(Self {
+            mode: settings.mode,
+            retain_days: settings.retain_days,
+            legal_hold: settings.legal_hold,
+        })
+    }
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+fn default_verify_checksum() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct S3Target {
+    pub endpoint: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    #[serde(default)]
+    pub object_lock: ObjectLock,
+    #[serde(default = "default_verify_checksum")]
+    pub verify_checksum: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UploadTarget {
+    S3(S3Target),
+}
+
+fn default_upload_timeout() -> u64 {
+    600
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Upload {
+    #[serde(default)]
+    pub targets: Vec<UploadTarget>,
+    // in seconds, per request
+    #[serde(default = "default_upload_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            timeout: default_upload_timeout(),
+        }
+    }
+}
+
+fn default_language() -> String {
+    messages::DEFAULT_LANGUAGE.to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub time: Time,
+    #[serde(default)]
     pub elevate: bool,
+    // asks to grant Full Disk Access on macOS if a workflow needs it
+    #[serde(default)]
+    pub full_disk_access_prompt: bool,
+    // of the prompts shown to the person running the collector
+    #[serde(default = "default_language")]
+    pub language: String,
+    // parallel steps of a workflow running at the same time, 0 for no limit
+    #[serde(default)]
+    pub max_parallel_actions: usize,
+    #[serde(default)]
+    pub reports: Reports,
+    #[serde(default)]
+    pub notifications: Notifications,
+    #[serde(default)]
+    pub upload: Upload,
+    #[serde(default)]
+    pub custom_files: CustomFiles,
+    // shared by all workflows, a secret of a workflow with the same name takes precedence
+    #[serde(default)]
+    pub secrets: BTreeMap<String, Secret>,
+    // paths which are never collected by any workflow, e.g. **/medical/**
+    #[serde(default)]
+    pub exclusions: Vec<String>,
 }
 
 pub fn read_config_file(yaml_path: &PathBuf) -> Result<Config, Box<dyn Error>> {
@@ -30,6 +329,219 @@ pub fn read_config_file(yaml_path: &PathBuf) -> Result<Config, Box<dyn Error>> {
     }
 }
 
+/// Selects the language of the prompts. A catalog in the languages directory adds a language
+/// or rewords the built-in messages, e.g. languages/fr.yaml with `press_any_key_to_exit: "..."`
+pub fn load_language(base_path: &Path, language: &str) -> Result<(), Box<dyn Error>> {
+    let path = base_path
+        .join(LANGUAGES_DIR)
+        .join(format!("{}.yaml", language));
+    let custom: HashMap<String, String> = match path.exists() {
+        true => serde_yaml::from_reader(BufReader::new(File::open(&path)?))
+            .map_err(|e| format!("Error parsing {:?}: {}", path.display(), e))?,
+        false => HashMap::new(),
+    };
+    debug!(
+        "Using language {:?} ({} custom messages)",
+        language,
+        custom.len()
+    );
+    messages::set_language(language, custom)?;
+    Ok(())
+}
+
+/// Prefix of environment variables overriding the config file, e.g. IRT_TIME__NTP_ENABLED=true
+pub const ENV_PREFIX: &str = "IRT_";
+
+// values of these keys are hidden when the config is printed
+const SECRET_KEYS: [&str; 3] = ["password", "secret_key", "secrets"];
+
+/// Passphrase of the encrypted secrets, not part of the config
+pub const SECRETS_PASSPHRASE_ENV: &str = "IRT_SECRETS_PASSPHRASE";
+/// Private key the encrypted secrets are sealed to, not part of the config
+pub const SECRETS_KEY_ENV: &str = "IRT_SECRETS_KEY";
+
+// read before the config is loaded
+const NON_CONFIG_ENV: [&str; 3] = [BASE_PATH_ENV, SECRETS_PASSPHRASE_ENV, SECRETS_KEY_ENV];
+
+/// Reads the config in layers: defaults -> config file -> IRT_* environment variables -> CLI overrides.
+/// Overrides have the form "time.ntp_enabled=true", the value is parsed as YAML
+pub fn load_config(yaml_path: &PathBuf, overrides: &[String]) -> Result<Config, Box<dyn Error>> {
+    load_config_with_secrets(yaml_path, &BTreeMap::new(), overrides)
+}
+
+/// Like load_config, the unsealed secrets are applied after the config file by their key,
+/// e.g. "upload.targets.0.secret_key". Their values are always strings
+pub fn load_config_with_secrets(
+    yaml_path: &PathBuf,
+    secrets: &BTreeMap<String, String>,
+    overrides: &[String],
+) -> Result<Config, Box<dyn Error>> {
+    let mut value = match yaml_path.exists() {
+        true => {
+            let reader = BufReader::new(File::open(yaml_path)?);
+            serde_yaml::from_reader(reader)?
+        }
+        false => {
+            warn!(
+                "Config file {:?} not found: using defaults",
+                yaml_path.display()
+            );
+            Value::Mapping(Mapping::new())
+        }
+    };
+
+    for (key, secret) in secrets {
+        debug!("Config value from the encrypted secrets: {}", key);
+        insert_value(&mut value, key, Value::String(secret.clone()))?;
+    }
+    apply_overrides(&mut value, std::env::vars(), overrides)?;
+
+    match serde_yaml::from_value(value) {
+        Ok(config) => Ok(config),
+        Err(e) => {
+            error!("Error parsing config schema: {}", e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+fn apply_overrides(
+    value: &mut Value,
+    env_vars: impl Iterator<Item = (String, String)>,
+    overrides: &[String],
+) -> Result<(), Box<dyn Error>> {
+    // e.g. IRT_REPORTS__RETENTION__MAX_COUNT -> reports.retention.max_count
+    let mut env_vars: Vec<(String, String)> = env_vars
+        .filter_map(|(key, raw)| {
+            if NON_CONFIG_ENV.contains(&key.as_str()) {
+                return None;
+            }
+            let key = key.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            Some((key.replace("__", "."), raw))
+        })
+        .collect();
+    // apply in a stable order, so nested keys win over their parents
+    env_vars.sort();
+    for (key, raw) in env_vars {
+        debug!("Config override from environment: {}", key);
+        set_value(value, &key, &raw)?;
+    }
+
+    for entry in overrides {
+        let (key, raw) = match entry.split_once('=') {
+            Some(pair) => pair,
+            None => return Err(format!("Invalid override {:?}: expected key=value", entry).into()),
+        };
+        debug!("Config override from command line: {}", key);
+        set_value(value, key.trim(), raw)?;
+    }
+    Ok(())
+}
+
+fn set_value(root: &mut Value, key: &str, raw: &str) -> Result<(), Box<dyn Error>> {
+    // "true" -> bool, "10" -> number, "[a, b]" -> list, everything else stays a string
+    let new_value = match raw.is_empty() {
+        true => Value::String(String::new()),
+        false => serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    };
+    insert_value(root, key, new_value)
+}
+
+// Items of a list are addressed by their index, e.g. "upload.targets.0.secret_key"
+fn insert_value(root: &mut Value, key: &str, new_value: Value) -> Result<(), Box<dyn Error>> {
+    let mut current = root;
+    let parts: Vec<&str> = key.split('.').filter(|p| !p.is_empty()).collect();
+    for (index, part) in parts.iter().enumerate() {
+        if current.is_null() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        current = match current {
+            Value::Mapping(mapping) => mapping
+                .entry(Value::String(part.to_string()))
+                .or_insert(Value::Null),
+            Value::Sequence(sequence) => {
+                match part.parse::<usize>().ok().and_then(|i| sequence.get_mut(i)) {
+                    Some(item) => item,
+                    None => {
+                        return Err(format!(
+                            "Invalid override {:?}: {:?} is not an index of the list",
+                            key, part
+                        )
+                        .into())
+                    }
+                }
+            }
+            _ => {
+                return Err(
+                    format!("Invalid override {:?}: {:?} is not a section", key, part).into(),
+                )
+            }
+        };
+        if index == parts.len() - 1 {
+            *current = new_value;
+            return Ok(());
+        }
+    }
+    Err(format!("Invalid override: empty key {:?}", key).into())
+}
+
+impl Config {
+    /// The effective config as YAML with secrets hidden
+    pub fn to_yaml_redacted(&self) -> Result<String, Box<dyn Error>> {
+        let mut value = serde_yaml::to_value(self)?;
+        redact(&mut value);
+        Ok(serde_yaml::to_string(&value)?)
+    }
+
+    /// Credentials of the notification and upload targets, redacted from the logs
+    pub fn credentials(&self) -> Vec<String> {
+        let mut credentials = vec![];
+        for target in &self.notifications.targets {
+            if let NotificationTarget::Smtp(smtp) = target {
+                credentials.push(smtp.password.clone());
+            }
+        }
+        for target in &self.upload.targets {
+            match target {
+                UploadTarget::S3(s3) => credentials.push(s3.secret_key.clone()),
+            }
+        }
+        credentials.retain(|credential| !credential.is_empty());
+        credentials
+    }
+}
+
+fn hide_strings(value: &mut Value) {
+    match value {
+        Value::String(s) if !s.is_empty() => *s = "********".to_string(),
+        Value::Mapping(mapping) => mapping
+            .iter_mut()
+            .for_each(|(_, value)| hide_strings(value)),
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(hide_strings),
+        Value::Tagged(tagged) => hide_strings(&mut tagged.value),
+        _ => {}
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let secret = key.as_str().is_some_and(|key| SECRET_KEYS.contains(&key));
+                match value {
+                    Value::String(s) if secret && !s.is_empty() => *s = "********".to_string(),
+                    // e.g. all values below secrets
+                    _ if secret => hide_strings(value),
+                    _ => redact(value),
+                }
+            }
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(redact),
+        Value::Tagged(tagged) => redact(&mut tagged.value),
+        _ => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -66,5 +578,259 @@ mod tests {
 
         let config = read_config_file(&yaml_path).unwrap();
         assert_config_valid(&config);
+        assert_eq!(config.reports.retention.max_count, 0);
+    }
+
+    #[test]
+    fn test_read_config_file_retention() {
+        let mut cleanup = Cleanup::new();
+        let yaml_path = cleanup
+            .tmp_dir("test_read_config_file_retention")
+            .join("config.yaml");
+
+        let yaml_content = r#"
+            time:
+                time_zone: "UTC"
+                ntp_enabled: true
+                ntp_servers:
+                    - "0.pool.ntp.org"
+                    - "1.pool.ntp.org"
+                ntp_timeout: 10
+            elevate: true
+            reports:
+                retention:
+                    max_count: 5
+                    max_age: 7days
+                    max_total_size: 10 GB
+        "#;
+        fs::write(&yaml_path, yaml_content).expect("Failed to write config file");
+
+        let config = read_config_file(&yaml_path).unwrap();
+        assert_config_valid(&config);
+        assert_eq!(config.reports.retention.max_count, 5);
+        assert_eq!(config.reports.retention.max_age, 7 * 24 * 60 * 60);
+        assert_eq!(config.reports.retention.max_total_size, 10_000_000_000);
+        assert_eq!(config.reports.retention.archive_dir, "");
+    }
+
+    #[test]
+    fn test_read_config_file_notifications() {
+        let mut cleanup = Cleanup::new();
+        let yaml_path = cleanup
+            .tmp_dir("test_read_config_file_notifications")
+            .join("config.yaml");
+
+        let yaml_content = r#"
+            time:
+                time_zone: "UTC"
+                ntp_enabled: true
+                ntp_servers:
+                    - "0.pool.ntp.org"
+                    - "1.pool.ntp.org"
+                ntp_timeout: 10
+            elevate: true
+            notifications:
+                events: ["start", "failure"]
+                targets:
+                    - type: slack
+                      url: "https://hooks.slack.com/services/T000/B000/XXX"
+                    - type: smtp
+                      server: "smtp.example.com"
+                      from: "ir@example.com"
+                      to: ["soc@example.com"]
+        "#;
+        fs::write(&yaml_path, yaml_content).expect("Failed to write config file");
+
+        let config = read_config_file(&yaml_path).unwrap();
+        assert_config_valid(&config);
+        let notifications = config.notifications;
+        assert_eq!(
+            notifications.events,
+            vec![NotificationEvent::Start, NotificationEvent::Failure]
+        );
+        assert_eq!(notifications.timeout, 10);
+        assert_eq!(notifications.targets.len(), 2);
+        match &notifications.targets[1] {
+            NotificationTarget::Smtp(smtp) => {
+                assert_eq!(smtp.port, 587);
+                assert!(smtp.starttls);
+                assert_eq!(smtp.to, vec!["soc@example.com"]);
+            }
+            _ => panic!("Expected smtp target"),
+        }
+    }
+
+    #[test]
+    fn test_read_config_file_upload() {
+        let mut cleanup = Cleanup::new();
+        let yaml_path = cleanup
+            .tmp_dir("test_read_config_file_upload")
+            .join("config.yaml");
+
+        let yaml_content = r#"
+            time:
+                time_zone: "UTC"
+                ntp_enabled: true
+                ntp_servers:
+                    - "0.pool.ntp.org"
+                    - "1.pool.ntp.org"
+                ntp_timeout: 10
+            elevate: true
+            upload:
+                targets:
+                    - type: s3
+                      endpoint: "https://s3.eu-central-1.amazonaws.com"
+                      region: "eu-central-1"
+                      bucket: "evidence"
+                      access_key: "AKIA"
+                      secret_key: "secret"
+                      object_lock:
+                          mode: compliance
+                          retain_days: 365
+        "#;
+        fs::write(&yaml_path, yaml_content).expect("Failed to write config file");
+
+        let config = read_config_file(&yaml_path).unwrap();
+        assert_config_valid(&config);
+        assert_eq!(config.upload.timeout, 600);
+        match &config.upload.targets[0] {
+            UploadTarget::S3(s3) => {
+                assert_eq!(s3.bucket, "evidence");
+                assert!(s3.path_style);
+                assert!(s3.verify_checksum);
+                assert_eq!(s3.object_lock.mode, Some(ObjectLockMode::Compliance));
+                assert_eq!(s3.object_lock.retain_days, 365);
+                assert!(!s3.object_lock.legal_hold);
+            }
+        }
+
+        // a lock without retention is rejected
+        fs::write(
+            &yaml_path,
+            yaml_content.replace("retain_days: 365", "retain_days: 0"),
+        )
+        .expect("Failed to write config file");
+        let error = read_config_file(&yaml_path).unwrap_err().to_string();
+        assert!(error.contains("retain_days"), "{}", error);
+    }
+
+    #[test]
+    fn test_load_config_overrides() {
+        let mut value: Value = serde_yaml::from_str(
+            r#"
+            time:
+                time_zone: "UTC"
+                ntp_enabled: false
+                ntp_servers: ["0.pool.ntp.org:123"]
+                ntp_timeout: 2
+            elevate: false
+            notifications:
+                targets:
+                    - type: smtp
+                      server: "smtp.example.com"
+                      password: "hunter2"
+                      from: "ir@example.com"
+                      to: ["soc@example.com"]
+            secrets:
+                VT_API_KEY: "vt-0123456789"
+                SHARE_PASSWORD:
+                    env: "IRT_TEST_SHARE_PASSWORD"
+        "#,
+        )
+        .unwrap();
+        let env_vars = vec![
+            ("IRT_TIME__NTP_ENABLED".to_string(), "true".to_string()),
+            (
+                "IRT_REPORTS__RETENTION__MAX_COUNT".to_string(),
+                "5".to_string(),
+            ),
+            ("IRT_ELEVATE".to_string(), "true".to_string()),
+            ("IRT_BASE_PATH".to_string(), "/tmp/ir-toolkit".to_string()),
+            (
+                "IRT_SECRETS_PASSPHRASE".to_string(),
+                "passphrase".to_string(),
+            ),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ];
+        // command line overrides win over environment variables
+        let overrides = vec![
+            "elevate=false".to_string(),
+            "time.time_zone=Europe/Berlin".to_string(),
+            "reports.retention.max_age=7days".to_string(),
+        ];
+        apply_overrides(&mut value, env_vars.into_iter(), &overrides).unwrap();
+        assert!(value.get("base_path").is_none());
+        assert!(value.get("secrets_passphrase").is_none());
+        let config: Config = serde_yaml::from_value(value.clone()).unwrap();
+
+        assert!(config.time.ntp_enabled);
+        assert_eq!(config.time.time_zone, "Europe/Berlin");
+        assert!(!config.elevate);
+        assert_eq!(config.reports.retention.max_count, 5);
+        assert_eq!(config.reports.retention.max_age, 7 * 24 * 60 * 60);
+        assert_eq!(config.credentials(), vec!["hunter2"]);
+        assert_eq!(
+            config.secrets["SHARE_PASSWORD"],
+            Secret::Env {
+                env: "IRT_TEST_SHARE_PASSWORD".to_string()
+            }
+        );
+
+        assert!(apply_overrides(&mut value, vec![].into_iter(), &["elevate".to_string()]).is_err());
+        assert!(
+            apply_overrides(&mut value, vec![].into_iter(), &["elevate.x=1".to_string()]).is_err()
+        );
+        // items of a list by their index, e.g. a password of the encrypted secrets
+        insert_value(
+            &mut value,
+            "notifications.targets.0.password",
+            Value::String("1234".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            value["notifications"]["targets"][0]["password"].as_str(),
+            Some("1234")
+        );
+        assert!(insert_value(&mut value, "notifications.targets.1.password", Value::Null).is_err());
+
+        // the effective config can be read again, but secrets are hidden
+        let yaml = config.to_yaml_redacted().unwrap();
+        assert!(!yaml.contains("hunter2"));
+        assert!(!yaml.contains("vt-0123456789"));
+        assert!(yaml.contains("********"));
+        assert!(yaml.contains("max_age: 7days"));
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.reports.retention.max_count, 5);
+
+        // defaults only
+        let config: Config = serde_yaml::from_value(Value::Mapping(Mapping::new())).unwrap();
+        assert_eq!(config.time.time_zone, "UTC");
+        assert!(!config.elevate);
+        assert_eq!(config.language, "en");
+    }
+
+    #[test]
+    fn test_load_language() {
+        let mut cleanup = Cleanup::new();
+        let base_path = cleanup.tmp_dir("test_load_language");
+        fs::create_dir_all(base_path.join(LANGUAGES_DIR)).unwrap();
+        fs::write(
+            base_path.join(LANGUAGES_DIR).join("fr.yaml"),
+            "press_any_key_to_exit: \"Appuyez sur une touche pour quitter...\"\n",
+        )
+        .unwrap();
+
+        load_language(&base_path, "fr").unwrap();
+        assert_eq!(
+            messages::text(messages::PRESS_ANY_KEY_TO_EXIT),
+            "Appuyez sur une touche pour quitter..."
+        );
+        // not translated by the catalog
+        assert_eq!(
+            messages::text(messages::TERMINAL_EXIT),
+            "Type 'exit' to exit the terminal session"
+        );
+        assert!(load_language(&base_path, "xx").is_err());
+        load_language(&base_path, "en").unwrap();
     }
 }
@@ -1,14 +1,20 @@
 use byte_unit::Byte;
+use chrono_tz::Tz;
 use humantime::parse_duration;
-use log::{error, warn};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::io::BufReader;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::{error::Error, fs::File};
+use system::profiles::USER_PROFILES;
+use utils::{
+    pattern::{normalize_separators, normalize_separators_lines},
+    sanitize::sanitize_dirname,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CustomCommand {
@@ -28,16 +34,30 @@ impl CustomCommand {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegistryValueCondition {
+    pub key: String,
+    pub value: String,
+    pub equals: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LaunchConditions {
     pub os: Vec<String>,
     pub enabled: Option<bool>,
     pub arch: Option<Vec<String>>,
     pub is_elevated: Option<bool>,
     pub custom_command: Option<CustomCommand>,
+    pub service_exists: Option<Vec<String>>,
+    pub process_running: Option<Vec<String>>,
+    pub registry_value: Option<Vec<RegistryValueCondition>>,
+    // DNS or AD domains, case-insensitive
+    pub domain: Option<Vec<String>>,
+    // networks in CIDR notation (e.g. 10.20.0.0/16), one address of the endpoint must be inside
+    pub network: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
 pub enum ActionType {
     #[serde(rename = "binary")]
     Binary,
@@ -49,6 +69,38 @@ pub enum ActionType {
     Yara,
     #[serde(rename = "terminal")]
     Terminal,
+    #[serde(rename = "wmi_persistence")]
+    WmiPersistence,
+    #[serde(rename = "fs_snapshot")]
+    FsSnapshot,
+    #[serde(rename = "network_config")]
+    NetworkConfig,
+    #[serde(rename = "usb_history")]
+    UsbHistory,
+    #[serde(rename = "etw_trace")]
+    EtwTrace,
+    #[serde(rename = "pcap")]
+    Pcap,
+    #[serde(rename = "memory_files")]
+    MemoryFiles,
+    #[serde(rename = "memory_image")]
+    MemoryImage,
+    #[serde(rename = "defender")]
+    Defender,
+    #[serde(rename = "ssh_artifacts")]
+    SshArtifacts,
+    #[serde(rename = "netwatch")]
+    Netwatch,
+    #[serde(rename = "grep")]
+    Grep,
+    #[serde(rename = "registry")]
+    Registry,
+    #[serde(rename = "recycle_bin")]
+    RecycleBin,
+    #[serde(rename = "shell_artifacts")]
+    ShellArtifacts,
+    #[serde(rename = "dns_ioc")]
+    DnsIoc,
 }
 
 impl std::fmt::Display for ActionType {
@@ -59,6 +111,22 @@ impl std::fmt::Display for ActionType {
             ActionType::Store => write!(f, "store"),
             ActionType::Yara => write!(f, "yara"),
             ActionType::Terminal => write!(f, "terminal"),
+            ActionType::WmiPersistence => write!(f, "wmi_persistence"),
+            ActionType::FsSnapshot => write!(f, "fs_snapshot"),
+            ActionType::NetworkConfig => write!(f, "network_config"),
+            ActionType::UsbHistory => write!(f, "usb_history"),
+            ActionType::EtwTrace => write!(f, "etw_trace"),
+            ActionType::Pcap => write!(f, "pcap"),
+            ActionType::MemoryFiles => write!(f, "memory_files"),
+            ActionType::MemoryImage => write!(f, "memory_image"),
+            ActionType::Defender => write!(f, "defender"),
+            ActionType::SshArtifacts => write!(f, "ssh_artifacts"),
+            ActionType::Netwatch => write!(f, "netwatch"),
+            ActionType::Grep => write!(f, "grep"),
+            ActionType::Registry => write!(f, "registry"),
+            ActionType::RecycleBin => write!(f, "recycle_bin"),
+            ActionType::ShellArtifacts => write!(f, "shell_artifacts"),
+            ActionType::DnsIoc => write!(f, "dns_ioc"),
         }
     }
 }
@@ -72,6 +140,11 @@ fn parallel_action_types() -> Vec<ActionType> {
     ]
 }
 
+// only actions starting a process can drop privileges
+fn run_as_action_types() -> Vec<ActionType> {
+    vec![ActionType::Binary, ActionType::Command]
+}
+
 // only some action typed support a timeout
 fn timeout_action_types() -> Vec<ActionType> {
     vec![ActionType::Binary, ActionType::Command]
@@ -85,6 +158,10 @@ fn default_size_limit() -> u64 {
     0
 }
 
+fn default_max_files() -> usize {
+    0
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StoreAttributes {
     #[serde(default = "default_case_sensitive")]
@@ -94,6 +171,51 @@ pub struct StoreAttributes {
     #[serde(deserialize_with = "deserialize_size_limit")]
     #[serde(serialize_with = "serialize_size_limit")]
     pub size_limit: u64,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    // overrides reporting.zip_archive.keep_plaintext for this action
+    #[serde(default)]
+    pub keep_plaintext: Option<bool>,
+    // MIME types detected by extension and content, e.g. "application/pdf" or "video/*"
+    #[serde(default)]
+    pub include_types: Vec<String>,
+    #[serde(default)]
+    pub exclude_types: Vec<String>,
+    // only files modified within the duration (0 = disabled) or after the timestamp (empty = disabled)
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub modified_within: i32,
+    #[serde(default)]
+    pub modified_after: String,
+    // store the content of link targets and traverse linked directories, otherwise links are only recorded
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    // executed for each stored file, ${FILE} is replaced by the path of the file
+    #[serde(default)]
+    pub per_file_command: Option<PerFileCommand>,
+    // run the parser plugins of custom_files/parsers on the stored files of their types
+    #[serde(default)]
+    pub parsers: bool,
+    // custom metadata recorded for each stored file, e.g. category: browser
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
+}
+
+fn default_per_file_timeout() -> i32 {
+    30
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PerFileCommand {
+    pub cmd: String,
+    #[serde(default = "default_args")]
+    pub args: Vec<String>,
+    // per file (0 = no timeout)
+    #[serde(default = "default_per_file_timeout")]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub timeout: i32,
 }
 
 fn default_args() -> Vec<String> {
@@ -104,19 +226,107 @@ fn default_log_to_file() -> bool {
     true
 }
 
+/// Normalizes common spellings of an architecture, e.g. amd64 -> x86_64
+pub fn normalize_arch(arch: &str) -> String {
+    let arch = arch.to_lowercase();
+    match arch.as_str() {
+        "amd64" | "x64" => "x86_64".to_string(),
+        "arm64" => "aarch64".to_string(),
+        "i386" | "i686" | "x32" => "x86".to_string(),
+        _ => arch,
+    }
+}
+
+/// Names of a platform, the most specific first: [os]_[arch], [os], default
+pub fn platform_keys(os: &str, arch: &str) -> Vec<String> {
+    let os = os.to_lowercase();
+    vec![
+        format!("{}_{}", os, normalize_arch(arch)),
+        os,
+        "default".to_string(),
+    ]
+}
+
+// e.g. "windows_amd64" -> "windows_x86_64"
+fn normalize_platform_key(key: &str) -> String {
+    let key = key.to_lowercase();
+    match key.split_once('_') {
+        Some((os, arch)) => format!("{}_{}", os, normalize_arch(arch)),
+        None => key,
+    }
+}
+
+/// A single path or one path per platform (e.g. windows_x86_64: tool.exe)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum BinaryPath {
+    Single(String),
+    PerPlatform(BTreeMap<String, String>),
+}
+
+impl BinaryPath {
+    /// The path for the platform, None if no entry matches
+    pub fn select(&self, os: &str, arch: &str) -> Option<&str> {
+        match self {
+            BinaryPath::Single(path) => Some(path),
+            BinaryPath::PerPlatform(paths) => platform_keys(os, arch).iter().find_map(|key| {
+                paths
+                    .iter()
+                    .find(|(platform, _)| normalize_platform_key(platform) == *key)
+                    .map(|(_, path)| path.as_str())
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for BinaryPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BinaryPath::Single(path) => write!(f, "{}", path),
+            BinaryPath::PerPlatform(paths) => write!(f, "{:?}", paths),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BinaryAttributes {
-    pub path: String,
+    pub path: BinaryPath,
     #[serde(default = "default_args")]
     pub args: Vec<String>,
     #[serde(default = "default_log_to_file")]
     pub log_to_file: bool,
+    // UNIX socket or named pipe to stream stdout to, empty to disable
+    #[serde(default = "default_stream_to")]
+    pub stream_to: String,
+    #[serde(default)]
+    pub sandbox: Sandbox,
 }
 
 fn default_cwd() -> String {
     String::new()
 }
 
+fn default_stream_to() -> String {
+    String::new()
+}
+
+/// Restrictions of the process started by a binary or command action, so a third-party
+/// tool can't touch more of the host than it needs to
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Sandbox {
+    #[serde(default)]
+    pub enabled: bool,
+    // the tool may connect to other hosts, required on Windows
+    #[serde(default)]
+    pub network: bool,
+    // Windows: the tool may use the clipboard, the desktop and the system settings
+    #[serde(default)]
+    pub ui: bool,
+    // Linux and macOS: files and directories the tool may write to
+    #[serde(default)]
+    pub writable_paths: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommandAttributes {
     pub cmd: String,
@@ -126,6 +336,11 @@ pub struct CommandAttributes {
     pub cwd: String,
     #[serde(default = "default_log_to_file")]
     pub log_to_file: bool,
+    // UNIX socket or named pipe to stream stdout to, empty to disable
+    #[serde(default = "default_stream_to")]
+    pub stream_to: String,
+    #[serde(default)]
+    pub sandbox: Sandbox,
 }
 
 fn default_store_on_match() -> bool {
@@ -152,9 +367,18 @@ pub struct YaraAttributes {
     #[serde(deserialize_with = "deserialize_timeout")]
     #[serde(serialize_with = "serialize_timeout")]
     pub scan_timeout: i32,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    // only files modified within the duration (0 = disabled) or after the timestamp (empty = disabled)
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub modified_within: i32,
+    #[serde(default)]
+    pub modified_after: String,
 }
 
-fn deserialize_timeout<'de, D>(deserializer: D) -> Result<i32, D::Error>
+pub(crate) fn deserialize_timeout<'de, D>(deserializer: D) -> Result<i32, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -166,7 +390,14 @@ where
     }
 }
 
-fn serialize_timeout<S>(value: &i32, serializer: S) -> Result<S::Ok, S::Error>
+fn deserialize_optional_timeout<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_timeout(deserializer).map(Some)
+}
+
+pub(crate) fn serialize_timeout<S>(value: &i32, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
@@ -187,6 +418,39 @@ fn default_separate_window() -> bool {
     false
 }
 
+fn default_audit_enabled() -> bool {
+    false
+}
+
+fn default_command_list() -> Vec<String> {
+    Vec::new()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalAudit {
+    // log each command line entered in the terminal
+    #[serde(default = "default_audit_enabled")]
+    pub enabled: bool,
+    // if not empty, only these commands are passed to the shell
+    #[serde(default = "default_command_list")]
+    pub allow: Vec<String>,
+    #[serde(default = "default_command_list")]
+    pub deny: Vec<String>,
+    #[serde(default = "default_command_list")]
+    pub warn: Vec<String>,
+}
+
+impl Default for TerminalAudit {
+    fn default() -> Self {
+        Self {
+            enabled: default_audit_enabled(),
+            allow: default_command_list(),
+            deny: default_command_list(),
+            warn: default_command_list(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TerminalAttributes {
     #[serde(default = "default_shell")]
@@ -198,6 +462,405 @@ pub struct TerminalAttributes {
     pub separate_window: bool,
     #[serde(default = "default_enable_transcript")]
     pub enable_transcript: bool,
+    #[serde(default)]
+    pub audit: TerminalAudit,
+    // tried in order if no shell is set, the first one found is used
+    #[serde(default)]
+    pub shells: Vec<String>,
+    // start the shell as a login shell (e.g. bash -l), which loads the profile of the user
+    #[serde(default)]
+    pub login_shell: bool,
+}
+
+fn default_wmi_namespaces() -> Vec<String> {
+    vec![
+        "root\\subscription".to_string(),
+        "root\\default".to_string(),
+    ]
+}
+
+fn default_collect_bits() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WmiPersistenceAttributes {
+    #[serde(default = "default_wmi_namespaces")]
+    pub namespaces: Vec<String>,
+    #[serde(default = "default_collect_bits")]
+    pub collect_bits: bool,
+}
+
+fn default_baseline() -> String {
+    String::new()
+}
+
+fn default_snapshot_checksums() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FsSnapshotAttributes {
+    pub patterns: String,
+    #[serde(default = "default_baseline")]
+    pub baseline: String,
+    #[serde(default = "default_case_sensitive")]
+    pub case_sensitive: bool,
+    #[serde(default = "default_snapshot_checksums")]
+    pub checksums: bool,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_collect_network_section() -> bool {
+    true
+}
+
+fn default_store_raw() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkConfigAttributes {
+    #[serde(default = "default_collect_network_section")]
+    pub firewall: bool,
+    #[serde(default = "default_collect_network_section")]
+    pub interfaces: bool,
+    #[serde(default = "default_collect_network_section")]
+    pub proxy: bool,
+    #[serde(default = "default_collect_network_section")]
+    pub hosts: bool,
+    // store copies of the underlying configuration files
+    #[serde(default = "default_store_raw")]
+    pub store_raw: bool,
+}
+
+fn default_log_days() -> u32 {
+    30
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UsbHistoryAttributes {
+    // number of days to search in the system journal (Linux) or unified log (macOS)
+    #[serde(default = "default_log_days")]
+    pub log_days: u32,
+    // store copies of the parsed log files
+    #[serde(default = "default_store_raw")]
+    pub store_raw: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SshArtifactsAttributes {
+    // store the auth logs modified within this number of days (0 = all)
+    #[serde(default = "default_log_days")]
+    pub log_days: u32,
+    #[serde(default = "default_store_raw")]
+    pub auth_logs: bool,
+    // store copies of the configuration and key files
+    #[serde(default = "default_store_raw")]
+    pub store_raw: bool,
+}
+
+fn default_netwatch_interval() -> u64 {
+    2
+}
+
+fn default_netwatch_duration() -> u64 {
+    60
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetwatchAttributes {
+    // seconds between two samples
+    #[serde(default = "default_netwatch_interval")]
+    pub interval: u64,
+    // sampling duration in seconds
+    #[serde(default = "default_netwatch_duration")]
+    pub duration: u64,
+}
+
+fn default_load_hives() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegistryAttributes {
+    // e.g. HKLM\SOFTWARE\Microsoft\Windows\CurrentVersion\Run, HKCU keys are queried for each user
+    pub keys: Vec<String>,
+    // levels of subkeys queried below each key, 0 only queries the key itself
+    #[serde(default)]
+    pub depth: u32,
+    // temporarily load the hives of users who are not logged in
+    #[serde(default = "default_load_hives")]
+    pub load_hives: bool,
+}
+
+fn default_store_content() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecycleBinAttributes {
+    // store the deleted files, the $I and .trashinfo files are always stored
+    #[serde(default = "default_store_content")]
+    pub store_content: bool,
+    // deleted files above the limit are only listed
+    #[serde(default = "default_size_limit")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_limit: u64,
+}
+
+fn default_collect_lnk() -> bool {
+    true
+}
+
+fn default_collect_jump_lists() -> bool {
+    true
+}
+
+fn default_collect_shellbags() -> bool {
+    true
+}
+
+fn default_store_files() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShellArtifactsAttributes {
+    // shortcuts of the recent items, the Office recent items and the desktop
+    #[serde(default = "default_collect_lnk")]
+    pub lnk: bool,
+    // automatic and custom Jump Lists
+    #[serde(default = "default_collect_jump_lists")]
+    pub jump_lists: bool,
+    // BagMRU keys of NTUSER.DAT and UsrClass.dat
+    #[serde(default = "default_collect_shellbags")]
+    pub shellbags: bool,
+    // temporarily load the hives of users who are not logged in for the ShellBags
+    #[serde(default = "default_load_hives")]
+    pub load_hives: bool,
+    // store the LNK and Jump List files in addition to the parsed records
+    #[serde(default = "default_store_files")]
+    pub store_files: bool,
+}
+
+fn default_check_dns_cache() -> bool {
+    true
+}
+
+fn default_check_hosts_file() -> bool {
+    true
+}
+
+fn default_lookup_timeout() -> i32 {
+    5
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DnsIocAttributes {
+    // domains and IP addresses, one per line
+    #[serde(default)]
+    pub iocs: String,
+    // IOC lists with one domain or IP address per line, relative to custom_files
+    #[serde(default)]
+    pub ioc_files: String,
+    #[serde(default = "default_check_dns_cache")]
+    pub dns_cache: bool,
+    #[serde(default = "default_check_hosts_file")]
+    pub hosts_file: bool,
+    // resolve the domains, the queries reach the DNS servers and possibly the attacker
+    #[serde(default)]
+    pub lookups: bool,
+    // per domain
+    #[serde(default = "default_lookup_timeout")]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub lookup_timeout: i32,
+}
+
+fn default_grep_context() -> usize {
+    32
+}
+
+fn default_grep_max_hits() -> usize {
+    100
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrepAttributes {
+    // files to search, one pattern per line
+    pub patterns: String,
+    // literal keywords and regular expressions, one per line
+    #[serde(default)]
+    pub keywords: String,
+    #[serde(default)]
+    pub regexes: String,
+    // IOC packs with one keyword or regular expression per line, relative to custom_files
+    #[serde(default)]
+    pub keyword_files: String,
+    #[serde(default)]
+    pub regex_files: String,
+    #[serde(default = "default_case_sensitive")]
+    pub case_sensitive: bool,
+    // bytes before and after a hit written to the output
+    #[serde(default = "default_grep_context")]
+    pub context: usize,
+    #[serde(default = "default_grep_max_hits")]
+    pub max_hits_per_file: usize,
+    #[serde(default = "default_size_limit")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_limit: u64,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    #[serde(default)]
+    pub store_on_match: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EtwTraceMode {
+    // start the session, wait for the duration and stop it again
+    #[default]
+    Capture,
+    // start the session and leave it running until a later stop step
+    Start,
+    // stop a session started by an earlier step and store the trace
+    Stop,
+}
+
+fn default_etw_session_name() -> String {
+    "irt_trace".to_string()
+}
+
+fn default_etw_duration() -> u64 {
+    60
+}
+
+fn default_etw_max_size() -> u32 {
+    256
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EtwTraceAttributes {
+    #[serde(default)]
+    pub mode: EtwTraceMode,
+    #[serde(default = "default_etw_session_name")]
+    pub session_name: String,
+    // provider names, GUIDs or one of the presets (dns, process, network, file, registry, powershell)
+    #[serde(default)]
+    pub providers: Vec<String>,
+    // capture duration in seconds (capture mode only)
+    #[serde(default = "default_etw_duration")]
+    pub duration: u64,
+    // maximum size of the trace file in MB
+    #[serde(default = "default_etw_max_size")]
+    pub max_size: u32,
+}
+
+fn default_pcap_duration() -> u64 {
+    60
+}
+
+fn default_pcap_rotate_size() -> u64 {
+    100_000_000
+}
+
+fn default_pcap_snaplen() -> i32 {
+    65535
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PcapAttributes {
+    // interfaces to capture on, the first available interface is used if empty
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    // BPF filter expression
+    #[serde(default)]
+    pub filter: String,
+    // capture duration in seconds
+    #[serde(default = "default_pcap_duration")]
+    pub duration: u64,
+    // start a new file once the current one exceeds this size
+    #[serde(default = "default_pcap_rotate_size")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub rotate_size: u64,
+    // stop capturing on an interface once all its files exceed this size (0 = unlimited)
+    #[serde(default = "default_size_limit")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_limit: u64,
+    #[serde(default = "default_pcap_snaplen")]
+    pub snaplen: i32,
+    #[serde(default)]
+    pub promiscuous: bool,
+}
+
+fn default_memory_file_types() -> Vec<String> {
+    vec![
+        "pagefile".to_string(),
+        "hiberfil".to_string(),
+        "swap".to_string(),
+    ]
+}
+
+fn default_memory_files_size_warning() -> u64 {
+    8_000_000_000
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemoryFilesAttributes {
+    // pagefile, hiberfil and/or swap
+    #[serde(default = "default_memory_file_types")]
+    pub types: Vec<String>,
+    // additional files or devices to collect
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // warn about files exceeding this size
+    #[serde(default = "default_memory_files_size_warning")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_warning: u64,
+    // skip files exceeding this size (0 = unlimited)
+    #[serde(default = "default_size_limit")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_limit: u64,
+    // gzip the files before they are stored
+    #[serde(default)]
+    pub compress: bool,
+}
+
+fn default_memory_image_source() -> String {
+    "auto".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemoryImageAttributes {
+    // auto, kcore or crash
+    #[serde(default = "default_memory_image_source")]
+    pub source: String,
+    // don't acquire if the physical memory exceeds this size (0 = unlimited)
+    #[serde(default = "default_size_limit")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_limit: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DefenderAttributes {
+    // temporarily exclude the paths from scanning, removed once the workflow has finished
+    #[serde(default)]
+    pub exclude: bool,
+    // paths to exclude, the custom_files directory if empty
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // write the EICAR test file to the custom_files directory to see if it gets quarantined
+    #[serde(default)]
+    pub eicar_test: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -208,6 +871,22 @@ pub enum ActionAttributes {
     Store(StoreAttributes),
     Terminal(TerminalAttributes),
     Yara(YaraAttributes),
+    WmiPersistence(WmiPersistenceAttributes),
+    FsSnapshot(FsSnapshotAttributes),
+    NetworkConfig(NetworkConfigAttributes),
+    UsbHistory(UsbHistoryAttributes),
+    EtwTrace(EtwTraceAttributes),
+    Pcap(PcapAttributes),
+    MemoryFiles(MemoryFilesAttributes),
+    MemoryImage(MemoryImageAttributes),
+    Defender(DefenderAttributes),
+    SshArtifacts(SshArtifactsAttributes),
+    Netwatch(NetwatchAttributes),
+    Grep(GrepAttributes),
+    Registry(RegistryAttributes),
+    RecycleBin(RecycleBinAttributes),
+    ShellArtifacts(ShellArtifactsAttributes),
+    DnsIoc(DnsIocAttributes),
 }
 
 fn replace_in_value(value: Value, variables: &HashMap<String, String>) -> Value {
@@ -238,11 +917,110 @@ fn replace_in_value(value: Value, variables: &HashMap<String, String>) -> Value
 }
 
 impl ActionAttributes {
+    // The attributes are parsed based on the action type, as the untagged representation
+    // can't tell apart attribute sets that share (or only have optional) fields
+    pub fn from_value(action_type: ActionType, value: Value) -> Result<Self, serde_yaml::Error> {
+        Ok(match action_type {
+            ActionType::Binary => ActionAttributes::Binary(serde_yaml::from_value(value)?),
+            ActionType::Command => ActionAttributes::Command(serde_yaml::from_value(value)?),
+            ActionType::Store => ActionAttributes::Store(serde_yaml::from_value(value)?),
+            ActionType::Terminal => ActionAttributes::Terminal(serde_yaml::from_value(value)?),
+            ActionType::Yara => ActionAttributes::Yara(serde_yaml::from_value(value)?),
+            ActionType::WmiPersistence => {
+                ActionAttributes::WmiPersistence(serde_yaml::from_value(value)?)
+            }
+            ActionType::FsSnapshot => ActionAttributes::FsSnapshot(serde_yaml::from_value(value)?),
+            ActionType::NetworkConfig => {
+                ActionAttributes::NetworkConfig(serde_yaml::from_value(value)?)
+            }
+            ActionType::UsbHistory => ActionAttributes::UsbHistory(serde_yaml::from_value(value)?),
+            ActionType::EtwTrace => ActionAttributes::EtwTrace(serde_yaml::from_value(value)?),
+            ActionType::Pcap => ActionAttributes::Pcap(serde_yaml::from_value(value)?),
+            ActionType::MemoryFiles => {
+                ActionAttributes::MemoryFiles(serde_yaml::from_value(value)?)
+            }
+            ActionType::MemoryImage => {
+                ActionAttributes::MemoryImage(serde_yaml::from_value(value)?)
+            }
+            ActionType::Defender => ActionAttributes::Defender(serde_yaml::from_value(value)?),
+            ActionType::SshArtifacts => {
+                ActionAttributes::SshArtifacts(serde_yaml::from_value(value)?)
+            }
+            ActionType::Netwatch => ActionAttributes::Netwatch(serde_yaml::from_value(value)?),
+            ActionType::Grep => ActionAttributes::Grep(serde_yaml::from_value(value)?),
+            ActionType::Registry => ActionAttributes::Registry(serde_yaml::from_value(value)?),
+            ActionType::RecycleBin => ActionAttributes::RecycleBin(serde_yaml::from_value(value)?),
+            ActionType::ShellArtifacts => {
+                ActionAttributes::ShellArtifacts(serde_yaml::from_value(value)?)
+            }
+            ActionType::DnsIoc => ActionAttributes::DnsIoc(serde_yaml::from_value(value)?),
+        })
+    }
+
+    pub fn action_type(&self) -> ActionType {
+        match self {
+            ActionAttributes::Binary(_) => ActionType::Binary,
+            ActionAttributes::Command(_) => ActionType::Command,
+            ActionAttributes::Store(_) => ActionType::Store,
+            ActionAttributes::Terminal(_) => ActionType::Terminal,
+            ActionAttributes::Yara(_) => ActionType::Yara,
+            ActionAttributes::WmiPersistence(_) => ActionType::WmiPersistence,
+            ActionAttributes::FsSnapshot(_) => ActionType::FsSnapshot,
+            ActionAttributes::NetworkConfig(_) => ActionType::NetworkConfig,
+            ActionAttributes::UsbHistory(_) => ActionType::UsbHistory,
+            ActionAttributes::EtwTrace(_) => ActionType::EtwTrace,
+            ActionAttributes::Pcap(_) => ActionType::Pcap,
+            ActionAttributes::MemoryFiles(_) => ActionType::MemoryFiles,
+            ActionAttributes::MemoryImage(_) => ActionType::MemoryImage,
+            ActionAttributes::Defender(_) => ActionType::Defender,
+            ActionAttributes::SshArtifacts(_) => ActionType::SshArtifacts,
+            ActionAttributes::Netwatch(_) => ActionType::Netwatch,
+            ActionAttributes::Grep(_) => ActionType::Grep,
+            ActionAttributes::Registry(_) => ActionType::Registry,
+            ActionAttributes::RecycleBin(_) => ActionType::RecycleBin,
+            ActionAttributes::ShellArtifacts(_) => ActionType::ShellArtifacts,
+            ActionAttributes::DnsIoc(_) => ActionType::DnsIoc,
+        }
+    }
+
     pub fn replace_vars(&mut self, variables: &HashMap<String, String>) {
         let cloned_self = self.clone();
         let value = serde_yaml::to_value(cloned_self).unwrap();
         let updated_value = replace_in_value(value, variables);
-        *self = serde_yaml::from_value(updated_value).unwrap();
+        *self = ActionAttributes::from_value(self.action_type(), updated_value).unwrap();
+    }
+
+    /// Uses the separator of the platform in the patterns and paths, so a workflow can be
+    /// written with `/` or `\` no matter where it runs
+    pub fn normalize_paths(&mut self, windows: bool) {
+        let lines = |text: &mut String| *text = normalize_separators_lines(text, windows);
+        let paths = |paths: &mut Vec<String>| {
+            for path in paths.iter_mut() {
+                *path = normalize_separators(path, windows);
+            }
+        };
+        match self {
+            ActionAttributes::Store(store) => lines(&mut store.patterns),
+            ActionAttributes::Yara(yara) => {
+                lines(&mut yara.rules_paths);
+                lines(&mut yara.files_to_scan);
+            }
+            ActionAttributes::FsSnapshot(snapshot) => {
+                lines(&mut snapshot.patterns);
+                lines(&mut snapshot.baseline);
+            }
+            ActionAttributes::Grep(grep) => {
+                lines(&mut grep.patterns);
+                lines(&mut grep.keyword_files);
+                lines(&mut grep.regex_files);
+            }
+            ActionAttributes::DnsIoc(dns_ioc) => lines(&mut dns_ioc.ioc_files),
+            ActionAttributes::MemoryFiles(memory_files) => paths(&mut memory_files.paths),
+            ActionAttributes::Defender(defender) => paths(&mut defender.paths),
+            ActionAttributes::Binary(binary) => paths(&mut binary.sandbox.writable_paths),
+            ActionAttributes::Command(command) => paths(&mut command.sandbox.writable_paths),
+            _ => {}
+        }
     }
 }
 
@@ -287,36 +1065,245 @@ impl Into<YaraAttributes> for ActionAttributes {
         }
     }
 }
+impl Into<WmiPersistenceAttributes> for ActionAttributes {
+    fn into(self) -> WmiPersistenceAttributes {
+        match self {
+            ActionAttributes::WmiPersistence(wmi) => wmi,
+            _ => panic!("ActionAttributes is not WmiPersistence"),
+        }
+    }
+}
+impl Into<FsSnapshotAttributes> for ActionAttributes {
+    fn into(self) -> FsSnapshotAttributes {
+        match self {
+            ActionAttributes::FsSnapshot(snapshot) => snapshot,
+            _ => panic!("ActionAttributes is not FsSnapshot"),
+        }
+    }
+}
 
-#[derive(Debug, Deserialize)]
+impl Into<NetworkConfigAttributes> for ActionAttributes {
+    fn into(self) -> NetworkConfigAttributes {
+        match self {
+            ActionAttributes::NetworkConfig(network) => network,
+            _ => panic!("ActionAttributes is not NetworkConfig"),
+        }
+    }
+}
+
+impl Into<UsbHistoryAttributes> for ActionAttributes {
+    fn into(self) -> UsbHistoryAttributes {
+        match self {
+            ActionAttributes::UsbHistory(usb) => usb,
+            _ => panic!("ActionAttributes is not UsbHistory"),
+        }
+    }
+}
+
+impl Into<EtwTraceAttributes> for ActionAttributes {
+    fn into(self) -> EtwTraceAttributes {
+        match self {
+            ActionAttributes::EtwTrace(etw) => etw,
+            _ => panic!("ActionAttributes is not EtwTrace"),
+        }
+    }
+}
+
+impl Into<PcapAttributes> for ActionAttributes {
+    fn into(self) -> PcapAttributes {
+        match self {
+            ActionAttributes::Pcap(pcap) => pcap,
+            _ => panic!("ActionAttributes is not Pcap"),
+        }
+    }
+}
+
+impl Into<MemoryFilesAttributes> for ActionAttributes {
+    fn into(self) -> MemoryFilesAttributes {
+        match self {
+            ActionAttributes::MemoryFiles(memory) => memory,
+            _ => panic!("ActionAttributes is not MemoryFiles"),
+        }
+    }
+}
+
+impl Into<MemoryImageAttributes> for ActionAttributes {
+    fn into(self) -> MemoryImageAttributes {
+        match self {
+            ActionAttributes::MemoryImage(memory) => memory,
+            _ => panic!("ActionAttributes is not MemoryImage"),
+        }
+    }
+}
+impl Into<DefenderAttributes> for ActionAttributes {
+    fn into(self) -> DefenderAttributes {
+        match self {
+            ActionAttributes::Defender(defender) => defender,
+            _ => panic!("ActionAttributes is not Defender"),
+        }
+    }
+}
+
+impl Into<SshArtifactsAttributes> for ActionAttributes {
+    fn into(self) -> SshArtifactsAttributes {
+        match self {
+            ActionAttributes::SshArtifacts(ssh) => ssh,
+            _ => panic!("ActionAttributes is not SshArtifacts"),
+        }
+    }
+}
+
+impl Into<NetwatchAttributes> for ActionAttributes {
+    fn into(self) -> NetwatchAttributes {
+        match self {
+            ActionAttributes::Netwatch(netwatch) => netwatch,
+            _ => panic!("ActionAttributes is not Netwatch"),
+        }
+    }
+}
+
+impl Into<GrepAttributes> for ActionAttributes {
+    fn into(self) -> GrepAttributes {
+        match self {
+            ActionAttributes::Grep(grep) => grep,
+            _ => panic!("ActionAttributes is not Grep"),
+        }
+    }
+}
+
+impl Into<RegistryAttributes> for ActionAttributes {
+    fn into(self) -> RegistryAttributes {
+        match self {
+            ActionAttributes::Registry(registry) => registry,
+            _ => panic!("ActionAttributes is not Registry"),
+        }
+    }
+}
+
+impl Into<RecycleBinAttributes> for ActionAttributes {
+    fn into(self) -> RecycleBinAttributes {
+        match self {
+            ActionAttributes::RecycleBin(recycle_bin) => recycle_bin,
+            _ => panic!("ActionAttributes is not RecycleBin"),
+        }
+    }
+}
+
+impl Into<ShellArtifactsAttributes> for ActionAttributes {
+    fn into(self) -> ShellArtifactsAttributes {
+        match self {
+            ActionAttributes::ShellArtifacts(shell_artifacts) => shell_artifacts,
+            _ => panic!("ActionAttributes is not ShellArtifacts"),
+        }
+    }
+}
+
+impl Into<DnsIocAttributes> for ActionAttributes {
+    fn into(self) -> DnsIocAttributes {
+        match self {
+            ActionAttributes::DnsIoc(dns_ioc) => dns_ioc,
+            _ => panic!("ActionAttributes is not DnsIoc"),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Action {
-    #[serde(default)]
     pub name: String,
-    #[serde(rename = "type")]
-    #[serde(deserialize_with = "deserialize_action")]
     pub action_type: ActionType,
     pub attributes: ActionAttributes,
+    // file name used for the outputs in action_output, defaults to the action name
+    pub output_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Action {
+    // The (sanitized) base name of the files this action writes to action_output
+    pub fn output_base_name(&self) -> String {
+        sanitize_dirname(self.output_name.as_ref().unwrap_or(&self.name))
+    }
+}
+
+#[derive(Deserialize)]
+struct RawAction {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    #[serde(deserialize_with = "deserialize_action")]
+    action_type: ActionType,
+    attributes: Value,
+    #[serde(default)]
+    output_name: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = RawAction::deserialize(deserializer)?;
+        let attributes =
+            ActionAttributes::from_value(raw.action_type, raw.attributes).map_err(|e| {
+                serde::de::Error::custom(format!(
+                    "Invalid attributes for {} action {:?}: {}",
+                    raw.action_type, raw.name, e
+                ))
+            })?;
+
+        Ok(Action {
+            name: raw.name,
+            action_type: raw.action_type,
+            attributes,
+            output_name: raw.output_name,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct Reporting {
     pub zip_archive: ReportingZipArchive,
     pub metadata: ReportingMetadata,
+    #[serde(default)]
+    pub delta: ReportingDelta,
+    #[serde(default)]
+    pub dedup: ReportingDedup,
+    #[serde(default)]
+    pub triage: ReportingTriage,
+    #[serde(default)]
+    pub heartbeat: ReportingHeartbeat,
+    #[serde(default)]
+    pub yara_sweep: ReportingYaraSweep,
 }
 impl Default for Reporting {
     fn default() -> Self {
         Self {
             zip_archive: ReportingZipArchive::default(),
             metadata: ReportingMetadata::default(),
+            delta: ReportingDelta::default(),
+            dedup: ReportingDedup::default(),
+            triage: ReportingTriage::default(),
+            heartbeat: ReportingHeartbeat::default(),
+            yara_sweep: ReportingYaraSweep::default(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReportingZipArchive {
     pub enabled: bool,
     pub encryption: ReportingEncryption,
     pub compression: ReportingCompression,
+    // keep files of the report directory after they were added to the archive
+    #[serde(default)]
+    pub keep_plaintext: bool,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    // re-read the finished archive before it is encrypted
+    #[serde(default)]
+    pub self_test: ArchiveSelfTest,
+    #[serde(default)]
+    pub entry_order: ReportingEntryOrder,
 }
 impl Default for ReportingZipArchive {
     fn default() -> Self {
@@ -324,6 +1311,53 @@ impl Default for ReportingZipArchive {
             enabled: true,
             encryption: ReportingEncryption::default(),
             compression: ReportingCompression::default(),
+            keep_plaintext: false,
+            format: ArchiveFormat::default(),
+            self_test: ArchiveSelfTest::default(),
+            entry_order: ReportingEntryOrder::default(),
+        }
+    }
+}
+
+/// How much of the finished archive is read back to verify the CRC of its entries
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveSelfTest {
+    #[default]
+    Off,
+    // the central directory and a sample of the entries, spread over the archive
+    Sample,
+    Full,
+}
+
+/// Container of the evidence, for tools which can't read zstd inside a zip archive
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case", try_from = "String")]
+pub enum ArchiveFormat {
+    // zip archive with entries compressed by zstd
+    #[default]
+    Zip,
+    // zip archive with entries compressed by deflate, readable by almost any tool
+    ZipDeflate,
+    // tar archive compressed by zstd as a whole
+    TarZstd,
+    // 7z archive with entries compressed by LZMA2
+    SevenZip,
+}
+
+impl TryFrom<String> for ArchiveFormat {
+    type Error = String;
+
+    fn try_from(format: String) -> Result<Self, Self::Error> {
+        match format.as_str() {
+            "zip" => Ok(Self::Zip),
+            "zip_deflate" => Ok(Self::ZipDeflate),
+            "tar_zstd" => Ok(Self::TarZstd),
+            "7z" => Ok(Self::SevenZip),
+            _ => Err(format!(
+                "Unknown archive format {:?}: expected zip, zip_deflate, tar_zstd or 7z",
+                format
+            )),
         }
     }
 }
@@ -378,11 +1412,17 @@ impl Algorithm {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReportingEncryption {
     pub enabled: bool,
     pub public_key: String,
     pub algorithm: Algorithm,
+    // action logs are encrypted with the session key while they are written
+    #[serde(default)]
+    pub encrypt_action_output: bool,
+    // metadata.csv and the log of the workflow only exist inside the encrypted archive
+    #[serde(default)]
+    pub seal_sidecars: bool,
 }
 impl Default for ReportingEncryption {
     fn default() -> Self {
@@ -390,17 +1430,59 @@ impl Default for ReportingEncryption {
             enabled: false,
             public_key: "".to_string(),
             algorithm: Algorithm::None,
+            encrypt_action_output: false,
+            seal_sidecars: false,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// archives and formats based on them, media and encrypted files are already compressed,
+// compressing them again only costs CPU time
+const INCOMPRESSIBLE_ARCHIVES: &[&str] = &[
+    "zip", "7z", "rar", "gz", "tgz", "bz2", "xz", "zst", "lz4", "cab", "jar", "apk", "docx",
+    "xlsx", "pptx", "odt", "ods", "e01",
+];
+const INCOMPRESSIBLE_MEDIA: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "mp3", "m4a", "aac", "ogg", "flac", "mp4", "mkv",
+    "avi", "mov", "wmv", "webm",
+];
+const INCOMPRESSIBLE_ENCRYPTED: &[&str] = &["gpg", "pgp", "age", "kdbx"];
+
+fn default_exclude_extensions() -> Vec<String> {
+    [
+        INCOMPRESSIBLE_ARCHIVES,
+        INCOMPRESSIBLE_MEDIA,
+        INCOMPRESSIBLE_ENCRYPTED,
+    ]
+    .concat()
+    .iter()
+    .map(|ext| ext.to_string())
+    .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReportingCompression {
     pub enabled: bool,
     #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
     pub size_limit: u64,
+    // files with these extensions are stored without compression, replaces the built-in list
+    #[serde(default = "default_exclude_extensions")]
+    pub exclude_extensions: Vec<String>,
+}
+
+impl ReportingCompression {
+    /// Whether the file is stored without compression, by its extension (case-insensitive)
+    pub fn is_excluded(&self, path: &std::path::Path) -> bool {
+        let Some(ext) = path.extension().map(|ext| ext.to_string_lossy()) else {
+            return false;
+        };
+        self.exclude_extensions
+            .iter()
+            .any(|excluded| excluded.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+    }
 }
-fn deserialize_size_limit<'de, D>(deserializer: D) -> Result<u64, D::Error>
+pub(crate) fn deserialize_size_limit<'de, D>(deserializer: D) -> Result<u64, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -411,7 +1493,7 @@ where
         Err(_) => Err(serde::de::Error::custom("Invalid size limit")),
     }
 }
-fn serialize_size_limit<S>(value: &u64, serializer: S) -> Result<S::Ok, S::Error>
+pub(crate) fn serialize_size_limit<S>(value: &u64, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
@@ -424,22 +1506,173 @@ impl Default for ReportingCompression {
         Self {
             enabled: false,
             size_limit: 0,
+            exclude_extensions: default_exclude_extensions(),
+        }
+    }
+}
+
+fn default_large_file_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_alignment() -> u16 {
+    4096
+}
+
+/// Order of the archive entries: the metadata, the action output and the small files first,
+/// so they can be read from a partial download or with a few range requests
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportingEntryOrder {
+    #[serde(default)]
+    pub enabled: bool,
+    // stored files larger than this are added last, without compression and aligned
+    #[serde(default = "default_large_file_size")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub large_file_size: u64,
+    // of the data of the large files, in bytes
+    #[serde(default = "default_alignment")]
+    pub alignment: u16,
+}
+
+impl Default for ReportingEntryOrder {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            large_file_size: default_large_file_size(),
+            alignment: default_alignment(),
+        }
+    }
+}
+
+fn default_flush_records() -> usize {
+    1
+}
+
+/// When the records of the metadata.csv are written to the disk.
+/// The file is always flushed once an action has finished.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetadataFlush {
+    // flush after this many records (0 = disabled)
+    #[serde(default = "default_flush_records")]
+    pub records: usize,
+    // flush if the last flush was longer ago (0 = disabled)
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub interval: i32,
+}
+impl Default for MetadataFlush {
+    fn default() -> Self {
+        Self {
+            records: default_flush_records(),
+            interval: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportingMetadata {
+    pub mac_times: bool,
+    pub checksums: bool,
+    pub paths: bool,
+    #[serde(default)]
+    pub flush: MetadataFlush,
+    // restores the access time of stored files after they were read
+    #[serde(default)]
+    pub preserve_atime: bool,
+}
+impl Default for ReportingMetadata {
+    fn default() -> Self {
+        Self {
+            mac_times: false,
+            checksums: false,
+            paths: false,
+            flush: MetadataFlush::default(),
+            preserve_atime: false,
+        }
+    }
+}
+
+/// Incremental collection based on the metadata.csv of a previous report
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReportingDelta {
+    // path to the previous metadata.csv, relative paths are relative to the root directory of the toolkit
+    #[serde(default)]
+    pub baseline: String,
+}
+
+fn default_dedup_cache() -> String {
+    "dedup_cache.txt".to_string()
+}
+
+/// Findings ranked by severity, based on the rules of a YAML file
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReportingTriage {
+    // path to the rules, relative paths are relative to the root directory of the toolkit. Disabled if empty
+    #[serde(default)]
+    pub rules: String,
+}
+
+/// Progress of a running collection, written to heartbeat.json in the report directory
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReportingHeartbeat {
+    // seconds between two updates (0 = disabled)
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub interval: i32,
+}
+
+fn default_sweep_size_limit() -> u64 {
+    256_000_000
+}
+
+fn default_sweep_timeout() -> i32 {
+    60
+}
+
+/// YARA scan of everything collected by the workflow, once the archive is complete
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportingYaraSweep {
+    // rule files (one glob pattern per line), relative paths are relative to the custom_files directory. Disabled if empty
+    #[serde(default)]
+    pub rules: String,
+    // entries are scanned in memory, larger ones are skipped (0 = unlimited)
+    #[serde(default = "default_sweep_size_limit")]
+    #[serde(deserialize_with = "deserialize_size_limit")]
+    #[serde(serialize_with = "serialize_size_limit")]
+    pub size_limit: u64,
+    // per entry
+    #[serde(default = "default_sweep_timeout")]
+    #[serde(deserialize_with = "deserialize_timeout")]
+    #[serde(serialize_with = "serialize_timeout")]
+    pub timeout: i32,
+}
+impl Default for ReportingYaraSweep {
+    fn default() -> Self {
+        Self {
+            rules: String::new(),
+            size_limit: default_sweep_size_limit(),
+            timeout: default_sweep_timeout(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct ReportingMetadata {
-    pub mac_times: bool,
-    pub checksums: bool,
-    pub paths: bool,
+/// Content-addressed storage shared by all reports collected with the same toolkit
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportingDedup {
+    #[serde(default)]
+    pub enabled: bool,
+    // checksums of files collected by previous reports, relative paths are relative to the root directory of the toolkit
+    #[serde(default = "default_dedup_cache")]
+    pub cache: String,
 }
-impl Default for ReportingMetadata {
+impl Default for ReportingDedup {
     fn default() -> Self {
         Self {
-            mac_times: false,
-            checksums: false,
-            paths: false,
+            enabled: false,
+            cache: default_dedup_cache(),
         }
     }
 }
@@ -450,6 +1683,9 @@ pub enum OnError {
     Goto { goto: String },
     #[serde(rename = "abort")]
     Abort,
+    // stop the workflow, but finish (archive and encrypt) the report and mark it as aborted
+    #[serde(rename = "abort_and_finalize")]
+    AbortAndFinalize,
     #[serde(rename = "continue")]
     Continue,
 }
@@ -459,6 +1695,7 @@ impl PartialEq for OnError {
         match (self, other) {
             (OnError::Goto { goto: a }, OnError::Goto { goto: b }) => a == b,
             (OnError::Abort, OnError::Abort) => true,
+            (OnError::AbortAndFinalize, OnError::AbortAndFinalize) => true,
             (OnError::Continue, OnError::Continue) => true,
             _ => false,
         }
@@ -469,6 +1706,44 @@ fn default_on_error() -> OnError {
     OnError::Continue
 }
 
+/// Why an action failed, used to pick the on_error handler of a step
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum ErrorCategory {
+    #[serde(rename = "timeout")]
+    Timeout,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_denied")]
+    PermissionDenied,
+    #[serde(rename = "nonzero_exit")]
+    NonzeroExit,
+    #[serde(rename = "other")]
+    Other,
+}
+
+impl ErrorCategory {
+    pub fn from_io_error(error: &std::io::Error) -> ErrorCategory {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => ErrorCategory::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorCategory::PermissionDenied,
+            std::io::ErrorKind::TimedOut => ErrorCategory::Timeout,
+            _ => ErrorCategory::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErrorCategory::Timeout => write!(f, "timeout"),
+            ErrorCategory::NotFound => write!(f, "not_found"),
+            ErrorCategory::PermissionDenied => write!(f, "permission_denied"),
+            ErrorCategory::NonzeroExit => write!(f, "nonzero_exit"),
+            ErrorCategory::Other => write!(f, "other"),
+        }
+    }
+}
+
 fn default_parallel() -> bool {
     false
 }
@@ -483,6 +1758,19 @@ pub struct WorkflowItem {
     #[serde(default = "default_on_error")]
     #[serde(deserialize_with = "deserialize_on_error")]
     pub on_error: OnError,
+    // handlers for specific error categories, falling back to on_error
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_optional_on_error")]
+    pub on_timeout: Option<OnError>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_optional_on_error")]
+    pub on_not_found: Option<OnError>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_optional_on_error")]
+    pub on_permission_denied: Option<OnError>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_optional_on_error")]
+    pub on_nonzero_exit: Option<OnError>,
     #[serde(default = "default_parallel")]
     pub parallel: bool,
     #[serde(default = "default_timeout")]
@@ -491,6 +1779,19 @@ pub struct WorkflowItem {
     pub timeout: i32,
     #[serde(default)]
     pub continue_after_keypress: bool,
+    // run the started process as another user (e.g. logged_in_user) instead of the elevated user
+    #[serde(default)]
+    pub run_as: String,
+    // run the step once per element of a list variable (e.g. ${USER_PROFILES})
+    #[serde(default)]
+    pub for_each: String,
+    // expected runtime of the step, see Schedule::QuickFirst
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_optional_timeout")]
+    pub estimated_duration: Option<i32>,
+    // actions which must have run before this step, even if the schedule moves it
+    #[serde(default)]
+    pub after: Vec<String>,
 }
 
 fn deserialize_on_error<'de, D>(deserializer: D) -> Result<OnError, D::Error>
@@ -501,6 +1802,7 @@ where
     if let Some(s) = value.as_str() {
         match s {
             "abort" => return Ok(OnError::Abort),
+            "abort_and_finalize" => return Ok(OnError::AbortAndFinalize),
             "continue" => return Ok(OnError::Continue),
             _ => {}
         }
@@ -516,6 +1818,40 @@ where
     Err(serde::de::Error::custom("Invalid OnError value"))
 }
 
+fn deserialize_optional_on_error<'de, D>(deserializer: D) -> Result<Option<OnError>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_on_error(deserializer).map(Some)
+}
+
+impl WorkflowItem {
+    /// The handler for a failed step: the one set for the error category or on_error
+    pub fn on_error_for(&self, category: Option<ErrorCategory>) -> &OnError {
+        let handler = match category {
+            Some(ErrorCategory::Timeout) => &self.on_timeout,
+            Some(ErrorCategory::NotFound) => &self.on_not_found,
+            Some(ErrorCategory::PermissionDenied) => &self.on_permission_denied,
+            Some(ErrorCategory::NonzeroExit) => &self.on_nonzero_exit,
+            Some(ErrorCategory::Other) | None => &None,
+        };
+        handler.as_ref().unwrap_or(&self.on_error)
+    }
+
+    fn has_custom_on_error(&self) -> bool {
+        [
+            Some(&self.on_error),
+            self.on_timeout.as_ref(),
+            self.on_not_found.as_ref(),
+            self.on_permission_denied.as_ref(),
+            self.on_nonzero_exit.as_ref(),
+        ]
+        .iter()
+        .flatten()
+        .any(|handler| **handler != OnError::Continue)
+    }
+}
+
 fn deserialize_action<'de, D>(deserializer: D) -> Result<ActionType, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -528,17 +1864,262 @@ where
         "store" => Ok(ActionType::Store),
         "yara" => Ok(ActionType::Yara),
         "terminal" => Ok(ActionType::Terminal),
+        "wmi_persistence" => Ok(ActionType::WmiPersistence),
+        "fs_snapshot" => Ok(ActionType::FsSnapshot),
+        "network_config" => Ok(ActionType::NetworkConfig),
+        "usb_history" => Ok(ActionType::UsbHistory),
+        "etw_trace" => Ok(ActionType::EtwTrace),
+        "pcap" => Ok(ActionType::Pcap),
+        "memory_files" => Ok(ActionType::MemoryFiles),
+        "memory_image" => Ok(ActionType::MemoryImage),
+        "defender" => Ok(ActionType::Defender),
+        "ssh_artifacts" => Ok(ActionType::SshArtifacts),
+        "netwatch" => Ok(ActionType::Netwatch),
+        "grep" => Ok(ActionType::Grep),
+        "registry" => Ok(ActionType::Registry),
+        "recycle_bin" => Ok(ActionType::RecycleBin),
+        "shell_artifacts" => Ok(ActionType::ShellArtifacts),
+        "dns_ioc" => Ok(ActionType::DnsIoc),
         _ => Err(serde::de::Error::custom("Invalid action type")),
     }
 }
 
 #[derive(Debug, Deserialize)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct WorkflowRunner {
+    // version of the workflow format, see SCHEMA_VERSION
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     pub properties: HashMap<String, String>,
     pub launch_conditions: LaunchConditions,
     pub actions: Vec<Action>,
     pub workflow: Vec<WorkflowItem>,
     pub reporting: Reporting,
+    #[serde(default)]
+    pub options: WorkflowOptions,
+    // available as variables, their values are redacted from all logs
+    #[serde(default)]
+    pub secrets: BTreeMap<String, Secret>,
+    // paths which are never collected, in addition to the exclusions of the config
+    #[serde(default)]
+    pub exclusions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WorkflowOptions {
+    // of the timestamps in the report, overrides the time_zone of the config
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    // parallel steps running at the same time, overrides max_parallel_actions of the config
+    #[serde(default)]
+    pub max_parallel_actions: Option<usize>,
+}
+
+/// Order in which the steps of a workflow run
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    #[default]
+    InOrder,
+    // steps with an estimated_duration are reordered, the shortest first, so volatile evidence
+    // is collected before long sweeps if the machine is powered off during the collection
+    QuickFirst,
+}
+
+/// Orders a run of movable steps: the shortest step whose `after` steps have run comes first.
+/// The steps of `after` always come earlier in the workflow, so the first step is always ready
+fn quick_first(steps: Vec<WorkflowItem>) -> Vec<WorkflowItem> {
+    let mut pending: Vec<(usize, WorkflowItem)> = steps.into_iter().enumerate().collect();
+    let mut ordered = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let next = pending
+            .iter()
+            .enumerate()
+            .filter(|(_, (index, item))| {
+                !pending.iter().any(|(other, dependency)| {
+                    other < index && item.after.contains(&dependency.action)
+                })
+            })
+            .min_by_key(|(_, (index, item))| (item.estimated_duration, *index))
+            .map(|(position, _)| position)
+            .unwrap_or(0);
+        ordered.push(pending.remove(next).1);
+    }
+    ordered
+}
+
+impl WorkflowOptions {
+    /// Time zone of the workflow, otherwise the given time zone of the config (UTC if invalid)
+    pub fn time_zone_or(&self, default: &str) -> Tz {
+        self.time_zone
+            .as_deref()
+            .unwrap_or(default)
+            .parse()
+            .unwrap_or(Tz::UTC)
+    }
+}
+
+/// The value of a secret or the environment variable to read it from
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Secret {
+    Env { env: String },
+    Value(String),
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String, String> {
+        match self {
+            Secret::Value(value) => Ok(value.clone()),
+            Secret::Env { env } => {
+                std::env::var(env).map_err(|_| format!("Environment variable {:?} is not set", env))
+            }
+        }
+    }
+}
+
+/// Resolves the secrets to variables. Secrets that can't be resolved are returned as errors
+pub fn resolve_secrets(
+    secrets: &BTreeMap<String, Secret>,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut variables = HashMap::new();
+    let mut errors = vec![];
+    for (name, secret) in secrets {
+        match secret.resolve() {
+            Ok(value) => {
+                variables.insert(name.clone(), value);
+            }
+            Err(e) => errors.push(format!("Error resolving secret {:?}: {}", name, e)),
+        }
+    }
+    (variables, errors)
+}
+
+// Workflows with a higher priority run first
+pub fn workflow_priority(properties: &HashMap<String, String>) -> i32 {
+    properties
+        .get("priority")
+        .and_then(|priority| priority.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Whether a workflow needs the collector to run with elevated privileges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Elevation {
+    // the collector is elevated for the workflow, which is skipped if the elevation is denied
+    Required,
+    // the collector is elevated for the workflow, which runs anyway if the elevation is denied
+    Preferred,
+    // the workflow doesn't need elevated privileges
+    Never,
+}
+
+impl Elevation {
+    pub fn parse(value: &str) -> Option<Elevation> {
+        match value.trim().to_lowercase().as_str() {
+            "required" => Some(Elevation::Required),
+            "preferred" => Some(Elevation::Preferred),
+            "never" => Some(Elevation::Never),
+            _ => None,
+        }
+    }
+}
+
+// Whether the workflow needs Full Disk Access on macOS, with the same levels as the elevation
+pub fn workflow_full_disk_access(properties: &HashMap<String, String>) -> Elevation {
+    properties
+        .get("full_disk_access")
+        .and_then(|full_disk_access| Elevation::parse(full_disk_access))
+        .unwrap_or(Elevation::Never)
+}
+
+// None if the workflow doesn't declare its elevation, so the elevate setting of the config applies
+pub fn workflow_elevation(properties: &HashMap<String, String>) -> Option<Elevation> {
+    properties
+        .get("elevation")
+        .and_then(|elevation| Elevation::parse(elevation))
+}
+
+/// Time of day in which a workflow may start, e.g. 22:00-06:00 (local time).
+/// Windows ending before they start span midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionWindow {
+    // minutes since midnight
+    pub start: u32,
+    pub end: u32,
+}
+
+fn parse_time_of_day(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+impl ExecutionWindow {
+    pub fn parse(value: &str) -> Option<ExecutionWindow> {
+        let (start, end) = value.split_once('-')?;
+        Some(ExecutionWindow {
+            start: parse_time_of_day(start)?,
+            end: parse_time_of_day(end)?,
+        })
+    }
+
+    pub fn contains(&self, minute: u32) -> bool {
+        match self.start <= self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+
+    /// Minutes until the window opens, 0 if it is open
+    pub fn minutes_until_open(&self, minute: u32) -> u32 {
+        match self.contains(minute) {
+            true => 0,
+            false => (self.start + 24 * 60 - minute) % (24 * 60),
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutionWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+pub fn workflow_window(properties: &HashMap<String, String>) -> Option<ExecutionWindow> {
+    properties
+        .get("window")
+        .and_then(|window| ExecutionWindow::parse(window))
+}
+
+// Time to wait before the workflow is started, e.g. 30m
+pub fn workflow_start_delay(properties: &HashMap<String, String>) -> Option<std::time::Duration> {
+    properties
+        .get("start_delay")
+        .and_then(|delay| parse_duration(delay.trim()).ok())
+}
+
+// Titles or file names of the workflows that have to run before this one
+pub fn workflow_depends_on(properties: &HashMap<String, String>) -> Vec<String> {
+    match properties.get("depends_on") {
+        Some(depends_on) => depends_on
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        None => vec![],
+    }
 }
 
 impl WorkflowRunner {
@@ -555,6 +2136,52 @@ impl WorkflowRunner {
                 fatal = true;
             }
         }
+        if let Some(priority) = self.properties.get("priority") {
+            if priority.trim().parse::<i32>().is_err() {
+                conflicts.push(format!(
+                    "Property priority {:?} is not an integer: using the default priority",
+                    priority
+                ));
+                self.properties.remove("priority");
+            }
+        }
+        if let Some(elevation) = self.properties.get("elevation") {
+            if Elevation::parse(elevation).is_none() {
+                conflicts.push(format!(
+                    "Property elevation {:?} must be required, preferred or never: using the elevate setting of the config",
+                    elevation
+                ));
+                self.properties.remove("elevation");
+            }
+        }
+        if let Some(window) = self.properties.get("window") {
+            if ExecutionWindow::parse(window).is_none() {
+                conflicts.push(format!(
+                    "Property window {:?} must be formatted as HH:MM-HH:MM: the workflow may run at any time",
+                    window
+                ));
+                self.properties.remove("window");
+            }
+        }
+        if let Some(delay) = self.properties.get("start_delay") {
+            if parse_duration(delay.trim()).is_err() {
+                conflicts.push(format!(
+                    "Property start_delay {:?} is not a duration: starting the workflow immediately",
+                    delay
+                ));
+                self.properties.remove("start_delay");
+            }
+        }
+
+        if let Some(time_zone) = &self.options.time_zone {
+            if time_zone.parse::<Tz>().is_err() {
+                conflicts.push(format!(
+                    "Option time_zone {:?} is not a valid time zone: using the time zone of the config",
+                    time_zone
+                ));
+                self.options.time_zone = None;
+            }
+        }
 
         // Invalid LaunchConditions settings
         // if custom_command is set, either contains_any, contains_all or contains_regex must be set
@@ -603,9 +2230,20 @@ impl WorkflowRunner {
             );
             self.reporting.zip_archive.compression.enabled = false;
         }
+        // Action outputs are encrypted with the key of the archive
+        let encryption = &mut self.reporting.zip_archive.encryption;
+        if encryption.encrypt_action_output && !encryption.enabled {
+            conflicts.push(
+                "encrypt_action_output requires encryption to be enabled: disabling encrypt_action_output"
+                    .to_string(),
+            );
+            encryption.encrypt_action_output = false;
+        }
+        let encrypt_action_output = encryption.encrypt_action_output;
 
         // Invalid Action settings
         let mut action_names = HashMap::new();
+        let mut output_names: HashMap<String, String> = HashMap::new();
         for action in self.actions.iter_mut() {
             if action.action_type == ActionType::Terminal {
                 if let ActionAttributes::Terminal(ref mut terminal) = action.attributes {
@@ -627,6 +2265,11 @@ impl WorkflowRunner {
                         conflicts.push(format!("Action {:?} has enable_transcript set to true while not waiting for the terminal to close. Disabling transcript...", action.name));
                         terminal.enable_transcript = false;
                     }
+
+                    // The transcript is written by the shell itself
+                    if encrypt_action_output && terminal.enable_transcript {
+                        conflicts.push(format!("Action {:?} has enable_transcript set to true, but transcripts can't be encrypted while they are written. The transcript is only encrypted as part of the archive", action.name));
+                    }
                 }
             }
 
@@ -637,10 +2280,33 @@ impl WorkflowRunner {
             } else {
                 action_names.insert(action.name.clone(), ());
             }
+
+            // Different names might end up as the same file name in action_output
+            let output_name = action.output_base_name();
+            match output_names.get(&output_name) {
+                Some(other) if *other != action.name => {
+                    conflicts.push(format!("Actions {:?} and {:?} share the output name {:?}: the step number is appended to the output files of the one running later", other, action.name, output_name));
+                }
+                _ => {
+                    output_names.insert(output_name, action.name.clone());
+                }
+            }
         }
 
         // Invalid Workflow settings
+        let mut previous_actions: Vec<String> = vec![];
         for item in self.workflow.iter_mut() {
+            // Only earlier steps can be waited for, otherwise the steps could wait for each other
+            let (after, unknown): (Vec<String>, Vec<String>) = item
+                .after
+                .drain(..)
+                .partition(|action| previous_actions.contains(action));
+            for action in unknown {
+                conflicts.push(format!("Action {:?} should run after {:?}, which is not an earlier step of the workflow. Ignoring it...", item.action, action));
+            }
+            item.after = after;
+            previous_actions.push(item.action.clone());
+
             // If parallel is enabled we can't wait for a keypress
             if item.parallel && item.continue_after_keypress {
                 conflicts.push(format!("Action {:?} is set to run in parallel and wait for keypress at the same time. Disabling continue_after_keypress...", item.action));
@@ -686,10 +2352,38 @@ impl WorkflowRunner {
                         }
                     }
 
+                    // Only started processes can be run as another user
+                    if !item.run_as.is_empty()
+                        && !run_as_action_types().contains(&action.action_type)
+                    {
+                        conflicts.push(format!("Action {:?} has run_as set, but only binary and command actions can run as another user. Ignoring run_as...", action.name));
+                        item.run_as.clear();
+                    }
+
+                    // Steps can only iterate over list variables
+                    if !item.for_each.is_empty() {
+                        let variable = item
+                            .for_each
+                            .trim()
+                            .trim_start_matches("${")
+                            .trim_end_matches('}')
+                            .to_string();
+                        if variable == USER_PROFILES {
+                            item.for_each = variable;
+                        } else {
+                            conflicts.push(format!("Action {:?} iterates over {:?}, but only ${{{}}} can be used with for_each. Ignoring for_each...", action.name, item.for_each, USER_PROFILES));
+                            item.for_each.clear();
+                        }
+                    }
+
                     // Parallel and custom on_error are not compatible
-                    if item.parallel && item.on_error != OnError::Continue {
+                    if item.parallel && item.has_custom_on_error() {
                         conflicts.push(format!("Action {:?} is set to run in parallel and has a custom on_error. Setting on_error to continue...", action.name));
                         item.on_error = OnError::Continue;
+                        item.on_timeout = None;
+                        item.on_not_found = None;
+                        item.on_permission_denied = None;
+                        item.on_nonzero_exit = None;
                     }
                 }
             }
@@ -723,18 +2417,129 @@ impl WorkflowRunner {
 
         Ok(())
     }
+
+    /// Reorders the steps if the workflow uses schedule: quick_first. Only steps with an
+    /// estimated_duration move: the other steps and steps waiting for a keypress keep their
+    /// position, the movable steps between them are ordered by their estimated_duration
+    pub fn schedule(&mut self) {
+        if self.options.schedule != Schedule::QuickFirst {
+            return;
+        }
+        let original: Vec<String> = self
+            .workflow
+            .iter()
+            .map(|item| item.action.clone())
+            .collect();
+        let mut scheduled = Vec::with_capacity(self.workflow.len());
+        let mut movable = vec![];
+        for item in self.workflow.drain(..) {
+            if item.estimated_duration.is_some() && !item.continue_after_keypress {
+                movable.push(item);
+            } else {
+                scheduled.extend(quick_first(std::mem::take(&mut movable)));
+                scheduled.push(item);
+            }
+        }
+        scheduled.extend(quick_first(movable));
+        self.workflow = scheduled;
+
+        let order: Vec<String> = self
+            .workflow
+            .iter()
+            .map(|item| item.action.clone())
+            .collect();
+        if order != original {
+            info!("Running the quick steps first: {}", order.join(", "));
+        }
+    }
 }
 
-pub fn read_workflow_file(yaml_path: &PathBuf) -> Result<WorkflowRunner, Box<dyn Error>> {
+#[derive(Debug, Deserialize)]
+struct WorkflowHeader {
+    properties: HashMap<String, String>,
+}
+
+// Only reads the properties of a workflow, e.g. to determine the execution order
+pub fn read_workflow_properties(
+    yaml_path: &PathBuf,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
     let file = File::open(yaml_path)?;
-    let reader = BufReader::new(file);
-    let mut runner: WorkflowRunner = match serde_yaml::from_reader(reader) {
-        Ok(runner) => runner,
-        Err(e) => {
-            error!("Error parsing workflow schema: {}", e);
-            return Err(Box::new(e));
+    let header: WorkflowHeader = serde_yaml::from_reader(BufReader::new(file))?;
+    Ok(header.properties)
+}
+
+/// Whether the workflow has to run on its own when workflows run in parallel: it interacts with
+/// the operator (terminal actions, keypress prompts) or seals the log of the collector
+pub fn workflow_runs_exclusively(yaml_path: &PathBuf) -> bool {
+    let value: Value = match File::open(yaml_path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_yaml::from_reader(BufReader::new(file)).map_err(|e| e.to_string()))
+    {
+        Ok(value) => value,
+        // invalid files report the error once they are run
+        Err(_) => return false,
+    };
+    let interactive_action = value["actions"]
+        .as_sequence()
+        .map(|actions| {
+            actions
+                .iter()
+                .any(|action| action["type"].as_str() == Some("terminal"))
+        })
+        .unwrap_or(false);
+    let keypress = value["workflow"]
+        .as_sequence()
+        .map(|items| {
+            items
+                .iter()
+                .any(|item| item["continue_after_keypress"].as_bool() == Some(true))
+        })
+        .unwrap_or(false);
+    let sealed =
+        value["reporting"]["zip_archive"]["encryption"]["seal_sidecars"].as_bool() == Some(true);
+    interactive_action || keypress || sealed
+}
+
+// Version of the workflow format supported by this build. It is increased
+// whenever attributes are renamed or change their meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+fn default_schema_version() -> u32 {
+    SCHEMA_VERSION
+}
+
+/// Workflows written for another version of the format are rejected, as they
+/// might be misparsed silently. Workflows without a version are assumed to be current.
+pub fn check_schema_version(version: Option<&Value>, file_name: &str) -> Result<(), String> {
+    let version = match version {
+        Some(version) => version,
+        None => {
+            warn!(
+                "Workflow {:?} has no schema_version: assuming version {}",
+                file_name, SCHEMA_VERSION
+            );
+            return Ok(());
         }
     };
+    match version.as_u64() {
+        Some(version) if version == SCHEMA_VERSION as u64 => Ok(()),
+        Some(version) if version > SCHEMA_VERSION as u64 => Err(format!(
+            "Workflow {:?} requires schema_version {}, but only version {} is supported: update the toolkit",
+            file_name, version, SCHEMA_VERSION
+        )),
+        Some(version) => Err(format!(
+            "Workflow {:?} uses schema_version {}, but version {} is required: update the workflow",
+            file_name, version, SCHEMA_VERSION
+        )),
+        None => Err(format!(
+            "Workflow {:?} has an invalid schema_version: {:?}",
+            file_name, version
+        )),
+    }
+}
+
+pub fn read_workflow_file(yaml_path: &PathBuf) -> Result<WorkflowRunner, Box<dyn Error>> {
+    let content = std::fs::read_to_string(yaml_path)?;
 
     let file_name = std::path::Path::new(yaml_path)
         .file_name()
@@ -742,12 +2547,34 @@ pub fn read_workflow_file(yaml_path: &PathBuf) -> Result<WorkflowRunner, Box<dyn
         .to_str()
         .unwrap();
 
+    // the version is checked before the attributes are parsed
+    let value: Value = match serde_yaml::from_str(&content) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Error parsing workflow file: {}", e);
+            return Err(Box::new(e));
+        }
+    };
+    if let Err(e) = check_schema_version(value.get("schema_version"), file_name) {
+        error!("{}", e);
+        return Err(e.into());
+    }
+
+    let mut runner: WorkflowRunner = match serde_yaml::from_str(&content) {
+        Ok(runner) => runner,
+        Err(e) => {
+            error!("Error parsing workflow schema: {}", e);
+            return Err(Box::new(e));
+        }
+    };
+
     match runner.validate(Some(file_name)) {
         Ok(_) => {}
         Err(e) => {
             return Err(e);
         }
     }
+    runner.schedule();
 
     Ok(runner)
 }
@@ -831,11 +2658,103 @@ mod tests {
             log_to_file: true
         "#;
         let ba: BinaryAttributes = serde_yaml::from_str(yaml).unwrap();
-        assert_eq!(ba.path, "/usr/bin/test");
+        assert_eq!(ba.path, BinaryPath::Single("/usr/bin/test".to_string()));
         assert_eq!(ba.args, vec!["--verbose"]);
         assert!(ba.log_to_file);
     }
 
+    #[test]
+    fn test_resolve_secrets() {
+        let secrets: BTreeMap<String, Secret> = serde_yaml::from_str(
+            r#"
+            API_TOKEN: "tok-123"
+            SHARE_PASSWORD:
+              env: "IRT_TEST_SECRET_UNSET"
+            PATH:
+              env: "PATH"
+        "#,
+        )
+        .unwrap();
+        let (variables, errors) = resolve_secrets(&secrets);
+        assert_eq!(variables["API_TOKEN"], "tok-123");
+        assert_eq!(variables["PATH"], std::env::var("PATH").unwrap());
+        assert!(!variables.contains_key("SHARE_PASSWORD"));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("IRT_TEST_SECRET_UNSET"));
+    }
+
+    #[test]
+    fn test_normalize_paths() {
+        let yaml: Value = serde_yaml::from_str(
+            r#"
+            patterns: |
+              ${USER_HOME}/AppData/Local/Temp/*.exe
+              //fileserver/share\logs/*.log
+        "#,
+        )
+        .unwrap();
+        let mut aa = ActionAttributes::from_value(ActionType::Store, yaml).unwrap();
+        let variables =
+            HashMap::from([("USER_HOME".to_string(), "C:\\Users\\alice\\".to_string())]);
+        aa.replace_vars(&variables);
+        let mut windows = aa.clone();
+        windows.normalize_paths(true);
+        let store: StoreAttributes = windows.into();
+        assert_eq!(
+            store.patterns,
+            "C:\\Users\\alice\\AppData\\Local\\Temp\\*.exe\n\\\\fileserver\\share\\logs\\*.log\n"
+        );
+        aa.normalize_paths(false);
+        let store: StoreAttributes = aa.into();
+        assert_eq!(
+            store.patterns,
+            "C:/Users/alice/AppData/Local/Temp/*.exe\n//fileserver/share/logs/*.log\n"
+        );
+
+        let mut aa = ActionAttributes::Defender(DefenderAttributes {
+            exclude: true,
+            paths: vec!["/C:/Tools/".to_string()],
+            eicar_test: false,
+        });
+        aa.normalize_paths(true);
+        let ActionAttributes::Defender(defender) = aa else {
+            panic!("not a defender action");
+        };
+        assert_eq!(defender.paths, vec!["C:\\Tools\\"]);
+    }
+
+    #[test]
+    fn test_binary_path_per_platform() {
+        let yaml = r#"
+            path:
+              windows_amd64: "winpmem/winpmem_x64.exe"
+              windows: "winpmem/winpmem_x86.exe"
+              linux_aarch64: "${OS}/avml_arm"
+            args: ["${LOOT_DIR}/memory.raw"]
+        "#;
+        let ba: BinaryAttributes = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            ba.path.select("windows", "x86_64"),
+            Some("winpmem/winpmem_x64.exe")
+        );
+        // falls back to the entry of the os
+        assert_eq!(
+            ba.path.select("windows", "x86"),
+            Some("winpmem/winpmem_x86.exe")
+        );
+        assert_eq!(ba.path.select("linux", "x86_64"), None);
+
+        // variables are replaced in each path
+        let mut aa = ActionAttributes::Binary(ba);
+        let variables = HashMap::from([("OS".to_string(), "linux".to_string())]);
+        aa.replace_vars(&variables);
+        let ba: BinaryAttributes = aa.into();
+        assert_eq!(ba.path.select("linux", "arm64"), Some("linux/avml_arm"));
+
+        let single = BinaryPath::Single("tool".to_string());
+        assert_eq!(single.select("macos", "aarch64"), Some("tool"));
+    }
+
     #[test]
     fn test_deserialize_command_attributes() {
         let yaml = r#"
@@ -859,7 +2778,10 @@ mod tests {
         let ba: BinaryAttributes = serde_yaml::from_str(yaml_binary).unwrap();
         let aa: ActionAttributes = ActionAttributes::Binary(ba.clone());
         let converted_ba: BinaryAttributes = aa.into();
-        assert_eq!(converted_ba.path, "/usr/bin/test");
+        assert_eq!(
+            converted_ba.path,
+            BinaryPath::Single("/usr/bin/test".to_string())
+        );
         assert_eq!(converted_ba.args, vec!["--verbose"]);
         assert!(converted_ba.log_to_file);
 
@@ -888,6 +2810,7 @@ mod tests {
             compression:
                 enabled: true
                 size_limit: "10 MB"
+            format: tar_zstd
         metadata:
             mac_times: true
             checksums: true
@@ -903,17 +2826,92 @@ mod tests {
         );
         assert!(reporting.zip_archive.compression.enabled);
         assert_eq!(reporting.zip_archive.compression.size_limit, 10_000_000);
+        let compression = &reporting.zip_archive.compression;
+        assert!(compression.is_excluded(std::path::Path::new("C:/Users/alice/video.MP4")));
+        assert!(!compression.is_excluded(std::path::Path::new("/var/log/syslog")));
+        let compression: ReportingCompression = serde_yaml::from_str(
+            "enabled: true\nsize_limit: \"0\"\nexclude_extensions: [\".evtx\"]",
+        )
+        .unwrap();
+        assert!(compression.is_excluded(std::path::Path::new("Security.evtx")));
+        assert!(!compression.is_excluded(std::path::Path::new("archive.zip")));
+        assert_eq!(reporting.zip_archive.format, ArchiveFormat::TarZstd);
+        assert_eq!(
+            serde_yaml::from_str::<ArchiveFormat>("7z").unwrap(),
+            ArchiveFormat::SevenZip
+        );
+        assert!(serde_yaml::from_str::<ArchiveFormat>("rar").is_err());
         assert!(reporting.metadata.mac_times);
         assert!(reporting.metadata.checksums);
         assert!(reporting.metadata.paths);
+        assert!(!reporting.dedup.enabled);
+        assert_eq!(reporting.dedup.cache, "dedup_cache.txt");
+    }
+
+    #[test]
+    fn test_execution_window() {
+        // spanning midnight
+        let night = ExecutionWindow::parse("22:00-06:00").unwrap();
+        assert!(night.contains(23 * 60));
+        assert!(night.contains(5 * 60 + 59));
+        assert!(!night.contains(6 * 60));
+        assert_eq!(night.minutes_until_open(21 * 60 + 30), 30);
+        assert_eq!(night.minutes_until_open(23 * 60), 0);
+
+        let lunch = ExecutionWindow::parse(" 12:00 - 13:30 ").unwrap();
+        assert!(lunch.contains(13 * 60));
+        assert!(!lunch.contains(13 * 60 + 30));
+        assert_eq!(lunch.minutes_until_open(14 * 60), 22 * 60);
+
+        assert_eq!(lunch.to_string(), "12:00-13:30");
+
+        assert!(ExecutionWindow::parse("22:00").is_none());
+        assert!(ExecutionWindow::parse("24:00-06:00").is_none());
+        assert!(ExecutionWindow::parse("22:00-6").is_none());
+    }
+
+    #[test]
+    fn test_workflow_runs_exclusively() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_workflow_runs_exclusively");
+        let workflow = |name: &str, content: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            workflow_runs_exclusively(&path)
+        };
+        assert!(!workflow(
+            "store.yaml",
+            "actions:\n  - name: a\n    type: store\nworkflow:\n  - action: a\n"
+        ));
+        assert!(workflow(
+            "terminal.yaml",
+            "actions:\n  - name: a\n    type: terminal\nworkflow:\n  - action: a\n"
+        ));
+        assert!(workflow(
+            "keypress.yaml",
+            "workflow:\n  - action: a\n    continue_after_keypress: true\n"
+        ));
+        assert!(workflow(
+            "sealed.yaml",
+            "reporting:\n  zip_archive:\n    encryption:\n      seal_sidecars: true\n"
+        ));
+        assert!(!workflow("invalid.yaml", "actions: ["));
     }
 
     #[test]
     fn test_read_workflow_file() {
         let yaml_content = r#"
+        schema_version: 1
         properties:
           title: "value1"
           version: "value2"
+          priority: 10
+          depends_on: "volatile, memory.yaml"
+          elevation: Required
+          window: "22:00-06:00"
+          start_delay: 30m
+        options:
+          time_zone: "Europe/Berlin"
         launch_conditions:
           os: ["linux"]
           arch: ["x86_64"]
@@ -952,13 +2950,37 @@ mod tests {
         let workflow = read_workflow_file(&file_path).unwrap();
         assert_eq!(workflow.properties["title"], "value1");
         assert_eq!(workflow.properties["version"], "value2");
+        assert_eq!(workflow_priority(&workflow.properties), 10);
+        assert_eq!(
+            workflow_depends_on(&workflow.properties),
+            vec!["volatile", "memory.yaml"]
+        );
+        assert_eq!(
+            workflow_elevation(&workflow.properties),
+            Some(Elevation::Required)
+        );
+        assert_eq!(
+            workflow_window(&workflow.properties),
+            Some(ExecutionWindow {
+                start: 22 * 60,
+                end: 6 * 60
+            })
+        );
+        assert_eq!(
+            workflow_start_delay(&workflow.properties),
+            Some(std::time::Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            workflow.options.time_zone_or("UTC"),
+            chrono_tz::Europe::Berlin
+        );
         assert_eq!(workflow.launch_conditions.os, vec!["linux"]);
         assert_eq!(workflow.launch_conditions.arch.unwrap(), vec!["x86_64"]);
         assert_eq!(workflow.actions.len(), 1);
         assert_eq!(workflow.actions[0].name, "Test Action");
         assert_eq!(workflow.actions[0].action_type, ActionType::Binary);
         if let ActionAttributes::Binary(ref ba) = workflow.actions[0].attributes {
-            assert_eq!(ba.path, "/bin/true");
+            assert_eq!(ba.path, BinaryPath::Single("/bin/true".to_string()));
             assert!(!ba.log_to_file);
         } else {
             panic!("Expected ActionAttributes::Binary variant");
@@ -966,6 +2988,121 @@ mod tests {
         assert_eq!(workflow.workflow.len(), 1);
         assert_eq!(workflow.workflow[0].action, "Test Action");
         assert_eq!(workflow.workflow[0].on_error, OnError::Continue);
+        assert_eq!(workflow.schema_version, SCHEMA_VERSION);
+
+        // a workflow written for a newer toolkit is rejected before its attributes are parsed
+        let newer = yaml_content.replace("schema_version: 1", "schema_version: 2");
+        std::fs::write(&file_path, newer).unwrap();
+        assert!(read_workflow_file(&file_path).is_err());
+    }
+
+    #[test]
+    fn test_schedule_quick_first() {
+        let action = |name: &str| {
+            format!(
+                "- name: {}\n  type: binary\n  attributes:\n    path: /bin/true\n",
+                name
+            )
+        };
+        let actions: String = [
+            "disk_sweep",
+            "processes",
+            "network",
+            "memory",
+            "prompt",
+            "late_slow",
+            "late_quick",
+        ]
+        .iter()
+        .map(|name| action(name))
+        .collect();
+        let yaml = format!(
+            r#"
+properties:
+  title: "Schedule"
+  version: "1.0"
+options:
+  schedule: quick_first
+launch_conditions:
+  os: ["linux"]
+actions:
+{}
+workflow:
+  - action: disk_sweep
+    estimated_duration: 2h
+  - action: processes
+    estimated_duration: 30s
+  - action: network
+    estimated_duration: 10s
+    after: ["processes"]
+  - action: memory
+    estimated_duration: 5m
+  - action: prompt
+    continue_after_keypress: true
+  - action: late_slow
+    estimated_duration: 1h
+    after: ["late_quick"]
+  - action: late_quick
+    estimated_duration: 1s
+reporting:
+  zip_archive:
+    enabled: false
+    encryption:
+      enabled: false
+      public_key: ""
+      algorithm: None
+    compression:
+      enabled: false
+      size_limit: "0"
+  metadata:
+    mac_times: false
+    checksums: false
+    paths: false
+"#,
+            actions
+        );
+        let order = |runner: &WorkflowRunner| -> Vec<String> {
+            runner
+                .workflow
+                .iter()
+                .map(|item| item.action.clone())
+                .collect()
+        };
+
+        let mut runner: WorkflowRunner = serde_yaml::from_str(&yaml).unwrap();
+        runner.validate(None).unwrap();
+        // late_quick is not an earlier step
+        assert!(runner.workflow[5].after.is_empty());
+        runner.schedule();
+        assert_eq!(
+            order(&runner),
+            vec![
+                "processes",
+                "network",
+                "memory",
+                "disk_sweep",
+                "prompt",
+                "late_quick",
+                "late_slow"
+            ]
+        );
+
+        let in_order = yaml.replace("schedule: quick_first", "schedule: in_order");
+        let mut runner: WorkflowRunner = serde_yaml::from_str(&in_order).unwrap();
+        runner.schedule();
+        assert_eq!(order(&runner)[0], "disk_sweep");
+    }
+
+    #[test]
+    fn test_check_schema_version() {
+        let version = |v: &str| serde_yaml::from_str::<Value>(v).unwrap();
+        assert!(check_schema_version(None, "test.yaml").is_ok());
+        assert!(check_schema_version(Some(&version("1")), "test.yaml").is_ok());
+        let error = check_schema_version(Some(&version("2")), "test.yaml").unwrap_err();
+        assert!(error.contains("update the toolkit"));
+        let error = check_schema_version(Some(&version("0")), "test.yaml").unwrap_err();
+        assert!(error.contains("update the workflow"));
+        assert!(check_schema_version(Some(&version("\"1.0\"")), "test.yaml").is_err());
     }
 
     #[test]
@@ -981,6 +3118,8 @@ mod tests {
             on_error:
               goto: test
             parallel: false
+          - action: test4
+            on_error: abort_and_finalize
         "#;
 
         let workflow: Vec<WorkflowItem> = serde_yaml::from_str(yaml).unwrap();
@@ -992,5 +3131,42 @@ mod tests {
                 goto: "test".to_string()
             }
         );
+        assert_eq!(workflow[3].on_error, OnError::AbortAndFinalize);
+    }
+
+    #[test]
+    fn test_on_error_for_category() {
+        let yaml = r#"
+          - action: test1
+            on_error: abort
+            on_timeout: continue
+            on_permission_denied:
+              goto: elevate_hint
+          - action: test2
+        "#;
+
+        let workflow: Vec<WorkflowItem> = serde_yaml::from_str(yaml).unwrap();
+        let item = &workflow[0];
+        assert_eq!(
+            item.on_error_for(Some(ErrorCategory::Timeout)),
+            &OnError::Continue
+        );
+        assert_eq!(
+            item.on_error_for(Some(ErrorCategory::PermissionDenied)),
+            &OnError::Goto {
+                goto: "elevate_hint".to_string()
+            }
+        );
+        // categories without a handler fall back to on_error
+        assert_eq!(
+            item.on_error_for(Some(ErrorCategory::NonzeroExit)),
+            &OnError::Abort
+        );
+        assert_eq!(item.on_error_for(None), &OnError::Abort);
+        assert!(item.has_custom_on_error());
+        assert!(!workflow[1].has_custom_on_error());
+
+        let invalid = "- action: test\n  on_timeout: retry\n";
+        assert!(serde_yaml::from_str::<Vec<WorkflowItem>>(invalid).is_err());
     }
 }
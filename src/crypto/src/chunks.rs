@@ -0,0 +1,200 @@
+// Tags of the chunks of the encrypted archive. The AEAD tag only tells whether the whole archive
+// is authentic once it has been decrypted completely, so a single damaged byte (e.g. during the
+// transfer) makes the archive unusable. The chunk tags locate the damage, so the intact data
+// before it can still be decrypted
+use crate::{session::SessionKey, EncryptionMeta};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+// size of the chunks of the archive with a tag in encryption.json
+pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+// size of a truncated chunk tag
+const CHUNK_TAG_SIZE: usize = 16;
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let pkey = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+    for part in parts {
+        signer.update(part)?;
+    }
+    Ok(signer.sign_to_vec()?)
+}
+
+// the chunk tags use a key derived from the session key, it is never used for encryption
+fn chunk_tag_key(key: &SessionKey) -> Result<Vec<u8>, Box<dyn Error>> {
+    hmac_sha256(key.key(), &[b"ir-toolkit chunk tags"])
+}
+
+// HMAC-SHA256 of the index, the IV and the ciphertext of the chunk
+fn chunk_tag(
+    tag_key: &[u8],
+    iv: &[u8],
+    index: u64,
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut tag = hmac_sha256(tag_key, &[&index.to_be_bytes(), iv, ciphertext])?;
+    tag.truncate(CHUNK_TAG_SIZE);
+    Ok(tag)
+}
+
+/// Computes the tags of the chunks while the ciphertext is written
+pub struct ChunkTagger {
+    tag_key: Vec<u8>,
+    iv: Vec<u8>,
+    chunk_size: u64,
+    chunk: Vec<u8>,
+    tags: Vec<Vec<u8>>,
+    size: u64,
+}
+
+impl ChunkTagger {
+    pub fn new(key: &SessionKey, iv: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Self::with_chunk_size(key, iv, CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(
+        key: &SessionKey,
+        iv: &[u8],
+        chunk_size: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            tag_key: chunk_tag_key(key)?,
+            iv: iv.to_vec(),
+            chunk_size: chunk_size.max(1),
+            chunk: vec![],
+            tags: vec![],
+            size: 0,
+        })
+    }
+
+    pub fn update(&mut self, mut ciphertext: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.size += ciphertext.len() as u64;
+        while !ciphertext.is_empty() {
+            let missing = self.chunk_size as usize - self.chunk.len();
+            let (head, rest) = ciphertext.split_at(missing.min(ciphertext.len()));
+            self.chunk.extend_from_slice(head);
+            ciphertext = rest;
+            if self.chunk.len() as u64 == self.chunk_size {
+                self.push_tag()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push_tag(&mut self) -> Result<(), Box<dyn Error>> {
+        let index = self.tags.len() as u64;
+        self.tags
+            .push(chunk_tag(&self.tag_key, &self.iv, index, &self.chunk)?);
+        self.chunk.clear();
+        Ok(())
+    }
+
+    /// Tags the last chunk and stores the tags in the metadata
+    pub fn finish(mut self, metadata: &mut EncryptionMeta) -> Result<(), Box<dyn Error>> {
+        if !self.chunk.is_empty() {
+            self.push_tag()?;
+        }
+        metadata.plaintext_size = self.size;
+        metadata.chunk_size = self.chunk_size;
+        metadata.chunk_tags = self.tags;
+        Ok(())
+    }
+}
+
+/// Result of the check of the chunk tags
+#[derive(Debug, PartialEq)]
+pub struct ChunkCheck {
+    // size of the intact data at the start of the archive
+    pub intact: u64,
+    // index of the first damaged chunk, None if the archive is intact
+    pub damaged_chunk: Option<u64>,
+}
+
+// reads until the buffer is full or the end of the data is reached
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, Box<dyn Error>> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..])? {
+            0 => break,
+            count => read += count,
+        }
+    }
+    Ok(read)
+}
+
+/// Checks the chunks of the encrypted archive against the tags of encryption.json. A missing
+/// or an additional byte at the end of the archive counts as damage as well
+pub fn check_chunks<R: Read>(
+    mut reader: R,
+    key: &SessionKey,
+    metadata: &EncryptionMeta,
+) -> Result<ChunkCheck, Box<dyn Error>> {
+    if metadata.chunk_size == 0 {
+        return Err("encryption.json contains no chunk tags".into());
+    }
+    let tag_key = chunk_tag_key(key)?;
+    let mut buffer = vec![0u8; metadata.chunk_size as usize];
+    let mut intact = 0;
+    for (index, tag) in metadata.chunk_tags.iter().enumerate() {
+        let expected = metadata
+            .plaintext_size
+            .saturating_sub(intact)
+            .min(metadata.chunk_size) as usize;
+        let read = read_full(&mut reader, &mut buffer[..expected])?;
+        if read < expected
+            || chunk_tag(&tag_key, &metadata.iv, index as u64, &buffer[..expected])? != *tag
+        {
+            return Ok(ChunkCheck {
+                intact,
+                damaged_chunk: Some(index as u64),
+            });
+        }
+        intact += expected as u64;
+    }
+    let additional = read_full(&mut reader, &mut buffer[..1])? > 0;
+    Ok(ChunkCheck {
+        intact,
+        damaged_chunk: (additional || intact != metadata.plaintext_size)
+            .then_some(metadata.chunk_tags.len() as u64),
+    })
+}
+
+/// Decrypts the intact chunks at the start of a damaged archive into the output file.
+/// The data is not authenticated by the AEAD tag, but by the chunk tags
+pub fn salvage_evidence(
+    input_path: &Path,
+    output_path: &Path,
+    key: &SessionKey,
+    metadata: &EncryptionMeta,
+) -> Result<ChunkCheck, Box<dyn Error>> {
+    let check = check_chunks(BufReader::new(File::open(input_path)?), key, metadata)?;
+
+    // the stream cipher of the AEAD decrypts the data without the tag of the whole archive
+    let mut crypter = key.keystream_crypter(&metadata.iv, 0)?;
+    let mut input = File::open(input_path)?.take(check.intact);
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output_path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut plaintext = vec![0u8; buffer.len() + 64];
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let count = crypter.update(&buffer[..read], &mut plaintext)?;
+        output.write_all(&plaintext[..count])?;
+    }
+    let count = crypter.finalize(&mut plaintext)?;
+    output.write_all(&plaintext[..count])?;
+    plaintext.iter_mut().for_each(|b| *b = 0);
+    output.sync_all()?;
+    Ok(check)
+}
@@ -61,11 +61,11 @@ mod tests {
             encrypt_evidence(&test_file, public_key, algorithm).expect("Failed to encrypt file");
 
         let metadata = EncryptionMeta {
-            version: "1.0".to_string(),
             algorithm: algorithm,
             encrypted_key,
             iv,
             tag,
+            ..Default::default()
         };
 
         // Step 8: Decrypt the file
@@ -132,11 +132,11 @@ mod tests {
             encrypt_evidence(&test_file, public_key, algorithm).expect("Failed to encrypt file");
 
         let metadata = EncryptionMeta {
-            version: "1.0".to_string(),
             algorithm: algorithm,
             encrypted_key,
             iv,
             tag,
+            ..Default::default()
         };
 
         // Step 8: Decrypt the file
@@ -150,4 +150,418 @@ mod tests {
 
         assert_eq!(pre_checksum, post_checksum, "Checksums do not match");
     }
+
+    #[test]
+    fn check_encryption_interrupted() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_encryption_interrupted");
+
+        let rsa = Rsa::generate(2048).unwrap();
+        let public_key = Rsa::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+
+        let test_file = tmp_dir.join("report.zip");
+        let data = generate_random(1024 * 1024);
+        std::fs::write(&test_file, &data).expect("Failed to write test file");
+
+        // simulate a crash: the journal and a partially written temporary file remain
+        std::fs::write(encryption_temp_path(&test_file), &data[..1024]).unwrap();
+        write_encryption_journal(
+            &test_file,
+            &EncryptionJournal {
+                algorithm: Algorithm::AES128GCM,
+                file_size: data.len() as u64,
+                offset: 1024,
+            },
+        )
+        .unwrap();
+        assert_eq!(read_encryption_journal(&test_file).unwrap().offset, 1024);
+        assert_eq!(std::fs::read(&test_file).unwrap(), data);
+        assert_eq!(interrupted_encryptions(&tmp_dir), vec![test_file.clone()]);
+
+        // the next run starts over and cleans up
+        encrypt_evidence(&test_file, public_key, Algorithm::AES128GCM)
+            .expect("Failed to encrypt file");
+        assert_ne!(std::fs::read(&test_file).unwrap(), data);
+        assert_eq!(
+            std::fs::metadata(&test_file).unwrap().len(),
+            data.len() as u64
+        );
+        assert!(!encryption_temp_path(&test_file).exists());
+        assert!(read_encryption_journal(&test_file).is_none());
+        assert!(interrupted_encryptions(&tmp_dir).is_empty());
+    }
+
+    #[test]
+    fn check_encrypted_output() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_encrypted_output");
+        let output = tmp_dir.join("whoami.log");
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+
+        // the session key is only stored encrypted with the public key
+        let rsa = Rsa::generate(2048).unwrap();
+        let public_key = Rsa::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+        let key = SessionKey::generate(Algorithm::CHACHA20POLY1305).unwrap();
+        let encrypted_key = key.encrypt(&public_key).unwrap();
+        let decrypted_key =
+            SessionKey::decrypt(&rsa, Algorithm::CHACHA20POLY1305, &encrypted_key).unwrap();
+
+        let encrypted_path = encrypted_output_path(&output);
+        assert!(encrypted_path.ends_with("whoami.log.enc"));
+        let file = File::create(&encrypted_path).unwrap();
+        let mut writer = EncryptingWriter::new(file, &key).unwrap();
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        assert!(!output.exists());
+
+        // tampered files are rejected and left as is
+        let mut tampered = fs::read(&encrypted_path).unwrap();
+        tampered[100] ^= 0xff;
+        let tampered_path = tmp_dir.join("tampered.log.enc");
+        fs::write(&tampered_path, &tampered).unwrap();
+        assert!(decrypt_output_file(&tampered_path, &decrypted_key).is_err());
+        assert!(tampered_path.exists());
+        assert!(!tmp_dir.join("tampered.log").exists());
+
+        // forwarded outputs are encrypted with another session key, tampered ones are rejected
+        let new_key = SessionKey::generate(Algorithm::CHACHA20POLY1305).unwrap();
+        let forwarded_path = tmp_dir.join("forwarded.log.enc");
+        let size = fs::metadata(&encrypted_path).unwrap().len();
+        let mut reader = ReencryptingReader::new(
+            File::open(&encrypted_path).unwrap(),
+            size,
+            &decrypted_key,
+            &new_key,
+        )
+        .unwrap();
+        io::copy(&mut reader, &mut File::create(&forwarded_path).unwrap()).unwrap();
+        assert_eq!(fs::metadata(&forwarded_path).unwrap().len(), size);
+        assert!(decrypt_output_file(&forwarded_path, &decrypted_key).is_err());
+        decrypt_output_file(&forwarded_path, &new_key).unwrap();
+        assert_eq!(fs::read(tmp_dir.join("forwarded.log")).unwrap(), data);
+        let mut reader =
+            ReencryptingReader::new(&tampered[..], size, &decrypted_key, &new_key).unwrap();
+        assert!(io::copy(&mut reader, &mut io::sink()).is_err());
+
+        let decrypted = decrypt_output_file(&encrypted_path, &decrypted_key).unwrap();
+        assert_eq!(decrypted, output);
+        assert_eq!(fs::read(&output).unwrap(), data);
+        assert!(!encrypted_path.exists());
+    }
+
+    #[test]
+    fn check_evidence_reader() {
+        use std::io::{Cursor, Seek, SeekFrom};
+
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_evidence_reader");
+        let rsa = Rsa::generate(2048).unwrap();
+        let public_key = Rsa::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+        // an odd size, so the data doesn't end on a block boundary
+        let data: Vec<u8> = (0..100_003u32).map(|i| (i % 251) as u8).collect();
+
+        for algorithm in [Algorithm::AES128GCM, Algorithm::CHACHA20POLY1305] {
+            let archive = tmp_dir.join(format!("{}.zip", algorithm));
+            fs::write(&archive, &data).unwrap();
+            let key = SessionKey::generate(algorithm).unwrap();
+            let EncryptionMeta { iv, tag, .. } = encrypt_evidence_with_key(&archive, &key).unwrap();
+            let encrypted = fs::read(&archive).unwrap();
+            let key = || SessionKey::decrypt(&rsa, algorithm, &key.encrypt(&public_key).unwrap());
+
+            verify_evidence(Cursor::new(&encrypted), &key().unwrap(), &iv, &tag).unwrap();
+            let mut tampered = encrypted.clone();
+            tampered[5000] ^= 0xff;
+            assert!(verify_evidence(Cursor::new(&tampered), &key().unwrap(), &iv, &tag).is_err());
+
+            let mut reader =
+                EvidenceReader::new(Cursor::new(encrypted.clone()), key().unwrap(), &iv).unwrap();
+            let mut decrypted = vec![];
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert!(decrypted == data, "{}", algorithm);
+
+            // any position can be read, also inside a keystream block
+            for position in [99_000u64, 17, 64, 65_535, 0] {
+                let mut chunk = vec![0u8; 1000];
+                reader.seek(SeekFrom::Start(position)).unwrap();
+                reader.read_exact(&mut chunk).unwrap();
+                let start = position as usize;
+                assert!(
+                    chunk == data[start..start + 1000],
+                    "{} {}",
+                    algorithm,
+                    position
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn check_reencrypt_evidence() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_reencrypt_evidence");
+        let data: Vec<u8> = (0..70_001u32).map(|i| (i % 253) as u8).collect();
+
+        for algorithm in [Algorithm::AES128GCM, Algorithm::CHACHA20POLY1305] {
+            let archive = tmp_dir.join(format!("{}.zip", algorithm));
+            let forwarded = tmp_dir.join(format!("{}_forwarded.zip", algorithm));
+            fs::write(&archive, &data).unwrap();
+            let key = SessionKey::generate(algorithm).unwrap();
+            let EncryptionMeta { iv, tag, .. } = encrypt_evidence_with_key(&archive, &key).unwrap();
+
+            let new_key = SessionKey::generate(algorithm).unwrap();
+            let forwarded_meta =
+                reencrypt_evidence(&archive, &forwarded, &key, &new_key, &iv, &tag).unwrap();
+            let (new_iv, new_tag) = (forwarded_meta.iv.clone(), forwarded_meta.tag.clone());
+            assert_ne!(iv, new_iv);
+            assert_eq!(
+                check_chunks(File::open(&forwarded).unwrap(), &new_key, &forwarded_meta)
+                    .unwrap()
+                    .damaged_chunk,
+                None
+            );
+            assert_ne!(fs::read(&archive).unwrap(), fs::read(&forwarded).unwrap());
+            verify_evidence(File::open(&forwarded).unwrap(), &new_key, &new_iv, &new_tag).unwrap();
+            assert!(
+                verify_evidence(File::open(&forwarded).unwrap(), &key, &new_iv, &new_tag).is_err()
+            );
+
+            // a tampered archive is not forwarded
+            let original = fs::read(&archive).unwrap();
+            let mut tampered = original.clone();
+            tampered[100] ^= 0xff;
+            let tampered_archive = tmp_dir.join(format!("{}_tampered.zip", algorithm));
+            let tampered_forwarded = tmp_dir.join(format!("{}_tampered_forwarded.zip", algorithm));
+            fs::write(&tampered_archive, &tampered).unwrap();
+            assert!(reencrypt_evidence(
+                &tampered_archive,
+                &tampered_forwarded,
+                &key,
+                &new_key,
+                &iv,
+                &tag
+            )
+            .is_err());
+            assert!(!tampered_forwarded.exists());
+
+            let mut reader =
+                EvidenceReader::new(File::open(&forwarded).unwrap(), new_key, &new_iv).unwrap();
+            let mut decrypted = vec![];
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert!(decrypted == data, "{}", algorithm);
+
+            // archives which are rewritten while they are re-encrypted
+            let key = SessionKey::generate(algorithm).unwrap();
+            let rewritten = tmp_dir.join(format!("{}_rewritten.zip", algorithm));
+            let mut writer = EvidenceWriter::new(File::create(&rewritten).unwrap(), &key).unwrap();
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            assert_eq!(writer.stream_position().unwrap(), data.len() as u64);
+            assert!(writer.seek(SeekFrom::Start(0)).is_err());
+            let (_, rewritten_meta) = writer.finish().unwrap();
+            let (iv, tag) = (rewritten_meta.iv.clone(), rewritten_meta.tag.clone());
+            assert_eq!(
+                check_chunks(File::open(&rewritten).unwrap(), &key, &rewritten_meta)
+                    .unwrap()
+                    .damaged_chunk,
+                None
+            );
+            verify_evidence(File::open(&rewritten).unwrap(), &key, &iv, &tag).unwrap();
+            let mut reader =
+                EvidenceReader::new(File::open(&rewritten).unwrap(), key, &iv).unwrap();
+            let mut decrypted = vec![];
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert!(decrypted == data, "{}", algorithm);
+        }
+    }
+
+    #[test]
+    fn check_hashing_reader_writer() {
+        // echo -n "abc" | md5sum / sha1sum / sha256sum
+        let md5 = "900150983cd24fb0d6963f7d28e17f72";
+        let sha1 = "a9993e364706816aba3e25717850c26c9cd0d89d";
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        let digests = [Digest::Md5, Digest::Sha1, Digest::Sha256, Digest::Sha1];
+        let mut reader = HashingReader::new(&b"abc"[..], &digests).unwrap();
+        let mut content = vec![];
+        reader.read_to_end(&mut content).unwrap();
+        let result = reader.finish().unwrap();
+        assert_eq!(content, b"abc");
+        assert_eq!(result.hex(Digest::Md5), md5);
+        assert_eq!(result.hex(Digest::Sha1), sha1);
+        assert_eq!(result.hex(Digest::Sha256), sha256);
+
+        let mut writer = HashingWriter::new(vec![], &[Digest::Sha256]).unwrap();
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"c").unwrap();
+        let (written, result) = writer.finish().unwrap();
+        assert_eq!(written, b"abc");
+        assert_eq!(result.hex(Digest::Sha256), sha256);
+        // digests which weren't requested are empty
+        assert_eq!(result.hex(Digest::Sha1), "");
+        assert_eq!(get_reader_sha1(&b"abc"[..]).unwrap(), sha1);
+    }
+
+    #[test]
+    fn check_ssh_key_fingerprint() {
+        // generated with ssh-keygen -t ed25519, fingerprint from ssh-keygen -lf
+        assert_eq!(
+            ssh_key_fingerprint(
+                "AAAAC3NzaC1lZDI1NTE5AAAAIAcgPTFcrTFy5PabeCS9tjgQr/rvyZC24/Vb9KHLHu/W"
+            )
+            .unwrap(),
+            "SHA256:revxAa6oCS/N+BXbfaIf/sHromUjjvtUJg1q0LdgPGI"
+        );
+        assert!(ssh_key_fingerprint("not base64!").is_none());
+    }
+
+    #[test]
+    fn check_certificate_and_fingerprints() {
+        let (private_key, public_key) = generate_ed25519_keypair().unwrap();
+        let fingerprint = public_key_fingerprint(&public_key).unwrap();
+        // 32 bytes as colon separated hex
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        assert_eq!(fingerprint, public_key_fingerprint(&private_key).unwrap());
+
+        let options = CertificateOptions {
+            organization: "ACME CERT".to_string(),
+            not_before: "2026-01-01".to_string(),
+            days: 10,
+            ..Default::default()
+        };
+        let certificate = generate_certificate(&private_key, &public_key, &options).unwrap();
+        assert!(certificate.verify(&public_key).unwrap());
+        assert_eq!(
+            certificate.not_before().to_string(),
+            "Jan  1 00:00:00 2026 GMT"
+        );
+        assert_eq!(
+            certificate.not_after().to_string(),
+            "Jan 11 00:00:00 2026 GMT"
+        );
+        let certificate_key = certificate.public_key().unwrap();
+        assert_eq!(
+            public_key_fingerprint(&certificate_key).unwrap(),
+            fingerprint
+        );
+        assert_ne!(certificate_fingerprint(&certificate).unwrap(), fingerprint);
+
+        // RSA keys are signed with SHA-256
+        let (private_key, public_key) = generate_rsa_keypair(2048).unwrap();
+        let certificate =
+            generate_certificate(&private_key, &public_key, &CertificateOptions::default())
+                .unwrap();
+        assert!(certificate.verify(&public_key).unwrap());
+
+        let options = CertificateOptions {
+            not_before: "01.01.2026".to_string(),
+            ..Default::default()
+        };
+        assert!(generate_certificate(&private_key, &public_key, &options).is_err());
+    }
+
+    #[test]
+    fn check_secrets_store() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("check_secrets_store");
+        let path = dir.join(SECRETS_PATH);
+
+        // sealed with a passphrase
+        let passphrase = SealKey::Passphrase("correct horse".to_string());
+        let mut store = SecretsStore::new(&passphrase).unwrap();
+        store
+            .set("upload.targets.0.secret_key", "s3cr3t", &passphrase)
+            .unwrap();
+        store
+            .set("notifications.targets.0.url", "https://hooks", &passphrase)
+            .unwrap();
+        // a different passphrase would lock out the other values
+        let wrong = SealKey::Passphrase("wrong".to_string());
+        assert!(store.set("secrets.VT_API_KEY", "x", &wrong).is_err());
+        store.write(&path).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("s3cr3t"));
+
+        let store = SecretsStore::read(&path).unwrap();
+        assert_eq!(store.mode, SealMode::Passphrase);
+        let values = store
+            .unseal(&UnsealKey::Passphrase("correct horse".to_string()))
+            .unwrap();
+        assert_eq!(values["upload.targets.0.secret_key"], "s3cr3t");
+        assert_eq!(values.len(), 2);
+        assert!(store
+            .unseal(&UnsealKey::Passphrase("wrong".to_string()))
+            .is_err());
+
+        // sealed to a public key, values are added without the private key
+        let (private_key, _) = generate_rsa_keypair(2048).unwrap();
+        let private_key = private_key.rsa().unwrap();
+        let public_key =
+            openssl::rsa::Rsa::public_key_from_pem(&private_key.public_key_to_pem().unwrap())
+                .unwrap();
+        let mut store = SecretsStore::new(&SealKey::PublicKey(public_key)).unwrap();
+        let seal_key = store.public_seal_key().unwrap().unwrap();
+        store.set("smtp", "hunter2", &seal_key).unwrap();
+        assert!(store.set("other", "x", &passphrase).is_err());
+        store.write(&path).unwrap();
+        let mut store = SecretsStore::read(&path).unwrap();
+        let seal_key = store.public_seal_key().unwrap().unwrap();
+        store.set("token", "abc", &seal_key).unwrap();
+        let values = store.unseal(&UnsealKey::PrivateKey(private_key)).unwrap();
+        assert_eq!(values["smtp"], "hunter2");
+        assert_eq!(values["token"], "abc");
+        assert!(store.remove("token"));
+        assert_eq!(store.names(), vec!["smtp"]);
+    }
+
+    #[test]
+    fn check_salvage_evidence() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_salvage_evidence");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 247) as u8).collect();
+
+        for algorithm in [Algorithm::AES128GCM, Algorithm::CHACHA20POLY1305] {
+            let archive = tmp_dir.join(format!("{}.zip", algorithm));
+            fs::write(&archive, &data).unwrap();
+            let key = SessionKey::generate(algorithm).unwrap();
+            let metadata = encrypt_evidence_chunked(&archive, &key, 4096).unwrap();
+            assert_eq!(metadata.plaintext_size, 10_000);
+            assert_eq!(metadata.chunk_tags.len(), 3);
+
+            // the tags survive encryption.json
+            let metadata: EncryptionMeta =
+                serde_json::from_str(&serde_json::to_string(&metadata).unwrap()).unwrap();
+            let encrypted = fs::read(&archive).unwrap();
+            let check = check_chunks(encrypted.as_slice(), &key, &metadata).unwrap();
+            assert_eq!(check.intact, 10_000);
+            assert_eq!(check.damaged_chunk, None);
+
+            // a damaged byte in the second chunk
+            let mut damaged = encrypted.clone();
+            damaged[5000] ^= 0xff;
+            fs::write(&archive, &damaged).unwrap();
+            let salvaged = tmp_dir.join(format!("{}_salvaged.zip", algorithm));
+            let check = salvage_evidence(&archive, &salvaged, &key, &metadata).unwrap();
+            assert_eq!(
+                check,
+                ChunkCheck {
+                    intact: 4096,
+                    damaged_chunk: Some(1)
+                }
+            );
+            assert!(fs::read(&salvaged).unwrap() == data[..4096]);
+
+            // a truncated archive
+            let check = check_chunks(&encrypted[..9000], &key, &metadata).unwrap();
+            assert_eq!(check.intact, 8192);
+            assert_eq!(check.damaged_chunk, Some(2));
+            // an additional byte
+            let mut extended = encrypted.clone();
+            extended.push(0);
+            let check = check_chunks(extended.as_slice(), &key, &metadata).unwrap();
+            assert_eq!(check.damaged_chunk, Some(3));
+        }
+    }
 }
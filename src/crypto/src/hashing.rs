@@ -0,0 +1,134 @@
+// Computes any set of digests while data is read or written, so a file
+// is only read once, e.g. while it is copied into the archive
+use openssl::hash::{Hasher, MessageDigest};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Digest {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl Digest {
+    fn message_digest(&self) -> MessageDigest {
+        match self {
+            Digest::Md5 => MessageDigest::md5(),
+            Digest::Sha1 => MessageDigest::sha1(),
+            Digest::Sha256 => MessageDigest::sha256(),
+        }
+    }
+}
+
+/// Digests of the data passed through a HashingReader or HashingWriter
+#[derive(Debug, Default, PartialEq)]
+pub struct Digests(BTreeMap<Digest, Vec<u8>>);
+
+impl Digests {
+    pub fn get(&self, digest: Digest) -> Option<&[u8]> {
+        self.0.get(&digest).map(Vec::as_slice)
+    }
+
+    /// Hex encoded digest, empty if it wasn't computed
+    pub fn hex(&self, digest: Digest) -> String {
+        self.get(digest).map(hex::encode).unwrap_or_default()
+    }
+}
+
+struct Hashers(Vec<(Digest, Hasher)>);
+
+impl Hashers {
+    fn new(digests: &[Digest]) -> Result<Self, Box<dyn Error>> {
+        let mut hashers = Vec::with_capacity(digests.len());
+        for digest in digests {
+            if !hashers.iter().any(|(other, _)| other == digest) {
+                hashers.push((*digest, Hasher::new(digest.message_digest())?));
+            }
+        }
+        Ok(Self(hashers))
+    }
+
+    fn update(&mut self, data: &[u8]) -> io::Result<()> {
+        for (_, hasher) in &mut self.0 {
+            hasher.update(data).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Digests, Box<dyn Error>> {
+        let mut digests = BTreeMap::new();
+        for (digest, mut hasher) in self.0 {
+            digests.insert(digest, hasher.finish()?.to_vec());
+        }
+        Ok(Digests(digests))
+    }
+}
+
+/// Hashes everything read from the inner reader. Without digests, it only passes the data through
+pub struct HashingReader<R> {
+    inner: R,
+    hashers: Hashers,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, digests: &[Digest]) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            inner,
+            hashers: Hashers::new(digests)?,
+        })
+    }
+
+    /// Reads the remaining data and returns the digests
+    pub fn digest_remaining(mut self) -> Result<Digests, Box<dyn Error>> {
+        io::copy(&mut self, &mut io::sink())?;
+        self.finish()
+    }
+
+    pub fn finish(self) -> Result<Digests, Box<dyn Error>> {
+        self.hashers.finish()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hashers.update(&buf[..bytes_read])?;
+        Ok(bytes_read)
+    }
+}
+
+/// Hashes everything written to the inner writer
+pub struct HashingWriter<W> {
+    inner: W,
+    hashers: Hashers,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, digests: &[Digest]) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            inner,
+            hashers: Hashers::new(digests)?,
+        })
+    }
+
+    /// Flushes the inner writer and returns it along with the digests
+    pub fn finish(mut self) -> Result<(W, Digests), Box<dyn Error>> {
+        self.inner.flush()?;
+        Ok((self.inner, self.hashers.finish()?))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // only the bytes accepted by the inner writer are hashed
+        let written = self.inner.write(buf)?;
+        self.hashers.update(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
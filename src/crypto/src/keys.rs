@@ -0,0 +1,148 @@
+// Signing keys, fingerprints and self-signed certificates created by the keygen
+use openssl::asn1::Asn1Time;
+use openssl::base64::{decode_block, encode_block};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{HasPublic, Id, PKey, PKeyRef, Private, Public};
+use openssl::sha::sha256;
+use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectKeyIdentifier};
+use openssl::x509::{X509NameBuilder, X509};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Generate an Ed25519 key pair, used to sign reports
+pub fn generate_ed25519_keypair() -> Result<(PKey<Private>, PKey<Public>), Box<dyn Error>> {
+    let private_key = PKey::generate_ed25519()?;
+    let public_key = PKey::public_key_from_raw_bytes(&private_key.raw_public_key()?, Id::ED25519)?;
+    Ok((private_key, public_key))
+}
+
+fn format_fingerprint(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// SHA-256 fingerprint of the DER encoded public key (SubjectPublicKeyInfo),
+/// the same as `openssl pkey -pubin -outform DER | sha256sum`
+pub fn public_key_fingerprint<T: HasPublic>(key: &PKeyRef<T>) -> Result<String, Box<dyn Error>> {
+    Ok(format_fingerprint(&sha256(&key.public_key_to_der()?)))
+}
+
+/// SHA-256 fingerprint of the DER encoded certificate
+pub fn certificate_fingerprint(certificate: &X509) -> Result<String, Box<dyn Error>> {
+    Ok(format_fingerprint(
+        &certificate.digest(MessageDigest::sha256())?,
+    ))
+}
+
+/// Fingerprint of an OpenSSH public key (the base64 part of a line in authorized_keys),
+/// the same as `ssh-keygen -lf`, e.g. "SHA256:revxAa6o..."
+pub fn ssh_key_fingerprint(key: &str) -> Option<String> {
+    let blob = decode_block(key.trim()).ok()?;
+    if blob.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "SHA256:{}",
+        encode_block(&sha256(&blob)).trim_end_matches('=')
+    ))
+}
+
+#[derive(Debug, Clone)]
+pub struct CertificateOptions {
+    pub common_name: String,
+    pub organization: String,
+    // start of the validity as "YYYY-MM-DD", the current time if empty
+    pub not_before: String,
+    pub days: u32,
+}
+
+impl Default for CertificateOptions {
+    fn default() -> Self {
+        Self {
+            common_name: "IR Toolkit".to_string(),
+            organization: "".to_string(),
+            not_before: "".to_string(),
+            days: 365,
+        }
+    }
+}
+
+// Asn1Time only accepts the format YYYYMMDDHHMMSSZ
+fn parse_date(date: &str) -> Result<Asn1Time, Box<dyn Error>> {
+    let parts: Vec<&str> = date.trim().split('-').collect();
+    let valid = parts.len() == 3
+        && [4, 2, 2]
+            .iter()
+            .zip(&parts)
+            .all(|(len, part)| part.len() == *len && part.chars().all(|c| c.is_ascii_digit()));
+    if !valid {
+        return Err(format!("Invalid date {:?}: expected YYYY-MM-DD", date).into());
+    }
+    Ok(Asn1Time::from_str(&format!("{}000000Z", parts.concat()))?)
+}
+
+/// Create a self-signed X.509 certificate for the key pair, so the public key
+/// can be distributed along with its owner and validity
+pub fn generate_certificate(
+    private_key: &PKey<Private>,
+    public_key: &PKey<Public>,
+    options: &CertificateOptions,
+) -> Result<X509, Box<dyn Error>> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", &options.common_name)?;
+    if !options.organization.is_empty() {
+        name.append_entry_by_text("O", &options.organization)?;
+    }
+    let name = name.build();
+
+    let not_before = match options.not_before.is_empty() {
+        true => Asn1Time::days_from_now(0)?,
+        false => parse_date(&options.not_before)?,
+    };
+    // Asn1Time can't be added to, so the end is calculated in seconds since the epoch
+    let since_epoch = Asn1Time::from_unix(0)?.diff(&not_before)?;
+    let not_after = Asn1Time::from_unix(
+        since_epoch.days as i64 * 86400 + since_epoch.secs as i64 + options.days as i64 * 86400,
+    )?;
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    let serial = serial.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(public_key)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    let mut key_usage = KeyUsage::new();
+    key_usage.critical().digital_signature();
+    if public_key.id() == Id::RSA {
+        key_usage.key_encipherment();
+    }
+    builder.append_extension(key_usage.build()?)?;
+    let subject_key_identifier =
+        SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(subject_key_identifier)?;
+
+    // Ed25519 signs the message itself, without a separate digest
+    let digest = match private_key.id() {
+        Id::ED25519 => MessageDigest::null(),
+        _ => MessageDigest::sha256(),
+    };
+    builder.sign(private_key, digest)?;
+    Ok(builder.build())
+}
+
+pub fn save_certificate(certificate: &X509, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, certificate.to_pem()?)?;
+    Ok(())
+}
@@ -1,18 +1,39 @@
+mod chunks;
 mod crypto_tests;
+mod hashing;
+mod keys;
+mod secrets;
+mod session;
+mod stream;
 use config::workflow::Algorithm;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
 use openssl::pkey::{PKey, Public};
 use openssl::rsa::{Padding, Rsa};
-use openssl::sha::Sha1;
 use openssl::symm::{Cipher, Crypter, Mode};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
+pub use chunks::{check_chunks, salvage_evidence, ChunkCheck, ChunkTagger, CHUNK_SIZE};
+pub use hashing::{Digest, Digests, HashingReader, HashingWriter};
+pub use keys::{
+    certificate_fingerprint, generate_certificate, generate_ed25519_keypair,
+    public_key_fingerprint, save_certificate, ssh_key_fingerprint, CertificateOptions,
+};
+pub use secrets::{SealKey, SealMode, SecretsStore, UnsealKey, SECRETS_PATH};
+use session::get_cipher;
+pub use session::{
+    decrypt_output_file, encrypted_output_path, EncryptingWriter, ReencryptingReader, SessionKey,
+    ENCRYPTED_OUTPUT_EXTENSION,
+};
+pub use stream::{verify_evidence, EvidenceReader, EvidenceWriter};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct EncryptionMeta {
     pub version: String,
     pub algorithm: Algorithm,
@@ -31,15 +52,30 @@ pub struct EncryptionMeta {
         serialize_with = "serialize_vec_hex"
     )]
     pub tag: Vec<u8>,
+    // size of the archive, the encrypted archive has the same size
+    #[serde(default)]
+    pub plaintext_size: u64,
+    // 0 if the archive has no chunk tags (reports before version 1.1)
+    #[serde(default)]
+    pub chunk_size: u64,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_vec_hex_list",
+        serialize_with = "serialize_vec_hex_list"
+    )]
+    pub chunk_tags: Vec<Vec<u8>>,
 }
 impl Default for EncryptionMeta {
     fn default() -> Self {
         Self {
-            version: "1.0".to_string(),
+            version: "1.1".to_string(),
             algorithm: Algorithm::None,
             encrypted_key: vec![],
             iv: vec![],
             tag: vec![],
+            plaintext_size: 0,
+            chunk_size: 0,
+            chunk_tags: vec![],
         }
     }
 }
@@ -62,6 +98,23 @@ where
     serializer.serialize_str(&hex::encode(data))
 }
 
+fn deserialize_vec_hex_list<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let list: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+    list.iter()
+        .map(|s| hex::decode(s).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn serialize_vec_hex_list<S>(data: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(data.iter().map(hex::encode))
+}
+
 /// Generate a symmetric key of the given size
 pub fn generate_random(size: usize) -> Vec<u8> {
     let mut key = vec![0; size];
@@ -195,8 +248,106 @@ pub fn get_metadata(input_path: &Path) -> Result<EncryptionMeta, Box<dyn std::er
     Ok(metadata)
 }
 
-const BLOCK_SIZE: usize = 4096 * 4;
+// fsync the encrypted data and update the journal after this many bytes
+const SYNC_INTERVAL: u64 = 64 * 1024 * 1024;
+
+/// Progress of an encryption that has not finished yet. The journal is stored next to
+/// the evidence file and removed once the encrypted file has replaced the original.
+/// If it still exists, the encryption was interrupted and the original file is untouched
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct EncryptionJournal {
+    pub algorithm: Algorithm,
+    pub file_size: u64,
+    // number of bytes encrypted and synced to the temporary file
+    pub offset: u64,
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Path of the temporary file the encrypted data is written to
+pub fn encryption_temp_path(output_path: &Path) -> PathBuf {
+    with_suffix(output_path, ".part")
+}
+
+/// Path of the journal of an unfinished encryption
+pub fn encryption_journal_path(output_path: &Path) -> PathBuf {
+    with_suffix(output_path, ".journal")
+}
+
+/// Returns the journal if a previous encryption of the file was interrupted
+pub fn read_encryption_journal(output_path: &Path) -> Option<EncryptionJournal> {
+    let file = File::open(encryption_journal_path(output_path)).ok()?;
+    serde_json::from_reader(file).ok()
+}
 
+fn write_encryption_journal(
+    output_path: &Path,
+    journal: &EncryptionJournal,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // write to a separate file first, so the journal itself is never half written
+    let journal_path = encryption_journal_path(output_path);
+    let tmp_path = with_suffix(&journal_path, ".tmp");
+    let mut file = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut file, journal)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &journal_path)?;
+    Ok(())
+}
+
+/// Files in the directory whose encryption was interrupted, i.e. which are still unencrypted
+/// and have a journal or temporary file next to them
+pub fn interrupted_encryptions(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let original = name
+                .strip_suffix(".journal")
+                .or_else(|| name.strip_suffix(".part"))?;
+            Some(dir.join(original))
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+// Removes the temporary file and the journal of an unfinished encryption
+fn remove_encryption_state(output_path: &Path) {
+    for path in [
+        encryption_temp_path(output_path),
+        encryption_journal_path(output_path),
+    ] {
+        if path.exists() {
+            if let Err(e) = fs::remove_file(&path) {
+                error!("Failed to remove {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+// Persists a rename by syncing the parent directory (not supported on Windows)
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Err(e) = File::open(parent).and_then(|dir| dir.sync_all()) {
+            warn!("Failed to sync directory {:?}: {}", parent, e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Encrypts the file into a temporary file which replaces the original once finished.
+/// The encrypted data is synced periodically and the progress is recorded in a journal,
+/// so a crash never leaves a partially encrypted file behind
 pub fn encrypt_evidence(
     output_path: &Path,
     public_key: Rsa<Public>,
@@ -216,46 +367,69 @@ pub fn encrypt_evidence(
         return Ok((vec![], vec![], vec![]));
     }
 
+    // Step 1: Generate a random key
+    let key = SessionKey::generate(algorithm)?;
+
+    // Step 2: Encrypt the key using the public key
+    let encrypted_key = key.encrypt(&public_key)?;
+
+    let metadata = encrypt_evidence_with_key(output_path, &key)?;
+    Ok((encrypted_key, metadata.iv, metadata.tag))
+}
+
+/// Same as encrypt_evidence, but with the session key of the report
+/// (e.g. if action outputs were already encrypted with it). Returns the metadata with the IV,
+/// the tag and the chunk tags, but without the encrypted key
+pub fn encrypt_evidence_with_key(
+    output_path: &Path,
+    key: &SessionKey,
+) -> Result<EncryptionMeta, Box<dyn std::error::Error>> {
+    encrypt_evidence_chunked(output_path, key, CHUNK_SIZE)
+}
+
+pub(crate) fn encrypt_evidence_chunked(
+    output_path: &Path,
+    key: &SessionKey,
+    chunk_size: u64,
+) -> Result<EncryptionMeta, Box<dyn std::error::Error>> {
+    // the cipher state can't be persisted without the plain key,
+    // so an interrupted encryption is started over
+    if let Some(journal) = read_encryption_journal(output_path) {
+        warn!(
+            "Previous encryption of {:?} was interrupted at {} of {} bytes: starting over",
+            output_path, journal.offset, journal.file_size
+        );
+    }
+    remove_encryption_state(output_path);
+
     info!("Encrypting evidence file: {:?}", output_path);
 
     // Step 0: Initialize the sizes
+    let algorithm = key.algorithm;
     let block_size = algorithm.block_size();
-    let key_size = algorithm.key_size();
     let iv_size = algorithm.iv_size();
     let tag_size = algorithm.tag_size();
 
-    // Step 1: Generate a random key
-    let mut key = generate_random(key_size);
-
-    // Step 2: Encrypt the key using the public key
-    let mut encrypted_key = vec![0; public_key.size() as usize];
-    public_key.public_encrypt(&key, &mut encrypted_key, Padding::PKCS1)?;
-
     // Step 3: Initialize crypter and generate a random IV
-    let cipher = match algorithm {
-        Algorithm::AES128GCM => Cipher::aes_128_gcm(),
-        Algorithm::CHACHA20POLY1305 => Cipher::chacha20_poly1305(),
-        _ => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Unsupported algorithm",
-            )))
-        }
-    };
+    let cipher = get_cipher(algorithm)?;
     let iv = generate_random(iv_size);
-    let mut crypter = Crypter::new(cipher, Mode::Encrypt, &key, Some(&iv))?;
-    crypter.pad(false);
+    let mut crypter = key.crypter(Mode::Encrypt, &iv)?;
+    let mut tagger = ChunkTagger::with_chunk_size(key, &iv, chunk_size)?;
 
-    // Step 4: Encrypt the file using the key in-place
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(output_path)?;
+    // Step 4: Encrypt the file into the temporary file
+    let mut file = File::open(output_path)?;
+    let temp_path = encryption_temp_path(output_path);
+    let mut temp_file = File::create(&temp_path)?;
 
-    file.seek(SeekFrom::Start(0))?;
+    let file_size = file.metadata()?.len();
+    let mut journal = EncryptionJournal {
+        algorithm,
+        file_size,
+        offset: 0,
+    };
+    write_encryption_journal(output_path, &journal)?;
 
     // Initialize progress bar
-    let file_size = file.metadata()?.len();
     let pb = ProgressBar::new(file_size);
     pb.set_style(
         ProgressStyle::with_template(
@@ -266,18 +440,24 @@ pub fn encrypt_evidence(
     );
 
     let mut buffer = vec![0u8; block_size];
-    let mut position = 0;
+    let mut ciphertext = vec![0u8; block_size + cipher.block_size()];
+    let mut position: u64 = 0;
     loop {
         let bytes_read = file.read(&mut buffer)?;
         if bytes_read == 0 {
             break;
         }
-        let mut ciphertext = vec![0; bytes_read];
         let count = crypter.update(&buffer[..bytes_read], &mut ciphertext)?;
-        file.seek(SeekFrom::Start(position as u64))?;
-        file.write_all(&ciphertext[..count])?;
-        position += count;
-        pb.set_position(position as u64);
+        temp_file.write_all(&ciphertext[..count])?;
+        tagger.update(&ciphertext[..count])?;
+        position += count as u64;
+        pb.set_position(position);
+
+        if position - journal.offset >= SYNC_INTERVAL {
+            temp_file.sync_data()?;
+            journal.offset = position;
+            write_encryption_journal(output_path, &journal)?;
+        }
     }
     pb.finish_and_clear();
 
@@ -285,17 +465,30 @@ pub fn encrypt_evidence(
     let mut final_buffer = vec![0; block_size];
     let count = crypter.finalize(&mut final_buffer)?;
     if count > 0 {
-        file.seek(SeekFrom::Start(position as u64))?;
-        file.write_all(&buffer[..count])?;
+        temp_file.write_all(&final_buffer[..count])?;
+        tagger.update(&final_buffer[..count])?;
     }
 
     let mut tag = vec![0; tag_size];
     crypter.get_tag(&mut tag)?;
+    let mut metadata = EncryptionMeta {
+        algorithm,
+        iv,
+        tag,
+        ..Default::default()
+    };
+    tagger.finish(&mut metadata)?;
 
-    // Step 6: Disallocate memory for key
-    key.iter_mut().for_each(|b| *b = 0);
+    // Step 6: Replace the original file
+    temp_file.sync_all()?;
+    drop(temp_file);
+    drop(file);
+    fs::rename(&temp_path, output_path)?;
+    sync_parent_dir(output_path);
+    fs::remove_file(encryption_journal_path(output_path))?;
+    debug!("Encrypted {} bytes of {:?}", position, output_path);
 
-    Ok((encrypted_key, iv, tag))
+    Ok(metadata)
 }
 
 pub fn decrypt_evidence(
@@ -386,37 +579,98 @@ pub fn decrypt_evidence(
     Ok(())
 }
 
-pub fn get_file_sha1(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
-    let mut file = File::open(path)?;
-    let mut hasher = Sha1::new();
-    let mut buffer = [0u8; BLOCK_SIZE];
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+/// Decrypts the evidence file and encrypts it again with the new session key into the output
+/// file, e.g. to forward it to another recipient. The plaintext is never written to disk. The
+/// output is removed if the tag of the input is invalid. Returns the metadata with the new IV,
+/// tag and chunk tags, but without the encrypted key
+pub fn reencrypt_evidence(
+    input_path: &Path,
+    output_path: &Path,
+    key: &SessionKey,
+    new_key: &SessionKey,
+    iv: &[u8],
+    tag: &[u8],
+) -> Result<EncryptionMeta, Box<dyn Error>> {
+    if key.algorithm != new_key.algorithm {
+        return Err("The session keys use different algorithms".into());
+    }
+    let algorithm = key.algorithm;
+    let block_size = algorithm.block_size();
+    let mut decrypter = key.crypter(Mode::Decrypt, iv)?;
+    let new_iv = generate_random(algorithm.iv_size());
+    let mut encrypter = new_key.crypter(Mode::Encrypt, &new_iv)?;
+    let mut tagger = ChunkTagger::new(new_key, &new_iv)?;
+
+    let mut input = File::open(input_path)?;
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output_path)?;
+
+    let result = (|| -> Result<EncryptionMeta, Box<dyn Error>> {
+        let cipher_block_size = get_cipher(algorithm)?.block_size();
+        let mut buffer = vec![0u8; block_size];
+        let mut plaintext = vec![0u8; block_size + cipher_block_size];
+        let mut ciphertext = vec![0u8; block_size + cipher_block_size];
+        loop {
+            let bytes_read = input.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            let count = decrypter.update(&buffer[..bytes_read], &mut plaintext)?;
+            let count = encrypter.update(&plaintext[..count], &mut ciphertext)?;
+            output.write_all(&ciphertext[..count])?;
+            tagger.update(&ciphertext[..count])?;
+        }
+        plaintext.iter_mut().for_each(|b| *b = 0);
+
+        // the input must be authentic before the output is used
+        decrypter.set_tag(tag)?;
+        decrypter.finalize(&mut buffer)?;
+        let count = encrypter.finalize(&mut ciphertext)?;
+        output.write_all(&ciphertext[..count])?;
+        tagger.update(&ciphertext[..count])?;
+        let mut new_tag = vec![0; algorithm.tag_size()];
+        encrypter.get_tag(&mut new_tag)?;
+        output.sync_all()?;
+        let mut metadata = EncryptionMeta {
+            algorithm,
+            iv: new_iv,
+            tag: new_tag,
+            ..Default::default()
+        };
+        tagger.finish(&mut metadata)?;
+        Ok(metadata)
+    })();
+
+    match result {
+        Ok(metadata) => Ok(metadata),
+        Err(e) => {
+            drop(output);
+            if let Err(e) = fs::remove_file(output_path) {
+                error!("Failed to remove {:?}: {}", output_path, e);
+            }
+            Err(e)
         }
-        hasher.update(&buffer[..bytes_read]);
     }
-    Ok(format!("{:0>40}", hex::encode(hasher.finish())))
+}
+
+pub fn get_file_sha1(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
+    get_reader_sha1(File::open(path)?)
+}
+
+// e.g. to hash an entry of an archive without extracting it
+pub fn get_reader_sha1<R: Read>(reader: R) -> Result<String, Box<dyn std::error::Error>> {
+    let digests = HashingReader::new(reader, &[Digest::Sha1])?.digest_remaining()?;
+    Ok(digests.hex(Digest::Sha1))
 }
 
 pub fn copy_file_with_sha1(
     src: &PathBuf,
     dest: &PathBuf,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let mut src_file = File::open(src)?;
-    let mut dest_file = File::create(dest)?;
-    let mut hasher = Sha1::new();
-    let mut buffer = [0u8; BLOCK_SIZE];
-
-    loop {
-        let bytes_read = src_file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
-        }
-        dest_file.write_all(&buffer[..bytes_read])?;
-        hasher.update(&buffer[..bytes_read]);
-    }
-
-    Ok(format!("{:0>40}", hex::encode(hasher.finish())))
+    let mut writer = HashingWriter::new(File::create(dest)?, &[Digest::Sha1])?;
+    io::copy(&mut File::open(src)?, &mut writer)?;
+    let (_, digests) = writer.finish()?;
+    Ok(digests.hex(Digest::Sha1))
 }
@@ -0,0 +1,229 @@
+// Credentials of the upload and notification targets, kept encrypted next to the config instead
+// of in plaintext. Each value is sealed on its own, so a value can be added with the public key
+// alone. Its name is bound to it, so sealed values can't be swapped between names
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::pkey::{Private, Public};
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use crate::{deserialize_vec_hex, generate_random, serialize_vec_hex};
+
+/// Encrypted credentials in the root directory of the toolkit
+pub const SECRETS_PATH: &str = "secrets.json";
+
+const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const SALT_SIZE: usize = 16;
+const PBKDF2_ITERATIONS: usize = 200_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SealMode {
+    Passphrase,
+    PublicKey,
+}
+
+/// Key to seal new values with
+pub enum SealKey {
+    Passphrase(String),
+    PublicKey(Rsa<Public>),
+}
+
+/// Key to unseal the values with
+pub enum UnsealKey {
+    Passphrase(String),
+    PrivateKey(Rsa<Private>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct SealedValue {
+    // of the key derivation, empty if sealed to a public key
+    #[serde(
+        deserialize_with = "deserialize_vec_hex",
+        serialize_with = "serialize_vec_hex"
+    )]
+    salt: Vec<u8>,
+    // the value key encrypted with the public key, empty if sealed with a passphrase
+    #[serde(
+        deserialize_with = "deserialize_vec_hex",
+        serialize_with = "serialize_vec_hex"
+    )]
+    encrypted_key: Vec<u8>,
+    #[serde(
+        deserialize_with = "deserialize_vec_hex",
+        serialize_with = "serialize_vec_hex"
+    )]
+    iv: Vec<u8>,
+    #[serde(
+        deserialize_with = "deserialize_vec_hex",
+        serialize_with = "serialize_vec_hex"
+    )]
+    tag: Vec<u8>,
+    #[serde(
+        deserialize_with = "deserialize_vec_hex",
+        serialize_with = "serialize_vec_hex"
+    )]
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SecretsStore {
+    pub version: String,
+    pub mode: SealMode,
+    // PEM of the public key the values are sealed to, so values can be added without it
+    #[serde(default)]
+    pub public_key: Option<String>,
+    // by config key, e.g. upload.targets.0.secret_key
+    values: BTreeMap<String, SealedValue>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut key = vec![0; KEY_SIZE];
+    pbkdf2_hmac(
+        passphrase.as_bytes(),
+        salt,
+        PBKDF2_ITERATIONS,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+impl SecretsStore {
+    pub fn new(key: &SealKey) -> Result<Self, Box<dyn Error>> {
+        let (mode, public_key) = match key {
+            SealKey::Passphrase(_) => (SealMode::Passphrase, None),
+            SealKey::PublicKey(public_key) => (
+                SealMode::PublicKey,
+                Some(String::from_utf8(public_key.public_key_to_pem()?)?),
+            ),
+        };
+        Ok(Self {
+            version: "1.0".to_string(),
+            mode,
+            public_key,
+            values: BTreeMap::new(),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.values.keys().map(String::as_str).collect()
+    }
+
+    /// Key to add values to a store sealed to a public key, None for a passphrase
+    pub fn public_seal_key(&self) -> Result<Option<SealKey>, Box<dyn Error>> {
+        match (&self.mode, &self.public_key) {
+            (SealMode::Passphrase, _) => Ok(None),
+            (SealMode::PublicKey, Some(pem)) => Ok(Some(SealKey::PublicKey(
+                Rsa::public_key_from_pem(pem.as_bytes())?,
+            ))),
+            (SealMode::PublicKey, None) => Err("The public key of the secrets is missing".into()),
+        }
+    }
+
+    /// Seals the value under its name, replacing a previous value
+    pub fn set(&mut self, name: &str, value: &str, key: &SealKey) -> Result<(), Box<dyn Error>> {
+        let sealed_mode = match key {
+            SealKey::Passphrase(_) => SealMode::Passphrase,
+            SealKey::PublicKey(_) => SealMode::PublicKey,
+        };
+        if sealed_mode != self.mode {
+            return Err(format!("The secrets are sealed with a {:?} key", self.mode).into());
+        }
+        let (salt, value_key, encrypted_key) = match key {
+            SealKey::Passphrase(passphrase) => {
+                // a typo in the passphrase would make the other values unreadable
+                if let Some(other) = self.values.keys().next().cloned() {
+                    self.get(&other, &UnsealKey::Passphrase(passphrase.clone()))
+                        .map_err(|_| "The passphrase doesn't match the other secrets")?;
+                }
+                let salt = generate_random(SALT_SIZE);
+                let value_key = derive_key(passphrase, &salt)?;
+                (salt, value_key, vec![])
+            }
+            SealKey::PublicKey(public_key) => {
+                let value_key = generate_random(KEY_SIZE);
+                let mut encrypted_key = vec![0; public_key.size() as usize];
+                public_key.public_encrypt(&value_key, &mut encrypted_key, Padding::PKCS1)?;
+                (vec![], value_key, encrypted_key)
+            }
+        };
+        let iv = generate_random(IV_SIZE);
+        let mut tag = vec![0; TAG_SIZE];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &value_key,
+            Some(&iv),
+            name.as_bytes(),
+            value.as_bytes(),
+            &mut tag,
+        )?;
+        self.values.insert(
+            name.to_string(),
+            SealedValue {
+                salt,
+                encrypted_key,
+                iv,
+                tag,
+                ciphertext,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.values.remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str, key: &UnsealKey) -> Result<String, Box<dyn Error>> {
+        let sealed = self
+            .values
+            .get(name)
+            .ok_or_else(|| format!("Unknown secret {:?}", name))?;
+        let value_key = match key {
+            UnsealKey::Passphrase(passphrase) => derive_key(passphrase, &sealed.salt)?,
+            UnsealKey::PrivateKey(private_key) => {
+                let mut value_key = vec![0; private_key.size() as usize];
+                let size = private_key.private_decrypt(
+                    &sealed.encrypted_key,
+                    &mut value_key,
+                    Padding::PKCS1,
+                )?;
+                value_key.truncate(size);
+                value_key
+            }
+        };
+        let value = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &value_key,
+            Some(&sealed.iv),
+            name.as_bytes(),
+            &sealed.ciphertext,
+            &sealed.tag,
+        )
+        .map_err(|_| format!("Failed to unseal secret {:?}: wrong key", name))?;
+        Ok(String::from_utf8(value)?)
+    }
+
+    /// All values by name. Fails if any value can't be unsealed
+    pub fn unseal(&self, key: &UnsealKey) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        self.values
+            .keys()
+            .map(|name| Ok((name.clone(), self.get(name, key)?)))
+            .collect()
+    }
+}
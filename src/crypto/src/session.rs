@@ -0,0 +1,325 @@
+// Session key shared by the archive and the action outputs encrypted while the workflow runs
+use crate::generate_random;
+use config::workflow::Algorithm;
+use openssl::pkey::{Private, Public};
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{Cipher, Crypter, Mode};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// File extension of an action output written through the EncryptingWriter
+pub const ENCRYPTED_OUTPUT_EXTENSION: &str = "enc";
+
+// Identifies an encrypted output file, followed by the IV
+const OUTPUT_MAGIC: &[u8; 8] = b"IRTENC1\0";
+
+pub(crate) fn get_cipher(algorithm: Algorithm) -> Result<Cipher, Box<dyn Error>> {
+    match algorithm {
+        Algorithm::AES128GCM => Ok(Cipher::aes_128_gcm()),
+        Algorithm::CHACHA20POLY1305 => Ok(Cipher::chacha20_poly1305()),
+        _ => Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unsupported algorithm",
+        ))),
+    }
+}
+
+/// Symmetric key generated once per report. Only the key encrypted with the
+/// public key is stored (encryption.json), the plain key is wiped on drop.
+pub struct SessionKey {
+    pub algorithm: Algorithm,
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SessionKey")
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SessionKey {
+    fn drop(&mut self) {
+        self.key.iter_mut().for_each(|b| *b = 0);
+    }
+}
+
+impl SessionKey {
+    pub fn generate(algorithm: Algorithm) -> Result<Self, Box<dyn Error>> {
+        get_cipher(algorithm)?;
+        Ok(Self {
+            algorithm,
+            key: generate_random(algorithm.key_size()),
+        })
+    }
+
+    /// Decrypt the session key of a report with the private key
+    pub fn decrypt(
+        private_key: &Rsa<Private>,
+        algorithm: Algorithm,
+        encrypted_key: &[u8],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut key = vec![0; private_key.size() as usize];
+        let size = private_key.private_decrypt(encrypted_key, &mut key, Padding::PKCS1)?;
+        key.truncate(size.min(algorithm.key_size()));
+        Ok(Self { algorithm, key })
+    }
+
+    /// Encrypt the session key with the public key, so only the owner of the private key can use it
+    pub fn encrypt(&self, public_key: &Rsa<Public>) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut encrypted_key = vec![0; public_key.size() as usize];
+        public_key.public_encrypt(&self.key, &mut encrypted_key, Padding::PKCS1)?;
+        Ok(encrypted_key)
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub(crate) fn crypter(&self, mode: Mode, iv: &[u8]) -> Result<Crypter, Box<dyn Error>> {
+        let mut crypter = Crypter::new(get_cipher(self.algorithm)?, mode, &self.key, Some(iv))?;
+        crypter.pad(false);
+        Ok(crypter)
+    }
+
+    /// Crypter of the stream cipher underlying the AEAD, starting at a block of the keystream.
+    /// It decrypts at any position of the data, but doesn't verify the tag
+    pub(crate) fn keystream_crypter(
+        &self,
+        iv: &[u8],
+        block: u64,
+    ) -> Result<Crypter, Box<dyn Error>> {
+        let (cipher, counter) = match self.algorithm {
+            // GCM starts encrypting with counter 2 (the 32 bit counter follows the 96 bit IV)
+            Algorithm::AES128GCM => {
+                let counter = (block + 2) as u32;
+                (Cipher::aes_128_ctr(), [iv, &counter.to_be_bytes()].concat())
+            }
+            // ChaCha20-Poly1305 starts with counter 1 (the 32 bit counter precedes the 96 bit nonce)
+            Algorithm::CHACHA20POLY1305 => {
+                let counter = (block + 1) as u32;
+                (Cipher::chacha20(), [&counter.to_le_bytes(), iv].concat())
+            }
+            _ => return Err("Unsupported algorithm".into()),
+        };
+        let mut crypter = Crypter::new(cipher, Mode::Decrypt, &self.key, Some(&counter))?;
+        crypter.pad(false);
+        Ok(crypter)
+    }
+}
+
+/// Encrypts everything written to it. The file starts with a magic and the IV,
+/// the authentication tag is appended once the writer is finished.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    crypter: Crypter,
+    buffer: Vec<u8>,
+    tag_size: usize,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(mut inner: W, key: &SessionKey) -> Result<Self, Box<dyn Error>> {
+        let iv = generate_random(key.algorithm.iv_size());
+        let crypter = key.crypter(Mode::Encrypt, &iv)?;
+        inner.write_all(OUTPUT_MAGIC)?;
+        inner.write_all(&iv)?;
+        Ok(Self {
+            inner,
+            crypter,
+            buffer: vec![],
+            tag_size: key.algorithm.tag_size(),
+        })
+    }
+
+    /// Write the remaining data and the tag. Without it the file can't be decrypted.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.buffer.resize(self.tag_size.max(32), 0);
+        let count = self.crypter.finalize(&mut self.buffer)?;
+        self.inner.write_all(&self.buffer[..count])?;
+        let mut tag = vec![0; self.tag_size];
+        self.crypter.get_tag(&mut tag)?;
+        self.inner.write_all(&tag)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // stream ciphers need at most the input size, block ciphers one block more
+        self.buffer.resize(buf.len() + 32, 0);
+        let count = self.crypter.update(buf, &mut self.buffer)?;
+        self.inner.write_all(&self.buffer[..count])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads an output file written by the EncryptingWriter and encrypts it with another session key,
+/// e.g. to forward a report. The size of the file locates the tag, which is verified before
+/// the end of the re-encrypted file is returned
+pub struct ReencryptingReader<R: Read> {
+    inner: R,
+    decrypter: Crypter,
+    encrypter: Crypter,
+    tag_size: usize,
+    // ciphertext before the tag, which hasn't been read yet
+    remaining: u64,
+    // re-encrypted data which hasn't been returned yet
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> ReencryptingReader<R> {
+    pub fn new(
+        mut inner: R,
+        size: u64,
+        key: &SessionKey,
+        new_key: &SessionKey,
+    ) -> Result<Self, Box<dyn Error>> {
+        if key.algorithm != new_key.algorithm {
+            return Err("The session keys use different algorithms".into());
+        }
+        let iv_size = key.algorithm.iv_size();
+        let tag_size = key.algorithm.tag_size();
+        let header_size = (OUTPUT_MAGIC.len() + iv_size) as u64;
+        if size < header_size + tag_size as u64 {
+            return Err("Encrypted output is too short".into());
+        }
+        let mut magic = [0u8; 8];
+        inner.read_exact(&mut magic)?;
+        if &magic != OUTPUT_MAGIC {
+            return Err("Not an encrypted output file".into());
+        }
+        let mut iv = vec![0; iv_size];
+        inner.read_exact(&mut iv)?;
+        let new_iv = generate_random(iv_size);
+        let mut pending = OUTPUT_MAGIC.to_vec();
+        pending.extend_from_slice(&new_iv);
+        Ok(Self {
+            decrypter: key.crypter(Mode::Decrypt, &iv)?,
+            encrypter: new_key.crypter(Mode::Encrypt, &new_iv)?,
+            inner,
+            tag_size,
+            remaining: size - header_size - tag_size as u64,
+            pending,
+            finished: false,
+        })
+    }
+
+    // re-encrypts the next part of the file, or its tag once all data was read
+    fn fill(&mut self) -> io::Result<()> {
+        if self.remaining > 0 {
+            let size = self.remaining.min(16 * 1024) as usize;
+            let mut buffer = vec![0u8; size];
+            self.inner.read_exact(&mut buffer)?;
+            let mut plaintext = vec![0u8; size + 32];
+            let count = self.decrypter.update(&buffer, &mut plaintext)?;
+            self.pending.resize(count + 32, 0);
+            let count = self
+                .encrypter
+                .update(&plaintext[..count], &mut self.pending)?;
+            self.pending.truncate(count);
+            plaintext.iter_mut().for_each(|b| *b = 0);
+            self.remaining -= size as u64;
+            return Ok(());
+        }
+        let mut tag = vec![0; self.tag_size];
+        self.inner.read_exact(&mut tag)?;
+        self.decrypter.set_tag(&tag)?;
+        let mut buffer = vec![0u8; 32];
+        self.decrypter.finalize(&mut buffer).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The encrypted output was modified: the authentication tag doesn't match",
+            )
+        })?;
+        let count = self.encrypter.finalize(&mut buffer)?;
+        self.pending = buffer[..count].to_vec();
+        let mut new_tag = vec![0; self.tag_size];
+        self.encrypter.get_tag(&mut new_tag)?;
+        self.pending.extend_from_slice(&new_tag);
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ReencryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() && !self.finished {
+            self.fill()?;
+        }
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+/// Path of the encrypted file, e.g. whoami.log -> whoami.log.enc
+pub fn encrypted_output_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(ENCRYPTED_OUTPUT_EXTENSION);
+    path.with_file_name(file_name)
+}
+
+/// Decrypt an output file written by the EncryptingWriter next to it (without the .enc extension).
+/// The encrypted file is removed once the tag was verified.
+pub fn decrypt_output_file(path: &Path, key: &SessionKey) -> Result<PathBuf, Box<dyn Error>> {
+    let iv_size = key.algorithm.iv_size();
+    let tag_size = key.algorithm.tag_size();
+    let file_size = fs::metadata(path)?.len();
+    let header_size = (OUTPUT_MAGIC.len() + iv_size) as u64;
+    if file_size < header_size + tag_size as u64 {
+        return Err("Encrypted output is too short".into());
+    }
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != OUTPUT_MAGIC {
+        return Err("Not an encrypted output file".into());
+    }
+    let mut iv = vec![0; iv_size];
+    reader.read_exact(&mut iv)?;
+    let mut crypter = key.crypter(Mode::Decrypt, &iv)?;
+
+    let output_path = path.with_extension("");
+    let mut writer = BufWriter::new(File::create(&output_path)?);
+    let mut remaining = file_size - header_size - tag_size as u64;
+    let mut buffer = vec![0u8; 16 * 1024];
+    let mut plaintext = vec![0u8; buffer.len() + 32];
+    while remaining > 0 {
+        let size = remaining.min(buffer.len() as u64) as usize;
+        reader.read_exact(&mut buffer[..size])?;
+        let count = crypter.update(&buffer[..size], &mut plaintext)?;
+        writer.write_all(&plaintext[..count])?;
+        remaining -= size as u64;
+    }
+
+    let mut tag = vec![0; tag_size];
+    reader.read_exact(&mut tag)?;
+    crypter.set_tag(&tag)?;
+    // finalize fails if the tag is invalid
+    let result = crypter.finalize(&mut plaintext);
+    let count = match result {
+        Ok(count) => count,
+        Err(e) => {
+            drop(writer);
+            let _ = fs::remove_file(&output_path);
+            return Err(Box::new(e));
+        }
+    };
+    writer.write_all(&plaintext[..count])?;
+    writer.flush()?;
+    drop(reader);
+    fs::remove_file(path)?;
+    Ok(output_path)
+}
@@ -0,0 +1,189 @@
+// Reads the encrypted archive without writing a decrypted copy to disk, e.g. to pipe it into other tools
+use crate::session::SessionKey;
+use crate::{generate_random, ChunkTagger, EncryptionMeta};
+use config::workflow::Algorithm;
+use openssl::symm::{Crypter, Mode};
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// size of a keystream block of the cipher underlying the AEAD
+fn keystream_block_size(algorithm: Algorithm) -> u64 {
+    match algorithm {
+        Algorithm::CHACHA20POLY1305 => 64,
+        _ => 16,
+    }
+}
+
+/// Decrypts the archive while it is read. Seeking is supported, so single entries
+/// can be read by the zip crate. The tag is not verified, use `verify_evidence` first
+pub struct EvidenceReader<R: Read + Seek> {
+    inner: R,
+    key: SessionKey,
+    iv: Vec<u8>,
+    position: u64,
+    // None after a seek, created again at the new position on the next read
+    crypter: Option<Crypter>,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read + Seek> EvidenceReader<R> {
+    pub fn new(mut inner: R, key: SessionKey, iv: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let position = inner.stream_position()?;
+        // fails early for an unsupported algorithm
+        key.keystream_crypter(iv, 0)?;
+        Ok(Self {
+            inner,
+            key,
+            iv: iv.to_vec(),
+            position,
+            crypter: None,
+            buffer: vec![],
+        })
+    }
+
+    fn crypter(&mut self) -> io::Result<&mut Crypter> {
+        if self.crypter.is_none() {
+            let block_size = keystream_block_size(self.key.algorithm);
+            let mut crypter = self
+                .key
+                .keystream_crypter(&self.iv, self.position / block_size)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            // skip the keystream of the data before the position inside the block
+            let offset = (self.position % block_size) as usize;
+            let mut skipped = vec![0u8; offset + block_size as usize];
+            crypter.update(&vec![0u8; offset], &mut skipped)?;
+            self.crypter = Some(crypter);
+        }
+        Ok(self.crypter.as_mut().unwrap())
+    }
+}
+
+impl<R: Read + Seek> Read for EvidenceReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        if count == 0 {
+            return Ok(0);
+        }
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.resize(count + 64, 0);
+        let decrypted = self.crypter()?.update(&buf[..count], &mut buffer)?;
+        buf[..decrypted].copy_from_slice(&buffer[..decrypted]);
+        self.buffer = buffer;
+        self.position += count as u64;
+        Ok(decrypted)
+    }
+}
+
+impl<R: Read + Seek> Seek for EvidenceReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        if position != self.position {
+            self.position = position;
+            self.crypter = None;
+        }
+        Ok(position)
+    }
+}
+
+/// Verifies the tag of the encrypted archive without writing the decrypted content
+pub fn verify_evidence<R: Read>(
+    mut reader: R,
+    key: &SessionKey,
+    iv: &[u8],
+    tag: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let block_size = key.algorithm.block_size();
+    let mut crypter = key.crypter(Mode::Decrypt, iv)?;
+    let mut buffer = vec![0u8; block_size];
+    let mut plaintext = vec![0u8; block_size + 32];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        crypter.update(&buffer[..bytes_read], &mut plaintext)?;
+    }
+    crypter.set_tag(tag)?;
+    crypter
+        .finalize(&mut plaintext)
+        .map_err(|_| "The archive was modified: the authentication tag doesn't match")?;
+    Ok(())
+}
+
+/// Encrypts the archive while it is written, e.g. to re-encrypt a report without writing a
+/// decrypted copy to disk. Seeking only returns the position, the data can only be appended
+pub struct EvidenceWriter<W: Write> {
+    inner: W,
+    algorithm: Algorithm,
+    iv: Vec<u8>,
+    crypter: Crypter,
+    tagger: ChunkTagger,
+    position: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EvidenceWriter<W> {
+    pub fn new(inner: W, key: &SessionKey) -> Result<Self, Box<dyn Error>> {
+        let iv = generate_random(key.algorithm.iv_size());
+        Ok(Self {
+            crypter: key.crypter(Mode::Encrypt, &iv)?,
+            tagger: ChunkTagger::new(key, &iv)?,
+            algorithm: key.algorithm,
+            iv,
+            inner,
+            position: 0,
+            buffer: vec![],
+        })
+    }
+
+    /// Writes the remaining data. Returns the metadata with the IV, the tag and the chunk tags,
+    /// but without the encrypted key
+    pub fn finish(mut self) -> Result<(W, EncryptionMeta), Box<dyn Error>> {
+        self.buffer.resize(32, 0);
+        let count = self.crypter.finalize(&mut self.buffer)?;
+        self.inner.write_all(&self.buffer[..count])?;
+        self.tagger.update(&self.buffer[..count])?;
+        self.inner.flush()?;
+        let mut tag = vec![0; self.algorithm.tag_size()];
+        self.crypter.get_tag(&mut tag)?;
+        let mut metadata = EncryptionMeta {
+            algorithm: self.algorithm,
+            iv: self.iv,
+            tag,
+            ..Default::default()
+        };
+        self.tagger.finish(&mut metadata)?;
+        Ok((self.inner, metadata))
+    }
+}
+
+impl<W: Write> Write for EvidenceWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // stream ciphers need at most the input size, block ciphers one block more
+        self.buffer.resize(buf.len() + 32, 0);
+        let count = self.crypter.update(buf, &mut self.buffer)?;
+        self.inner.write_all(&self.buffer[..count])?;
+        self.tagger
+            .update(&self.buffer[..count])
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Seek for EvidenceWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) | SeekFrom::End(0) => Ok(self.position),
+            SeekFrom::Start(position) if position == self.position => Ok(position),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The encrypted archive can only be appended to",
+            )),
+        }
+    }
+}
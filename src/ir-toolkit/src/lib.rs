@@ -0,0 +1,51 @@
+//! Library interface of the IR Toolkit.
+//!
+//! The collector and unpacker binaries are thin wrappers around the crates of this workspace.
+//! This crate re-exports the parts needed to run workflows and to package or unpack reports,
+//! so other tools (e.g. an EDR agent) can embed them without invoking the binaries.
+//!
+//! Only the items re-exported here are part of the stable API. Breaking changes to them
+//! require a new major version (or minor version while still at `0.x`).
+//! The re-exported structs are `#[non_exhaustive]`, as fields may be added in a minor version:
+//! create them with their constructors (e.g. [`Workflow::init`]) instead of a struct literal.
+//! The internal crates might change at any time.
+//!
+//! On Windows, steps with `run_as` start the running executable again, which starts the
+//! process of the step as the user: call [`run_as_proxy`] first thing in `main`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ir_toolkit::{run_workflow_file, SystemVariables};
+//! use std::path::PathBuf;
+//!
+//! let mut system_variables = SystemVariables::new();
+//! let workflow = PathBuf::from("workflows/triage.yaml");
+//! match run_workflow_file(&workflow, &mut system_variables) {
+//!     Ok(Some(report_dir)) => println!("Report written to {:?}", report_dir),
+//!     Ok(None) => println!("Launch conditions not met"),
+//!     Err(e) => eprintln!("Collection failed: {}", e),
+//! }
+//! ```
+
+pub use config::workflow::{Algorithm, Reporting, WorkflowRunner};
+pub use privileges::run_as_proxy;
+pub use report::{
+    Report, ACTION_LOG_DIR, ENCRYPTION_PATH, LOOT_DIR, METADATA_PATH, SEVEN_ZIP_PATH, STORAGE_DIR,
+    TAR_ZSTD_PATH, ZIP_PATH,
+};
+pub use storage::{read_metadata, FileMeta, FileProcessor};
+pub use system::SystemVariables;
+pub use workflow::{
+    handler::{run_workflow_file, WorkflowHandler},
+    launch_conditions::check_launch_conditions,
+    runner::Workflow,
+};
+
+/// Key handling and encryption of the report archive
+pub mod crypto {
+    pub use ::crypto::{
+        decrypt_evidence, encrypt_evidence, generate_rsa_keypair, get_file_sha1, get_metadata,
+        load_private_key, load_public_key, save_keypair, EncryptionMeta,
+    };
+}
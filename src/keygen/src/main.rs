@@ -1,7 +1,11 @@
 use clap::{Arg, Command};
-use crypto::{generate_rsa_keypair, save_keypair};
+use crypto::{
+    certificate_fingerprint, generate_certificate, generate_ed25519_keypair, generate_rsa_keypair,
+    public_key_fingerprint, save_certificate, save_keypair, CertificateOptions,
+};
 use log::{error, info, LevelFilter};
 use logging::Logger;
+use std::path::Path;
 fn main() {
     let matches = get_command().get_matches();
 
@@ -20,13 +24,22 @@ fn main() {
 fn get_command() -> Command {
     Command::new("Keygen")
         .version("1.0")
-        .about("Generates an RSA key pair")
+        .about("Generates an RSA key pair (encryption) or an Ed25519 key pair (signing)")
+        .arg(
+            Arg::new("type")
+                .short('t')
+                .long("type")
+                .value_name("TYPE")
+                .help("The type of the key pair")
+                .value_parser(["rsa", "ed25519"])
+                .default_value("rsa"),
+        )
         .arg(
             Arg::new("size")
                 .short('s')
                 .long("size")
                 .value_name("SIZE")
-                .help("The size of the RSA key")
+                .help("The size of the RSA key (ignored for Ed25519)")
                 .value_parser(clap::value_parser!(u32))
                 .default_value("2048"),
         )
@@ -46,6 +59,44 @@ fn get_command() -> Command {
                 .required(true)
                 .help("The filename for the public key (e.g. public_key.pem)"),
         )
+        .arg(
+            Arg::new("certificate")
+                .short('c')
+                .long("certificate")
+                .value_name("CERTIFICATE")
+                .help("Also create a self-signed X.509 certificate for the public key (e.g. certificate.pem)"),
+        )
+        .arg(
+            Arg::new("common_name")
+                .long("common-name")
+                .value_name("NAME")
+                .requires("certificate")
+                .help("The common name (CN) of the certificate")
+                .default_value("IR Toolkit"),
+        )
+        .arg(
+            Arg::new("organization")
+                .long("organization")
+                .value_name("ORGANIZATION")
+                .requires("certificate")
+                .help("The organization (O) of the certificate"),
+        )
+        .arg(
+            Arg::new("not_before")
+                .long("not-before")
+                .value_name("YYYY-MM-DD")
+                .requires("certificate")
+                .help("The start of the validity of the certificate (default: now)"),
+        )
+        .arg(
+            Arg::new("days")
+                .long("days")
+                .value_name("DAYS")
+                .requires("certificate")
+                .help("The number of days the certificate is valid")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("365"),
+        )
         .arg(
             Arg::new("verbose")
                 .short('v')
@@ -57,18 +108,59 @@ fn get_command() -> Command {
 
 fn run(matches: clap::ArgMatches) {
     let size: u32 = matches.get_one::<u32>("size").unwrap().clone();
+    let key_type = matches.get_one::<String>("type").unwrap();
 
     let private_key_file = matches.get_one::<String>("private_key").unwrap();
     let public_key_file = matches.get_one::<String>("public_key").unwrap();
 
-    match generate_rsa_keypair(size) {
-        Ok((private_key, public_key)) => {
-            match save_keypair(private_key, public_key, private_key_file, public_key_file) {
-                Ok(_) => info!("Successfully generated RSA key pair"),
-                Err(e) => error!("Failed to save RSA key pair: {}", e),
+    let keypair = match key_type.as_str() {
+        "ed25519" => generate_ed25519_keypair(),
+        _ => generate_rsa_keypair(size),
+    };
+    let (private_key, public_key) = match keypair {
+        Ok(keypair) => keypair,
+        Err(e) => {
+            error!("Failed to generate {} key pair: {}", key_type, e);
+            return;
+        }
+    };
+
+    // the fingerprint allows recipients to check they received the right public key
+    match public_key_fingerprint(&public_key) {
+        Ok(fingerprint) => info!("SHA-256 fingerprint of the public key: {}", fingerprint),
+        Err(e) => error!("Failed to calculate fingerprint: {}", e),
+    }
+
+    if let Some(certificate_file) = matches.get_one::<String>("certificate") {
+        let options = CertificateOptions {
+            common_name: matches.get_one::<String>("common_name").unwrap().clone(),
+            organization: matches
+                .get_one::<String>("organization")
+                .cloned()
+                .unwrap_or_default(),
+            not_before: matches
+                .get_one::<String>("not_before")
+                .cloned()
+                .unwrap_or_default(),
+            days: *matches.get_one::<u32>("days").unwrap(),
+        };
+        let certificate =
+            generate_certificate(&private_key, &public_key, &options).and_then(|certificate| {
+                save_certificate(&certificate, Path::new(certificate_file))?;
+                certificate_fingerprint(&certificate)
+            });
+        match certificate {
+            Ok(fingerprint) => {
+                info!("Successfully generated certificate: {}", certificate_file);
+                info!("SHA-256 fingerprint of the certificate: {}", fingerprint);
             }
+            Err(e) => error!("Failed to generate certificate: {}", e),
         }
-        Err(e) => error!("Failed to generate RSA key pair: {}", e),
+    }
+
+    match save_keypair(private_key, public_key, private_key_file, public_key_file) {
+        Ok(_) => info!("Successfully generated {} key pair", key_type),
+        Err(e) => error!("Failed to save {} key pair: {}", key_type, e),
     }
 }
 
@@ -150,6 +242,55 @@ mod tests {
         assert_keys_exist_and_valid(&private_key_file, &public_key_file);
     }
 
+    #[test]
+    fn test_keygen_command_ed25519_certificate() {
+        let mut cleanup = Cleanup::new();
+        let temp_dir = cleanup.tmp_dir("test_keygen_command_ed25519_certificate");
+        let private_key_file = temp_dir.join("signing_key.pem");
+        let public_key_file = temp_dir.join("signing_key.pub.pem");
+        let certificate_file = temp_dir.join("signing_cert.pem");
+
+        let matches = test_command()
+            .try_get_matches_from(vec![
+                "keygen",
+                "--type",
+                "ed25519",
+                "--private",
+                private_key_file.to_str().unwrap(),
+                "--public",
+                public_key_file.to_str().unwrap(),
+                "--certificate",
+                certificate_file.to_str().unwrap(),
+                "--organization",
+                "ACME CERT",
+                "--not-before",
+                "2026-01-01",
+                "--days",
+                "30",
+            ])
+            .unwrap();
+
+        run(matches);
+
+        assert_keys_exist_and_valid(&private_key_file, &public_key_file);
+        let certificate = fs::read_to_string(&certificate_file).unwrap();
+        assert!(certificate.contains("-----BEGIN CERTIFICATE-----"));
+    }
+
+    #[test]
+    fn test_keygen_command_certificate_options_require_certificate() {
+        let result = test_command().try_get_matches_from(vec![
+            "keygen",
+            "--private",
+            "private_key.pem",
+            "--public",
+            "public_key.pem",
+            "--organization",
+            "ACME CERT",
+        ]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_keygen_command_invalid_size() {
         let mut cleanup = Cleanup::new();
@@ -0,0 +1,268 @@
+// Rate limits warnings and errors logged over and over from the same place, e.g. thousands of
+// files a store pattern can't read. The first messages are logged, the rest is only counted
+use log::{Level, Log, Metadata, Record};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// messages logged per place and window, before the following ones are suppressed
+const DEDUP_LIMIT: u64 = 10;
+const DEDUP_WINDOW: Duration = Duration::from_secs(60);
+
+static DEDUP: Mutex<Option<Dedup>> = Mutex::new(None);
+static SUPPRESSED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+struct Site {
+    window_start: Instant,
+    logged: u64,
+    suppressed: u64,
+    // first suppressed message, as an example in the summary
+    sample: String,
+}
+
+// summary of the messages suppressed at a place
+#[derive(Debug, PartialEq)]
+struct Suppressed {
+    level: Level,
+    site: String,
+    count: u64,
+    sample: String,
+}
+
+impl Suppressed {
+    fn message(&self) -> String {
+        format!(
+            "Suppressed {} similar messages from {}, e.g.: {}",
+            self.count, self.site, self.sample
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Log,
+    // the limit is reached, following messages are suppressed
+    LogLast,
+    Suppress,
+}
+
+struct Dedup {
+    limit: u64,
+    window: Duration,
+    sites: HashMap<(Level, String), Site>,
+}
+
+impl Dedup {
+    fn new(limit: u64, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            sites: HashMap::new(),
+        }
+    }
+
+    // the summary of the previous window is returned once a new window starts
+    fn check(
+        &mut self,
+        level: Level,
+        site: &str,
+        message: impl FnOnce() -> String,
+        now: Instant,
+    ) -> (Verdict, Option<Suppressed>) {
+        let entry = self.sites.entry((level, site.to_string())).or_insert(Site {
+            window_start: now,
+            logged: 0,
+            suppressed: 0,
+            sample: String::new(),
+        });
+        let mut summary = None;
+        if now.duration_since(entry.window_start) >= self.window {
+            if entry.suppressed > 0 {
+                summary = Some(Suppressed {
+                    level,
+                    site: site.to_string(),
+                    count: entry.suppressed,
+                    sample: std::mem::take(&mut entry.sample),
+                });
+            }
+            entry.window_start = now;
+            entry.logged = 0;
+            entry.suppressed = 0;
+        }
+        if entry.logged < self.limit {
+            entry.logged += 1;
+            let verdict = match entry.logged == self.limit {
+                true => Verdict::LogLast,
+                false => Verdict::Log,
+            };
+            return (verdict, summary);
+        }
+        if entry.suppressed == 0 {
+            entry.sample = message();
+        }
+        entry.suppressed += 1;
+        (Verdict::Suppress, summary)
+    }
+
+    // summaries of all places with suppressed messages, the counting starts over
+    fn drain(&mut self) -> Vec<Suppressed> {
+        let mut summaries: Vec<Suppressed> = self
+            .sites
+            .drain()
+            .filter(|(_, site)| site.suppressed > 0)
+            .map(|((level, name), site)| Suppressed {
+                level,
+                site: name,
+                count: site.suppressed,
+                sample: site.sample,
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.site.cmp(&b.site));
+        summaries
+    }
+}
+
+fn site(record: &Record) -> String {
+    format!("{}:{}", record.target(), record.line().unwrap_or(0))
+}
+
+/// Passes the records to the inner logger, except the warnings and errors above the limit
+pub struct DedupLog {
+    inner: Box<dyn Log>,
+}
+
+impl DedupLog {
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        if let Ok(mut dedup) = DEDUP.lock() {
+            *dedup = Some(Dedup::new(DEDUP_LIMIT, DEDUP_WINDOW));
+        }
+        Self { inner }
+    }
+
+    fn log_summary(&self, summary: &Suppressed) {
+        self.inner.log(
+            &Record::builder()
+                .level(summary.level)
+                .target(summary.site.split(':').next().unwrap_or_default())
+                .args(format_args!("{}", summary.message()))
+                .build(),
+        );
+    }
+}
+
+impl Log for DedupLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() > Level::Warn || !self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+            return;
+        }
+        let site = site(record);
+        let (verdict, summary) = match DEDUP.lock() {
+            Ok(mut dedup) => match dedup.as_mut() {
+                Some(dedup) => dedup.check(
+                    record.level(),
+                    &site,
+                    || record.args().to_string(),
+                    Instant::now(),
+                ),
+                None => (Verdict::Log, None),
+            },
+            Err(_) => (Verdict::Log, None),
+        };
+        if let Some(summary) = summary {
+            self.log_summary(&summary);
+        }
+        match verdict {
+            Verdict::Log => self.inner.log(record),
+            Verdict::LogLast => {
+                self.inner.log(record);
+                self.inner.log(
+                    &Record::builder()
+                        .level(record.level())
+                        .target(record.target())
+                        .args(format_args!(
+                            "Similar messages from {} are suppressed for {} seconds",
+                            site,
+                            DEDUP_WINDOW.as_secs()
+                        ))
+                        .build(),
+                );
+            }
+            Verdict::Suppress => {
+                SUPPRESSED_TOTAL.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        report_suppressed();
+        self.inner.flush();
+    }
+}
+
+/// Logs the number of suppressed messages per place, e.g. once an action has finished
+pub fn report_suppressed() {
+    let summaries = match DEDUP.lock() {
+        Ok(mut dedup) => dedup.as_mut().map(Dedup::drain).unwrap_or_default(),
+        Err(_) => return,
+    };
+    for summary in summaries {
+        log::log!(target: "logging", summary.level, "{}", summary.message());
+    }
+}
+
+/// Number of warnings and errors suppressed since the start of the collector
+pub fn suppressed_messages() -> u64 {
+    SUPPRESSED_TOTAL.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Dedup::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        let mut check = |site: &str, message: &str, seconds: u64| {
+            dedup.check(
+                Level::Error,
+                site,
+                || message.to_string(),
+                start + Duration::from_secs(seconds),
+            )
+        };
+        assert_eq!(check("store:10", "a", 0), (Verdict::Log, None));
+        assert_eq!(check("store:10", "b", 1), (Verdict::LogLast, None));
+        assert_eq!(check("store:10", "c", 2), (Verdict::Suppress, None));
+        assert_eq!(check("store:10", "d", 3), (Verdict::Suppress, None));
+        // other places are counted separately
+        assert_eq!(check("yara:20", "e", 4), (Verdict::Log, None));
+
+        // the next window starts with the summary of the previous one
+        let (verdict, summary) = check("store:10", "f", 61);
+        assert_eq!(verdict, Verdict::Log);
+        let summary = summary.unwrap();
+        assert_eq!(summary.count, 2);
+        assert_eq!(
+            summary.message(),
+            "Suppressed 2 similar messages from store:10, e.g.: c"
+        );
+
+        assert_eq!(check("store:10", "g", 62), (Verdict::LogLast, None));
+        assert_eq!(check("store:10", "h", 63), (Verdict::Suppress, None));
+        let summaries = dedup.drain();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].sample, "h");
+        assert!(dedup.sites.is_empty());
+    }
+}
@@ -1,13 +1,186 @@
+mod dedup;
+
 use config::config::Time;
 use system::get_base_path;
-use time::get_ntp_time;
+use time::query_ntp;
+
+pub use dedup::{report_suppressed, suppressed_messages};
 
 use chrono::{Local, Utc};
 use chrono_tz::{self, Tz, UTC};
+use dedup::DedupLog;
 use fern::colors::{Color, ColoredLevelConfig};
 use log::{error, info, warn};
 use log::{Level, LevelFilter};
-use std::{fs, panic};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    fs,
+    io::{self, Write},
+    panic,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+// latest message of level info or above, e.g. for the heartbeat of a running collection
+static LAST_LOG_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+// log of the running workflow, written into its report instead of the log file
+static CAPTURE: Mutex<Option<Box<dyn SealedLog>>> = Mutex::new(None);
+static SEALED: AtomicBool = AtomicBool::new(false);
+
+// values of the declared secrets, replaced in all log output
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Registers values that are replaced by [REDACTED] in all following log messages
+pub fn add_secrets<I: IntoIterator<Item = String>>(values: I) {
+    if let Ok(mut secrets) = SECRETS.write() {
+        for value in values {
+            // the JSON logs (e.g. actions.jsonl) contain the escaped value, e.g. p\"w for p"w
+            let escaped = serde_json::to_string(&value)
+                .map(|quoted| quoted[1..quoted.len() - 1].to_string())
+                .unwrap_or_default();
+            for value in [value, escaped] {
+                if !value.is_empty() && !secrets.contains(&value) {
+                    secrets.push(value);
+                }
+            }
+        }
+        // a secret containing another one is replaced as a whole
+        secrets.sort_by_key(|secret| Reverse(secret.len()));
+    }
+}
+
+/// Replaces the registered secrets in the text
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let secrets = match SECRETS.read() {
+        Ok(secrets) => secrets,
+        Err(_) => return Cow::Borrowed(text),
+    };
+    let mut text = Cow::Borrowed(text);
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = Cow::Owned(text.replace(secret.as_str(), REDACTED));
+        }
+    }
+    text
+}
+
+fn replace_bytes(content: &[u8], from: &[u8], to: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(content.len());
+    let mut position = 0;
+    let mut replaced = false;
+    while position < content.len() {
+        if content[position..].starts_with(from) {
+            result.extend_from_slice(to);
+            position += from.len();
+            replaced = true;
+        } else {
+            result.push(content[position]);
+            position += 1;
+        }
+    }
+    replaced.then_some(result)
+}
+
+/// Replaces the registered secrets in a file written by another process (e.g. a terminal transcript).
+/// Returns whether the file has been changed
+pub fn redact_file(path: &Path) -> io::Result<bool> {
+    let secrets = match SECRETS.read() {
+        Ok(secrets) if !secrets.is_empty() => secrets.clone(),
+        _ => return Ok(false),
+    };
+    // transcripts may contain control sequences, so the content is not decoded
+    let mut content = fs::read(path)?;
+    let mut changed = false;
+    for secret in &secrets {
+        if let Some(replaced) = replace_bytes(&content, secret.as_bytes(), REDACTED.as_bytes()) {
+            content = replaced;
+            changed = true;
+        }
+    }
+    if changed {
+        fs::write(path, content)?;
+    }
+    Ok(changed)
+}
+
+/// Returns the latest message logged with level info or above, None while the log is sealed
+pub fn last_log_line() -> Option<String> {
+    if SEALED.load(Ordering::Relaxed) {
+        return None;
+    }
+    LAST_LOG_LINE.lock().ok()?.clone()
+}
+
+/// Destination of a sealed log, e.g. a file encrypted with the key of the report
+pub trait SealedLog: Write + Send {
+    /// Called once the log is unsealed, e.g. to append the tag of the encryption
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// Writes all following messages to the sealed log instead of the log file,
+/// so they are only stored inside the (encrypted) report. Fails if the log is already sealed,
+/// as the messages of both workflows would end up in one report
+pub fn seal_to(log: Box<dyn SealedLog>) -> io::Result<()> {
+    let mut capture = CAPTURE
+        .lock()
+        .map_err(|_| io::Error::other("The log capture is poisoned"))?;
+    if capture.is_some() {
+        return Err(io::Error::other(
+            "The log is already sealed by another workflow",
+        ));
+    }
+    *capture = Some(log);
+    SEALED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Writes to the log file again and finishes the sealed log
+pub fn unseal() -> io::Result<()> {
+    SEALED.store(false, Ordering::Relaxed);
+    let log = match CAPTURE.lock() {
+        Ok(mut capture) => capture.take(),
+        Err(_) => None,
+    };
+    match log {
+        Some(log) => log.finish(),
+        None => Ok(()),
+    }
+}
+
+fn format_file_record(
+    out: fern::FormatCallback,
+    message: &std::fmt::Arguments,
+    record: &log::Record,
+    time_zone: &Tz,
+) {
+    let time = Local::now().with_timezone(time_zone).to_rfc3339();
+    let message = message.to_string();
+    let message = redact(&message);
+    if record.level() == Level::Error {
+        out.finish(format_args!(
+            "[{}] [{}] [{}:{}] {}",
+            time,
+            record.level(),
+            record.target(),
+            record.line().unwrap_or(0),
+            message
+        ))
+    } else {
+        out.finish(format_args!(
+            "[{}] [{}] [{}] {}",
+            time,
+            record.level(),
+            record.target(),
+            message
+        ))
+    }
+}
 
 pub struct Logger {
     _status: Option<String>,
@@ -18,6 +191,8 @@ pub struct Logger {
     file_level: LevelFilter,
     time_config: Option<Time>,
     time_zone: Tz,
+    // log to stderr instead of stdout, e.g. if stdout is piped into another tool
+    console_stderr: bool,
 }
 
 fn format_duration(duration: std::time::Duration) -> String {
@@ -43,6 +218,7 @@ impl Logger {
             file_level: LevelFilter::Debug,
             time_config: None,
             time_zone: UTC,
+            console_stderr: false,
         };
 
         // Create a panic hook
@@ -69,8 +245,8 @@ impl Logger {
     pub fn log_initial_info(&self) {
         let utc_time = Utc::now();
 
-        let ntp_time = match &self.time_config {
-            Some(time_config) if time_config.ntp_enabled => get_ntp_time(time_config.clone()),
+        let ntp_result = match &self.time_config {
+            Some(time_config) if time_config.ntp_enabled => query_ntp(time_config),
             _ => None,
         };
 
@@ -79,19 +255,38 @@ impl Logger {
         let pid = std::process::id();
         let cwd = std::env::current_dir().unwrap();
 
-        let initial_info = format!(
-            "\nCWD: {:?}\nPID: {}\nLocal time: {}\nUTC time: {}\nNTP UTC time: {}\nTimezone: {}\n",
+        let mut initial_info = format!(
+            "\nCWD: {:?}\nPID: {}\nLocal time: {}\nUTC time: {}\nNTP UTC time: {}\nClock offset: {}\nTimezone: {}\n",
             cwd,
             pid,
             local_time.to_rfc3339(),
             utc_time.to_rfc3339(),
-            if let Some(ntp_time) = ntp_time {
-                ntp_time.to_rfc3339()
-            } else {
-                "N/A".to_string()
+            match &ntp_result {
+                Some(ntp_result) => ntp_result.ntp_time.to_rfc3339(),
+                None => "N/A".to_string(),
+            },
+            match &ntp_result {
+                Some(ntp_result) => format!("{} ms", ntp_result.offset_ms),
+                None => "N/A".to_string(),
             },
             self.time_zone
         );
+        // the offset of each server, so a single wrong server can be spotted
+        for server in ntp_result.iter().flat_map(|ntp_result| &ntp_result.servers) {
+            initial_info.push_str(&match (server.offset_ms, &server.error) {
+                (Some(offset_ms), _) => format!(
+                    "NTP server {}: {} ms (round trip {} ms)\n",
+                    server.server,
+                    offset_ms,
+                    server.round_trip_ms.unwrap_or_default()
+                ),
+                (None, error) => format!(
+                    "NTP server {}: {}\n",
+                    server.server,
+                    error.as_deref().unwrap_or("No response")
+                ),
+            });
+        }
 
         info!("{}", initial_info);
     }
@@ -103,6 +298,10 @@ impl Logger {
             .warn(Color::Yellow)
             .error(Color::Red);
 
+        let console: fern::Output = match self.console_stderr {
+            true => std::io::stderr().into(),
+            false => std::io::stdout().into(),
+        };
         let mut base_config = fern::Dispatch::new().chain(
             fern::Dispatch::new()
                 .level(self.level)
@@ -110,6 +309,8 @@ impl Logger {
                     let time = Local::now()
                         .with_timezone(&self.time_zone)
                         .format("%Y-%m-%d %H:%M:%S");
+                    let message = message.to_string();
+                    let message = redact(&message);
                     if record.level() == Level::Error {
                         out.finish(format_args!(
                             "[{}] [{}] [{}:{}] {}",
@@ -129,39 +330,52 @@ impl Logger {
                         ))
                     }
                 })
-                .chain(std::io::stdout()),
+                .chain(console),
         );
 
+        let time_zone = self.time_zone;
         if let Some(ref file_path) = self.file_path {
             base_config = base_config.chain(
                 fern::Dispatch::new()
                     .format(move |out, message, record| {
-                        let time = Local::now().with_timezone(&self.time_zone).to_rfc3339();
-                        if record.level() == Level::Error {
-                            out.finish(format_args!(
-                                "[{}] [{}] [{}:{}] {}",
-                                time,
-                                record.level(),
-                                record.target(),
-                                record.line().unwrap_or(0),
-                                message
-                            ))
-                        } else {
-                            out.finish(format_args!(
-                                "[{}] [{}] [{}] {}",
-                                time,
-                                record.level(),
-                                record.target(),
-                                message
-                            ))
-                        }
+                        format_file_record(out, message, record, &time_zone)
                     })
                     .level(self.file_level)
+                    // the log of a sealed workflow is only written into its report
+                    .filter(|_| !SEALED.load(Ordering::Relaxed))
                     .chain(fern::log_file(file_path).unwrap()),
             );
         }
 
-        base_config.apply().unwrap();
+        base_config = base_config.chain(
+            fern::Dispatch::new()
+                .format(move |out, message, record| {
+                    format_file_record(out, message, record, &time_zone)
+                })
+                .level(self.file_level)
+                .filter(|_| SEALED.load(Ordering::Relaxed))
+                .chain(fern::Output::call(|record| {
+                    if let Ok(mut capture) = CAPTURE.lock() {
+                        if let Some(writer) = capture.as_mut() {
+                            let _ = writeln!(writer, "{}", record.args());
+                        }
+                    }
+                })),
+        );
+
+        base_config = base_config.chain(fern::Dispatch::new().level(LevelFilter::Info).chain(
+            fern::Output::call(|record| {
+                if let Ok(mut line) = LAST_LOG_LINE.lock() {
+                    let message = record.args().to_string();
+                    *line = Some(format!("[{}] {}", record.level(), redact(&message)));
+                }
+            }),
+        ));
+
+        // warnings and errors repeated over and over are rate limited
+        let (max_level, log) = base_config.into_log();
+        log::set_boxed_logger(Box::new(DedupLog::new(log))).unwrap();
+        log::set_max_level(max_level);
 
         self
     }
@@ -191,6 +405,11 @@ impl Logger {
         self
     }
 
+    pub fn set_console_stderr(mut self, stderr: bool) -> Self {
+        self.console_stderr = stderr;
+        self
+    }
+
     pub fn set_file_level(mut self, level: LevelFilter) -> Self {
         self.file_level = level;
         self
@@ -323,4 +542,37 @@ mod tests {
         let log_content = fs::read_to_string(&log_file).unwrap();
         assert!(log_content.contains("This is a panic message"));
     }
+
+    #[test]
+    fn test_redact() {
+        let mut cleanup = Cleanup::new();
+        add_secrets([
+            "tok-123".to_string(),
+            "tok-123-full".to_string(),
+            String::new(),
+        ]);
+
+        assert_eq!(
+            redact("curl -H 'Authorization: Bearer tok-123-full'"),
+            "curl -H 'Authorization: Bearer [REDACTED]'"
+        );
+        assert_eq!(redact("tok-123 and tok-1234"), "[REDACTED] and [REDACTED]4");
+        assert!(matches!(redact("nothing to hide"), Cow::Borrowed(_)));
+        // the value escaped inside a JSON log
+        add_secrets(["p\"w\\d".to_string()]);
+        assert_eq!(
+            redact(r#"{"error":"login -p p\"w\\d"}"#),
+            r#"{"error":"login -p [REDACTED]"}"#
+        );
+
+        let dir = cleanup.tmp_dir("test_redact");
+        let transcript = dir.join("transcript.log");
+        fs::write(&transcript, b"\x1b[0m$ export TOKEN=tok-123\xff\n").unwrap();
+        assert!(redact_file(&transcript).unwrap());
+        assert_eq!(
+            fs::read(&transcript).unwrap(),
+            b"\x1b[0m$ export TOKEN=[REDACTED]\xff\n"
+        );
+        assert!(!redact_file(&transcript).unwrap());
+    }
 }
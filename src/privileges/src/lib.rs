@@ -1,6 +1,9 @@
-use log::{error, info};
-use std::{env, error::Error, path::Path};
-use utils::misc::exit_after_user_input;
+use log::info;
+use std::{
+    env,
+    error::Error,
+    path::{Path, PathBuf},
+};
 
 #[cfg(windows)]
 mod windows;
@@ -11,6 +14,61 @@ mod macos;
 #[cfg(all(unix, not(target_os = "macos")))]
 mod unix;
 
+#[cfg(unix)]
+mod user;
+
+pub mod tcc;
+pub use tcc::{full_disk_access, FullDiskAccess};
+
+// Resolves to the user logged in on the system instead of the elevated user
+pub const LOGGED_IN_USER: &str = "logged_in_user";
+
+// First argument of the collector if it only starts a process as another user (Windows)
+pub const RUN_AS_ARG: &str = "--run-as-proxy";
+
+/// The user a process is started as after dropping the privileges of the collector
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunAs {
+    pub user: String,
+    pub uid: u32,
+    pub gid: u32,
+    // supplementary groups, the ones of the collector would be kept otherwise
+    pub groups: Vec<u32>,
+    // e.g. for tools resolving ~, empty on Windows where the environment of the user is loaded
+    pub home: PathBuf,
+    // Windows session of the user, the process is started with its token
+    pub session: u32,
+}
+
+pub fn resolve_user(name: &str) -> Result<RunAs, Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        return user::resolve_user(name);
+    }
+
+    #[cfg(windows)]
+    {
+        return windows::resolve_user(name);
+    }
+
+    #[allow(unreachable_code)]
+    Err(format!("Running as {:?} is not supported on this platform", name).into())
+}
+
+/// Runs the process given on the command line as another user and returns its exit code,
+/// if the collector was started as proxy with RUN_AS_ARG. Only CreateProcessAsUserW starts a
+/// process with the token of another user on Windows, which Command can't, so the collector
+/// starts itself as proxy and the process keeps the pipes and the job object of the proxy
+pub fn run_as_proxy() -> Option<i32> {
+    #[cfg(windows)]
+    {
+        return windows::run_as_proxy();
+    }
+
+    #[allow(unreachable_code)]
+    None
+}
+
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use libc;
 
@@ -30,6 +88,18 @@ pub fn is_elevated() -> bool {
     windows::is_elevated()
 }
 
+/// Enables a privilege of the process token, e.g. SeRestorePrivilege to load registry hives.
+/// The user has to hold the privilege (e.g. as admin), it is only disabled by default
+pub fn enable_privilege(name: &str) -> Result<(), Box<dyn Error>> {
+    #[cfg(windows)]
+    {
+        return windows::enable_privilege(name);
+    }
+
+    #[allow(unreachable_code)]
+    Err(format!("Privilege {} is only supported on Windows", name).into())
+}
+
 pub fn run_elevated<P: AsRef<Path>>(path: P) -> Result<(), Box<dyn Error>> {
     #[cfg(windows)]
     {
@@ -50,24 +120,12 @@ pub fn run_elevated<P: AsRef<Path>>(path: P) -> Result<(), Box<dyn Error>> {
     Err("Unsupported platform".into())
 }
 
-pub fn restart_elevated() {
-    let current_exe = match env::current_exe() {
-        Ok(path) => path,
-        Err(e) => {
-            error!("Failed to get current exe: {}", e);
-            exit_after_user_input("Press any key to exit...", 1);
-        }
-    };
+/// Restarts the current executable elevated and exits once it has been started.
+/// Returns the error if the elevation failed (e.g. the UAC prompt was denied)
+pub fn try_restart_elevated() -> Result<(), Box<dyn Error>> {
+    let current_exe = env::current_exe()?;
 
     info!("Restarting {:?} as admin", &current_exe.to_string_lossy());
-    let res = run_elevated(&current_exe);
-    match res {
-        Ok(_) => {
-            std::process::exit(0);
-        }
-        Err(e) => {
-            error!("Failed to restart as admin: {}", e);
-            exit_after_user_input("Press any key to exit...", 1);
-        }
-    }
+    run_elevated(&current_exe)?;
+    std::process::exit(0);
 }
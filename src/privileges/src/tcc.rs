@@ -0,0 +1,102 @@
+// Full Disk Access on macOS. Without it, the privacy protection (TCC) denies access to e.g. Mail,
+// Messages, Safari and the TCC databases, even for root. Collections of these locations don't
+// fail, they silently come back empty, so the access is probed before the workflows run
+use std::{env, error::Error, fmt, fs::File, path::PathBuf};
+
+// denied by TCC, unlike EACCES (13) of the file permissions
+const EPERM: i32 = 1;
+
+// pane of the system settings to grant Full Disk Access
+pub const FULL_DISK_ACCESS_SETTINGS: &str =
+    "x-apple.systempreferences:com.apple.preference.security?Privacy_AllFiles";
+
+/// Whether the collector has Full Disk Access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullDiskAccess {
+    Granted,
+    Denied,
+    // none of the protected paths could be probed
+    Unknown,
+    // not macOS
+    NotApplicable,
+}
+
+impl fmt::Display for FullDiskAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FullDiskAccess::Granted => write!(f, "granted"),
+            FullDiskAccess::Denied => write!(f, "denied"),
+            FullDiskAccess::Unknown => write!(f, "unknown"),
+            FullDiskAccess::NotApplicable => write!(f, "not_applicable"),
+        }
+    }
+}
+
+// only readable with Full Disk Access
+fn protected_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(
+        "/Library/Application Support/com.apple.TCC/TCC.db",
+    )];
+    if let Some(home) = env::var_os("HOME") {
+        paths.push(PathBuf::from(home).join("Library/Application Support/com.apple.TCC/TCC.db"));
+    }
+    paths
+}
+
+/// Opens the paths until one tells whether the access is granted or denied
+pub fn probe(paths: &[PathBuf]) -> FullDiskAccess {
+    for path in paths {
+        match File::open(path) {
+            Ok(_) => return FullDiskAccess::Granted,
+            Err(e) if e.raw_os_error() == Some(EPERM) => return FullDiskAccess::Denied,
+            // e.g. not found or the file permissions of another user
+            Err(_) => continue,
+        }
+    }
+    FullDiskAccess::Unknown
+}
+
+pub fn full_disk_access() -> FullDiskAccess {
+    match cfg!(target_os = "macos") {
+        true => probe(&protected_paths()),
+        false => FullDiskAccess::NotApplicable,
+    }
+}
+
+/// Opens the Full Disk Access pane of the system settings
+pub fn open_full_disk_access_settings() -> Result<(), Box<dyn Error>> {
+    if !cfg!(target_os = "macos") {
+        return Err("Full Disk Access only exists on macOS".into());
+    }
+    let status = std::process::Command::new("open")
+        .arg(FULL_DISK_ACCESS_SETTINGS)
+        .status()?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("Failed to open the system settings: {}", status).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_probe() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_probe_full_disk_access");
+        let readable = dir.join("TCC.db");
+        std::fs::write(&readable, "").unwrap();
+        let missing = dir.join("missing.db");
+
+        assert_eq!(probe(&[]), FullDiskAccess::Unknown);
+        assert_eq!(
+            probe(std::slice::from_ref(&missing)),
+            FullDiskAccess::Unknown
+        );
+        assert_eq!(probe(&[missing, readable]), FullDiskAccess::Granted);
+        #[cfg(not(target_os = "macos"))]
+        assert_eq!(full_disk_access(), FullDiskAccess::NotApplicable);
+    }
+}
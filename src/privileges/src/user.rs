@@ -0,0 +1,123 @@
+use crate::{RunAs, LOGGED_IN_USER};
+use log::debug;
+use std::{
+    env,
+    error::Error,
+    ffi::{CStr, CString, OsStr},
+    io,
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    process::Command,
+};
+
+fn command_output(cmd: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new(cmd).args(args).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// The user that started the collector with sudo or owns the graphical session
+fn logged_in_user() -> Result<String, Box<dyn Error>> {
+    if let Ok(user) = env::var("SUDO_USER") {
+        if !user.is_empty() && user != "root" {
+            return Ok(user);
+        }
+    }
+
+    let user = match cfg!(target_os = "macos") {
+        true => command_output("stat", &["-f", "%Su", "/dev/console"])?,
+        false => command_output("logname", &[])?,
+    };
+    if user.is_empty() || user == "root" {
+        return Err("No logged in user found".into());
+    }
+    Ok(user)
+}
+
+// Uses the id command instead of the passwd database, so users from
+// directory services (e.g. LDAP, Open Directory) are resolved as well
+pub fn resolve_user(name: &str) -> Result<RunAs, Box<dyn Error>> {
+    let user = match name {
+        LOGGED_IN_USER => logged_in_user()?,
+        _ => name.to_string(),
+    };
+    let uid = command_output("id", &["-u", &user])?.parse()?;
+    let gid = command_output("id", &["-g", &user])?.parse()?;
+    #[allow(unused_mut)]
+    let mut groups = command_output("id", &["-G", &user])?
+        .split_whitespace()
+        .map(|group| group.parse())
+        .collect::<Result<Vec<u32>, _>>()?;
+    // setgroups fails with more than NGROUPS_MAX groups
+    #[cfg(target_os = "macos")]
+    groups.truncate(16);
+    let home = home_dir(&user)?;
+
+    debug!(
+        "Resolved user {:?} to uid {}, gid {} and groups {:?}",
+        user, uid, gid, groups
+    );
+    Ok(RunAs {
+        user,
+        uid,
+        gid,
+        groups,
+        home,
+        session: 0,
+    })
+}
+
+// getpwnam_r asks directory services as well
+fn home_dir(user: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let name = CString::new(user)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let code = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if code != 0 || result.is_null() || passwd.pw_dir.is_null() {
+        return Err(format!("No home directory found for user {:?}", user).into());
+    }
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Ok(PathBuf::from(OsStr::from_bytes(home.to_bytes())))
+}
+
+impl RunAs {
+    /// Switches the calling process to the user and its supplementary groups, like initgroups
+    /// followed by setgid and setuid. The groups are resolved beforehand, as initgroups reads
+    /// the group database, which isn't safe between fork and exec
+    pub fn switch_user(&self) -> io::Result<()> {
+        unsafe {
+            if libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+                || libc::setgid(self.gid) != 0
+                || libc::setuid(self.uid) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // the variables of the collector would point to the home of root
+    pub fn env(&self) -> [(&str, &OsStr); 3] {
+        [
+            ("HOME", self.home.as_os_str()),
+            ("USER", OsStr::new(&self.user)),
+            ("LOGNAME", OsStr::new(&self.user)),
+        ]
+    }
+}
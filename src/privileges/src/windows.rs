@@ -1,9 +1,43 @@
 extern crate winapi;
-use std::ffi::OsStr;
+use crate::{RunAs, LOGGED_IN_USER, RUN_AS_ARG};
+use log::debug;
+use std::ffi::{OsStr, OsString};
 use std::os::windows::ffi::OsStrExt;
-use std::{error::Error, path::Path};
+use std::ptr::{null, null_mut};
+use std::{env, error::Error, io, iter, mem, path::Path};
+use winapi::shared::winerror::ERROR_PRIVILEGE_NOT_HELD;
+use winapi::um::handleapi::{CloseHandle, SetHandleInformation, INVALID_HANDLE_VALUE};
+use winapi::um::jobapi2::{
+    AssignProcessToJobObject, CreateJobObjectW, QueryInformationJobObject, SetInformationJobObject,
+};
+use winapi::um::processenv::GetStdHandle;
+use winapi::um::processthreadsapi::{
+    CreateProcessAsUserW, GetExitCodeProcess, OpenProcess, OpenProcessToken, ProcessIdToSessionId,
+    ResumeThread, PROCESS_INFORMATION, STARTUPINFOW,
+};
+use winapi::um::securitybaseapi::{DuplicateTokenEx, GetTokenInformation};
 use winapi::um::shellapi::ShellExecuteW;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::tlhelp32::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+};
+use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock};
+use winapi::um::winbase::{
+    CreateProcessWithTokenW, LookupAccountSidW, WTSGetActiveConsoleSessionId, CREATE_SUSPENDED,
+    CREATE_UNICODE_ENVIRONMENT, HANDLE_FLAG_INHERIT, INFINITE, LOGON_WITH_PROFILE,
+    STARTF_USESTDHANDLES, STD_ERROR_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+};
+use winapi::um::winnt::{
+    JobObjectBasicUIRestrictions, JobObjectExtendedLimitInformation, SecurityImpersonation,
+    TokenPrimary, TokenUser, HANDLE, JOBOBJECT_BASIC_UI_RESTRICTIONS,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, MAXIMUM_ALLOWED,
+    PROCESS_QUERY_LIMITED_INFORMATION, SID_NAME_USE, TOKEN_DUPLICATE, TOKEN_QUERY, TOKEN_USER,
+};
 use winapi::um::winuser::SW_SHOWNORMAL;
+use winapi::um::wtsapi32::WTSQueryUserToken;
+
+// the shell of an interactive session, its token is the one of the logged in user
+const SHELL_PROCESS: &str = "explorer.exe";
 
 pub fn run_elevated<P: AsRef<Path>>(path: P) -> Result<(), Box<dyn Error>> {
     let path_wide: Vec<u16> = OsStr::new(path.as_ref())
@@ -57,3 +91,422 @@ pub fn is_elevated() -> bool {
         elevation.TokenIsElevated != 0
     }
 }
+
+pub fn enable_privilege(name: &str) -> Result<(), Box<dyn Error>> {
+    use std::{io, mem, ptr::null};
+    use winapi::shared::winerror::ERROR_NOT_ALL_ASSIGNED;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+    use winapi::um::winbase::LookupPrivilegeValueW;
+    use winapi::um::winnt::{
+        HANDLE, LUID, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES,
+        TOKEN_PRIVILEGES, TOKEN_QUERY,
+    };
+
+    let name_wide: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let mut luid: LUID = mem::zeroed();
+        if LookupPrivilegeValueW(null(), name_wide.as_ptr(), &mut luid) == 0 {
+            return Err(
+                format!("Unknown privilege {}: {}", name, io::Error::last_os_error()).into(),
+            );
+        }
+
+        let mut token: HANDLE = std::ptr::null_mut();
+        if OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        ) == 0
+        {
+            return Err(format!(
+                "Failed to open process token: {}",
+                io::Error::last_os_error()
+            )
+            .into());
+        }
+
+        let mut privileges = TOKEN_PRIVILEGES {
+            PrivilegeCount: 1,
+            Privileges: [LUID_AND_ATTRIBUTES {
+                Luid: luid,
+                Attributes: SE_PRIVILEGE_ENABLED,
+            }],
+        };
+        let adjusted = AdjustTokenPrivileges(
+            token,
+            0,
+            &mut privileges,
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        // succeeds even if the privilege isn't held, which is only reported by the last error
+        let error = io::Error::last_os_error();
+        CloseHandle(token);
+        if adjusted == 0 {
+            return Err(format!("Failed to enable {}: {}", name, error).into());
+        }
+        if error.raw_os_error() == Some(ERROR_NOT_ALL_ASSIGNED as i32) {
+            return Err(format!("The user doesn't hold the privilege {}", name).into());
+        }
+    }
+    Ok(())
+}
+
+// Closes the handle once it goes out of scope
+struct Handle(HANDLE);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if !self.0.is_null() && self.0 != INVALID_HANDLE_VALUE {
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+}
+
+fn last_error(action: &str) -> Box<dyn Error> {
+    format!("{}: {}", action, io::Error::last_os_error()).into()
+}
+
+// DOMAIN\user the token belongs to
+fn token_user(token: HANDLE) -> Option<String> {
+    unsafe {
+        let mut length: u32 = 0;
+        GetTokenInformation(token, TokenUser, null_mut(), 0, &mut length);
+        let mut buffer = vec![0u8; length as usize];
+        if length == 0
+            || GetTokenInformation(
+                token,
+                TokenUser,
+                buffer.as_mut_ptr() as *mut _,
+                length,
+                &mut length,
+            ) == 0
+        {
+            return None;
+        }
+        let token_user = &*(buffer.as_ptr() as *const TOKEN_USER);
+        let mut name = [0u16; 256];
+        let mut name_length = name.len() as u32;
+        let mut domain = [0u16; 256];
+        let mut domain_length = domain.len() as u32;
+        let mut sid_type: SID_NAME_USE = 0;
+        if LookupAccountSidW(
+            null(),
+            token_user.User.Sid,
+            name.as_mut_ptr(),
+            &mut name_length,
+            domain.as_mut_ptr(),
+            &mut domain_length,
+            &mut sid_type,
+        ) == 0
+        {
+            return None;
+        }
+        Some(format!(
+            "{}\\{}",
+            String::from_utf16_lossy(&domain[..domain_length as usize]),
+            String::from_utf16_lossy(&name[..name_length as usize])
+        ))
+    }
+}
+
+fn process_token(pid: u32) -> Option<Handle> {
+    unsafe {
+        let process = Handle(OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid));
+        if process.0.is_null() {
+            return None;
+        }
+        let mut token: HANDLE = null_mut();
+        if OpenProcessToken(process.0, TOKEN_QUERY | TOKEN_DUPLICATE, &mut token) == 0 {
+            return None;
+        }
+        Some(Handle(token))
+    }
+}
+
+// Shells of the interactive sessions with their session, user and token
+fn session_shells() -> Vec<(u32, String, Handle)> {
+    // tokens of other users can only be opened with SeDebugPrivilege
+    if let Err(e) = enable_privilege("SeDebugPrivilege") {
+        debug!("{}", e);
+    }
+    let mut shells = vec![];
+    unsafe {
+        let snapshot = Handle(CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0));
+        if snapshot.0 == INVALID_HANDLE_VALUE {
+            return shells;
+        }
+        let mut entry: PROCESSENTRY32W = mem::zeroed();
+        entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut found = Process32FirstW(snapshot.0, &mut entry) != 0;
+        while found {
+            let length = entry
+                .szExeFile
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(entry.szExeFile.len());
+            let name = String::from_utf16_lossy(&entry.szExeFile[..length]);
+            let mut session: u32 = 0;
+            if name.eq_ignore_ascii_case(SHELL_PROCESS)
+                && ProcessIdToSessionId(entry.th32ProcessID, &mut session) != 0
+            {
+                if let Some(token) = process_token(entry.th32ProcessID) {
+                    if let Some(user) = token_user(token.0) {
+                        shells.push((session, user, token));
+                    }
+                }
+            }
+            found = Process32NextW(snapshot.0, &mut entry) != 0;
+        }
+    }
+    shells
+}
+
+// user matches DOMAIN\user as well
+fn same_user(account: &str, name: &str) -> bool {
+    account.eq_ignore_ascii_case(name)
+        || account
+            .rsplit('\\')
+            .next()
+            .is_some_and(|user| user.eq_ignore_ascii_case(name))
+}
+
+// Without the password of the user, LogonUser can't be used, so the user must be logged in
+pub fn resolve_user(name: &str) -> Result<RunAs, Box<dyn Error>> {
+    let shells = session_shells();
+    let console = unsafe { WTSGetActiveConsoleSessionId() };
+    let shell = match name {
+        LOGGED_IN_USER => shells
+            .iter()
+            .find(|(session, ..)| *session == console)
+            .or(shells.first()),
+        _ => shells.iter().find(|(_, user, _)| same_user(user, name)),
+    };
+    let Some((session, user, _)) = shell else {
+        return Err(format!(
+            "No session of {:?} found: on Windows, only users who are logged in can be used",
+            name
+        )
+        .into());
+    };
+
+    debug!(
+        "Resolved user {:?} to {:?} in session {}",
+        name, user, session
+    );
+    Ok(RunAs {
+        user: user.clone(),
+        session: *session,
+        ..Default::default()
+    })
+}
+
+// Primary token of the user: from the session if the collector runs as SYSTEM,
+// otherwise duplicated from the shell of the user
+fn user_token(session: u32, user: &str) -> Result<Handle, Box<dyn Error>> {
+    unsafe {
+        if let Err(e) = enable_privilege("SeTcbPrivilege") {
+            debug!("{}", e);
+        }
+        let mut token: HANDLE = null_mut();
+        if WTSQueryUserToken(session, &mut token) != 0 {
+            let token = Handle(token);
+            if token_user(token.0).is_some_and(|owner| same_user(&owner, user)) {
+                return Ok(token);
+            }
+        }
+
+        let shell = session_shells()
+            .into_iter()
+            .find(|(shell_session, owner, _)| *shell_session == session && same_user(owner, user))
+            .ok_or_else(|| format!("No session of {:?} found", user))?;
+        let mut primary: HANDLE = null_mut();
+        if DuplicateTokenEx(
+            shell.2 .0,
+            MAXIMUM_ALLOWED,
+            null_mut(),
+            SecurityImpersonation,
+            TokenPrimary,
+            &mut primary,
+        ) == 0
+        {
+            return Err(last_error("Failed to duplicate the token of the user"));
+        }
+        Ok(Handle(primary))
+    }
+}
+
+// Appends the argument to the command line, quoted like Command does
+fn append_arg(command_line: &mut Vec<u16>, arg: &OsStr) {
+    let arg: Vec<u16> = arg.encode_wide().collect();
+    let quote = arg.is_empty()
+        || arg
+            .iter()
+            .any(|&c| c == b' ' as u16 || c == b'\t' as u16 || c == b'"' as u16);
+    if !command_line.is_empty() {
+        command_line.push(b' ' as u16);
+    }
+    if !quote {
+        command_line.extend(arg);
+        return;
+    }
+    command_line.push(b'"' as u16);
+    let mut backslashes = 0;
+    for c in arg {
+        if c == b'\\' as u16 {
+            backslashes += 1;
+        } else {
+            // backslashes are only escaped in front of a quote
+            if c == b'"' as u16 {
+                command_line.extend(iter::repeat_n(b'\\' as u16, backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        command_line.push(c);
+    }
+    command_line.extend(iter::repeat_n(b'\\' as u16, backslashes));
+    command_line.push(b'"' as u16);
+}
+
+// The process is killed with the proxy (e.g. on a timeout of the action) and gets the
+// UI restrictions of the sandbox, even if the secondary logon service started it
+fn confine(process: HANDLE) -> Result<(), Box<dyn Error>> {
+    unsafe {
+        let job = Handle(CreateJobObjectW(null_mut(), null()));
+        if job.0.is_null() {
+            return Err(last_error("Failed to create job object"));
+        }
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let mut restrictions: JOBOBJECT_BASIC_UI_RESTRICTIONS = mem::zeroed();
+        // the job of the proxy, if it is in one
+        QueryInformationJobObject(
+            null_mut(),
+            JobObjectBasicUIRestrictions,
+            &mut restrictions as *mut _ as *mut _,
+            mem::size_of_val(&restrictions) as u32,
+            null_mut(),
+        );
+        if SetInformationJobObject(
+            job.0,
+            JobObjectExtendedLimitInformation,
+            &mut limits as *mut _ as *mut _,
+            mem::size_of_val(&limits) as u32,
+        ) == 0
+            || SetInformationJobObject(
+                job.0,
+                JobObjectBasicUIRestrictions,
+                &mut restrictions as *mut _ as *mut _,
+                mem::size_of_val(&restrictions) as u32,
+            ) == 0
+            || AssignProcessToJobObject(job.0, process) == 0
+        {
+            return Err(last_error("Failed to assign the process to a job object"));
+        }
+        // the job is closed with the proxy, which kills the process
+        mem::forget(job);
+    }
+    Ok(())
+}
+
+// Starts the program with its arguments as the user of the session and returns its exit code
+fn spawn_as_user(args: &[OsString]) -> Result<i32, Box<dyn Error>> {
+    let [session, user, program_args @ ..] = args else {
+        return Err("Expected the session, the user and the program".into());
+    };
+    let session: u32 = session.to_string_lossy().parse()?;
+    let token = user_token(session, &user.to_string_lossy())?;
+    let mut command_line: Vec<u16> = vec![];
+    for arg in program_args {
+        append_arg(&mut command_line, arg);
+    }
+    command_line.push(0);
+
+    unsafe {
+        // e.g. USERPROFILE and APPDATA of the user
+        let mut environment = null_mut();
+        if CreateEnvironmentBlock(&mut environment, token.0, 0) == 0 {
+            return Err(last_error("Failed to create the environment of the user"));
+        }
+
+        // the pipes and files of the proxy are passed on
+        let mut startup: STARTUPINFOW = mem::zeroed();
+        startup.cb = mem::size_of::<STARTUPINFOW>() as u32;
+        startup.dwFlags = STARTF_USESTDHANDLES;
+        startup.hStdInput = GetStdHandle(STD_INPUT_HANDLE);
+        startup.hStdOutput = GetStdHandle(STD_OUTPUT_HANDLE);
+        startup.hStdError = GetStdHandle(STD_ERROR_HANDLE);
+        for handle in [startup.hStdInput, startup.hStdOutput, startup.hStdError] {
+            SetHandleInformation(handle, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT);
+        }
+
+        let flags = CREATE_UNICODE_ENVIRONMENT | CREATE_SUSPENDED;
+        let mut process: PROCESS_INFORMATION = mem::zeroed();
+        let mut created = CreateProcessAsUserW(
+            token.0,
+            null(),
+            command_line.as_mut_ptr(),
+            null_mut(),
+            null_mut(),
+            1,
+            flags,
+            environment,
+            null(),
+            &mut startup,
+            &mut process,
+        );
+        // only SYSTEM holds SeAssignPrimaryTokenPrivilege, an administrator
+        // starts the process through the secondary logon service instead
+        if created == 0
+            && io::Error::last_os_error().raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD as i32)
+        {
+            created = CreateProcessWithTokenW(
+                token.0,
+                LOGON_WITH_PROFILE,
+                null(),
+                command_line.as_mut_ptr(),
+                flags,
+                environment,
+                null(),
+                &mut startup,
+                &mut process,
+            );
+        }
+        let error = io::Error::last_os_error();
+        DestroyEnvironmentBlock(environment);
+        if created == 0 {
+            return Err(format!("Failed to start {:?}: {}", program_args.first(), error).into());
+        }
+
+        let child = Handle(process.hProcess);
+        let thread = Handle(process.hThread);
+        if let Err(e) = confine(child.0) {
+            winapi::um::processthreadsapi::TerminateProcess(child.0, 1);
+            return Err(e);
+        }
+        ResumeThread(thread.0);
+        WaitForSingleObject(child.0, INFINITE);
+        let mut code: u32 = 0;
+        if GetExitCodeProcess(child.0, &mut code) == 0 {
+            return Err(last_error("Failed to get the exit code"));
+        }
+        Ok(code as i32)
+    }
+}
+
+pub fn run_as_proxy() -> Option<i32> {
+    let args: Vec<OsString> = env::args_os().collect();
+    if args.get(1).map(|arg| arg.as_os_str()) != Some(OsStr::new(RUN_AS_ARG)) {
+        return None;
+    }
+    match spawn_as_user(&args[2..]) {
+        Ok(code) => Some(code),
+        Err(e) => {
+            eprintln!("Failed to run as another user: {}", e);
+            Some(1)
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// SQLite catalog of the received reports, the central record of what arrived from where and when
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{error::Error, path::Path};
+
+use crate::intake::Intake;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY,
+    received TEXT NOT NULL,
+    path TEXT NOT NULL UNIQUE,
+    report TEXT NOT NULL,
+    case_name TEXT NOT NULL,
+    device TEXT NOT NULL,
+    workflow TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    started TEXT NOT NULL,
+    finished TEXT NOT NULL,
+    archive TEXT NOT NULL,
+    archive_size INTEGER NOT NULL,
+    archive_sha1 TEXT NOT NULL,
+    archive_sha256 TEXT NOT NULL,
+    algorithm TEXT NOT NULL,
+    aborted TEXT,
+    status TEXT NOT NULL,
+    errors TEXT NOT NULL,
+    unpacked TEXT
+)";
+
+#[derive(Debug)]
+pub struct CatalogEntry {
+    pub id: i64,
+    pub received: String,
+    pub case: String,
+    pub device: String,
+    pub report: String,
+    pub status: String,
+    pub archive_sha256: String,
+    pub unpacked: Option<String>,
+}
+
+pub struct Catalog {
+    connection: Connection,
+}
+
+impl Catalog {
+    /// Opens the catalog, it is created if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let connection = Connection::open(path)?;
+        connection.execute(SCHEMA, [])?;
+        Ok(Self { connection })
+    }
+
+    /// Whether the report directory has been cataloged before
+    pub fn contains(&self, path: &Path) -> Result<bool, Box<dyn Error>> {
+        let id: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT id FROM reports WHERE path = ?1",
+                [path.to_string_lossy()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id.is_some())
+    }
+
+    /// Records the report, returns its id
+    pub fn insert(&self, intake: &Intake, received: &str) -> Result<i64, Box<dyn Error>> {
+        self.connection.execute(
+            "INSERT INTO reports (received, path, report, case_name, device, workflow, run_id,
+                started, finished, archive, archive_size, archive_sha1, archive_sha256, algorithm,
+                aborted, status, errors)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            params![
+                received,
+                intake.path.to_string_lossy(),
+                intake.report,
+                intake.case,
+                intake.device,
+                intake.workflow,
+                intake.run_id,
+                intake.started,
+                intake.finished,
+                intake.archive,
+                intake.archive_size as i64,
+                intake.archive_sha1,
+                intake.archive_sha256,
+                intake.algorithm,
+                intake.aborted,
+                intake.status.as_str(),
+                intake.errors.join("; "),
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Records where the report was unpacked to, or why it failed
+    pub fn set_unpacked(&self, id: i64, unpacked: &str) -> Result<(), Box<dyn Error>> {
+        self.connection.execute(
+            "UPDATE reports SET unpacked = ?1 WHERE id = ?2",
+            params![unpacked, id],
+        )?;
+        Ok(())
+    }
+
+    /// All reports, the first received first
+    pub fn entries(&self) -> Result<Vec<CatalogEntry>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, received, case_name, device, report, status, archive_sha256, unpacked
+            FROM reports ORDER BY id",
+        )?;
+        let entries = statement
+            .query_map([], |row| {
+                Ok(CatalogEntry {
+                    id: row.get(0)?,
+                    received: row.get(1)?,
+                    case: row.get(2)?,
+                    device: row.get(3)?,
+                    report: row.get(4)?,
+                    status: row.get(5)?,
+                    archive_sha256: row.get(6)?,
+                    unpacked: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intake::verify;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_catalog() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_catalog");
+        let report_dir = dir.join("HOST01_Triage");
+        std::fs::create_dir_all(&report_dir).unwrap();
+        let intake = verify(&report_dir, "ACME", None);
+
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        assert!(!catalog.contains(&report_dir).unwrap());
+        let id = catalog.insert(&intake, "2026-01-01T10:00:00Z").unwrap();
+        assert!(catalog.contains(&report_dir).unwrap());
+        assert!(catalog.insert(&intake, "2026-01-01T10:01:00Z").is_err());
+        catalog
+            .set_unpacked(id, "/srv/unpacked/HOST01_Triage")
+            .unwrap();
+
+        // reopened, e.g. after a restart of the receiver
+        let catalog = Catalog::open(&dir.join("catalog.db")).unwrap();
+        let entries = catalog.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].case, "ACME");
+        assert_eq!(entries[0].status, "unverified");
+        assert_eq!(
+            entries[0].unpacked.as_deref(),
+            Some("/srv/unpacked/HOST01_Triage")
+        );
+    }
+}
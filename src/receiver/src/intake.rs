@@ -0,0 +1,435 @@
+// Finds the reports in the drop directory and verifies them before they are cataloged. The
+// manifest and the endpoint summary are written along with the archive, so their checksums only
+// detect damage. Only the authentication tag of the encrypted archive, checked with the private
+// key, tells that the archive wasn't modified. The reports are only read, they stay where they were dropped
+use config::workflow::Algorithm;
+use crypto::{get_metadata, verify_evidence, Digest, EncryptionMeta, HashingReader, SessionKey};
+use openssl::{pkey::Private, rsa::Rsa};
+use report::{
+    ENCRYPTION_PATH, ENDPOINT_SUMMARY_PATH, MANIFEST_PATH, METADATA_PATH, SEVEN_ZIP_PATH,
+    TAR_ZSTD_PATH, ZIP_PATH,
+};
+use serde_json::Value;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use storage::Manifest;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    // the authentication tag of the encrypted archive was checked with the private key
+    Verified,
+    // the archive matches its manifest and endpoint summary (if any), but it couldn't be
+    // authenticated, e.g. without a private key or if it isn't encrypted
+    Unverified,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Verified => "verified",
+            Status::Unverified => "unverified",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Intake {
+    // name of the report directory
+    pub report: String,
+    pub path: PathBuf,
+    // directory the report was dropped into, empty at the top level of the drop directory
+    pub case: String,
+    pub device: String,
+    pub workflow: String,
+    pub run_id: String,
+    pub started: String,
+    pub finished: String,
+    pub archive: String,
+    pub archive_size: u64,
+    pub archive_sha1: String,
+    pub archive_sha256: String,
+    pub algorithm: String,
+    pub aborted: Option<String>,
+    pub status: Status,
+    pub errors: Vec<String>,
+}
+
+fn is_report_dir(dir: &Path) -> bool {
+    [ZIP_PATH, TAR_ZSTD_PATH, SEVEN_ZIP_PATH, METADATA_PATH]
+        .iter()
+        .any(|name| dir.join(name).is_file())
+}
+
+// time of the last change of any file of the directory
+fn last_modified(dir: &Path) -> Option<SystemTime> {
+    let mut latest = fs::metadata(dir).and_then(|meta| meta.modified()).ok();
+    for entry in fs::read_dir(dir).ok()?.filter_map(Result::ok) {
+        let modified = match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => last_modified(&entry.path()),
+            _ => entry.metadata().and_then(|meta| meta.modified()).ok(),
+        };
+        latest = latest.max(modified);
+    }
+    latest
+}
+
+/// Reports of the drop directory and their case, either at the top level or one directory below it
+/// (drop/[case]/[report]). Reports which changed during the last `settle` are still being written,
+/// hidden directories are skipped
+pub fn find_reports(drop_dir: &Path, settle: Duration) -> Vec<(PathBuf, String)> {
+    let settled = |dir: &Path| {
+        last_modified(dir)
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|elapsed| elapsed >= settle)
+    };
+    let subdirs = |dir: &Path| -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    // hidden directories hold reports which are still being downloaded
+                    .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir())
+                    .collect()
+            })
+            .unwrap_or_default();
+        dirs.sort();
+        dirs
+    };
+
+    let mut reports = vec![];
+    for dir in subdirs(drop_dir) {
+        if is_report_dir(&dir) {
+            reports.push((dir, String::new()));
+            continue;
+        }
+        let case = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        for report_dir in subdirs(&dir) {
+            if is_report_dir(&report_dir) {
+                reports.push((report_dir, case.clone()));
+            }
+        }
+    }
+    reports.retain(|(dir, _)| settled(dir));
+    reports
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+    Ok(serde_json::from_reader(File::open(path)?)?)
+}
+
+// decrypts the session key with the private key and checks the authentication tag of the archive
+fn authenticate(
+    archive_path: &Path,
+    meta: &EncryptionMeta,
+    private_key: &Rsa<Private>,
+) -> Result<(), Box<dyn Error>> {
+    let key = SessionKey::decrypt(private_key, meta.algorithm, &meta.encrypted_key)?;
+    verify_evidence(
+        BufReader::new(File::open(archive_path)?),
+        &key,
+        &meta.iv,
+        &meta.tag,
+    )
+}
+
+/// Verifies the report directory. Problems are recorded in the errors of the intake.
+/// Without the private key, the report is at most unverified
+pub fn verify(dir: &Path, case: &str, private_key: Option<&Rsa<Private>>) -> Intake {
+    let mut intake = Intake {
+        report: dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        path: dir.to_path_buf(),
+        case: case.to_string(),
+        device: String::new(),
+        workflow: String::new(),
+        run_id: String::new(),
+        started: String::new(),
+        finished: String::new(),
+        archive: String::new(),
+        archive_size: 0,
+        archive_sha1: String::new(),
+        archive_sha256: String::new(),
+        algorithm: String::new(),
+        aborted: None,
+        status: Status::Unverified,
+        errors: vec![],
+    };
+    // whether the authentication tag of the archive matched
+    let mut authenticated = false;
+
+    let archive_path = [TAR_ZSTD_PATH, SEVEN_ZIP_PATH, ZIP_PATH]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file());
+    if let Some(archive_path) = &archive_path {
+        intake.archive = archive_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let digests = File::open(archive_path)
+            .map_err(|e| e.into())
+            .and_then(|file| HashingReader::new(file, &[Digest::Sha1, Digest::Sha256]))
+            .and_then(|reader| reader.digest_remaining());
+        match digests {
+            Ok(digests) => {
+                intake.archive_sha1 = digests.hex(Digest::Sha1);
+                intake.archive_sha256 = digests.hex(Digest::Sha256);
+            }
+            Err(e) => intake
+                .errors
+                .push(format!("Failed to read {}: {}", intake.archive, e)),
+        }
+        intake.archive_size = fs::metadata(archive_path)
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+
+        match get_metadata(&dir.join(ENCRYPTION_PATH)) {
+            Ok(meta) => {
+                intake.algorithm = meta.algorithm.to_string();
+                if let (Some(private_key), false) = (private_key, meta.algorithm == Algorithm::None)
+                {
+                    match authenticate(archive_path, &meta, private_key) {
+                        Ok(()) => authenticated = true,
+                        Err(e) => intake
+                            .errors
+                            .push(format!("Failed to authenticate the archive: {}", e)),
+                    }
+                }
+            }
+            Err(e) => intake
+                .errors
+                .push(format!("Invalid {}: {}", ENCRYPTION_PATH, e)),
+        }
+    }
+
+    let manifest_path = dir.join(MANIFEST_PATH);
+    if manifest_path.exists() {
+        match read_json::<Manifest>(&manifest_path) {
+            Ok(manifest) => {
+                if manifest.archive != intake.archive {
+                    intake.errors.push(format!(
+                        "The manifest describes {:?}, but the report contains {:?}",
+                        manifest.archive, intake.archive
+                    ));
+                } else if manifest.archive_size != intake.archive_size
+                    || !manifest
+                        .archive_sha1
+                        .eq_ignore_ascii_case(&intake.archive_sha1)
+                {
+                    intake
+                        .errors
+                        .push("The archive doesn't match the manifest".to_string());
+                }
+                intake.run_id = manifest.run_id;
+                intake.aborted = manifest.aborted;
+            }
+            Err(e) => intake
+                .errors
+                .push(format!("Invalid {}: {}", MANIFEST_PATH, e)),
+        }
+    }
+
+    let summary_path = dir.join(ENDPOINT_SUMMARY_PATH);
+    if summary_path.exists() {
+        match read_json::<Value>(&summary_path) {
+            Ok(summary) => {
+                let field = |name: &str| summary[name].as_str().unwrap_or_default().to_string();
+                intake.device = field("hostname");
+                intake.workflow = field("workflow");
+                intake.started = field("started");
+                intake.finished = field("finished");
+                let run_id = field("run_id");
+                if !intake.run_id.is_empty() && run_id != intake.run_id {
+                    intake.errors.push(format!(
+                        "The run ID of the manifest ({}) differs from the endpoint summary ({})",
+                        intake.run_id, run_id
+                    ));
+                }
+                intake.run_id = run_id;
+                if let Some(sha1) = summary["report_sha1"].as_str() {
+                    if !sha1.eq_ignore_ascii_case(&intake.archive_sha1) {
+                        intake
+                            .errors
+                            .push("The archive doesn't match the endpoint summary".to_string());
+                    }
+                }
+            }
+            Err(e) => intake
+                .errors
+                .push(format!("Invalid {}: {}", ENDPOINT_SUMMARY_PATH, e)),
+        }
+    }
+
+    intake.status = match (intake.errors.is_empty(), authenticated) {
+        (false, _) => Status::Failed,
+        (true, false) => Status::Unverified,
+        (true, true) => Status::Verified,
+    };
+    intake
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use config::workflow::Algorithm;
+    use crypto::EncryptionMeta;
+    use utils::tests::Cleanup;
+
+    fn write_report(dir: &Path, archive: &[u8]) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(ZIP_PATH), archive).unwrap();
+        let mut meta = EncryptionMeta::default();
+        meta.algorithm = Algorithm::AES128GCM;
+        fs::write(
+            dir.join(ENCRYPTION_PATH),
+            serde_json::to_string(&meta).unwrap(),
+        )
+        .unwrap();
+        let manifest = Manifest {
+            report: "HOST01_Triage".to_string(),
+            created: "2026-01-01T10:00:00Z".to_string(),
+            archive: ZIP_PATH.to_string(),
+            archive_size: 8,
+            archive_sha1: "a3b4f3c0e2d0a1f06ea9a0b6fab7f4e0a8e1e9fd".to_string(),
+            algorithm: Algorithm::AES128GCM,
+            run_id: "0b6f1c5e".to_string(),
+            aborted: None,
+            ip_addresses: vec![],
+            mac_addresses: vec![],
+            domain: None,
+            boot_time: None,
+            locale: String::new(),
+            exclusions: vec![],
+        };
+        fs::write(
+            dir.join(MANIFEST_PATH),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+    }
+
+    /// A report whose checksums match its archive, so it is unverified without a private key
+    pub(crate) fn write_valid_report(dir: &Path, archive: &[u8]) {
+        write_report(dir, archive);
+        let sha1 = crypto::get_file_sha1(&dir.join(ZIP_PATH)).unwrap();
+        let mut manifest: Manifest = read_json(&dir.join(MANIFEST_PATH)).unwrap();
+        manifest.archive_size = archive.len() as u64;
+        manifest.archive_sha1 = sha1.clone();
+        fs::write(
+            dir.join(MANIFEST_PATH),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            dir.join(ENDPOINT_SUMMARY_PATH),
+            format!(
+                r#"{{"hostname": "HOST01", "workflow": "Triage", "run_id": "0b6f1c5e", "report_sha1": "{}"}}"#,
+                sha1
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_find_and_verify_reports() {
+        let mut cleanup = Cleanup::new();
+        let drop_dir = cleanup.tmp_dir("test_find_and_verify_reports");
+        write_report(&drop_dir.join("HOST01_Triage"), b"evidence");
+        write_report(&drop_dir.join("ACME").join("HOST02_Triage"), b"modified");
+        fs::create_dir_all(drop_dir.join("empty")).unwrap();
+
+        assert!(find_reports(&drop_dir, Duration::from_secs(3600)).is_empty());
+        let reports = find_reports(&drop_dir, Duration::ZERO);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].1, "ACME");
+        assert_eq!(reports[1].1, "");
+
+        let sha1 = crypto::get_file_sha1(&reports[1].0.join(ZIP_PATH)).unwrap();
+        let mut manifest: Manifest = read_json(&reports[1].0.join(MANIFEST_PATH)).unwrap();
+        manifest.archive_sha1 = sha1.clone();
+        fs::write(
+            reports[1].0.join(MANIFEST_PATH),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            reports[1].0.join(ENDPOINT_SUMMARY_PATH),
+            format!(
+                r#"{{"hostname": "HOST01", "workflow": "Triage", "run_id": "0b6f1c5e", "report_sha1": "{}"}}"#,
+                sha1
+            ),
+        )
+        .unwrap();
+
+        // the checksums are written along with the archive, they don't authenticate it
+        let intake = verify(&reports[1].0, &reports[1].1, None);
+        assert_eq!(intake.status, Status::Unverified, "{:?}", intake.errors);
+        assert_eq!(intake.report, "HOST01_Triage");
+        assert_eq!(intake.device, "HOST01");
+        assert_eq!(intake.run_id, "0b6f1c5e");
+        assert_eq!(intake.algorithm, "AES-128-GCM");
+        assert_eq!(intake.archive_sha1, sha1);
+        assert_eq!(intake.archive_sha256.len(), 64);
+
+        // the archive was changed after the manifest was written
+        let intake = verify(&reports[0].0, &reports[0].1, None);
+        assert_eq!(intake.status, Status::Failed);
+        assert_eq!(intake.case, "ACME");
+        assert_eq!(
+            intake.errors,
+            vec!["The archive doesn't match the manifest"]
+        );
+    }
+
+    #[test]
+    fn test_verify_authenticated() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup
+            .tmp_dir("test_verify_authenticated")
+            .join("HOST01_Triage");
+        fs::create_dir_all(&dir).unwrap();
+        let archive_path = dir.join(ZIP_PATH);
+        fs::write(&archive_path, b"evidence").unwrap();
+        let private_key = Rsa::generate(2048).unwrap();
+        let public_key =
+            Rsa::public_key_from_pem(&private_key.public_key_to_pem().unwrap()).unwrap();
+        let key = SessionKey::generate(Algorithm::AES128GCM).unwrap();
+        let mut meta = crypto::encrypt_evidence_with_key(&archive_path, &key).unwrap();
+        meta.encrypted_key = key.encrypt(&public_key).unwrap();
+        fs::write(
+            dir.join(ENCRYPTION_PATH),
+            serde_json::to_string(&meta).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(verify(&dir, "", None).status, Status::Unverified);
+        let intake = verify(&dir, "", Some(&private_key));
+        assert_eq!(intake.status, Status::Verified, "{:?}", intake.errors);
+
+        // the key of another receiver can't decrypt the session key
+        let other_key = Rsa::generate(2048).unwrap();
+        assert_eq!(verify(&dir, "", Some(&other_key)).status, Status::Failed);
+
+        // a modified archive with matching checksums is still detected
+        let mut archive = fs::read(&archive_path).unwrap();
+        archive[0] ^= 1;
+        fs::write(&archive_path, archive).unwrap();
+        let intake = verify(&dir, "", Some(&private_key));
+        assert_eq!(intake.status, Status::Failed);
+        assert!(intake.errors[0].starts_with("Failed to authenticate the archive"));
+    }
+}
@@ -0,0 +1,345 @@
+// Central intake of the reports: watches a drop directory (and optionally a bucket) for incoming
+// reports, verifies them, records them in a SQLite catalog and optionally unpacks them
+mod catalog;
+mod intake;
+mod s3;
+
+use catalog::Catalog;
+use chrono::{SecondsFormat, Utc};
+use clap::{Arg, ArgAction, Command};
+use config::config::S3Target;
+use crypto::load_private_key;
+use intake::{find_reports, verify, Status};
+use log::{error, info, warn, LevelFilter};
+use logging::Logger;
+use openssl::{pkey::Private, rsa::Rsa};
+use report::{Report, TOOLKIT_VERSION};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+// copies of the reports being unpacked, inside output_dir
+const STAGING_DIR: &str = ".staging";
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_settle() -> u64 {
+    30
+}
+
+fn default_timeout() -> u64 {
+    600
+}
+
+#[derive(Debug, Deserialize)]
+struct UnpackSettings {
+    private_key: PathBuf,
+    output_dir: PathBuf,
+    // the unpacker next to the receiver by default
+    #[serde(default)]
+    unpacker: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReceiverConfig {
+    drop_dir: PathBuf,
+    // drop_dir/catalog.db by default
+    #[serde(default)]
+    catalog: Option<PathBuf>,
+    // seconds between two scans
+    #[serde(default = "default_interval")]
+    interval: u64,
+    // seconds without changes before a report is considered complete
+    #[serde(default = "default_settle")]
+    settle: u64,
+    // seconds per request to the bucket
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    #[serde(default)]
+    s3: Option<S3Target>,
+    #[serde(default)]
+    unpack: Option<UnpackSettings>,
+    // authenticates the archives, the private key of unpack by default
+    #[serde(default)]
+    private_key: Option<PathBuf>,
+}
+
+impl ReceiverConfig {
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let config: Self = serde_yaml::from_reader(fs::File::open(path)?)?;
+        if !config.drop_dir.is_dir() {
+            return Err(format!(
+                "The drop directory {} does not exist",
+                config.drop_dir.display()
+            )
+            .into());
+        }
+        Ok(config)
+    }
+
+    fn private_key_path(&self) -> Option<&PathBuf> {
+        self.private_key
+            .as_ref()
+            .or(self.unpack.as_ref().map(|unpack| &unpack.private_key))
+    }
+
+    fn catalog_path(&self) -> PathBuf {
+        self.catalog
+            .clone()
+            .unwrap_or_else(|| self.drop_dir.join("catalog.db"))
+    }
+}
+
+fn get_command() -> Command {
+    Command::new("Receiver")
+        .version(TOOLKIT_VERSION)
+        .about("Verifies and catalogs incoming reports")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("CONFIG")
+                .default_value("receiver.yaml")
+                .help("The configuration of the receiver"),
+        )
+        .arg(
+            Arg::new("once")
+                .long("once")
+                .action(ArgAction::SetTrue)
+                .help("Scan once and exit instead of watching the drop directory"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Enables verbose logging")
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(Command::new("list").about("Lists the cataloged reports"))
+}
+
+fn unpacker_path(settings: &UnpackSettings) -> PathBuf {
+    if let Some(unpacker) = &settings.unpacker {
+        return unpacker.clone();
+    }
+    let name = format!("unpacker{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(&name)))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+// returns the output directory, or why the report could not be unpacked.
+// The unpacker decrypts the archive in place, so it unpacks a copy: the dropped report keeps
+// matching its checksums in the catalog and the manifest
+fn unpack(settings: &UnpackSettings, report_dir: &Path, case: &str) -> Result<PathBuf, String> {
+    let name = report_dir.file_name().unwrap_or_default();
+    let output = settings.output_dir.join(case).join(name);
+    let staging = settings.output_dir.join(STAGING_DIR).join(case).join(name);
+    // left over if the receiver was stopped while unpacking
+    let _ = fs::remove_dir_all(&staging);
+    Report::copy_dir_all(report_dir, &staging)
+        .map_err(|e| format!("Failed to copy the report to {}: {}", staging.display(), e))?;
+    let status = process::Command::new(unpacker_path(settings))
+        .arg("--input")
+        .arg(&staging)
+        .arg("--private")
+        .arg(&settings.private_key)
+        .arg("--output")
+        .arg(&output)
+        .status();
+    if let Err(e) = fs::remove_dir_all(&staging) {
+        warn!("Failed to remove {}: {}", staging.display(), e);
+    }
+    let status = status.map_err(|e| format!("Failed to start the unpacker: {}", e))?;
+    match status.success() {
+        true => Ok(output),
+        false => Err(format!("The unpacker exited with {}", status)),
+    }
+}
+
+fn scan(
+    config: &ReceiverConfig,
+    catalog: &Catalog,
+    private_key: Option<&Rsa<Private>>,
+) -> Result<(), Box<dyn Error>> {
+    let settle = Duration::from_secs(config.settle);
+    if let Some(target) = &config.s3 {
+        // the reports already downloaded are still cataloged if the bucket can't be reached
+        if let Err(e) = s3::sync(
+            target,
+            &config.drop_dir,
+            settle,
+            Duration::from_secs(config.timeout),
+        ) {
+            error!(
+                "Failed to download the reports of bucket {:?}: {}",
+                target.bucket, e
+            );
+        }
+    }
+
+    for (report_dir, case) in find_reports(&config.drop_dir, settle) {
+        if catalog.contains(&report_dir)? {
+            continue;
+        }
+        let intake = verify(&report_dir, &case, private_key);
+        match intake.status {
+            Status::Failed => warn!(
+                "Report {} failed the verification: {}",
+                report_dir.display(),
+                intake.errors.join("; ")
+            ),
+            status => info!("Report {} is {}", report_dir.display(), status.as_str()),
+        }
+        let received = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let id = catalog.insert(&intake, &received)?;
+
+        // a report which failed the verification is kept for the analyst to look at
+        if let (Some(settings), false) = (&config.unpack, intake.status == Status::Failed) {
+            let unpacked = match unpack(settings, &report_dir, &case) {
+                Ok(output) => {
+                    info!("Unpacked report {} to {}", intake.report, output.display());
+                    output.to_string_lossy().to_string()
+                }
+                Err(e) => {
+                    error!("Failed to unpack report {}: {}", intake.report, e);
+                    format!("failed: {}", e)
+                }
+            };
+            catalog.set_unpacked(id, &unpacked)?;
+        }
+    }
+    Ok(())
+}
+
+fn run(matches: clap::ArgMatches) -> Result<(), Box<dyn Error>> {
+    let config_path = PathBuf::from(matches.get_one::<String>("config").unwrap());
+    let config = ReceiverConfig::read(&config_path)
+        .map_err(|e| format!("Invalid config {}: {}", config_path.display(), e))?;
+    let catalog = Catalog::open(&config.catalog_path())?;
+
+    if let Some(("list", _)) = matches.subcommand() {
+        for entry in catalog.entries()? {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                entry.id,
+                entry.received,
+                entry.status,
+                entry.case,
+                entry.device,
+                entry.report,
+                entry.archive_sha256,
+                entry.unpacked.unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
+    let private_key = match config.private_key_path() {
+        Some(path) => Some(
+            load_private_key(path.clone())
+                .map_err(|e| format!("Failed to load private key {}: {}", path.display(), e))?,
+        ),
+        None => {
+            warn!("No private key configured: the reports can't be authenticated and are at most unverified");
+            None
+        }
+    };
+
+    info!(
+        "Watching {} (catalog {})",
+        config.drop_dir.display(),
+        config.catalog_path().display()
+    );
+    loop {
+        scan(&config, &catalog, private_key.as_ref())?;
+        if matches.get_flag("once") {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_secs(config.interval));
+    }
+}
+
+fn main() {
+    let matches = get_command().get_matches();
+
+    let logger = Logger::init()
+        .set_level(match matches.get_flag("verbose") {
+            true => LevelFilter::Debug,
+            false => LevelFilter::Info,
+        })
+        .apply();
+
+    if let Err(e) = run(matches) {
+        error!("{}", e);
+        process::exit(1);
+    }
+
+    logger.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use report::ZIP_PATH;
+    use utils::tests::Cleanup;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_unpacks_a_copy() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_scan_unpacks_a_copy");
+        let drop_dir = dir.join("drop");
+        let report_dir = drop_dir.join("ACME").join("HOST01_Triage");
+        intake::tests::write_valid_report(&report_dir, b"evidence");
+        let archive = fs::read(report_dir.join(ZIP_PATH)).unwrap();
+
+        // like the unpacker, decrypts the archive of --input in place
+        let unpacker = dir.join("unpacker.sh");
+        fs::write(
+            &unpacker,
+            "#!/bin/sh\necho plaintext > \"$2/report.zip\"\nmkdir -p \"$6\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&unpacker, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = ReceiverConfig {
+            drop_dir: drop_dir.clone(),
+            catalog: Some(dir.join("catalog.db")),
+            interval: 0,
+            settle: 0,
+            timeout: 0,
+            s3: None,
+            unpack: Some(UnpackSettings {
+                private_key: dir.join("private.pem"),
+                output_dir: dir.join("unpacked"),
+                unpacker: Some(unpacker),
+            }),
+            private_key: None,
+        };
+        let catalog = Catalog::open(&config.catalog_path()).unwrap();
+        scan(&config, &catalog, None).unwrap();
+
+        let entries = catalog.entries().unwrap();
+        let unpacked = dir.join("unpacked").join("ACME").join("HOST01_Triage");
+        assert_eq!(
+            entries[0].unpacked.as_deref(),
+            Some(unpacked.to_string_lossy().as_ref())
+        );
+        assert!(unpacked.is_dir());
+        assert_eq!(fs::read(report_dir.join(ZIP_PATH)).unwrap(), archive);
+        let staging = dir.join("unpacked").join(STAGING_DIR);
+        assert!(!staging.join("ACME").join("HOST01_Triage").exists());
+    }
+}
@@ -0,0 +1,324 @@
+// Downloads the reports uploaded by the collectors from the bucket into the drop directory,
+// so they are verified and cataloged like reports dropped there by hand
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use config::config::S3Target;
+use log::{debug, info, warn};
+use report::{METADATA_PATH, SEVEN_ZIP_PATH, TAR_ZSTD_PATH, ZIP_PATH};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use workflow::uploader::{build_request, send};
+
+// SHA-256 of the empty payload of GET requests
+const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+#[derive(Debug, PartialEq)]
+struct RemoteObject {
+    key: String,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+// values of all elements with the tag, without nested elements
+fn xml_values<'a>(text: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut values = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                values.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    values
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// objects of a ListObjectsV2 response and the token of the next page
+fn parse_list(xml: &str) -> (Vec<RemoteObject>, Option<String>) {
+    let objects = xml_values(xml, "Contents")
+        .iter()
+        .filter_map(|contents| {
+            let key = xml_values(contents, "Key")
+                .first()
+                .map(|key| xml_unescape(key))?;
+            let last_modified = xml_values(contents, "LastModified")
+                .first()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Utc));
+            Some(RemoteObject { key, last_modified })
+        })
+        .collect();
+    let truncated = xml_values(xml, "IsTruncated").first() == Some(&"true");
+    let token = xml_values(xml, "NextContinuationToken")
+        .first()
+        .filter(|_| truncated)
+        .map(|token| xml_unescape(token));
+    (objects, token)
+}
+
+fn list_objects(
+    agent: &ureq::Agent,
+    target: &S3Target,
+) -> Result<Vec<RemoteObject>, Box<dyn Error>> {
+    let prefix = target.prefix.trim_matches('/');
+    let mut objects = vec![];
+    let mut token = None;
+    loop {
+        let mut query = BTreeMap::new();
+        query.insert("list-type".to_string(), "2".to_string());
+        if !prefix.is_empty() {
+            query.insert("prefix".to_string(), format!("{}/", prefix));
+        }
+        if let Some(token) = token {
+            query.insert("continuation-token".to_string(), token);
+        }
+        let (url, headers) = build_request(
+            target,
+            "GET",
+            "",
+            &query,
+            BTreeMap::new(),
+            EMPTY_PAYLOAD_SHA256,
+            Utc::now(),
+        )?;
+        let response = send(agent.get(&url), &headers, None)?.into_string()?;
+        let (page, next) = parse_list(&response);
+        objects.extend(page);
+        match next {
+            Some(next) => token = Some(next),
+            None => break,
+        }
+    }
+    Ok(objects)
+}
+
+/// Groups the objects by the report they belong to. Reports are recognized by their archive
+/// or metadata file, the key of a report is relative to the prefix, e.g. "ACME/HOST01_Triage".
+/// Like in the drop directory, reports are at most one directory (the case) below the prefix
+fn group_reports<'a>(
+    objects: &'a [RemoteObject],
+    prefix: &str,
+) -> BTreeMap<String, Vec<&'a RemoteObject>> {
+    let prefix = match prefix.trim_matches('/') {
+        "" => String::new(),
+        prefix => format!("{}/", prefix),
+    };
+    let mut reports: BTreeMap<String, Vec<&RemoteObject>> = BTreeMap::new();
+    for object in objects {
+        let Some(relative) = object.key.strip_prefix(&prefix) else {
+            continue;
+        };
+        if let Some((report, name)) = relative.rsplit_once('/') {
+            if ![ZIP_PATH, TAR_ZSTD_PATH, SEVEN_ZIP_PATH, METADATA_PATH].contains(&name) {
+                continue;
+            }
+            // it would be downloaded, but never found in the drop directory
+            if report.split('/').count() > 2 {
+                warn!(
+                    "Skipping report {:?} of the bucket: reports are expected at most one directory below the prefix",
+                    report
+                );
+                continue;
+            }
+            reports.entry(report.to_string()).or_default();
+        }
+    }
+    for object in objects {
+        let relative = object.key.strip_prefix(&prefix).unwrap_or(&object.key);
+        if let Some(report) = reports
+            .keys()
+            .find(|report| relative.starts_with(&format!("{}/", report)))
+            .cloned()
+        {
+            reports.entry(report).or_default().push(object);
+        }
+    }
+    reports
+}
+
+// the key relative to the report as path, None if it would leave the report directory
+fn local_path(dir: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    for part in relative
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+    {
+        // on Windows, a drive (C:) or a backslash leaves the directory as well
+        if part.contains(['\\', ':']) {
+            return None;
+        }
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => path.push(part),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn download(
+    agent: &ureq::Agent,
+    target: &S3Target,
+    key: &str,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let (url, headers) = build_request(
+        target,
+        "GET",
+        key,
+        &BTreeMap::new(),
+        BTreeMap::new(),
+        EMPTY_PAYLOAD_SHA256,
+        Utc::now(),
+    )?;
+    let response = send(agent.get(&url), &headers, None)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    io::copy(&mut response.into_reader(), &mut File::create(path)?)?;
+    Ok(())
+}
+
+/// Downloads the reports of the bucket which are not in the drop directory yet.
+/// Reports with objects uploaded during the last `settle` are still being uploaded.
+/// Returns the number of downloaded reports
+pub fn sync(
+    target: &S3Target,
+    drop_dir: &Path,
+    settle: Duration,
+    timeout: Duration,
+) -> Result<usize, Box<dyn Error>> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    let objects = list_objects(&agent, target)?;
+    let settled_before = Utc::now() - ChronoDuration::from_std(settle)?;
+    let prefix = match target.prefix.trim_matches('/') {
+        "" => String::new(),
+        prefix => format!("{}/", prefix),
+    };
+
+    let mut downloaded = 0;
+    for (report, objects) in group_reports(&objects, &target.prefix) {
+        let Some(report_dir) = local_path(drop_dir, &report) else {
+            warn!("Skipping report {:?} of the bucket: invalid key", report);
+            continue;
+        };
+        if report_dir.exists() {
+            continue;
+        }
+        let settled = objects.iter().all(|object| {
+            object
+                .last_modified
+                .is_some_and(|modified| modified < settled_before)
+        });
+        if !settled {
+            debug!("Report {:?} is still being uploaded", report);
+            continue;
+        }
+
+        // downloaded into a hidden directory first, so a partial report is never verified
+        let name = report_dir.file_name().unwrap_or_default().to_string_lossy();
+        let partial_dir = report_dir.with_file_name(format!(".{}.partial", name));
+        let _ = fs::remove_dir_all(&partial_dir);
+        let mut result = Ok(());
+        for object in &objects {
+            let relative = &object.key[prefix.len() + report.len() + 1..];
+            let Some(path) = local_path(&partial_dir, relative) else {
+                result = Err(format!("invalid key {:?}", object.key).into());
+                break;
+            };
+            result = download(&agent, target, &object.key, &path);
+            if result.is_err() {
+                break;
+            }
+        }
+        match result.and_then(|_| fs::rename(&partial_dir, &report_dir).map_err(|e| e.into())) {
+            Ok(_) => {
+                info!("Downloaded report {:?} ({} objects)", report, objects.len());
+                downloaded += 1;
+            }
+            Err(e) => {
+                warn!("Failed to download report {:?}: {}", report, e);
+                let _ = fs::remove_dir_all(&partial_dir);
+            }
+        }
+    }
+    Ok(downloaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>evidence</Name><IsTruncated>true</IsTruncated>
+<Contents><Key>cases/ACME/HOST01_Triage/report.zip</Key><LastModified>2026-01-01T10:00:00.000Z</LastModified><Size>8</Size></Contents>
+<Contents><Key>cases/ACME/HOST01_Triage/action_output/a&amp;b.txt</Key><LastModified>2026-01-01T10:00:01.000Z</LastModified></Contents>
+<Contents><Key>cases/HOST02_Triage/metadata.csv</Key><LastModified>2026-01-01T10:00:02.000Z</LastModified></Contents>
+<Contents><Key>cases/notes.txt</Key><LastModified>2026-01-01T10:00:03.000Z</LastModified></Contents>
+<Contents><Key>cases/ACME/2026/HOST03_Triage/report.zip</Key><LastModified>2026-01-01T10:00:04.000Z</LastModified></Contents>
+<NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+</ListBucketResult>"#;
+        let (objects, token) = parse_list(xml);
+        assert_eq!(objects.len(), 5);
+        assert_eq!(
+            objects[1].key,
+            "cases/ACME/HOST01_Triage/action_output/a&b.txt"
+        );
+        assert_eq!(
+            objects[0].last_modified.unwrap().to_rfc3339(),
+            "2026-01-01T10:00:00+00:00"
+        );
+        assert_eq!(
+            token.as_deref(),
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=")
+        );
+        assert_eq!(parse_list(&xml.replace("true", "false")).1, None);
+
+        // HOST03_Triage is nested too deep to be found in the drop directory
+        let reports = group_reports(&objects, "/cases/");
+        assert_eq!(
+            reports.keys().collect::<Vec<_>>(),
+            vec!["ACME/HOST01_Triage", "HOST02_Triage"]
+        );
+        assert_eq!(reports["ACME/HOST01_Triage"].len(), 2);
+
+        let dir = Path::new("drop");
+        assert_eq!(
+            local_path(dir, "ACME/HOST01_Triage"),
+            Some(dir.join("ACME").join("HOST01_Triage"))
+        );
+        assert_eq!(local_path(dir, "ACME/../../etc"), None);
+        assert_eq!(
+            local_path(dir, "ACME/./HOST01"),
+            Some(dir.join("ACME").join("HOST01"))
+        );
+        for key in [
+            "C:/Windows",
+            "ACME/C:",
+            "ACME/..\\..\\etc",
+            "\\\\server\\share",
+            "a:b",
+        ] {
+            assert_eq!(local_path(dir, key), None, "{}", key);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Declares the layout of a report, so an unpacker refuses a layout it doesn't know
+// instead of silently misreading it (e.g. chunked files or per-action directories)
+use crate::{
+    ACTION_LOG_DIR, ENDPOINT_SUMMARY_PATH, LOOT_DIR, METADATA_PATH, SEVEN_ZIP_PATH, STORAGE_DIR,
+    TAR_ZSTD_PATH, TOOLKIT_VERSION, ZIP_PATH,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fs, io::Read, path::Path};
+
+pub const LAYOUT_PATH: &str = "layout.json";
+// increased whenever the meaning of a directory or file of the report changes
+pub const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Layout {
+    pub version: u32,
+    pub toolkit_version: String,
+    // directories (ending with /) and files of the report with their meaning
+    pub entries: BTreeMap<String, String>,
+}
+
+impl Layout {
+    pub fn current() -> Self {
+        let entries = [
+            (
+                format!("{}/", LOOT_DIR),
+                "Files written by the actions (e.g. memory dumps), stored under their file name",
+            ),
+            (
+                format!("{}/", STORAGE_DIR),
+                "Files stored by the actions, named by the SHA-1 checksum of their original path (or of their content if content_addressed is set in metadata.csv)",
+            ),
+            (
+                format!("{}/", ACTION_LOG_DIR),
+                "Output of each action, actions.jsonl, summary.json and operator_audit.jsonl. Files ending with .enc are encrypted with the session key of the report",
+            ),
+            (
+                METADATA_PATH.to_string(),
+                "One record per stored file with its original path, MAC times, checksums and link target",
+            ),
+            (
+                ZIP_PATH.to_string(),
+                "Archive of the report, encrypted as described by encryption.json",
+            ),
+            (
+                TAR_ZSTD_PATH.to_string(),
+                "Archive of the report in the tar_zstd format instead of report.zip, encrypted as described by encryption.json",
+            ),
+            (
+                SEVEN_ZIP_PATH.to_string(),
+                "Archive of the report in the 7z format instead of report.zip, encrypted as described by encryption.json",
+            ),
+            (
+                ENDPOINT_SUMMARY_PATH.to_string(),
+                "Single JSON line with the endpoint (hostname, OS, IP addresses, domain) and the result of the run, outside of the archive",
+            ),
+        ];
+        Self {
+            version: LAYOUT_VERSION,
+            toolkit_version: TOOLKIT_VERSION.to_string(),
+            entries: entries
+                .into_iter()
+                .map(|(name, meaning)| (name, meaning.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Error if the report was written in a layout newer than this toolkit supports
+    pub fn check(&self) -> Result<(), String> {
+        if self.version > LAYOUT_VERSION {
+            return Err(format!(
+                "The report uses layout version {} (toolkit {}), but this unpacker only supports version {}. Please update the unpacker",
+                self.version, self.toolkit_version, LAYOUT_VERSION
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Comment of the archive, which binds it to its metadata.csv
+pub fn archive_comment(collected: &str, metadata_sha1: &str) -> String {
+    format!(
+        "ir-toolkit {} report (layout {}), collected {}, {} sha1 {}",
+        TOOLKIT_VERSION, LAYOUT_VERSION, collected, METADATA_PATH, metadata_sha1
+    )
+}
+
+/// The checksum of the metadata.csv in the comment of an archive
+pub fn comment_metadata_sha1(comment: &str) -> Option<&str> {
+    let (_, sha1) = comment.rsplit_once(&format!("{} sha1 ", METADATA_PATH))?;
+    Some(sha1.trim()).filter(|sha1| !sha1.is_empty())
+}
+
+pub fn write_layout(report_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let content = serde_json::to_string_pretty(&Layout::current())?;
+    fs::write(report_dir.join(LAYOUT_PATH), content)?;
+    Ok(())
+}
+
+pub fn read_layout<R: Read>(reader: R) -> Result<Layout, String> {
+    serde_json::from_reader(reader).map_err(|e| format!("Failed to read {}: {}", LAYOUT_PATH, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_layout() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_layout");
+        write_layout(&dir).unwrap();
+
+        let layout = read_layout(fs::File::open(dir.join(LAYOUT_PATH)).unwrap()).unwrap();
+        assert_eq!(layout, Layout::current());
+        assert!(layout.entries.contains_key("stored_files/"));
+        assert!(layout.check().is_ok());
+
+        // a newer layout is refused instead of being misread
+        let newer = Layout {
+            version: LAYOUT_VERSION + 1,
+            ..Layout::current()
+        };
+        assert!(newer.check().unwrap_err().contains("update the unpacker"));
+        assert!(read_layout("{}".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_archive_comment() {
+        let comment = archive_comment("2024-08-12T13:45:20Z", "ab12");
+        assert!(comment.contains("collected 2024-08-12T13:45:20Z"));
+        assert_eq!(comment_metadata_sha1(&comment), Some("ab12"));
+        assert_eq!(comment_metadata_sha1(""), None);
+        assert_eq!(comment_metadata_sha1("metadata.csv sha1 "), None);
+    }
+}
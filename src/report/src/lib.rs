@@ -2,25 +2,55 @@ use chrono::Local;
 use log::{debug, warn};
 use std::path::{Path, PathBuf};
 use std::{fs, io};
-use system::SystemVariables;
+use system::{endpoint::HostContext, SystemVariables};
 use utils::sanitize::sanitize_dirname;
+use uuid::Uuid;
 
+pub mod layout;
+pub mod retention;
+
+pub const REPORTS_DIR: &str = "reports";
 pub const ZIP_PATH: &str = "report.zip";
+// the archive in the tar_zstd format, converted from report.zip once it is complete
+pub const TAR_ZSTD_PATH: &str = "report.tar.zst";
+// the archive in the 7z format, converted from report.zip once it is complete
+pub const SEVEN_ZIP_PATH: &str = "report.7z";
 pub const METADATA_PATH: &str = "metadata.csv";
 pub const ENCRYPTION_PATH: &str = "encryption.json";
 pub const LOOT_DIR: &str = "loot_files";
 pub const STORAGE_DIR: &str = "stored_files";
 pub const ACTION_LOG_DIR: &str = "action_output";
+pub const README_PATH: &str = "README.txt";
+pub const FINDINGS_PATH: &str = "findings.json";
+// matches of the YARA sweep over the collected files
+pub const SWEEP_PATH: &str = "yara_sweep.csv";
+// log of the workflow, only written if the sidecar files are sealed into the archive
+pub const COLLECTOR_LOG_PATH: &str = "collector.log";
+// the only description of a sealed report outside of the archive
+pub const MANIFEST_PATH: &str = "manifest.json";
+// single line describing the endpoint and the run, aggregated across collections
+pub const ENDPOINT_SUMMARY_PATH: &str = "endpoint_summary.json";
+
+// Version of the collector and unpacker
+pub const TOOLKIT_VERSION: &str = "1.0";
 
 #[derive(Debug)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct Report {
     pub dir: PathBuf,
     pub loot_dir: PathBuf,
     pub action_log_dir: PathBuf,
     pub zip_path: PathBuf,
+    pub tar_path: PathBuf,
+    pub seven_zip_path: PathBuf,
     pub metadata_path: PathBuf,
     pub encryption_path: PathBuf,
     pub archive_enabled: bool,
+    // random UUID of the run, correlates the outputs of a run after they have been moved around
+    pub run_id: String,
+    // network context of the endpoint, recorded in the manifest
+    pub host: HostContext,
 }
 
 impl Report {
@@ -39,7 +69,7 @@ impl Report {
         let report_name = sanitize_dirname(&report_name);
 
         // check if reports directory exists and create it if not
-        let reports_dir = system_variables.base_path.join("reports");
+        let reports_dir = system_variables.base_path.join(REPORTS_DIR);
         if !reports_dir.exists() {
             fs::create_dir(&reports_dir).expect("Failed to create reports directory");
         }
@@ -71,8 +101,12 @@ impl Report {
         // update system variables with current loot directory
         // each report has its own loot directory
         system_variables.loot_directory = loot_dir.clone();
+        let run_id = Uuid::new_v4().to_string();
+        system_variables.run_id = run_id.clone();
 
         let zip_path = report_dir.join(ZIP_PATH);
+        let tar_path = report_dir.join(TAR_ZSTD_PATH);
+        let seven_zip_path = report_dir.join(SEVEN_ZIP_PATH);
         let metadata_path = report_dir.join(METADATA_PATH);
         let encryption_path = report_dir.join(ENCRYPTION_PATH);
 
@@ -81,9 +115,13 @@ impl Report {
             loot_dir,
             action_log_dir,
             zip_path,
+            tar_path,
+            seven_zip_path,
             metadata_path,
             encryption_path,
             archive_enabled,
+            run_id,
+            host: system_variables.host.clone(),
         });
     }
 
@@ -173,6 +211,8 @@ mod tests {
             report.action_log_dir.exists(),
             "Action log directory does not exist"
         );
+        assert!(Uuid::parse_str(&report.run_id).is_ok());
+        assert_eq!(system_variables.run_id, report.run_id);
 
         if report.archive_enabled {
             assert!(
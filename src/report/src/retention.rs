@@ -0,0 +1,294 @@
+use crate::{Report, REPORTS_DIR};
+use config::config::Retention;
+use log::{debug, info, warn};
+use std::{
+    cmp::Reverse,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug)]
+pub struct ReportEntry {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    pub size: u64,
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(ty) if ty.is_dir() => dir_size(&entry.path()),
+            Ok(_) => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// List all reports inside the reports directory, newest first
+pub fn list_reports(reports_dir: &Path) -> io::Result<Vec<ReportEntry>> {
+    let mut reports: Vec<ReportEntry> = fs::read_dir(reports_dir)?
+        .flatten()
+        .filter(|entry| entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false))
+        .map(|entry| {
+            let path = entry.path();
+            ReportEntry {
+                modified: entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+                size: dir_size(&path),
+                path,
+            }
+        })
+        .collect();
+    reports.sort_by_key(|report| Reverse(report.modified));
+    Ok(reports)
+}
+
+// Absolute path without links or "..", even if the directory doesn't exist yet
+fn resolve(path: &Path) -> PathBuf {
+    let mut missing = vec![];
+    let mut existing = path;
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(resolved, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Returns the reports that violate the retention policy.
+/// Expects the reports to be sorted newest first. The newest report and the reports of the
+/// current run (by their directory name) are always kept, but count towards the limits
+pub fn select_expired<'a>(
+    reports: &'a [ReportEntry],
+    retention: &Retention,
+    now: SystemTime,
+    current_run: &[String],
+) -> Vec<&'a ReportEntry> {
+    let max_age = Duration::from_secs(retention.max_age.max(0) as u64);
+    let mut total_size: u64 = 0;
+
+    reports
+        .iter()
+        .enumerate()
+        .filter(|(index, report)| {
+            total_size += report.size;
+            let name = report.path.file_name().unwrap_or_default();
+            if *index == 0 || current_run.iter().any(|current| name == current.as_str()) {
+                return false;
+            }
+            let too_many = retention.max_count != 0 && *index >= retention.max_count;
+            let too_old = retention.max_age > 0
+                && now
+                    .duration_since(report.modified)
+                    .is_ok_and(|age| age > max_age);
+            let too_large = retention.max_total_size != 0 && total_size > retention.max_total_size;
+            too_many || too_old || too_large
+        })
+        .map(|(_, report)| report)
+        .collect()
+}
+
+/// Delete (or move to the archive directory) all reports violating the retention policy,
+/// except the reports of the current run. Returns the paths of the removed reports
+pub fn enforce_retention(
+    base_path: &Path,
+    retention: &Retention,
+    current_run: &[String],
+) -> io::Result<Vec<PathBuf>> {
+    if retention.max_count == 0 && retention.max_age <= 0 && retention.max_total_size == 0 {
+        debug!("No report retention policy configured");
+        return Ok(vec![]);
+    }
+
+    let reports_dir = base_path.join(REPORTS_DIR);
+    if !reports_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let archive_dir = match retention.archive_dir.is_empty() {
+        true => None,
+        false => {
+            let dir = PathBuf::from(&retention.archive_dir);
+            Some(match dir.is_absolute() {
+                true => dir,
+                false => base_path.join(dir),
+            })
+        }
+    };
+
+    // archived reports would be listed and pruned again on the next run
+    if let Some(archive_dir) = &archive_dir {
+        if resolve(archive_dir).starts_with(resolve(&reports_dir)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "archive_dir {:?} must not be inside the reports directory",
+                    archive_dir.display()
+                ),
+            ));
+        }
+    }
+
+    let reports = list_reports(&reports_dir)?;
+    let mut removed = vec![];
+    for report in select_expired(&reports, retention, SystemTime::now(), current_run) {
+        let result = match &archive_dir {
+            Some(archive_dir) => {
+                let target = archive_dir.join(report.path.file_name().unwrap_or_default());
+                info!(
+                    "Retention policy: moving report {:?} to {:?}",
+                    report.path.display(),
+                    target.display()
+                );
+                fs::create_dir_all(archive_dir).and_then(|_| {
+                    // rename fails across file systems, so fall back to copying
+                    fs::rename(&report.path, &target).or_else(|_| {
+                        Report::copy_dir_all(&report.path, &target)
+                            .and_then(|_| fs::remove_dir_all(&report.path))
+                    })
+                })
+            }
+            None => {
+                info!(
+                    "Retention policy: deleting report {:?}",
+                    report.path.display()
+                );
+                fs::remove_dir_all(&report.path)
+            }
+        };
+
+        match result {
+            Ok(_) => removed.push(report.path.clone()),
+            Err(e) => warn!(
+                "Failed to apply retention policy to {:?}: {}",
+                report.path.display(),
+                e
+            ),
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    fn entry(name: &str, age_secs: u64, size: u64, now: SystemTime) -> ReportEntry {
+        ReportEntry {
+            path: PathBuf::from(name),
+            modified: now - Duration::from_secs(age_secs),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_select_expired() {
+        let now = SystemTime::now();
+        let reports = vec![
+            entry("newest", 10, 500, now),
+            entry("middle", 100, 300, now),
+            entry("old", 1000, 300, now),
+        ];
+
+        let mut retention = Retention::default();
+        assert!(select_expired(&reports, &retention, now, &[]).is_empty());
+
+        retention.max_count = 2;
+        let expired = select_expired(&reports, &retention, now, &[]);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].path, PathBuf::from("old"));
+
+        let retention = Retention {
+            max_age: 50,
+            ..Default::default()
+        };
+        assert_eq!(select_expired(&reports, &retention, now, &[]).len(), 2);
+
+        // the newest report is kept, even if it exceeds the limit on its own
+        let retention = Retention {
+            max_total_size: 400,
+            ..Default::default()
+        };
+        assert_eq!(select_expired(&reports, &retention, now, &[]).len(), 2);
+
+        // both reports of the current run are kept
+        let current_run = ["newest".to_string(), "middle".to_string()];
+        let retention = Retention {
+            max_count: 1,
+            ..Default::default()
+        };
+        let expired = select_expired(&reports, &retention, now, &current_run);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].path, PathBuf::from("old"));
+    }
+
+    #[test]
+    fn test_enforce_retention() {
+        let mut cleanup = Cleanup::new();
+        let base_path = cleanup.tmp_dir("test_enforce_retention");
+        let reports_dir = base_path.join(REPORTS_DIR);
+        // make sure the modification times differ
+        cleanup.create_files(&reports_dir, vec!["first/metadata.csv"]);
+        std::thread::sleep(Duration::from_millis(50));
+        cleanup.create_files(&reports_dir, vec!["second/metadata.csv"]);
+
+        let retention = Retention {
+            max_count: 1,
+            archive_dir: "archive".to_string(),
+            ..Default::default()
+        };
+        let removed = enforce_retention(&base_path, &retention, &[]).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(base_path.join("archive").join("first").exists());
+        assert!(reports_dir.join("second").exists());
+
+        // two reports of the current run, written after second
+        std::thread::sleep(Duration::from_millis(50));
+        cleanup.create_files(&reports_dir, vec!["third/metadata.csv"]);
+        std::thread::sleep(Duration::from_millis(50));
+        cleanup.create_files(&reports_dir, vec!["fourth/metadata.csv"]);
+        let current_run = ["third".to_string(), "fourth".to_string()];
+        let removed = enforce_retention(&base_path, &retention, &current_run).unwrap();
+        assert_eq!(removed, [reports_dir.join("second")]);
+        assert!(reports_dir.join("third").exists());
+        assert!(reports_dir.join("fourth").exists());
+
+        // an archive inside the reports directory is rejected
+        for archive_dir in [
+            "reports/archive",
+            "reports",
+            "archive/../reports/old/archive",
+        ] {
+            let retention = Retention {
+                max_count: 1,
+                archive_dir: archive_dir.to_string(),
+                ..Default::default()
+            };
+            assert!(
+                enforce_retention(&base_path, &retention, &[]).is_err(),
+                "{}",
+                archive_dir
+            );
+        }
+        assert!(!reports_dir.join("archive").exists());
+    }
+}
@@ -0,0 +1,217 @@
+// How a file is laid out on the volume: the identity shared by its hard links and the
+// extents holding data if the file is sparse (e.g. disk images of virtual machines)
+use std::fs::File;
+use std::path::Path;
+
+/// Identity of a file with several hard links as [device]:[inode] (volume serial and file index on Windows).
+/// None if the file has a single link
+#[cfg(unix)]
+pub fn hard_link_id(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path).ok()?;
+    match metadata.nlink() > 1 {
+        true => Some(format!("{}:{}", metadata.dev(), metadata.ino())),
+        false => None,
+    }
+}
+
+#[cfg(windows)]
+pub fn hard_link_id(path: &Path) -> Option<String> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
+
+    let file = File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    let file_index = (info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64;
+    match info.nNumberOfLinks > 1 {
+        true => Some(format!("{}:{}", info.dwVolumeSerialNumber, file_index)),
+        false => None,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn hard_link_id(_path: &Path) -> Option<String> {
+    None
+}
+
+// a single extent covering the whole file is no sparse file
+fn sparse_only(extents: Vec<(u64, u64)>, size: u64) -> Option<Vec<(u64, u64)>> {
+    match extents.as_slice() {
+        [(0, length)] if *length >= size => None,
+        _ => Some(extents),
+    }
+}
+
+/// Extents holding data as (offset, length), None if the file has no holes
+#[cfg(target_os = "linux")]
+pub fn data_extents(path: &Path) -> Option<Vec<(u64, u64)>> {
+    use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+
+    let file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    let size = metadata.len();
+    // fewer allocated blocks than the size requires, so most files are not seeked at all
+    if size == 0 || metadata.blocks() * 512 >= size {
+        return None;
+    }
+    let fd = file.as_raw_fd();
+    let mut extents = vec![];
+    let mut offset = 0;
+    while offset < size {
+        let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if start < 0 {
+            // ENXIO: only a hole follows, anything else: seeking data is not supported
+            match std::io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENXIO) => break,
+                _ => return None,
+            }
+        }
+        let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+        if end < start {
+            return None;
+        }
+        extents.push((start as u64, (end - start) as u64));
+        offset = end as u64;
+    }
+    sparse_only(extents, size)
+}
+
+#[cfg(windows)]
+pub fn data_extents(path: &Path) -> Option<Vec<(u64, u64)>> {
+    use std::mem::{size_of, size_of_val, zeroed};
+    use std::os::windows::{fs::MetadataExt, io::AsRawHandle};
+    use winapi::shared::winerror::ERROR_MORE_DATA;
+    use winapi::um::{
+        errhandlingapi::GetLastError,
+        ioapiset::DeviceIoControl,
+        winioctl::{FILE_ALLOCATED_RANGE_BUFFER, FSCTL_QUERY_ALLOCATED_RANGES},
+        winnt::FILE_ATTRIBUTE_SPARSE_FILE,
+    };
+
+    let file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    let size = metadata.len();
+    if size == 0 || metadata.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE == 0 {
+        return None;
+    }
+    let mut extents = vec![];
+    let mut query: FILE_ALLOCATED_RANGE_BUFFER = unsafe { zeroed() };
+    let mut ranges: [FILE_ALLOCATED_RANGE_BUFFER; 64] = unsafe { zeroed() };
+    let mut offset = 0;
+    while offset < size {
+        unsafe {
+            *query.FileOffset.QuadPart_mut() = offset as i64;
+            *query.Length.QuadPart_mut() = (size - offset) as i64;
+        }
+        let mut returned = 0;
+        let success = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as _,
+                FSCTL_QUERY_ALLOCATED_RANGES,
+                &mut query as *mut _ as _,
+                size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32,
+                ranges.as_mut_ptr() as _,
+                size_of_val(&ranges) as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        // the ranges are queried in batches
+        let more = success == 0 && unsafe { GetLastError() } == ERROR_MORE_DATA;
+        if success == 0 && !more {
+            return None;
+        }
+        let count = returned as usize / size_of::<FILE_ALLOCATED_RANGE_BUFFER>();
+        for range in &ranges[..count] {
+            let (start, length) = unsafe {
+                (
+                    *range.FileOffset.QuadPart() as u64,
+                    *range.Length.QuadPart() as u64,
+                )
+            };
+            extents.push((start, length));
+            offset = start + length;
+        }
+        if !more || count == 0 {
+            break;
+        }
+    }
+    sparse_only(extents, size)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn data_extents(_path: &Path) -> Option<Vec<(u64, u64)>> {
+    None
+}
+
+/// Extents as recorded in the metadata.csv, e.g. "0+4096;1048576+8192"
+pub fn format_extents(extents: &[(u64, u64)]) -> String {
+    extents
+        .iter()
+        .map(|(offset, length)| format!("{}+{}", offset, length))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+pub fn parse_extents(extents: &str) -> Option<Vec<(u64, u64)>> {
+    extents
+        .split(';')
+        .filter(|extent| !extent.is_empty())
+        .map(|extent| {
+            let (offset, length) = extent.split_once('+')?;
+            Some((offset.parse().ok()?, length.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_extents() {
+        let extents = vec![(0, 4096), (1048576, 8192)];
+        let formatted = format_extents(&extents);
+        assert_eq!(formatted, "0+4096;1048576+8192");
+        assert_eq!(parse_extents(&formatted), Some(extents));
+        assert_eq!(parse_extents(""), Some(vec![]));
+        assert_eq!(parse_extents("0-4096"), None);
+        assert_eq!(sparse_only(vec![(0, 10)], 10), None);
+    }
+
+    #[test]
+    fn test_hard_link_and_sparse_file() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_hard_link_and_sparse_file");
+        let file = dir.join("file.txt");
+        fs::write(&file, "content").unwrap();
+        assert_eq!(hard_link_id(&file), None);
+        assert_eq!(data_extents(&file), None);
+
+        fs::hard_link(&file, dir.join("link.txt")).unwrap();
+        let id = hard_link_id(&file);
+        assert!(id.is_some());
+        assert_eq!(hard_link_id(&dir.join("link.txt")), id);
+
+        // holes depend on the file system, e.g. tmpfs and ext4 support them
+        #[cfg(target_os = "linux")]
+        {
+            use std::io::{Seek, SeekFrom, Write};
+            let sparse = dir.join("disk.img");
+            let mut writer = File::create(&sparse).unwrap();
+            writer.seek(SeekFrom::Start(16 * 1024 * 1024)).unwrap();
+            writer.write_all(&[1; 4096]).unwrap();
+            drop(writer);
+            if let Some(extents) = data_extents(&sparse) {
+                assert!(extents
+                    .iter()
+                    .all(|(offset, _)| *offset >= 16 * 1024 * 1024 - 4096));
+            }
+        }
+    }
+}
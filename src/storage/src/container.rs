@@ -0,0 +1,212 @@
+// Alternative containers of the evidence, for tools which can't read zstd inside a zip archive.
+// The archive is always written as zip first, as the sweep and the appended files rely on it
+use chrono::{TimeZone, Utc};
+use sevenz_rust::nt_time::FileTime;
+use sevenz_rust::{Password, SevenZArchiveEntry, SevenZReader, SevenZWriter};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+use zip::ZipArchive;
+
+// the first 4 bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+// the signature at the start of a 7z archive
+const SEVEN_ZIP_MAGIC: [u8; 6] = [0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
+
+// the entries of the zip archive are written in UTC, see zip_timestamp
+fn unix_time(time: zip::DateTime) -> u64 {
+    Utc.with_ymd_and_hms(
+        time.year() as i32,
+        time.month() as u32,
+        time.day() as u32,
+        time.hour() as u32,
+        time.minute() as u32,
+        time.second() as u32,
+    )
+    .single()
+    .map(|time| time.timestamp().max(0) as u64)
+    .unwrap_or_default()
+}
+
+/// Rewrites a finished zip archive as tar archive compressed by zstd. The entries keep
+/// their names, order and modification times. Returns the number of entries
+pub fn zip_to_tar_zstd(zip_path: &Path, tar_path: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(zip_path)?))?;
+    let encoder = zstd::Encoder::new(BufWriter::new(File::create(tar_path)?), 0)?;
+    let mut builder = Builder::new(encoder);
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let mut header = Header::new_gnu();
+        header.set_mtime(entry.last_modified().map(unix_time).unwrap_or_default());
+        let name = entry.name().to_string();
+        if entry.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, &name, std::io::empty())?;
+            continue;
+        }
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(entry.size());
+        builder.append_data(&mut header, &name, &mut entry)?;
+    }
+    let encoder = builder.into_inner()?;
+    encoder.finish()?.into_inner()?.sync_all()?;
+    Ok(archive.len())
+}
+
+/// Rewrites a finished zip archive as 7z archive compressed by LZMA2. The entries keep
+/// their names and modification times. Readers list the directories after the files,
+/// as they have no content. Returns the number of entries
+pub fn zip_to_7z(zip_path: &Path, seven_zip_path: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(zip_path)?))?;
+    let mut writer = SevenZWriter::new(BufWriter::new(File::create(seven_zip_path)?))?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let mut seven_zip_entry = SevenZArchiveEntry::new();
+        seven_zip_entry.name = entry.name().trim_end_matches('/').to_string();
+        seven_zip_entry.is_directory = entry.is_dir();
+        let mtime = entry.last_modified().map(unix_time).unwrap_or_default();
+        if let Ok(mtime) = FileTime::from_unix_time(mtime as i64) {
+            seven_zip_entry.has_last_modified_date = true;
+            seven_zip_entry.last_modified_date = mtime;
+        }
+        if entry.is_dir() {
+            writer.push_archive_entry::<&[u8]>(seven_zip_entry, None)?;
+            continue;
+        }
+        writer.push_archive_entry(seven_zip_entry, Some(&mut entry))?;
+    }
+    writer.finish()?.into_inner()?.sync_all()?;
+    Ok(archive.len())
+}
+
+/// Whether the file starts with the 7z signature, e.g. a decrypted report.7z
+pub fn is_7z_file(path: &Path) -> bool {
+    let mut magic = [0u8; 6];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| magic == SEVEN_ZIP_MAGIC)
+}
+
+/// Reads a 7z archive, e.g. while it is decrypted. The reader must be seekable,
+/// as the index of the archive is stored at its end
+pub fn read_7z<R: Read + Seek>(mut reader: R) -> Result<SevenZReader<R>, Box<dyn Error>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    Ok(SevenZReader::new(reader, len, Password::empty())?)
+}
+
+/// Whether the file starts with a zstd frame, e.g. a decrypted report.tar.zst
+pub fn is_zstd_file(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| magic == ZSTD_MAGIC)
+}
+
+/// Reads a tar archive compressed by zstd, e.g. while it is decrypted
+pub fn read_tar_zstd<R: Read>(reader: R) -> io::Result<tar::Archive<impl Read>> {
+    Ok(tar::Archive::new(zstd::Decoder::new(reader)?))
+}
+
+pub fn open_tar_zstd(path: &Path) -> io::Result<tar::Archive<impl Read>> {
+    read_tar_zstd(File::open(path)?)
+}
+
+/// Writes a tar archive compressed by zstd, e.g. while it is encrypted.
+/// The writer is returned by builder.into_inner()?.finish()
+pub fn write_tar_zstd<W: Write>(writer: W) -> io::Result<Builder<zstd::Encoder<'static, W>>> {
+    Ok(Builder::new(zstd::Encoder::new(writer, 0)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    #[test]
+    fn test_zip_to_tar_zstd() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_zip_to_tar_zstd");
+        let zip_path = dir.join("report.zip");
+        let tar_path = dir.join("report.tar.zst");
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        writer
+            .add_directory("stored_files", SimpleFileOptions::default())
+            .unwrap();
+        writer
+            .start_file("stored_files/abc", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"evidence").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(zip_to_tar_zstd(&zip_path, &tar_path).unwrap(), 2);
+        assert!(is_zstd_file(&tar_path));
+        assert!(!is_zstd_file(&zip_path));
+
+        let mut archive = open_tar_zstd(&tar_path).unwrap();
+        let mut entries = archive.entries().unwrap().map(|entry| entry.unwrap());
+        let dir_entry = entries.next().unwrap();
+        assert_eq!(dir_entry.header().entry_type(), EntryType::Directory);
+        let mut file_entry = entries.next().unwrap();
+        assert_eq!(
+            file_entry.path().unwrap().to_str(),
+            Some("stored_files/abc")
+        );
+        assert!(file_entry.header().mtime().unwrap() > 0);
+        let mut content = String::new();
+        file_entry.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "evidence");
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn test_zip_to_7z() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_zip_to_7z");
+        let zip_path = dir.join("report.zip");
+        let seven_zip_path = dir.join("report.7z");
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        writer
+            .add_directory("stored_files", SimpleFileOptions::default())
+            .unwrap();
+        writer
+            .start_file("stored_files/abc", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"evidence").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(zip_to_7z(&zip_path, &seven_zip_path).unwrap(), 2);
+        assert!(is_7z_file(&seven_zip_path));
+        assert!(!is_7z_file(&zip_path));
+
+        let mut entries = Vec::new();
+        read_7z(File::open(&seven_zip_path).unwrap())
+            .unwrap()
+            .for_each_entries(|entry, reader| {
+                let mut content = String::new();
+                reader.read_to_string(&mut content)?;
+                assert!(entry.has_last_modified_date);
+                entries.push((entry.name().to_string(), entry.is_directory(), content));
+                Ok(true)
+            })
+            .unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ("stored_files".to_string(), true, String::new()),
+                (
+                    "stored_files/abc".to_string(),
+                    false,
+                    "evidence".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// Paths which are never collected, e.g. medical records or the documents of a works council.
+// The exclusions are enforced when a file is stored, so they apply regardless of the patterns
+// of the actions. Only the number of held back files per exclusion is recorded, not their paths
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::error::Error;
+use std::path::Path;
+use utils::pattern::{expand_braces, normalize_pattern};
+
+// error of storing an excluded file
+pub const EXCLUDED: &str = "Held back by the exclusions";
+
+/// An exclusion as recorded in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub pattern: String,
+    pub held_back_files: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Exclusions {
+    // the pattern as configured and the glob patterns it expands to
+    patterns: Vec<(String, Vec<Pattern>)>,
+    // paths held back by each pattern, only counted in the manifest
+    held_back: Vec<HashSet<String>>,
+}
+
+impl Exclusions {
+    /// Compiles the patterns. Exclusions protect privacy, so an invalid pattern is an error
+    /// instead of being skipped
+    pub fn new(patterns: &[String]) -> Result<Self, Box<dyn Error>> {
+        // the patterns of the config and the workflow may overlap
+        let patterns: BTreeSet<&String> = patterns
+            .iter()
+            .filter(|pattern| !pattern.trim().is_empty())
+            .collect();
+        let mut compiled = vec![];
+        for pattern in patterns {
+            let mut globs = vec![];
+            for expanded in expand_braces(pattern.trim()) {
+                globs.push(
+                    Pattern::new(&normalize_pattern(&expanded))
+                        .map_err(|e| format!("Invalid exclusion {:?}: {}", pattern, e))?,
+                );
+            }
+            compiled.push((pattern.clone(), globs));
+        }
+        Ok(Self {
+            held_back: vec![HashSet::new(); compiled.len()],
+            patterns: compiled,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns the exclusion matching the absolute path, if any, and counts the path as held back.
+    /// Exclusions are matched case-insensitively, so no spelling of a path slips through
+    pub fn check(&mut self, path: &Path) -> Option<&str> {
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let path = path.to_string_lossy().replace('\\', "/");
+        // canonical paths on Windows are verbatim paths, e.g. \\?\C:\Users
+        let path = path
+            .strip_prefix("//?/")
+            .map(str::to_string)
+            .unwrap_or(path);
+        let index = self
+            .patterns
+            .iter()
+            .position(|(_, globs)| globs.iter().any(|glob| glob.matches_with(&path, options)))?;
+        self.held_back[index].insert(path);
+        Some(&self.patterns[index].0)
+    }
+
+    /// Number of files held back, each counted once
+    pub fn held_back_files(&self) -> usize {
+        let mut paths = HashSet::new();
+        for held_back in &self.held_back {
+            paths.extend(held_back);
+        }
+        paths.len()
+    }
+
+    pub fn records(&self) -> Vec<Exclusion> {
+        self.patterns
+            .iter()
+            .zip(&self.held_back)
+            .map(|((pattern, _), held_back)| Exclusion {
+                pattern: pattern.clone(),
+                held_back_files: held_back.len(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusions() {
+        let mut exclusions = Exclusions::new(&[
+            "**/medical/**".to_string(),
+            "C:\\Users\\*\\Documents\\{Betriebsrat,private}\\**".to_string(),
+            "**/medical/**".to_string(),
+            " ".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(exclusions.records().len(), 2);
+
+        assert_eq!(
+            exclusions.check(Path::new("/home/alice/Medical/scan.pdf")),
+            Some("**/medical/**")
+        );
+        assert_eq!(
+            exclusions.check(Path::new("/home/alice/medical/2024/scan.pdf")),
+            Some("**/medical/**")
+        );
+        assert_eq!(
+            exclusions.check(Path::new("C:\\Users\\bob\\Documents\\Private\\diary.docx")),
+            Some("C:\\Users\\*\\Documents\\{Betriebsrat,private}\\**")
+        );
+        // * doesn't match across directories
+        assert_eq!(
+            exclusions.check(Path::new("C:\\Users\\bob\\x\\Documents\\private\\a.txt")),
+            None
+        );
+        assert_eq!(
+            exclusions.check(Path::new("/home/alice/medicals.txt")),
+            None
+        );
+        // a file is counted once, even if it is checked again
+        exclusions.check(Path::new(
+            "\\\\?\\C:\\Users\\bob\\Documents\\Private\\diary.docx",
+        ));
+        assert_eq!(exclusions.held_back_files(), 3);
+        assert_eq!(
+            exclusions.records(),
+            [
+                Exclusion {
+                    pattern: "**/medical/**".to_string(),
+                    held_back_files: 2
+                },
+                Exclusion {
+                    pattern: "C:\\Users\\*\\Documents\\{Betriebsrat,private}\\**".to_string(),
+                    held_back_files: 1
+                },
+            ]
+        );
+
+        assert!(Exclusions::new(&["/home/[".to_string()]).is_err());
+    }
+}
@@ -1,23 +1,49 @@
-use chrono::{Local, TimeZone};
+pub mod allocation;
+pub mod container;
+pub mod exclusions;
+pub mod order;
+pub mod self_test;
+pub mod sweep;
+pub mod triage;
+
+use allocation::{data_extents, format_extents, hard_link_id};
+use chrono::{Datelike, Local, SecondsFormat, TimeZone, Timelike, Utc};
 use chrono_tz::{self, Tz};
-use config::workflow::Reporting;
-use crypto::{copy_file_with_sha1, encrypt_evidence, EncryptionMeta};
+use config::workflow::{Algorithm, ArchiveFormat, ArchiveSelfTest, Reporting};
+use crypto::{
+    copy_file_with_sha1, encrypt_evidence_with_key, encrypted_output_path, get_file_sha1, Digest,
+    EncryptionMeta, HashingReader, SessionKey,
+};
+use exclusions::{Exclusion, Exclusions, EXCLUDED};
 use filetime::FileTime;
 use log::{debug, error, info, warn};
 use openssl::pkey::Public;
 use openssl::rsa::Rsa;
-use openssl::sha::Sha1;
-use report::{Report, ACTION_LOG_DIR, LOOT_DIR, STORAGE_DIR};
+use report::layout::{archive_comment, write_layout, LAYOUT_PATH};
+use report::{
+    Report, ACTION_LOG_DIR, COLLECTOR_LOG_PATH, FINDINGS_PATH, LOOT_DIR, MANIFEST_PATH,
+    METADATA_PATH, README_PATH, SEVEN_ZIP_PATH, STORAGE_DIR, SWEEP_PATH, TAR_ZSTD_PATH, ZIP_PATH,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
-use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use utils::misc::{file_name_checksum, get_files_by_patterns};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+use sweep::{sweep_archive, sweep_directory, sweep_spool, write_sweep, ContentScanner};
+use triage::{write_findings, ParserFinding, Triage, TriageRule};
+use utils::fail_injection::{self, ENCRYPTION, ZIP_WRITE};
+use utils::misc::{file_name_checksum, iter_files_by_patterns};
 use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct FileMeta {
     pub original_path: String,
     pub modified_time: String,
@@ -27,16 +53,121 @@ pub struct FileMeta {
     pub path_checksum: String,
     pub size: u64,
     pub comment: Option<String>,
+    // set if the file was skipped because it did not change since the baseline
+    #[serde(default)]
+    pub unchanged: bool,
+    // stored as STORAGE_DIR/[sha1 checksum] instead of STORAGE_DIR/[path checksum]
+    #[serde(default)]
+    pub content_addressed: bool,
+    // set if the file was skipped because a previous report already contains its content
+    #[serde(default)]
+    pub deduplicated: bool,
+    // target of a symbolic link or junction, recorded without storing any content
+    #[serde(default)]
+    pub link_target: Option<String>,
+    // identity shared by the hard links of a file, see allocation::hard_link_id
+    #[serde(default)]
+    pub link_group: Option<String>,
+    // original path of the stored hard link with the same content, the content is not stored again
+    #[serde(default)]
+    pub hard_link_of: Option<String>,
+    // extents holding data if the file is sparse, see allocation::format_extents
+    #[serde(default)]
+    pub sparse_extents: Option<String>,
+    // custom metadata of the store action as JSON object, e.g. {"category":"browser"}
+    #[serde(default)]
+    pub extra: Option<String>,
+    // run of the workflow which stored the file, see report::Report::run_id
+    #[serde(default)]
+    pub run_id: String,
+}
+
+impl FileMeta {
+    /// Name of the file inside STORAGE_DIR
+    pub fn storage_name(&self) -> &str {
+        match self.content_addressed {
+            true => &self.sha1_checksum,
+            false => &self.path_checksum,
+        }
+    }
+
+    /// Custom metadata attached by the store action, empty if there is none or it is invalid
+    pub fn extra_metadata(&self) -> BTreeMap<String, String> {
+        self.extra
+            .as_deref()
+            .and_then(|extra| serde_json::from_str(extra).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// A file added to the report, named as inside the archive (e.g. files/[checksum])
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredFile {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Number and size of the stored files, readable from other threads while files are stored
+#[derive(Debug, Default)]
+pub struct StorageProgress {
+    pub files: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+// where the YARA sweep reads the collected files from
+enum SweepSource<'a> {
+    Archive(&'a Path),
+    Spool(&'a Path),
+    Directory,
 }
 
 #[derive(Debug)]
 pub struct FileProcessor<'a> {
     public_key: Option<Rsa<Public>>,
+    // encrypts the archive and, if enabled, the action outputs
+    session_key: Option<Arc<SessionKey>>,
     zip_writer: Option<ZipWriter<BufWriter<File>>>,
     csv_writer: Option<csv::Writer<BufWriter<File>>>,
     report_settings: Reporting,
     report: &'a Report,
     added_files: HashMap<String, bool>,
+    // metadata of the previous report by original path (delta collection)
+    baseline: HashMap<String, FileMeta>,
+    unchanged_files: usize,
+    // checksums of files collected by previous reports (content-addressed storage)
+    dedup_cache: HashSet<String>,
+    // checksums of files stored in this report
+    stored_checksums: HashSet<String>,
+    deduplicated_files: usize,
+    // original path and checksum of the stored file by its hard link identity
+    hard_links: HashMap<String, (String, String)>,
+    // files stored in this report, in the order they were added
+    stored_files: Vec<StoredFile>,
+    progress: Arc<StorageProgress>,
+    // metadata records written since the last flush
+    unflushed_records: usize,
+    last_flush: Instant,
+    triage: Triage,
+    // namespaces of the YARA rules matching a file, by original path
+    yara_matches: HashMap<String, BTreeSet<String>>,
+    // findings of the parser plugins, by original path
+    parser_findings: HashMap<String, Vec<ParserFinding>>,
+    // scans the collected files once the collection is complete
+    content_scanner: Option<Box<dyn ContentScanner>>,
+    // timestamp of all archive entries, so the archive doesn't depend on the time zone or the time of archiving
+    collected: chrono::DateTime<Utc>,
+    // custom metadata recorded for the following files, see set_extra_metadata
+    extra: Option<String>,
+    // files of interest found by the triage rules
+    findings: usize,
+    // of the MAC times in metadata.csv
+    time_zone: Tz,
+    // reason if the workflow was stopped early, recorded in the manifest
+    aborted: Option<String>,
+    // archive the files are written to until the collection is complete, see order.rs
+    spool_path: Option<PathBuf>,
+    // paths which are never collected, recorded in the manifest
+    exclusions: Exclusions,
 }
 
 impl<'a> FileProcessor<'a> {
@@ -58,32 +189,84 @@ impl<'a> FileProcessor<'a> {
 
         Ok(Self {
             public_key: None,
+            session_key: None,
             zip_writer: None,
             csv_writer: csv_writer,
             report_settings: Reporting::default(),
             report: report,
             added_files: HashMap::new(),
+            baseline: HashMap::new(),
+            unchanged_files: 0,
+            dedup_cache: HashSet::new(),
+            stored_checksums: HashSet::new(),
+            deduplicated_files: 0,
+            hard_links: HashMap::new(),
+            stored_files: Vec::new(),
+            progress: Arc::new(StorageProgress::default()),
+            unflushed_records: 0,
+            last_flush: Instant::now(),
+            triage: Triage::new(vec![]),
+            yara_matches: HashMap::new(),
+            parser_findings: HashMap::new(),
+            content_scanner: None,
+            collected: Utc::now(),
+            extra: None,
+            findings: 0,
+            time_zone: Tz::UTC,
+            aborted: None,
+            spool_path: None,
+            exclusions: Exclusions::default(),
         })
     }
 
-    fn initialize_zip_archive(&mut self) {
-        let zip_path = self.report.zip_path.clone();
+    /// Time zone of the MAC times written to metadata.csv, UTC by default
+    pub fn set_time_zone(&mut self, time_zone: Tz) {
+        self.time_zone = time_zone;
+    }
+
+    /// Marks the report as aborted. The archive of an aborted report always has a manifest
+    pub fn set_aborted(&mut self, reason: String) {
+        self.aborted = Some(reason);
+    }
 
-        let zip_file = match File::create(&zip_path) {
+    fn file_options(&self) -> SimpleFileOptions {
+        SimpleFileOptions::default().last_modified_time(zip_timestamp(&self.collected))
+    }
+
+    // entries of a tar or 7z archive are compressed again once the archive is converted
+    fn compression_method(&self) -> CompressionMethod {
+        match self.report_settings.zip_archive.format {
+            ArchiveFormat::Zip => CompressionMethod::ZSTD,
+            ArchiveFormat::ZipDeflate => CompressionMethod::Deflated,
+            ArchiveFormat::TarZstd | ArchiveFormat::SevenZip => CompressionMethod::Stored,
+        }
+    }
+
+    /// Path and name of the finished archive, depending on its format
+    fn archive_path(&self) -> (&PathBuf, &'static str) {
+        match self.report_settings.zip_archive.format {
+            ArchiveFormat::TarZstd => (&self.report.tar_path, TAR_ZSTD_PATH),
+            ArchiveFormat::SevenZip => (&self.report.seven_zip_path, SEVEN_ZIP_PATH),
+            _ => (&self.report.zip_path, ZIP_PATH),
+        }
+    }
+
+    fn initialize_zip_archive(&mut self, zip_path: &Path) {
+        let zip_file = match File::create(zip_path) {
             Ok(file) => file,
             Err(_) => {
-                error!("Failed to create zip archive: {:?}", &zip_path);
+                error!("Failed to create zip archive: {:?}", zip_path);
                 return;
             }
         };
         let mut zip_writer = ZipWriter::new(BufWriter::new(zip_file));
 
         // create directory in the zip archive
-        let file_options = SimpleFileOptions::default();
+        let file_options = self.file_options();
         zip_writer.add_directory(LOOT_DIR, file_options).unwrap();
-        let file_options = SimpleFileOptions::default().large_file(true);
+        let file_options = self.file_options().large_file(true);
         zip_writer.add_directory(STORAGE_DIR, file_options).unwrap();
-        let file_options = SimpleFileOptions::default();
+        let file_options = self.file_options();
         zip_writer
             .add_directory(ACTION_LOG_DIR, file_options)
             .unwrap();
@@ -91,37 +274,331 @@ impl<'a> FileProcessor<'a> {
         self.zip_writer = Some(zip_writer);
     }
 
-    pub fn set_public_key(&mut self, public_key: Rsa<Public>) -> &mut Self {
+    /// Sets the public key and generates the session key. Fails if encryption is enabled and
+    /// no session key can be generated, as the archive would otherwise remain unencrypted
+    pub fn set_public_key(&mut self, public_key: Rsa<Public>) -> Result<&mut Self, Box<dyn Error>> {
+        self.public_key = Some(public_key);
+
         // warn if the public key is set and encryption is disabled
         if !self.report_settings.zip_archive.encryption.enabled {
             warn!("Setting public key won't have any effect: encryption is disabled");
+            return Ok(self);
         }
 
-        self.public_key = Some(public_key);
-        self
+        // the key is required before the workflow runs to encrypt the action outputs
+        let algorithm = self.report_settings.zip_archive.encryption.algorithm;
+        let key = SessionKey::generate(algorithm)
+            .map_err(|e| format!("Failed to generate session key ({}): {}", algorithm, e))?;
+        self.session_key = Some(Arc::new(key));
+        Ok(self)
+    }
+
+    /// Key to encrypt the action outputs with, if enabled
+    pub fn output_key(&self) -> Option<Arc<SessionKey>> {
+        let zip_archive = &self.report_settings.zip_archive;
+        if !zip_archive.enabled
+            || !zip_archive.encryption.enabled
+            || !zip_archive.encryption.encrypt_action_output
+        {
+            return None;
+        }
+        self.session_key.clone()
+    }
+
+    /// Key to encrypt the log of the workflow with, if it is sealed
+    pub fn sealed_log_key(&self) -> Option<Arc<SessionKey>> {
+        match self.sidecars_sealed() {
+            true => self.session_key.clone(),
+            false => None,
+        }
+    }
+
+    /// Whether metadata.csv and the log of the workflow are only kept inside the encrypted archive
+    pub fn sidecars_sealed(&self) -> bool {
+        let zip_archive = &self.report_settings.zip_archive;
+        zip_archive.enabled
+            && zip_archive.encryption.enabled
+            && zip_archive.encryption.seal_sidecars
     }
 
     pub fn set_report_settings(&mut self, report_settings: Reporting) -> &mut Self {
         self.report_settings = report_settings;
 
+        let zip_archive = &self.report_settings.zip_archive;
+        if zip_archive.enabled && zip_archive.keep_plaintext && zip_archive.encryption.enabled {
+            warn!("keep_plaintext is enabled: unencrypted copies of the archived files remain in the report directory");
+        }
+        if zip_archive.encryption.seal_sidecars && !self.sidecars_sealed() {
+            warn!("seal_sidecars requires an encrypted archive: metadata.csv and the log remain unencrypted");
+        }
+
         // check if archiving is enabled
         if self.report_settings.zip_archive.enabled {
-            self.initialize_zip_archive();
+            let zip_path = match self.report_settings.zip_archive.entry_order.enabled {
+                true => order::spool_path(&self.report.zip_path),
+                false => self.report.zip_path.clone(),
+            };
+            self.initialize_zip_archive(&zip_path);
+            self.spool_path = Some(zip_path).filter(|path| *path != self.report.zip_path);
         }
 
         self
     }
 
+    /// Loads the metadata.csv of a previous report, e.g. output/metadata.csv of the unpacked report.
+    /// Files whose path, modification time, size and checksum did not change since are no longer
+    /// stored, but recorded as unchanged
+    pub fn set_baseline(&mut self, metadata_path: &Path) -> Result<usize, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(metadata_path)?;
+        let mut baseline = HashMap::new();
+        for record in reader.deserialize() {
+            let record: FileMeta = record?;
+            // files without MAC times or checksums can't be compared
+            if record.modified_time.is_empty()
+                || record.original_path.is_empty()
+                || record.sha1_checksum.is_empty()
+            {
+                continue;
+            }
+            baseline.insert(record.original_path.clone(), record);
+        }
+
+        if baseline.is_empty() {
+            warn!(
+                "Baseline {:?} contains no files with paths, MAC times and checksums: all files will be stored",
+                metadata_path.display()
+            );
+        } else {
+            if !self.report_settings.metadata.mac_times {
+                warn!("Delta collection requires MAC times: enabling mac_times");
+                self.report_settings.metadata.mac_times = true;
+            }
+            if !self.report_settings.metadata.checksums {
+                warn!("Delta collection requires checksums: enabling checksums");
+                self.report_settings.metadata.checksums = true;
+            }
+        }
+
+        self.baseline = baseline;
+        Ok(self.baseline.len())
+    }
+
+    /// Loads the checksums of files collected by previous reports, one per line.
+    /// A missing cache is not an error, it is created by `update_dedup_cache`
+    pub fn set_dedup_cache(&mut self, cache_path: &Path) -> Result<usize, Box<dyn Error>> {
+        if !cache_path.exists() {
+            debug!("Dedup cache {:?} does not exist yet", cache_path.display());
+            return Ok(0);
+        }
+        self.dedup_cache = fs::read_to_string(cache_path)?
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .filter(|line| line.len() == 40)
+            .collect();
+        Ok(self.dedup_cache.len())
+    }
+
+    /// Appends the checksums of the files stored in this report to the cache,
+    /// so they are not collected again. Returns the number of new checksums
+    pub fn update_dedup_cache(&self, cache_path: &Path) -> Result<usize, Box<dyn Error>> {
+        let mut new_checksums: Vec<&String> = self
+            .stored_checksums
+            .iter()
+            .filter(|checksum| !self.dedup_cache.contains(*checksum))
+            .collect();
+        if new_checksums.is_empty() {
+            return Ok(0);
+        }
+        new_checksums.sort();
+
+        if let Some(parent) = cache_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(cache_path)?;
+        let mut lines = String::new();
+        for checksum in &new_checksums {
+            lines.push_str(checksum);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())?;
+        Ok(new_checksums.len())
+    }
+
+    /// Paths which are never stored, whatever the patterns of the actions.
+    /// Returns the number of exclusions
+    pub fn set_exclusions(&mut self, patterns: &[String]) -> Result<usize, Box<dyn Error>> {
+        self.exclusions = Exclusions::new(patterns)?;
+        Ok(self.exclusions.records().len())
+    }
+
+    /// Whether the file or link is held back by the exclusions. Both the path of a link and
+    /// its target are checked, so a link can't be used to collect an excluded file
+    pub fn is_excluded(&mut self, path: &Path) -> bool {
+        if self.exclusions.is_empty() || path.starts_with(&self.report.dir) {
+            return false;
+        }
+        let link_path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => parent
+                .canonicalize()
+                .unwrap_or_else(|_| parent.to_path_buf())
+                .join(name),
+            _ => path.to_path_buf(),
+        };
+        let excluded = match self.exclusions.check(&link_path) {
+            Some(exclusion) => Some(exclusion.to_string()),
+            None => path
+                .canonicalize()
+                .ok()
+                .and_then(|target| self.exclusions.check(&target).map(str::to_string)),
+        };
+        match excluded {
+            // the path itself is not logged, as the log is part of the report
+            Some(exclusion) => {
+                debug!("Holding back a file matching the exclusion {:?}", exclusion);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Rules evaluated against the collected files once the report is finished
+    pub fn set_triage_rules(&mut self, rules: Vec<TriageRule>) -> &mut Self {
+        self.triage = Triage::new(rules);
+        self
+    }
+
+    pub fn set_content_scanner(&mut self, scanner: Box<dyn ContentScanner>) -> &mut Self {
+        self.content_scanner = Some(scanner);
+        self
+    }
+
+    /// Custom metadata recorded for the files stored from now on, e.g. by a store action.
+    /// An empty map stops recording it
+    pub fn set_extra_metadata(&mut self, extra: &BTreeMap<String, String>) {
+        self.extra = match extra.is_empty() {
+            true => None,
+            false => serde_json::to_string(extra).ok(),
+        };
+    }
+
+    /// Remembers a YARA match for the triage rules
+    pub fn add_yara_match(&mut self, original_path: &Path, namespace: &str) {
+        self.yara_matches
+            .entry(original_path.to_string_lossy().to_string())
+            .or_default()
+            .insert(namespace.to_string());
+    }
+
+    /// Remembers the findings of a parser plugin, they are merged into findings.json
+    pub fn add_parser_findings(&mut self, original_path: &Path, findings: Vec<ParserFinding>) {
+        self.parser_findings
+            .entry(original_path.to_string_lossy().to_string())
+            .or_default()
+            .extend(findings);
+    }
+
+    /// Records a symbolic link or junction with its target, without storing the content of the target
+    pub fn store_link(&mut self, link: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+        if self.is_excluded(link) {
+            return Err(EXCLUDED.into());
+        }
+        // the link itself is recorded, so only its parent directories are resolved
+        let abs_link_path = match (link.parent(), link.file_name()) {
+            (Some(parent), Some(name)) => parent
+                .canonicalize()
+                .unwrap_or_else(|_| parent.to_path_buf())
+                .join(name),
+            _ => link.to_path_buf(),
+        };
+        let original_path = abs_link_path.to_string_lossy().to_string();
+        let path_checksum = file_name_checksum(&original_path);
+        if self.added_files.contains_key(&path_checksum) {
+            return Err("Link already added".into());
+        }
+
+        let mut metadata = FileMeta {
+            original_path,
+            modified_time: "".to_string(),
+            accessed_time: "".to_string(),
+            created_time: "".to_string(),
+            sha1_checksum: "".to_string(),
+            path_checksum,
+            size: 0,
+            comment: None,
+            unchanged: false,
+            content_addressed: false,
+            deduplicated: false,
+            link_target: Some(target.to_string_lossy().to_string()),
+            link_group: None,
+            hard_link_of: None,
+            sparse_extents: None,
+            extra: self.extra.clone(),
+            run_id: self.report.run_id.clone(),
+        };
+        if self.report_settings.metadata.mac_times {
+            if let Ok(link_metadata) = fs::symlink_metadata(link) {
+                let (mtime, atime, ctime) = mac_times(&link_metadata, &self.time_zone);
+                metadata.modified_time = mtime;
+                metadata.accessed_time = atime;
+                metadata.created_time = ctime;
+            }
+        }
+        debug!("Recording link {:?} -> {:?}", abs_link_path, target);
+        self.added_files
+            .insert(metadata.path_checksum.clone(), true);
+        self.write_metadata(&metadata)
+    }
+
     pub fn store(
         &mut self,
         file_path: &Path,
         comment: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let keep_plaintext = self.report_settings.zip_archive.keep_plaintext;
+        self.store_keep_plaintext(file_path, comment, keep_plaintext)
+    }
+
+    /// Same as `store`, but overrides whether files inside the report directory
+    /// are kept after they were added to the archive
+    pub fn store_keep_plaintext(
+        &mut self,
+        file_path: &Path,
+        comment: Option<String>,
+        keep_plaintext: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // reading the file for the checksum and the archive updates its access time
+        let atime = match self.report_settings.metadata.preserve_atime
+            && !file_path.starts_with(&self.report.dir)
+        {
+            true => fs::metadata(file_path)
+                .ok()
+                .map(|metadata| FileTime::from_last_access_time(&metadata)),
+            false => None,
+        };
+        let result = self.store_file(file_path, comment, keep_plaintext);
+        if let Some(atime) = atime {
+            restore_atime(file_path, atime);
+        }
+        result
+    }
+
+    fn store_file(
+        &mut self,
+        file_path: &Path,
+        comment: Option<String>,
+        keep_plaintext: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Step 1: Check if the file exists
         if !file_path.exists() {
             error!("File not found: {:?}", file_path);
             return Err("File not found".into());
         }
+        if self.is_excluded(file_path) {
+            return Err(EXCLUDED.into());
+        }
 
         // Step 2: Get the absolute path
         let abs_file_path = match file_path.canonicalize() {
@@ -144,6 +621,15 @@ impl<'a> FileProcessor<'a> {
             path_checksum: file_name_checksum(&abs_file_path.to_str().unwrap()),
             size: 0,
             comment: comment,
+            unchanged: false,
+            content_addressed: false,
+            deduplicated: false,
+            link_target: None,
+            link_group: None,
+            hard_link_of: None,
+            sparse_extents: None,
+            extra: self.extra.clone(),
+            run_id: self.report.run_id.clone(),
         };
 
         // Step 4: Get MAC (Modified, Accessed, Created) times
@@ -154,40 +640,96 @@ impl<'a> FileProcessor<'a> {
         if self.report_settings.metadata.mac_times && !in_loot_dir {
             debug!("Obtaining MAC times for file");
             let file_metadata = fs::metadata(file_path).unwrap();
+            let (mtime, atime, ctime) = mac_times(&file_metadata, &self.time_zone);
             let size = file_metadata.len();
 
-            let mtime = FileTime::from_last_modification_time(&file_metadata);
-            let atime = FileTime::from_last_access_time(&file_metadata);
-            let ctime = FileTime::from_creation_time(&file_metadata);
-
-            // convert to rfc3339 string
-            let tz = Tz::UTC;
-            let mtime: String = Local
-                .timestamp_opt(mtime.unix_seconds(), 0)
-                .unwrap()
-                .with_timezone(&tz)
-                .to_rfc3339();
-            let atime: String = Local
-                .timestamp_opt(atime.unix_seconds(), 0)
-                .unwrap()
-                .with_timezone(&tz)
-                .to_rfc3339();
-            let ctime: String = match ctime {
-                Some(ctime) => Local
-                    .timestamp_opt(ctime.unix_seconds(), 0)
-                    .unwrap()
-                    .with_timezone(&tz)
-                    .to_rfc3339(),
-                None => "None".to_string(),
-            };
-
             metadata.modified_time = mtime;
             metadata.accessed_time = atime;
             metadata.created_time = ctime;
             metadata.size = size;
+
+            // skip files that did not change since the baseline
+            let already_added = self.added_files.contains_key(&metadata.path_checksum);
+            if let Some(previous) = self.baseline.get(&metadata.original_path) {
+                // the modification time may have been set back (timestomping), so the content
+                // is compared as well. It is read, but not stored again
+                if !already_added
+                    && same_time(&previous.modified_time, &metadata.modified_time)
+                    && previous.size == size
+                    && get_file_sha1(&abs_file_path).is_ok_and(|checksum| {
+                        checksum.eq_ignore_ascii_case(&previous.sha1_checksum)
+                    })
+                {
+                    debug!("File unchanged since baseline: {:?}", abs_file_path);
+                    metadata.sha1_checksum = previous.sha1_checksum.clone();
+                    metadata.unchanged = true;
+                    self.added_files
+                        .insert(metadata.path_checksum.clone(), true);
+                    self.unchanged_files += 1;
+                    self.write_metadata(&metadata)?;
+                    return Ok(());
+                }
+            }
+        }
+
+        // Step 5: Hard links and sparse files
+        // further links to a stored file are recorded without storing the content again
+        if !in_loot_dir {
+            metadata.link_group = hard_link_id(&abs_file_path);
+            metadata.sparse_extents =
+                data_extents(&abs_file_path).map(|extents| format_extents(&extents));
+        }
+        let stored_link = metadata
+            .link_group
+            .as_ref()
+            .and_then(|link_group| self.hard_links.get(link_group));
+        if let Some((stored_path, checksum)) = stored_link {
+            if self.added_files.contains_key(&metadata.path_checksum) {
+                return Err("File already added to the archive".into());
+            }
+            debug!("Hard link of {:?}: {:?}", stored_path, abs_file_path);
+            metadata.sha1_checksum = checksum.clone();
+            metadata.hard_link_of = Some(stored_path.clone());
+            if metadata.size == 0 {
+                metadata.size = fs::metadata(&abs_file_path)?.len();
+            }
+            self.added_files
+                .insert(metadata.path_checksum.clone(), true);
+            self.write_metadata(&metadata)?;
+            return Ok(());
+        }
+
+        // Step 6: Content-addressed storage
+        // files are stored by the checksum of their content, so identical files are only stored once
+        let content_addressed = self.report_settings.dedup.enabled && !in_loot_dir;
+        if content_addressed {
+            if self.added_files.contains_key(&metadata.path_checksum) {
+                return Err("File already added to the archive".into());
+            }
+            metadata.sha1_checksum = get_file_sha1(&abs_file_path)?;
+            metadata.content_addressed = true;
+            if metadata.size == 0 {
+                metadata.size = fs::metadata(&abs_file_path)?.len();
+            }
+
+            let known = self.dedup_cache.contains(&metadata.sha1_checksum);
+            if known || self.stored_checksums.contains(&metadata.sha1_checksum) {
+                debug!(
+                    "Content of {:?} already collected: {}",
+                    abs_file_path, metadata.sha1_checksum
+                );
+                metadata.deduplicated = known;
+                if known {
+                    self.deduplicated_files += 1;
+                }
+                self.added_files
+                    .insert(metadata.path_checksum.clone(), true);
+                self.write_metadata(&metadata)?;
+                return Ok(());
+            }
         }
 
-        // Step 5: Add file to the archive
+        // Step 7: Add file to the archive
         // use the SHA1 checksum of the abs_file_path to avoid duplicate file names
         // enable_archive && loot -> loot_files/[filename]
         // enable_archive && !loot -> STORAGE_DIR/[checksum]
@@ -196,7 +738,7 @@ impl<'a> FileProcessor<'a> {
         let archive_filename = match in_loot_dir {
             true => {
                 // return LOOT_DIR/[filename]
-                let file_name = abs_file_path.file_name().unwrap().to_str().unwrap();
+                let file_name = abs_file_path.file_name().unwrap().to_string_lossy();
                 format!("{}/{}", LOOT_DIR, file_name)
             }
             false => {
@@ -206,15 +748,29 @@ impl<'a> FileProcessor<'a> {
                 if self.added_files.contains_key(&metadata.path_checksum) {
                     return Err("File already added to the archive".into());
                 }
-                format!("{}/{}", STORAGE_DIR, &metadata.path_checksum)
+                format!("{}/{}", STORAGE_DIR, metadata.storage_name())
             }
         };
 
-        // Step 6: Add file to the archive
+        // the file may be removed once it was added to the archive
+        let size = match metadata.size {
+            0 => fs::metadata(&abs_file_path).map(|m| m.len()).unwrap_or(0),
+            size => size,
+        };
+        let stored_file = StoredFile {
+            name: archive_filename.clone(),
+            size,
+        };
+
+        // Step 8: Add file to the archive
         let enable_archive = self.report_settings.zip_archive.enabled;
+        // the checksum of a content-addressed file is already known, so it isn't hashed again.
+        // It names the entry, so the metadata must not take the checksum of a later read
+        let checksum = self.report_settings.metadata.checksums && !content_addressed;
         // If archiving is enabled, add the file to the zip archive
         if enable_archive {
-            match self.add_file_to_zip(&abs_file_path, archive_filename) {
+            match self.add_file_to_zip(&abs_file_path, archive_filename, keep_plaintext, checksum) {
+                Ok(_) if !checksum => (),
                 Ok(checksum) => metadata.sha1_checksum = checksum,
                 Err(e) => {
                     return Err(format!("Failed to add file to zip archive: {:?}", e).into());
@@ -222,7 +778,7 @@ impl<'a> FileProcessor<'a> {
             }
         }
         // If archiving is disabled, but checksum enabled, copy the file to the loot directory
-        else if self.report_settings.metadata.checksums {
+        else if checksum {
             let loot_file_path = self.report.dir.join(&archive_filename);
             match copy_file_with_sha1(&abs_file_path, &loot_file_path) {
                 Ok(checksum) => metadata.sha1_checksum = checksum,
@@ -235,7 +791,7 @@ impl<'a> FileProcessor<'a> {
                 }
             }
         }
-        // If archiving and checksum is disabled, copy the file to the loot directory
+        // If archiving is disabled and no checksum is needed, copy the file to the loot directory
         else {
             let loot_file_path = self.report.dir.join(&archive_filename);
             match fs::copy(&file_path, &loot_file_path) {
@@ -250,26 +806,89 @@ impl<'a> FileProcessor<'a> {
             }
         }
 
-        // Step 7: Add the file to the added_files hashmap
+        // Step 9: Add the file to the added_files hashmap
         if !in_loot_dir {
             self.added_files
                 .insert(metadata.path_checksum.clone(), true);
         }
+        if content_addressed {
+            self.stored_checksums.insert(metadata.sha1_checksum.clone());
+        }
+        if let Some(link_group) = &metadata.link_group {
+            self.hard_links.insert(
+                link_group.clone(),
+                (
+                    metadata.original_path.clone(),
+                    metadata.sha1_checksum.clone(),
+                ),
+            );
+        }
+        self.progress.files.fetch_add(1, Ordering::Relaxed);
+        self.progress
+            .bytes
+            .fetch_add(stored_file.size, Ordering::Relaxed);
+        self.stored_files.push(stored_file);
 
-        // Step 8: Write metadata
-        if let Some(csv_writer) = &mut self.csv_writer {
-            csv_writer.serialize(metadata)?;
+        // Step 10: Write metadata
+        self.write_metadata(&metadata)?;
+
+        Ok(())
+    }
+
+    /// Writes a record to the metadata.csv, flushed according to reporting.metadata.flush
+    fn write_metadata(&mut self, metadata: &FileMeta) -> Result<(), Box<dyn Error>> {
+        let csv_writer = match &mut self.csv_writer {
+            Some(csv_writer) => csv_writer,
+            None => return Ok(()),
+        };
+        csv_writer.serialize(metadata)?;
+        self.unflushed_records += 1;
+
+        let flush = &self.report_settings.metadata.flush;
+        let records_reached = flush.records != 0 && self.unflushed_records >= flush.records;
+        let interval_reached = flush.interval > 0
+            && self.last_flush.elapsed() >= Duration::from_secs(flush.interval as u64);
+        if records_reached || interval_reached {
             csv_writer.flush()?;
+            self.unflushed_records = 0;
+            self.last_flush = Instant::now();
         }
+        Ok(())
+    }
 
+    /// Writes all buffered records of the metadata.csv to the disk.
+    /// Called once an action has finished, so a crash loses at most the records of the running action
+    pub fn flush_metadata(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(csv_writer) = &mut self.csv_writer {
+            csv_writer.flush()?;
+            csv_writer.get_ref().get_ref().sync_data()?;
+        }
+        self.unflushed_records = 0;
+        self.last_flush = Instant::now();
         Ok(())
     }
 
+    /// Files stored so far, skipped (unchanged or deduplicated) files are not included
+    pub fn stored_files(&self) -> &[StoredFile] {
+        &self.stored_files
+    }
+
+    /// Number of files of interest found by the triage rules, known once the report is finished
+    pub fn findings(&self) -> usize {
+        self.findings
+    }
+
+    pub fn progress(&self) -> Arc<StorageProgress> {
+        self.progress.clone()
+    }
+
     /// Adds a single file to the archive by its path
     fn add_file_to_zip(
         &mut self,
         abs_file_path: &PathBuf,
         zip_file_name: String,
+        keep_plaintext: bool,
+        checksum: bool,
     ) -> Result<String, Box<dyn std::error::Error>> {
         // Step 0: Error if the archive is disabled or not initialized
         if self.zip_writer.is_none() {
@@ -289,12 +908,25 @@ impl<'a> FileProcessor<'a> {
 
         // Step 2: Set compression options
         let settings = &self.report_settings.zip_archive.compression;
-        let method =
-            if settings.enabled && (file_size <= settings.size_limit || settings.size_limit == 0) {
-                CompressionMethod::ZSTD
-            } else {
-                CompressionMethod::Stored
-            };
+        let excluded = settings.enabled && settings.is_excluded(abs_file_path);
+        if excluded {
+            debug!(
+                "Storing {:?} without compression: excluded by its extension",
+                abs_file_path.display()
+            );
+        }
+        // the large spooled files are added last without compression
+        let large = self.spool_path.is_some()
+            && file_size > self.report_settings.zip_archive.entry_order.large_file_size;
+        let method = if settings.enabled
+            && !excluded
+            && !large
+            && (file_size <= settings.size_limit || settings.size_limit == 0)
+        {
+            self.compression_method()
+        } else {
+            CompressionMethod::Stored
+        };
 
         // Check if file is larger than 4 GB
         // See: https://docs.rs/zip/2.1.3/zip/write/struct.FileOptions.html#method.large_file
@@ -305,7 +937,8 @@ impl<'a> FileProcessor<'a> {
             warn!("Adding files larger than 4 GB to the zip archive");
         }
 
-        let options = SimpleFileOptions::default()
+        let options = self
+            .file_options()
             .large_file(large_file)
             .compression_method(method);
 
@@ -326,43 +959,27 @@ impl<'a> FileProcessor<'a> {
 
         // Step 4: Write the file to the archive
         // Combine this step with checksum calculation to avoid redundant file reads
-        let enable_checksum = self.report_settings.metadata.checksums;
         if let Some(writer) = &mut self.zip_writer {
+            fail_injection::check(ZIP_WRITE)?;
             writer.start_file(zip_file_name, options)?;
 
-            let mut hasher = Sha1::new();
-            let mut reader = BufReader::new(file);
-            let mut buffer = [0u8; 4096];
-            loop {
-                let bytes_read = reader.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                if enable_checksum {
-                    hasher.update(&buffer[..bytes_read]);
-                }
-                writer.write_all(&buffer[..bytes_read])?;
-            }
+            let digests: &[Digest] = match checksum {
+                true => &[Digest::Sha1],
+                false => &[],
+            };
+            let mut reader = HashingReader::new(BufReader::new(file), digests)?;
+            io::copy(&mut reader, writer)?;
 
             // delete the file if it is inside the report directory
-            if abs_file_path.starts_with(&self.report.dir) {
+            if abs_file_path.starts_with(&self.report.dir) && !keep_plaintext {
                 match fs::remove_file(abs_file_path) {
                     Ok(_) => (),
                     Err(e) => error!("Failed to remove file: {:?}", e),
                 }
             }
 
-            match enable_checksum {
-                true => {
-                    let checksum = hasher.finish();
-                    // ensure the checksum has the same length
-                    let checksum: String = format!("{:0>40}", hex::encode(checksum));
-                    return Ok(checksum);
-                }
-                false => {
-                    return Ok("".to_string());
-                }
-            }
+            // empty if no checksum was requested
+            return Ok(reader.finish()?.hex(Digest::Sha1));
         }
         Err("Failed to add file to zip archive".into())
     }
@@ -385,37 +1002,119 @@ impl<'a> FileProcessor<'a> {
         if !metadata_path.exists() {
             warn!("Metadata file not found: {:?}", metadata_path);
         }
+        // the buffered records have to be written before the metadata.csv is archived
+        self.flush_metadata()?;
+        // the unpacker refuses reports in a layout it doesn't know
+        if let Err(e) = write_layout(&self.report.dir) {
+            error!("Failed to write {}: {}", LAYOUT_PATH, e);
+        }
+        // read before metadata.csv is moved into the archive
+        let records = match self.content_scanner.is_some()
+            || !self.triage.is_empty()
+            || !self.parser_findings.is_empty()
+        {
+            true => self.read_records(),
+            false => vec![],
+        };
+
+        if !self.baseline.is_empty() {
+            info!(
+                "Skipped {} files unchanged since the baseline",
+                self.unchanged_files
+            );
+        }
+        if self.report_settings.dedup.enabled {
+            info!(
+                "Skipped {} files already collected by previous reports",
+                self.deduplicated_files
+            );
+        }
+        if !self.exclusions.is_empty() {
+            info!(
+                "Held back {} files matching the exclusions",
+                self.exclusions.held_back_files()
+            );
+        }
 
         // if archiving is disabled, we can skip the zip archive creation and encryption
         let archive_enabled = self.report_settings.zip_archive.enabled;
         if !archive_enabled {
+            self.sweep(SweepSource::Directory, &records);
+            self.evaluate_triage(&records);
             return Ok(());
         }
 
+        // metadata.csv is removed once it was added to the archive
+        let metadata_sha1 = get_file_sha1(&metadata_path).unwrap_or_default();
+
+        // the report files are written first, the spooled files are copied behind them
+        let mut appended = vec![];
+        let swept = self.spool_path.is_some();
+        if let Some(spool_path) = self.spool_path.clone() {
+            if let Some(writer) = self.zip_writer.take() {
+                writer.finish()?;
+            }
+            // the sweep reads the spool, so its results and the findings precede the large files
+            if self.sweep(SweepSource::Spool(&spool_path), &records) {
+                appended.push(SWEEP_PATH);
+            }
+            if self.evaluate_triage(&records) {
+                appended.push(FINDINGS_PATH);
+            }
+            let zip_path = self.report.zip_path.clone();
+            self.initialize_zip_archive(&zip_path);
+        }
+
         info!("Adding all remaining files to the archive");
-        let include_files = match get_files_by_patterns(
+        let include_files = iter_files_by_patterns(
             vec![
                 format!("{}/{}", loot_dir.to_str().unwrap(), "**/*"),
                 //format!("{}/{}", loot_dir.to_str().unwrap(), "*"),
                 format!("{}/{}", action_log_dir.to_str().unwrap(), "*"),
                 format!("{}", metadata_path.to_str().unwrap()),
+                format!("{}", self.report.dir.join(README_PATH).to_str().unwrap()),
+                format!("{}", self.report.dir.join(LAYOUT_PATH).to_str().unwrap()),
+                format!(
+                    "{}",
+                    self.report.dir.join(COLLECTOR_LOG_PATH).to_str().unwrap()
+                ),
+                // the log of a sealed workflow, encrypted with the session key
+                format!(
+                    "{}",
+                    encrypted_output_path(&self.report.dir.join(COLLECTOR_LOG_PATH))
+                        .to_str()
+                        .unwrap()
+                ),
             ],
             true,
-        ) {
-            Ok(files) => files,
-            Err(e) => {
-                error!("Failed to get files by pattern: {:?}", e);
-                vec![]
-            }
-        };
+        );
+        if let Err(e) = &include_files {
+            error!("Failed to get files by pattern: {:?}", e);
+        }
+        let mut include_files: Vec<PathBuf> = include_files.into_iter().flatten().collect();
+        if self.spool_path.is_some() {
+            order::sort_report_files(&mut include_files, &self.report.dir);
+        }
 
-        for file in &include_files {
+        for file in include_files {
             // the zip file is the relative path to the report directory
             let zip_file_name = match file.strip_prefix(&self.report.dir) {
                 Ok(path) => path,
                 Err(_) => file.as_path(),
             };
-            match self.add_file_to_zip(&file, zip_file_name.to_str().unwrap().to_string()) {
+            // sealed sidecar files never remain in plaintext
+            let log_path = self.report.dir.join(COLLECTOR_LOG_PATH);
+            let sealed = self.sidecars_sealed()
+                && (file == metadata_path
+                    || file == log_path
+                    || file == encrypted_output_path(&log_path));
+            let keep_plaintext = self.report_settings.zip_archive.keep_plaintext && !sealed;
+            match self.add_file_to_zip(
+                &file,
+                zip_file_name.to_string_lossy().to_string(),
+                keep_plaintext,
+                self.report_settings.metadata.checksums,
+            ) {
                 Ok(checksum) => {
                     debug!("Checksum: {:?}", checksum);
                 }
@@ -427,41 +1126,389 @@ impl<'a> FileProcessor<'a> {
             }
         }
 
-        if let Some(writer) = self.zip_writer.take() {
+        // like the metadata, the results reveal what has been collected
+        let keep_plaintext =
+            self.report_settings.zip_archive.keep_plaintext && !self.sidecars_sealed();
+        for name in appended.drain(..) {
+            if let Err(e) = self.add_file_to_zip(
+                &self.report.dir.join(name),
+                name.to_string(),
+                keep_plaintext,
+                self.report_settings.metadata.checksums,
+            ) {
+                error!("Failed to add file {} to zip archive: {:?}", name, e);
+            }
+        }
+
+        let options = self.file_options();
+        if let (Some(spool_path), Some(writer)) = (self.spool_path.take(), &mut self.zip_writer) {
+            let order = &self.report_settings.zip_archive.entry_order;
+            let copied = order::copy_spooled(&spool_path, writer, order, options)?;
+            info!("Added {} spooled files to the archive", copied);
+            fs::remove_file(&spool_path)?;
+        }
+
+        // the comment binds the archive to its metadata.csv
+        let comment = archive_comment(
+            &self.collected.to_rfc3339_opts(SecondsFormat::Secs, true),
+            &metadata_sha1,
+        );
+        if let Some(mut writer) = self.zip_writer.take() {
+            writer.set_comment(comment);
             writer.finish()?;
         }
 
+        // without a spool the sweep reads the finished archive, so its results and the findings
+        // are appended
+        let report = self.report;
+        if !swept && self.sweep(SweepSource::Archive(&report.zip_path), &records) {
+            appended.push(SWEEP_PATH);
+        }
+        if !swept && self.evaluate_triage(&records) {
+            appended.push(FINDINGS_PATH);
+        }
+        if !appended.is_empty() {
+            self.append_to_zip(&appended)?;
+        }
+        // the archive is encrypted anyway, so a failed self-test doesn't leave it in plaintext
+        let self_test_result = self.self_test(&report.zip_path);
+        match self.report_settings.zip_archive.format {
+            ArchiveFormat::TarZstd => {
+                info!("Converting the archive to {}", TAR_ZSTD_PATH);
+                container::zip_to_tar_zstd(&report.zip_path, &report.tar_path)?;
+                fs::remove_file(&report.zip_path)?;
+            }
+            ArchiveFormat::SevenZip => {
+                info!("Converting the archive to {}", SEVEN_ZIP_PATH);
+                container::zip_to_7z(&report.zip_path, &report.seven_zip_path)?;
+                fs::remove_file(&report.zip_path)?;
+            }
+            _ => {}
+        }
+        let (archive_path, _) = self.archive_path();
+
         // if encryption is disabled, we can skip the rest
         let encryption_enabled = self.report_settings.zip_archive.encryption.enabled;
         if !encryption_enabled {
             // save as encryption.json in the same directory as the output file
             self.write_encryption_metadata(&EncryptionMeta::default())?;
-            return Ok(());
+            if self.aborted.is_some() || !self.exclusions.is_empty() {
+                self.write_manifest(Algorithm::None)?;
+            }
+            return self_test_result.map_err(Into::into);
         }
 
         let algorithm = self.report_settings.zip_archive.encryption.algorithm;
 
-        let (encrypted_key, iv, tag) = match &self.public_key {
-            Some(pub_key) => {
-                encrypt_evidence(&self.report.zip_path, pub_key.clone(), algorithm.clone())?
+        let encryption_metadata = match (&self.public_key, &self.session_key) {
+            (Some(pub_key), Some(key)) => {
+                fail_injection::check(ENCRYPTION)?;
+                let mut metadata = encrypt_evidence_with_key(archive_path, key)?;
+                metadata.encrypted_key = key.encrypt(pub_key)?;
+                metadata
+            }
+            // the archive would remain in plaintext, but encryption.json would name the algorithm
+            (Some(_), None) => {
+                return Err("Encryption is enabled, but no session key was generated".into())
+            }
+            _ => {
+                let mut metadata = EncryptionMeta::default();
+                metadata.algorithm = algorithm;
+                metadata
             }
-            None => (vec![], vec![], vec![]),
-        };
-
-        // write metadata into json file
-        let encryption_metadata = EncryptionMeta {
-            version: "1.0".to_string(),
-            algorithm: algorithm,
-            encrypted_key: encrypted_key,
-            iv: iv,
-            tag: tag,
         };
 
         // save as encryption.json in the same directory as the output file
         self.write_encryption_metadata(&encryption_metadata)?;
 
+        if self.sidecars_sealed() || self.aborted.is_some() || !self.exclusions.is_empty() {
+            self.write_manifest(algorithm)?;
+        }
+
+        self_test_result.map_err(Into::into)
+    }
+
+    /// Verifies the finished archive: all stored files and metadata.csv are entries of it
+    /// and the entries can be read with a valid CRC
+    fn self_test(&self, zip_path: &Path) -> Result<(), String> {
+        let mode = self.report_settings.zip_archive.self_test;
+        if mode == ArchiveSelfTest::Off {
+            return Ok(());
+        }
+        info!("Verifying the archive ({:?})", mode);
+        let mut expected: Vec<&str> = self
+            .stored_files
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect();
+        expected.push(METADATA_PATH);
+        let result = self_test::verify_archive(zip_path, mode, &expected)
+            .map_err(|e| format!("Archive self-test failed: {}", e))?;
+        for e in &result.errors {
+            error!("Archive self-test: {}", e);
+        }
+        info!(
+            "Verified {} of {} archive entries, {} errors",
+            result.verified,
+            result.entries,
+            result.errors.len()
+        );
+        match result.errors.is_empty() {
+            true => Ok(()),
+            false => Err(format!(
+                "Archive self-test failed: {} errors, e.g. {}",
+                result.errors.len(),
+                result.errors[0]
+            )),
+        }
+    }
+
+    fn read_records(&self) -> Vec<FileMeta> {
+        File::open(&self.report.metadata_path)
+            .map_err(|e| e.into())
+            .and_then(read_metadata_from_reader)
+            .unwrap_or_else(|e| {
+                error!("Failed to read metadata for the sweep and triage: {}", e);
+                vec![]
+            })
+    }
+
+    /// Scans the collected files inside the archive (or the report directory)
+    /// and writes the matches. Returns whether yara_sweep.csv has been written
+    fn sweep(&mut self, source: SweepSource, records: &[FileMeta]) -> bool {
+        let scanner = match &self.content_scanner {
+            Some(scanner) => scanner.as_ref(),
+            None => return false,
+        };
+        info!("Sweeping the collected files with YARA rules");
+        let original_paths = sweep::original_paths(records, &self.report.loot_dir);
+        let size_limit = self.report_settings.yara_sweep.size_limit;
+        let results = match source {
+            SweepSource::Archive(zip_path) => sweep_archive(
+                zip_path,
+                scanner,
+                size_limit,
+                &self.report.dir,
+                &original_paths,
+            ),
+            SweepSource::Spool(spool_path) => sweep_spool(
+                spool_path,
+                scanner,
+                size_limit,
+                &self.report.dir,
+                &original_paths,
+            ),
+            SweepSource::Directory => Ok(sweep_directory(
+                scanner,
+                size_limit,
+                &self.report.dir,
+                &original_paths,
+            )),
+        };
+        let results = match results {
+            Ok(results) => results,
+            Err(e) => {
+                error!("YARA sweep failed: {}", e);
+                return false;
+            }
+        };
+
+        let errors = results.iter().filter(|r| r.error.is_some()).count();
+        info!(
+            "YARA sweep found {} matches, {} files could not be scanned",
+            results.len() - errors,
+            errors
+        );
+        for result in results.iter().filter(|r| r.error.is_none()) {
+            let original_path = PathBuf::from(&result.original_path);
+            self.add_yara_match(&original_path, &result.namespace);
+        }
+        if let Err(e) = write_sweep(&results, &self.report.dir.join(SWEEP_PATH)) {
+            error!("Failed to write YARA sweep results: {}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Ranks the collected files by the triage rules and the findings of the parser plugins.
+    /// Returns whether findings.json has been written
+    fn evaluate_triage(&mut self, records: &[FileMeta]) -> bool {
+        if self.triage.is_empty() && self.parser_findings.is_empty() {
+            return false;
+        }
+        let findings = self
+            .triage
+            .evaluate(records, &self.yara_matches, &self.parser_findings);
+        info!("Triage rules found {} files of interest", findings.len());
+        self.findings = findings.len();
+        if let Err(e) = write_findings(&findings, &self.report.dir.join(FINDINGS_PATH)) {
+            error!("Failed to write findings: {}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Appends files of the report directory to the finished archive
+    fn append_to_zip(&self, names: &[&str]) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.report.zip_path)?;
+        let mut writer = ZipWriter::new_append(file)?;
+        let method = match self.report_settings.zip_archive.compression.enabled {
+            true => self.compression_method(),
+            false => CompressionMethod::Stored,
+        };
+        let options = self.file_options().compression_method(method);
+        // like the metadata, the results reveal what has been collected
+        let keep_plaintext =
+            self.report_settings.zip_archive.keep_plaintext && !self.sidecars_sealed();
+        for name in names {
+            let path = self.report.dir.join(name);
+            debug!("Appending file {:?} to zip archive", name);
+            writer.start_file(name.to_string(), options)?;
+            writer.write_all(&fs::read(&path)?)?;
+            if !keep_plaintext {
+                fs::remove_file(&path)?;
+            }
+        }
+        writer.finish()?;
         Ok(())
     }
+
+    /// Describes the sealed archive, without revealing what has been collected
+    fn write_manifest(&self, algorithm: Algorithm) -> Result<(), Box<dyn Error>> {
+        let (archive_path, archive_name) = self.archive_path();
+        let manifest = Manifest {
+            report: self
+                .report
+                .dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            archive: archive_name.to_string(),
+            archive_size: fs::metadata(archive_path)?.len(),
+            archive_sha1: get_file_sha1(archive_path)?,
+            algorithm,
+            run_id: self.report.run_id.clone(),
+            aborted: self.aborted.clone(),
+            ip_addresses: self.report.host.ip_addresses.clone(),
+            mac_addresses: self.report.host.mac_addresses.clone(),
+            domain: self.report.host.domain.clone(),
+            boot_time: self
+                .report
+                .host
+                .boot_time
+                .map(|boot_time| boot_time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            locale: self.report.host.locale.clone(),
+            exclusions: self.exclusions.records(),
+        };
+        let file = File::create(self.report.dir.join(MANIFEST_PATH))?;
+        serde_json::to_writer_pretty(file, &manifest)
+            .map_err(|e| format!("Failed to write manifest: {:?}", e).into())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub report: String,
+    pub created: String,
+    pub archive: String,
+    pub archive_size: u64,
+    // of the encrypted archive
+    pub archive_sha1: String,
+    pub algorithm: Algorithm,
+    #[serde(default)]
+    pub run_id: String,
+    // why the workflow was stopped early (on_error: abort_and_finalize), None if it completed
+    #[serde(default)]
+    pub aborted: Option<String>,
+    // network context of the endpoint, to tell where the report was collected
+    #[serde(default)]
+    pub ip_addresses: Vec<String>,
+    #[serde(default)]
+    pub mac_addresses: Vec<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub boot_time: Option<String>,
+    #[serde(default)]
+    pub locale: String,
+    // paths which were never collected and how many files each held back
+    #[serde(default)]
+    pub exclusions: Vec<Exclusion>,
+}
+
+// entries of a zip archive have no time zone, UTC is used to be independent of the collecting system.
+// The archive stores seconds with a resolution of 2 seconds, so odd seconds are rounded down
+fn zip_timestamp(time: &chrono::DateTime<Utc>) -> zip::DateTime {
+    zip::DateTime::from_date_and_time(
+        time.year() as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8 & !1,
+    )
+    .unwrap_or_default()
+}
+
+// Resets the access time if it was changed. The change time (ctime) of the file is updated
+// on UNIX, the modification time is kept
+fn restore_atime(file_path: &Path, atime: FileTime) {
+    let current = match fs::metadata(file_path) {
+        Ok(metadata) => FileTime::from_last_access_time(&metadata),
+        // e.g. a file that was removed in the meantime
+        Err(_) => return,
+    };
+    if current == atime {
+        debug!("Access time of {:?} unchanged", file_path);
+        return;
+    }
+    match filetime::set_file_atime(file_path, atime) {
+        Ok(_) => debug!("Restored access time of {:?}", file_path),
+        Err(e) => warn!("Failed to restore access time of {:?}: {}", file_path, e),
+    }
+}
+
+// Returns the MAC times as rfc3339 strings in the time zone
+fn mac_times(file_metadata: &fs::Metadata, tz: &Tz) -> (String, String, String) {
+    let mtime = FileTime::from_last_modification_time(file_metadata);
+    let atime = FileTime::from_last_access_time(file_metadata);
+    let ctime = FileTime::from_creation_time(file_metadata);
+
+    // convert to rfc3339 string
+    let mtime: String = Local
+        .timestamp_opt(mtime.unix_seconds(), 0)
+        .unwrap()
+        .with_timezone(tz)
+        .to_rfc3339();
+    let atime: String = Local
+        .timestamp_opt(atime.unix_seconds(), 0)
+        .unwrap()
+        .with_timezone(tz)
+        .to_rfc3339();
+    let ctime: String = match ctime {
+        Some(ctime) => Local
+            .timestamp_opt(ctime.unix_seconds(), 0)
+            .unwrap()
+            .with_timezone(tz)
+            .to_rfc3339(),
+        None => "None".to_string(),
+    };
+    (mtime, atime, ctime)
+}
+
+// the baseline may have been collected with another time zone
+fn same_time(a: &str, b: &str) -> bool {
+    match (
+        chrono::DateTime::parse_from_rfc3339(a),
+        chrono::DateTime::parse_from_rfc3339(b),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
 }
 
 pub fn read_metadata(metadata_path: &PathBuf) -> Vec<FileMeta> {
@@ -474,12 +1521,22 @@ pub fn read_metadata(metadata_path: &PathBuf) -> Vec<FileMeta> {
     file_metadata
 }
 
+// Reads the metadata from any source, e.g. an entry of the archive
+pub fn read_metadata_from_reader<R: Read>(reader: R) -> Result<Vec<FileMeta>, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_reader(reader);
+    let mut file_metadata = Vec::new();
+    for result in rdr.deserialize() {
+        file_metadata.push(result?);
+    }
+    Ok(file_metadata)
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
 
     use super::*;
-    use config::workflow::{ReportingMetadata, ReportingZipArchive};
+    use crypto::EncryptingWriter;
     use system::SystemVariables;
     use utils::tests::Cleanup;
 
@@ -517,10 +1574,7 @@ mod tests {
         cleanup.add(report.dir.clone());
         let mut file_processor = FileProcessor::new(&report).unwrap();
 
-        let reporting_settings = Reporting {
-            zip_archive: ReportingZipArchive::default(),
-            metadata: ReportingMetadata::default(),
-        };
+        let reporting_settings = Reporting::default();
         file_processor.set_report_settings(reporting_settings);
 
         let file_dir = cleanup.tmp_dir("test_file_processor_store_file");
@@ -533,6 +1587,7 @@ mod tests {
         let metadata_path = report.metadata_path.clone();
         let metadata = read_metadata(&metadata_path);
         assert_eq!(metadata.len(), 1, "Metadata not correctly written");
+        assert_eq!(metadata[0].run_id, report.run_id);
 
         let metadata_path = metadata[0]
             .original_path
@@ -542,6 +1597,37 @@ mod tests {
         assert_eq!(metadata_path, file_path.to_str().unwrap().to_string());
     }
 
+    #[test]
+    fn test_file_processor_metadata_flush() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_metadata_flush".to_string(), false);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.enabled = false;
+        reporting_settings.metadata.flush.records = 3;
+        file_processor.set_report_settings(reporting_settings);
+
+        let file_dir = cleanup.tmp_dir("test_file_processor_metadata_flush");
+        let files = vec!["a.txt", "b.txt", "c.txt", "d.txt"];
+        cleanup.create_files(&file_dir, files.clone());
+        let mut store = |file: &str| file_processor.store(&file_dir.join(file), None).unwrap();
+
+        // records are buffered until the third one
+        store(files[0]);
+        store(files[1]);
+        assert_eq!(read_metadata(&report.metadata_path).len(), 0);
+        store(files[2]);
+        assert_eq!(read_metadata(&report.metadata_path).len(), 3);
+        store(files[3]);
+        assert_eq!(read_metadata(&report.metadata_path).len(), 3);
+
+        // at the end of an action
+        file_processor.flush_metadata().unwrap();
+        assert_eq!(read_metadata(&report.metadata_path).len(), 4);
+    }
+
     #[test]
     fn test_file_processor_add_file_to_zip() {
         let mut cleanup = Cleanup::new();
@@ -549,10 +1635,7 @@ mod tests {
         let report = generate_test_report("test_file_processor_add_file_to_zip".to_string(), true);
         cleanup.add(report.dir.clone());
 
-        let reporting_settings = Reporting {
-            zip_archive: ReportingZipArchive::default(),
-            metadata: ReportingMetadata::default(),
-        };
+        let reporting_settings = Reporting::default();
 
         let mut file_processor = FileProcessor::new(&report).unwrap();
         file_processor.set_report_settings(reporting_settings);
@@ -568,6 +1651,472 @@ mod tests {
         assert!(zip_path.exists(), "Zip file was not created");
     }
 
+    #[test]
+    fn test_file_processor_baseline() {
+        let mut cleanup = Cleanup::new();
+
+        let file_dir = cleanup.tmp_dir("test_file_processor_baseline");
+        cleanup.create_files(
+            &file_dir,
+            vec!["unchanged.txt", "changed.txt", "timestomped.txt"],
+        );
+        let timestomped = file_dir.join("timestomped.txt");
+        fs::write(&timestomped, "original").unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.enabled = false;
+        reporting_settings.metadata.mac_times = true;
+        reporting_settings.metadata.checksums = true;
+
+        // initial collection
+        let first = generate_test_report("test_file_processor_baseline_1".to_string(), false);
+        cleanup.add(first.dir.clone());
+        let mut file_processor = FileProcessor::new(&first).unwrap();
+        file_processor.set_report_settings(reporting_settings.clone());
+        for file in ["unchanged.txt", "changed.txt", "timestomped.txt"] {
+            file_processor.store(&file_dir.join(file), None).unwrap();
+        }
+
+        fs::write(file_dir.join("changed.txt"), "new content").unwrap();
+        // same size and modification time, but another content
+        let modified = fs::metadata(&timestomped).unwrap().modified().unwrap();
+        fs::write(&timestomped, "tampered").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&timestomped)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        // delta collection
+        let second = generate_test_report("test_file_processor_baseline_2".to_string(), false);
+        cleanup.add(second.dir.clone());
+        let mut file_processor = FileProcessor::new(&second).unwrap();
+        file_processor.set_report_settings(reporting_settings);
+        // the MAC times of the baseline are in UTC
+        file_processor.set_time_zone(chrono_tz::Europe::Berlin);
+        assert_eq!(
+            file_processor.set_baseline(&first.metadata_path).unwrap(),
+            3
+        );
+        for file in ["unchanged.txt", "changed.txt", "timestomped.txt"] {
+            file_processor.store(&file_dir.join(file), None).unwrap();
+        }
+
+        // all files are recorded, but only the changed ones are stored
+        let metadata = read_metadata(&second.metadata_path);
+        assert_eq!(metadata.len(), 3);
+        for record in metadata {
+            let unchanged = record.original_path.ends_with("unchanged.txt");
+            assert_eq!(record.unchanged, unchanged);
+            assert!(!record.modified_time.ends_with("+00:00"));
+            let stored = second.dir.join(STORAGE_DIR).join(&record.path_checksum);
+            assert_eq!(stored.exists(), !unchanged);
+        }
+    }
+
+    #[test]
+    fn test_file_processor_dedup() {
+        let mut cleanup = Cleanup::new();
+
+        let file_dir = cleanup.tmp_dir("test_file_processor_dedup");
+        fs::write(file_dir.join("a.dll"), "same content").unwrap();
+        fs::write(file_dir.join("b.dll"), "same content").unwrap();
+        fs::write(file_dir.join("c.dll"), "other content").unwrap();
+        let cache_path = file_dir.join("cache").join("dedup_cache.txt");
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.enabled = false;
+        reporting_settings.dedup.enabled = true;
+
+        // identical files are only stored once
+        let first = generate_test_report("test_file_processor_dedup_1".to_string(), false);
+        cleanup.add(first.dir.clone());
+        let mut file_processor = FileProcessor::new(&first).unwrap();
+        file_processor.set_report_settings(reporting_settings.clone());
+        assert_eq!(file_processor.set_dedup_cache(&cache_path).unwrap(), 0);
+        for file in ["a.dll", "b.dll"] {
+            file_processor.store(&file_dir.join(file), None).unwrap();
+        }
+        assert_eq!(file_processor.update_dedup_cache(&cache_path).unwrap(), 1);
+        let metadata = read_metadata(&first.metadata_path);
+        assert_eq!(metadata[0].storage_name(), metadata[1].storage_name());
+        assert!(metadata
+            .iter()
+            .all(|r| r.content_addressed && !r.deduplicated));
+        assert_eq!(
+            fs::read_dir(first.dir.join(STORAGE_DIR)).unwrap().count(),
+            1
+        );
+
+        // known content is skipped by the next report
+        let second = generate_test_report("test_file_processor_dedup_2".to_string(), false);
+        cleanup.add(second.dir.clone());
+        let mut file_processor = FileProcessor::new(&second).unwrap();
+        file_processor.set_report_settings(reporting_settings);
+        assert_eq!(file_processor.set_dedup_cache(&cache_path).unwrap(), 1);
+        for file in ["a.dll", "c.dll"] {
+            file_processor.store(&file_dir.join(file), None).unwrap();
+        }
+        assert_eq!(file_processor.update_dedup_cache(&cache_path).unwrap(), 1);
+        // only the new content counts as stored
+        let stored_files = file_processor.stored_files();
+        assert_eq!(stored_files.len(), 1);
+        assert_eq!(stored_files[0].size, "other content".len() as u64);
+        assert!(stored_files[0].name.starts_with(STORAGE_DIR));
+        assert_eq!(file_processor.progress().files.load(Ordering::Relaxed), 1);
+        for record in read_metadata(&second.metadata_path) {
+            let deduplicated = record.original_path.ends_with("a.dll");
+            assert_eq!(record.deduplicated, deduplicated);
+            let stored = second.dir.join(STORAGE_DIR).join(record.storage_name());
+            assert_eq!(stored.exists(), !deduplicated);
+        }
+        let cache = fs::read_to_string(&cache_path).unwrap();
+        assert_eq!(cache.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_file_processor_hard_links() {
+        let mut cleanup = Cleanup::new();
+
+        let file_dir = cleanup.tmp_dir("test_file_processor_hard_links");
+        fs::write(file_dir.join("access.log"), "GET /").unwrap();
+        fs::hard_link(file_dir.join("access.log"), file_dir.join("access.log.1")).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.enabled = false;
+
+        let report = generate_test_report("test_file_processor_hard_links".to_string(), false);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        file_processor.set_report_settings(reporting_settings);
+        for file in ["access.log", "access.log.1"] {
+            file_processor.store(&file_dir.join(file), None).unwrap();
+        }
+        assert!(file_processor
+            .store(&file_dir.join("access.log.1"), None)
+            .is_err());
+        file_processor.flush_metadata().unwrap();
+
+        // the content of the second link is not stored again
+        assert_eq!(file_processor.stored_files().len(), 1);
+        let metadata = read_metadata(&report.metadata_path);
+        assert_eq!(metadata.len(), 2);
+        assert!(metadata[0].link_group.is_some());
+        assert_eq!(metadata[0].link_group, metadata[1].link_group);
+        assert_eq!(metadata[0].hard_link_of, None);
+        assert_eq!(
+            metadata[1].hard_link_of.as_ref(),
+            Some(&metadata[0].original_path)
+        );
+        assert_eq!(
+            fs::read_dir(report.dir.join(STORAGE_DIR)).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_file_processor_preserve_atime() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_preserve_atime".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.metadata.checksums = true;
+        reporting_settings.metadata.preserve_atime = true;
+        file_processor.set_report_settings(reporting_settings);
+
+        let file_dir = cleanup.tmp_dir("test_file_processor_preserve_atime");
+        cleanup.create_files(&file_dir, vec!["evidence.txt"]);
+        let file_path = file_dir.join("evidence.txt");
+        // older than the modification time, so reading the file would update it
+        let atime = FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_atime(&file_path, atime).unwrap();
+
+        file_processor.store(&file_path, None).unwrap();
+        let metadata = fs::metadata(&file_path).unwrap();
+        assert_eq!(FileTime::from_last_access_time(&metadata), atime);
+    }
+
+    #[test]
+    fn test_file_processor_keep_plaintext() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_keep_plaintext".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.keep_plaintext = true;
+        file_processor.set_report_settings(reporting_settings);
+
+        cleanup.create_files(&report.loot_dir, vec!["kept.txt", "deleted.txt"]);
+        file_processor
+            .store(&report.loot_dir.join("kept.txt"), None)
+            .unwrap();
+        file_processor
+            .store_keep_plaintext(&report.loot_dir.join("deleted.txt"), None, false)
+            .unwrap();
+
+        assert!(report.loot_dir.join("kept.txt").exists());
+        assert!(!report.loot_dir.join("deleted.txt").exists());
+    }
+
+    #[test]
+    fn test_file_processor_archive_metadata() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_archive_metadata".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.encryption.enabled = false;
+        // the archive is read back, finish fails if it is corrupted
+        reporting_settings.zip_archive.self_test = ArchiveSelfTest::Full;
+        file_processor.set_report_settings(reporting_settings);
+
+        cleanup.create_files(&report.loot_dir, vec!["beweisstück.txt"]);
+        let stored = cleanup.tmp_dir("test_file_processor_archive_metadata");
+        fs::write(stored.join("evidence.txt"), "content").unwrap();
+        file_processor
+            .store(&stored.join("evidence.txt"), None)
+            .unwrap();
+        // an aborted report is finished as usual and described by a manifest
+        file_processor.set_aborted("Action \"memory\" failed".to_string());
+        file_processor.finish().unwrap();
+        let manifest: Manifest =
+            serde_json::from_reader(File::open(report.dir.join(MANIFEST_PATH)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.aborted.as_deref(),
+            Some("Action \"memory\" failed")
+        );
+        assert_eq!(manifest.algorithm, Algorithm::None);
+
+        let mut archive = zip::ZipArchive::new(File::open(&report.zip_path).unwrap()).unwrap();
+        let comment = String::from_utf8_lossy(archive.comment()).to_string();
+        let metadata_sha1 =
+            crypto::get_reader_sha1(archive.by_name(report::METADATA_PATH).unwrap()).unwrap();
+        assert_eq!(
+            report::layout::comment_metadata_sha1(&comment),
+            Some(metadata_sha1.as_str())
+        );
+
+        // all entries carry the time of the collection
+        let collected = zip_timestamp(&file_processor.collected);
+        let names: Vec<String> = archive.file_names().map(String::from).collect();
+        assert!(names.contains(&"loot_files/beweisstück.txt".to_string()));
+        for name in names {
+            let entry = archive.by_name(&name).unwrap();
+            assert_eq!(entry.last_modified(), Some(collected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_file_processor_exclusions() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_exclusions".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.encryption.enabled = false;
+        file_processor.set_report_settings(reporting_settings);
+        let exclusions = vec!["**/Medical/**".to_string()];
+        assert_eq!(file_processor.set_exclusions(&exclusions).unwrap(), 1);
+
+        let dir = cleanup.tmp_dir("test_file_processor_exclusions");
+        cleanup.create_files(&dir, vec!["medical/scan.pdf", "notes.txt"]);
+        let result = file_processor.store(&dir.join("medical/scan.pdf"), None);
+        assert_eq!(result.unwrap_err().to_string(), EXCLUDED);
+        file_processor.store(&dir.join("notes.txt"), None).unwrap();
+        // a link is held back if its target is excluded
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("medical/scan.pdf"), dir.join("scan.pdf")).unwrap();
+            assert!(file_processor.is_excluded(&dir.join("scan.pdf")));
+        }
+        file_processor.finish().unwrap();
+
+        // the manifest records the exclusions, even if the report wasn't aborted
+        let manifest: Manifest =
+            serde_json::from_reader(File::open(report.dir.join(MANIFEST_PATH)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.exclusions,
+            [Exclusion {
+                pattern: "**/Medical/**".to_string(),
+                held_back_files: 1
+            }]
+        );
+        assert_eq!(file_processor.stored_files().len(), 1);
+    }
+
+    #[test]
+    fn test_file_processor_entry_order() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_entry_order".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.compression.enabled = true;
+        reporting_settings.zip_archive.entry_order.enabled = true;
+        reporting_settings.zip_archive.entry_order.large_file_size = 100;
+        reporting_settings.zip_archive.self_test = ArchiveSelfTest::Full;
+        file_processor.set_report_settings(reporting_settings);
+        let spool_path = order::spool_path(&report.zip_path);
+        assert!(spool_path.exists());
+
+        let stored = cleanup.tmp_dir("test_file_processor_entry_order");
+        fs::write(stored.join("memory.raw"), vec![0u8; 1000]).unwrap();
+        fs::write(stored.join("hosts"), "127.0.0.1 localhost").unwrap();
+        file_processor
+            .store(&stored.join("memory.raw"), None)
+            .unwrap();
+        file_processor.store(&stored.join("hosts"), None).unwrap();
+        fs::write(stored.join("evil.txt"), "an EVIL file").unwrap();
+        file_processor
+            .store(&stored.join("evil.txt"), None)
+            .unwrap();
+        file_processor.set_content_scanner(Box::new(EvilScanner));
+        cleanup.create_files(&report.loot_dir, vec!["processes.txt"]);
+        file_processor.finish().unwrap();
+        assert!(!spool_path.exists());
+
+        let mut archive = zip::ZipArchive::new(File::open(&report.zip_path).unwrap()).unwrap();
+        let names: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index_raw(i).unwrap().name().to_string())
+            .filter(|name| !name.ends_with('/'))
+            .collect();
+        let position = |name: &str| names.iter().position(|n| n == name).unwrap();
+        let metadata = position(METADATA_PATH);
+        let loot = position("loot_files/processes.txt");
+        let stored_files = &file_processor.stored_files;
+        let (memory, hosts) = (&stored_files[0].name, &stored_files[1].name);
+        assert!(metadata < loot);
+        assert!(loot < position(hosts));
+        // the sweep results are written before the large files
+        assert!(position(SWEEP_PATH) < position(memory));
+        assert_eq!(position(memory), names.len() - 1);
+        let mut sweep = String::new();
+        archive
+            .by_name(SWEEP_PATH)
+            .unwrap()
+            .read_to_string(&mut sweep)
+            .unwrap();
+        assert!(sweep.contains("evil.txt,evil_string,malware"));
+        let memory = archive.by_name(memory).unwrap();
+        assert_eq!(memory.compression(), CompressionMethod::Stored);
+        assert_eq!(memory.data_start() % 4096, 0);
+    }
+
+    #[derive(Debug)]
+    struct EvilScanner;
+
+    impl ContentScanner for EvilScanner {
+        fn scan(&self, content: &[u8]) -> Result<Vec<sweep::ContentMatch>, String> {
+            let found = content.windows(4).any(|window| window == b"EVIL");
+            Ok(found
+                .then(|| sweep::ContentMatch {
+                    rule: "evil_string".to_string(),
+                    namespace: "malware".to_string(),
+                })
+                .into_iter()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_file_processor_yara_sweep() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_yara_sweep".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.encryption.enabled = false;
+        file_processor.set_report_settings(reporting_settings);
+        file_processor.set_content_scanner(Box::new(EvilScanner));
+        file_processor.set_triage_rules(vec![TriageRule {
+            name: "Malware".to_string(),
+            severity: 90,
+            paths: vec![],
+            min_entropy: None,
+            unsigned: false,
+            yara_namespaces: vec!["malware".to_string()],
+        }]);
+
+        let dir = cleanup.tmp_dir("test_file_processor_yara_sweep_files");
+        fs::write(dir.join("evil.txt"), "an EVIL file").unwrap();
+        fs::write(dir.join("clean.txt"), "a clean file").unwrap();
+        file_processor.store(&dir.join("evil.txt"), None).unwrap();
+        file_processor.store(&dir.join("clean.txt"), None).unwrap();
+        file_processor.finish().unwrap();
+
+        // the results are appended to the archive
+        let mut archive = zip::ZipArchive::new(File::open(&report.zip_path).unwrap()).unwrap();
+        let mut sweep = String::new();
+        archive
+            .by_name(SWEEP_PATH)
+            .unwrap()
+            .read_to_string(&mut sweep)
+            .unwrap();
+        assert_eq!(sweep.lines().count(), 2);
+        assert!(sweep.contains("evil.txt,evil_string,malware"));
+        // the matches are used by the triage rules
+        let mut findings = String::new();
+        archive
+            .by_name(FINDINGS_PATH)
+            .unwrap()
+            .read_to_string(&mut findings)
+            .unwrap();
+        assert!(findings.contains("evil.txt"));
+        assert!(!findings.contains("clean.txt"));
+        assert_eq!(file_processor.findings(), 1);
+        assert!(!report.dir.join(SWEEP_PATH).exists());
+    }
+
+    #[test]
+    fn test_file_processor_seal_sidecars() {
+        let mut cleanup = Cleanup::new();
+
+        let report = generate_test_report("test_file_processor_seal_sidecars".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.keep_plaintext = true;
+        reporting_settings.zip_archive.encryption.enabled = true;
+        reporting_settings.zip_archive.encryption.algorithm = Algorithm::CHACHA20POLY1305;
+        reporting_settings.zip_archive.encryption.seal_sidecars = true;
+        file_processor.set_report_settings(reporting_settings);
+        let rsa = Rsa::generate(2048).unwrap();
+        let public_key = Rsa::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+        file_processor.set_public_key(public_key).unwrap();
+        assert!(file_processor.sidecars_sealed());
+
+        cleanup.create_files(&report.loot_dir, vec!["kept.txt"]);
+        file_processor
+            .store(&report.loot_dir.join("kept.txt"), None)
+            .unwrap();
+        // the log is encrypted with the session key while the workflow runs
+        let log_path = encrypted_output_path(&report.dir.join(COLLECTOR_LOG_PATH));
+        let key = file_processor.sealed_log_key().unwrap();
+        let mut log = EncryptingWriter::new(File::create(&log_path).unwrap(), &key).unwrap();
+        log.write_all(b"[INFO] collected").unwrap();
+        log.finish().unwrap();
+        file_processor.finish().unwrap();
+
+        // keep_plaintext doesn't apply to the sealed files
+        assert!(report.loot_dir.join("kept.txt").exists());
+        assert!(!report.metadata_path.exists());
+        assert!(!log_path.exists());
+        let manifest: Manifest =
+            serde_json::from_reader(File::open(report.dir.join(MANIFEST_PATH)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.archive_sha1,
+            get_file_sha1(&report.zip_path).unwrap()
+        );
+        assert_eq!(manifest.algorithm, Algorithm::CHACHA20POLY1305);
+        assert_eq!(manifest.run_id, report.run_id);
+        assert_eq!(manifest.mac_addresses, report.host.mac_addresses);
+    }
+
     #[test]
     fn test_file_processor_set_public_key() {
         let mut cleanup = Cleanup::new();
@@ -579,10 +2128,36 @@ mod tests {
         let rsa = Rsa::generate(2048).unwrap();
         let public_key = rsa.public_key_to_pem().unwrap();
 
-        file_processor.set_public_key(Rsa::public_key_from_pem(&public_key).unwrap());
+        file_processor
+            .set_public_key(Rsa::public_key_from_pem(&public_key).unwrap())
+            .unwrap();
         assert!(
             file_processor.public_key.is_some(),
             "Public key was not set"
         );
     }
+
+    #[test]
+    fn test_file_processor_session_key_failure() {
+        let mut cleanup = Cleanup::new();
+
+        let report =
+            generate_test_report("test_file_processor_session_key_failure".to_string(), true);
+        cleanup.add(report.dir.clone());
+        let mut file_processor = FileProcessor::new(&report).unwrap();
+        let mut reporting_settings = Reporting::default();
+        reporting_settings.zip_archive.encryption.enabled = true;
+        // no session key can be generated for this algorithm
+        reporting_settings.zip_archive.encryption.algorithm = Algorithm::None;
+        file_processor.set_report_settings(reporting_settings);
+
+        let rsa = Rsa::generate(2048).unwrap();
+        let public_key = Rsa::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+        assert!(file_processor.set_public_key(public_key).is_err());
+        assert!(file_processor.session_key.is_none());
+
+        // the archive isn't passed off as encrypted
+        assert!(file_processor.finish().is_err());
+        assert!(!report.dir.join(report::ENCRYPTION_PATH).exists());
+    }
 }
@@ -0,0 +1,160 @@
+// Order of the entries of the archive. The stored files are written while the workflow runs,
+// before the metadata and the action output exist. With the entry order, they are spooled into
+// a separate archive and copied behind the report files once the collection is complete, so an
+// analyst can list and pull the small, high-value files first, e.g. from a partial download
+use config::workflow::ReportingEntryOrder;
+use report::{ACTION_LOG_DIR, LOOT_DIR};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Seek, Write};
+use std::path::{Path, PathBuf};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// Path of the archive the stored files are spooled to
+pub fn spool_path(zip_path: &Path) -> PathBuf {
+    zip_path.with_extension("zip.spool")
+}
+
+/// Sorts the files of the report directory: the metadata (and the other files in the root of
+/// the report) first, then the action output and the loot files, each by size
+pub fn sort_report_files(files: &mut [PathBuf], report_dir: &Path) {
+    files.sort_by_cached_key(|file| {
+        let relative = file.strip_prefix(report_dir).unwrap_or(file);
+        let rank = match relative {
+            path if path.starts_with(ACTION_LOG_DIR) => 1,
+            path if path.starts_with(LOOT_DIR) => 2,
+            _ => 0,
+        };
+        let size = fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+        (rank, size)
+    });
+}
+
+/// Copies the files of the spooled archive into the archive, the smaller ones first. Files larger
+/// than the large file size are added last, without compression and with their data aligned.
+/// Returns the number of copied files
+pub fn copy_spooled<W: Write + Seek>(
+    spool_path: &Path,
+    writer: &mut ZipWriter<W>,
+    order: &ReportingEntryOrder,
+    options: SimpleFileOptions,
+) -> Result<usize, Box<dyn Error>> {
+    let mut spool = ZipArchive::new(BufReader::new(File::open(spool_path)?))?;
+    let mut files = vec![];
+    for index in 0..spool.len() {
+        let entry = spool.by_index_raw(index)?;
+        // the directories already exist in the archive
+        if !entry.is_dir() {
+            files.push((entry.size() > order.large_file_size, entry.size(), index));
+        }
+    }
+    files.sort();
+
+    for (large, size, index) in &files {
+        let mut entry = spool.by_index(*index)?;
+        if !large {
+            writer.raw_copy_file(entry)?;
+            continue;
+        }
+        let name = entry.name().to_string();
+        let options = options
+            .compression_method(CompressionMethod::Stored)
+            .large_file(*size > u32::MAX as u64)
+            .with_alignment(order.alignment.max(1));
+        writer.start_file(name, options)?;
+        io::copy(&mut entry, writer)?;
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_copy_spooled() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_copy_spooled");
+        let zip_path = dir.join("report.zip");
+        let spool_path = spool_path(&zip_path);
+        assert_eq!(spool_path, dir.join("report.zip.spool"));
+
+        let mut spool = ZipWriter::new(File::create(&spool_path).unwrap());
+        let options = SimpleFileOptions::default();
+        spool.add_directory("stored_files", options).unwrap();
+        for (name, size) in [("large", 3000), ("medium", 200), ("small", 10)] {
+            spool
+                .start_file(format!("stored_files/{}", name), options)
+                .unwrap();
+            spool.write_all(&vec![b'a'; size]).unwrap();
+        }
+        spool.finish().unwrap();
+
+        let order = ReportingEntryOrder {
+            enabled: true,
+            large_file_size: 1000,
+            alignment: 512,
+        };
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        writer.add_directory("stored_files", options).unwrap();
+        assert_eq!(
+            copy_spooled(&spool_path, &mut writer, &order, options).unwrap(),
+            3
+        );
+        writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let names: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index_raw(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "stored_files/",
+                "stored_files/small",
+                "stored_files/medium",
+                "stored_files/large"
+            ]
+        );
+        let mut large = archive.by_name("stored_files/large").unwrap();
+        assert_eq!(large.compression(), CompressionMethod::Stored);
+        assert_eq!(large.data_start() % 512, 0);
+        let mut content = vec![];
+        large.read_to_end(&mut content).unwrap();
+        assert_eq!(content, vec![b'a'; 3000]);
+    }
+
+    #[test]
+    fn test_sort_report_files() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_sort_report_files");
+        let mut files = vec![];
+        for (name, size) in [
+            ("loot_files/big", 100),
+            ("loot_files/tiny", 1),
+            ("action_output/ps.log", 50),
+            ("metadata.csv", 70),
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, vec![b'a'; size]).unwrap();
+            files.push(path);
+        }
+        sort_report_files(&mut files, &dir);
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.strip_prefix(&dir).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Path::new("metadata.csv"),
+                Path::new("action_output/ps.log"),
+                Path::new("loot_files/tiny"),
+                Path::new("loot_files/big")
+            ]
+        );
+    }
+}
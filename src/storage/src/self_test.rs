@@ -0,0 +1,134 @@
+// Reads the finished archive back before it is encrypted. A corrupted archive is noticed while
+// the evidence can still be collected again, rather than once the report is analyzed
+use config::workflow::ArchiveSelfTest;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use zip::ZipArchive;
+
+// entries read in sample mode
+const SAMPLE_SIZE: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct SelfTestResult {
+    pub entries: usize,
+    // entries read back with a valid CRC
+    pub verified: usize,
+    pub errors: Vec<String>,
+}
+
+/// Indices of up to `size` entries spread evenly over the archive, including the first and the last
+fn sample_indices(len: usize, size: usize) -> Vec<usize> {
+    if len <= size {
+        return (0..len).collect();
+    }
+    let mut indices: Vec<usize> = (0..size).map(|i| i * (len - 1) / (size - 1)).collect();
+    indices.dedup();
+    indices
+}
+
+/// Walks the central directory, checks that all expected entries exist and
+/// verifies the CRC of the entries (all or a sample). Fails if the archive can't be opened
+pub fn verify_archive(
+    zip_path: &Path,
+    mode: ArchiveSelfTest,
+    expected: &[&str],
+) -> Result<SelfTestResult, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(zip_path)?))?;
+    let mut result = SelfTestResult {
+        entries: archive.len(),
+        ..Default::default()
+    };
+
+    let names: HashSet<&str> = archive.file_names().collect();
+    let missing: Vec<String> = expected
+        .iter()
+        .filter(|name| !names.contains(*name))
+        .map(|name| format!("Missing entry {}", name))
+        .collect();
+    result.errors.extend(missing);
+
+    let indices = match mode {
+        ArchiveSelfTest::Off => vec![],
+        ArchiveSelfTest::Sample => sample_indices(archive.len(), SAMPLE_SIZE),
+        ArchiveSelfTest::Full => (0..archive.len()).collect(),
+    };
+    for index in indices {
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                result.errors.push(format!("Entry {}: {}", index, e));
+                continue;
+            }
+        };
+        // the CRC is checked once the entry is read to its end
+        match io::copy(&mut entry, &mut io::sink()) {
+            Ok(size) if size == entry.size() => result.verified += 1,
+            Ok(size) => result.errors.push(format!(
+                "{}: read {} of {} bytes",
+                entry.name(),
+                size,
+                entry.size()
+            )),
+            Err(e) => result.errors.push(format!("{}: {}", entry.name(), e)),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use utils::tests::Cleanup;
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    #[test]
+    fn test_verify_archive() {
+        assert_eq!(sample_indices(3, 64), vec![0, 1, 2]);
+        assert_eq!(sample_indices(100, 3), vec![0, 49, 99]);
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_verify_archive");
+        let zip_path = dir.join("report.zip");
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, content) in [
+            ("stored_files/abc", "first file"),
+            ("metadata.csv", "original_path"),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let result =
+            verify_archive(&zip_path, ArchiveSelfTest::Full, &["stored_files/abc"]).unwrap();
+        assert_eq!(result.entries, 2);
+        assert_eq!(result.verified, 2);
+        assert!(result.errors.is_empty());
+
+        let result =
+            verify_archive(&zip_path, ArchiveSelfTest::Off, &["stored_files/def"]).unwrap();
+        assert_eq!(result.verified, 0);
+        assert_eq!(result.errors, vec!["Missing entry stored_files/def"]);
+
+        // flip a byte of the stored content, the central directory stays intact
+        let mut data = fs::read(&zip_path).unwrap();
+        let offset = data
+            .windows(10)
+            .position(|window| window == b"first file")
+            .unwrap();
+        data[offset] = b'F';
+        fs::write(&zip_path, data).unwrap();
+        let result = verify_archive(&zip_path, ArchiveSelfTest::Sample, &[]).unwrap();
+        assert_eq!(result.verified, 1);
+        assert!(result.errors[0].starts_with("stored_files/abc"));
+
+        fs::write(&zip_path, "not an archive").unwrap();
+        assert!(verify_archive(&zip_path, ArchiveSelfTest::Full, &[]).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+// Scans everything collected by a workflow once the collection is complete,
+// so one rule set covers the files stored by all actions
+use crate::FileMeta;
+use log::debug;
+use report::{ACTION_LOG_DIR, LOOT_DIR, STORAGE_DIR};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// A rule matching the content of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentMatch {
+    pub rule: String,
+    pub namespace: String,
+}
+
+/// Scans the content of the collected files, e.g. with YARA rules
+pub trait ContentScanner: std::fmt::Debug {
+    fn scan(&self, content: &[u8]) -> Result<Vec<ContentMatch>, String>;
+}
+
+/// One row of yara_sweep.csv, either a match or a file which could not be scanned
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SweepResult {
+    // name inside the archive, e.g. stored_files/[checksum]
+    pub entry: String,
+    pub original_path: String,
+    pub rule: String,
+    pub namespace: String,
+    pub error: Option<String>,
+}
+
+// only the files written by the actions are scanned, not the files of the report itself
+fn is_collected(entry: &str) -> bool {
+    [LOOT_DIR, STORAGE_DIR, ACTION_LOG_DIR]
+        .iter()
+        .any(|dir| entry.starts_with(&format!("{}/", dir)))
+}
+
+/// Maps the names inside the archive to the original paths of the stored files
+pub fn original_paths(records: &[FileMeta], loot_dir: &Path) -> HashMap<String, String> {
+    records
+        .iter()
+        .filter(|record| !record.unchanged && !record.deduplicated)
+        .map(|record| {
+            let path = Path::new(&record.original_path);
+            let entry = match (path.starts_with(loot_dir), path.file_name()) {
+                (true, Some(name)) => format!("{}/{}", LOOT_DIR, name.to_string_lossy()),
+                _ => format!("{}/{}", STORAGE_DIR, record.storage_name()),
+            };
+            (entry, record.original_path.clone())
+        })
+        .collect()
+}
+
+struct Sweep<'a> {
+    scanner: &'a dyn ContentScanner,
+    size_limit: u64,
+    report_dir: &'a Path,
+    original_paths: &'a HashMap<String, String>,
+    results: Vec<SweepResult>,
+}
+
+impl Sweep<'_> {
+    fn result(&self, entry: &str, error: Option<String>) -> SweepResult {
+        // files without a record (e.g. action outputs) are named by their path inside the report
+        let original_path = match self.original_paths.get(entry) {
+            Some(path) => path.clone(),
+            None => self.report_dir.join(entry).to_string_lossy().to_string(),
+        };
+        SweepResult {
+            entry: entry.to_string(),
+            original_path,
+            rule: "".to_string(),
+            namespace: "".to_string(),
+            error,
+        }
+    }
+
+    fn scan(&mut self, entry: &str, size: u64, reader: &mut dyn Read) {
+        if self.size_limit > 0 && size > self.size_limit {
+            debug!("Skipping {} in the YARA sweep: {} bytes", entry, size);
+            let error = format!("larger than the size limit of {} bytes", self.size_limit);
+            self.results.push(self.result(entry, Some(error)));
+            return;
+        }
+        let mut content = Vec::with_capacity(size as usize);
+        let matches = reader
+            .read_to_end(&mut content)
+            .map_err(|e| e.to_string())
+            .and_then(|_| self.scanner.scan(&content));
+        match matches {
+            Ok(matches) => {
+                for content_match in matches {
+                    let result = SweepResult {
+                        rule: content_match.rule,
+                        namespace: content_match.namespace,
+                        ..self.result(entry, None)
+                    };
+                    self.results.push(result);
+                }
+            }
+            Err(e) => self.results.push(self.result(entry, Some(e))),
+        }
+    }
+}
+
+impl Sweep<'_> {
+    /// Returns the names of the scanned entries
+    fn scan_archive(&mut self, zip_path: &Path) -> Result<HashSet<String>, Box<dyn Error>> {
+        let mut scanned = HashSet::new();
+        let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            let entry = file.name().to_string();
+            if file.is_dir() || !is_collected(&entry) {
+                continue;
+            }
+            let size = file.size();
+            self.scan(&entry, size, &mut file);
+            scanned.insert(entry);
+        }
+        Ok(scanned)
+    }
+
+    // files already scanned inside an archive are skipped
+    fn scan_directory(&mut self, skip: &HashSet<String>) {
+        let mut files = vec![];
+        for dir in [LOOT_DIR, STORAGE_DIR, ACTION_LOG_DIR] {
+            collect_files(&self.report_dir.join(dir), &mut files);
+        }
+        for path in files {
+            let entry = match path.strip_prefix(self.report_dir) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            if skip.contains(&entry) {
+                continue;
+            }
+            let size = path.metadata().map(|m| m.len()).unwrap_or(0);
+            match File::open(&path) {
+                Ok(mut file) => self.scan(&entry, size, &mut file),
+                Err(e) => self.results.push(self.result(&entry, Some(e.to_string()))),
+            }
+        }
+    }
+}
+
+/// Scans the collected files inside the finished archive
+pub fn sweep_archive(
+    zip_path: &Path,
+    scanner: &dyn ContentScanner,
+    size_limit: u64,
+    report_dir: &Path,
+    original_paths: &HashMap<String, String>,
+) -> Result<Vec<SweepResult>, Box<dyn Error>> {
+    let mut sweep = Sweep {
+        scanner,
+        size_limit,
+        report_dir,
+        original_paths,
+        results: vec![],
+    };
+    sweep.scan_archive(zip_path)?;
+    Ok(sweep.results)
+}
+
+/// Scans the files stored in the spool archive and the action outputs still inside the report
+/// directory, before the spooled files are copied into the archive
+pub fn sweep_spool(
+    spool_path: &Path,
+    scanner: &dyn ContentScanner,
+    size_limit: u64,
+    report_dir: &Path,
+    original_paths: &HashMap<String, String>,
+) -> Result<Vec<SweepResult>, Box<dyn Error>> {
+    let mut sweep = Sweep {
+        scanner,
+        size_limit,
+        report_dir,
+        original_paths,
+        results: vec![],
+    };
+    let spooled = sweep.scan_archive(spool_path)?;
+    sweep.scan_directory(&spooled);
+    Ok(sweep.results)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => collect_files(&path, files),
+            Ok(file_type) if file_type.is_file() => files.push(path),
+            _ => (),
+        }
+    }
+}
+
+/// Scans the collected files inside the report directory, if archiving is disabled
+pub fn sweep_directory(
+    scanner: &dyn ContentScanner,
+    size_limit: u64,
+    report_dir: &Path,
+    original_paths: &HashMap<String, String>,
+) -> Vec<SweepResult> {
+    let mut sweep = Sweep {
+        scanner,
+        size_limit,
+        report_dir,
+        original_paths,
+        results: vec![],
+    };
+    sweep.scan_directory(&HashSet::new());
+    sweep.results
+}
+
+pub fn write_sweep(results: &[SweepResult], path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for result in results {
+        writer.serialize(result)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use utils::tests::Cleanup;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    // matches every file containing "EVIL"
+    #[derive(Debug)]
+    struct MockScanner;
+
+    impl ContentScanner for MockScanner {
+        fn scan(&self, content: &[u8]) -> Result<Vec<ContentMatch>, String> {
+            if content.starts_with(b"BROKEN") {
+                return Err("scan failed".to_string());
+            }
+            let found = content.windows(4).any(|window| window == b"EVIL");
+            Ok(found
+                .then(|| ContentMatch {
+                    rule: "evil_string".to_string(),
+                    namespace: "malware".to_string(),
+                })
+                .into_iter()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_sweep_archive() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_sweep_archive");
+        let zip_path = dir.join("report.zip");
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        for (name, content) in [
+            ("stored_files/abc", "an EVIL file"),
+            ("stored_files/def", "a clean file"),
+            ("action_output/ps.txt", "EVIL process"),
+            ("loot_files/big.bin", "EVIL, but too large to be scanned"),
+            ("loot_files/broken.bin", "BROKEN"),
+            // not collected by an action
+            ("README.txt", "EVIL"),
+        ] {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let original_paths =
+            HashMap::from([("stored_files/abc".to_string(), "/etc/evil".to_string())]);
+        let results = sweep_archive(&zip_path, &MockScanner, 20, &dir, &original_paths).unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].entry, "stored_files/abc");
+        assert_eq!(results[0].original_path, "/etc/evil");
+        assert_eq!(results[0].rule, "evil_string");
+        assert_eq!(results[0].namespace, "malware");
+        assert_eq!(results[1].entry, "action_output/ps.txt");
+        assert_eq!(
+            results[1].original_path,
+            dir.join("action_output/ps.txt").to_string_lossy()
+        );
+        assert!(results[2].error.as_ref().unwrap().contains("size limit"));
+        assert_eq!(results[3].error.as_deref(), Some("scan failed"));
+
+        // the same files are found inside the report directory
+        let report_dir = cleanup.tmp_dir("test_sweep_directory");
+        fs::create_dir_all(report_dir.join(STORAGE_DIR)).unwrap();
+        fs::write(report_dir.join(STORAGE_DIR).join("abc"), "an EVIL file").unwrap();
+        fs::write(report_dir.join("README.txt"), "EVIL").unwrap();
+        let results = sweep_directory(&MockScanner, 0, &report_dir, &original_paths);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].original_path, "/etc/evil");
+
+        let csv_path = dir.join("yara_sweep.csv");
+        write_sweep(&results, &csv_path).unwrap();
+        assert!(fs::read_to_string(&csv_path)
+            .unwrap()
+            .starts_with("entry,original_path,rule,namespace,error\n"));
+    }
+}
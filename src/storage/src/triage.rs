@@ -0,0 +1,439 @@
+// Ranks the collected files by the rules of a triage file, so the analyst knows where to look first
+use crate::FileMeta;
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use utils::pattern::{expand_braces, normalize_pattern};
+
+// only the beginning of a file is read to calculate its entropy
+const ENTROPY_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+/// Conditions of a rule, all set conditions have to match
+#[derive(Debug, Deserialize, Clone)]
+pub struct TriageRule {
+    pub name: String,
+    // 0 (informational) to 100 (critical)
+    pub severity: u32,
+    // glob patterns matched against the original path of a file
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // minimum Shannon entropy (0.0 to 8.0 bits per byte), e.g. 7.5 for packed or encrypted content
+    #[serde(default)]
+    pub min_entropy: Option<f64>,
+    // executable (PE) without an embedded Authenticode signature
+    #[serde(default)]
+    pub unsigned: bool,
+    // matched by a YARA rule of one of the namespaces, any namespace if it contains "*"
+    #[serde(default)]
+    pub yara_namespaces: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TriageRules {
+    rules: Vec<TriageRule>,
+}
+
+/// Finding reported by a parser plugin for a stored file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ParserFinding {
+    // set by the toolkit to the name of the parser
+    #[serde(default)]
+    pub parser: String,
+    pub name: String,
+    // 0 (informational) to 100 (critical)
+    pub severity: u32,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub path: String,
+    // highest severity of the matched rules
+    pub severity: u32,
+    pub level: String,
+    pub rules: Vec<String>,
+    pub sha1_checksum: String,
+    pub entropy: Option<f64>,
+    pub signed: Option<bool>,
+    pub yara_namespaces: Vec<String>,
+    pub parser_findings: Vec<ParserFinding>,
+}
+
+pub fn severity_level(severity: u32) -> &'static str {
+    match severity {
+        90.. => "critical",
+        70..=89 => "high",
+        40..=69 => "medium",
+        1..=39 => "low",
+        0 => "info",
+    }
+}
+
+pub fn read_rules(path: &Path) -> Result<Vec<TriageRule>, Box<dyn Error>> {
+    let rules: TriageRules = serde_yaml::from_reader(File::open(path)?)?;
+    for rule in &rules.rules {
+        let has_condition = !rule.paths.is_empty()
+            || rule.min_entropy.is_some()
+            || rule.unsigned
+            || !rule.yara_namespaces.is_empty();
+        if !has_condition {
+            return Err(format!("Triage rule {:?} has no conditions", rule.name).into());
+        }
+        if rule.severity > 100 {
+            return Err(format!(
+                "Triage rule {:?}: severity must be between 0 and 100",
+                rule.name
+            )
+            .into());
+        }
+        for pattern in &rule.paths {
+            for expanded in expand_braces(pattern) {
+                Pattern::new(&normalize_pattern(&expanded))?;
+            }
+        }
+    }
+    Ok(rules.rules)
+}
+
+/// Shannon entropy in bits per byte
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn file_entropy(path: &Path) -> Option<f64> {
+    let mut data = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(ENTROPY_SAMPLE_SIZE)
+        .read_to_end(&mut data)
+        .ok()?;
+    Some((entropy(&data) * 100.0).round() / 100.0)
+}
+
+/// Whether a PE file has an embedded signature (security data directory), None if it is no PE file
+pub fn pe_signed(data: &[u8]) -> Option<bool> {
+    let u16_at = |offset: usize| -> Option<u16> {
+        Some(u16::from_le_bytes(
+            data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    if data.get(0..2)? != b"MZ" {
+        return None;
+    }
+    let pe_offset = u32_at(0x3C)? as usize;
+    if data.get(pe_offset..pe_offset + 4)? != b"PE\0\0" {
+        return None;
+    }
+    // the optional header follows the 20 byte file header
+    let optional_header = pe_offset + 24;
+    let data_directories = match u16_at(optional_header)? {
+        0x10b => optional_header + 96,
+        0x20b => optional_header + 112,
+        _ => return None,
+    };
+    // the security directory is the fifth entry (address and size)
+    let security_size = u32_at(data_directories + 4 * 8 + 4)?;
+    Some(security_size > 0)
+}
+
+fn file_signed(path: &Path) -> Option<bool> {
+    // the headers are within the first pages
+    let mut data = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(4096)
+        .read_to_end(&mut data)
+        .ok()?;
+    pe_signed(&data)
+}
+
+#[derive(Debug)]
+pub struct Triage {
+    rules: Vec<TriageRule>,
+    // expanded and compiled path patterns per rule
+    patterns: Vec<Vec<Pattern>>,
+}
+
+impl Triage {
+    pub fn new(rules: Vec<TriageRule>) -> Self {
+        let patterns = rules
+            .iter()
+            .map(|rule| {
+                rule.paths
+                    .iter()
+                    .flat_map(|pattern| expand_braces(pattern))
+                    .filter_map(|pattern| Pattern::new(&normalize_pattern(&pattern)).ok())
+                    .collect()
+            })
+            .collect();
+        Self { rules, patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluates the rules for each file and returns the findings, the most severe first.
+    /// Content based conditions are checked against the original file, as the stored copy may be encrypted.
+    /// Files with findings of parser plugins are findings even if no rule matches
+    pub fn evaluate(
+        &self,
+        records: &[FileMeta],
+        yara_matches: &HashMap<String, BTreeSet<String>>,
+        parser_findings: &HashMap<String, Vec<ParserFinding>>,
+    ) -> Vec<Finding> {
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+
+        // files matched by YARA may not have been stored
+        let mut paths: Vec<(String, String)> = records
+            .iter()
+            .map(|record| (record.original_path.clone(), record.sha1_checksum.clone()))
+            .collect();
+        for path in yara_matches.keys().chain(parser_findings.keys()) {
+            if !paths.iter().any(|(known, _)| known == path) {
+                paths.push((path.clone(), String::new()));
+            }
+        }
+
+        let mut findings: Vec<Finding> = vec![];
+        for (original_path, sha1_checksum) in paths {
+            let path = original_path
+                .strip_prefix("\\\\?\\")
+                .unwrap_or(&original_path)
+                .replace('\\', "/");
+            let namespaces = yara_matches.get(&original_path);
+            // read lazily, only if a rule needs them
+            let mut entropy: Option<Option<f64>> = None;
+            let mut signed: Option<Option<bool>> = None;
+
+            let mut matched: Vec<&TriageRule> = vec![];
+            for (rule, patterns) in self.rules.iter().zip(&self.patterns) {
+                if !rule.paths.is_empty()
+                    && !patterns
+                        .iter()
+                        .any(|pattern| pattern.matches_with(&path, options))
+                {
+                    continue;
+                }
+                if !rule.yara_namespaces.is_empty() {
+                    let hit = namespaces.is_some_and(|namespaces| {
+                        rule.yara_namespaces
+                            .iter()
+                            .any(|namespace| namespace == "*" || namespaces.contains(namespace))
+                    });
+                    if !hit {
+                        continue;
+                    }
+                }
+                if let Some(min_entropy) = rule.min_entropy {
+                    let value =
+                        *entropy.get_or_insert_with(|| file_entropy(Path::new(&original_path)));
+                    if !value.is_some_and(|value| value >= min_entropy) {
+                        continue;
+                    }
+                }
+                if rule.unsigned {
+                    let value =
+                        *signed.get_or_insert_with(|| file_signed(Path::new(&original_path)));
+                    if value != Some(false) {
+                        continue;
+                    }
+                }
+                matched.push(rule);
+            }
+
+            let parsed = parser_findings
+                .get(&original_path)
+                .cloned()
+                .unwrap_or_default();
+            if matched.is_empty() && parsed.is_empty() {
+                continue;
+            }
+            let severity = matched
+                .iter()
+                .map(|rule| rule.severity)
+                .chain(parsed.iter().map(|finding| finding.severity))
+                .max()
+                .unwrap_or(0);
+            findings.push(Finding {
+                path: original_path,
+                severity,
+                level: severity_level(severity).to_string(),
+                rules: matched.iter().map(|rule| rule.name.clone()).collect(),
+                sha1_checksum,
+                entropy: entropy.flatten(),
+                signed: signed.flatten(),
+                yara_namespaces: namespaces
+                    .map(|namespaces| namespaces.iter().cloned().collect())
+                    .unwrap_or_default(),
+                parser_findings: parsed,
+            });
+        }
+
+        // files matching several rules come first among those with the same severity
+        findings.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then(b.rules.len().cmp(&a.rules.len()))
+                .then(a.path.cmp(&b.path))
+        });
+        findings
+    }
+}
+
+pub fn write_findings(findings: &[Finding], path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string_pretty(findings)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    fn record(path: &str) -> FileMeta {
+        FileMeta {
+            original_path: path.to_string(),
+            modified_time: "".to_string(),
+            accessed_time: "".to_string(),
+            created_time: "".to_string(),
+            sha1_checksum: "".to_string(),
+            path_checksum: "".to_string(),
+            size: 0,
+            comment: None,
+            unchanged: false,
+            content_addressed: false,
+            deduplicated: false,
+            link_target: None,
+            link_group: None,
+            hard_link_of: None,
+            sparse_extents: None,
+            extra: None,
+            run_id: "".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_entropy_and_signature() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(b"aaaa"), 0.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(entropy(&all_bytes), 8.0);
+
+        // minimal PE32+ headers with and without a security directory
+        let mut pe = vec![0u8; 0x200];
+        pe[0..2].copy_from_slice(b"MZ");
+        pe[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        pe[0x98..0x9A].copy_from_slice(&0x20bu16.to_le_bytes());
+        assert_eq!(pe_signed(&pe), Some(false));
+        let security_size = 0x98 + 112 + 4 * 8 + 4;
+        pe[security_size..security_size + 4].copy_from_slice(&0x100u32.to_le_bytes());
+        assert_eq!(pe_signed(&pe), Some(true));
+        assert_eq!(pe_signed(b"#!/bin/sh"), None);
+    }
+
+    #[test]
+    fn test_triage_evaluate() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_triage_evaluate");
+        let rules_path = dir.join("rules.yaml");
+        fs::write(
+            &rules_path,
+            r#"
+rules:
+  - name: Script in temp
+    severity: 60
+    paths: ["**/{Temp,tmp}/**/*.{ps1,sh}"]
+  - name: Packed
+    severity: 50
+    min_entropy: 7.5
+  - name: Malware
+    severity: 95
+    yara_namespaces: ["malware"]
+"#,
+        )
+        .unwrap();
+        let rules = read_rules(&rules_path).unwrap();
+        let triage = Triage::new(rules);
+
+        let random: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let packed = dir.join("tmp").join("x").join("packed.sh");
+        fs::create_dir_all(packed.parent().unwrap()).unwrap();
+        fs::write(&packed, &random).unwrap();
+        let packed = packed.to_string_lossy().to_string();
+
+        let records = vec![
+            record(&packed),
+            record("C:\\Users\\john\\AppData\\Local\\Temp\\run.PS1"),
+            record("/home/john/notes.txt"),
+        ];
+        let mut yara_matches = HashMap::new();
+        yara_matches.insert(
+            "/opt/implant".to_string(),
+            BTreeSet::from(["malware".to_string()]),
+        );
+
+        // the findings of parser plugins are merged, even without a matching rule
+        let mut parser_findings = HashMap::new();
+        parser_findings.insert(
+            "/home/john/notes.txt".to_string(),
+            vec![ParserFinding {
+                parser: "notes".to_string(),
+                name: "Credentials".to_string(),
+                severity: 70,
+                description: "Password in plaintext".to_string(),
+            }],
+        );
+
+        let findings = triage.evaluate(&records, &yara_matches, &parser_findings);
+        let paths: Vec<&str> = findings.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/opt/implant",
+                "/home/john/notes.txt",
+                packed.as_str(),
+                "C:\\Users\\john\\AppData\\Local\\Temp\\run.PS1"
+            ]
+        );
+        assert_eq!(findings[0].level, "critical");
+        assert!(findings[1].rules.is_empty());
+        assert_eq!(findings[1].level, "high");
+        assert_eq!(findings[1].parser_findings[0].parser, "notes");
+        assert_eq!(findings[2].rules, vec!["Script in temp", "Packed"]);
+        assert_eq!(findings[2].entropy, Some(8.0));
+
+        fs::write(&rules_path, "rules:\n  - name: empty\n    severity: 10\n").unwrap();
+        assert!(read_rules(&rules_path).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+// Native checks used by the launch conditions.
+// They don't rely on parsing command output, which is localized on many systems.
+use log::debug;
+
+/// Compares process names case-insensitively and ignores the ".exe" extension
+pub fn process_name_matches(candidate: &str, name: &str) -> bool {
+    let strip = |s: &str| {
+        let lower = s.trim().to_lowercase();
+        match lower.strip_suffix(".exe") {
+            Some(stripped) => stripped.to_string(),
+            None => lower,
+        }
+    };
+    strip(candidate) == strip(name)
+}
+
+/// Splits a registry path (e.g. HKLM\SOFTWARE\Microsoft) into the
+/// canonical name of the root key and the sub key
+pub fn split_registry_key(key: &str) -> Option<(&'static str, String)> {
+    let key = key.replace('/', "\\");
+    let (root, sub_key) = match key.split_once('\\') {
+        Some((root, sub_key)) => (root.to_string(), sub_key.to_string()),
+        None => (key.clone(), String::new()),
+    };
+    let root = match root.to_uppercase().as_str() {
+        "HKLM" | "HKEY_LOCAL_MACHINE" => "HKEY_LOCAL_MACHINE",
+        "HKCU" | "HKEY_CURRENT_USER" => "HKEY_CURRENT_USER",
+        "HKU" | "HKEY_USERS" => "HKEY_USERS",
+        "HKCR" | "HKEY_CLASSES_ROOT" => "HKEY_CLASSES_ROOT",
+        "HKCC" | "HKEY_CURRENT_CONFIG" => "HKEY_CURRENT_CONFIG",
+        _ => return None,
+    };
+    Some((root, sub_key.trim_matches('\\').to_string()))
+}
+
+#[cfg(target_os = "windows")]
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(target_os = "windows")]
+fn from_wide(s: &[u16]) -> String {
+    let len = s.iter().position(|&c| c == 0).unwrap_or(s.len());
+    String::from_utf16_lossy(&s[..len])
+}
+
+/// Check if a service with the given name is installed
+#[cfg(target_os = "windows")]
+pub fn service_exists(name: &str) -> bool {
+    use std::ptr::null;
+    use winapi::um::winsvc::{
+        CloseServiceHandle, OpenSCManagerW, OpenServiceW, SC_MANAGER_CONNECT, SERVICE_QUERY_STATUS,
+    };
+
+    unsafe {
+        let manager = OpenSCManagerW(null(), null(), SC_MANAGER_CONNECT);
+        if manager.is_null() {
+            debug!("Failed to connect to the service control manager");
+            return false;
+        }
+        let service = OpenServiceW(manager, to_wide(name).as_ptr(), SERVICE_QUERY_STATUS);
+        let exists = !service.is_null();
+        if exists {
+            CloseServiceHandle(service);
+        }
+        CloseServiceHandle(manager);
+        exists
+    }
+}
+
+/// Check if a service with the given name is installed
+#[cfg(not(target_os = "windows"))]
+pub fn service_exists(name: &str) -> bool {
+    use std::path::PathBuf;
+
+    // systemd units, SysV init scripts and launchd jobs
+    let mut candidates: Vec<PathBuf> = vec![];
+    for dir in [
+        "/etc/systemd/system",
+        "/run/systemd/system",
+        "/lib/systemd/system",
+        "/usr/lib/systemd/system",
+    ] {
+        candidates.push(PathBuf::from(dir).join(name));
+        candidates.push(PathBuf::from(dir).join(format!("{}.service", name)));
+    }
+    candidates.push(PathBuf::from("/etc/init.d").join(name));
+    for dir in [
+        "/Library/LaunchDaemons",
+        "/Library/LaunchAgents",
+        "/System/Library/LaunchDaemons",
+        "/System/Library/LaunchAgents",
+    ] {
+        candidates.push(PathBuf::from(dir).join(format!("{}.plist", name)));
+    }
+
+    candidates.iter().any(|path| {
+        let exists = path.exists();
+        if exists {
+            debug!("Found service {:?} at {:?}", name, path.display());
+        }
+        exists
+    })
+}
+
+/// Check if a process with the given name is running
+#[cfg(target_os = "windows")]
+pub fn process_running(name: &str) -> bool {
+    use std::mem::{size_of, zeroed};
+    use winapi::um::{
+        handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+        tlhelp32::{
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+            TH32CS_SNAPPROCESS,
+        },
+    };
+
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            debug!("Failed to create process snapshot");
+            return false;
+        }
+
+        let mut entry: PROCESSENTRY32W = zeroed();
+        entry.dwSize = size_of::<PROCESSENTRY32W>() as u32;
+
+        let mut found = false;
+        let mut next = Process32FirstW(snapshot, &mut entry);
+        while next != 0 {
+            if process_name_matches(&from_wide(&entry.szExeFile), name) {
+                found = true;
+                break;
+            }
+            next = Process32NextW(snapshot, &mut entry);
+        }
+
+        CloseHandle(snapshot);
+        found
+    }
+}
+
+/// Check if a process with the given name is running
+#[cfg(target_os = "linux")]
+pub fn process_running(name: &str) -> bool {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Failed to read /proc: {}", e);
+            return false;
+        }
+    };
+
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        // the comm file is truncated to 15 characters, so prefer the first argument of the cmdline
+        let cmdline = std::fs::read(path.join("cmdline")).unwrap_or_default();
+        let first_arg = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+        let first_arg = String::from_utf8_lossy(first_arg);
+        let exe_name = first_arg.rsplit('/').next().unwrap_or_default();
+        if !exe_name.is_empty() && process_name_matches(exe_name, name) {
+            return true;
+        }
+        match std::fs::read_to_string(path.join("comm")) {
+            Ok(comm) => process_name_matches(&comm, name),
+            Err(_) => false,
+        }
+    })
+}
+
+/// Check if a process with the given name is running
+#[cfg(target_os = "macos")]
+pub fn process_running(name: &str) -> bool {
+    use std::ffi::CStr;
+
+    unsafe {
+        let count = libc::proc_listallpids(std::ptr::null_mut(), 0);
+        if count <= 0 {
+            return false;
+        }
+        // reserve some space for processes started in the meantime
+        let mut pids: Vec<libc::c_int> = vec![0; count as usize + 64];
+        let count = libc::proc_listallpids(
+            pids.as_mut_ptr() as *mut libc::c_void,
+            (pids.len() * std::mem::size_of::<libc::c_int>()) as libc::c_int,
+        );
+        if count <= 0 {
+            return false;
+        }
+
+        pids.iter().take(count as usize).any(|&pid| {
+            let mut buffer = [0u8; 256];
+            let len = libc::proc_name(
+                pid,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len() as u32,
+            );
+            if len <= 0 {
+                return false;
+            }
+            match CStr::from_bytes_until_nul(&buffer) {
+                Ok(proc_name) => process_name_matches(&proc_name.to_string_lossy(), name),
+                Err(_) => false,
+            }
+        })
+    }
+}
+
+/// Check if a process with the given name is running
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+pub fn process_running(_name: &str) -> bool {
+    false
+}
+
+/// Read a registry value as string. Returns None if the key or value does not exist
+#[cfg(target_os = "windows")]
+pub fn registry_value(key: &str, value: &str) -> Option<String> {
+    use std::ptr::null_mut;
+    use winapi::{
+        shared::{minwindef::HKEY, winerror::ERROR_SUCCESS},
+        um::{
+            winnt::{REG_DWORD, REG_EXPAND_SZ, REG_MULTI_SZ, REG_QWORD, REG_SZ},
+            winreg::{
+                RegGetValueW, HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG, HKEY_CURRENT_USER,
+                HKEY_LOCAL_MACHINE, HKEY_USERS, RRF_NOEXPAND, RRF_RT_ANY,
+            },
+        },
+    };
+
+    let (root, sub_key) = split_registry_key(key)?;
+    let root: HKEY = match root {
+        "HKEY_LOCAL_MACHINE" => HKEY_LOCAL_MACHINE,
+        "HKEY_CURRENT_USER" => HKEY_CURRENT_USER,
+        "HKEY_USERS" => HKEY_USERS,
+        "HKEY_CLASSES_ROOT" => HKEY_CLASSES_ROOT,
+        _ => HKEY_CURRENT_CONFIG,
+    };
+    let sub_key = to_wide(&sub_key);
+    let value = to_wide(value);
+
+    unsafe {
+        // query the size first
+        let mut value_type = 0;
+        let mut size = 0;
+        let status = RegGetValueW(
+            root,
+            sub_key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_ANY | RRF_NOEXPAND,
+            &mut value_type,
+            null_mut(),
+            &mut size,
+        );
+        if status != ERROR_SUCCESS as i32 {
+            return None;
+        }
+
+        let mut data = vec![0u8; size as usize];
+        let status = RegGetValueW(
+            root,
+            sub_key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_ANY | RRF_NOEXPAND,
+            &mut value_type,
+            data.as_mut_ptr() as *mut _,
+            &mut size,
+        );
+        if status != ERROR_SUCCESS as i32 {
+            return None;
+        }
+        data.truncate(size as usize);
+
+        let wide: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Some(match value_type {
+            REG_SZ | REG_EXPAND_SZ => from_wide(&wide),
+            REG_MULTI_SZ => wide
+                .split(|&c| c == 0)
+                .filter(|s| !s.is_empty())
+                .map(String::from_utf16_lossy)
+                .collect::<Vec<String>>()
+                .join("\n"),
+            REG_DWORD if data.len() >= 4 => {
+                u32::from_le_bytes([data[0], data[1], data[2], data[3]]).to_string()
+            }
+            REG_QWORD if data.len() >= 8 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&data[..8]);
+                u64::from_le_bytes(bytes).to_string()
+            }
+            _ => data.iter().map(|b| format!("{:02x}", b)).collect(),
+        })
+    }
+}
+
+/// Read a registry value as string. Returns None if the key or value does not exist
+#[cfg(not(target_os = "windows"))]
+pub fn registry_value(key: &str, _value: &str) -> Option<String> {
+    debug!("Registry key {:?} can only be queried on Windows", key);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_name_matches() {
+        assert!(process_name_matches("MsMpEng.exe", "msmpeng"));
+        assert!(process_name_matches("sshd\n", "sshd"));
+        assert!(!process_name_matches("sshd-session", "sshd"));
+    }
+
+    #[test]
+    fn test_split_registry_key() {
+        assert_eq!(
+            split_registry_key("HKLM\\SOFTWARE\\Microsoft\\"),
+            Some(("HKEY_LOCAL_MACHINE", "SOFTWARE\\Microsoft".to_string()))
+        );
+        assert_eq!(
+            split_registry_key("hkey_current_user/Software"),
+            Some(("HKEY_CURRENT_USER", "Software".to_string()))
+        );
+        assert_eq!(split_registry_key("HKXX\\Software"), None);
+    }
+
+    #[test]
+    fn test_process_running() {
+        assert!(!process_running("this-process-does-not-exist"));
+
+        // the test binary itself is always running
+        #[cfg(target_os = "linux")]
+        {
+            let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+            assert!(process_running(&comm));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+// Identity of the endpoint on the network, e.g. for the inventory of an engagement
+use chrono::{DateTime, Utc};
+use std::net::{IpAddr, UdpSocket};
+
+/// Network context and state of the endpoint, collected once at startup
+#[derive(Debug, Clone, Default)]
+pub struct HostContext {
+    pub ip_addresses: Vec<String>,
+    pub mac_addresses: Vec<String>,
+    pub domain: Option<String>,
+    pub boot_time: Option<DateTime<Utc>>,
+    // BCP 47 tag, e.g. de-DE
+    pub locale: String,
+}
+
+impl HostContext {
+    pub fn collect() -> Self {
+        Self {
+            ip_addresses: ip_addresses(),
+            mac_addresses: mac_addresses(),
+            domain: domain(),
+            boot_time: boot_time(),
+            locale: locale(),
+        }
+    }
+
+    /// Seconds since the boot, None if the boot time is unknown
+    pub fn uptime(&self) -> Option<i64> {
+        self.boot_time
+            .map(|boot_time| (Utc::now() - boot_time).num_seconds().max(0))
+    }
+}
+
+/// Addresses of the interfaces holding the default routes (IPv4 and IPv6).
+/// Connecting a UDP socket only selects the route, no packet is sent
+pub fn ip_addresses() -> Vec<String> {
+    let mut addresses = vec![];
+    for (bind, target) in [("0.0.0.0:0", "192.0.2.1:9"), ("[::]:0", "[2001:db8::1]:9")] {
+        let address = UdpSocket::bind(bind)
+            .and_then(|socket| socket.connect(target).map(|_| socket))
+            .and_then(|socket| socket.local_addr());
+        if let Ok(address) = address {
+            if !address.ip().is_unspecified() && !address.ip().is_loopback() {
+                addresses.push(address.ip());
+            }
+        }
+    }
+    addresses.dedup();
+    addresses.iter().map(IpAddr::to_string).collect()
+}
+
+/// DNS domain the endpoint belongs to, None if it isn't part of one
+#[cfg(target_os = "windows")]
+pub fn domain() -> Option<String> {
+    crate::checks::registry_value(
+        "HKLM\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters",
+        "Domain",
+    )
+    .or_else(|| std::env::var("USERDNSDOMAIN").ok())
+    .filter(|domain| !domain.is_empty())
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn domain() -> Option<String> {
+    let resolv_conf = std::fs::read_to_string("/etc/resolv.conf").ok()?;
+    parse_resolv_domain(&resolv_conf)
+}
+
+// the domain entry, otherwise the first search domain (as the resolver does)
+#[cfg(not(target_os = "windows"))]
+fn parse_resolv_domain(text: &str) -> Option<String> {
+    let mut search = None;
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some("domain"), Some(domain)) => return Some(domain.to_string()),
+            (Some("search"), Some(domain)) if search.is_none() => search = Some(domain.to_string()),
+            _ => {}
+        }
+    }
+    search.filter(|domain| domain != ".")
+}
+
+// lowercase and separated by colons, None for anything else than a MAC address (e.g. "N/A")
+fn normalize_mac(mac: &str) -> Option<String> {
+    let mac = mac
+        .trim()
+        .trim_matches('"')
+        .to_lowercase()
+        .replace('-', ":");
+    let octets: Vec<&str> = mac.split(':').collect();
+    let valid = octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()));
+    match valid && mac != "00:00:00:00:00:00" {
+        true => Some(mac),
+        false => None,
+    }
+}
+
+fn unique_macs(macs: impl Iterator<Item = String>) -> Vec<String> {
+    let mut unique: Vec<String> = vec![];
+    for mac in macs.filter_map(|mac| normalize_mac(&mac)) {
+        if !unique.contains(&mac) {
+            unique.push(mac);
+        }
+    }
+    unique
+}
+
+/// Hardware addresses of the network interfaces, without loopback
+#[cfg(target_os = "linux")]
+pub fn mac_addresses() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return vec![];
+    };
+    let mut interfaces: Vec<_> = entries.filter_map(Result::ok).collect();
+    interfaces.sort_by_key(|entry| entry.file_name());
+    unique_macs(
+        interfaces
+            .iter()
+            .filter_map(|entry| std::fs::read_to_string(entry.path().join("address")).ok()),
+    )
+}
+
+#[cfg(target_os = "macos")]
+pub fn mac_addresses() -> Vec<String> {
+    let output = std::process::Command::new("ifconfig").output();
+    match output {
+        Ok(output) => parse_ifconfig_macs(&String::from_utf8_lossy(&output.stdout)),
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn mac_addresses() -> Vec<String> {
+    // the first column of "getmac /fo csv /nh" is the address, the text of the others is localized
+    let output = std::process::Command::new("getmac")
+        .args(["/fo", "csv", "/nh"])
+        .output();
+    match output {
+        Ok(output) => unique_macs(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split(',').next().map(str::to_string)),
+        ),
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub fn mac_addresses() -> Vec<String> {
+    vec![]
+}
+
+#[cfg(any(target_os = "macos", test))]
+fn parse_ifconfig_macs(text: &str) -> Vec<String> {
+    unique_macs(text.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some("ether"), Some(mac)) => Some(mac.to_string()),
+            _ => None,
+        }
+    }))
+}
+
+/// Time of the last boot
+#[cfg(target_os = "linux")]
+pub fn boot_time() -> Option<DateTime<Utc>> {
+    parse_proc_stat_btime(&std::fs::read_to_string("/proc/stat").ok()?)
+}
+
+#[cfg(target_os = "macos")]
+pub fn boot_time() -> Option<DateTime<Utc>> {
+    let output = std::process::Command::new("sysctl")
+        .args(["-n", "kern.boottime"])
+        .output()
+        .ok()?;
+    parse_kern_boottime(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(target_os = "windows")]
+pub fn boot_time() -> Option<DateTime<Utc>> {
+    // milliseconds since the boot
+    let uptime = unsafe { winapi::um::sysinfoapi::GetTickCount64() };
+    Some(Utc::now() - chrono::Duration::milliseconds(uptime as i64))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub fn boot_time() -> Option<DateTime<Utc>> {
+    None
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_stat_btime(text: &str) -> Option<DateTime<Utc>> {
+    let seconds = text
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    DateTime::from_timestamp(seconds, 0)
+}
+
+// e.g. "{ sec = 1718000000, usec = 123456 } Mon Jun 10 08:13:20 2024"
+#[cfg(any(target_os = "macos", test))]
+fn parse_kern_boottime(text: &str) -> Option<DateTime<Utc>> {
+    let seconds = text
+        .split_once("sec = ")?
+        .1
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    DateTime::from_timestamp(seconds, 0)
+}
+
+/// Locale of the user, e.g. en-US. Empty if unknown
+#[cfg(target_os = "windows")]
+pub fn locale() -> String {
+    use winapi::um::winnls::GetUserDefaultLocaleName;
+
+    // LOCALE_NAME_MAX_LENGTH
+    let mut name = [0u16; 85];
+    let len = unsafe { GetUserDefaultLocaleName(name.as_mut_ptr(), name.len() as i32) };
+    match len > 0 {
+        true => String::from_utf16_lossy(&name[..len as usize - 1]),
+        false => String::new(),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn locale() -> String {
+    let from_env = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find_map(|value| normalize_locale(&value));
+    if let Some(locale) = from_env {
+        return locale;
+    }
+    // applications started from the Finder have no LANG
+    #[cfg(target_os = "macos")]
+    if let Ok(output) = std::process::Command::new("defaults")
+        .args(["read", "-g", "AppleLocale"])
+        .output()
+    {
+        if let Some(locale) = normalize_locale(&String::from_utf8_lossy(&output.stdout)) {
+            return locale;
+        }
+    }
+    String::new()
+}
+
+// de_DE.UTF-8@euro -> de-DE, the C and POSIX locales don't name a language
+#[cfg(not(target_os = "windows"))]
+fn normalize_locale(value: &str) -> Option<String> {
+    let locale = value.trim().split(['.', '@']).next()?.replace('_', "-");
+    match locale.as_str() {
+        "" | "C" | "POSIX" => None,
+        _ => Some(locale),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_parse_resolv_domain() {
+        let text = "# generated\nnameserver 10.0.0.1\nsearch corp.example.com example.com\n";
+        assert_eq!(
+            parse_resolv_domain(text).as_deref(),
+            Some("corp.example.com")
+        );
+        let text = "search other.example.com\ndomain corp.example.com\n";
+        assert_eq!(
+            parse_resolv_domain(text).as_deref(),
+            Some("corp.example.com")
+        );
+        assert_eq!(parse_resolv_domain("nameserver 10.0.0.1\n"), None);
+        assert_eq!(parse_resolv_domain("search .\n"), None);
+    }
+
+    #[test]
+    fn test_parse_host_context() {
+        let ifconfig = "lo0: flags=8049<UP,LOOPBACK>\n\tinet 127.0.0.1\nen0: flags=8863<UP>\n\tether A4:83:E7:01:02:03\n\tinet 10.0.0.5\nen1: flags=8863<UP>\n\tether a4:83:e7:01:02:03\n";
+        assert_eq!(parse_ifconfig_macs(ifconfig), vec!["a4:83:e7:01:02:03"]);
+        assert_eq!(
+            normalize_mac("\"00-15-5D-0A-0B-0C\"").as_deref(),
+            Some("00:15:5d:0a:0b:0c")
+        );
+        assert_eq!(normalize_mac("N/A"), None);
+        assert_eq!(normalize_mac("00:00:00:00:00:00"), None);
+
+        let boot_time = parse_proc_stat_btime("cpu  1 2 3\nbtime 1718000000\nprocesses 42\n");
+        assert_eq!(boot_time.unwrap().timestamp(), 1718000000);
+        let boot_time =
+            parse_kern_boottime("{ sec = 1718000000, usec = 123456 } Mon Jun 10 06:13:20 2024");
+        assert_eq!(boot_time.unwrap().timestamp(), 1718000000);
+        assert!(parse_kern_boottime("unknown").is_none());
+
+        let host = HostContext {
+            boot_time: Some(Utc::now() - chrono::Duration::seconds(90)),
+            ..Default::default()
+        };
+        assert!(host.uptime().unwrap() >= 90);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_normalize_locale() {
+        assert_eq!(
+            normalize_locale("de_DE.UTF-8@euro").as_deref(),
+            Some("de-DE")
+        );
+        assert_eq!(normalize_locale("en_US").as_deref(), Some("en-US"));
+        assert_eq!(normalize_locale("C.UTF-8"), None);
+        assert_eq!(normalize_locale("POSIX"), None);
+    }
+
+    #[test]
+    fn test_ip_addresses() {
+        for address in ip_addresses() {
+            assert!(address.parse::<IpAddr>().is_ok());
+        }
+    }
+}
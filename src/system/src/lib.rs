@@ -1,12 +1,23 @@
-use core::panic;
 use dirs;
+use endpoint::HostContext;
 use privileges::is_elevated;
-use std::{collections::HashMap, fmt, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 use whoami;
 
+pub mod checks;
+pub mod endpoint;
+pub mod profiles;
+
 pub const CUSTOM_FILES_DIR: &str = "custom_files";
 
 #[derive(Debug, Clone)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct SystemVariables {
     pub os: String,
     pub arch: String,
@@ -18,6 +29,9 @@ pub struct SystemVariables {
     pub user: String,
     pub loot_directory: PathBuf,
     pub custom_files_directory: PathBuf,
+    // set for each report, see report::Report::run_id
+    pub run_id: String,
+    pub host: HostContext,
 }
 
 impl SystemVariables {
@@ -36,6 +50,8 @@ impl SystemVariables {
             user: whoami::username(),
             loot_directory: PathBuf::new(),
             custom_files_directory: custom_files_directory,
+            run_id: String::new(),
+            host: HostContext::collect(),
         }
     }
 
@@ -64,8 +80,35 @@ impl SystemVariables {
             "CUSTOM_FILES_DIR".to_string(),
             self.custom_files_directory.to_string_lossy().to_string(),
         );
+        map.insert("RUN_ID".to_string(), self.run_id.clone());
         map.insert("OS".to_string(), self.os.clone());
         map.insert("ARCH".to_string(), self.arch.clone());
+        // lists are separated by commas
+        map.insert("IP_ADDRESSES".to_string(), self.host.ip_addresses.join(","));
+        map.insert(
+            "MAC_ADDRESSES".to_string(),
+            self.host.mac_addresses.join(","),
+        );
+        map.insert(
+            "DOMAIN".to_string(),
+            self.host.domain.clone().unwrap_or_default(),
+        );
+        map.insert(
+            "BOOT_TIME".to_string(),
+            self.host
+                .boot_time
+                .map(|boot_time| boot_time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                .unwrap_or_default(),
+        );
+        // in seconds
+        map.insert(
+            "UPTIME".to_string(),
+            self.host
+                .uptime()
+                .map(|uptime| uptime.to_string())
+                .unwrap_or_default(),
+        );
+        map.insert("LOCALE".to_string(), self.host.locale.clone());
         map
     }
 }
@@ -74,6 +117,7 @@ impl fmt::Display for SystemVariables {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut map = self.as_map();
         map.remove("LOOT_DIR");
+        map.remove("RUN_ID");
 
         write!(f, "System Variables:\n")?;
         for (key, value) in map {
@@ -94,16 +138,60 @@ fn get_user_home() -> PathBuf {
 // possible bin subdirectories (windows, macos, linux)
 const BIN_SUBDIRS: [&str; 3] = ["windows", "macos", "linux"];
 
-/// Returns the base path where this application stores its data
+/// Environment variable overriding the base path, e.g. for a toolkit pushed into a temporary folder
+pub const BASE_PATH_ENV: &str = "IRT_BASE_PATH";
+
+static BASE_PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Overrides the base path (e.g. from --base-path), takes precedence over IRT_BASE_PATH.
+/// Has to be called before the base path is used for the first time
+pub fn set_base_path(path: PathBuf) {
+    let path = std::path::absolute(&path).unwrap_or(path);
+    if BASE_PATH_OVERRIDE.set(path).is_err() {
+        eprintln!("The base path has already been set");
+    }
+}
+
+/// Returns the base path where this application stores its data:
+/// --base-path -> IRT_BASE_PATH -> derived from the location of the binary -> directory of the binary
 pub fn get_base_path() -> PathBuf {
+    BASE_PATH_OVERRIDE
+        .get_or_init(|| {
+            if let Some(path) = std::env::var_os(BASE_PATH_ENV).filter(|path| !path.is_empty()) {
+                let path = PathBuf::from(path);
+                return std::path::absolute(&path).unwrap_or(path);
+            }
+            match try_get_base_path() {
+                Ok(path) => path,
+                Err(e) => {
+                    // portable use from any directory instead of failing
+                    let fallback = std::env::current_exe()
+                        .ok()
+                        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+                        .unwrap_or_default();
+                    eprintln!(
+                        "{}. Using {} as base path, set --base-path or {} to change it",
+                        e,
+                        fallback.display(),
+                        BASE_PATH_ENV
+                    );
+                    fallback
+                }
+            }
+        })
+        .clone()
+}
+
+/// Same as get_base_path, but returns an error if the binary is not placed inside the /bin directory
+pub fn try_get_base_path() -> Result<PathBuf, String> {
     // get current exe and retun the parent dir of it
-    let current_exe = match std::env::current_exe() {
-        Ok(path) => path,
-        Err(e) => {
-            panic!("Error getting current exe: {}", e);
-        }
-    };
+    let current_exe =
+        std::env::current_exe().map_err(|e| format!("Error getting current exe: {}", e))?;
+    base_path_of(&current_exe)
+}
 
+/// Derives the base path from the location of the executable
+pub fn base_path_of(current_exe: &Path) -> Result<PathBuf, String> {
     // remove the filename from the path
     let current_path = match current_exe.parent() {
         Some(path) => path.to_path_buf(),
@@ -115,24 +203,30 @@ pub fn get_base_path() -> PathBuf {
         None => PathBuf::new(),
     };
 
+    let dir_name = |path: &Path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
     // if we are inside the bin directory (or its subdirectories), we are in production mode
     // .../bin
-    if current_path.file_name().unwrap() == "bin" {
-        return parent_dir;
+    if dir_name(&current_path) == "bin" {
+        Ok(parent_dir)
     }
     // if we are inside the bin subdirectories, we are in production mode
     // .../bin/windows
-    else if parent_dir.file_name().unwrap() == "bin"
-        && BIN_SUBDIRS.contains(&current_path.file_name().unwrap().to_str().unwrap())
+    else if dir_name(&parent_dir) == "bin"
+        && BIN_SUBDIRS.contains(&dir_name(&current_path).as_str())
     {
         let mut parent_dir = parent_dir.clone();
         // .../bin
         parent_dir.pop();
         // .../
-        return parent_dir;
+        Ok(parent_dir)
     }
     // check if test
-    else if current_path.file_name().unwrap() == "deps" {
+    else if dir_name(&current_path) == "deps" {
         // we fake the base path by returning the output directory in the project root
         let mut parent_dir = parent_dir.clone();
         // .../target/debug
@@ -142,25 +236,25 @@ pub fn get_base_path() -> PathBuf {
         // .../
         parent_dir.push("output");
         // .../output
-        return parent_dir;
+        Ok(parent_dir)
     }
     // we are in debug mode
     // we fake the base path by returning the output directory in the project root
-    else if current_path.file_name().unwrap() == "debug" {
+    else if dir_name(&current_path) == "debug" {
         let mut parent_dir = parent_dir.clone();
         // .../target
         parent_dir.pop();
         // .../
         parent_dir.push("output");
         // .../output
-        return parent_dir;
+        Ok(parent_dir)
     } else {
-        // no idea where we are, panic
-        panic!("Unknown directory structure. Make sure the application is inside the /bin directory for production");
+        // no idea where we are
+        Err("Unknown directory structure. Make sure the application is inside the /bin directory for production".to_string())
     }
 }
 
-fn get_arch() -> String {
+pub fn get_arch() -> String {
     #[cfg(target_arch = "x86")]
     return "x86".to_string();
 
@@ -198,7 +292,7 @@ fn get_arch() -> String {
     return "wasm64".to_string();
 }
 
-fn get_os() -> String {
+pub fn get_os() -> String {
     let machine_kind = if cfg!(windows) {
         "windows"
     } else if cfg!(target_os = "macos") {
@@ -222,6 +316,22 @@ mod tests {
         assert!(base_path.exists());
     }
 
+    #[test]
+    fn test_base_path_of() {
+        let root = PathBuf::from("/opt/ir-toolkit");
+        assert_eq!(
+            base_path_of(&root.join("bin").join("collector")),
+            Ok(root.clone())
+        );
+        assert_eq!(
+            base_path_of(&root.join("bin").join("linux").join("collector")),
+            Ok(root.clone())
+        );
+        assert!(base_path_of(&root.join("collector")).is_err());
+        assert!(base_path_of(&root.join("bin").join("other").join("collector")).is_err());
+        assert!(base_path_of(Path::new("collector")).is_err());
+    }
+
     #[test]
     fn test_get_user_home() {
         let user_home = get_user_home();
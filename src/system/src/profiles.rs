@@ -0,0 +1,301 @@
+// Enumeration of the local user profiles, used to run a step once per user
+use log::debug;
+use std::{collections::HashMap, path::PathBuf};
+
+// Name of the loopable variable for the for_each property of a step
+pub const USER_PROFILES: &str = "USER_PROFILES";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserProfile {
+    pub name: String,
+    pub home: PathBuf,
+    // SID on Windows, uid on Linux and macOS
+    pub id: String,
+    pub last_logon: Option<String>,
+}
+
+impl UserProfile {
+    /// Variables available to a step iterating over the user profiles
+    pub fn as_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        map.insert("PROFILE_NAME".to_string(), self.name.clone());
+        map.insert(
+            "PROFILE_HOME".to_string(),
+            self.home.to_string_lossy().to_string(),
+        );
+        map.insert("PROFILE_ID".to_string(), self.id.clone());
+        map.insert(
+            "PROFILE_LAST_LOGON".to_string(),
+            self.last_logon.clone().unwrap_or_default(),
+        );
+        map
+    }
+}
+
+// System accounts have a home directory as well, only keep interactive users
+fn is_login_shell(shell: &str) -> bool {
+    !shell.ends_with("nologin") && !shell.ends_with("/false") && !shell.ends_with("/sync")
+}
+
+/// Parse /etc/passwd. Returns root and all regular users (uid >= 1000)
+pub fn parse_passwd(text: &str) -> Vec<UserProfile> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            let uid: u32 = fields[2].parse().ok()?;
+            // 65534 is nobody
+            if (uid != 0 && uid < 1000) || uid == 65534 || !is_login_shell(fields[6]) {
+                return None;
+            }
+            Some(UserProfile {
+                name: fields[0].to_string(),
+                home: PathBuf::from(fields[5]),
+                id: uid.to_string(),
+                last_logon: None,
+            })
+        })
+        .collect()
+}
+
+/// Parse the output of "lastlog -u <user>". The columns Port and From may be
+/// empty, so the date is read from the position of the Latest header.
+pub fn parse_lastlog(text: &str) -> Option<String> {
+    let mut lines = text.lines();
+    let offset = lines.next()?.find("Latest")?;
+    let line = lines.next()?;
+    if line.contains("**Never logged in**") {
+        return None;
+    }
+    let latest = line.get(offset..)?.trim();
+    match latest.is_empty() {
+        true => None,
+        false => Some(latest.to_string()),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    match std::process::Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        }
+        Ok(output) => {
+            debug!("{} {:?} failed: {}", program, args, output.status);
+            None
+        }
+        Err(e) => {
+            debug!("Failed to run {}: {}", program, e);
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn enumerate_profiles() -> Vec<UserProfile> {
+    let passwd = match std::fs::read_to_string("/etc/passwd") {
+        Ok(passwd) => passwd,
+        Err(e) => {
+            debug!("Failed to read /etc/passwd: {}", e);
+            return vec![];
+        }
+    };
+    parse_passwd(&passwd)
+        .into_iter()
+        .map(|mut profile| {
+            profile.last_logon = command_output("lastlog", &["-u", &profile.name])
+                .and_then(|output| parse_lastlog(&output));
+            profile
+        })
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+fn enumerate_profiles() -> Vec<UserProfile> {
+    // lines of "<name> <uid>"
+    let users = match command_output("dscl", &[".", "-list", "/Users", "UniqueID"]) {
+        Some(users) => users,
+        None => return vec![],
+    };
+    users
+        .lines()
+        .filter_map(|line| {
+            let (name, uid) = line.split_once(char::is_whitespace)?;
+            let uid: u32 = uid.trim().parse().ok()?;
+            // service accounts start with an underscore and use low uids
+            if name.starts_with('_') || (uid != 0 && uid < 500) {
+                return None;
+            }
+            let record = format!("/Users/{}", name);
+            let home = command_output("dscl", &[".", "-read", &record, "NFSHomeDirectory"])?;
+            let home = home.split_once(':')?.1.trim();
+            Some(UserProfile {
+                name: name.to_string(),
+                home: PathBuf::from(home),
+                id: uid.to_string(),
+                last_logon: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn enumerate_profiles() -> Vec<UserProfile> {
+    use crate::checks::registry_value;
+    use std::ptr::null_mut;
+    use winapi::{
+        shared::{
+            minwindef::{DWORD, HKEY},
+            winerror::ERROR_SUCCESS,
+        },
+        um::{
+            winnt::KEY_READ,
+            winreg::{RegCloseKey, RegEnumKeyExW, RegOpenKeyExW, HKEY_LOCAL_MACHINE},
+        },
+    };
+
+    const PROFILE_LIST: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\ProfileList";
+
+    let mut sids = vec![];
+    unsafe {
+        let path: Vec<u16> = PROFILE_LIST
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut key: HKEY = null_mut();
+        if RegOpenKeyExW(HKEY_LOCAL_MACHINE, path.as_ptr(), 0, KEY_READ, &mut key) as DWORD
+            != ERROR_SUCCESS
+        {
+            debug!("Failed to open {}", PROFILE_LIST);
+            return vec![];
+        }
+        let mut buffer = [0u16; 256];
+        loop {
+            let mut len = buffer.len() as DWORD;
+            let status = RegEnumKeyExW(
+                key,
+                sids.len() as DWORD,
+                buffer.as_mut_ptr(),
+                &mut len,
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+            );
+            if status as DWORD != ERROR_SUCCESS {
+                break;
+            }
+            sids.push(String::from_utf16_lossy(&buffer[..len as usize]));
+        }
+        RegCloseKey(key);
+    }
+
+    sids.into_iter()
+        // local and domain accounts, this skips SYSTEM, LocalService and NetworkService
+        .filter(|sid| sid.starts_with("S-1-5-21-"))
+        .filter_map(|sid| {
+            let key = format!("HKLM\\{}\\{}", PROFILE_LIST, sid);
+            let home = expand_env(&registry_value(&key, "ProfileImagePath")?);
+            let name = PathBuf::from(&home)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let high = registry_value(&key, "LocalProfileLoadTimeHigh");
+            let low = registry_value(&key, "LocalProfileLoadTimeLow");
+            Some(UserProfile {
+                name,
+                home: PathBuf::from(home),
+                id: sid,
+                last_logon: filetime_from_parts(high, low),
+            })
+        })
+        .collect()
+}
+
+// ProfileImagePath is stored unexpanded, e.g. %SystemDrive%\Users\john
+#[cfg(target_os = "windows")]
+fn expand_env(value: &str) -> String {
+    let mut expanded = value.to_string();
+    for (key, val) in std::env::vars() {
+        let pattern = format!("%{}%", key);
+        if let Some(pos) = expanded.to_lowercase().find(&pattern.to_lowercase()) {
+            expanded.replace_range(pos..pos + pattern.len(), &val);
+        }
+    }
+    expanded
+}
+
+/// Combine the two DWORD halves of a FILETIME to an RFC 3339 timestamp
+pub fn filetime_from_parts(high: Option<String>, low: Option<String>) -> Option<String> {
+    let high: u64 = high?.parse().ok()?;
+    let low: u64 = low?.parse().ok()?;
+    let filetime = (high << 32) | low;
+    // 100ns intervals since 1601-01-01
+    let unix = filetime.checked_sub(116_444_736_000_000_000)?;
+    chrono::DateTime::from_timestamp((unix / 10_000_000) as i64, 0)
+        .map(|date| date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+fn enumerate_profiles() -> Vec<UserProfile> {
+    vec![]
+}
+
+/// Returns all local user profiles with an existing home directory
+pub fn get_user_profiles() -> Vec<UserProfile> {
+    enumerate_profiles()
+        .into_iter()
+        .filter(|profile| profile.home.is_dir())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_passwd() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+            daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin\n\
+            # comment\n\
+            john:x:1000:1000:John,,,:/home/john:/bin/zsh\n\
+            svc:x:1001:1001::/srv/svc:/bin/false\n\
+            nobody:x:65534:65534:nobody:/nonexistent:/bin/sh\n\
+            broken:x:1002\n";
+        let profiles = parse_passwd(passwd);
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].name, "root");
+        assert_eq!(profiles[1].home, PathBuf::from("/home/john"));
+        assert_eq!(profiles[1].id, "1000");
+    }
+
+    #[test]
+    fn test_parse_lastlog() {
+        let output = "Username         Port     From             Latest\n\
+            john             pts/0    10.0.0.1         Mon Oct  5 10:00:00 +0000 2026\n";
+        assert_eq!(
+            parse_lastlog(output),
+            Some("Mon Oct  5 10:00:00 +0000 2026".to_string())
+        );
+        let never = "Username         Port     From             Latest\n\
+            john                                       **Never logged in**\n";
+        assert_eq!(parse_lastlog(never), None);
+    }
+
+    #[test]
+    fn test_filetime_from_parts() {
+        // 2024-01-01T00:00:00Z
+        let filetime: u64 = 133_485_408_000_000_000;
+        assert_eq!(
+            filetime_from_parts(
+                Some((filetime >> 32).to_string()),
+                Some((filetime & 0xffff_ffff).to_string())
+            ),
+            Some("2024-01-01T00:00:00Z".to_string())
+        );
+        assert_eq!(filetime_from_parts(None, Some("1".to_string())), None);
+    }
+}
@@ -1,70 +1,220 @@
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
 use config::config::Time;
-use log::{debug, error};
-use std::sync::mpsc;
+use log::{debug, error, info};
+use serde::Serialize;
+use std::sync::{mpsc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-pub fn get_ntp_time(time_config: Time) -> Option<DateTime<Utc>> {
+// seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+// result of the last query, e.g. for the time drift record of each report
+static LAST_RESULT: Mutex<Option<NtpResult>> = Mutex::new(None);
+
+/// Response of a single NTP server
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ServerOffset {
+    pub server: String,
+    // time of the server minus the local time
+    pub offset_ms: Option<i64>,
+    pub round_trip_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Time of the NTP servers compared with the local clock
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NtpResult {
+    // local UTC time when the servers were queried
+    pub local_time: DateTime<Utc>,
+    // local time corrected by the chosen offset
+    pub ntp_time: DateTime<Utc>,
+    // median of the offsets of all responding servers
+    pub offset_ms: i64,
+    pub servers: Vec<ServerOffset>,
+}
+
+/// Queries all servers concurrently and chooses the median offset of the servers which
+/// responded within ntp_timeout. Each server is asked again until it responds or the timeout is reached
+pub fn query_ntp(time_config: &Time) -> Option<NtpResult> {
     let (tx, rx) = mpsc::channel();
-    let servers = time_config.ntp_servers;
-    let timeout_secs = Duration::from_secs(time_config.ntp_timeout);
+    let timeout = Duration::from_secs(time_config.ntp_timeout);
 
-    thread::spawn(move || {
-        for server in servers {
+    for server in time_config.ntp_servers.clone() {
+        let tx = tx.clone();
+        thread::spawn(move || {
             debug!("Requesting NTP time from server: {}", server);
             let server_start = Instant::now();
-
-            while server_start.elapsed() < timeout_secs {
-                match request_ntp_time(&server) {
-                    Ok(ntp_time) => {
-                        tx.send(Some(ntp_time)).unwrap();
-                        return;
-                    }
-                    Err(e) => {
-                        error!("Error contacting NTP server {}: {}", server, e);
+            let result = loop {
+                match request_offset(&server) {
+                    Ok(offset) => break Ok(offset),
+                    // without a timeout, each server is asked once
+                    Err(e) if timeout.is_zero() || server_start.elapsed() >= timeout => {
+                        break Err(e)
                     }
+                    Err(e) => debug!("Error contacting NTP server {}: {}", server, e),
                 }
                 // Short sleep to avoid busy waiting
                 thread::sleep(Duration::from_millis(100));
-            }
+            };
+            // the receiver is gone once the timeout is reached
+            let _ = tx.send((server, result));
+        });
+    }
+    drop(tx);
 
+    let local_time = Utc::now();
+    let deadline = Instant::now() + timeout;
+    let mut servers = vec![];
+    loop {
+        let response = match timeout.is_zero() {
+            true => rx.recv().ok(),
+            false => rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+        };
+        let (server, result) = match response {
+            Some(response) => response,
+            None => break,
+        };
+        servers.push(match result {
+            Ok((offset_ms, round_trip_ms)) => ServerOffset {
+                server,
+                offset_ms: Some(offset_ms),
+                round_trip_ms: Some(round_trip_ms),
+                error: None,
+            },
+            Err(e) => {
+                error!("Error contacting NTP server {}: {}", server, e);
+                ServerOffset {
+                    server,
+                    offset_ms: None,
+                    round_trip_ms: None,
+                    error: Some(e),
+                }
+            }
+        });
+    }
+    for server in &time_config.ntp_servers {
+        if !servers.iter().any(|response| &response.server == server) {
             error!("NTP request to server {} timed out", server);
+            servers.push(ServerOffset {
+                server: server.clone(),
+                offset_ms: None,
+                round_trip_ms: None,
+                error: Some("Timed out".to_string()),
+            });
         }
-        tx.send(None).unwrap();
-    });
+    }
 
-    // Main thread waits for a response
-    match rx.recv() {
-        Ok(ntp_time) => ntp_time,
-        Err(_) => {
+    let offset_ms = match median_offset(&servers) {
+        Some(offset_ms) => offset_ms,
+        None => {
             error!("Failed to receive NTP time");
-            None
+            return None;
         }
+    };
+    let result = NtpResult {
+        local_time,
+        ntp_time: local_time + ChronoDuration::milliseconds(offset_ms),
+        offset_ms,
+        servers,
+    };
+    info!("Clock offset to NTP: {} ms", offset_ms);
+    if let Ok(mut last_result) = LAST_RESULT.lock() {
+        *last_result = Some(result.clone());
     }
+    Some(result)
 }
 
-fn request_ntp_time(server: &str) -> Result<DateTime<Utc>, String> {
-    match ntp::request(server) {
-        Ok(response) => {
-            let ntp_time = response.transmit_time;
-            let mut unix_time = ntp_time.sec as i64 - 2_208_988_800; // 70 years in seconds
-
-            // Normalize the frac value to be within the valid range
-            let mut frac = ntp_time.frac as i64;
-            if frac >= 1_000_000_000 {
-                let extra_seconds = frac / 1_000_000_000;
-                frac = frac % 1_000_000_000;
-                unix_time += extra_seconds;
-            }
+pub fn get_ntp_time(time_config: Time) -> Option<DateTime<Utc>> {
+    query_ntp(&time_config).map(|result| result.ntp_time)
+}
 
-            let ntp_time = Utc.timestamp_opt(unix_time, frac as u32).single();
-            if let Some(ntp_time) = ntp_time {
-                Ok(ntp_time)
-            } else {
-                Err("Failed to convert NTP time to DateTime<Utc>".to_string())
-            }
+/// Result of the last successful query, None if NTP is disabled or no server responded
+pub fn last_ntp_result() -> Option<NtpResult> {
+    LAST_RESULT.lock().ok()?.clone()
+}
+
+// a single server with a wrong time doesn't shift the chosen offset
+fn median_offset(servers: &[ServerOffset]) -> Option<i64> {
+    let mut offsets: Vec<i64> = servers
+        .iter()
+        .filter_map(|server| server.offset_ms)
+        .collect();
+    if offsets.is_empty() {
+        return None;
+    }
+    offsets.sort();
+    let middle = offsets.len() / 2;
+    Some(match offsets.len() % 2 {
+        0 => (offsets[middle - 1] + offsets[middle]) / 2,
+        _ => offsets[middle],
+    })
+}
+
+fn ntp_to_utc(time: ntp::formats::timestamp::TimestampFormat) -> Option<DateTime<Utc>> {
+    let unix_time = time.sec as i64 - NTP_UNIX_OFFSET;
+    // the fraction is in units of 2^-32 seconds
+    let nanos = ((time.frac as u64 * 1_000_000_000) >> 32) as u32;
+    Utc.timestamp_opt(unix_time, nanos).single()
+}
+
+// offset of the server to the local clock and the round trip time in milliseconds
+fn request_offset(server: &str) -> Result<(i64, u64), String> {
+    let sent = Utc::now();
+    let start = Instant::now();
+    let response = ntp::request(server).map_err(|e| format!("Error: {}", e))?;
+    let round_trip = start.elapsed();
+    let server_time =
+        ntp_to_utc(response.transmit_time).ok_or("Failed to convert NTP time to DateTime<Utc>")?;
+    // the server answered halfway through the round trip
+    let local_time = sent + ChronoDuration::from_std(round_trip / 2).unwrap_or_default();
+    Ok((
+        (server_time - local_time).num_milliseconds(),
+        round_trip.as_millis() as u64,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(server: &str, offset_ms: Option<i64>) -> ServerOffset {
+        ServerOffset {
+            server: server.to_string(),
+            offset_ms,
+            round_trip_ms: offset_ms.map(|_| 20),
+            error: None,
         }
-        Err(e) => Err(format!("Error: {}", e)),
+    }
+
+    #[test]
+    fn test_median_offset() {
+        assert_eq!(median_offset(&[]), None);
+        assert_eq!(median_offset(&[response("a", None)]), None);
+        let servers = [
+            response("a", Some(120)),
+            response("b", None),
+            response("c", Some(-3000)),
+            response("d", Some(100)),
+        ];
+        assert_eq!(median_offset(&servers), Some(100));
+        assert_eq!(median_offset(&servers[..2]), Some(120));
+        assert_eq!(median_offset(&servers[1..]), Some(-1450));
+    }
+
+    #[test]
+    fn test_unreachable_servers() {
+        let time_config = Time {
+            time_zone: "UTC".to_string(),
+            ntp_enabled: true,
+            ntp_servers: vec!["invalid.invalid:123".to_string(), "".to_string()],
+            ntp_timeout: 1,
+        };
+        let start = Instant::now();
+        assert_eq!(query_ntp(&time_config), None);
+        // the servers are queried concurrently
+        assert!(start.elapsed() < Duration::from_secs(3));
     }
 }
@@ -1,19 +1,34 @@
 mod unpacker_tests;
 use clap::{Arg, ArgAction, Command};
 use config::workflow::Algorithm;
-use crypto::{decrypt_evidence, get_file_sha1, get_metadata, load_private_key, EncryptionMeta};
+use crypto::{
+    check_chunks, decrypt_evidence, decrypt_output_file, encrypted_output_path, get_file_sha1,
+    get_metadata, get_reader_sha1, interrupted_encryptions, load_private_key, load_public_key,
+    read_encryption_journal, reencrypt_evidence, salvage_evidence, verify_evidence, EncryptionMeta,
+    EvidenceReader, EvidenceWriter, ReencryptingReader, SessionKey, ENCRYPTED_OUTPUT_EXTENSION,
+};
 use log::{debug, error, info, warn, LevelFilter};
 use logging::Logger;
-use report::{ENCRYPTION_PATH, METADATA_PATH, STORAGE_DIR};
+use report::layout::{comment_metadata_sha1, read_layout, LAYOUT_PATH};
+use report::{
+    ACTION_LOG_DIR, COLLECTOR_LOG_PATH, ENCRYPTION_PATH, MANIFEST_PATH, METADATA_PATH,
+    SEVEN_ZIP_PATH, STORAGE_DIR, TAR_ZSTD_PATH, TOOLKIT_VERSION,
+};
 use std::{
+    collections::HashMap,
     fs,
-    io::Read,
+    io::{self, BufReader, Read, Seek, Write},
     path::{Path, PathBuf},
     vec,
 };
-use storage::{read_metadata, FileMeta};
+use storage::{
+    allocation::parse_extents,
+    container::{is_7z_file, is_zstd_file, open_tar_zstd, read_7z, read_tar_zstd, write_tar_zstd},
+    read_metadata, read_metadata_from_reader, FileMeta, Manifest,
+};
+use tar::EntryType;
 use utils::sanitize::sanitize_dirname;
-use zip::ZipArchive;
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
 
 fn main() {
     let matches = get_command().get_matches();
@@ -23,6 +38,8 @@ fn main() {
             true => LevelFilter::Debug,
             false => LevelFilter::Info,
         })
+        // stdout is reserved for the archive
+        .set_console_stderr(matches.get_flag("stdout"))
         .apply();
 
     if let Err(e) = run(matches) {
@@ -35,7 +52,7 @@ fn main() {
 
 fn get_command() -> Command {
     Command::new("Unpacker")
-        .version("1.0")
+        .version(TOOLKIT_VERSION)
         .about("Unpacks an encrypted archive")
         .arg(
             Arg::new("input")
@@ -66,6 +83,13 @@ fn get_command() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Restore the stored files with their original names")
         )
+        .arg(
+            Arg::new("sparse")
+                .long("sparse")
+                .action(ArgAction::SetTrue)
+                .requires("restore")
+                .help("Restore sparse files with their holes, so they take no more space than the original files")
+        )
         .arg(
             Arg::new("verify")
                 .long("verify")
@@ -73,6 +97,27 @@ fn get_command() -> Command {
                 .default_value("true")
                 .help("Verify the checksums of the metadata file")
         )
+        .arg(
+            Arg::new("verify_only")
+                .long("verify-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("restore")
+                .help("Only verify the checksums of the stored files inside the (decrypted) archive without extracting it")
+        )
+        .arg(
+            Arg::new("stdout")
+                .long("stdout")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["output", "restore", "verify_only"])
+                .help("Write the decrypted archive to stdout instead of unpacking it, e.g. to pipe it into other tools. No decrypted copy is written to disk")
+        )
+        .arg(
+            Arg::new("entry")
+                .long("entry")
+                .value_name("ENTRY")
+                .requires("stdout")
+                .help("Only write this entry of the archive to stdout, e.g. stored_files/[checksum]")
+        )
         .arg(
             Arg::new("verbose")
                 .short('v')
@@ -80,9 +125,49 @@ fn get_command() -> Command {
                 .help("Enables verbose logging")
                 .action(clap::ArgAction::SetTrue),
         )
+        // the input of the subcommand is given to the subcommand
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("reencrypt")
+                .about("Encrypts the archive for another recipient without writing it decrypted to disk, e.g. to forward it")
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .value_name("INPUT")
+                        .required(true)
+                        .help("The report directory containing the encrypted archive"),
+                )
+                .arg(
+                    Arg::new("private_key")
+                        .short('k')
+                        .long("private")
+                        .value_name("PRIVATE_KEY")
+                        .required(true)
+                        .help("The private key to decrypt the archive"),
+                )
+                .arg(
+                    Arg::new("recipient")
+                        .long("recipient")
+                        .value_name("PUBLIC_KEY")
+                        .required(true)
+                        .help("The public key of the recipient"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .help("The directory of the re-encrypted report. Defaults to the report directory with the suffix _reencrypted"),
+                ),
+        )
 }
 
 pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
+    if let Some(("reencrypt", sub_matches)) = matches.subcommand() {
+        return reencrypt(sub_matches);
+    }
+
     let report_dir: PathBuf = PathBuf::from(matches.get_one::<String>("input").unwrap());
     if !report_dir.exists() {
         return Err(format!(
@@ -92,7 +177,8 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
     }
 
     // Check if the report was archived or not
-    let archive_path = Path::new(&report_dir).join(report::ZIP_PATH);
+    let archive_path = find_archive(&report_dir);
+    let container = Container::of(&archive_path);
     let storage_dir = Path::new(&report_dir).join(STORAGE_DIR);
 
     // if both exist or does not exist, it is an error
@@ -105,10 +191,14 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
     }
 
     let is_archived = archive_path.exists();
+    if !is_archived {
+        check_layout_file(&report_dir.join(LAYOUT_PATH))?;
+    }
 
     // if is_archived, we expect the "encryption.json" to exist
     let mut encryption_metadata = EncryptionMeta::default();
     if is_archived {
+        check_interrupted_encryption(&report_dir)?;
         let meta_path = Path::new(&report_dir).join(ENCRYPTION_PATH);
         if !meta_path.exists() {
             return Err(format!(
@@ -120,11 +210,71 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
             .map_err(|e| format!("Failed to read metadata file {:?}: {}", ENCRYPTION_PATH, e))?;
     }
 
+    // the private key is required for the archive and the encrypted action outputs
+    let private_key_file = || -> Result<PathBuf, String> {
+        let private_key_file = matches
+            .get_one::<String>("private_key")
+            .ok_or("No private key specified")?;
+        if !Path::new(&private_key_file).exists() {
+            return Err(format!(
+                "Private key file {:?} does not exist",
+                private_key_file
+            ));
+        }
+        Ok(PathBuf::from(&private_key_file))
+    };
+
+    // the archive is decrypted while it is written to stdout
+    if matches.get_flag("stdout") {
+        if !is_archived {
+            return Err(
+                "The report is not archived, its files can be read from the report directory"
+                    .to_string(),
+            );
+        }
+        let encrypted = encryption_metadata.algorithm != Algorithm::None
+            && !is_decrypted_archive(&archive_path);
+        let key = match encrypted {
+            true => {
+                let private_key = load_private_key(private_key_file()?)
+                    .map_err(|e| format!("Failed to load private key: {}", e))?;
+                let key = SessionKey::decrypt(
+                    &private_key,
+                    encryption_metadata.algorithm,
+                    &encryption_metadata.encrypted_key,
+                )
+                .map_err(|e| format!("Failed to decrypt session key: {}", e))?;
+                Some(key)
+            }
+            false => None,
+        };
+        let entry = matches.get_one::<String>("entry").map(String::as_str);
+        return stream_archive(
+            &archive_path,
+            &encryption_metadata,
+            key,
+            entry,
+            io::stdout().lock(),
+        );
+    }
+
+    // nothing is extracted, the files are verified inside the archive
+    let verify_only = matches.get_flag("verify_only");
+    if verify_only && is_archived && container != Container::Zip {
+        return Err(format!(
+            "--verify-only can't read {:?}: extract the report and use --verify instead",
+            archive_path.file_name().unwrap_or_default()
+        ));
+    }
+    if verify_only && matches.get_one::<String>("output").is_some() {
+        warn!("Output directory will be ignored because nothing is extracted");
+    }
+
     // Determine the output directory
     // - if archived && user supplied an output directory -> use it
     // - if archived && not user supplied -> create new directory inside the report directory
     // - if not archived -> ignore the user supplied output directory
-    let output_path: PathBuf = if is_archived {
+    let output_path: PathBuf = if is_archived && !verify_only {
         let path = match matches.get_one::<String>("output") {
             Some(output) => PathBuf::from(output),
             None => Path::new(&report_dir).join("output"),
@@ -138,7 +288,7 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
         path
     } else {
         // warn the user that the output directory will be ignored
-        if matches.get_one::<String>("output").is_some() {
+        if matches.get_one::<String>("output").is_some() && !verify_only {
             warn!("Output directory will be ignored because the report is not archived");
         }
         report_dir.clone()
@@ -149,7 +299,7 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
     // So we have to check if the file magic is correct
     let already_decrypted = is_archived
         && encryption_metadata.algorithm != Algorithm::None
-        && is_valid_zip_archive(&archive_path);
+        && is_decrypted_archive(&archive_path);
 
     if already_decrypted {
         warn!("The archive has already been decrypted: skipping decryption");
@@ -157,43 +307,111 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
 
     // check if decryption is needed
     if !already_decrypted && is_archived && encryption_metadata.algorithm != Algorithm::None {
-        // load private key
-        let private_key_file = matches.get_one::<String>("private_key").unwrap();
-        if !Path::new(&private_key_file).exists() {
-            return Err(format!(
-                "Private key file {:?} does not exist",
-                private_key_file
-            ));
+        let private_key = load_private_key(private_key_file()?).unwrap();
+
+        // damage is located before the archive is decrypted in place
+        if encryption_metadata.chunk_size > 0 {
+            let key = SessionKey::decrypt(
+                &private_key,
+                encryption_metadata.algorithm,
+                &encryption_metadata.encrypted_key,
+            )
+            .map_err(|e| format!("Failed to decrypt session key: {}", e))?;
+            check_archive_chunks(&archive_path, &key, &encryption_metadata)?;
         }
-        let private_key = load_private_key(PathBuf::from(&private_key_file)).unwrap();
 
         // decrypt the evidence
         info!("Decrypting archive");
-        decrypt_evidence(Path::new(&archive_path), private_key, encryption_metadata)
-            .map_err(|e| format!("Failed to decrypt archive: {}", e))?;
+        decrypt_evidence(
+            Path::new(&archive_path),
+            private_key,
+            encryption_metadata.clone(),
+        )
+        .map_err(|e| format!("Failed to decrypt archive: {}", e))?;
 
         info!("Decrypted archive");
     }
 
+    if verify_only {
+        let summary = match is_archived {
+            true => {
+                info!("Verifying archive {:?}", archive_path.display());
+                let file = fs::File::open(&archive_path)
+                    .map_err(|e| format!("Failed to open archive: {}", e))?;
+                let mut archive =
+                    ZipArchive::new(file).map_err(|e| format!("Failed to read archive: {}", e))?;
+                check_archive_layout(&mut archive)?;
+                check_archive_comment(&mut archive)?;
+                verify_archive(&mut archive)?
+            }
+            false => verify_directory(&report_dir)?,
+        };
+        info!(
+            "Verified {} files: {} mismatched, {} missing, {} skipped",
+            summary.verified, summary.mismatched, summary.missing, summary.skipped
+        );
+        if summary.mismatched > 0 || summary.missing > 0 {
+            return Err("Verification failed".to_string());
+        }
+        return Ok(());
+    }
+
     // check if extraction is needed
     if is_archived {
         info!("Unpacking archive to {:?}", output_path.display());
-        let file = std::fs::File::open(&archive_path).unwrap();
-        let mut archive = ZipArchive::new(file).unwrap();
-        match archive.extract(&output_path) {
-            Ok(_) => {}
-            Err(e) => {
-                error!("Failed to extract archive: {}", e);
+        let rejected = match container {
+            // tar and 7z archives have no comment, so the layout is checked afterwards
+            Container::TarZstd => {
+                let rejected = extract_tar_archive(&archive_path, &output_path)?;
+                check_layout_file(&output_path.join(LAYOUT_PATH))?;
+                rejected
+            }
+            Container::SevenZip => {
+                let rejected = extract_7z_archive(&archive_path, &output_path)?;
+                check_layout_file(&output_path.join(LAYOUT_PATH))?;
+                rejected
             }
+            Container::Zip => {
+                let file = std::fs::File::open(&archive_path).unwrap();
+                let mut archive = ZipArchive::new(file).unwrap();
+                check_archive_layout(&mut archive)?;
+                check_archive_comment(&mut archive)?;
+                extract_archive(&mut archive, &output_path)
+            }
+        };
+        if rejected > 0 {
+            warn!("Rejected {} entries of the archive", rejected);
         }
 
         info!("Unpacked archive to {:?}", output_path.display());
+
+        // action outputs and the sealed log, encrypted with the session key while the workflow was running
+        let mut outputs = encrypted_outputs(&output_path.join(ACTION_LOG_DIR));
+        let sealed_log = encrypted_output_path(&output_path.join(COLLECTOR_LOG_PATH));
+        if sealed_log.is_file() {
+            outputs.push(sealed_log);
+        }
+        if !outputs.is_empty() {
+            let private_key = load_private_key(private_key_file()?)
+                .map_err(|e| format!("Failed to load private key: {}", e))?;
+            let key = SessionKey::decrypt(
+                &private_key,
+                encryption_metadata.algorithm,
+                &encryption_metadata.encrypted_key,
+            )
+            .map_err(|e| format!("Failed to decrypt session key: {}", e))?;
+            let failed = decrypt_outputs(&outputs, &key);
+            if failed > 0 {
+                error!("Failed to decrypt {} outputs", failed);
+            }
+        }
     }
 
     // check if user wants to verify the checksums of the metadata file
     let verify = matches.get_flag("verify");
     // check if user wants to extract the files with their original names
     let restore = matches.get_flag("restore");
+    let sparse = matches.get_flag("sparse");
 
     // if not any of the above, return
     if !verify && !restore {
@@ -220,7 +438,7 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
     }
 
     for record in file_metadata {
-        let file_name_checksum = &record.path_checksum;
+        let file_name_checksum = record.storage_name();
 
         // check if we have a valid checksum
         if file_name_checksum.len() != 40 {
@@ -231,10 +449,39 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
             continue;
         }
 
+        // the file was not collected as it did not change since the baseline
+        if record.unchanged {
+            debug!(
+                "Skipping file unchanged since baseline: {:?}",
+                record.original_path
+            );
+            continue;
+        }
+        // the content was collected by a previous report (content-addressed storage)
+        if record.deduplicated {
+            debug!(
+                "Skipping file collected by a previous report: {:?}",
+                record.original_path
+            );
+            continue;
+        }
+        // links are recorded with their target, but without content
+        if let Some(target) = &record.link_target {
+            debug!("Skipping link {:?} -> {:?}", record.original_path, target);
+            continue;
+        }
+        // further hard links are restored as links to the stored file
+        if let Some(stored_path) = &record.hard_link_of {
+            if restore {
+                restore_hard_link(&output_path, stored_path, &record)?;
+            }
+            continue;
+        }
+
         // search for the corresponding file in the output directory
         let file_path = Path::new(&output_path)
             .join(STORAGE_DIR)
-            .join(&file_name_checksum);
+            .join(file_name_checksum);
         if !file_path.exists() {
             error!("File {:?} does not exist", file_path.display());
             continue;
@@ -246,13 +493,400 @@ pub fn run(matches: clap::ArgMatches) -> Result<(), String> {
         }
 
         if restore {
-            restore_file(&output_path, &file_path, &record)?;
+            restore_file(&output_path, &file_path, &record, sparse)?;
         }
     }
 
     Ok(())
 }
 
+/// Writes a copy of the report for the recipient: the archive encrypted with a new session key,
+/// which is encrypted with the public key of the recipient. The encrypted action outputs and the
+/// sealed log inside the archive are encrypted with the new session key as well
+fn reencrypt(matches: &clap::ArgMatches) -> Result<(), String> {
+    let report_dir = PathBuf::from(matches.get_one::<String>("input").unwrap());
+    let archive_path = find_archive(&report_dir);
+    check_interrupted_encryption(&report_dir)?;
+    if !archive_path.exists() {
+        return Err(format!(
+            "No archive found in {:?}: only archived reports can be re-encrypted",
+            report_dir.display()
+        ));
+    }
+    let metadata = get_metadata(&report_dir.join(ENCRYPTION_PATH))
+        .map_err(|e| format!("Failed to read metadata file {:?}: {}", ENCRYPTION_PATH, e))?;
+    if metadata.algorithm == Algorithm::None {
+        return Err("The archive is not encrypted".to_string());
+    }
+    if is_decrypted_archive(&archive_path) {
+        return Err(
+            "The archive has already been decrypted: re-encrypt the original report instead"
+                .to_string(),
+        );
+    }
+
+    let private_key = load_private_key(PathBuf::from(
+        matches.get_one::<String>("private_key").unwrap(),
+    ))
+    .map_err(|e| format!("Failed to load private key: {}", e))?;
+    let recipient = load_public_key(PathBuf::from(
+        matches.get_one::<String>("recipient").unwrap(),
+    ))
+    .map_err(|e| format!("Failed to load public key of the recipient: {}", e))?;
+    // the session key is decrypted again where it is needed, as readers take ownership of it
+    let decrypt_key = || {
+        SessionKey::decrypt(&private_key, metadata.algorithm, &metadata.encrypted_key)
+            .map_err(|e| format!("Failed to decrypt session key: {}", e))
+    };
+    decrypt_key()?;
+    // the recipient must not be able to decrypt other reports encrypted with the old session key
+    let new_key = SessionKey::generate(metadata.algorithm)
+        .map_err(|e| format!("Failed to generate session key: {}", e))?;
+    let encrypted_key = new_key
+        .encrypt(&recipient)
+        .map_err(|e| format!("Failed to encrypt session key for the recipient: {}", e))?;
+
+    let output_dir = match matches.get_one::<String>("output") {
+        Some(output) => PathBuf::from(output),
+        None => {
+            let mut name = report_dir.file_name().unwrap_or_default().to_os_string();
+            name.push("_reencrypted");
+            report_dir.with_file_name(name)
+        }
+    };
+    if output_dir.exists() {
+        return Err(format!(
+            "Output directory {:?} already exists. Please remove it or specify a different directory",
+            output_dir.display()
+        ));
+    }
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| format!("Failed to create {:?}: {}", output_dir.display(), e))?;
+
+    info!("Re-encrypting archive to {:?}", output_dir.display());
+    let result = write_reencrypted(
+        &report_dir,
+        &archive_path,
+        &output_dir,
+        &metadata,
+        decrypt_key,
+        &new_key,
+        encrypted_key,
+    );
+    if result.is_err() {
+        if let Err(e) = fs::remove_dir_all(&output_dir) {
+            error!("Failed to remove {:?}: {}", output_dir.display(), e);
+        }
+    }
+    result?;
+    info!("Re-encrypted report written to {:?}", output_dir.display());
+    Ok(())
+}
+
+// manifest.json describes the encrypted archive, so it is updated. Other files of the report
+// directory are not copied, as they may reveal what has been collected
+fn write_reencrypted(
+    report_dir: &Path,
+    archive_path: &Path,
+    output_dir: &Path,
+    metadata: &EncryptionMeta,
+    decrypt_key: impl Fn() -> Result<SessionKey, String>,
+    new_key: &SessionKey,
+    encrypted_key: Vec<u8>,
+) -> Result<(), String> {
+    let output_archive = output_dir.join(archive_path.file_name().unwrap_or_default());
+    let mut reencrypted = reencrypt_archive(
+        archive_path,
+        &output_archive,
+        metadata,
+        decrypt_key,
+        new_key,
+    )?;
+    reencrypted.encrypted_key = encrypted_key;
+    let file = fs::File::create(output_dir.join(ENCRYPTION_PATH))
+        .map_err(|e| format!("Failed to create {:?}: {}", ENCRYPTION_PATH, e))?;
+    serde_json::to_writer_pretty(file, &reencrypted)
+        .map_err(|e| format!("Failed to write {:?}: {}", ENCRYPTION_PATH, e))?;
+
+    let manifest_path = report_dir.join(MANIFEST_PATH);
+    if manifest_path.exists() {
+        let mut manifest: Manifest = fs::File::open(&manifest_path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| serde_json::from_reader(file).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to read {:?}: {}", MANIFEST_PATH, e))?;
+        manifest.archive_size = fs::metadata(&output_archive)
+            .map_err(|e| format!("Failed to read {:?}: {}", output_archive.display(), e))?
+            .len();
+        manifest.archive_sha1 = get_file_sha1(&output_archive)
+            .map_err(|e| format!("Failed to hash {:?}: {}", output_archive.display(), e))?;
+        let file = fs::File::create(output_dir.join(MANIFEST_PATH))
+            .map_err(|e| format!("Failed to create {:?}: {}", MANIFEST_PATH, e))?;
+        serde_json::to_writer_pretty(file, &manifest)
+            .map_err(|e| format!("Failed to write {:?}: {}", MANIFEST_PATH, e))?;
+    }
+    Ok(())
+}
+
+// action outputs and the sealed log, encrypted with the session key while the workflow was running
+fn is_encrypted_output(name: &str) -> bool {
+    let sealed_log = encrypted_output_path(Path::new(COLLECTOR_LOG_PATH));
+    name.ends_with(&format!(".{}", ENCRYPTED_OUTPUT_EXTENSION))
+        && (name.starts_with(&format!("{}/", ACTION_LOG_DIR))
+            || Path::new(name) == sealed_log.as_path())
+}
+
+// Encrypts the archive with the new session key. Archives with encrypted outputs are rewritten
+// while they are re-encrypted, so the outputs are encrypted with the new session key as well.
+// 7z archives with encrypted outputs are refused, as they can't be rewritten as a stream.
+// The plaintext is never written to disk. Returns the metadata without the encrypted key
+fn reencrypt_archive(
+    archive_path: &Path,
+    output_archive: &Path,
+    metadata: &EncryptionMeta,
+    decrypt_key: impl Fn() -> Result<SessionKey, String>,
+    new_key: &SessionKey,
+) -> Result<EncryptionMeta, String> {
+    let open =
+        || fs::File::open(archive_path).map_err(|e| format!("Failed to open archive: {}", e));
+    let key = decrypt_key()?;
+    let reader =
+        EvidenceReader::new(open()?, decrypt_key()?, &metadata.iv).map_err(|e| read_error(&e))?;
+    if Container::of(archive_path) == Container::SevenZip {
+        // a 7z archive is only complete once its header at the start is rewritten,
+        // which an encrypted stream doesn't allow
+        if has_encrypted_7z_entries(reader)? {
+            return Err("A 7z archive with encrypted action outputs can't be re-encrypted: extract the report and share the decrypted outputs instead".to_string());
+        }
+        return reencrypt_evidence(
+            archive_path,
+            output_archive,
+            &key,
+            new_key,
+            &metadata.iv,
+            &metadata.tag,
+        )
+        .map_err(|e| reencrypt_error(&e));
+    }
+    if Container::of(archive_path) == Container::Zip {
+        let archive = ZipArchive::new(BufReader::new(reader)).map_err(|e| read_error(&e))?;
+        if !archive.file_names().any(is_encrypted_output) {
+            return reencrypt_evidence(
+                archive_path,
+                output_archive,
+                &key,
+                new_key,
+                &metadata.iv,
+                &metadata.tag,
+            )
+            .map_err(|e| reencrypt_error(&e));
+        }
+        // the modified archive would otherwise be forwarded with a valid tag
+        info!("Verifying the authentication tag of the archive");
+        verify_evidence(BufReader::new(open()?), &key, &metadata.iv, &metadata.tag)
+            .map_err(|e| format!("Failed to verify archive: {}", e))?;
+        return rewrite_zip(archive, output_archive, &key, new_key);
+    }
+    info!("Verifying the authentication tag of the archive");
+    verify_evidence(BufReader::new(open()?), &key, &metadata.iv, &metadata.tag)
+        .map_err(|e| format!("Failed to verify archive: {}", e))?;
+    rewrite_tar_zstd(reader, output_archive, &key, new_key)
+}
+
+fn read_error(e: &dyn std::fmt::Display) -> String {
+    format!("Failed to read archive: {}", e)
+}
+
+fn reencrypt_error(e: &dyn std::fmt::Display) -> String {
+    format!("Failed to re-encrypt archive: {}", e)
+}
+
+fn create_evidence_writer(
+    output_archive: &Path,
+    new_key: &SessionKey,
+) -> Result<EvidenceWriter<io::BufWriter<fs::File>>, String> {
+    let file = fs::File::create(output_archive).map_err(|e| reencrypt_error(&e))?;
+    EvidenceWriter::new(io::BufWriter::new(file), new_key).map_err(|e| reencrypt_error(&e))
+}
+
+// the entries are copied as they are, except for the encrypted outputs
+fn rewrite_zip<R: Read + Seek>(
+    mut archive: ZipArchive<R>,
+    output_archive: &Path,
+    key: &SessionKey,
+    new_key: &SessionKey,
+) -> Result<EncryptionMeta, String> {
+    let mut writer = ZipWriter::new(create_evidence_writer(output_archive, new_key)?);
+    writer.set_raw_comment(archive.comment().into());
+    let temp_path = output_archive.with_file_name("entry.tmp");
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(|e| read_error(&e))?;
+        if !is_encrypted_output(entry.name()) {
+            writer
+                .raw_copy_file(entry)
+                .map_err(|e| reencrypt_error(&e))?;
+            continue;
+        }
+        let name = entry.name().to_string();
+        let mut options = SimpleFileOptions::default()
+            .compression_method(entry.compression())
+            .large_file(entry.size() >= u32::MAX as u64);
+        if let Some(time) = entry.last_modified() {
+            options = options.last_modified_time(time);
+        }
+        if let Some(mode) = entry.unix_mode() {
+            options = options.unix_permissions(mode);
+        }
+        drop(entry);
+
+        // the checksum of the entry changes and the archive can only be appended to,
+        // so the entry is written to a temporary archive first
+        let mut entry = archive.by_index(index).map_err(|e| read_error(&e))?;
+        let size = entry.size();
+        let mut reader = ReencryptingReader::new(&mut entry, size, key, new_key)
+            .map_err(|e| reencrypt_error(&e))?;
+        let file = fs::File::create(&temp_path).map_err(|e| reencrypt_error(&e))?;
+        let mut temp = ZipWriter::new(file);
+        temp.start_file(name, options)
+            .map_err(|e| reencrypt_error(&e))?;
+        io::copy(&mut reader, &mut temp).map_err(|e| reencrypt_error(&e))?;
+        temp.finish().map_err(|e| reencrypt_error(&e))?;
+        let mut temp = fs::File::open(&temp_path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| ZipArchive::new(file).map_err(|e| e.to_string()))
+            .map_err(|e| reencrypt_error(&e))?;
+        let temp_entry = temp.by_index_raw(0).map_err(|e| reencrypt_error(&e))?;
+        writer
+            .raw_copy_file(temp_entry)
+            .map_err(|e| reencrypt_error(&e))?;
+        fs::remove_file(&temp_path).map_err(|e| reencrypt_error(&e))?;
+    }
+    finish_reencrypted(writer.finish().map_err(|e| reencrypt_error(&e))?)
+}
+
+// tar archives are compressed as a whole, so they are always rewritten
+fn rewrite_tar_zstd<R: Read>(
+    reader: R,
+    output_archive: &Path,
+    key: &SessionKey,
+    new_key: &SessionKey,
+) -> Result<EncryptionMeta, String> {
+    let mut archive = read_tar_zstd(BufReader::new(reader)).map_err(|e| read_error(&e))?;
+    let mut builder = write_tar_zstd(create_evidence_writer(output_archive, new_key)?)
+        .map_err(|e| reencrypt_error(&e))?;
+    for entry in archive.entries().map_err(|e| read_error(&e))? {
+        let mut entry = entry.map_err(|e| read_error(&e))?;
+        let mut header = entry.header().clone();
+        let name = entry.path().map_err(|e| read_error(&e))?.into_owned();
+        let size = entry.size();
+        if is_encrypted_output(&name.to_string_lossy()) {
+            let reader = ReencryptingReader::new(&mut entry, size, key, new_key)
+                .map_err(|e| reencrypt_error(&e))?;
+            builder.append_data(&mut header, &name, reader)
+        } else {
+            builder.append_data(&mut header, &name, &mut entry)
+        }
+        .map_err(|e| reencrypt_error(&e))?;
+    }
+    let writer = builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(|e| reencrypt_error(&e))?;
+    finish_reencrypted(writer)
+}
+
+fn finish_reencrypted(
+    writer: EvidenceWriter<io::BufWriter<fs::File>>,
+) -> Result<EncryptionMeta, String> {
+    let (writer, metadata) = writer.finish().map_err(|e| reencrypt_error(&e))?;
+    writer
+        .into_inner()
+        .map_err(|e| reencrypt_error(&e))?
+        .sync_all()
+        .map_err(|e| reencrypt_error(&e))?;
+    Ok(metadata)
+}
+
+/// Writes the archive or one of its entries, decrypted with the session key if given.
+/// The tag is verified before anything is written, so no modified data reaches the writer
+fn stream_archive<W: Write>(
+    archive_path: &Path,
+    metadata: &EncryptionMeta,
+    key: Option<SessionKey>,
+    entry: Option<&str>,
+    writer: W,
+) -> Result<(), String> {
+    let open =
+        || fs::File::open(archive_path).map_err(|e| format!("Failed to open archive: {}", e));
+    match key {
+        Some(key) => {
+            info!("Verifying the authentication tag of the archive");
+            verify_evidence(BufReader::new(open()?), &key, &metadata.iv, &metadata.tag)
+                .map_err(|e| format!("Failed to verify archive: {}", e))?;
+            let reader = EvidenceReader::new(open()?, key, &metadata.iv)
+                .map_err(|e| format!("Failed to decrypt archive: {}", e))?;
+            copy_archive(reader, entry, Container::of(archive_path), writer)
+        }
+        None => copy_archive(open()?, entry, Container::of(archive_path), writer),
+    }
+}
+
+fn copy_archive<R: Read + Seek, W: Write>(
+    mut reader: R,
+    entry: Option<&str>,
+    container: Container,
+    mut writer: W,
+) -> Result<(), String> {
+    let copied = match entry {
+        Some(entry) if container == Container::SevenZip => {
+            let mut archive = read_7z(reader).map_err(|e| read_error(&e))?;
+            let mut copied = None;
+            archive
+                .for_each_entries(|file, reader| {
+                    if file.is_directory() || file.name() != entry {
+                        return Ok(true);
+                    }
+                    copied = Some(io::copy(reader, &mut writer));
+                    Ok(false)
+                })
+                .map_err(|e| format!("Failed to read entry {:?}: {}", entry, e))?;
+            copied.unwrap_or(Err(io::ErrorKind::NotFound.into()))
+        }
+        Some(entry) if container == Container::TarZstd => {
+            let mut archive =
+                read_tar_zstd(reader).map_err(|e| format!("Failed to read archive: {}", e))?;
+            let mut file = archive
+                .entries()
+                .and_then(|entries| {
+                    entries
+                        .flatten()
+                        .find(|file| *file.path_bytes() == *entry.as_bytes())
+                        .ok_or(io::ErrorKind::NotFound.into())
+                })
+                .map_err(|e| format!("Failed to read entry {:?}: {}", entry, e))?;
+            io::copy(&mut file, &mut writer)
+        }
+        Some(entry) => {
+            let mut archive = ZipArchive::new(BufReader::new(reader))
+                .map_err(|e| format!("Failed to read archive: {}", e))?;
+            check_archive_layout(&mut archive)?;
+            let mut file = archive
+                .by_name(entry)
+                .map_err(|e| format!("Failed to read entry {:?}: {}", entry, e))?;
+            io::copy(&mut file, &mut writer)
+        }
+        None => io::copy(&mut reader, &mut writer),
+    };
+    match copied.and_then(|_| writer.flush()) {
+        Ok(_) => Ok(()),
+        // the reading tool exited early, e.g. head
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            debug!("Output closed by the reader");
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to write to stdout: {}", e)),
+    }
+}
+
 fn verify_checksum(file_path: &PathBuf, record: &FileMeta) -> Result<bool, String> {
     match get_file_sha1(file_path) {
         Ok(checksum) => {
@@ -291,6 +925,168 @@ fn verify_checksum(file_path: &PathBuf, record: &FileMeta) -> Result<bool, Strin
     }
 }
 
+#[derive(Debug, Default, PartialEq)]
+struct VerifySummary {
+    verified: usize,
+    mismatched: usize,
+    missing: usize,
+    // no checksum or not collected (delta collection)
+    skipped: usize,
+}
+
+impl VerifySummary {
+    // Returns the name of the stored file or None if the record can't be verified
+    fn check_record(&mut self, record: &FileMeta) -> Option<String> {
+        if record.unchanged || record.deduplicated || record.sha1_checksum.is_empty() {
+            debug!("Skipping verification of {:?}", record.original_path);
+            self.skipped += 1;
+            return None;
+        }
+        if record.storage_name().len() != 40 {
+            warn!(
+                "Invalid checksum found in metadata file: {:?}",
+                record.storage_name()
+            );
+            self.skipped += 1;
+            return None;
+        }
+        Some(record.storage_name().to_string())
+    }
+
+    fn add(&mut self, record: &FileMeta, checksum: Result<String, String>) {
+        match checksum {
+            Ok(checksum) if checksum == record.sha1_checksum => {
+                debug!("Checksum verified for file {:?}", record.original_path);
+                self.verified += 1;
+            }
+            Ok(checksum) => {
+                error!(
+                    "Checksum mismatch for file {:?}: expected {}, got {}",
+                    record.original_path, record.sha1_checksum, checksum
+                );
+                self.mismatched += 1;
+            }
+            Err(e) => {
+                error!("File {:?} is missing: {}", record.original_path, e);
+                self.missing += 1;
+            }
+        }
+    }
+}
+
+/// Refuses a report in a layout newer than this unpacker supports.
+/// Reports without a layout.json were written before the layout was versioned (version 1)
+fn check_layout_file(path: &Path) -> Result<(), String> {
+    if !path.exists() {
+        debug!("{} not found: assuming layout version 1", LAYOUT_PATH);
+        return Ok(());
+    }
+    let file =
+        fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", LAYOUT_PATH, e))?;
+    read_layout(file)?.check()
+}
+
+fn check_archive_layout<R: io::Read + io::Seek>(archive: &mut ZipArchive<R>) -> Result<(), String> {
+    let layout = match archive.by_name(LAYOUT_PATH) {
+        Ok(entry) => read_layout(entry)?,
+        Err(_) => {
+            debug!(
+                "{} not found in archive: assuming layout version 1",
+                LAYOUT_PATH
+            );
+            return Ok(());
+        }
+    };
+    debug!(
+        "Report layout version {} (toolkit {})",
+        layout.version, layout.toolkit_version
+    );
+    layout.check()
+}
+
+/// Checks the metadata.csv against the checksum in the comment of the archive.
+/// Archives without the checksum were written by an older collector
+fn check_archive_comment<R: io::Read + io::Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<(), String> {
+    let comment = String::from_utf8_lossy(archive.comment()).to_string();
+    debug!("Archive comment: {:?}", comment);
+    let Some(expected) = comment_metadata_sha1(&comment) else {
+        return Ok(());
+    };
+    let actual = archive
+        .by_name(METADATA_PATH)
+        .map_err(|e| e.to_string())
+        .and_then(|entry| get_reader_sha1(entry).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to read {}: {}", METADATA_PATH, e))?;
+    if actual != expected {
+        return Err(format!(
+            "{} doesn't match the checksum in the archive comment (expected {}, got {})",
+            METADATA_PATH, expected, actual
+        ));
+    }
+    Ok(())
+}
+
+/// Verifies the stored files against the metadata file by streaming the entries
+/// of the archive. Nothing is written to disk
+fn verify_archive<R: io::Read + io::Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<VerifySummary, String> {
+    // entry names use the path separator of the collecting system
+    let entries: HashMap<PathBuf, String> = archive
+        .file_names()
+        .filter_map(|name| sanitize_entry_path(name).map(|path| (path, name.to_string())))
+        .collect();
+
+    let metadata_name = entries.get(&PathBuf::from(METADATA_PATH)).ok_or(format!(
+        "Metadata file {:?} not found in archive",
+        METADATA_PATH
+    ))?;
+    let metadata = archive
+        .by_name(metadata_name)
+        .map_err(|e| e.to_string())
+        .and_then(|entry| read_metadata_from_reader(entry).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to read metadata file: {}", e))?;
+
+    let mut summary = VerifySummary::default();
+    for record in &metadata {
+        let Some(checksum) = summary.check_record(record) else {
+            continue;
+        };
+        let entry_name = entries.get(&Path::new(STORAGE_DIR).join(&checksum));
+        let actual = match entry_name {
+            Some(name) => archive
+                .by_name(name)
+                .map_err(|e| e.to_string())
+                .and_then(|entry| get_reader_sha1(entry).map_err(|e| e.to_string())),
+            None => Err("not found in archive".to_string()),
+        };
+        summary.add(record, actual);
+    }
+    Ok(summary)
+}
+
+/// Verifies the stored files of a report that was not archived
+fn verify_directory(report_dir: &Path) -> Result<VerifySummary, String> {
+    let metadata_path = report_dir.join(METADATA_PATH);
+    let file = fs::File::open(&metadata_path)
+        .map_err(|e| format!("Failed to open {:?}: {}", metadata_path.display(), e))?;
+    let metadata = read_metadata_from_reader(file)
+        .map_err(|e| format!("Failed to read metadata file: {}", e))?;
+
+    let mut summary = VerifySummary::default();
+    for record in &metadata {
+        let Some(checksum) = summary.check_record(record) else {
+            continue;
+        };
+        let file_path = report_dir.join(STORAGE_DIR).join(&checksum);
+        let actual = get_file_sha1(&file_path).map_err(|e| e.to_string());
+        summary.add(record, actual);
+    }
+    Ok(summary)
+}
+
 fn path_to_storage_location(file_path: &String, output_path: &Path) -> PathBuf {
     // The path has to be reconstructed inside the storage directory
     // The original path looks like: \\?\C:\Users\user\Documents\file.txt
@@ -341,13 +1137,17 @@ fn path_to_storage_location(file_path: &String, output_path: &Path) -> PathBuf {
     new_path
 }
 
-fn restore_file(output_path: &Path, file_path: &Path, record: &FileMeta) -> Result<(), String> {
-    let new_path = path_to_storage_location(&record.original_path, output_path);
+// Path of the restored file, None if it must be skipped
+fn restore_destination(
+    output_path: &Path,
+    original_path: &String,
+) -> Result<Option<PathBuf>, String> {
+    let new_path = path_to_storage_location(original_path, output_path);
 
     // Skip if the file already exists
     if new_path.exists() {
         warn!("File {:?} already exists: skipping", new_path.display());
-        return Ok(());
+        return Ok(None);
     }
 
     // Skip if the file is not inside the output directory
@@ -356,7 +1156,7 @@ fn restore_file(output_path: &Path, file_path: &Path, record: &FileMeta) -> Resu
             "File {:?} is not inside the output directory: skipping",
             new_path.display()
         );
-        return Ok(());
+        return Ok(None);
     }
 
     // We want to preserve the directory structure of the original files
@@ -365,6 +1165,72 @@ fn restore_file(output_path: &Path, file_path: &Path, record: &FileMeta) -> Resu
         fs::create_dir_all(parent)
             .map_err(|e| format!("Failed to create directory {:?}: {}", parent.display(), e))?;
     }
+    Ok(Some(new_path))
+}
+
+/// Writes only the extents holding data, the holes stay unallocated on file systems supporting sparse files
+fn restore_sparse(file_path: &Path, new_path: &Path, extents: &[(u64, u64)]) -> io::Result<()> {
+    let mut source = fs::File::open(file_path)?;
+    let size = source.metadata()?.len();
+    let mut destination = fs::File::create(new_path)?;
+    for (offset, length) in extents {
+        source.seek(io::SeekFrom::Start(*offset))?;
+        destination.seek(io::SeekFrom::Start(*offset))?;
+        io::copy(&mut (&mut source).take(*length), &mut destination)?;
+    }
+    destination.set_len(size)
+}
+
+fn restore_file(
+    output_path: &Path,
+    file_path: &Path,
+    record: &FileMeta,
+    sparse: bool,
+) -> Result<(), String> {
+    let new_path = match restore_destination(output_path, &record.original_path)? {
+        Some(new_path) => new_path,
+        None => return Ok(()),
+    };
+
+    let extents = record.sparse_extents.as_deref().and_then(parse_extents);
+    if let (true, Some(extents)) = (sparse, extents) {
+        debug!(
+            "Restoring sparse file {:?} to {:?}",
+            file_path.display(),
+            new_path.display()
+        );
+        restore_sparse(file_path, &new_path, &extents).map_err(|e| {
+            format!(
+                "Failed to restore sparse file {:?} to {:?}: {}",
+                file_path.display(),
+                new_path.display(),
+                e
+            )
+        })?;
+        // like a moved file, unless it may be restored to several paths
+        if !record.content_addressed {
+            fs::remove_file(file_path)
+                .map_err(|e| format!("Failed to remove {:?}: {}", file_path.display(), e))?;
+        }
+        return Ok(());
+    }
+
+    // content-addressed files may be restored to several paths, so they are copied
+    if record.content_addressed {
+        debug!(
+            "Copying file {:?} to {:?}",
+            file_path.display(),
+            new_path.display()
+        );
+        return fs::copy(file_path, &new_path).map(|_| ()).map_err(|e| {
+            format!(
+                "Failed to copy file {:?} to {:?}: {}",
+                file_path.display(),
+                new_path.display(),
+                e
+            )
+        });
+    }
 
     // Move the file to the new path
     debug!(
@@ -382,6 +1248,408 @@ fn restore_file(output_path: &Path, file_path: &Path, record: &FileMeta) -> Resu
     })
 }
 
+/// Links a further hard link to the restored file, or copies it if the file system doesn't support hard links
+fn restore_hard_link(
+    output_path: &Path,
+    stored_path: &String,
+    record: &FileMeta,
+) -> Result<(), String> {
+    let stored_file = path_to_storage_location(stored_path, output_path);
+    if !stored_file.exists() {
+        error!(
+            "Hard link {:?} of {:?}: file was not restored",
+            record.original_path,
+            stored_file.display()
+        );
+        return Ok(());
+    }
+    let new_path = match restore_destination(output_path, &record.original_path)? {
+        Some(new_path) => new_path,
+        None => return Ok(()),
+    };
+
+    debug!(
+        "Linking file {:?} to {:?}",
+        stored_file.display(),
+        new_path.display()
+    );
+    if let Err(e) = fs::hard_link(&stored_file, &new_path) {
+        warn!(
+            "Failed to link {:?} to {:?}, copying it instead: {}",
+            stored_file.display(),
+            new_path.display(),
+            e
+        );
+        fs::copy(&stored_file, &new_path).map_err(|e| {
+            format!(
+                "Failed to copy file {:?} to {:?}: {}",
+                stored_file.display(),
+                new_path.display(),
+                e
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns the relative path of a zip entry or None if it could leave the output directory.
+/// Absolute paths, drive letters and ".." components are rejected
+fn sanitize_entry_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') || name.starts_with(['/', '\\']) {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for component in name.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return None,
+            // e.g. "C:" or alternate data streams like "file.txt:stream"
+            c if c.contains(':') => return None,
+            c => path.push(c),
+        }
+    }
+
+    match path.as_os_str().is_empty() {
+        true => None,
+        false => Some(path),
+    }
+}
+
+// creates the output directory, entries are checked against its normalized path
+fn prepare_output_dir(output_path: &Path) -> Result<PathBuf, String> {
+    fs::create_dir_all(output_path).map_err(|e| {
+        format!(
+            "Failed to create output directory {:?}: {}",
+            output_path.display(),
+            e
+        )
+    })?;
+    Ok(match output_path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => output_path.to_path_buf(),
+    })
+}
+
+/// Destination of an entry inside the output directory, with its parent directories created.
+/// None (logged) if the entry could be written outside of the output directory
+fn entry_destination(output_path: &Path, name: &str, is_dir: bool) -> Option<PathBuf> {
+    let relative_path = match sanitize_entry_path(name) {
+        Some(path) => path,
+        None => {
+            warn!(
+                "Rejected entry {:?}: path leaves the output directory",
+                name
+            );
+            return None;
+        }
+    };
+
+    let dest_path = output_path.join(&relative_path);
+    let parent = match is_dir {
+        true => dest_path.as_path(),
+        false => dest_path.parent().unwrap_or(output_path),
+    };
+    if let Err(e) = fs::create_dir_all(parent) {
+        error!("Failed to create directory {:?}: {}", parent.display(), e);
+        return None;
+    }
+
+    // the normalized destination must still be inside the output directory
+    // (e.g. if a directory on the way is a symlink)
+    let inside = parent
+        .canonicalize()
+        .is_ok_and(|parent| parent.starts_with(output_path));
+    if !inside {
+        warn!(
+            "Rejected entry {:?}: path leaves the output directory",
+            name
+        );
+        return None;
+    }
+    Some(dest_path)
+}
+
+/// Writes the content of an entry. Returns false (logged) if it was rejected
+fn write_entry(dest_path: &Path, name: &str, entry: &mut impl Read) -> bool {
+    // never overwrite files, e.g. if the archive contains duplicate entries
+    let mut file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dest_path)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Rejected entry {:?}: {}", name, e);
+            return false;
+        }
+    };
+    if let Err(e) = io::copy(entry, &mut file) {
+        error!("Failed to extract {:?}: {}", name, e);
+        return false;
+    }
+    debug!("Extracted {:?}", dest_path.display());
+    true
+}
+
+/// Extracts the archive entry by entry. Entries that could be written outside of the
+/// output directory, symlinks and existing files are rejected and logged.
+/// Returns the number of rejected entries
+fn extract_archive<R: io::Read + io::Seek>(
+    archive: &mut ZipArchive<R>,
+    output_path: &Path,
+) -> usize {
+    let output_path = match prepare_output_dir(output_path) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("{}", e);
+            return archive.len();
+        }
+    };
+
+    let mut rejected = 0;
+    for index in 0..archive.len() {
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("Failed to read entry {} of the archive: {}", index, e);
+                rejected += 1;
+                continue;
+            }
+        };
+        let name = entry.name().to_string();
+
+        // symlinks are never created, as they could point anywhere on the analyst's machine
+        let is_symlink = entry
+            .unix_mode()
+            .is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if is_symlink {
+            warn!("Rejected entry {:?}: symlinks are not extracted", name);
+            rejected += 1;
+            continue;
+        }
+
+        let Some(dest_path) = entry_destination(&output_path, &name, entry.is_dir()) else {
+            rejected += 1;
+            continue;
+        };
+        if !entry.is_dir() && !write_entry(&dest_path, &name, &mut entry) {
+            rejected += 1;
+        }
+    }
+
+    rejected
+}
+
+/// Extracts a tar archive (tar_zstd format) like extract_archive.
+/// Only directories and regular files are extracted
+fn extract_tar_archive(archive_path: &Path, output_path: &Path) -> Result<usize, String> {
+    let mut archive =
+        open_tar_zstd(archive_path).map_err(|e| format!("Failed to read archive: {}", e))?;
+    let output_path = prepare_output_dir(output_path)?;
+    let entries = archive
+        .entries()
+        .map_err(|e| format!("Failed to read archive: {}", e))?;
+
+    let mut rejected = 0;
+    for entry in entries {
+        // the archive is a stream, nothing can be read after a broken entry
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("Failed to read entry of the archive: {}", e);
+                rejected += 1;
+                break;
+            }
+        };
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let is_dir = match entry.header().entry_type() {
+            EntryType::Directory => true,
+            EntryType::Regular => false,
+            _ => {
+                warn!(
+                    "Rejected entry {:?}: only files and directories are extracted",
+                    name
+                );
+                rejected += 1;
+                continue;
+            }
+        };
+        let Some(dest_path) = entry_destination(&output_path, &name, is_dir) else {
+            rejected += 1;
+            continue;
+        };
+        if !is_dir && !write_entry(&dest_path, &name, &mut entry) {
+            rejected += 1;
+        }
+    }
+    Ok(rejected)
+}
+
+/// Extracts a 7z archive like extract_archive.
+/// Only directories and regular files are extracted
+fn extract_7z_archive(archive_path: &Path, output_path: &Path) -> Result<usize, String> {
+    let file = fs::File::open(archive_path).map_err(|e| read_error(&e))?;
+    let mut archive = read_7z(BufReader::new(file)).map_err(|e| read_error(&e))?;
+    let output_path = prepare_output_dir(output_path)?;
+
+    let mut rejected = 0;
+    archive
+        .for_each_entries(|entry, mut reader| {
+            let name = entry.name().to_string();
+            // symlinks are stored as unix mode in the upper bits of the attributes
+            let mode = entry.windows_attributes >> 16;
+            let is_symlink = entry.has_windows_attributes
+                && entry.windows_attributes & 0x8000 != 0
+                && mode & 0o170000 == 0o120000;
+            let written = if is_symlink {
+                warn!("Rejected entry {:?}: symlinks are not extracted", name);
+                false
+            } else {
+                entry_destination(&output_path, &name, entry.is_directory()).is_some_and(
+                    |dest_path| entry.is_directory() || write_entry(&dest_path, &name, &mut reader),
+                )
+            };
+            if !written {
+                rejected += 1;
+                // the entries of a folder are read from one stream
+                io::copy(reader, &mut io::sink())?;
+            }
+            Ok(true)
+        })
+        .map_err(|e| read_error(&e))?;
+    Ok(rejected)
+}
+
+fn encrypted_outputs(action_log_dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(action_log_dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path.extension().and_then(|ext| ext.to_str()) == Some(ENCRYPTED_OUTPUT_EXTENSION)
+        })
+        .collect()
+}
+
+// Returns the number of outputs that could not be decrypted
+fn decrypt_outputs(outputs: &[PathBuf], key: &SessionKey) -> usize {
+    let mut failed = 0;
+    for path in outputs {
+        match decrypt_output_file(path, key) {
+            Ok(output) => debug!("Decrypted {:?}", output.display()),
+            Err(e) => {
+                error!("Failed to decrypt {:?}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+// Checks the chunk tags of encryption.json. If a chunk is damaged, the archive is left untouched
+// and the intact chunks before the damage are decrypted into a copy next to it
+fn check_archive_chunks(
+    archive_path: &Path,
+    key: &SessionKey,
+    metadata: &EncryptionMeta,
+) -> Result<(), String> {
+    info!("Checking the chunks of the archive");
+    let check = fs::File::open(archive_path)
+        .map_err(|e| e.into())
+        .and_then(|file| check_chunks(BufReader::new(file), key, metadata))
+        .map_err(|e| format!("Failed to check the chunks of the archive: {}", e))?;
+    let Some(chunk) = check.damaged_chunk else {
+        return Ok(());
+    };
+
+    let name = archive_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let salvaged_path = archive_path.with_file_name(format!("salvaged_{}", name));
+    if salvaged_path.exists() {
+        fs::remove_file(&salvaged_path)
+            .map_err(|e| format!("Failed to remove {:?}: {}", salvaged_path.display(), e))?;
+    }
+    salvage_evidence(archive_path, &salvaged_path, key, metadata)
+        .map_err(|e| format!("Failed to salvage the archive: {}", e))?;
+    Err(format!(
+        "The archive is damaged at chunk {} (offset {}): the intact {} of {} bytes before it \
+         were decrypted to {:?}, the entries stored there can be recovered with e.g. `zip -FF`",
+        chunk,
+        check.intact,
+        check.intact,
+        metadata.plaintext_size,
+        salvaged_path.display()
+    ))
+}
+
+// the collector crashed while it encrypted the archive, which is incomplete and still unencrypted
+fn check_interrupted_encryption(report_dir: &Path) -> Result<(), String> {
+    let Some(file) = interrupted_encryptions(report_dir).into_iter().next() else {
+        return Ok(());
+    };
+    let progress = read_encryption_journal(&file)
+        .map(|journal| format!(" at {} of {} bytes", journal.offset, journal.file_size))
+        .unwrap_or_default();
+    Err(format!(
+        "Encryption of {:?} was interrupted{}: the report is incomplete and the archive was never encrypted",
+        file.display(),
+        progress
+    ))
+}
+
+// container of the archive, told apart by its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    Zip,
+    TarZstd,
+    SevenZip,
+}
+
+impl Container {
+    fn of(archive_path: &Path) -> Self {
+        match archive_path.file_name().and_then(|name| name.to_str()) {
+            Some(TAR_ZSTD_PATH) => Self::TarZstd,
+            Some(SEVEN_ZIP_PATH) => Self::SevenZip,
+            _ => Self::Zip,
+        }
+    }
+}
+
+// the archive of the report, report.zip unless it was converted to another format
+fn find_archive(report_dir: &Path) -> PathBuf {
+    [TAR_ZSTD_PATH, SEVEN_ZIP_PATH]
+        .iter()
+        .map(|name| report_dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| report_dir.join(report::ZIP_PATH))
+}
+
+// the names are stored in the header at the end of the archive, so no content is decrypted
+fn has_encrypted_7z_entries<R: Read + Seek>(reader: R) -> Result<bool, String> {
+    let archive = read_7z(BufReader::new(reader)).map_err(|e| read_error(&e))?;
+    Ok(archive
+        .archive()
+        .files
+        .iter()
+        .any(|file| is_encrypted_output(file.name())))
+}
+
+// a decrypted archive starts with the magic bytes of its format
+fn is_decrypted_archive(archive_path: &Path) -> bool {
+    match Container::of(archive_path) {
+        Container::TarZstd => is_zstd_file(archive_path),
+        Container::SevenZip => is_7z_file(archive_path),
+        Container::Zip => is_valid_zip_archive(archive_path),
+    }
+}
+
 fn is_valid_zip_archive(file_path: &Path) -> bool {
     // The first 4 bytes of an encrypted zip archive are always the same
     // 0x50 0x4B 0x03 0x04
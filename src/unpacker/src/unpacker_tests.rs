@@ -70,7 +70,7 @@ mod tests {
 
             info!("Loading public key: {}", public_key_path.to_string_lossy());
             if let Ok(public_key) = load_public_key(public_key_path.clone()) {
-                fp.set_public_key(public_key);
+                fp.set_public_key(public_key).unwrap();
             } else {
                 panic!("Error loading public key");
             }
@@ -128,6 +128,9 @@ mod tests {
                 attributes:
                   patterns: |
                     {}/*
+                  extra:
+                    category: "test"
+                    device: "${{DEVICE_NAME}}"
             workflow:
               - action: store_file
               - action: run_command
@@ -225,6 +228,519 @@ mod tests {
                 file.to_str().unwrap()
             );
         }
+
+        // the custom metadata of the store action is part of the unpacked metadata.csv
+        let records = read_metadata(&output_dir.join(METADATA_PATH));
+        // the files and the workflow inside the temporary directory
+        assert_eq!(records.len(), tmp_files.len() + 1);
+        for record in records {
+            let extra = record.extra_metadata();
+            assert_eq!(extra["category"], "test");
+            assert!(!extra["device"].contains("${"));
+        }
+    }
+
+    #[test]
+    fn check_stream_archive() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_stream_archive");
+        std::fs::write(tmp_dir.join("evidence.txt"), "streamed content").unwrap();
+
+        let workflow_file = format!(
+            r#"
+            properties:
+              title: "test"
+              description: "test"
+              author: "test"
+              version: "1.0"
+            launch_conditions:
+              os: ["windows", "linux", "macos"]
+              arch: ["x86", "x86_64", "aarch64", "arm"]
+              is_elevated: false
+            actions:
+              - name: store_file
+                type: store
+                attributes:
+                  patterns: |
+                    {}/*
+            workflow:
+              - action: store_file
+            reporting:
+              zip_archive:
+                enabled: true
+                encryption:
+                  enabled: true
+                  public_key: "example_public.pem"
+                  algorithm: AES-128-GCM
+                compression:
+                  enabled: true
+                  size_limit: "100 MB"
+              metadata:
+                mac_times: true
+                checksums: true
+                paths: true
+        "#,
+            tmp_dir.to_str().unwrap()
+        );
+        let report = generate_test_report(
+            tmp_dir.clone(),
+            workflow_file,
+            "test_check_stream_archive".to_string(),
+        );
+        cleanup.add(report.dir.clone());
+
+        let metadata = get_metadata(&report.dir.join(ENCRYPTION_PATH)).unwrap();
+        let key = || {
+            let private_key =
+                load_private_key(get_base_path().join("keys").join("example_private.pem")).unwrap();
+            SessionKey::decrypt(&private_key, metadata.algorithm, &metadata.encrypted_key).ok()
+        };
+
+        // the whole archive
+        let mut stream = vec![];
+        stream_archive(&report.zip_path, &metadata, key(), None, &mut stream).unwrap();
+        let mut archive = ZipArchive::new(std::io::Cursor::new(stream)).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name(METADATA_PATH)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        let record = read_metadata_from_reader(content.as_bytes())
+            .unwrap()
+            .into_iter()
+            .find(|record| record.original_path.ends_with("evidence.txt"))
+            .unwrap();
+        let entry = archive
+            .file_names()
+            .find(|name| name.ends_with(record.storage_name()))
+            .unwrap()
+            .to_string();
+
+        // a single entry
+        let mut stream = vec![];
+        stream_archive(
+            &report.zip_path,
+            &metadata,
+            key(),
+            Some(&entry),
+            &mut stream,
+        )
+        .unwrap();
+        assert_eq!(stream, b"streamed content");
+        let result = stream_archive(&report.zip_path, &metadata, key(), Some("missing"), vec![]);
+        assert!(result.unwrap_err().contains("missing"));
+
+        // nothing was decrypted on disk
+        assert!(!is_valid_zip_archive(&report.zip_path));
+        assert!(!report.dir.join("output").exists());
+
+        // a modified archive is rejected before anything is written
+        let mut encrypted = fs::read(&report.zip_path).unwrap();
+        encrypted[10] ^= 0xff;
+        fs::write(&report.zip_path, &encrypted).unwrap();
+        let mut stream = vec![];
+        assert!(stream_archive(&report.zip_path, &metadata, key(), None, &mut stream).is_err());
+        assert!(stream.is_empty());
+
+        // --entry only works with --stdout
+        assert!(get_command()
+            .try_get_matches_from(vec!["unpacker", "-i", "report", "--entry", "metadata.csv"])
+            .is_err());
+    }
+
+    #[test]
+    fn check_unpack_interrupted_encryption() {
+        let mut cleanup = Cleanup::new();
+        let report_dir = cleanup.tmp_dir("check_unpack_interrupted_encryption");
+        fs::write(report_dir.join(report::ZIP_PATH), "unencrypted").unwrap();
+        fs::write(
+            crypto::encryption_temp_path(&report_dir.join(report::ZIP_PATH)),
+            "partial",
+        )
+        .unwrap();
+        let unpack = |args: &[&str]| {
+            let mut command = vec!["unpacker"];
+            command.extend(args);
+            command.extend(["-i", report_dir.to_str().unwrap()]);
+            run(get_command().get_matches_from(command)).unwrap_err()
+        };
+        assert!(unpack(&[]).contains("was interrupted"));
+        assert!(unpack(&[
+            "reencrypt",
+            "-k",
+            "private.pem",
+            "--recipient",
+            "recipient.pem"
+        ])
+        .contains("was interrupted"));
+    }
+
+    #[test]
+    fn check_unpack_tar_zstd() {
+        check_unpack_converted("tar_zstd", TAR_ZSTD_PATH);
+    }
+
+    #[test]
+    fn check_unpack_7z() {
+        check_unpack_converted("7z", SEVEN_ZIP_PATH);
+    }
+
+    // the archive is converted from report.zip once it is complete
+    fn check_unpack_converted(format: &str, archive_name: &str) {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir(&format!("check_unpack_{}", format));
+        std::fs::write(tmp_dir.join("evidence.txt"), "converted content").unwrap();
+
+        let workflow_file = format!(
+            r#"
+            properties:
+              title: "test"
+              description: "test"
+              author: "test"
+              version: "1.0"
+            launch_conditions:
+              os: ["windows", "linux", "macos"]
+              arch: ["x86", "x86_64", "aarch64", "arm"]
+              is_elevated: false
+            actions:
+              - name: store_file
+                type: store
+                attributes:
+                  patterns: |
+                    {}/*
+            workflow:
+              - action: store_file
+            reporting:
+              zip_archive:
+                enabled: true
+                format: {}
+                encryption:
+                  enabled: true
+                  public_key: "example_public.pem"
+                  algorithm: AES-128-GCM
+                compression:
+                  enabled: true
+                  size_limit: "100 MB"
+              metadata:
+                mac_times: true
+                checksums: true
+                paths: true
+        "#,
+            tmp_dir.to_str().unwrap(),
+            format
+        );
+        let report = generate_test_report(
+            tmp_dir.clone(),
+            workflow_file,
+            format!("test_check_unpack_{}", format),
+        );
+        cleanup.add(report.dir.clone());
+        let archive_path = report.dir.join(archive_name);
+        assert!(archive_path.exists());
+        assert!(!report.zip_path.exists());
+        assert!(!is_decrypted_archive(&archive_path));
+
+        let private_key = get_base_path().join("keys").join("example_private.pem");
+        let unpack = |args: &[&str]| {
+            let mut command = vec![
+                "unpacker",
+                "-i",
+                report.dir.to_str().unwrap(),
+                "-k",
+                private_key.to_str().unwrap(),
+            ];
+            command.extend(args);
+            run(get_command().get_matches_from(command))
+        };
+
+        // a single entry is found while the archive is decrypted
+        let metadata = get_metadata(&report.dir.join(ENCRYPTION_PATH)).unwrap();
+        let key = SessionKey::decrypt(
+            &load_private_key(private_key.clone()).unwrap(),
+            metadata.algorithm,
+            &metadata.encrypted_key,
+        )
+        .ok();
+        let mut stream = vec![];
+        stream_archive(
+            &archive_path,
+            &metadata,
+            key,
+            Some(METADATA_PATH),
+            &mut stream,
+        )
+        .unwrap();
+        assert!(String::from_utf8_lossy(&stream).contains("evidence.txt"));
+
+        assert!(unpack(&["--verify-only"])
+            .unwrap_err()
+            .contains(archive_name));
+        unpack(&["--verify"]).unwrap();
+        let output_dir = report.dir.join("output");
+        let record = read_metadata(&output_dir.join(METADATA_PATH))
+            .into_iter()
+            .find(|record| record.original_path.ends_with("evidence.txt"))
+            .unwrap();
+        let stored = output_dir.join(STORAGE_DIR).join(record.storage_name());
+        assert_eq!(fs::read_to_string(stored).unwrap(), "converted content");
+        assert!(is_decrypted_archive(&archive_path));
+        assert!(output_dir.join(LAYOUT_PATH).exists());
+    }
+
+    #[test]
+    fn check_unpack_encrypted_action_output() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_unpack_encrypted_action_output");
+
+        let workflow_file = format!(
+            r#"
+            properties:
+              title: "test"
+              description: "test"
+              author: "test"
+              version: "1.0"
+            launch_conditions:
+              os: ["windows", "linux", "macos"]
+              arch: ["x86", "x86_64", "aarch64", "arm"]
+              is_elevated: false
+            options:
+              time_zone: "Europe/Berlin"
+            actions:
+              - name: run_command
+                type: command
+                attributes:
+                  cmd: "{}"
+                  args: ["{}", "echo secret"]
+                  log_to_file: true
+            workflow:
+              - action: run_command
+            reporting:
+              zip_archive:
+                enabled: true
+                encryption:
+                  enabled: true
+                  public_key: "example_public.pem"
+                  algorithm: AES-128-GCM
+                  encrypt_action_output: true
+                compression:
+                  enabled: false
+                  size_limit: "100 MB"
+              metadata:
+                mac_times: true
+                checksums: true
+                paths: true
+        "#,
+            match cfg!(windows) {
+                true => "cmd",
+                false => "sh",
+            },
+            match cfg!(windows) {
+                true => "/c",
+                false => "-c",
+            },
+        );
+
+        let report = generate_test_report(
+            tmp_dir.clone(),
+            workflow_file,
+            "test_check_unpack_encrypted_action_output".to_string(),
+        );
+        cleanup.add(report.dir.clone());
+
+        let matches = get_command().get_matches_from(vec![
+            "unpacker",
+            "-i",
+            report.dir.to_str().unwrap(),
+            "-k",
+            get_base_path()
+                .join("keys")
+                .join("example_private.pem")
+                .to_str()
+                .unwrap(),
+        ]);
+        if let Err(e) = run(matches) {
+            panic!("Unpacker failed: {}", e);
+        }
+
+        // the output is decrypted after unpacking
+        let action_log_dir = report.dir.join("output").join(report::ACTION_LOG_DIR);
+        let output = std::fs::read_to_string(action_log_dir.join("run_command.log"))
+            .expect("Decrypted action output not found");
+        assert!(output.contains("secret"));
+        assert!(encrypted_outputs(&action_log_dir).is_empty());
+    }
+
+    #[test]
+    fn check_reencrypt() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_reencrypt");
+
+        // key pair of the recipient
+        let (private_key, public_key) = crypto::generate_rsa_keypair(2048).unwrap();
+        let recipient_private = tmp_dir.join("recipient_private.pem");
+        let recipient_public = tmp_dir.join("recipient_public.pem");
+        crypto::save_keypair(
+            private_key,
+            public_key,
+            &recipient_private.to_string_lossy().to_string(),
+            &recipient_public.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        for (format, encrypt_action_output) in [
+            ("zip_deflate", true),
+            ("tar_zstd", true),
+            ("7z", true),
+            ("7z", false),
+        ] {
+            check_reencrypt_format(
+                &mut cleanup,
+                &tmp_dir,
+                format,
+                encrypt_action_output,
+                &recipient_private,
+                &recipient_public,
+            );
+        }
+    }
+
+    fn check_reencrypt_format(
+        cleanup: &mut Cleanup,
+        tmp_dir: &Path,
+        format: &str,
+        encrypt_action_output: bool,
+        recipient_private: &Path,
+        recipient_public: &Path,
+    ) {
+        let workflow_file = format!(
+            r#"
+            properties:
+              title: "test"
+              description: "test"
+              author: "test"
+              version: "1.0"
+            launch_conditions:
+              os: ["windows", "linux", "macos"]
+              arch: ["x86", "x86_64", "aarch64", "arm"]
+              is_elevated: false
+            actions:
+              - name: run_command
+                type: command
+                attributes:
+                  cmd: "{}"
+                  args: ["{}", "echo forwarded"]
+                  log_to_file: true
+            workflow:
+              - action: run_command
+            reporting:
+              zip_archive:
+                enabled: true
+                format: {}
+                encryption:
+                  enabled: true
+                  public_key: "example_public.pem"
+                  algorithm: CHACHA20-POLY1305
+                  encrypt_action_output: {}
+                compression:
+                  enabled: false
+                  size_limit: "100 MB"
+              metadata:
+                mac_times: false
+                checksums: true
+                paths: true
+        "#,
+            match cfg!(windows) {
+                true => "cmd",
+                false => "sh",
+            },
+            match cfg!(windows) {
+                true => "/c",
+                false => "-c",
+            },
+            format,
+            encrypt_action_output,
+        );
+        let name = format!("{}_{}", format, encrypt_action_output);
+        let report = generate_test_report(
+            tmp_dir.to_path_buf(),
+            workflow_file,
+            format!("test_check_reencrypt_{}", name),
+        );
+        cleanup.add(report.dir.clone());
+
+        let forwarded = tmp_dir.join(format!("forwarded_{}", name));
+        let matches = get_command().get_matches_from(vec![
+            "unpacker",
+            "reencrypt",
+            "-i",
+            report.dir.to_str().unwrap(),
+            "-k",
+            get_base_path()
+                .join("keys")
+                .join("example_private.pem")
+                .to_str()
+                .unwrap(),
+            "--recipient",
+            recipient_public.to_str().unwrap(),
+            "-o",
+            forwarded.to_str().unwrap(),
+        ]);
+        // the header of a 7z archive can't be rewritten while it is encrypted
+        if format == "7z" && encrypt_action_output {
+            assert!(run(matches).unwrap_err().contains("7z archive"));
+            assert!(!is_decrypted_archive(&report.seven_zip_path));
+            return;
+        }
+        if let Err(e) = run(matches) {
+            panic!("Re-encryption failed: {}", e);
+        }
+        // the original report is left untouched
+        let archive_name = match format {
+            "tar_zstd" => TAR_ZSTD_PATH,
+            "7z" => SEVEN_ZIP_PATH,
+            _ => report::ZIP_PATH,
+        };
+        assert!(!is_decrypted_archive(&report.dir.join(archive_name)));
+        assert!(!forwarded.join(METADATA_PATH).exists());
+
+        // the recipient gets a new session key, not the one of the original report
+        let original_meta = get_metadata(&report.dir.join(ENCRYPTION_PATH)).unwrap();
+        let forwarded_meta = get_metadata(&forwarded.join(ENCRYPTION_PATH)).unwrap();
+        let original_key = SessionKey::decrypt(
+            &load_private_key(get_base_path().join("keys").join("example_private.pem")).unwrap(),
+            original_meta.algorithm,
+            &original_meta.encrypted_key,
+        )
+        .unwrap();
+        let forwarded_archive = fs::File::open(forwarded.join(archive_name)).unwrap();
+        assert!(verify_evidence(
+            forwarded_archive,
+            &original_key,
+            &forwarded_meta.iv,
+            &forwarded_meta.tag
+        )
+        .is_err());
+
+        let unpack = |private_key: &Path| {
+            run(get_command().get_matches_from(vec![
+                "unpacker",
+                "-i",
+                forwarded.to_str().unwrap(),
+                "-k",
+                private_key.to_str().unwrap(),
+            ]))
+        };
+        // only the recipient can decrypt the forwarded report
+        assert!(unpack(&get_base_path().join("keys").join("example_private.pem")).is_err());
+        std::fs::remove_dir_all(forwarded.join("output")).ok();
+        unpack(recipient_private).unwrap();
+
+        // the action outputs are encrypted with the new session key as well
+        let action_log_dir = forwarded.join("output").join(report::ACTION_LOG_DIR);
+        let output = std::fs::read_to_string(action_log_dir.join("run_command.log"))
+            .expect("Decrypted action output not found");
+        assert!(output.contains("forwarded"));
     }
 
     #[test]
@@ -460,4 +976,236 @@ mod tests {
 
         assert_eq!(is_valid_zip_archive(&zip_path), true);
     }
+
+    #[test]
+    fn check_restore_sparse_and_hard_link() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_restore_sparse_and_hard_link");
+        let output_path = tmp_dir.join("output");
+        fs::create_dir_all(output_path.join(STORAGE_DIR)).unwrap();
+
+        // stored content of a sparse file: a hole followed by data
+        let stored = output_path.join(STORAGE_DIR).join("stored");
+        let mut content = vec![0u8; 8192];
+        content[4096..].fill(7);
+        fs::write(&stored, &content).unwrap();
+        let mut record = read_metadata_from_reader(
+            "original_path,modified_time,accessed_time,created_time,sha1_checksum,path_checksum,size,comment\n/var/lib/disk.img,,,,,abc,8192,\n".as_bytes(),
+        )
+        .unwrap()
+        .remove(0);
+        record.sparse_extents = Some("4096+4096".to_string());
+        restore_file(&output_path, &stored, &record, true).unwrap();
+        let restored = path_to_storage_location(&record.original_path, &output_path);
+        assert_eq!(fs::read(&restored).unwrap(), content);
+        assert!(!stored.exists());
+
+        // a further hard link points to the restored file
+        let original_path = record.original_path.clone();
+        record.original_path = "/var/lib/disk2.img".to_string();
+        record.hard_link_of = Some(original_path);
+        restore_hard_link(&output_path, record.hard_link_of.as_ref().unwrap(), &record).unwrap();
+        let linked = path_to_storage_location(&record.original_path, &output_path);
+        assert_eq!(fs::read(&linked).unwrap(), content);
+    }
+
+    #[test]
+    fn check_sanitize_entry_path() {
+        assert_eq!(
+            sanitize_entry_path("storage/abc"),
+            Some(PathBuf::from("storage").join("abc"))
+        );
+        assert_eq!(
+            sanitize_entry_path("./action_output\\\\log.txt"),
+            Some(PathBuf::from("action_output").join("log.txt"))
+        );
+        for name in [
+            "../evil.txt",
+            "storage/../../evil.txt",
+            "..\\evil.txt",
+            "/etc/passwd",
+            "\\\\server\\share\\evil.txt",
+            "C:\\Windows\\evil.txt",
+            "C:evil.txt",
+            "",
+            "./",
+        ] {
+            assert_eq!(sanitize_entry_path(name), None, "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn check_extract_archive_rejects_traversal() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_extract_archive_rejects_traversal");
+
+        // Create a hostile zip archive
+        let zip_path = tmp_dir.join("hostile.zip");
+        let file = File::create(&zip_path).unwrap();
+        let mut zip_writer = ZipWriter::new(BufWriter::new(file));
+        let file_options: FileOptions<ExtendedFileOptions> = FileOptions::default();
+        for name in ["storage/good.txt", "../evil.txt", "/evil_abs.txt"] {
+            zip_writer.start_file(name, file_options.clone()).unwrap();
+            zip_writer.write_all(b"content").unwrap();
+        }
+        zip_writer
+            .add_symlink("storage/link", "/etc/passwd", file_options.clone())
+            .unwrap();
+        zip_writer.finish().unwrap();
+
+        let output_path = tmp_dir.join("output");
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let rejected = extract_archive(&mut archive, &output_path);
+
+        // traversal, absolute path and symlink
+        assert_eq!(rejected, 3);
+        assert!(output_path.join("storage").join("good.txt").exists());
+        assert!(!tmp_dir.join("evil.txt").exists());
+        assert!(!output_path.join("storage").join("link").exists());
+    }
+
+    #[test]
+    fn check_archive_layout_version() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_archive_layout_version");
+
+        let write_archive = |name: &str, layout: Option<String>| {
+            let zip_path = tmp_dir.join(name);
+            let mut zip_writer = ZipWriter::new(BufWriter::new(File::create(&zip_path).unwrap()));
+            if let Some(layout) = layout {
+                let file_options: FileOptions<ExtendedFileOptions> = FileOptions::default();
+                zip_writer.start_file(LAYOUT_PATH, file_options).unwrap();
+                zip_writer.write_all(layout.as_bytes()).unwrap();
+            }
+            zip_writer.finish().unwrap();
+            ZipArchive::new(File::open(&zip_path).unwrap()).unwrap()
+        };
+
+        report::layout::write_layout(&tmp_dir).unwrap();
+        let current = fs::read_to_string(tmp_dir.join(LAYOUT_PATH)).unwrap();
+        let newer = current.replace("\"version\": 1,", "\"version\": 2,");
+        assert_ne!(current, newer);
+        assert!(check_archive_layout(&mut write_archive("current.zip", Some(current))).is_ok());
+        // reports written before the layout was versioned
+        assert!(check_archive_layout(&mut write_archive("old.zip", None)).is_ok());
+        assert!(check_archive_layout(&mut write_archive("newer.zip", Some(newer))).is_err());
+    }
+
+    #[test]
+    fn check_archive_comment_checksum() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_archive_comment_checksum");
+
+        let write_archive = |name: &str, metadata: &str, comment: &str| {
+            let zip_path = tmp_dir.join(name);
+            let mut zip_writer = ZipWriter::new(BufWriter::new(File::create(&zip_path).unwrap()));
+            let file_options: FileOptions<ExtendedFileOptions> = FileOptions::default();
+            zip_writer.start_file(METADATA_PATH, file_options).unwrap();
+            zip_writer.write_all(metadata.as_bytes()).unwrap();
+            zip_writer.set_comment(comment);
+            zip_writer.finish().unwrap();
+            ZipArchive::new(File::open(&zip_path).unwrap()).unwrap()
+        };
+
+        let metadata = "original_path\n/etc/passwd\n";
+        let sha1 = get_reader_sha1(metadata.as_bytes()).unwrap();
+        let comment = report::layout::archive_comment("2024-08-12T13:45:20Z", &sha1);
+        assert!(check_archive_comment(&mut write_archive("valid.zip", metadata, &comment)).is_ok());
+        // archives of older collectors have no comment
+        assert!(check_archive_comment(&mut write_archive("old.zip", metadata, "")).is_ok());
+        let tampered = "original_path\n/etc/shadow\n";
+        assert!(
+            check_archive_comment(&mut write_archive("tampered.zip", tampered, &comment))
+                .unwrap_err()
+                .contains("doesn't match")
+        );
+    }
+
+    #[test]
+    fn check_verify_archive() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_verify_archive");
+
+        let good = "a".repeat(40);
+        let modified = "b".repeat(40);
+        let missing = "c".repeat(40);
+        let metadata = format!(
+            "original_path,modified_time,accessed_time,created_time,sha1_checksum,path_checksum,size,comment,unchanged\n\
+            /good.txt,,,,{},{},4,,false\n\
+            /modified.txt,,,,{},{},4,,false\n\
+            /missing.txt,,,,{},{},4,,false\n\
+            /unchanged.txt,,,,{},{},4,,true\n",
+            get_reader_sha1(&b"good"[..]).unwrap(),
+            good,
+            get_reader_sha1(&b"good"[..]).unwrap(),
+            modified,
+            get_reader_sha1(&b"good"[..]).unwrap(),
+            missing,
+            get_reader_sha1(&b"good"[..]).unwrap(),
+            "d".repeat(40),
+        );
+
+        let zip_path = tmp_dir.join("report.zip");
+        let mut zip_writer = ZipWriter::new(BufWriter::new(File::create(&zip_path).unwrap()));
+        let file_options: FileOptions<ExtendedFileOptions> = FileOptions::default();
+        zip_writer
+            .start_file(METADATA_PATH, file_options.clone())
+            .unwrap();
+        zip_writer.write_all(metadata.as_bytes()).unwrap();
+        for (name, content) in [(&good, "good"), (&modified, "evil")] {
+            zip_writer
+                .start_file(format!("{}\\{}", STORAGE_DIR, name), file_options.clone())
+                .unwrap();
+            zip_writer.write_all(content.as_bytes()).unwrap();
+        }
+        zip_writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let summary = verify_archive(&mut archive).unwrap();
+        assert_eq!(
+            summary,
+            VerifySummary {
+                verified: 1,
+                mismatched: 1,
+                missing: 1,
+                skipped: 1,
+            }
+        );
+
+        // nothing was extracted
+        assert_eq!(fs::read_dir(&tmp_dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn check_verify_content_addressed() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("check_verify_content_addressed");
+
+        // two paths with the same content and one collected by a previous report
+        let checksum = get_reader_sha1(&b"dll"[..]).unwrap();
+        let metadata = format!(
+            "original_path,modified_time,accessed_time,created_time,sha1_checksum,path_checksum,size,comment,unchanged,content_addressed,deduplicated\n\
+            /a.dll,,,,{checksum},{},3,,false,true,false\n\
+            /b.dll,,,,{checksum},{},3,,false,true,false\n\
+            /c.dll,,,,{},{},3,,false,true,true\n",
+            "a".repeat(40),
+            "b".repeat(40),
+            "e".repeat(40),
+            "c".repeat(40),
+        );
+        fs::write(tmp_dir.join(METADATA_PATH), metadata).unwrap();
+        fs::create_dir_all(tmp_dir.join(STORAGE_DIR)).unwrap();
+        fs::write(tmp_dir.join(STORAGE_DIR).join(&checksum), "dll").unwrap();
+
+        let summary = verify_directory(&tmp_dir).unwrap();
+        assert_eq!(
+            summary,
+            VerifySummary {
+                verified: 2,
+                mismatched: 0,
+                missing: 0,
+                skipped: 1,
+            }
+        );
+    }
 }
@@ -0,0 +1,113 @@
+// Selects files by their modification time, e.g. only files modified within the last 30 days
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// Parses an RFC 3339 timestamp, a date and time (UTC) or a date (midnight UTC)
+pub fn parse_timestamp(value: &str) -> Result<SystemTime, String> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc).into());
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+        return Ok(time.and_utc().into());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().into());
+    }
+    Err(format!(
+        "Invalid timestamp {:?}, expected e.g. 2024-01-15, 2024-01-15 08:00:00 or 2024-01-15T08:00:00+01:00",
+        value
+    ))
+}
+
+/// Files modified before the start of the window are skipped. Files without
+/// a readable modification time are kept, so no evidence is lost
+#[derive(Debug, Clone, Default)]
+pub struct AgeFilter {
+    since: Option<SystemTime>,
+}
+
+impl AgeFilter {
+    /// modified_within in seconds (0 = disabled), modified_after as timestamp (empty = disabled).
+    /// If both are set, the later point in time applies
+    pub fn new(modified_within: i32, modified_after: &str) -> Result<Self, String> {
+        let mut since = None;
+        if modified_within > 0 {
+            since = SystemTime::now().checked_sub(Duration::from_secs(modified_within as u64));
+        }
+        if !modified_after.trim().is_empty() {
+            let after = parse_timestamp(modified_after)?;
+            since = Some(since.map_or(after, |since: SystemTime| since.max(after)));
+        }
+        Ok(Self { since })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none()
+    }
+
+    pub fn allows(&self, path: &Path) -> bool {
+        let since = match self.since {
+            Some(since) => since,
+            None => return true,
+        };
+        match path.metadata().and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified >= since,
+            Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Cleanup;
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_parse_timestamp() {
+        let date = parse_timestamp("2024-01-15").unwrap();
+        assert_eq!(parse_timestamp("2024-01-15 00:00:00").unwrap(), date);
+        assert_eq!(parse_timestamp("2024-01-15T01:00:00+01:00").unwrap(), date);
+        assert_eq!(
+            date.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_secs(1705276800)
+        );
+        assert!(parse_timestamp("15.01.2024").is_err());
+    }
+
+    #[test]
+    fn test_age_filter() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_age_filter");
+        let old = dir.join("old.log");
+        let new = dir.join("new.log");
+        fs::write(&old, "old").unwrap();
+        fs::write(&new, "new").unwrap();
+        let sixty_days = Duration::from_secs(60 * 24 * 60 * 60);
+        File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - sixty_days)
+            .unwrap();
+
+        let filter = AgeFilter::new(30 * 24 * 60 * 60, "").unwrap();
+        assert!(!filter.allows(&old));
+        assert!(filter.allows(&new));
+        // missing metadata doesn't exclude a file
+        assert!(filter.allows(&dir.join("missing.log")));
+
+        let filter = AgeFilter::new(0, "2000-01-01").unwrap();
+        assert!(filter.allows(&old));
+        // the later point in time applies
+        let filter = AgeFilter::new(30 * 24 * 60 * 60, "2000-01-01").unwrap();
+        assert!(!filter.allows(&old));
+
+        assert!(AgeFilter::new(0, "").unwrap().is_empty());
+        assert!(AgeFilter::new(0, "yesterday").is_err());
+    }
+}
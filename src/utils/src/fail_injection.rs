@@ -0,0 +1,113 @@
+// Deterministic failures of subsystems, so the error handling and the finalization of partial
+// reports can be tested. Set by the hidden --fail-injection flag of the collector, e.g. "zip_write=3,spawn"
+use log::warn;
+use std::{io, sync::Mutex};
+
+// adding the Nth file to the archive
+pub const ZIP_WRITE: &str = "zip_write";
+// encrypting the finished archive
+pub const ENCRYPTION: &str = "encryption";
+// starting the Nth child process (command, binary and terminal actions)
+pub const SPAWN: &str = "spawn";
+
+const POINTS: [&str; 3] = [ZIP_WRITE, ENCRYPTION, SPAWN];
+
+#[derive(Debug, Default)]
+struct FailInjection {
+    // point, the call that fails and the number of calls so far
+    points: Vec<(String, u64, u64)>,
+}
+
+impl FailInjection {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut points = vec![];
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (point, at) = match item.split_once('=') {
+                Some((point, at)) => match at.trim().parse::<u64>() {
+                    Ok(at) if at > 0 => (point.trim(), at),
+                    _ => {
+                        return Err(format!(
+                            "Invalid call number in {:?}, expected e.g. 3",
+                            item
+                        ))
+                    }
+                },
+                None => (item, 1),
+            };
+            if !POINTS.contains(&point) {
+                return Err(format!(
+                    "Unknown fail injection point {:?}, expected one of {}",
+                    point,
+                    POINTS.join(", ")
+                ));
+            }
+            points.push((point.to_string(), at, 0));
+        }
+        Ok(Self { points })
+    }
+
+    fn fails(&mut self, point: &str) -> bool {
+        let mut fails = false;
+        for (name, at, calls) in self.points.iter_mut().filter(|(name, ..)| name == point) {
+            *calls += 1;
+            if calls == at {
+                warn!("Injecting failure of {} (call {})", name, calls);
+                fails = true;
+            }
+        }
+        fails
+    }
+}
+
+static INJECTION: Mutex<Option<FailInjection>> = Mutex::new(None);
+
+/// Enables the failures of the spec, a comma separated list of points, each optionally with the
+/// number of the call that fails (the first one by default), e.g. "zip_write=3,encryption"
+pub fn set(spec: &str) -> Result<(), String> {
+    let injection = FailInjection::parse(spec)?;
+    if let Ok(mut current) = INJECTION.lock() {
+        *current = Some(injection);
+    }
+    Ok(())
+}
+
+/// Fails if the point is due, e.g. `fail_injection::check(SPAWN).and_then(|_| child.spawn())`.
+/// Always succeeds unless a spec was set
+pub fn check(point: &str) -> io::Result<()> {
+    let fails = match INJECTION.lock() {
+        Ok(mut injection) => injection
+            .as_mut()
+            .map(|injection| injection.fails(point))
+            .unwrap_or(false),
+        Err(_) => false,
+    };
+    match fails {
+        true => Err(io::Error::other(format!("Injected failure of {}", point))),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fail_injection() {
+        let mut injection = FailInjection::parse("zip_write=3, spawn").unwrap();
+        let zip_writes: Vec<bool> = (0..4).map(|_| injection.fails(ZIP_WRITE)).collect();
+        assert_eq!(zip_writes, [false, false, true, false]);
+        assert!(injection.fails(SPAWN));
+        assert!(!injection.fails(SPAWN));
+        assert!(!injection.fails(ENCRYPTION));
+
+        assert!(FailInjection::parse("").unwrap().points.is_empty());
+        assert!(FailInjection::parse("zip_write=0").is_err());
+        assert!(FailInjection::parse("network").is_err());
+        // nothing fails without a spec
+        assert!(check(ENCRYPTION).is_ok());
+    }
+}
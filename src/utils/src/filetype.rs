@@ -0,0 +1,324 @@
+// Detects the MIME type of a file from its first bytes (magic numbers) and its extension
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+// enough for all signatures except ISO 9660
+const SNIFF_SIZE: u64 = 512;
+// "CD001" of the first volume descriptor
+const ISO9660_OFFSET: u64 = 0x8001;
+
+// (offset, magic bytes, MIME type), the first match wins
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xFF\xD8\xFF", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"7z\xBC\xAF\x27\x1C", "application/x-7z-compressed"),
+    (0, b"Rar!\x1A\x07", "application/vnd.rar"),
+    (0, b"\x1F\x8B", "application/gzip"),
+    (257, b"ustar", "application/x-tar"),
+    (0, b"MZ", "application/x-msdownload"),
+    (0, b"\x7FELF", "application/x-executable"),
+    (0, b"\xCF\xFA\xED\xFE", "application/x-mach-binary"),
+    (0, b"\xCE\xFA\xED\xFE", "application/x-mach-binary"),
+    (
+        0,
+        b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1",
+        "application/x-ole-storage",
+    ),
+    (0, b"\x1A\x45\xDF\xA3", "video/x-matroska"),
+    (0, b"\x00\x00\x01\xBA", "video/mpeg"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"conectix", "application/x-vhd"),
+    (0, b"vhdxfile", "application/x-vhdx"),
+    (0, b"KDMV", "application/x-vmdk"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (0, b"ElfFile\x00", "application/x-ms-evtx"),
+    (0, b"regf", "application/x-ms-registry"),
+    (
+        0,
+        b"L\x00\x00\x00\x01\x14\x02\x00",
+        "application/x-ms-shortcut",
+    ),
+    (0, b"<?xml", "text/xml"),
+    (0, b"{\\rtf", "application/rtf"),
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("csv", "text/csv"),
+    ("json", "application/json"),
+    ("xml", "text/xml"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("eml", "message/rfc822"),
+    ("msg", "application/vnd.ms-outlook"),
+    ("pst", "application/vnd.ms-outlook-pst"),
+    ("exe", "application/x-msdownload"),
+    ("dll", "application/x-msdownload"),
+    ("sys", "application/x-msdownload"),
+    ("msi", "application/x-msi"),
+    ("so", "application/x-sharedlib"),
+    ("dylib", "application/x-mach-binary"),
+    ("ps1", "text/x-powershell"),
+    ("bat", "text/x-msdos-batch"),
+    ("cmd", "text/x-msdos-batch"),
+    ("vbs", "text/vbscript"),
+    ("js", "text/javascript"),
+    ("sh", "text/x-shellscript"),
+    ("py", "text/x-python"),
+    ("zip", "application/zip"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("iso", "application/x-iso9660-image"),
+    ("img", "application/x-raw-disk-image"),
+    ("vhd", "application/x-vhd"),
+    ("vhdx", "application/x-vhdx"),
+    ("vmdk", "application/x-vmdk"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("mkv", "video/x-matroska"),
+    ("webm", "video/webm"),
+    ("avi", "video/x-msvideo"),
+    ("mov", "video/quicktime"),
+    ("wmv", "video/x-ms-wmv"),
+    ("mpg", "video/mpeg"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("ogg", "audio/ogg"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("bmp", "image/bmp"),
+    ("evtx", "application/x-ms-evtx"),
+    ("lnk", "application/x-ms-shortcut"),
+    ("sqlite", "application/vnd.sqlite3"),
+];
+
+/// MIME type by the magic number at the beginning of the data
+pub fn detect_type(data: &[u8]) -> Option<&'static str> {
+    // containers with a type at a fixed position
+    if data.len() >= 12 {
+        match (&data[0..4], &data[4..8], &data[8..12]) {
+            (b"RIFF", _, b"AVI ") => return Some("video/x-msvideo"),
+            (b"RIFF", _, b"WAVE") => return Some("audio/wav"),
+            (b"RIFF", _, b"WEBP") => return Some("image/webp"),
+            (_, b"ftyp", b"qt  ") => return Some("video/quicktime"),
+            (_, b"ftyp", b"M4A ") => return Some("audio/mp4"),
+            (_, b"ftyp", _) => return Some("video/mp4"),
+            _ => {}
+        }
+    }
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| data.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| *mime)
+}
+
+/// MIME type by the extension of the file name (case-insensitive)
+pub fn type_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, mime)| *mime)
+}
+
+/// MIME type by the first bytes of the file
+pub fn sniff_file(path: &Path) -> Option<&'static str> {
+    let mut file = File::open(path).ok()?;
+    let mut data = Vec::new();
+    (&mut file).take(SNIFF_SIZE).read_to_end(&mut data).ok()?;
+    if let Some(mime) = detect_type(&data) {
+        return Some(mime);
+    }
+    let mut volume_descriptor = [0u8; 5];
+    file.seek(SeekFrom::Start(ISO9660_OFFSET)).ok()?;
+    file.read_exact(&mut volume_descriptor).ok()?;
+    match &volume_descriptor {
+        b"CD001" => Some("application/x-iso9660-image"),
+        _ => None,
+    }
+}
+
+/// Whether a MIME type matches a filter, e.g. "video/mp4" or "video/*"
+pub fn type_matches(filter: &str, mime: &str) -> bool {
+    let filter = filter.trim().to_lowercase();
+    match filter.strip_suffix("/*") {
+        Some(prefix) => mime.split('/').next() == Some(prefix),
+        None => filter == "*" || filter == mime,
+    }
+}
+
+/// Filters files by the MIME types detected by their extension and their content.
+/// A file is excluded if any of its types is excluded, and included if any of its types
+/// is included (or no includes are set)
+#[derive(Debug, Clone, Default)]
+pub struct TypeFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl TypeFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Self {
+        Self {
+            include: include.to_vec(),
+            exclude: exclude.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Filters with an invalid format (expected type/subtype, type/* or *)
+    pub fn invalid_filters(&self) -> Vec<String> {
+        self.include
+            .iter()
+            .chain(&self.exclude)
+            .filter(|filter| {
+                let filter = filter.trim();
+                filter != "*"
+                    && !matches!(filter.split_once('/'), Some((t, s)) if !t.is_empty() && !s.is_empty() && !s.contains('/'))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn allows_types(&self, types: &[&str]) -> bool {
+        let matches_any = |filters: &[String]| {
+            filters
+                .iter()
+                .any(|filter| types.iter().any(|mime| type_matches(filter, mime)))
+        };
+        if matches_any(&self.exclude) {
+            return false;
+        }
+        self.include.is_empty() || matches_any(&self.include)
+    }
+
+    /// Only reads the file if a filter is set
+    pub fn allows(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let types: Vec<&str> = [type_from_extension(path), sniff_file(path)]
+            .into_iter()
+            .flatten()
+            .collect();
+        self.allows_types(&types)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Cleanup;
+    use std::fs;
+
+    #[test]
+    fn test_detect_type() {
+        assert_eq!(detect_type(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(detect_type(b"MZ\x90\x00"), Some("application/x-msdownload"));
+        assert_eq!(
+            detect_type(b"\x7FELF\x02\x01"),
+            Some("application/x-executable")
+        );
+        assert_eq!(
+            detect_type(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            detect_type(b"\x00\x00\x00\x14ftypqt  \x00\x00\x00\x00"),
+            Some("video/quicktime")
+        );
+        assert_eq!(
+            detect_type(b"RIFF\x00\x00\x00\x00AVI LIST"),
+            Some("video/x-msvideo")
+        );
+        assert_eq!(detect_type(b"hello world"), None);
+        assert_eq!(detect_type(b""), None);
+
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(detect_type(&tar), Some("application/x-tar"));
+    }
+
+    #[test]
+    fn test_type_filter() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_type_filter");
+        fs::write(dir.join("report.pdf"), b"%PDF-1.7").unwrap();
+        // an executable disguised as a document
+        fs::write(dir.join("invoice.pdf"), b"MZ\x90\x00").unwrap();
+        fs::write(dir.join("holiday.mp4"), b"\x00\x00\x00\x20ftypisom").unwrap();
+        let mut iso = vec![0u8; ISO9660_OFFSET as usize];
+        iso.extend(b"CD001");
+        fs::write(dir.join("installer.bin"), iso).unwrap();
+        fs::write(dir.join("notes"), b"plain text").unwrap();
+
+        let filter = TypeFilter::new(
+            &[],
+            &[
+                "video/*".to_string(),
+                "application/x-iso9660-image".to_string(),
+            ],
+        );
+        assert!(filter.allows(&dir.join("report.pdf")));
+        assert!(filter.allows(&dir.join("notes")));
+        assert!(!filter.allows(&dir.join("holiday.mp4")));
+        assert!(!filter.allows(&dir.join("installer.bin")));
+
+        let filter = TypeFilter::new(
+            &[
+                "application/pdf".to_string(),
+                "application/x-msdownload".to_string(),
+            ],
+            &[],
+        );
+        assert!(filter.allows(&dir.join("report.pdf")));
+        assert!(filter.allows(&dir.join("invoice.pdf")));
+        assert!(!filter.allows(&dir.join("holiday.mp4")));
+        assert!(!filter.allows(&dir.join("notes")));
+
+        // excludes win over includes
+        let filter = TypeFilter::new(
+            &["application/pdf".to_string()],
+            &["application/x-msdownload".to_string()],
+        );
+        assert!(!filter.allows(&dir.join("invoice.pdf")));
+
+        let filter = TypeFilter::new(&["video".to_string(), "*".to_string()], &[]);
+        assert_eq!(filter.invalid_filters(), vec!["video"]);
+    }
+}
@@ -1,4 +1,11 @@
+pub mod age;
+pub mod fail_injection;
+pub mod filetype;
+pub mod links;
+pub mod messages;
 pub mod misc;
+pub mod path_cache;
+pub mod pattern;
 pub mod process;
 pub mod sanitize;
 pub mod tests;
@@ -0,0 +1,155 @@
+// Detects symbolic links and junctions, so storing files neither loops on recursive
+// junctions (e.g. C:\Users\*\Application Data) nor silently stores the content of a link target
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Target of a symbolic link or junction, None for regular files and directories
+pub fn link_target(path: &Path) -> Option<PathBuf> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::read_link(path).ok(),
+        _ => None,
+    }
+}
+
+/// How a path matched by a pattern is stored
+#[derive(Debug, PartialEq)]
+pub enum LinkCheck {
+    /// A regular file
+    Store,
+    /// A link, recorded with its target. The content of the target is
+    /// only stored if links are followed and it wasn't reached before
+    Link { target: PathBuf, store_target: bool },
+    /// Reached through a linked directory which is not followed,
+    /// or reached again through another path (e.g. a recursive junction)
+    Skip(String),
+}
+
+#[derive(Debug)]
+pub struct LinkGuard {
+    follow: bool,
+    // links below the base directory of the pattern are detected, links above (e.g. /etc -> /private/etc on macOS) are part of the pattern
+    base: PathBuf,
+    // whether a directory is a link, so each directory is only checked once
+    checked_dirs: HashMap<PathBuf, bool>,
+    // canonical paths of the files reached so far
+    visited: HashSet<PathBuf>,
+}
+
+impl LinkGuard {
+    pub fn new(follow: bool, base: impl Into<PathBuf>) -> Self {
+        Self {
+            follow,
+            base: base.into(),
+            checked_dirs: HashMap::new(),
+            visited: HashSet::new(),
+        }
+    }
+
+    /// The first link among the directories between the base directory and the path
+    fn linked_dir(&mut self, path: &Path) -> Option<PathBuf> {
+        let parent = path.parent()?;
+        if !parent.starts_with(&self.base) || parent == self.base {
+            return None;
+        }
+        for dir in parent.ancestors().take_while(|dir| *dir != self.base) {
+            let is_link = *self
+                .checked_dirs
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| link_target(dir).is_some());
+            if is_link {
+                return Some(dir.to_path_buf());
+            }
+        }
+        None
+    }
+
+    pub fn check(&mut self, path: &Path) -> LinkCheck {
+        if !self.follow {
+            if let Some(dir) = self.linked_dir(path) {
+                return LinkCheck::Skip(format!("inside linked directory {}", dir.display()));
+            }
+        }
+        let target = link_target(path);
+        let first_visit = match (self.follow, path.canonicalize()) {
+            (true, Ok(canonical)) => self.visited.insert(canonical),
+            _ => true,
+        };
+        match target {
+            Some(target) => LinkCheck::Link {
+                target,
+                store_target: self.follow && first_visit && path.is_file(),
+            },
+            None if !first_visit => LinkCheck::Skip("already reached through a link".to_string()),
+            None => LinkCheck::Store,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Cleanup;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_guard() {
+        use std::os::unix::fs::symlink;
+
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_link_guard");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("data").join("file.txt"), "content").unwrap();
+        symlink(dir.join("data").join("file.txt"), dir.join("file_link")).unwrap();
+        symlink(dir.join("missing"), dir.join("broken_link")).unwrap();
+        // recursive link, like the Application Data junction on Windows
+        symlink(dir.join("data"), dir.join("data").join("loop")).unwrap();
+        let looped = dir.join("data").join("loop").join("loop").join("file.txt");
+
+        let mut guard = LinkGuard::new(false, &dir);
+        assert_eq!(
+            guard.check(&dir.join("data").join("file.txt")),
+            LinkCheck::Store
+        );
+        assert_eq!(
+            guard.check(&dir.join("file_link")),
+            LinkCheck::Link {
+                target: dir.join("data").join("file.txt"),
+                store_target: false
+            }
+        );
+        assert!(matches!(guard.check(&looped), LinkCheck::Skip(_)));
+        assert!(matches!(
+            guard.check(&dir.join("broken_link")),
+            LinkCheck::Link {
+                store_target: false,
+                ..
+            }
+        ));
+
+        // followed links are stored once, however often they are reached
+        let mut guard = LinkGuard::new(true, &dir);
+        assert_eq!(
+            guard.check(&dir.join("data").join("file.txt")),
+            LinkCheck::Store
+        );
+        assert!(matches!(guard.check(&looped), LinkCheck::Skip(_)));
+        assert!(matches!(
+            guard.check(&dir.join("file_link")),
+            LinkCheck::Link {
+                store_target: false,
+                ..
+            }
+        ));
+        let mut guard = LinkGuard::new(true, &dir);
+        assert!(matches!(
+            guard.check(&dir.join("file_link")),
+            LinkCheck::Link {
+                store_target: true,
+                ..
+            }
+        ));
+    }
+}
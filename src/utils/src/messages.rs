@@ -0,0 +1,154 @@
+// Catalog of the messages shown to the person running the collector, who is often a local admin
+// and not the responder. Selected by the language of the config, English if a message is missing
+use std::{collections::HashMap, sync::RwLock};
+
+pub const PRESS_ANY_KEY_TO_EXIT: &str = "press_any_key_to_exit";
+pub const PRESS_ANY_KEY_TO_CONTINUE: &str = "press_any_key_to_continue";
+pub const TERMINAL_EXIT: &str = "terminal_exit";
+// {command} and {name} are replaced
+pub const TERMINAL_BLOCKED: &str = "terminal_blocked";
+pub const TERMINAL_WARNED: &str = "terminal_warned";
+pub const GRANT_FULL_DISK_ACCESS: &str = "grant_full_disk_access";
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+const EN: [(&str, &str); 6] = [
+    (PRESS_ANY_KEY_TO_EXIT, "Press any key to exit..."),
+    (
+        PRESS_ANY_KEY_TO_CONTINUE,
+        "Press any key to continue with...",
+    ),
+    (TERMINAL_EXIT, "Type 'exit' to exit the terminal session"),
+    (
+        TERMINAL_BLOCKED,
+        "Blocked command {command}: {name} is not permitted",
+    ),
+    (
+        TERMINAL_WARNED,
+        "Command {command} uses {name}, which may alter evidence",
+    ),
+    (
+        GRANT_FULL_DISK_ACCESS,
+        "Grant Full Disk Access to the collector (or the terminal running it) in System Settings > Privacy & Security > Full Disk Access, then press any key to continue...",
+    ),
+];
+
+const DE: [(&str, &str); 6] = [
+    (
+        PRESS_ANY_KEY_TO_EXIT,
+        "Beliebige Taste drücken, um das Programm zu beenden...",
+    ),
+    (
+        PRESS_ANY_KEY_TO_CONTINUE,
+        "Beliebige Taste drücken, um fortzufahren...",
+    ),
+    (
+        TERMINAL_EXIT,
+        "'exit' eingeben, um die Terminal-Sitzung zu beenden",
+    ),
+    (
+        TERMINAL_BLOCKED,
+        "Befehl {command} blockiert: {name} ist nicht erlaubt",
+    ),
+    (
+        TERMINAL_WARNED,
+        "Befehl {command} verwendet {name}, was Beweise verändern kann",
+    ),
+    (
+        GRANT_FULL_DISK_ACCESS,
+        "Dem Collector (oder dem ausführenden Terminal) unter Systemeinstellungen > Datenschutz & Sicherheit > Festplattenvollzugriff den Zugriff erlauben, dann beliebige Taste drücken, um fortzufahren...",
+    ),
+];
+
+/// Messages shipped with the toolkit, further languages are added by a deployment
+pub const BUILTIN_LANGUAGES: [&str; 2] = ["en", "de"];
+
+static CATALOG: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+
+fn builtin(language: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    match language.to_lowercase().as_str() {
+        "en" => Some(&EN),
+        "de" => Some(&DE),
+        _ => None,
+    }
+}
+
+/// Selects the language. The custom messages (e.g. of a deployment) take precedence over the
+/// built-in ones, so they can add a language or reword single messages
+pub fn set_language(language: &str, custom: HashMap<String, String>) -> Result<(), String> {
+    let builtin = builtin(language);
+    if builtin.is_none() && custom.is_empty() {
+        return Err(format!(
+            "Unknown language {:?}, expected one of {} or a catalog of its messages",
+            language,
+            BUILTIN_LANGUAGES.join(", ")
+        ));
+    }
+    let mut catalog: HashMap<String, String> = builtin
+        .unwrap_or_default()
+        .iter()
+        .map(|(id, text)| (id.to_string(), text.to_string()))
+        .collect();
+    catalog.extend(custom);
+    if let Ok(mut current) = CATALOG.write() {
+        *current = Some(catalog);
+    }
+    Ok(())
+}
+
+fn lookup(catalog: Option<&HashMap<String, String>>, id: &str) -> String {
+    catalog
+        .and_then(|catalog| catalog.get(id).cloned())
+        .or_else(|| {
+            EN.iter()
+                .find(|(key, _)| *key == id)
+                .map(|(_, text)| text.to_string())
+        })
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// The message in the selected language
+pub fn text(id: &str) -> String {
+    match CATALOG.read() {
+        Ok(catalog) => lookup(catalog.as_ref(), id),
+        Err(_) => lookup(None, id),
+    }
+}
+
+/// The message with its placeholders replaced, e.g. `format(TERMINAL_BLOCKED, &[("command", "rm")])`
+pub fn format(id: &str, args: &[(&str, &str)]) -> String {
+    args.iter().fold(text(id), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let mut catalog = HashMap::new();
+        catalog.insert(TERMINAL_EXIT.to_string(), "Tapez 'exit'".to_string());
+        assert_eq!(lookup(Some(&catalog), TERMINAL_EXIT), "Tapez 'exit'");
+        // missing messages fall back to English
+        assert_eq!(
+            lookup(Some(&catalog), PRESS_ANY_KEY_TO_EXIT),
+            "Press any key to exit..."
+        );
+        assert_eq!(lookup(None, "unknown"), "unknown");
+
+        assert!(set_language("xx", HashMap::new()).is_err());
+        // every message has a German translation
+        for (id, _) in EN {
+            assert!(DE.iter().any(|(key, _)| *key == id), "{}", id);
+        }
+        assert_eq!(
+            format(
+                TERMINAL_BLOCKED,
+                &[("command", "\"rm -rf /\""), ("name", "\"rm\"")]
+            ),
+            "Blocked command \"rm -rf /\": \"rm\" is not permitted"
+        );
+    }
+}
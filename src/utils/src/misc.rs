@@ -1,57 +1,217 @@
+use crate::path_cache::{CachedPath, PathCache, Recording};
+use crate::pattern::expand_pattern;
 use glob::{glob_with, MatchOptions};
-use log::{debug, error};
+use log::{debug, error, warn};
 use openssl::sha::Sha1;
+use std::any::Any;
+use std::collections::HashSet;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
 
-/// Get files by pattern
-pub fn get_files_by_pattern(
+/// Iterate over the files matching a pattern.
+/// Matches are yielded as the file system is traversed, so nothing is buffered
+pub fn iter_files_by_pattern(
     pattern: &str,
     case_sensitive: bool,
-) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    // Create a vector to store the matched files
-    let mut files = Vec::new();
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    iter_paths_by_pattern(pattern, case_sensitive, false)
+}
+
+// links are yielded even if their target is a directory or doesn't exist
+fn iter_paths_by_pattern(
+    pattern: &str,
+    case_sensitive: bool,
+    include_links: bool,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    Ok(glob_paths(pattern, case_sensitive)?
+        .filter(move |path| path.is_file || (include_links && path.is_symlink))
+        .map(|path| path.path))
+}
+
+// all files and links matching the pattern, along with their file type
+fn glob_paths(
+    pattern: &str,
+    case_sensitive: bool,
+) -> Result<impl Iterator<Item = CachedPath> + Send, Box<dyn std::error::Error>> {
+    let options = MatchOptions {
+        case_sensitive,
+        ..MatchOptions::default()
+    };
 
-    let mut options = MatchOptions::default();
-    options.case_sensitive = case_sensitive;
+    // braces and wildcard drive letters expand to several glob patterns
+    let expanded = expand_pattern(pattern, case_sensitive);
+    if expanded.len() > 1 {
+        debug!("Pattern {:?} expanded to: {:?}", pattern, expanded);
+    }
+    let mut paths = Vec::new();
+    for expanded_pattern in &expanded {
+        paths.push(glob_with(expanded_pattern, options)?);
+    }
 
-    // Iterate over the matching paths
-    for entry in glob_with(pattern, options)? {
-        match entry {
+    // alternatives may overlap (e.g. {*.log,auth.*}), so each file is only yielded once
+    let deduplicate = expanded.len() > 1;
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let pattern_str = pattern.to_string();
+    Ok(paths
+        .into_iter()
+        .flatten()
+        .filter_map(move |entry| match entry {
+            // Only yield files, directories are traversed by the pattern itself
             Ok(path) => {
-                // Call add_file for each matched file
-                if path.is_file() {
-                    files.push(path);
-                }
+                let is_file = path.is_file();
+                let is_symlink = path.is_symlink();
+                (is_file || is_symlink).then_some(CachedPath {
+                    path,
+                    is_file,
+                    is_symlink,
+                })
             }
-            Err(e) => error!(
-                "Error matching pattern.\nError: {}\nPattern: {}",
-                e, pattern
-            ),
-        }
-    }
+            Err(e) => {
+                error!(
+                    "Error matching pattern.\nError: {}\nPattern: {}",
+                    e, pattern_str
+                );
+                None
+            }
+        })
+        .filter(move |path| !deduplicate || seen.insert(path.path.clone())))
+}
 
-    // Return the vector of matched files
-    Ok(files)
+// takes the expansion of the pattern from the cache, or records it for the following actions
+fn iter_paths_by_pattern_cached(
+    pattern: &str,
+    case_sensitive: bool,
+    include_links: bool,
+    cache: Option<&Arc<PathCache>>,
+) -> Result<Box<dyn Iterator<Item = PathBuf> + Send>, Box<dyn std::error::Error>> {
+    let paths: Box<dyn Iterator<Item = CachedPath> + Send> = match cache {
+        None => Box::new(glob_paths(pattern, case_sensitive)?),
+        Some(cache) => match cache.get(pattern, case_sensitive) {
+            Some(paths) => {
+                debug!("Using the cached expansion of pattern {:?}", pattern);
+                Box::new((0..paths.len()).map(move |i| paths[i].clone()))
+            }
+            None => Box::new(Recording::new(
+                glob_paths(pattern, case_sensitive)?,
+                cache.clone(),
+                pattern,
+                case_sensitive,
+            )),
+        },
+    };
+    Ok(Box::new(
+        paths
+            .filter(move |path| path.is_file || (include_links && path.is_symlink))
+            .map(|path| path.path),
+    ))
 }
 
-pub fn get_files_by_patterns(
-    patterns: Vec<String>,
+/// Iterate over the files matching a pattern, but stop expanding the pattern after max_files matches (0 = unlimited)
+pub fn iter_files_by_pattern_limited(
+    pattern: &str,
     case_sensitive: bool,
-) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    // Create a vector to store the matched files
-    let mut files = Vec::new();
+    max_files: usize,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    iter_paths_by_pattern_limited(pattern, case_sensitive, max_files, false, None)
+}
+
+/// Same as `iter_files_by_pattern_limited`, but symbolic links and junctions are
+/// yielded as well, even if they point to a directory or their target doesn't exist
+pub fn iter_files_and_links_by_pattern_limited(
+    pattern: &str,
+    case_sensitive: bool,
+    max_files: usize,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    iter_paths_by_pattern_limited(pattern, case_sensitive, max_files, true, None)
+}
+
+/// Same as `iter_files_by_pattern_limited`, but the expansion of the pattern is shared
+/// through the cache with other actions of the workflow run
+pub fn iter_files_by_pattern_cached(
+    pattern: &str,
+    case_sensitive: bool,
+    max_files: usize,
+    cache: Option<&Arc<PathCache>>,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    iter_paths_by_pattern_limited(pattern, case_sensitive, max_files, false, cache)
+}
+
+/// Same as `iter_files_and_links_by_pattern_limited`, but the expansion of the pattern is
+/// shared through the cache with other actions of the workflow run
+pub fn iter_files_and_links_by_pattern_cached(
+    pattern: &str,
+    case_sensitive: bool,
+    max_files: usize,
+    cache: Option<&Arc<PathCache>>,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    iter_paths_by_pattern_limited(pattern, case_sensitive, max_files, true, cache)
+}
+
+fn iter_paths_by_pattern_limited(
+    pattern: &str,
+    case_sensitive: bool,
+    max_files: usize,
+    include_links: bool,
+    cache: Option<&Arc<PathCache>>,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    let pattern_str = pattern.to_string();
+    let mut count: usize = 0;
 
-    // Iterate over the patterns
+    // an expansion stopped by max_files is incomplete, so it is never cached
+    Ok(
+        iter_paths_by_pattern_cached(pattern, case_sensitive, include_links, cache)?.take_while(move |_| {
+            // Overly broad patterns (e.g. C:/**/*) might otherwise run for hours
+            if max_files != 0 && count >= max_files {
+                warn!(
+                    "!!! Pattern {:?} matched more than {} files: skipping all remaining files. Consider narrowing the pattern or raising max_files !!!",
+                    pattern_str, max_files
+                );
+                return false;
+            }
+            count += 1;
+            true
+        }),
+    )
+}
+
+/// Iterate over the files matching any of the patterns (in order of the patterns)
+pub fn iter_files_by_patterns(
+    patterns: Vec<String>,
+    case_sensitive: bool,
+) -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
+    // Parse all patterns upfront, so invalid patterns are reported before any file is yielded
+    let mut iterators = Vec::new();
     for pattern in patterns {
         debug!("Searching for pattern: {:?}", pattern);
-        // Call get_files_by_pattern for each pattern
-        let mut pattern_files = get_files_by_pattern(&pattern, case_sensitive)?;
-        files.append(&mut pattern_files);
+        iterators.push(iter_files_by_pattern(&pattern, case_sensitive)?);
     }
 
-    // Return the vector of matched files
-    Ok(files)
+    Ok(iterators.into_iter().flatten())
+}
+
+/// Get files by pattern
+pub fn get_files_by_pattern(
+    pattern: &str,
+    case_sensitive: bool,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    Ok(iter_files_by_pattern(pattern, case_sensitive)?.collect())
+}
+
+/// Get files by pattern, but stop expanding the pattern after max_files matches (0 = unlimited)
+pub fn get_files_by_pattern_limited(
+    pattern: &str,
+    case_sensitive: bool,
+    max_files: usize,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    Ok(iter_files_by_pattern_limited(pattern, case_sensitive, max_files)?.collect())
+}
+
+pub fn get_files_by_patterns(
+    patterns: Vec<String>,
+    case_sensitive: bool,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    Ok(iter_files_by_patterns(patterns, case_sensitive)?.collect())
 }
 
 pub fn file_name_checksum(abs_file_path: &str) -> String {
@@ -73,3 +233,112 @@ pub fn wait_for_user_input(message: &str) {
     std::io::stdout().flush().unwrap();
     let _ = std::io::stdin().read(&mut [0u8]).unwrap();
 }
+
+/// Message of a caught panic, e.g. the argument of panic!()
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Unknown panic message".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Cleanup;
+
+    #[test]
+    fn test_get_files_by_pattern_limited() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_get_files_by_pattern_limited");
+        cleanup.create_files(&tmp_dir, vec!["a.txt", "b.txt", "c.txt", "d.log"]);
+
+        let pattern = tmp_dir.join("*.txt").to_str().unwrap().to_string();
+        let files = get_files_by_pattern_limited(&pattern, true, 2).unwrap();
+        assert_eq!(files.len(), 2);
+
+        let files = get_files_by_pattern_limited(&pattern, true, 0).unwrap();
+        assert_eq!(files.len(), 3);
+    }
+
+    #[test]
+    fn test_iter_files_by_patterns() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_iter_files_by_patterns");
+        cleanup.create_files(&tmp_dir, vec!["a.txt", "b.log", "sub/c.txt"]);
+
+        let patterns = vec![
+            tmp_dir.join("**/*.txt").to_str().unwrap().to_string(),
+            tmp_dir.join("*.log").to_str().unwrap().to_string(),
+        ];
+        let mut files = iter_files_by_patterns(patterns, true).unwrap();
+        // the first match is available without traversing the remaining patterns
+        assert!(files.next().is_some());
+        assert_eq!(files.count(), 2);
+
+        // invalid patterns are reported before any file is yielded
+        let patterns = vec!["[".to_string()];
+        assert!(iter_files_by_patterns(patterns, true).is_err());
+    }
+
+    #[test]
+    fn test_get_files_by_pattern_braces() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_get_files_by_pattern_braces");
+        cleanup.create_files(
+            &tmp_dir,
+            vec!["Chrome/History", "Edge/History", "Firefox/History", "a.log"],
+        );
+
+        let pattern = tmp_dir.join("{Chrome,edge}/[^x]istory");
+        let files = get_files_by_pattern(pattern.to_str().unwrap(), false).unwrap();
+        assert_eq!(files.len(), 2);
+
+        // overlapping alternatives yield each file once
+        let pattern = tmp_dir.join("{*.log,a.*}");
+        let files = get_files_by_pattern(pattern.to_str().unwrap(), true).unwrap();
+        assert_eq!(files.len(), 1);
+    }
+
+    #[test]
+    fn test_iter_files_by_pattern_cached() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_iter_files_by_pattern_cached");
+        cleanup.create_files(&tmp_dir, vec!["a.txt", "b.txt", "c.txt"]);
+        let pattern = tmp_dir.join("*.txt").to_str().unwrap().to_string();
+        let cache = Arc::new(PathCache::default());
+
+        // stopped by max_files, so the expansion is incomplete and not cached
+        let files = iter_files_by_pattern_cached(&pattern, true, 2, Some(&cache)).unwrap();
+        assert_eq!(files.count(), 2);
+        assert!(cache.get(&pattern, true).is_none());
+
+        let files = iter_files_by_pattern_cached(&pattern, true, 0, Some(&cache)).unwrap();
+        assert_eq!(files.count(), 3);
+
+        // files created after the expansion are missing until the cache is cleared
+        cleanup.create_files(&tmp_dir, vec!["d.txt"]);
+        let files = iter_files_and_links_by_pattern_cached(&pattern, true, 0, Some(&cache));
+        assert_eq!(files.unwrap().count(), 3);
+        let files = iter_files_by_pattern_cached(&pattern, true, 2, Some(&cache)).unwrap();
+        assert_eq!(files.count(), 2);
+        assert_eq!(cache.hits(), 2);
+
+        cache.clear();
+        let files = iter_files_by_pattern_cached(&pattern, true, 0, Some(&cache)).unwrap();
+        assert_eq!(files.count(), 4);
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "static");
+        let payload = std::panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "formatted 1");
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "Unknown panic message");
+    }
+}
@@ -0,0 +1,125 @@
+// Expansions of the glob patterns, shared by the store, yara and grep actions of a workflow run.
+// Overlapping actions (e.g. all of them matching C:\Windows\Temp) would otherwise traverse the same
+// directories and stat the same files again. The runner clears the cache before any other action,
+// as it may change the file system
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// expansions with more paths are not cached, so a broad pattern (e.g. C:/**/*) can't fill the memory
+pub const MAX_CACHED_PATHS: usize = 100_000;
+// of all cached expansions
+const MAX_TOTAL_PATHS: usize = 10 * MAX_CACHED_PATHS;
+
+/// A path matched by a glob pattern along with its file type
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedPath {
+    pub path: PathBuf,
+    pub is_file: bool,
+    pub is_symlink: bool,
+}
+
+#[derive(Debug, Default)]
+struct Expansions {
+    // by the glob pattern and whether it is case-sensitive
+    by_pattern: HashMap<(String, bool), Arc<Vec<CachedPath>>>,
+    paths: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct PathCache {
+    expansions: Mutex<Expansions>,
+    hits: AtomicU64,
+}
+
+impl PathCache {
+    pub fn get(&self, pattern: &str, case_sensitive: bool) -> Option<Arc<Vec<CachedPath>>> {
+        let expansions = self.expansions.lock().unwrap();
+        let paths = expansions
+            .by_pattern
+            .get(&(pattern.to_string(), case_sensitive))
+            .cloned();
+        if paths.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        paths
+    }
+
+    /// Caches the complete expansion of a pattern, unless it is too large
+    pub fn insert(&self, pattern: &str, case_sensitive: bool, paths: Vec<CachedPath>) {
+        let mut expansions = self.expansions.lock().unwrap();
+        if paths.len() > MAX_CACHED_PATHS || expansions.paths + paths.len() > MAX_TOTAL_PATHS {
+            return;
+        }
+        expansions.paths += paths.len();
+        let replaced = expansions
+            .by_pattern
+            .insert((pattern.to_string(), case_sensitive), Arc::new(paths));
+        if let Some(replaced) = replaced {
+            expansions.paths -= replaced.len();
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut expansions = self.expansions.lock().unwrap();
+        expansions.by_pattern.clear();
+        expansions.paths = 0;
+    }
+
+    /// Number of expansions taken from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Records the matches of a pattern while it is traversed. The expansion is only cached once
+/// the traversal is complete, not if it was stopped early (e.g. by max_files)
+pub struct Recording<I> {
+    matches: I,
+    cache: Arc<PathCache>,
+    pattern: String,
+    case_sensitive: bool,
+    // None once the expansion became too large
+    paths: Option<Vec<CachedPath>>,
+}
+
+impl<I> Recording<I> {
+    pub fn new(matches: I, cache: Arc<PathCache>, pattern: &str, case_sensitive: bool) -> Self {
+        Self {
+            matches,
+            cache,
+            pattern: pattern.to_string(),
+            case_sensitive,
+            paths: Some(vec![]),
+        }
+    }
+}
+
+impl<I: Iterator<Item = CachedPath>> Iterator for Recording<I> {
+    type Item = CachedPath;
+
+    fn next(&mut self) -> Option<CachedPath> {
+        match self.matches.next() {
+            Some(path) => {
+                if let Some(paths) = &mut self.paths {
+                    match paths.len() < MAX_CACHED_PATHS {
+                        true => paths.push(path.clone()),
+                        false => self.paths = None,
+                    }
+                }
+                Some(path)
+            }
+            None => {
+                if let Some(paths) = self.paths.take() {
+                    self.cache.insert(&self.pattern, self.case_sensitive, paths);
+                }
+                None
+            }
+        }
+    }
+}
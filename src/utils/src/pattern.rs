@@ -0,0 +1,313 @@
+// Glob patterns behave the same on all platforms: braces are expanded, backslashes
+// are separators and drive letters may contain wildcards (e.g. *:/Users/**)
+
+/// Expands `{a,b}` alternatives, including nested ones. Braces inside character
+/// classes and unbalanced braces are kept as they are.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut in_class = false;
+    let mut start = None;
+    let mut depth = 0;
+    // position of the commas separating the alternatives of the outermost braces
+    let mut commas = vec![];
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '[' if !in_class => in_class = true,
+            ']' if in_class => in_class = false,
+            '{' if !in_class => {
+                if depth == 0 {
+                    start = Some(i);
+                    commas.clear();
+                }
+                depth += 1;
+            }
+            ',' if !in_class && depth == 1 => commas.push(i),
+            '}' if !in_class && depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let start = start.unwrap();
+                    let prefix: String = chars[..start].iter().collect();
+                    let suffix: String = chars[i + 1..].iter().collect();
+                    let mut bounds = vec![start];
+                    bounds.extend(&commas);
+                    bounds.push(i);
+                    let mut expanded = vec![];
+                    for window in bounds.windows(2) {
+                        let alternative: String = chars[window[0] + 1..window[1]].iter().collect();
+                        // the alternative and the rest of the pattern may contain further braces
+                        for rest in expand_braces(&format!("{}{}", alternative, suffix)) {
+                            expanded.push(format!("{}{}", prefix, rest));
+                        }
+                    }
+                    return expanded;
+                }
+            }
+            _ => {}
+        }
+    }
+    vec![pattern.to_string()]
+}
+
+/// Backslashes are separators on all platforms, `[^...]` is the same as `[!...]`
+pub fn normalize_pattern(pattern: &str) -> String {
+    pattern.replace('\\', "/").replace("[^", "[!")
+}
+
+/// Uses the separator of the platform (`\` on Windows, `/` elsewhere) and removes repeated
+/// separators, e.g. of `${USER_HOME}/` if the variable ends with one. UNC and device paths
+/// (`\\server\share`, `\\?\C:\`) keep their two leading separators, a drive letter written
+/// like `/C:/Windows` loses the one before it on Windows
+pub fn normalize_separators(path: &str, windows: bool) -> String {
+    let separator = if windows { '\\' } else { '/' };
+    let is_separator = |c: char| c == '/' || c == '\\';
+    let chars: Vec<char> = path.chars().collect();
+    let path = match chars.as_slice() {
+        [first, letter, ':', ..]
+            if windows && is_separator(*first) && letter.is_ascii_alphabetic() =>
+        {
+            &path[1..]
+        }
+        _ => path,
+    };
+    let mut result = String::with_capacity(path.len());
+    for (i, c) in path.chars().enumerate() {
+        match is_separator(c) {
+            true if i == 1 || !result.ends_with(separator) => result.push(separator),
+            true => {}
+            false => result.push(c),
+        }
+    }
+    result
+}
+
+/// Normalizes the separators of each line, e.g. of the patterns of a store action
+pub fn normalize_separators_lines(text: &str, windows: bool) -> String {
+    text.split('\n')
+        .map(|line| normalize_separators(line, windows))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The directory before the first wildcard of a pattern, e.g. `/var/log` for `/var/log/**/*.log`
+pub fn pattern_base(pattern: &str) -> String {
+    let pattern = normalize_pattern(pattern);
+    let mut components: Vec<&str> = pattern.split('/').collect();
+    // the last component names the files
+    components.pop();
+    let literal: Vec<&str> = components
+        .into_iter()
+        .take_while(|component| !component.contains(['*', '?', '[', '{']))
+        .collect();
+    let base = literal.join("/");
+    // the root of a file system or drive keeps its separator
+    match (base.is_empty() && pattern.starts_with('/')) || base.ends_with(':') {
+        true => format!("{}/", base),
+        false => base,
+    }
+}
+
+/// Turns letters outside of character classes into classes of both cases (e.g. `[eE]`).
+/// The glob crate only compares wildcard components case-insensitively, literal
+/// components are looked up as they are, which fails on case-sensitive file systems.
+pub fn case_insensitive_literals(pattern: &str) -> String {
+    let mut result = String::with_capacity(pattern.len() * 4);
+    let mut in_class = false;
+    for c in pattern.chars() {
+        match c {
+            '[' if !in_class => in_class = true,
+            ']' if in_class => in_class = false,
+            _ => {}
+        }
+        if !in_class && c.is_ascii_alphabetic() {
+            result.push('[');
+            result.push(c.to_ascii_lowercase());
+            result.push(c.to_ascii_uppercase());
+            result.push(']');
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn has_wildcard(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Replaces a wildcard drive letter (e.g. `*:` or `[CD]:`) with each of the drives
+pub fn expand_drives(pattern: &str, drives: &[char]) -> Vec<String> {
+    let (drive, rest) = match pattern.split_once(':') {
+        Some((drive, rest)) if has_wildcard(drive) && !drive.contains('/') => (drive, rest),
+        _ => return vec![pattern.to_string()],
+    };
+    let drive_pattern = match glob::Pattern::new(drive) {
+        Ok(drive_pattern) => drive_pattern,
+        Err(_) => return vec![pattern.to_string()],
+    };
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    drives
+        .iter()
+        .filter(|letter| drive_pattern.matches_with(&letter.to_string(), options))
+        .map(|letter| format!("{}:{}", letter, rest))
+        .collect()
+}
+
+/// Letters of the existing drives
+#[cfg(target_os = "windows")]
+pub fn drive_letters() -> Vec<char> {
+    ('A'..='Z')
+        .filter(|letter| std::path::Path::new(&format!("{}:/", letter)).exists())
+        .collect()
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn drive_letters() -> Vec<char> {
+    vec![]
+}
+
+/// The glob patterns to match instead of the pattern
+pub fn expand_pattern(pattern: &str, case_sensitive: bool) -> Vec<String> {
+    let mut drives: Option<Vec<char>> = None;
+    let mut patterns: Vec<String> = vec![];
+    for expanded in expand_braces(pattern) {
+        let mut expanded = normalize_pattern(&expanded);
+        // Windows and macOS file systems are case-insensitive by default
+        if !case_sensitive && cfg!(target_os = "linux") {
+            expanded = case_insensitive_literals(&expanded);
+        }
+        let expanded = match cfg!(target_os = "windows") {
+            true => expand_drives(&expanded, drives.get_or_insert_with(drive_letters)),
+            false => vec![expanded],
+        };
+        for expanded in expanded {
+            if !patterns.contains(&expanded) {
+                patterns.push(expanded);
+            }
+        }
+    }
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(
+            expand_braces("C:/Users/*/AppData/Local/{Google/Chrome,Microsoft/Edge}/User Data"),
+            vec![
+                "C:/Users/*/AppData/Local/Google/Chrome/User Data",
+                "C:/Users/*/AppData/Local/Microsoft/Edge/User Data"
+            ]
+        );
+        assert_eq!(
+            expand_braces("/var/log/{auth,sys{log,tem}}.{log,1}"),
+            vec![
+                "/var/log/auth.log",
+                "/var/log/auth.1",
+                "/var/log/syslog.log",
+                "/var/log/syslog.1",
+                "/var/log/system.log",
+                "/var/log/system.1"
+            ]
+        );
+        // an empty alternative
+        assert_eq!(expand_braces("a{,.bak}"), vec!["a", "a.bak"]);
+        // no braces, unbalanced braces and braces in character classes are kept
+        assert_eq!(expand_braces("/tmp/*.txt"), vec!["/tmp/*.txt"]);
+        assert_eq!(expand_braces("/tmp/{a,b"), vec!["/tmp/{a,b"]);
+        assert_eq!(expand_braces("/tmp/[{]a,b}"), vec!["/tmp/[{]a,b}"]);
+    }
+
+    #[test]
+    fn test_expand_drives() {
+        let drives = ['C', 'D', 'E'];
+        assert_eq!(
+            expand_drives("*:/Users/**/NTUSER.DAT", &drives),
+            vec![
+                "C:/Users/**/NTUSER.DAT",
+                "D:/Users/**/NTUSER.DAT",
+                "E:/Users/**/NTUSER.DAT"
+            ]
+        );
+        assert_eq!(expand_drives("[c-d]:/x", &drives), vec!["C:/x", "D:/x"]);
+        assert_eq!(expand_drives("C:/x", &drives), vec!["C:/x"]);
+        assert_eq!(expand_drives("/tmp/*:x", &drives), vec!["/tmp/*:x"]);
+        assert!(expand_drives("*:/x", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_case_insensitive_literals() {
+        assert_eq!(
+            case_insensitive_literals("/Ab/*.[a-z]1"),
+            "/[aA][bB]/*.[a-z]1"
+        );
+    }
+
+    #[test]
+    fn test_normalize_pattern() {
+        assert_eq!(normalize_pattern("/tmp/[^a]*"), "/tmp/[!a]*");
+        assert_eq!(normalize_pattern("C:\\Windows\\*.log"), "C:/Windows/*.log");
+    }
+
+    #[test]
+    fn test_pattern_base() {
+        assert_eq!(pattern_base("/var/log/**/*.log"), "/var/log");
+        assert_eq!(pattern_base("/etc/passwd"), "/etc");
+        assert_eq!(pattern_base("/*.txt"), "/");
+        assert_eq!(pattern_base("C:\\Users\\*\\NTUSER.DAT"), "C:/Users");
+        assert_eq!(pattern_base("C:/*"), "C:/");
+        assert_eq!(pattern_base("*:/Users/**"), "");
+        assert_eq!(pattern_base("/home/{alice,bob}/.ssh/*"), "/home");
+        assert_eq!(pattern_base("\\\\server\\share\\*.log"), "//server/share");
+    }
+
+    #[test]
+    fn test_normalize_separators() {
+        // drive letters
+        assert_eq!(
+            normalize_separators("C:/Windows//System32/", true),
+            "C:\\Windows\\System32\\"
+        );
+        assert_eq!(
+            normalize_separators("/c:/Users/*/NTUSER.DAT", true),
+            "c:\\Users\\*\\NTUSER.DAT"
+        );
+        assert_eq!(normalize_separators("C:/", true), "C:\\");
+        assert_eq!(
+            normalize_separators("*:\\**\\*.vhdx", false),
+            "*:/**/*.vhdx"
+        );
+        // UNC and device paths
+        assert_eq!(
+            normalize_separators("//server/share//logs/*.log", true),
+            "\\\\server\\share\\logs\\*.log"
+        );
+        assert_eq!(
+            normalize_separators("\\\\?\\C:\\Windows", true),
+            "\\\\?\\C:\\Windows"
+        );
+        assert_eq!(
+            normalize_separators("\\\\server\\share\\file", false),
+            "//server/share/file"
+        );
+        // a variable ending with a separator
+        assert_eq!(
+            normalize_separators("/root//.ssh\\authorized_keys", false),
+            "/root/.ssh/authorized_keys"
+        );
+        assert_eq!(
+            normalize_separators("relative/path", true),
+            "relative\\path"
+        );
+        assert_eq!(normalize_separators("", true), "");
+        assert_eq!(
+            normalize_separators_lines("/var/log/*.log\n\nC:\\Temp\\*\n", false),
+            "/var/log/*.log\n\nC:/Temp/*\n"
+        );
+    }
+}
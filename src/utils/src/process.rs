@@ -1,6 +1,41 @@
 use log::error;
+use std::{env, path::PathBuf};
 use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 
+/// Searches the PATH for a program (with the extensions of PATHEXT on Windows).
+/// Paths containing a separator are checked as they are
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let program = program.trim();
+    if program.is_empty() {
+        return None;
+    }
+    let extensions: Vec<String> = match cfg!(windows) {
+        true => env::var("PATHEXT")
+            .unwrap_or(".COM;.EXE;.BAT;.CMD".to_string())
+            .split(';')
+            .map(|extension| extension.to_lowercase())
+            .collect(),
+        false => vec![],
+    };
+    let candidates = |path: PathBuf| {
+        let mut candidates = vec![path.clone()];
+        for extension in &extensions {
+            candidates.push(PathBuf::from(format!("{}{}", path.display(), extension)));
+        }
+        candidates
+    };
+
+    if program.contains(['/', '\\']) {
+        return candidates(PathBuf::from(program))
+            .into_iter()
+            .find(|path| path.is_file());
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .flat_map(|dir| candidates(dir.join(program)))
+        .find(|path| path.is_file())
+}
+
 pub async fn print_stream<R: AsyncRead + Unpin>(stream: Option<R>) {
     if let Some(stream) = stream {
         let mut reader = BufReader::new(stream);
@@ -53,3 +88,21 @@ pub async fn read_stream<R: AsyncRead + Unpin>(stream: Option<R>, print: bool) -
         String::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_program() {
+        let shell = match cfg!(windows) {
+            true => "cmd",
+            false => "sh",
+        };
+        let path = find_program(shell).unwrap();
+        assert!(path.is_absolute());
+        assert_eq!(find_program(&path.to_string_lossy()), Some(path));
+        assert!(find_program("ir-toolkit-missing-program").is_none());
+        assert!(find_program("").is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use actions::{usage::ResourceUsage, ActionResult};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use config::workflow::Action;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// Name of the structured action log inside action_output
+pub const ACTION_LOG_FILE: &str = "actions.jsonl";
+
+/// One line of the structured action log, written once a step has finished
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ActionLogEntry {
+    // see report::Report::run_id
+    #[serde(default)]
+    pub run_id: String,
+    // 1-based position of the step in the workflow
+    pub step: usize,
+    pub action: String,
+    pub action_type: String,
+    // base name of the files the step wrote to action_output
+    pub output_name: String,
+    pub started: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub execution_time: f64,
+    pub error_message: Option<String>,
+    // e.g. timeout or nonzero_exit, see ErrorCategory
+    #[serde(default)]
+    pub error_category: Option<String>,
+    // counts reported by the action, e.g. the number and size of the stored files
+    #[serde(default)]
+    pub items_processed: Option<u64>,
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    // files written by the action
+    #[serde(default)]
+    pub produced_artifacts: Vec<String>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    // CPU time, peak memory and I/O of the started processes
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+    pub parallel: bool,
+    // schema_version of the workflow the attributes were read with
+    pub schema_version: u32,
+    // repeated warnings and errors not written to the log while the step ran
+    #[serde(default)]
+    pub suppressed_log_messages: u64,
+    #[serde(skip)]
+    suppressed_before: u64,
+}
+
+impl ActionLogEntry {
+    pub fn new(step: usize, action: &Action, output_name: &str) -> Self {
+        Self {
+            run_id: String::new(),
+            step,
+            action: action.name.clone(),
+            action_type: action.action_type.to_string(),
+            output_name: output_name.to_string(),
+            started: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            success: false,
+            exit_code: None,
+            execution_time: 0.0,
+            error_message: None,
+            error_category: None,
+            items_processed: None,
+            bytes: None,
+            produced_artifacts: vec![],
+            warnings: vec![],
+            resource_usage: None,
+            parallel: false,
+            schema_version: 0,
+            suppressed_log_messages: 0,
+            suppressed_before: logging::suppressed_messages(),
+        }
+    }
+
+    pub fn finish(mut self, result: &ActionResult) -> Self {
+        self.success = result.success;
+        self.exit_code = result.exit_code;
+        self.execution_time = result.execution_time.as_secs_f64();
+        self.error_message = result.error_message.clone();
+        self.error_category = result.error_category.map(|category| category.to_string());
+        self.items_processed = result.items_processed;
+        self.bytes = result.bytes;
+        self.produced_artifacts = result
+            .produced_artifacts
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        self.warnings = result.warnings.clone();
+        self.resource_usage = result.resource_usage;
+        self.parallel = result.parallel;
+        self.suppressed_log_messages = logging::suppressed_messages() - self.suppressed_before;
+        self
+    }
+}
+
+pub struct ActionLog {
+    path: PathBuf,
+    run_id: String,
+    schema_version: u32,
+    time_zone: Tz,
+}
+
+impl ActionLog {
+    pub fn new(action_log_dir: &Path, run_id: &str, schema_version: u32, time_zone: Tz) -> Self {
+        Self {
+            path: action_log_dir.join(ACTION_LOG_FILE),
+            run_id: run_id.to_string(),
+            schema_version,
+            time_zone,
+        }
+    }
+
+    pub fn append(&self, entry: &ActionLogEntry) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // e.g. an error message containing the arguments of a tool
+        let mut line = logging::redact(&serde_json::to_string(entry)?).to_string();
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    // A broken action log must not stop the workflow
+    pub fn record(&self, entry: ActionLogEntry, result: &ActionResult) {
+        // the log shows how many messages of the step were suppressed
+        logging::report_suppressed();
+        let mut entry = entry.finish(result);
+        entry.run_id = self.run_id.clone();
+        entry.schema_version = self.schema_version;
+        if let Ok(started) = DateTime::parse_from_rfc3339(&entry.started) {
+            entry.started = started
+                .with_timezone(&self.time_zone)
+                .to_rfc3339_opts(SecondsFormat::Secs, true);
+        }
+        if let Err(e) = self.append(&entry) {
+            error!(
+                "Error writing to action log {:?}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Returns an output name not used by a previous step. Collisions (different
+/// actions with the same sanitized name or an action running several times)
+/// get the step number and, if required, a counter appended.
+pub fn unique_output_name(used: &mut HashSet<String>, base_name: &str, step: usize) -> String {
+    if used.insert(base_name.to_string()) {
+        return base_name.to_string();
+    }
+    let mut name = format!("{}_step{}", base_name, step);
+    let mut counter = 2;
+    while !used.insert(name.clone()) {
+        name = format!("{}_step{}_{}", base_name, step, counter);
+        counter += 1;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_output_name() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_output_name(&mut used, "whoami", 1), "whoami");
+        assert_eq!(unique_output_name(&mut used, "ps", 2), "ps");
+        // e.g. "who/ami" and "whoami" both sanitize to "whoami"
+        assert_eq!(unique_output_name(&mut used, "whoami", 3), "whoami_step3");
+        // the same step running again after a goto
+        assert_eq!(unique_output_name(&mut used, "whoami", 3), "whoami_step3_2");
+        assert_eq!(unique_output_name(&mut used, "whoami", 3), "whoami_step3_3");
+    }
+}
@@ -0,0 +1,100 @@
+// A single line per run describing the endpoint and the result of the collection, so the
+// summaries of many collections can be concatenated into the inventory of an engagement
+use report::{Report, ENDPOINT_SUMMARY_PATH, TOOLKIT_VERSION};
+use serde::Serialize;
+use std::{error::Error, fs};
+use system::{endpoint, SystemVariables};
+
+#[derive(Debug, Serialize)]
+pub struct EndpointSummary {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub distro: String,
+    pub ip_addresses: Vec<String>,
+    pub domain: Option<String>,
+    pub user: String,
+    pub elevated: bool,
+    pub workflow: String,
+    // name of the report directory
+    pub report: String,
+    pub run_id: String,
+    pub started: String,
+    pub finished: String,
+    // in seconds
+    pub duration: f64,
+    pub success: bool,
+    pub failed_actions: usize,
+    pub warnings: usize,
+    // files of interest found by the triage rules
+    pub findings: usize,
+    // checksum of the (encrypted) archive, None if archiving is disabled
+    pub report_sha1: Option<String>,
+    pub toolkit_version: String,
+}
+
+impl EndpointSummary {
+    pub fn new(system_variables: &SystemVariables, workflow: &str, report: &Report) -> Self {
+        Self {
+            hostname: system_variables.device_name.clone(),
+            os: system_variables.os.clone(),
+            arch: system_variables.arch.clone(),
+            distro: system_variables.distro.clone(),
+            ip_addresses: endpoint::ip_addresses(),
+            domain: endpoint::domain(),
+            user: system_variables.user.clone(),
+            elevated: system_variables.is_elevated,
+            workflow: workflow.to_string(),
+            report: report
+                .dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            run_id: report.run_id.clone(),
+            started: String::new(),
+            finished: String::new(),
+            duration: 0.0,
+            success: false,
+            failed_actions: 0,
+            warnings: 0,
+            findings: 0,
+            report_sha1: None,
+            toolkit_version: TOOLKIT_VERSION.to_string(),
+        }
+    }
+
+    /// Writes the summary as a single line into the report directory, next to the archive
+    pub fn write(&self, report: &Report) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        fs::write(report.dir.join(ENDPOINT_SUMMARY_PATH), line)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_write_endpoint_summary() {
+        let mut cleanup = Cleanup::new();
+        let mut system_variables = SystemVariables::new();
+        let report = Report::new(&mut system_variables, true, "test_endpoint".to_string()).unwrap();
+        cleanup.add(report.dir.clone());
+
+        let mut summary = EndpointSummary::new(&system_variables, "triage", &report);
+        summary.success = true;
+        summary.findings = 2;
+        summary.write(&report).unwrap();
+
+        let content = fs::read_to_string(report.dir.join(ENDPOINT_SUMMARY_PATH)).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(value["hostname"], system_variables.device_name.as_str());
+        assert_eq!(value["workflow"], "triage");
+        assert_eq!(value["findings"], 2);
+        assert!(value["ip_addresses"].is_array());
+    }
+}
@@ -0,0 +1,209 @@
+// Machine-readable reason why a collection failed, for orchestration that can only fetch files
+// from the endpoint. Written into the reports directory next to the reports of the run
+use chrono::{SecondsFormat, Utc};
+use report::{REPORTS_DIR, TOOLKIT_VERSION};
+use serde::Serialize;
+use std::{error::Error, fs, path::Path};
+
+pub const FAILURE_FILE: &str = "failure.json";
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCategory {
+    // the config file can't be read or is invalid
+    Config,
+    // another collector is running on the same toolkit directory
+    Lock,
+    // the workflow requires elevated privileges, but the elevation was denied or failed
+    Elevation,
+    // the workflow requires Full Disk Access on macOS, but it was not granted
+    FullDiskAccess,
+    DiskFull,
+    // a workflow it depends on failed
+    Dependency,
+    // outside of its execution window
+    Window,
+    // any other error of a workflow, e.g. a validation error
+    Workflow,
+}
+
+impl FailureCategory {
+    /// Category of the error a workflow failed with
+    pub fn of_error(error: &str) -> Self {
+        // ENOSPC on Linux and macOS, ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows
+        let disk_full = ["os error 28)", "os error 39)", "os error 112)"]
+            .iter()
+            .any(|code| error.contains(code))
+            || error.contains("No space left on device");
+        match disk_full {
+            true => Self::DiskFull,
+            false => Self::Workflow,
+        }
+    }
+}
+
+/// Outcome of a single workflow of the run
+#[derive(Debug, Serialize, Clone)]
+pub struct WorkflowResult {
+    pub workflow: String,
+    pub success: bool,
+    pub category: Option<FailureCategory>,
+    pub error: Option<String>,
+    // name of the report directory, None if no report was finished
+    pub report: Option<String>,
+}
+
+impl WorkflowResult {
+    pub fn succeeded(workflow: &str, report: Option<&Path>) -> Self {
+        Self {
+            workflow: workflow.to_string(),
+            success: true,
+            category: None,
+            error: None,
+            report: report
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_string()),
+        }
+    }
+
+    pub fn failed(workflow: &str, category: FailureCategory, error: &str) -> Self {
+        Self {
+            workflow: workflow.to_string(),
+            success: false,
+            category: Some(category),
+            error: Some(error.to_string()),
+            report: None,
+        }
+    }
+
+    /// The report of a failed workflow, e.g. if the upload failed
+    pub fn with_report(mut self, report: &Path) -> Self {
+        self.report = report
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailureReport {
+    pub category: FailureCategory,
+    pub message: String,
+    pub timestamp: String,
+    pub hostname: String,
+    pub pid: u32,
+    pub toolkit_version: String,
+    // partial progress: the workflows run before the failure and their reports
+    pub workflows_total: usize,
+    pub workflows_succeeded: usize,
+    pub workflows: Vec<WorkflowResult>,
+}
+
+impl FailureReport {
+    /// Failure of the collector before any workflow was run
+    pub fn new(category: FailureCategory, message: &str, hostname: &str) -> Self {
+        Self {
+            category,
+            message: message.to_string(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            hostname: hostname.to_string(),
+            pid: std::process::id(),
+            toolkit_version: TOOLKIT_VERSION.to_string(),
+            workflows_total: 0,
+            workflows_succeeded: 0,
+            workflows: vec![],
+        }
+    }
+
+    /// The first failed workflow determines the category, None if all workflows succeeded
+    pub fn from_results(results: &[WorkflowResult], hostname: &str) -> Option<Self> {
+        let first = results.iter().find(|result| !result.success)?;
+        let mut report = Self::new(
+            first.category.unwrap_or(FailureCategory::Workflow),
+            &format!(
+                "Workflow {} failed: {}",
+                first.workflow,
+                first.error.as_deref().unwrap_or_default()
+            ),
+            hostname,
+        );
+        report.workflows_total = results.len();
+        report.workflows_succeeded = results.iter().filter(|result| result.success).count();
+        report.workflows = results.to_vec();
+        Some(report)
+    }
+
+    pub fn write(&self, base_path: &Path) -> Result<(), Box<dyn Error>> {
+        let reports_dir = base_path.join(REPORTS_DIR);
+        fs::create_dir_all(&reports_dir)?;
+        // replaced at once, so a reader never sees a partial file
+        let path = reports_dir.join(FAILURE_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Removes the failure report of a previous run, so it isn't mistaken for the result of this one
+pub fn remove_failure_report(base_path: &Path) -> Result<(), Box<dyn Error>> {
+    let path = base_path.join(REPORTS_DIR).join(FAILURE_FILE);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    #[test]
+    fn test_failure_report() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_failure_report");
+
+        assert_eq!(
+            FailureCategory::of_error(
+                "Failed to write report.zip: No space left on device (os error 28)"
+            ),
+            FailureCategory::DiskFull
+        );
+        assert_eq!(
+            FailureCategory::of_error("Invalid action type: foo"),
+            FailureCategory::Workflow
+        );
+
+        let results = vec![WorkflowResult::succeeded(
+            "volatile.yaml",
+            Some(Path::new("/reports/host_volatile")),
+        )];
+        assert!(FailureReport::from_results(&results, "host").is_none());
+
+        let mut results = results;
+        results.push(WorkflowResult::failed(
+            "triage.yaml",
+            FailureCategory::Elevation,
+            "it requires elevated privileges",
+        ));
+        let report = FailureReport::from_results(&results, "host").unwrap();
+        assert_eq!(report.category, FailureCategory::Elevation);
+        assert_eq!(report.workflows_total, 2);
+        assert_eq!(report.workflows_succeeded, 1);
+        report.write(&dir).unwrap();
+
+        let path = dir.join(REPORTS_DIR).join(FAILURE_FILE);
+        let value: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(value["category"], "elevation");
+        assert_eq!(value["workflows"][0]["report"], "host_volatile");
+        assert_eq!(
+            value["workflows"][1]["error"],
+            "it requires elevated privileges"
+        );
+
+        remove_failure_report(&dir).unwrap();
+        assert!(!path.exists());
+    }
+}
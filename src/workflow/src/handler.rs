@@ -1,16 +1,194 @@
-use crate::{launch_conditions::check_launch_conditions, runner};
-use crypto::load_public_key;
-use log::{debug, error, info};
-use std::path::PathBuf;
-use storage::FileProcessor;
+use crate::{
+    endpoint_summary::EndpointSummary,
+    failure::{FailureCategory, WorkflowResult},
+    heartbeat::{Heartbeat, HEARTBEAT_FILE, STATUS_FINISHED, STATUS_FINISHING},
+    launch_conditions::check_launch_conditions,
+    notifier::{Notification, Notifier},
+    operator_audit,
+    readme::write_readme,
+    runner,
+    uploader::Uploader,
+};
+use actions::{integrity::CustomFilesIntegrity, yara::YaraSweep};
+use chrono::{Local, SecondsFormat, Timelike, Utc};
+use config::{
+    config::{CustomFiles, NotificationEvent, Notifications, Upload},
+    workflow::{
+        read_workflow_properties, resolve_secrets, workflow_depends_on, workflow_elevation,
+        workflow_full_disk_access, workflow_priority, workflow_runs_exclusively,
+        workflow_start_delay, workflow_window, Elevation, ExecutionWindow, Secret,
+    },
+};
+use crypto::{encrypted_output_path, get_file_sha1, load_public_key, EncryptingWriter, SessionKey};
+use log::{debug, error, info, warn};
+use privileges::{full_disk_access, is_elevated, tcc, FullDiskAccess};
+use report::{COLLECTOR_LOG_PATH, ENDPOINT_SUMMARY_PATH};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+use storage::{triage, FileProcessor};
 use system::SystemVariables;
-use utils::misc::get_files_by_patterns;
+use utils::messages::{self, GRANT_FULL_DISK_ACCESS};
+use utils::misc::{get_files_by_patterns, panic_message, wait_for_user_input};
 
 pub const WORKFLOWS_DIR: &str = "workflows";
+// offset of the local clock to the NTP servers, inside action_output
+pub const TIME_DRIFT_FILE: &str = "time_drift.json";
+
+/// A workflow file along with the properties that determine its execution order
+#[derive(Debug, Clone)]
+pub struct WorkflowEntry {
+    pub file: PathBuf,
+    pub title: String,
+    pub priority: i32,
+    pub depends_on: Vec<String>,
+    // None if the elevate setting of the config applies
+    pub elevation: Option<Elevation>,
+    // on macOS
+    pub full_disk_access: Elevation,
+    pub window: Option<ExecutionWindow>,
+    pub start_delay: Option<Duration>,
+}
+
+impl WorkflowEntry {
+    pub fn read(file: &PathBuf) -> Self {
+        // invalid files keep their position and report the error once they are run
+        let properties = read_workflow_properties(file).unwrap_or_default();
+        Self {
+            file: file.clone(),
+            title: properties.get("title").cloned().unwrap_or_default(),
+            priority: workflow_priority(&properties),
+            depends_on: workflow_depends_on(&properties),
+            elevation: workflow_elevation(&properties),
+            full_disk_access: workflow_full_disk_access(&properties),
+            window: workflow_window(&properties),
+            start_delay: workflow_start_delay(&properties),
+        }
+    }
+
+    /// File name of the workflow, e.g. volatile.yaml
+    pub fn name(&self) -> String {
+        self.file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    // dependencies are referenced by title, file name or file name without extension
+    pub fn matches(&self, name: &str) -> bool {
+        let file_name = self.file.file_name().unwrap_or_default().to_string_lossy();
+        let file_stem = self.file.file_stem().unwrap_or_default().to_string_lossy();
+        self.title == name || file_name == name || file_stem == name
+    }
+}
+
+/// Orders the workflows by priority (highest first) and runs dependencies before
+/// the workflows depending on them. Workflows with the same priority keep their order.
+pub fn order_workflows(entries: Vec<WorkflowEntry>) -> Vec<WorkflowEntry> {
+    for entry in &entries {
+        for dependency in &entry.depends_on {
+            if !entries.iter().any(|other| other.matches(dependency)) {
+                warn!(
+                    "Workflow {} depends on unknown workflow {:?}",
+                    entry.file.display(),
+                    dependency
+                );
+            }
+        }
+    }
+
+    let mut remaining: Vec<WorkflowEntry> = entries;
+    let mut ordered: Vec<WorkflowEntry> = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        // all workflows whose dependencies already ran
+        let ready = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.depends_on.iter().all(|dependency| {
+                    !remaining
+                        .iter()
+                        .any(|other| other.matches(dependency) && other.file != entry.file)
+                })
+            })
+            // max_by_key returns the last maximum, so reverse to keep the first one
+            .rev()
+            .max_by_key(|(_, entry)| entry.priority)
+            .map(|(i, _)| i);
+
+        match ready {
+            Some(i) => ordered.push(remaining.remove(i)),
+            None => {
+                let files: Vec<String> = remaining
+                    .iter()
+                    .map(|entry| entry.file.display().to_string())
+                    .collect();
+                error!(
+                    "Cyclic dependencies between workflows: {}. Running them by priority",
+                    files.join(", ")
+                );
+                remaining.sort_by_key(|entry| std::cmp::Reverse(entry.priority));
+                ordered.append(&mut remaining);
+            }
+        }
+    }
+    ordered
+}
+
+// settings of the config which a workflow can override in its options
+#[derive(Debug, Clone)]
+struct WorkflowDefaults {
+    time_zone: String,
+    // 0 for no limit
+    max_parallel_actions: usize,
+    // can't be overridden, the exclusions of a workflow are added to them
+    exclusions: Vec<String>,
+}
+
+impl Default for WorkflowDefaults {
+    fn default() -> Self {
+        Self {
+            time_zone: "UTC".to_string(),
+            max_parallel_actions: 0,
+            exclusions: vec![],
+        }
+    }
+}
 
 pub struct WorkflowHandler {
     workflow_files: Vec<PathBuf>,
     system_variables: SystemVariables,
+    notifier: Notifier,
+    uploader: Uploader,
+    // secrets of the config, shared by all workflows
+    secrets: BTreeMap<String, Secret>,
+    // elevation of the workflows which don't declare it
+    default_elevation: Elevation,
+    // time zone and limits of the config, unless a workflow sets its own
+    defaults: WorkflowDefaults,
+    // number of independent workflows running at the same time
+    parallel_workflows: usize,
+    // manifest of the custom_files directory, checked before each binary action
+    custom_files_integrity: Arc<CustomFilesIntegrity>,
+    // probed before the workflows are run, see check_full_disk_access
+    full_disk_access: FullDiskAccess,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WorkflowState {
+    Pending,
+    Running,
+    // whether the workflow was run successfully
+    Done(bool),
 }
 
 impl WorkflowHandler {
@@ -18,92 +196,347 @@ impl WorkflowHandler {
         Self {
             workflow_files: WorkflowHandler::get_workflow_files(&system_variables.base_path),
             system_variables: system_variables,
+            notifier: Notifier::default(),
+            uploader: Uploader::default(),
+            secrets: BTreeMap::new(),
+            default_elevation: Elevation::Never,
+            defaults: WorkflowDefaults::default(),
+            parallel_workflows: 1,
+            custom_files_integrity: Arc::new(CustomFilesIntegrity::default()),
+            full_disk_access: FullDiskAccess::NotApplicable,
         }
     }
 
-    pub fn run(&mut self) {
+    /// Workflows without an elevation property are elevated if elevate is set in the config
+    pub fn set_elevate(&mut self, elevate: bool) {
+        self.default_elevation = match elevate {
+            true => Elevation::Required,
+            false => Elevation::Never,
+        };
+    }
+
+    fn elevation_of(&self, entry: &WorkflowEntry) -> Elevation {
+        entry.elevation.unwrap_or(self.default_elevation)
+    }
+
+    /// Whether any workflow requires or prefers elevated privileges
+    pub fn requests_elevation(&self) -> bool {
+        self.workflow_files
+            .iter()
+            .map(WorkflowEntry::read)
+            .any(|entry| self.elevation_of(&entry) != Elevation::Never)
+    }
+
+    /// Probes the Full Disk Access on macOS. If a workflow needs it and it is denied, the person
+    /// running the collector can be asked to grant it in the system settings
+    pub fn check_full_disk_access(&mut self, prompt: bool) {
+        self.full_disk_access = full_disk_access();
+        if self.full_disk_access == FullDiskAccess::NotApplicable {
+            return;
+        }
+        info!("Full Disk Access: {}", self.full_disk_access);
+        let requested = self
+            .workflow_files
+            .iter()
+            .map(WorkflowEntry::read)
+            .any(|entry| entry.full_disk_access != Elevation::Never);
+        if !requested || !prompt || self.full_disk_access != FullDiskAccess::Denied {
+            return;
+        }
+
+        let message = messages::text(GRANT_FULL_DISK_ACCESS);
+        operator_audit::record(operator_audit::FULL_DISK_ACCESS_PROMPT, &message, None);
+        if let Err(e) = tcc::open_full_disk_access_settings() {
+            warn!("{}", e);
+        }
+        wait_for_user_input(&message);
+        self.full_disk_access = full_disk_access();
+        let event = match self.full_disk_access {
+            FullDiskAccess::Denied => operator_audit::FULL_DISK_ACCESS_DENIED,
+            _ => operator_audit::FULL_DISK_ACCESS_GRANTED,
+        };
+        operator_audit::record(event, &self.full_disk_access.to_string(), None);
+        // the access of a running process may only change once it is restarted
+        if self.full_disk_access == FullDiskAccess::Denied {
+            warn!("Full Disk Access is still denied, restart the collector once it is granted");
+        }
+    }
+
+    pub fn set_notifications(&mut self, notifications: Notifications) {
+        self.notifier = Notifier::new(notifications);
+    }
+
+    pub fn set_upload(&mut self, upload: Upload) {
+        self.uploader = Uploader::new(upload);
+    }
+
+    pub fn set_time_zone(&mut self, time_zone: String) {
+        self.defaults.time_zone = time_zone;
+    }
+
+    /// Limits the parallel steps of the workflows which don't set their own limit
+    pub fn set_max_parallel_actions(&mut self, max_parallel_actions: usize) {
+        self.defaults.max_parallel_actions = max_parallel_actions;
+    }
+
+    /// Paths which are never collected by any workflow
+    pub fn set_exclusions(&mut self, exclusions: Vec<String>) {
+        self.defaults.exclusions = exclusions;
+    }
+
+    /// Runs up to this many workflows at the same time, each with its own report
+    pub fn set_parallel_workflows(&mut self, parallel_workflows: usize) {
+        self.parallel_workflows = parallel_workflows.max(1);
+    }
+
+    pub fn set_secrets(&mut self, secrets: BTreeMap<String, Secret>) {
+        self.secrets = secrets;
+    }
+
+    /// Loads the manifest of the custom_files directory and checks the listed files
+    pub fn set_custom_files(&mut self, custom_files: CustomFiles) {
+        self.custom_files_integrity = Arc::new(CustomFilesIntegrity::load(
+            &self.system_variables.custom_files_directory,
+            custom_files.on_mismatch,
+        ));
+    }
+
+    /// Runs all workflows and returns their results in the order they were run
+    pub fn run(&mut self) -> Vec<WorkflowResult> {
         // error if no workflow files are found
         if self.workflow_files.is_empty() {
             error!("No workflow files found.");
-            return;
+            return vec![];
         }
 
-        // iterate over all workflow files
-        for file in &self.workflow_files {
-            debug!("Reading workflow file: {}", file.display());
-            let mut workflow = match runner::Workflow::init(file) {
-                Ok(workflow) => workflow,
-                Err(_) => {
-                    error!("Error initializing workflow for file: {}", file.display());
+        let entries = order_workflows(
+            self.workflow_files
+                .iter()
+                .map(WorkflowEntry::read)
+                .collect(),
+        );
+        let this = &*self;
+
+        // a workflow waits for the workflows it depends on, if they are ordered before it
+        // (cyclic dependencies are run by priority)
+        let dependencies: Vec<Vec<usize>> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                (0..i)
+                    .filter(|&j| {
+                        entries[j].file != entry.file
+                            && entry
+                                .depends_on
+                                .iter()
+                                .any(|dependency| entries[j].matches(dependency))
+                    })
+                    .collect()
+            })
+            .collect();
+        let exclusive: Vec<bool> = match this.parallel_workflows {
+            1 => vec![false; entries.len()],
+            _ => entries
+                .iter()
+                .map(|entry| workflow_runs_exclusively(&entry.file))
+                .collect(),
+        };
+
+        let mut states = vec![WorkflowState::Pending; entries.len()];
+        let mut results = vec![];
+        let (sender, receiver) = mpsc::channel::<(usize, WorkflowResult)>();
+        thread::scope(|scope| loop {
+            for i in 0..entries.len() {
+                if states[i] != WorkflowState::Pending {
+                    continue;
+                }
+                let entry = &entries[i];
+                if dependencies[i]
+                    .iter()
+                    .any(|&j| !matches!(states[j], WorkflowState::Done(_)))
+                {
                     continue;
                 }
-            };
-
-            // check launch conditions
-            if !check_launch_conditions(
-                &mut workflow.runner.launch_conditions,
-                &mut self.system_variables,
-            ) {
-                debug!("Launch conditions not met for file: {}", file.display());
-                continue;
-            }
 
-            // initialize report
-            let tite = workflow.runner.properties.get("title").unwrap().to_string();
-            let archive_enabled = workflow.runner.reporting.zip_archive.enabled;
-            let report =
-                match report::Report::new(&mut self.system_variables, archive_enabled, tite) {
-                    Ok(report) => report,
-                    Err(e) => {
-                        error!("Error initializing report for {:?}: {}", file, e);
-                        continue;
+                // don't run workflows whose dependencies failed
+                if let Some(dependency) = entry.depends_on.iter().find(|dependency| {
+                    dependencies[i].iter().any(|&j| {
+                        states[j] == WorkflowState::Done(false) && entries[j].matches(dependency)
+                    })
+                }) {
+                    let message = format!("dependency {:?} failed", dependency);
+                    error!("Skipping workflow {}: {}", entry.file.display(), message);
+                    states[i] = WorkflowState::Done(false);
+                    results.push(WorkflowResult::failed(
+                        &entry.name(),
+                        FailureCategory::Dependency,
+                        &message,
+                    ));
+                    continue;
+                }
+
+                // the elevation was denied or failed before the workflows were run
+                if !is_elevated() {
+                    match this.elevation_of(entry) {
+                        Elevation::Required => {
+                            let message = "it requires elevated privileges";
+                            error!("Skipping workflow {}: {}", entry.file.display(), message);
+                            states[i] = WorkflowState::Done(false);
+                            results.push(WorkflowResult::failed(
+                                &entry.name(),
+                                FailureCategory::Elevation,
+                                message,
+                            ));
+                            continue;
+                        }
+                        Elevation::Preferred => warn!(
+                            "Running workflow {} without elevated privileges",
+                            entry.file.display()
+                        ),
+                        Elevation::Never => (),
                     }
-                };
+                }
 
-            // initialize file processor
-            let mut fp = match FileProcessor::new(&report) {
-                Ok(fp) => fp,
-                Err(e) => {
-                    error!("Error initializing file processor for {:?}: {}", file, e);
-                    continue;
+                // protected locations can't be read and would be collected empty
+                if this.full_disk_access == FullDiskAccess::Denied {
+                    match entry.full_disk_access {
+                        Elevation::Required => {
+                            let message = "it requires Full Disk Access";
+                            error!("Skipping workflow {}: {}", entry.file.display(), message);
+                            states[i] = WorkflowState::Done(false);
+                            results.push(WorkflowResult::failed(
+                                &entry.name(),
+                                FailureCategory::FullDiskAccess,
+                                message,
+                            ));
+                            continue;
+                        }
+                        Elevation::Preferred => warn!(
+                            "Running workflow {} without Full Disk Access, protected locations are not collected",
+                            entry.file.display()
+                        ),
+                        Elevation::Never => (),
+                    }
                 }
-            };
-
-            fp.set_report_settings(workflow.runner.reporting.clone());
-
-            // reporting
-            let encryption_settings = &workflow.runner.reporting.zip_archive.encryption;
-            if encryption_settings.enabled {
-                // convert public key filename to PathBuf (e.g. public.pem)
-                let public_key_path = PathBuf::from(&encryption_settings.public_key);
-                // prepend base path + /keys to public key filename
-                let public_key_path = self
-                    .system_variables
-                    .base_path
-                    .join("keys")
-                    .join(public_key_path);
-
-                info!("Loading public key: {}", public_key_path.to_string_lossy());
-                if let Ok(public_key) = load_public_key(public_key_path.clone()) {
-                    fp.set_public_key(public_key);
-                } else {
-                    error!(
-                        "Error loading public key: {}",
-                        public_key_path.to_string_lossy()
-                    );
+
+                if this.parallel_workflows == 1 {
+                    let result = this.execute(entry);
+                    states[i] = WorkflowState::Done(result.success);
+                    results.push(result);
                     continue;
                 }
+
+                // interactive and sealed workflows run on their own, the following ones wait for them
+                let running: Vec<usize> = (0..entries.len())
+                    .filter(|&j| states[j] == WorkflowState::Running)
+                    .collect();
+                if running.len() >= this.parallel_workflows
+                    || running.iter().any(|&j| exclusive[j])
+                    || (exclusive[i] && !running.is_empty())
+                {
+                    break;
+                }
+                info!(
+                    "Starting workflow {} ({} running)",
+                    entry.file.display(),
+                    running.len() + 1
+                );
+                states[i] = WorkflowState::Running;
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let _ = sender.send((i, this.execute(entry)));
+                });
             }
 
-            // run the workflow
-            if let Err(_) = workflow.run(&report, &self.system_variables, &mut fp) {
-                error!("Error running workflow for file: {}", file.display());
+            if !states.contains(&WorkflowState::Running) {
+                break;
             }
+            match receiver.recv() {
+                Ok((i, result)) => {
+                    states[i] = WorkflowState::Done(result.success);
+                    results.push(result);
+                }
+                Err(_) => break,
+            }
+        });
+        results
+    }
 
-            // finish the file processor
-            match fp.finish() {
-                Ok(_) => (),
-                Err(e) => error!("Error finishing file processor: {}", e),
+    // Waits for the start delay and the execution window, then runs the workflow
+    fn execute(&self, entry: &WorkflowEntry) -> WorkflowResult {
+        let file = &entry.file;
+        let workflow = entry.name();
+
+        // heavy collections are delayed or kept out of business hours
+        if let Some(delay) = entry.start_delay {
+            info!(
+                "Delaying workflow {} by {} seconds",
+                file.display(),
+                delay.as_secs()
+            );
+            thread::sleep(delay);
+        }
+        if let Some(window) = entry.window {
+            let now = Local::now();
+            let minute = now.hour() * 60 + now.minute();
+            if !window.contains(minute) {
+                let message = format!(
+                    "it may only run between {} (opens in {} minutes)",
+                    window,
+                    window.minutes_until_open(minute)
+                );
+                warn!("Skipping workflow {}: {}", file.display(), message);
+                // the workflows depending on it are skipped as well
+                return WorkflowResult::failed(&workflow, FailureCategory::Window, &message);
+            }
+        }
+
+        // each workflow updates the loot directory of its own copy
+        let mut system_variables = self.system_variables.clone();
+        // a panicking workflow fails on its own, the remaining workflows still run
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_workflow(
+                file,
+                &mut system_variables,
+                &self.notifier,
+                &self.uploader,
+                &self.secrets,
+                &self.defaults,
+                &self.custom_files_integrity,
+            )
+        }))
+        .unwrap_or_else(|payload| {
+            Err(format!(
+                "Workflow {} panicked: {}",
+                file.display(),
+                panic_message(payload.as_ref())
+            )
+            .into())
+        });
+        match result {
+            // the failure was already notified at the end of the run
+            Ok(Some(run)) if !run.errors.is_empty() => {
+                let error = run.errors.join("; ");
+                WorkflowResult::failed(&workflow, FailureCategory::of_error(&error), &error)
+                    .with_report(&run.report_dir)
+            }
+            Ok(run) => WorkflowResult::succeeded(
+                &workflow,
+                run.as_ref().map(|run| run.report_dir.as_path()),
+            ),
+            Err(e) => {
+                error!("{}", e);
+                let mut notification = Notification::new(
+                    NotificationEvent::Failure,
+                    &workflow,
+                    &self.system_variables.device_name,
+                );
+                notification.error = Some(e.to_string());
+                notification.run_id =
+                    Some(system_variables.run_id.clone()).filter(|run_id| !run_id.is_empty());
+                self.notifier.notify(&notification);
+                let error = e.to_string();
+                WorkflowResult::failed(&workflow, FailureCategory::of_error(&error), &error)
             }
         }
     }
@@ -128,6 +561,434 @@ impl WorkflowHandler {
     }
 }
 
+/// Records the offset of the local clock as queried at the start of the collector, if NTP is enabled
+fn write_time_drift(action_log_dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let ntp_result = match time::last_ntp_result() {
+        Some(ntp_result) => ntp_result,
+        None => return Ok(false),
+    };
+    fs::write(
+        action_log_dir.join(TIME_DRIFT_FILE),
+        serde_json::to_string_pretty(&ntp_result)?,
+    )?;
+    Ok(true)
+}
+
+// log of a sealed workflow, encrypted with the session key before it is written to disk
+struct EncryptedLog(EncryptingWriter<BufWriter<File>>);
+
+impl EncryptedLog {
+    fn create(path: &Path, key: &SessionKey) -> Result<Self, Box<dyn Error>> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Self(EncryptingWriter::new(file, key)?))
+    }
+}
+
+impl Write for EncryptedLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl logging::SealedLog for EncryptedLog {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish().map(|_| ())
+    }
+}
+
+// report directory of a workflow run and the errors it was finished with, e.g. a failed upload
+struct FinishedRun {
+    report_dir: PathBuf,
+    errors: Vec<String>,
+}
+
+/// Runs a single workflow file: checks the launch conditions, runs all actions and packages the report.
+/// Returns the report directory or None if the launch conditions are not met.
+/// Fails if the workflow was aborted or its report couldn't be finished
+pub fn run_workflow_file(
+    file: &PathBuf,
+    system_variables: &mut SystemVariables,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let run = run_workflow(
+        file,
+        system_variables,
+        &Notifier::default(),
+        &Uploader::default(),
+        &BTreeMap::new(),
+        &WorkflowDefaults::default(),
+        &Arc::new(CustomFilesIntegrity::default()),
+    )?;
+    match run {
+        Some(run) if !run.errors.is_empty() => Err(format!(
+            "Report {} finished with errors: {}",
+            run.report_dir.display(),
+            run.errors.join("; ")
+        )
+        .into()),
+        run => Ok(run.map(|run| run.report_dir)),
+    }
+}
+
+fn run_workflow(
+    file: &PathBuf,
+    system_variables: &mut SystemVariables,
+    notifier: &Notifier,
+    uploader: &Uploader,
+    secrets: &BTreeMap<String, Secret>,
+    defaults: &WorkflowDefaults,
+    custom_files_integrity: &Arc<CustomFilesIntegrity>,
+) -> Result<Option<FinishedRun>, Box<dyn Error>> {
+    let start_time = Instant::now();
+    let started = Utc::now();
+    debug!("Reading workflow file: {}", file.display());
+    let mut workflow = match runner::Workflow::init(file) {
+        Ok(workflow) => workflow,
+        Err(e) => {
+            return Err(format!(
+                "Error initializing workflow for file {}: {}",
+                file.display(),
+                e
+            )
+            .into());
+        }
+    };
+
+    // timestamps of the report are in the time zone of the workflow
+    workflow.time_zone = workflow.runner.options.time_zone_or(&defaults.time_zone);
+    workflow.max_parallel_actions = workflow
+        .runner
+        .options
+        .max_parallel_actions
+        .unwrap_or(defaults.max_parallel_actions);
+    let started = started
+        .with_timezone(&workflow.time_zone)
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    // registered before anything of the workflow is logged
+    let mut secrets = secrets.clone();
+    secrets.extend(workflow.runner.secrets.clone());
+    let (secrets, errors) = resolve_secrets(&secrets);
+    logging::add_secrets(secrets.values().cloned());
+    for error in errors {
+        error!("{}", error);
+    }
+    workflow.secrets = secrets;
+    workflow.custom_files_integrity = custom_files_integrity.clone();
+
+    // check launch conditions
+    if !check_launch_conditions(&mut workflow.runner.launch_conditions, system_variables) {
+        debug!("Launch conditions not met for file: {}", file.display());
+        return Ok(None);
+    }
+
+    // initialize report
+    let tite = workflow.runner.properties.get("title").unwrap().to_string();
+    let archive_enabled = workflow.runner.reporting.zip_archive.enabled;
+    let report = match report::Report::new(system_variables, archive_enabled, tite) {
+        Ok(report) => report,
+        Err(e) => {
+            return Err(format!("Error initializing report for {:?}: {}", file, e).into());
+        }
+    };
+
+    let mut notification = Notification::new(
+        NotificationEvent::Start,
+        &workflow.runner.properties["title"],
+        &system_variables.device_name,
+    );
+    notification.report = Some(report.dir.to_string_lossy().to_string());
+    notification.run_id = Some(report.run_id.clone());
+    notifier.notify(&notification);
+
+    // initialize file processor
+    let mut fp = match FileProcessor::new(&report) {
+        Ok(fp) => fp,
+        Err(e) => {
+            return Err(format!("Error initializing file processor for {:?}: {}", file, e).into());
+        }
+    };
+
+    fp.set_report_settings(workflow.runner.reporting.clone());
+    fp.set_time_zone(workflow.time_zone);
+
+    // nothing is collected unless the exclusions are in place
+    let mut exclusions = defaults.exclusions.clone();
+    exclusions.extend(workflow.runner.exclusions.clone());
+    match fp.set_exclusions(&exclusions) {
+        Ok(0) => {}
+        Ok(count) => info!("Holding back files matching {} exclusions", count),
+        Err(e) => {
+            return Err(format!("Error loading exclusions for {:?}: {}", file, e).into());
+        }
+    }
+
+    // delta collection: skip files that did not change since a previous report
+    let baseline = &workflow.runner.reporting.delta.baseline;
+    if !baseline.is_empty() {
+        let baseline_path = system_variables.base_path.join(baseline);
+        match fp.set_baseline(&baseline_path) {
+            Ok(count) => info!(
+                "Loaded {} files from baseline: {}",
+                count,
+                baseline_path.display()
+            ),
+            // fall back to a full collection
+            Err(e) => error!("Error loading baseline {}: {}", baseline_path.display(), e),
+        }
+    }
+
+    // content-addressed storage: skip files already collected by previous reports
+    let dedup = workflow.runner.reporting.dedup.clone();
+    let dedup_cache = system_variables.base_path.join(&dedup.cache);
+    if dedup.enabled {
+        match fp.set_dedup_cache(&dedup_cache) {
+            Ok(count) => info!(
+                "Loaded {} checksums from dedup cache: {}",
+                count,
+                dedup_cache.display()
+            ),
+            Err(e) => error!("Error loading dedup cache {}: {}", dedup_cache.display(), e),
+        }
+    }
+
+    // rank the collected files once the workflow has finished
+    let triage_rules = &workflow.runner.reporting.triage.rules;
+    if !triage_rules.is_empty() {
+        let rules_path = system_variables.base_path.join(triage_rules);
+        match triage::read_rules(&rules_path) {
+            Ok(rules) => {
+                info!(
+                    "Loaded {} triage rules: {}",
+                    rules.len(),
+                    rules_path.display()
+                );
+                fp.set_triage_rules(rules);
+            }
+            Err(e) => error!("Error loading triage rules {}: {}", rules_path.display(), e),
+        }
+    }
+
+    // sweep everything collected with one rule set before the archive is encrypted
+    let yara_sweep = &workflow.runner.reporting.yara_sweep;
+    if !yara_sweep.rules.trim().is_empty() {
+        match YaraSweep::new(yara_sweep, &system_variables.custom_files_directory) {
+            Ok(sweep) => {
+                info!(
+                    "Loaded {} YARA rule files for the sweep",
+                    sweep.rules_count()
+                );
+                fp.set_content_scanner(Box::new(sweep));
+            }
+            Err(e) => error!("Error loading YARA sweep rules: {}", e),
+        }
+    }
+
+    // reporting
+    let encryption_settings = &workflow.runner.reporting.zip_archive.encryption;
+    if encryption_settings.enabled {
+        // convert public key filename to PathBuf (e.g. public.pem)
+        let public_key_path = PathBuf::from(&encryption_settings.public_key);
+        // prepend base path + /keys to public key filename
+        let public_key_path = system_variables
+            .base_path
+            .join("keys")
+            .join(public_key_path);
+
+        info!("Loading public key: {}", public_key_path.to_string_lossy());
+        if let Ok(public_key) = load_public_key(public_key_path.clone()) {
+            fp.set_public_key(public_key)?;
+        } else {
+            return Err(format!(
+                "Error loading public key: {}",
+                public_key_path.to_string_lossy()
+            )
+            .into());
+        }
+    }
+
+    // the log of the workflow is encrypted and only stored inside the encrypted archive. Sealed
+    // workflows run on their own, so the log is never sealed by another workflow
+    let sealed = fp.sidecars_sealed();
+    if sealed {
+        let log_path = encrypted_output_path(&report.dir.join(COLLECTOR_LOG_PATH));
+        let seal = match fp.sealed_log_key() {
+            Some(key) => EncryptedLog::create(&log_path, &key)
+                .and_then(|log| logging::seal_to(Box::new(log)).map_err(Into::into)),
+            None => Err("No session key was generated".into()),
+        };
+        if let Err(e) = seal {
+            return Err(format!("Error sealing the log to {}: {}", log_path.display(), e).into());
+        }
+        info!("The log of the workflow is sealed into the encrypted archive");
+    }
+
+    // progress of the collection for remote operators
+    let heartbeat_interval = workflow.runner.reporting.heartbeat.interval;
+    let heartbeat = match heartbeat_interval > 0 {
+        true => {
+            let heartbeat = Heartbeat::start(
+                report.dir.join(HEARTBEAT_FILE),
+                Duration::from_secs(heartbeat_interval as u64),
+                &workflow.runner.properties["title"],
+                workflow.runner.workflow.len(),
+                fp.progress(),
+            );
+            workflow.heartbeat = Some(heartbeat.state());
+            Some(heartbeat)
+        }
+        false => None,
+    };
+
+    // interactions of the operator while the workflow runs
+    operator_audit::open(&report.action_log_dir);
+    if let Err(e) = write_time_drift(&report.action_log_dir) {
+        error!("Error writing {}: {}", TIME_DRIFT_FILE, e);
+    }
+
+    // run the workflow
+    let mut errors = vec![];
+    // the report is finished even if the workflow panicked
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        workflow.run(&report, system_variables, &mut fp)
+    }));
+    match run {
+        Ok(Ok(_)) => (),
+        Ok(Err(e)) => {
+            error!("Error running workflow for file: {}", file.display());
+            errors.push(e.to_string());
+        }
+        Err(payload) => {
+            let message = format!(
+                "Workflow {} panicked: {}",
+                file.display(),
+                panic_message(payload.as_ref())
+            );
+            error!("{}", message);
+            errors.push(message);
+        }
+    }
+
+    // the report of a stopped workflow is finished as usual, but marked as aborted
+    if let Some(reason) = &workflow.aborted {
+        warn!("Workflow {} was aborted: {}", file.display(), reason);
+        fp.set_aborted(reason.clone());
+        errors.push(format!("Workflow aborted: {}", reason));
+    }
+
+    // summary for the recipients of the report, added to the archive by the file processor
+    let finished = Utc::now()
+        .with_timezone(&workflow.time_zone)
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+    if let Err(e) = write_readme(
+        &report,
+        &workflow.runner,
+        system_variables,
+        &started,
+        &finished,
+        workflow.aborted.as_deref(),
+    ) {
+        error!("Error writing README: {}", e);
+    }
+
+    // finish the file processor
+    operator_audit::close(&report.action_log_dir);
+    if sealed {
+        if let Err(e) = logging::unseal() {
+            error!("Error finishing the sealed log: {}", e);
+            errors.push(format!("Error finishing the sealed log: {}", e));
+        }
+    }
+    if let Some(heartbeat) = &heartbeat {
+        heartbeat.set_status(STATUS_FINISHING);
+    }
+    let finished_report = match fp.finish() {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Error finishing file processor: {}", e);
+            errors.push(e.to_string());
+            false
+        }
+    };
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.stop(STATUS_FINISHED);
+    }
+
+    // inventory of the endpoint, uploaded along with the report
+    let report_sha1 = [&report.zip_path, &report.tar_path, &report.seven_zip_path]
+        .into_iter()
+        .find(|path| path.exists())
+        .and_then(|archive_path| get_file_sha1(archive_path).ok());
+    let mut endpoint_summary = EndpointSummary::new(
+        system_variables,
+        &workflow.runner.properties["title"],
+        &report,
+    );
+    endpoint_summary.started = started.clone();
+    endpoint_summary.finished = finished.clone();
+    endpoint_summary.duration = start_time.elapsed().as_secs_f64();
+    endpoint_summary.success = errors.is_empty() && workflow.failed_actions == 0;
+    endpoint_summary.failed_actions = workflow.failed_actions;
+    endpoint_summary.warnings = workflow.warnings;
+    endpoint_summary.findings = fp.findings();
+    endpoint_summary.report_sha1 = report_sha1.clone();
+    if let Err(e) = endpoint_summary.write(&report) {
+        error!("Error writing {}: {}", ENDPOINT_SUMMARY_PATH, e);
+    }
+
+    // upload the finished report
+    let mut uploaded = false;
+    if uploader.is_enabled() {
+        match uploader.upload(&report.dir, &report.run_id) {
+            Ok(count) => {
+                info!("Uploaded {} files of the report", count);
+                uploaded = true;
+            }
+            Err(e) => {
+                error!("Error uploading report: {}", e);
+                errors.push(e.to_string());
+            }
+        }
+    }
+
+    // only remember the stored files once the complete report has left the endpoint
+    if dedup.enabled && !(finished_report && uploaded) {
+        warn!("Not updating the dedup cache, as the report was not finished and uploaded");
+    } else if dedup.enabled {
+        match fp.update_dedup_cache(&dedup_cache) {
+            Ok(count) => info!("Added {} checksums to the dedup cache", count),
+            Err(e) => error!(
+                "Error updating dedup cache {}: {}",
+                dedup_cache.display(),
+                e
+            ),
+        }
+    }
+
+    // send a summary of the run
+    notification.event = match errors.is_empty() && workflow.failed_actions == 0 {
+        true => NotificationEvent::Success,
+        false => NotificationEvent::Failure,
+    };
+    notification.duration = Some(start_time.elapsed().as_secs_f64());
+    notification.failed_actions = workflow.failed_actions;
+    notification.warnings = workflow.warnings;
+    notification.error = match errors.is_empty() {
+        true => None,
+        false => Some(errors.join("; ")),
+    };
+    notification.report_sha1 = report_sha1;
+    notifier.notify(&notification);
+
+    Ok(Some(FinishedRun {
+        report_dir: report.dir.clone(),
+        errors,
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,4 +1014,187 @@ mod tests {
         // assert that all files are found
         assert_eq!(workflow_files.len(), 5, "Did not find all workflow files");
     }
+
+    #[test]
+    fn test_run_parallel_workflows() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_run_parallel_workflows");
+        let workflows = tmp_dir.join(WORKFLOWS_DIR);
+        std::fs::create_dir_all(&workflows).unwrap();
+        let workflow = |title: &str, depends_on: &str| {
+            format!(
+                r#"
+properties:
+  title: "{}"
+  version: "1.0"
+  depends_on: "{}"
+launch_conditions:
+  os: ["windows", "linux", "macos"]
+actions:
+  - name: echo
+    type: command
+    attributes:
+      cmd: "{}"
+      args: ["{}", "echo {}"]
+      log_to_file: true
+workflow:
+  - action: echo
+reporting:
+  zip_archive:
+    enabled: false
+    encryption:
+      enabled: false
+      public_key: ""
+      algorithm: None
+    compression:
+      enabled: false
+      size_limit: "0"
+  metadata:
+    mac_times: false
+    checksums: false
+    paths: false
+"#,
+                title,
+                depends_on,
+                if cfg!(windows) { "cmd" } else { "sh" },
+                if cfg!(windows) { "/c" } else { "-c" },
+                title
+            )
+        };
+        for (title, depends_on) in [("first", ""), ("second", ""), ("third", "first")] {
+            std::fs::write(
+                workflows.join(format!("{}.yaml", title)),
+                workflow(title, depends_on),
+            )
+            .unwrap();
+        }
+        // fails, so the workflow depending on it is skipped
+        std::fs::write(
+            workflows.join("broken.yaml"),
+            "properties:\n  title: broken\n",
+        )
+        .unwrap();
+        std::fs::write(
+            workflows.join("skipped.yaml"),
+            workflow("skipped", "broken"),
+        )
+        .unwrap();
+
+        let mut system_variables = SystemVariables::new();
+        system_variables.base_path = tmp_dir.clone();
+        let mut handler = WorkflowHandler::init(system_variables);
+        handler.set_parallel_workflows(2);
+        let results = handler.run();
+        assert_eq!(results.len(), 5);
+        let skipped = results
+            .iter()
+            .find(|result| result.workflow == "skipped.yaml")
+            .unwrap();
+        assert_eq!(skipped.category, Some(FailureCategory::Dependency));
+        assert_eq!(results.iter().filter(|result| result.success).count(), 3);
+
+        let reports: Vec<String> = std::fs::read_dir(tmp_dir.join(report::REPORTS_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        for title in ["first", "second", "third"] {
+            assert!(
+                reports
+                    .iter()
+                    .any(|report| report.contains(&format!("_{}_", title))),
+                "{:?}",
+                reports
+            );
+        }
+        assert!(!reports.iter().any(|report| report.contains("_skipped_")));
+    }
+
+    #[test]
+    fn test_requests_elevation() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_requests_elevation");
+        let workflows = tmp_dir.join(WORKFLOWS_DIR);
+        std::fs::create_dir_all(&workflows).unwrap();
+        std::fs::write(
+            workflows.join("volatile.yaml"),
+            "properties:\n  title: volatile\n  elevation: never\n",
+        )
+        .unwrap();
+        std::fs::write(
+            workflows.join("triage.yaml"),
+            "properties:\n  title: triage\n",
+        )
+        .unwrap();
+
+        let mut system_variables = SystemVariables::new();
+        system_variables.base_path = tmp_dir.clone();
+        let mut handler = WorkflowHandler::init(system_variables);
+        assert!(!handler.requests_elevation());
+
+        // the elevate setting of the config applies to workflows without an elevation
+        handler.set_elevate(true);
+        assert!(handler.requests_elevation());
+
+        std::fs::write(
+            workflows.join("triage.yaml"),
+            "properties:\n  title: triage\n  elevation: preferred\n",
+        )
+        .unwrap();
+        handler.set_elevate(false);
+        assert!(handler.requests_elevation());
+    }
+
+    fn entry(file: &str, priority: i32, depends_on: Vec<&str>) -> WorkflowEntry {
+        WorkflowEntry {
+            file: PathBuf::from("workflows").join(file),
+            title: file.trim_end_matches(".yaml").to_uppercase(),
+            priority,
+            depends_on: depends_on.into_iter().map(String::from).collect(),
+            elevation: None,
+            full_disk_access: Elevation::Never,
+            window: None,
+            start_delay: None,
+        }
+    }
+
+    fn file_names(entries: &[WorkflowEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| e.file.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_order_workflows() {
+        // higher priority first, equal priorities keep their order
+        let ordered = order_workflows(vec![
+            entry("disk.yaml", 0, vec![]),
+            entry("logs.yaml", 0, vec![]),
+            entry("volatile.yaml", 10, vec![]),
+        ]);
+        assert_eq!(
+            file_names(&ordered),
+            vec!["volatile.yaml", "disk.yaml", "logs.yaml"]
+        );
+
+        // dependencies run first, referenced by title, file name or stem
+        let ordered = order_workflows(vec![
+            entry("report.yaml", 100, vec!["DISK", "logs.yaml"]),
+            entry("disk.yaml", 0, vec!["volatile"]),
+            entry("logs.yaml", 5, vec![]),
+            entry("volatile.yaml", 1, vec!["unknown"]),
+        ]);
+        assert_eq!(
+            file_names(&ordered),
+            vec!["logs.yaml", "volatile.yaml", "disk.yaml", "report.yaml"]
+        );
+
+        // cycles don't drop any workflow
+        let ordered = order_workflows(vec![
+            entry("a.yaml", 0, vec!["b"]),
+            entry("b.yaml", 1, vec!["a"]),
+            entry("c.yaml", 0, vec![]),
+        ]);
+        assert_eq!(file_names(&ordered), vec!["c.yaml", "b.yaml", "a.yaml"]);
+    }
 }
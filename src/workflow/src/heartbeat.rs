@@ -0,0 +1,202 @@
+use chrono::{SecondsFormat, Utc};
+use log::{debug, warn};
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use storage::StorageProgress;
+
+// Name of the heartbeat inside the report directory, not part of the archive
+pub const HEARTBEAT_FILE: &str = "heartbeat.json";
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_FINISHING: &str = "finishing";
+pub const STATUS_FINISHED: &str = "finished";
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct HeartbeatState {
+    pub status: String,
+    pub workflow: String,
+    pub pid: u32,
+    pub started: String,
+    pub updated: String,
+    // step of the action started last
+    pub step: usize,
+    pub total_steps: usize,
+    pub action: String,
+    pub files_stored: u64,
+    pub bytes_written: u64,
+    pub last_log_line: Option<String>,
+}
+
+/// Rewrites heartbeat.json in the interval, so a long-running collection can be
+/// monitored by listing the report directory
+pub struct Heartbeat {
+    path: PathBuf,
+    state: Arc<Mutex<HeartbeatState>>,
+    progress: Arc<StorageProgress>,
+    // set to stop the thread, notified to wake it up early
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn write_heartbeat(
+    path: &PathBuf,
+    state: &Mutex<HeartbeatState>,
+    progress: &StorageProgress,
+) -> Result<(), Box<dyn Error>> {
+    let content = {
+        let mut state = state.lock().map_err(|e| e.to_string())?;
+        state.updated = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        state.files_stored = progress.files.load(Ordering::Relaxed);
+        state.bytes_written = progress.bytes.load(Ordering::Relaxed);
+        state.last_log_line = logging::last_log_line();
+        serde_json::to_string_pretty(&*state)?
+    };
+    // replaced at once, so a reader never sees a partial file
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+impl Heartbeat {
+    pub fn start(
+        path: PathBuf,
+        interval: Duration,
+        workflow: &str,
+        total_steps: usize,
+        progress: Arc<StorageProgress>,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(HeartbeatState {
+            status: STATUS_RUNNING.to_string(),
+            workflow: workflow.to_string(),
+            pid: std::process::id(),
+            started: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            total_steps,
+            ..Default::default()
+        }));
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+
+        let thread = {
+            let (path, state, progress, stop) =
+                (path.clone(), state.clone(), progress.clone(), stop.clone());
+            thread::spawn(move || loop {
+                if let Err(e) = write_heartbeat(&path, &state, &progress) {
+                    warn!("Error writing heartbeat {}: {}", path.display(), e);
+                }
+                let (stopped, condvar) = &*stop;
+                let stopped = match stopped.lock() {
+                    Ok(stopped) => stopped,
+                    Err(_) => return,
+                };
+                match condvar.wait_timeout_while(stopped, interval, |stopped| !*stopped) {
+                    Ok((stopped, _)) if !*stopped => continue,
+                    _ => return,
+                }
+            })
+        };
+        debug!("Writing heartbeat every {:?}: {}", interval, path.display());
+
+        Self {
+            path,
+            state,
+            progress,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Shared with the runner, which updates the current step
+    pub fn state(&self) -> Arc<Mutex<HeartbeatState>> {
+        self.state.clone()
+    }
+
+    pub fn set_status(&self, status: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.status = status.to_string();
+        }
+    }
+
+    fn stop_thread(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let (stopped, condvar) = &*self.stop;
+            if let Ok(mut stopped) = stopped.lock() {
+                *stopped = true;
+            }
+            condvar.notify_all();
+            let _ = thread.join();
+        }
+    }
+
+    /// Stops the updates and writes the final state
+    pub fn stop(mut self, status: &str) {
+        self.stop_thread();
+        self.set_status(status);
+        if let Err(e) = write_heartbeat(&self.path, &self.state, &self.progress) {
+            warn!("Error writing heartbeat {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+/// Sets the step of the action started last
+pub fn set_step(state: &Option<Arc<Mutex<HeartbeatState>>>, step: usize, action: &str) {
+    if let Some(Ok(mut state)) = state.as_ref().map(|state| state.lock()) {
+        state.step = step;
+        state.action = action.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    fn read_heartbeat(path: &PathBuf) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_heartbeat");
+        let path = dir.join(HEARTBEAT_FILE);
+        let progress = Arc::new(StorageProgress::default());
+
+        let heartbeat = Heartbeat::start(
+            path.clone(),
+            Duration::from_millis(50),
+            "Triage",
+            3,
+            progress.clone(),
+        );
+        set_step(&Some(heartbeat.state()), 2, "collect");
+        progress.files.store(4, Ordering::Relaxed);
+        progress.bytes.store(4096, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(300));
+
+        let value = read_heartbeat(&path);
+        assert_eq!(value["status"], STATUS_RUNNING);
+        assert_eq!(value["workflow"], "Triage");
+        assert_eq!(value["step"], 2);
+        assert_eq!(value["total_steps"], 3);
+        assert_eq!(value["action"], "collect");
+        assert_eq!(value["files_stored"], 4);
+        assert_eq!(value["bytes_written"], 4096);
+
+        heartbeat.stop(STATUS_FINISHED);
+        assert_eq!(read_heartbeat(&path)["status"], STATUS_FINISHED);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+}
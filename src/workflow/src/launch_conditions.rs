@@ -1,8 +1,8 @@
-use config::workflow::{CustomCommand, LaunchConditions};
-use log::debug;
+use config::workflow::{CustomCommand, LaunchConditions, RegistryValueCondition};
+use log::{debug, warn};
 use regex::Regex;
-use std::process::Command;
-use system::SystemVariables;
+use std::{net::IpAddr, process::Command};
+use system::{checks, SystemVariables};
 
 fn check_custom_command(custom_command: &CustomCommand, variables: &SystemVariables) -> bool {
     // replace variables in command
@@ -42,6 +42,73 @@ fn check_custom_command(custom_command: &CustomCommand, variables: &SystemVariab
     true
 }
 
+fn check_registry_value(condition: &RegistryValueCondition) -> bool {
+    match checks::registry_value(&condition.key, &condition.value) {
+        Some(data) => condition.equals.as_ref().is_none_or(|equals| {
+            debug!(
+                "Registry value {}\\{} is {:?}",
+                condition.key, condition.value, data
+            );
+            &data == equals
+        }),
+        None => false,
+    }
+}
+
+// e.g. 10.20.0.0/16 or fd00::/8, a single address without prefix length
+fn parse_network(network: &str) -> Option<(IpAddr, u32)> {
+    let (address, prefix) = match network.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (network, None),
+    };
+    let address: IpAddr = address.trim().parse().ok()?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse()
+            .ok()
+            .filter(|prefix| *prefix <= max_prefix)?,
+        None => max_prefix,
+    };
+    Some((address, prefix))
+}
+
+fn in_network(address: &IpAddr, network: &IpAddr, prefix: u32) -> bool {
+    let (address, network, bits) = match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            (u32::from(*address) as u128, u32::from(*network) as u128, 32)
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            (u128::from(*address), u128::from(*network), 128)
+        }
+        _ => return false,
+    };
+    let mask = match prefix {
+        0 => 0,
+        _ => u128::MAX << (bits - prefix) & (u128::MAX >> (128 - bits)),
+    };
+    address & mask == network & mask
+}
+
+fn check_network(networks: &[String], variables: &SystemVariables) -> bool {
+    let addresses: Vec<IpAddr> = variables
+        .host
+        .ip_addresses
+        .iter()
+        .filter_map(|address| address.parse().ok())
+        .collect();
+    networks.iter().any(|network| match parse_network(network) {
+        Some((network, prefix)) => addresses
+            .iter()
+            .any(|address| in_network(address, &network, prefix)),
+        None => {
+            warn!("Invalid network {:?} in launch conditions", network);
+            false
+        }
+    })
+}
+
 /// Check the launch conditions of the workflow YAML
 /// Returns true if all conditions are met, false otherwise
 pub fn check_launch_conditions(
@@ -83,6 +150,51 @@ pub fn check_launch_conditions(
                     })
             }),
         ),
+        (
+            "service_exists",
+            Box::new(|| {
+                condition
+                    .service_exists
+                    .as_ref()
+                    .is_none_or(|services| services.iter().all(|name| checks::service_exists(name)))
+            }),
+        ),
+        (
+            "process_running",
+            Box::new(|| {
+                condition.process_running.as_ref().is_none_or(|processes| {
+                    processes.iter().all(|name| checks::process_running(name))
+                })
+            }),
+        ),
+        (
+            "registry_value",
+            Box::new(|| {
+                condition
+                    .registry_value
+                    .as_ref()
+                    .is_none_or(|values| values.iter().all(check_registry_value))
+            }),
+        ),
+        (
+            "domain",
+            Box::new(|| {
+                condition.domain.as_ref().is_none_or(|domains| {
+                    variables.host.domain.as_ref().is_some_and(|domain| {
+                        domains.iter().any(|name| name.eq_ignore_ascii_case(domain))
+                    })
+                })
+            }),
+        ),
+        (
+            "network",
+            Box::new(|| {
+                condition
+                    .network
+                    .as_ref()
+                    .is_none_or(|networks| check_network(networks, variables))
+            }),
+        ),
     ];
 
     // check if all conditions are met
@@ -203,4 +315,55 @@ mod tests {
             "test.*txt"
         );
     }
+
+    #[test]
+    fn test_launch_conditions_native() {
+        let yaml = r#"
+            os: ["windows", "linux", "macos"]
+            process_running: ["this-process-does-not-exist"]
+            "#;
+        let mut lc: LaunchConditions = serde_yaml::from_str(yaml).unwrap();
+        let variables = SystemVariables::new();
+        assert!(!check_launch_conditions(&mut lc, &variables));
+
+        let yaml = r#"
+            os: ["windows", "linux", "macos"]
+            service_exists: ["this-service-does-not-exist"]
+            registry_value:
+              - key: "HKLM\\SOFTWARE\\does-not-exist"
+                value: "Test"
+                equals: "1"
+            "#;
+        let mut lc: LaunchConditions = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(lc.registry_value.as_ref().unwrap()[0].value, "Test");
+        assert!(!check_launch_conditions(&mut lc, &variables));
+    }
+
+    #[test]
+    fn test_launch_conditions_network() {
+        let mut variables = SystemVariables::new();
+        variables.host.ip_addresses = vec!["10.20.30.40".to_string(), "fd00::1".to_string()];
+        variables.host.domain = Some("corp.example.com".to_string());
+
+        let yaml = r#"
+            os: ["windows", "linux", "macos"]
+            domain: ["CORP.example.com"]
+            network: ["192.168.0.0/16", "10.20.0.0/16"]
+            "#;
+        let mut lc: LaunchConditions = serde_yaml::from_str(yaml).unwrap();
+        assert!(check_launch_conditions(&mut lc, &variables));
+
+        lc.network = Some(vec!["fd00::/8".to_string()]);
+        assert!(check_launch_conditions(&mut lc, &variables));
+        lc.network = Some(vec!["10.20.30.41".to_string(), "invalid/8".to_string()]);
+        assert!(!check_launch_conditions(&mut lc, &variables));
+        lc.network = Some(vec!["0.0.0.0/0".to_string()]);
+        assert!(check_launch_conditions(&mut lc, &variables));
+
+        lc.domain = Some(vec!["other.example.com".to_string()]);
+        assert!(!check_launch_conditions(&mut lc, &variables));
+        variables.host.domain = None;
+        lc.domain = None;
+        assert!(check_launch_conditions(&mut lc, &variables));
+    }
 }
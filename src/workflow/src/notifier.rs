@@ -0,0 +1,249 @@
+use config::config::{NotificationEvent, NotificationTarget, Notifications, SmtpTarget};
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, Message,
+    SmtpTransport, Transport,
+};
+use log::{debug, warn};
+use serde::Serialize;
+use std::{error::Error, time::Duration};
+
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub workflow: String,
+    pub device: String,
+    // None if the workflow failed before its report was created
+    pub run_id: Option<String>,
+    // in seconds
+    pub duration: Option<f64>,
+    pub failed_actions: usize,
+    // problems of the actions which didn't fail them, e.g. files that could not be stored
+    pub warnings: usize,
+    pub report: Option<String>,
+    pub report_sha1: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Notification {
+    pub fn new(event: NotificationEvent, workflow: &str, device: &str) -> Self {
+        Self {
+            event,
+            workflow: workflow.to_string(),
+            device: device.to_string(),
+            run_id: None,
+            duration: None,
+            failed_actions: 0,
+            warnings: 0,
+            report: None,
+            report_sha1: None,
+            error: None,
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        let state = match self.event {
+            NotificationEvent::Start => "started",
+            NotificationEvent::Success => "succeeded",
+            NotificationEvent::Failure => "failed",
+        };
+        format!(
+            "[IR Toolkit] Workflow {:?} {} on {}",
+            self.workflow, state, self.device
+        )
+    }
+
+    /// Human readable summary used for Slack and email
+    pub fn text(&self) -> String {
+        let mut lines = vec![self.subject()];
+        if let Some(duration) = self.duration {
+            lines.push(format!("Duration: {:.1}s", duration));
+        }
+        if self.event != NotificationEvent::Start {
+            lines.push(format!("Failed actions: {}", self.failed_actions));
+        }
+        if self.warnings > 0 {
+            lines.push(format!("Warnings: {}", self.warnings));
+        }
+        if let Some(report) = &self.report {
+            lines.push(format!("Report: {}", report));
+        }
+        if let Some(sha1) = &self.report_sha1 {
+            lines.push(format!("Archive SHA1: {}", sha1));
+        }
+        if let Some(run_id) = &self.run_id {
+            lines.push(format!("Run ID: {}", run_id));
+        }
+        if let Some(error) = &self.error {
+            lines.push(format!("Error: {}", error));
+        }
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Notifier {
+    settings: Notifications,
+}
+
+impl Notifier {
+    pub fn new(settings: Notifications) -> Self {
+        Self { settings }
+    }
+
+    /// Sends the notification to all targets subscribed to the event.
+    /// Failures are logged, but never abort the collection
+    pub fn notify(&self, notification: &Notification) {
+        if self.settings.targets.is_empty() || !self.settings.events.contains(&notification.event) {
+            return;
+        }
+
+        let timeout = Duration::from_secs(self.settings.timeout);
+        for target in &self.settings.targets {
+            let result = match target {
+                NotificationTarget::Webhook { url } => send_webhook(url, notification, timeout),
+                NotificationTarget::Slack { url } => send_slack(url, notification, timeout),
+                NotificationTarget::Smtp(smtp) => send_email(smtp, notification, timeout),
+            };
+            match result {
+                Ok(_) => debug!("Sent {:?} notification", notification.event),
+                Err(e) => warn!(
+                    "Failed to send {:?} notification: {}",
+                    notification.event, e
+                ),
+            }
+        }
+    }
+}
+
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(timeout).build()
+}
+
+fn send_webhook(
+    url: &str,
+    notification: &Notification,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    agent(timeout).post(url).send_json(notification)?;
+    Ok(())
+}
+
+fn send_slack(
+    url: &str,
+    notification: &Notification,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    agent(timeout)
+        .post(url)
+        .send_json(serde_json::json!({ "text": notification.text() }))?;
+    Ok(())
+}
+
+fn send_email(
+    smtp: &SmtpTarget,
+    notification: &Notification,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut builder = Message::builder()
+        .from(smtp.from.parse()?)
+        .subject(notification.subject())
+        .header(ContentType::TEXT_PLAIN);
+    for to in &smtp.to {
+        builder = builder.to(to.parse()?);
+    }
+    let email = builder.body(notification.text())?;
+
+    let mut transport = match smtp.starttls {
+        true => SmtpTransport::starttls_relay(&smtp.server)?,
+        false => SmtpTransport::builder_dangerous(&smtp.server),
+    }
+    .port(smtp.port)
+    .timeout(Some(timeout));
+    if !smtp.username.is_empty() {
+        transport = transport.credentials(Credentials::new(
+            smtp.username.clone(),
+            smtp.password.clone(),
+        ));
+    }
+
+    transport.build().send(&email)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    #[test]
+    fn test_notification_text() {
+        let mut notification = Notification::new(NotificationEvent::Failure, "Triage", "HOST01");
+        notification.duration = Some(12.34);
+        notification.failed_actions = 2;
+        notification.warnings = 3;
+        notification.report_sha1 = Some("abc".to_string());
+        notification.run_id = Some("0b6f1c5e".to_string());
+
+        let text = notification.text();
+        assert!(text.starts_with("[IR Toolkit] Workflow \"Triage\" failed on HOST01"));
+        assert!(text.contains("Duration: 12.3s"));
+        assert!(text.contains("Failed actions: 2"));
+        assert!(text.contains("Warnings: 3"));
+        assert!(text.contains("Archive SHA1: abc"));
+        assert!(text.contains("Run ID: 0b6f1c5e"));
+    }
+
+    #[test]
+    fn test_notify_webhook() {
+        // minimal http server that answers a single request
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(body).unwrap()
+        });
+
+        let notifier = Notifier::new(Notifications {
+            events: vec![NotificationEvent::Success],
+            targets: vec![NotificationTarget::Webhook { url }],
+            timeout: 5,
+        });
+        // not subscribed, so nothing is sent
+        notifier.notify(&Notification::new(
+            NotificationEvent::Start,
+            "Triage",
+            "HOST01",
+        ));
+        notifier.notify(&Notification::new(
+            NotificationEvent::Success,
+            "Triage",
+            "HOST01",
+        ));
+
+        let body: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(body["event"], "success");
+        assert_eq!(body["workflow"], "Triage");
+        assert_eq!(body["device"], "HOST01");
+    }
+}
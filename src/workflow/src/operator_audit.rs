@@ -0,0 +1,246 @@
+// Interactions of the operator with the collector (e.g. keypress continuations, terminal
+// sessions and elevation prompts), so the report documents what the responder did on the host
+use actions::ActionResult;
+use chrono::{SecondsFormat, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// Name of the operator audit log inside action_output
+pub const OPERATOR_AUDIT_FILE: &str = "operator_audit.jsonl";
+// events before an elevated restart, read by the elevated collector
+pub const PENDING_AUDIT_FILE: &str = "operator_audit.pending.jsonl";
+
+pub const ELEVATION_PROMPT: &str = "elevation_prompt";
+pub const ELEVATION_GRANTED: &str = "elevation_granted";
+pub const ELEVATION_DENIED: &str = "elevation_denied";
+pub const FULL_DISK_ACCESS_PROMPT: &str = "full_disk_access_prompt";
+pub const FULL_DISK_ACCESS_GRANTED: &str = "full_disk_access_granted";
+pub const FULL_DISK_ACCESS_DENIED: &str = "full_disk_access_denied";
+pub const KEYPRESS_PROMPT: &str = "keypress_prompt";
+pub const KEYPRESS: &str = "keypress";
+pub const TERMINAL_STARTED: &str = "terminal_started";
+pub const TERMINAL_STOPPED: &str = "terminal_stopped";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OperatorEvent {
+    pub timestamp: String,
+    // e.g. keypress_prompt or terminal_started
+    pub event: String,
+    // e.g. the prompt shown to the operator or the started shell
+    pub detail: String,
+    // name of the action, if the event belongs to a step
+    #[serde(default)]
+    pub action: Option<String>,
+}
+
+#[derive(Default)]
+struct OperatorAudit {
+    // operator_audit.jsonl of the running workflow
+    path: Option<PathBuf>,
+    // events while no workflow is running, e.g. the elevation prompt
+    pending: Vec<OperatorEvent>,
+}
+
+static AUDIT: Mutex<OperatorAudit> = Mutex::new(OperatorAudit {
+    path: None,
+    pending: Vec::new(),
+});
+
+fn append(path: &Path, events: &[OperatorEvent]) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for event in events {
+        // e.g. a command line containing a secret
+        let mut line = logging::redact(&serde_json::to_string(event)?).to_string();
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+impl OperatorAudit {
+    fn record(&mut self, event: OperatorEvent) {
+        match &self.path {
+            Some(path) => {
+                if let Err(e) = append(path, &[event]) {
+                    error!(
+                        "Error writing to operator audit log {:?}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            None => self.pending.push(event),
+        }
+    }
+
+    fn open(&mut self, path: PathBuf) {
+        let pending = std::mem::take(&mut self.pending);
+        if let Err(e) = append(&path, &pending) {
+            error!(
+                "Error writing to operator audit log {:?}: {}",
+                path.display(),
+                e
+            );
+        }
+        self.path = Some(path);
+    }
+
+    // interactive workflows run on their own, so events only occur while a single workflow is
+    // running. Of workflows running in parallel, only the last opened log is closed
+    fn close(&mut self, path: &Path) {
+        if self.path.as_deref() == Some(path) {
+            self.path = None;
+        }
+    }
+
+    fn persist_pending(&self, path: &Path) {
+        let _ = fs::remove_file(path);
+        if let Err(e) = append(path, &self.pending) {
+            error!("Error writing {:?}: {}", path.display(), e);
+        }
+    }
+
+    fn restore_pending(&mut self, path: &Path) -> bool {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return false,
+        };
+        if let Err(e) = fs::remove_file(path) {
+            error!("Error removing {:?}: {}", path.display(), e);
+        }
+        let events: Vec<OperatorEvent> = content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        // the events of this collector happened afterwards
+        let recorded = std::mem::replace(&mut self.pending, events);
+        self.pending.extend(recorded);
+        true
+    }
+}
+
+fn event(event: &str, detail: &str, action: Option<&str>) -> OperatorEvent {
+    OperatorEvent {
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        event: event.to_string(),
+        detail: detail.to_string(),
+        action: action.map(|action| action.to_string()),
+    }
+}
+
+/// Records an interaction into the audit log of the running workflow. Without a running workflow,
+/// it is kept until the next one is started. A broken audit log must not stop the workflow
+pub fn record(name: &str, detail: &str, action: Option<&str>) {
+    if let Ok(mut audit) = AUDIT.lock() {
+        audit.record(event(name, detail, action));
+    }
+}
+
+pub fn terminal_stopped(result: &ActionResult, action: &str) {
+    let detail = match result.exit_code {
+        Some(code) => format!("Exit code {}", code),
+        None => "No exit code".to_string(),
+    };
+    record(TERMINAL_STOPPED, &detail, Some(action));
+}
+
+/// Writes the following events into the action_output directory of a report, starting with the pending ones
+pub fn open(action_log_dir: &Path) {
+    if let Ok(mut audit) = AUDIT.lock() {
+        audit.open(action_log_dir.join(OPERATOR_AUDIT_FILE));
+    }
+}
+
+/// Keeps the following events until the next workflow is started
+pub fn close(action_log_dir: &Path) {
+    if let Ok(mut audit) = AUDIT.lock() {
+        audit.close(&action_log_dir.join(OPERATOR_AUDIT_FILE));
+    }
+}
+
+/// Hands the pending events over to the collector restarted elevated
+pub fn persist_pending(path: &Path) {
+    if let Ok(audit) = AUDIT.lock() {
+        audit.persist_pending(path);
+    }
+}
+
+/// Takes over the events persisted before an elevated restart. Returns whether there were any
+pub fn restore_pending(path: &Path) -> bool {
+    match AUDIT.lock() {
+        Ok(mut audit) => audit.restore_pending(path),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    fn read_events(path: &Path) -> Vec<OperatorEvent> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_operator_audit() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_operator_audit");
+        let pending_path = dir.join(PENDING_AUDIT_FILE);
+
+        // the prompt is handed over to the elevated collector
+        let mut audit = OperatorAudit::default();
+        audit.record(event(ELEVATION_PROMPT, "Restart as admin", None));
+        audit.persist_pending(&pending_path);
+        let mut audit = OperatorAudit::default();
+        audit.record(event(ELEVATION_GRANTED, "Restarted as admin", None));
+        assert!(audit.restore_pending(&pending_path));
+        assert!(!pending_path.exists());
+        assert!(!audit.restore_pending(&pending_path));
+
+        audit.open(dir.join(OPERATOR_AUDIT_FILE));
+        audit.record(event(KEYPRESS_PROMPT, "Press any key", Some("triage")));
+        // a workflow which ran in parallel doesn't close the log of another one
+        audit.close(&dir.join("other").join(OPERATOR_AUDIT_FILE));
+        assert!(audit.path.is_some());
+        audit.close(&dir.join(OPERATOR_AUDIT_FILE));
+        audit.record(event(TERMINAL_STARTED, "bash", Some("shell")));
+
+        let events = read_events(&dir.join(OPERATOR_AUDIT_FILE));
+        let names: Vec<&str> = events.iter().map(|event| event.event.as_str()).collect();
+        assert_eq!(
+            names,
+            [ELEVATION_PROMPT, ELEVATION_GRANTED, KEYPRESS_PROMPT]
+        );
+        assert_eq!(events[2].action.as_deref(), Some("triage"));
+        assert_eq!(audit.pending.len(), 1);
+    }
+
+    #[test]
+    fn test_operator_audit_redact() {
+        let mut cleanup = Cleanup::new();
+        let dir = cleanup.tmp_dir("test_operator_audit_redact");
+        // escaped in the JSON, e.g. as p\"w\\d
+        logging::add_secrets(["p\"w\\d\n".to_string()]);
+
+        let mut audit = OperatorAudit::default();
+        audit.open(dir.join(OPERATOR_AUDIT_FILE));
+        audit.record(event(TERMINAL_STARTED, "login -p p\"w\\d\n", Some("shell")));
+
+        let content = fs::read_to_string(dir.join(OPERATOR_AUDIT_FILE)).unwrap();
+        assert!(!content.contains("p\\\"w"));
+        let events = read_events(&dir.join(OPERATOR_AUDIT_FILE));
+        assert_eq!(events[0].detail, "login -p [REDACTED]");
+    }
+}
@@ -0,0 +1,507 @@
+// The plan of a collection: the workflows in the order they run, with their steps and settings
+// after validation. Comparing the plan before and after a change of the workflows shows what will
+// be executed and collected differently, without running anything
+use chrono::{SecondsFormat, Utc};
+use config::workflow::{read_workflow_file, OnError, WorkflowRunner};
+use report::TOOLKIT_VERSION;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::handler::{order_workflows, WorkflowEntry, WorkflowHandler, WORKFLOWS_DIR};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub toolkit_version: String,
+    pub created: String,
+    // Full Disk Access of the machine the plan was created on (macOS), see privileges::tcc
+    #[serde(default)]
+    pub full_disk_access: String,
+    pub workflows: Vec<WorkflowPlan>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WorkflowPlan {
+    // relative to the workflows directory, separated by "/"
+    pub file: String,
+    // why the workflow can't be read, the collector skips it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    #[serde(default)]
+    pub launch_conditions: Value,
+    #[serde(default)]
+    pub options: Value,
+    #[serde(default)]
+    pub reporting: Value,
+    // of the workflow itself, without the exclusions of the config
+    #[serde(default)]
+    pub exclusions: Vec<String>,
+    #[serde(default)]
+    pub steps: Vec<StepPlan>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StepPlan {
+    pub action: String,
+    // empty if the action isn't defined
+    #[serde(default)]
+    pub action_type: String,
+    #[serde(default)]
+    pub on_error: String,
+    // handlers of the error categories, e.g. on_timeout
+    #[serde(default)]
+    pub on_error_categories: BTreeMap<String, String>,
+    #[serde(default)]
+    pub parallel: bool,
+    // seconds, 0 for none
+    #[serde(default)]
+    pub timeout: i32,
+    #[serde(default)]
+    pub continue_after_keypress: bool,
+    #[serde(default)]
+    pub run_as: String,
+    #[serde(default)]
+    pub for_each: String,
+    #[serde(default)]
+    pub after: Vec<String>,
+    // variables and secrets are not resolved
+    #[serde(default)]
+    pub attributes: Value,
+}
+
+fn describe_on_error(on_error: &OnError) -> String {
+    match on_error {
+        OnError::Goto { goto } => format!("goto {}", goto),
+        OnError::Abort => "abort".to_string(),
+        OnError::AbortAndFinalize => "abort_and_finalize".to_string(),
+        OnError::Continue => "continue".to_string(),
+    }
+}
+
+impl WorkflowPlan {
+    pub fn from_runner(file: String, runner: &WorkflowRunner) -> Self {
+        let steps = runner
+            .workflow
+            .iter()
+            .map(|item| {
+                let action = runner
+                    .actions
+                    .iter()
+                    .find(|action| action.name == item.action);
+                let on_error_categories = [
+                    ("on_timeout", &item.on_timeout),
+                    ("on_not_found", &item.on_not_found),
+                    ("on_permission_denied", &item.on_permission_denied),
+                    ("on_nonzero_exit", &item.on_nonzero_exit),
+                ]
+                .into_iter()
+                .filter_map(|(name, handler)| {
+                    handler
+                        .as_ref()
+                        .map(|handler| (name.to_string(), describe_on_error(handler)))
+                })
+                .collect();
+                StepPlan {
+                    action: item.action.clone(),
+                    action_type: action
+                        .map(|action| action.action_type.to_string())
+                        .unwrap_or_default(),
+                    on_error: describe_on_error(&item.on_error),
+                    on_error_categories,
+                    parallel: item.parallel,
+                    timeout: item.timeout,
+                    continue_after_keypress: item.continue_after_keypress,
+                    run_as: item.run_as.clone(),
+                    for_each: item.for_each.clone(),
+                    after: item.after.clone(),
+                    attributes: action
+                        .and_then(|action| serde_json::to_value(&action.attributes).ok())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+        Self {
+            file,
+            error: None,
+            properties: runner.properties.clone().into_iter().collect(),
+            launch_conditions: serde_json::to_value(&runner.launch_conditions).unwrap_or_default(),
+            options: serde_json::to_value(&runner.options).unwrap_or_default(),
+            reporting: serde_json::to_value(&runner.reporting).unwrap_or_default(),
+            exclusions: runner.exclusions.clone(),
+            steps,
+        }
+    }
+}
+
+impl Plan {
+    /// Reads and validates the workflows of the base path in the order the collector runs them
+    pub fn create(base_path: &PathBuf) -> Self {
+        let workflows_dir = base_path.join(WORKFLOWS_DIR);
+        let entries = order_workflows(
+            WorkflowHandler::get_workflow_files(base_path)
+                .iter()
+                .map(WorkflowEntry::read)
+                .collect(),
+        );
+        let workflows = entries
+            .iter()
+            .map(|entry| {
+                let file = entry
+                    .file
+                    .strip_prefix(&workflows_dir)
+                    .unwrap_or(&entry.file)
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                match read_workflow_file(&entry.file) {
+                    Ok(runner) => WorkflowPlan::from_runner(file, &runner),
+                    Err(e) => WorkflowPlan {
+                        file,
+                        error: Some(e.to_string()),
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        Self {
+            toolkit_version: TOOLKIT_VERSION.to_string(),
+            created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            full_disk_access: privileges::full_disk_access().to_string(),
+            workflows,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanChange {
+    AddedWorkflow(String),
+    RemovedWorkflow(String),
+    // the workflows of both plans run in another order
+    WorkflowOrder {
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    AddedStep {
+        workflow: String,
+        step: String,
+        action_type: String,
+    },
+    RemovedStep {
+        workflow: String,
+        step: String,
+    },
+    // the steps of both plans run in another order
+    StepOrder {
+        workflow: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    Changed {
+        workflow: String,
+        // e.g. reporting.zip_archive.compression.enabled or step "logs": attributes.patterns
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Null => "(none)".to_string(),
+        value => value.to_string(),
+    }
+}
+
+impl fmt::Display for PlanChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanChange::AddedWorkflow(file) => write!(f, "+ workflow {}", file),
+            PlanChange::RemovedWorkflow(file) => write!(f, "- workflow {}", file),
+            PlanChange::WorkflowOrder { old, new } => write!(
+                f,
+                "~ order of the workflows: {} -> {}",
+                old.join(", "),
+                new.join(", ")
+            ),
+            PlanChange::AddedStep {
+                workflow,
+                step,
+                action_type,
+            } => write!(f, "+ {}: step {:?} ({})", workflow, step, action_type),
+            PlanChange::RemovedStep { workflow, step } => {
+                write!(f, "- {}: step {:?}", workflow, step)
+            }
+            PlanChange::StepOrder { workflow, old, new } => write!(
+                f,
+                "~ {}: order of the steps: {} -> {}",
+                workflow,
+                old.join(", "),
+                new.join(", ")
+            ),
+            PlanChange::Changed {
+                workflow,
+                path,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ {}: {}: {} -> {}",
+                workflow,
+                path,
+                describe_value(old),
+                describe_value(new)
+            ),
+        }
+    }
+}
+
+// the leaves that differ, objects are compared key by key and everything else as a whole
+fn diff_values(path: &str, old: &Value, new: &Value, changes: &mut Vec<(String, Value, Value)>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                let path = match path.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", path, key),
+                };
+                diff_values(
+                    &path,
+                    old.get(key).unwrap_or(&Value::Null),
+                    new.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        _ if old != new => changes.push((path.to_string(), old.clone(), new.clone())),
+        _ => {}
+    }
+}
+
+// an action can run more than once, so repeated steps are numbered: logs, logs#2, ...
+fn step_keys(steps: &[StepPlan]) -> Vec<String> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    steps
+        .iter()
+        .map(|step| {
+            let count = seen.entry(step.action.as_str()).or_default();
+            *count += 1;
+            match count {
+                1 => step.action.clone(),
+                _ => format!("{}#{}", step.action, count),
+            }
+        })
+        .collect()
+}
+
+// the items present in both lists, in the order of the first one
+fn common<'a>(items: &'a [String], others: &[String]) -> Vec<&'a String> {
+    items.iter().filter(|item| others.contains(item)).collect()
+}
+
+fn diff_workflow(baseline: &WorkflowPlan, plan: &WorkflowPlan, changes: &mut Vec<PlanChange>) {
+    let workflow = &plan.file;
+    let mut values = vec![];
+    let settings = |plan: &WorkflowPlan| {
+        serde_json::json!({
+            "error": plan.error,
+            "properties": plan.properties,
+            "launch_conditions": plan.launch_conditions,
+            "options": plan.options,
+            "reporting": plan.reporting,
+            "exclusions": plan.exclusions,
+        })
+    };
+    diff_values("", &settings(baseline), &settings(plan), &mut values);
+
+    let (old_keys, new_keys) = (step_keys(&baseline.steps), step_keys(&plan.steps));
+    for key in old_keys.iter().filter(|key| !new_keys.contains(key)) {
+        changes.push(PlanChange::RemovedStep {
+            workflow: workflow.clone(),
+            step: key.clone(),
+        });
+    }
+    for (key, step) in new_keys.iter().zip(&plan.steps) {
+        match old_keys.iter().position(|old| old == key) {
+            Some(position) => {
+                let old = serde_json::to_value(&baseline.steps[position]).unwrap_or_default();
+                let new = serde_json::to_value(step).unwrap_or_default();
+                let mut step_values = vec![];
+                diff_values("", &old, &new, &mut step_values);
+                values.extend(
+                    step_values
+                        .into_iter()
+                        .map(|(path, old, new)| (format!("step {:?}: {}", key, path), old, new)),
+                );
+            }
+            None => changes.push(PlanChange::AddedStep {
+                workflow: workflow.clone(),
+                step: key.clone(),
+                action_type: step.action_type.clone(),
+            }),
+        }
+    }
+    let (old_order, new_order) = (common(&old_keys, &new_keys), common(&new_keys, &old_keys));
+    if old_order != new_order {
+        changes.push(PlanChange::StepOrder {
+            workflow: workflow.clone(),
+            old: old_order.into_iter().cloned().collect(),
+            new: new_order.into_iter().cloned().collect(),
+        });
+    }
+
+    changes.extend(
+        values
+            .into_iter()
+            .map(|(path, old, new)| PlanChange::Changed {
+                workflow: workflow.clone(),
+                path,
+                old,
+                new,
+            }),
+    );
+}
+
+/// What changes from the baseline to the plan. The time the plans were created is ignored
+pub fn diff_plans(baseline: &Plan, plan: &Plan) -> Vec<PlanChange> {
+    let files = |plan: &Plan| -> Vec<String> {
+        plan.workflows
+            .iter()
+            .map(|workflow| workflow.file.clone())
+            .collect()
+    };
+    let (old_files, new_files) = (files(baseline), files(plan));
+    let mut changes = vec![];
+    for file in old_files.iter().filter(|file| !new_files.contains(file)) {
+        changes.push(PlanChange::RemovedWorkflow(file.clone()));
+    }
+    for file in new_files.iter().filter(|file| !old_files.contains(file)) {
+        changes.push(PlanChange::AddedWorkflow(file.clone()));
+    }
+    let (old_order, new_order) = (
+        common(&old_files, &new_files),
+        common(&new_files, &old_files),
+    );
+    if old_order != new_order {
+        changes.push(PlanChange::WorkflowOrder {
+            old: old_order.into_iter().cloned().collect(),
+            new: new_order.into_iter().cloned().collect(),
+        });
+    }
+    for workflow in &plan.workflows {
+        if let Some(old) = baseline
+            .workflows
+            .iter()
+            .find(|old| old.file == workflow.file)
+        {
+            diff_workflow(old, workflow, &mut changes);
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tests::Cleanup;
+
+    const WORKFLOW: &str = r#"
+properties:
+  title: "Triage"
+  version: "1.0"
+launch_conditions:
+  os: ["windows", "linux", "macos"]
+actions:
+  - name: echo
+    type: command
+    attributes:
+      cmd: "echo"
+      args: ["triage"]
+workflow:
+  - action: echo
+  - action: echo
+    on_timeout: abort
+reporting:
+  zip_archive:
+    enabled: false
+    encryption:
+      enabled: false
+      public_key: ""
+      algorithm: None
+    compression:
+      enabled: false
+      size_limit: "0"
+  metadata:
+    mac_times: false
+    checksums: false
+    paths: false
+"#;
+
+    #[test]
+    fn test_plan_and_diff() {
+        let mut cleanup = Cleanup::new();
+        let tmp_dir = cleanup.tmp_dir("test_plan_and_diff");
+        let workflows = tmp_dir.join(WORKFLOWS_DIR);
+        std::fs::create_dir_all(workflows.join("linux")).unwrap();
+        std::fs::write(workflows.join("linux").join("triage.yaml"), WORKFLOW).unwrap();
+        std::fs::write(workflows.join("broken.yaml"), "properties:\n  title: x\n").unwrap();
+
+        let baseline = Plan::create(&tmp_dir);
+        assert_eq!(baseline.workflows.len(), 2);
+        let triage = baseline
+            .workflows
+            .iter()
+            .find(|workflow| workflow.file == "linux/triage.yaml")
+            .unwrap();
+        assert!(triage.error.is_none());
+        assert_eq!(triage.steps.len(), 2);
+        assert_eq!(triage.steps[0].action_type, "command");
+        assert_eq!(triage.steps[1].on_error_categories["on_timeout"], "abort");
+        assert_eq!(triage.steps[0].attributes["args"][0], "triage");
+        assert!(baseline
+            .workflows
+            .iter()
+            .any(|workflow| workflow.file == "broken.yaml" && workflow.error.is_some()));
+
+        // the plan survives the round trip through JSON without changes
+        let baseline: Plan = serde_json::from_str(&baseline.to_json().unwrap()).unwrap();
+        assert!(diff_plans(&baseline, &Plan::create(&tmp_dir)).is_empty());
+
+        std::fs::remove_file(workflows.join("broken.yaml")).unwrap();
+        std::fs::write(
+            workflows.join("linux").join("triage.yaml"),
+            WORKFLOW
+                .replace("[\"triage\"]", "[\"triage\", \"-v\"]")
+                .replace("  - action: echo\n    on_timeout: abort\n", "")
+                .replace("checksums: false", "checksums: true"),
+        )
+        .unwrap();
+        let changes: Vec<String> = diff_plans(&baseline, &Plan::create(&tmp_dir))
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "- workflow broken.yaml",
+                "- linux/triage.yaml: step \"echo#2\"",
+                "~ linux/triage.yaml: reporting.metadata.checksums: false -> true",
+                "~ linux/triage.yaml: step \"echo\": attributes.args: [\"triage\"] -> [\"triage\",\"-v\"]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use crate::action_log::{ActionLogEntry, ACTION_LOG_FILE};
+use config::workflow::{Algorithm, WorkflowRunner};
+use report::{
+    Report, ACTION_LOG_DIR, ENCRYPTION_PATH, LOOT_DIR, METADATA_PATH, README_PATH, STORAGE_DIR,
+    TOOLKIT_VERSION,
+};
+use std::{error::Error, fmt::Write, fs, path::PathBuf};
+use system::SystemVariables;
+
+/// Reads the entries of the structured action log. Broken lines are skipped
+pub fn read_action_log(report: &Report) -> Vec<ActionLogEntry> {
+    fs::read_to_string(report.action_log_dir.join(ACTION_LOG_FILE))
+        .map(|log| {
+            log.lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn property<'a>(runner: &'a WorkflowRunner, key: &str) -> &'a str {
+    runner
+        .properties
+        .get(key)
+        .map(|s| s.as_str())
+        .unwrap_or("-")
+}
+
+/// Human-readable summary of a report, so it can be understood without the toolkit
+pub fn render_readme(
+    runner: &WorkflowRunner,
+    system_variables: &SystemVariables,
+    started: &str,
+    finished: &str,
+    aborted: Option<&str>,
+    entries: &[ActionLogEntry],
+) -> String {
+    let mut readme = String::new();
+    let failed = entries.iter().filter(|entry| !entry.success).count();
+    let zip_archive = &runner.reporting.zip_archive;
+    let encrypted = zip_archive.enabled
+        && zip_archive.encryption.enabled
+        && zip_archive.encryption.algorithm != Algorithm::None;
+
+    // writing to a String can't fail
+    let _ = writeln!(readme, "IR Toolkit Report");
+    let _ = writeln!(readme, "=================");
+    let _ = writeln!(readme);
+    let _ = writeln!(readme, "Device:          {}", system_variables.device_name);
+    let _ = writeln!(
+        readme,
+        "System:          {} {} ({})",
+        system_variables.os, system_variables.arch, system_variables.distro
+    );
+    let _ = writeln!(
+        readme,
+        "User:            {} (elevated: {})",
+        system_variables.user, system_variables.is_elevated
+    );
+    let _ = writeln!(
+        readme,
+        "Workflow:        {} (version {}, schema version {})",
+        property(runner, "title"),
+        property(runner, "version"),
+        runner.schema_version
+    );
+    if let Some(description) = runner.properties.get("description") {
+        let _ = writeln!(readme, "Description:     {}", description);
+    }
+    let _ = writeln!(readme, "Started:         {}", started);
+    let _ = writeln!(readme, "Finished:        {}", finished);
+    let _ = writeln!(readme, "Run ID:          {}", system_variables.run_id);
+    if let Some(reason) = aborted {
+        let _ = writeln!(readme, "Aborted:         {}", reason);
+    }
+    let _ = writeln!(readme, "Toolkit version: {}", TOOLKIT_VERSION);
+    let _ = writeln!(readme);
+
+    let _ = writeln!(readme, "Actions ({} run, {} failed)", entries.len(), failed);
+    let _ = writeln!(readme, "-------");
+    if entries.is_empty() {
+        let _ = writeln!(readme, "No actions were run.");
+    }
+    for entry in entries {
+        let status = match entry.success {
+            true => "OK",
+            false => "FAILED",
+        };
+        let _ = write!(
+            readme,
+            "{:>3}. [{:<6}] {} ({}, {:.1}s, output: {})",
+            entry.step,
+            status,
+            entry.action,
+            entry.action_type,
+            entry.execution_time,
+            entry.output_name
+        );
+        if let Some(exit_code) = entry.exit_code {
+            let _ = write!(readme, ", exit code {}", exit_code);
+        }
+        let _ = writeln!(readme);
+        if let Some(error) = &entry.error_message {
+            let _ = writeln!(readme, "       {}", error);
+        }
+    }
+    let _ = writeln!(readme);
+
+    let _ = writeln!(readme, "Contents");
+    let _ = writeln!(readme, "--------");
+    let _ = writeln!(
+        readme,
+        "{}/    Files created by the actions (e.g. captures or memory dumps)",
+        LOOT_DIR
+    );
+    let storage_name = match runner.reporting.dedup.enabled {
+        true => "the SHA-1 checksum of their content",
+        false => "the SHA-1 checksum of their original path",
+    };
+    let _ = writeln!(
+        readme,
+        "{}/  Collected files, named by {}",
+        STORAGE_DIR, storage_name
+    );
+    let _ = writeln!(
+        readme,
+        "{}/ Output of the actions and the status of each action ({})",
+        ACTION_LOG_DIR, ACTION_LOG_FILE
+    );
+    let _ = writeln!(
+        readme,
+        "{}    Original path, MAC times, size and SHA-1 checksum of each collected file",
+        METADATA_PATH
+    );
+    let _ = writeln!(readme);
+
+    let _ = writeln!(readme, "Verifying");
+    let _ = writeln!(readme, "---------");
+    match runner.reporting.metadata.checksums || runner.reporting.dedup.enabled {
+        true => {
+            let _ = writeln!(
+                readme,
+                "The column sha1_checksum of {} contains the SHA-1 checksum of each file at the\n\
+                 time of the collection. Compare it with the checksum of the file in {}/, e.g.\n\
+                 with sha1sum (Linux), shasum (macOS) or Get-FileHash -Algorithm SHA1 (Windows).\n\
+                 The unpacker verifies all checksums: unpacker -i <report directory> --verify-only",
+                METADATA_PATH, STORAGE_DIR
+            );
+        }
+        false => {
+            let _ = writeln!(
+                readme,
+                "Checksums were disabled for this collection (reporting.metadata.checksums)."
+            );
+        }
+    }
+    let _ = writeln!(readme);
+
+    let _ = writeln!(readme, "Decrypting");
+    let _ = writeln!(readme, "----------");
+    match encrypted {
+        true => {
+            let _ = writeln!(
+                readme,
+                "The archive was encrypted with {} using a random key. The key is stored in\n\
+                 {} next to the archive, encrypted with the RSA public key {}.\n\
+                 Decrypt and unpack it with the unpacker (version {} or later) and the private key:\n\
+                 unpacker -i <report directory> -k <private key> --restore",
+                zip_archive.encryption.algorithm,
+                ENCRYPTION_PATH,
+                zip_archive.encryption.public_key,
+                TOOLKIT_VERSION
+            );
+        }
+        false => {
+            let _ = writeln!(readme, "The report was not encrypted.");
+        }
+    }
+    readme
+}
+
+/// Writes the README into the report directory, it is added to the archive by the file processor
+pub fn write_readme(
+    report: &Report,
+    runner: &WorkflowRunner,
+    system_variables: &SystemVariables,
+    started: &str,
+    finished: &str,
+    aborted: Option<&str>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let entries = read_action_log(report);
+    let readme = render_readme(
+        runner,
+        system_variables,
+        started,
+        finished,
+        aborted,
+        &entries,
+    );
+    let path = report.dir.join(README_PATH);
+    fs::write(&path, readme)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actions::ActionResult;
+    use config::workflow::Action;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render_readme() {
+        let yaml = r#"
+        properties:
+          title: "Triage"
+          version: "2.1"
+        launch_conditions:
+          os: ["linux"]
+        actions:
+          - name: "whoami"
+            type: "command"
+            attributes:
+              cmd: "whoami"
+        workflow: []
+        reporting:
+          zip_archive:
+            enabled: true
+            encryption:
+              enabled: true
+              public_key: "public.pem"
+              algorithm: "AES-128-GCM"
+            compression:
+              enabled: false
+              size_limit: "0 B"
+          metadata:
+            mac_times: true
+            checksums: true
+            paths: true
+        "#;
+        let runner: WorkflowRunner = serde_yaml::from_str(yaml).unwrap();
+        let action: &Action = &runner.actions[0];
+        let failed = ActionResult {
+            success: false,
+            exit_code: Some(1),
+            execution_time: Duration::from_millis(1500),
+            error_message: Some("Access denied".to_string()),
+            parallel: false,
+            finished: true,
+            error_category: None,
+            ..Default::default()
+        };
+        let entries = vec![ActionLogEntry::new(1, action, "whoami").finish(&failed)];
+
+        let readme = render_readme(
+            &runner,
+            &SystemVariables::new(),
+            "2026-01-01T10:00:00Z",
+            "2026-01-01T10:05:00Z",
+            Some("Action \"whoami\" failed"),
+            &entries,
+        );
+        assert!(readme.contains("Triage (version 2.1, schema version 1)"));
+        assert!(readme.contains("Aborted:         Action \"whoami\" failed"));
+        assert!(readme.contains("Actions (1 run, 1 failed)"));
+        assert!(readme.contains("1. [FAILED] whoami (command, 1.5s, output: whoami), exit code 1"));
+        assert!(readme.contains("Access denied"));
+        assert!(readme.contains("--verify-only"));
+        assert!(readme.contains("encrypted with AES-128-GCM"));
+    }
+}
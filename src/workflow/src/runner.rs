@@ -1,23 +1,137 @@
+use crate::action_log::{unique_output_name, ActionLog, ActionLogEntry};
+use crate::heartbeat::{self, HeartbeatState};
+use crate::operator_audit;
+use crate::summary::{render_table, write_summary};
+use actions::integrity::CustomFilesIntegrity;
 use actions::{
-    binary, command, store, terminal, waiting_result, yara, ActionOptions, ActionResult,
+    binary, command, defender, dns_ioc, error_result, etw_trace, fs_snapshot, grep, memory_files,
+    memory_image, netwatch, network_config, pcap, recycle_bin, registry, shell_artifacts,
+    ssh_artifacts, store, terminal, usb_history, waiting_result, wmi_persistence, yara,
+    ActionOptions, ActionResult,
 };
+use chrono_tz::Tz;
 use config::workflow::{
-    read_workflow_file, ActionType, BinaryAttributes, CommandAttributes, OnError, StoreAttributes,
-    TerminalAttributes, WorkflowItem, WorkflowRunner, YaraAttributes,
+    read_workflow_file, ActionType, BinaryAttributes, CommandAttributes, DefenderAttributes,
+    DnsIocAttributes, EtwTraceAttributes, FsSnapshotAttributes, GrepAttributes,
+    MemoryFilesAttributes, MemoryImageAttributes, NetwatchAttributes, NetworkConfigAttributes,
+    OnError, PcapAttributes, RecycleBinAttributes, RegistryAttributes, ShellArtifactsAttributes,
+    SshArtifactsAttributes, StoreAttributes, TerminalAttributes, UsbHistoryAttributes,
+    WmiPersistenceAttributes, WorkflowItem, WorkflowRunner, YaraAttributes,
 };
 use futures::stream::FuturesUnordered;
-use futures::{executor::block_on, StreamExt};
-use log::{error, info};
+use futures::{executor::block_on, FutureExt, StreamExt};
+use log::{debug, error, info, warn};
 use report::Report;
-use std::{error::Error, future::Future, path::PathBuf, pin::Pin};
-use storage::FileProcessor;
-use system::SystemVariables;
-use utils::{misc::wait_for_user_input, sanitize::sanitize_dirname};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    error::Error,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use storage::{FileProcessor, StoredFile};
+use system::{
+    profiles::{get_user_profiles, UserProfile, USER_PROFILES},
+    SystemVariables,
+};
+use tokio::sync::Semaphore;
+use utils::{
+    messages::{self, PRESS_ANY_KEY_TO_CONTINUE},
+    misc::{panic_message, wait_for_user_input},
+    path_cache::PathCache,
+    sanitize::sanitize_dirname,
+};
+
+// Steps running in parallel, resolved once the action has finished
+type StepFuture = Pin<Box<dyn Future<Output = (WorkflowItem, ActionLogEntry, ActionResult)>>>;
+
+// Failed result of an action which panicked
+fn panicked_result(
+    action_name: &str,
+    payload: &(dyn Any + Send),
+    start_time: Instant,
+) -> ActionResult {
+    let message = format!(
+        "Action {} panicked: {}",
+        action_name,
+        panic_message(payload)
+    );
+    error!("{}", message);
+    error_result!(message, start_time)
+}
+
+// A panic of a step running in parallel only fails this step, not the remaining ones
+fn isolate_step(
+    future: StepFuture,
+    workflow_item: &WorkflowItem,
+    log_entry: &ActionLogEntry,
+    action_name: &str,
+) -> StepFuture {
+    let workflow_item = workflow_item.clone();
+    let log_entry = log_entry.clone();
+    let action_name = action_name.to_string();
+    let start_time = Instant::now();
+    Box::pin(async move {
+        match AssertUnwindSafe(future).catch_unwind().await {
+            Ok(step) => step,
+            Err(payload) => {
+                let result = panicked_result(&action_name, payload.as_ref(), start_time);
+                (
+                    workflow_item,
+                    log_entry,
+                    ActionResult {
+                        parallel: true,
+                        ..result
+                    },
+                )
+            }
+        }
+    })
+}
+
+// Waits for a free slot before the step starts, if the number of parallel steps is limited
+fn limit_step(future: StepFuture, limit: &Option<Arc<Semaphore>>) -> StepFuture {
+    let Some(limit) = limit.clone() else {
+        return future;
+    };
+    Box::pin(async move {
+        // the semaphore is never closed, so acquiring a permit can't fail
+        let _permit = limit.acquire_owned().await;
+        future.await
+    })
+}
 
 #[derive(Debug)]
+// re-exported by ir-toolkit, fields may be added in a minor version
+#[non_exhaustive]
 pub struct Workflow {
     pub runner: WorkflowRunner,
     pub current_step: usize,
+    pub failed_actions: usize,
+    // warnings of the finished actions, e.g. files that could not be stored
+    pub warnings: usize,
+    // the custom_files directory is checked once before the first binary action
+    pub defender_checked: bool,
+    // temporary Windows Defender exclusions, removed once the workflow has finished
+    pub defender_exclusions: Vec<String>,
+    // progress for heartbeat.json, if enabled
+    pub heartbeat: Option<Arc<Mutex<HeartbeatState>>>,
+    // resolved secrets of the config and the workflow, replaced like the system variables
+    pub secrets: HashMap<String, String>,
+    // of the timestamps in the report
+    pub time_zone: Tz,
+    // reason if a step stopped the workflow with abort_and_finalize
+    pub aborted: Option<String>,
+    // checked once at startup by the handler, shared by all workflows
+    pub custom_files_integrity: Arc<CustomFilesIntegrity>,
+    // parallel steps running at the same time, 0 for no limit
+    pub max_parallel_actions: usize,
+    // expansions of the glob patterns, shared by consecutive store, yara and grep steps
+    pub path_cache: Arc<PathCache>,
 }
 
 impl Workflow {
@@ -25,8 +139,19 @@ impl Workflow {
         let runner = read_workflow_file(yaml_path)?;
 
         Ok(Self {
-            runner: runner,
             current_step: 0,
+            failed_actions: 0,
+            warnings: 0,
+            defender_checked: false,
+            defender_exclusions: Vec::new(),
+            heartbeat: None,
+            secrets: HashMap::new(),
+            time_zone: runner.options.time_zone_or("UTC"),
+            aborted: None,
+            custom_files_integrity: Arc::new(CustomFilesIntegrity::default()),
+            max_parallel_actions: runner.options.max_parallel_actions.unwrap_or(0),
+            path_cache: Arc::new(PathCache::default()),
+            runner,
         })
     }
 
@@ -36,20 +161,70 @@ impl Workflow {
         report: &Report,
         system_variables: &SystemVariables,
         file_processor: &mut FileProcessor,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut stored_files: HashMap<String, Vec<StoredFile>> = HashMap::new();
+        let result = self
+            .run_steps(report, system_variables, file_processor, &mut stored_files)
+            .await;
+
+        if !self.defender_exclusions.is_empty() {
+            defender::remove_exclusions(&self.defender_exclusions);
+            self.defender_exclusions.clear();
+        }
+
+        // the summary also covers aborted workflows
+        let title = self
+            .runner
+            .properties
+            .get("title")
+            .cloned()
+            .unwrap_or_default();
+        match write_summary(report, &title, &stored_files) {
+            Ok(summary) => {
+                info!("Summary of workflow {:?}:", title);
+                for line in render_table(&summary) {
+                    info!("{}", line);
+                }
+            }
+            Err(e) => error!("Error writing workflow summary: {}", e),
+        }
+        if self.path_cache.hits() > 0 {
+            info!(
+                "Reused {} glob pattern expansions of previous actions",
+                self.path_cache.hits()
+            );
+        }
+        self.path_cache.clear();
+        result
+    }
+
+    async fn run_steps(
+        &mut self,
+        report: &Report,
+        system_variables: &SystemVariables,
+        file_processor: &mut FileProcessor<'_>,
+        stored_files: &mut HashMap<String, Vec<StoredFile>>,
     ) -> Result<(), Box<dyn Error>> {
         let num_steps = self.runner.workflow.len();
 
-        let mut futures: FuturesUnordered<
-            Pin<Box<dyn Future<Output = (WorkflowItem, ActionResult)>>>,
-        > = FuturesUnordered::new();
+        let mut futures: FuturesUnordered<StepFuture> = FuturesUnordered::new();
+        let action_log = ActionLog::new(
+            &report.action_log_dir,
+            &report.run_id,
+            self.runner.schema_version,
+            self.time_zone,
+        );
+        let mut output_names: HashSet<String> = HashSet::new();
+        let parallel_limit = (self.max_parallel_actions > 0)
+            .then(|| Arc::new(Semaphore::new(self.max_parallel_actions)));
 
-        while self.current_step < num_steps {
+        while self.current_step < num_steps && self.aborted.is_none() {
             let workflow_item = self.runner.workflow[self.current_step].clone();
 
-            let action: &mut config::workflow::Action = match self
+            let action: &config::workflow::Action = match self
                 .runner
                 .actions
-                .iter_mut() // Note: iter_mut to get a mutable reference
+                .iter()
                 .find(|action| action.name == workflow_item.action)
             {
                 Some(action) => action,
@@ -61,171 +236,579 @@ impl Workflow {
 
             let action_name = &action.name;
 
-            let options = ActionOptions {
-                timeout: workflow_item.timeout,
-                parallel: workflow_item.parallel,
-                start_time: std::time::Instant::now(),
-            };
+            // every step writes to its own files in action_output
+            let step = self.current_step + 1;
+            let start_time = std::time::Instant::now();
+            heartbeat::set_step(&self.heartbeat, step, action_name);
 
-            // iteralte over all attributes and replace placeholders with system variables
-            action.attributes.replace_vars(&system_variables.as_map());
-
-            //TODO: Normalize paths (e.g. forwards and backwards slashes)
-            let result: ActionResult = match action.action_type {
-                ActionType::Binary => {
-                    // convert action attributes to binary attributes
-                    let binary_attributes: BinaryAttributes = action.attributes.clone().into();
-                    info!("Running binary action: {}", action_name);
-
-                    // check if log to file is enabled
-                    let out_file: Option<PathBuf> = if binary_attributes.log_to_file {
-                        let sanitized_name = sanitize_dirname(action_name);
-                        Some(
-                            report
-                                .action_log_dir
-                                .join(format!("{}.log", sanitized_name)),
-                        )
-                    } else {
-                        None
-                    };
-
-                    let custom_files_dir = system_variables.custom_files_directory.clone();
-
-                    // check if we need to run in parallel
-                    // if so, add to the futures and run asynchronously
-                    // if not, wait for the result
-                    if options.parallel {
-                        let cloned_workflow_item = workflow_item.clone();
-                        let future: Pin<Box<dyn Future<Output = (WorkflowItem, ActionResult)>>> =
-                            Box::pin(async {
-                                (
-                                    cloned_workflow_item,
-                                    binary::Binary::run(
-                                        binary_attributes,
-                                        options,
-                                        out_file,
-                                        custom_files_dir,
-                                    )
-                                    .await,
-                                )
-                            });
-                        futures.push(future);
-                        waiting_result!()
-                    } else {
-                        block_on(binary::Binary::run(
-                            binary_attributes,
-                            options,
-                            out_file,
-                            custom_files_dir,
-                        ))
+            // the started process drops the privileges of the collector
+            let mut run_as = None;
+            if !workflow_item.run_as.is_empty() {
+                match privileges::resolve_user(&workflow_item.run_as) {
+                    Ok(user) => run_as = Some(user),
+                    Err(e) => {
+                        let output_name =
+                            unique_output_name(&mut output_names, &action.output_base_name(), step);
+                        let result = error_result!(
+                            format!("Failed to resolve user {:?}: {}", workflow_item.run_as, e),
+                            start_time
+                        );
+                        action_log.record(ActionLogEntry::new(step, action, &output_name), &result);
+                        if let Err(e) = self.handle_result(&result, &workflow_item) {
+                            error!("Error handling result: {}", e);
+                            return Err(e);
+                        }
+                        continue;
                     }
                 }
-                ActionType::Command => {
-                    // convert action attributes to command attributes
-                    let command_attributes: CommandAttributes = action.attributes.clone().into();
-                    info!("Running command action: {}", action_name);
-
-                    // check if log to file is enabled
-                    let out_file: Option<PathBuf> = if command_attributes.log_to_file {
-                        let sanitized_name = sanitize_dirname(action_name);
-                        Some(
-                            report
-                                .action_log_dir
-                                .join(format!("{}.log", sanitized_name)),
-                        )
-                    } else {
-                        None
-                    };
-
-                    // check if we need to run in parallel
-                    if options.parallel {
-                        let cloned_workflow_item = workflow_item.clone();
-                        let future: Pin<Box<dyn Future<Output = (WorkflowItem, ActionResult)>>> =
-                            Box::pin(async move {
-                                (
-                                    cloned_workflow_item,
-                                    command::ShellCommand::run(
-                                        command_attributes,
-                                        options,
-                                        out_file,
-                                    )
-                                    .await,
-                                )
-                            });
-                        futures.push(future);
-                        waiting_result!()
-                    } else {
-                        block_on(command::ShellCommand::run(
-                            command_attributes,
-                            options,
-                            out_file,
-                        ))
+            }
+
+            // other actions (e.g. a binary or the processes of parallel steps still running) may
+            // change the file system, so only consecutive store, yara and grep steps share the
+            // expansions of their glob patterns
+            let shares_paths = futures.is_empty()
+                && matches!(
+                    action.action_type,
+                    ActionType::Store | ActionType::Yara | ActionType::Grep
+                );
+            if !shares_paths {
+                self.path_cache.clear();
+            }
+
+            // a step with for_each runs once per user profile
+            let profiles: Vec<Option<UserProfile>> = match workflow_item.for_each.as_str() {
+                USER_PROFILES => {
+                    let profiles = get_user_profiles();
+                    if profiles.is_empty() {
+                        warn!("No user profiles found for action: {}", action_name);
                     }
+                    profiles.into_iter().map(Some).collect()
                 }
-                ActionType::Store => {
-                    // convert action attributes to store attributes
-                    let store_attributes: StoreAttributes = action.attributes.clone().into();
-                    info!("Running store action: {}", action_name);
+                _ => vec![None],
+            };
 
-                    store::Store::run(store_attributes, options, file_processor)
+            let mut results: Vec<ActionResult> = vec![];
+            for profile in profiles {
+                // iteralte over all attributes and replace placeholders with system variables
+                let mut variables = system_variables.as_map();
+                variables.extend(self.secrets.clone());
+                let mut base_name = action.output_base_name();
+                if let Some(profile) = &profile {
+                    info!(
+                        "Running action {} for user profile: {}",
+                        action_name, profile.name
+                    );
+                    variables.extend(profile.as_map());
+                    base_name = format!("{}_{}", base_name, sanitize_dirname(&profile.name));
                 }
-                ActionType::Terminal => {
-                    // convert action attributes to terminal attributes
-                    let terminal_attributes: TerminalAttributes = action.attributes.clone().into();
-                    info!("Running terminal action: {}", action_name);
-
-                    // check if transcript is enabled
-                    let out_file: Option<PathBuf> = if terminal_attributes.enable_transcript {
-                        let sanitized_name = sanitize_dirname(action_name);
-                        Some(
-                            report
-                                .action_log_dir
-                                .join(format!("{}_transcript.log", sanitized_name)),
-                        )
-                    } else {
-                        None
-                    };
-
-                    // check if we need to run in parallel
-                    if options.parallel {
-                        let cloned_workflow_item = workflow_item.clone();
-                        let future: Pin<Box<dyn Future<Output = (WorkflowItem, ActionResult)>>> =
-                            Box::pin(async move {
-                                (
-                                    cloned_workflow_item,
-                                    terminal::Terminal::run(terminal_attributes, options, out_file)
+                let mut attributes = action.attributes.clone();
+                attributes.replace_vars(&variables);
+                attributes.normalize_paths(cfg!(target_os = "windows"));
+
+                let output_name = unique_output_name(&mut output_names, &base_name, step);
+                let log_entry = ActionLogEntry::new(step, action, &output_name);
+
+                let options = ActionOptions {
+                    timeout: workflow_item.timeout,
+                    parallel: workflow_item.parallel,
+                    start_time: std::time::Instant::now(),
+                    run_as: run_as.clone(),
+                    output_key: file_processor.output_key(),
+                    custom_files_integrity: Some(self.custom_files_integrity.clone()),
+                    path_cache: shares_paths.then(|| self.path_cache.clone()),
+                };
+
+                // files stored by this run are listed in the summary
+                let stored_before = file_processor.stored_files().len();
+
+                // a panicking action fails like any other action, so the workflow and the report continue
+                let run_action =
+                    panic::catch_unwind(AssertUnwindSafe(|| match action.action_type {
+                        ActionType::Binary => {
+                            // convert action attributes to binary attributes
+                            let binary_attributes: BinaryAttributes = attributes.clone().into();
+                            info!("Running binary action: {}", action_name);
+
+                            // check if log to file is enabled
+                            let out_file: Option<PathBuf> = if binary_attributes.log_to_file {
+                                Some(report.action_log_dir.join(format!("{}.log", output_name)))
+                            } else {
+                                None
+                            };
+
+                            let custom_files_dir = system_variables.custom_files_directory.clone();
+
+                            // real-time protection may quarantine the binaries before they run
+                            if !self.defender_checked && cfg!(target_os = "windows") {
+                                self.defender_checked = true;
+                                defender::warn_custom_files(&custom_files_dir);
+                            }
+
+                            // check if we need to run in parallel
+                            // if so, add to the futures and run asynchronously
+                            // if not, wait for the result
+                            if options.parallel {
+                                let cloned_workflow_item = workflow_item.clone();
+                                let cloned_log_entry = log_entry.clone();
+                                let future: StepFuture = Box::pin(async {
+                                    (
+                                        cloned_workflow_item,
+                                        cloned_log_entry,
+                                        binary::Binary::run(
+                                            binary_attributes,
+                                            options,
+                                            out_file,
+                                            custom_files_dir,
+                                        )
                                         .await,
+                                    )
+                                });
+                                futures.push(limit_step(
+                                    isolate_step(future, &workflow_item, &log_entry, action_name),
+                                    &parallel_limit,
+                                ));
+                                waiting_result!()
+                            } else {
+                                block_on(binary::Binary::run(
+                                    binary_attributes,
+                                    options,
+                                    out_file,
+                                    custom_files_dir,
+                                ))
+                            }
+                        }
+                        ActionType::Command => {
+                            // convert action attributes to command attributes
+                            let command_attributes: CommandAttributes = attributes.clone().into();
+                            info!("Running command action: {}", action_name);
+
+                            // check if log to file is enabled
+                            let out_file: Option<PathBuf> = if command_attributes.log_to_file {
+                                Some(report.action_log_dir.join(format!("{}.log", output_name)))
+                            } else {
+                                None
+                            };
+
+                            // check if we need to run in parallel
+                            if options.parallel {
+                                let cloned_workflow_item = workflow_item.clone();
+                                let cloned_log_entry = log_entry.clone();
+                                let future: StepFuture = Box::pin(async move {
+                                    (
+                                        cloned_workflow_item,
+                                        cloned_log_entry,
+                                        command::ShellCommand::run(
+                                            command_attributes,
+                                            options,
+                                            out_file,
+                                        )
+                                        .await,
+                                    )
+                                });
+                                futures.push(limit_step(
+                                    isolate_step(future, &workflow_item, &log_entry, action_name),
+                                    &parallel_limit,
+                                ));
+                                waiting_result!()
+                            } else {
+                                block_on(command::ShellCommand::run(
+                                    command_attributes,
+                                    options,
+                                    out_file,
+                                ))
+                            }
+                        }
+                        ActionType::Store => {
+                            // convert action attributes to store attributes
+                            let store_attributes: StoreAttributes = attributes.clone().into();
+                            info!("Running store action: {}", action_name);
+
+                            // outputs of the per file command, if configured
+                            let out_file = report
+                                .action_log_dir
+                                .join(format!("{}_per_file.csv", output_name));
+
+                            store::Store::run(
+                                store_attributes,
+                                options,
+                                out_file,
+                                &system_variables.custom_files_directory,
+                                file_processor,
+                            )
+                        }
+                        ActionType::Terminal => {
+                            // convert action attributes to terminal attributes
+                            let terminal_attributes: TerminalAttributes = attributes.clone().into();
+                            info!("Running terminal action: {}", action_name);
+
+                            // check if transcript is enabled
+                            let out_file: Option<PathBuf> = if terminal_attributes.enable_transcript
+                            {
+                                Some(
+                                    report
+                                        .action_log_dir
+                                        .join(format!("{}_transcript.log", output_name)),
                                 )
-                            });
-                        futures.push(future);
-                        waiting_result!()
-                    } else {
-                        block_on(terminal::Terminal::run(
-                            terminal_attributes,
-                            options,
-                            out_file,
-                        ))
-                    }
+                            } else {
+                                None
+                            };
+
+                            // each entered command line is logged separately
+                            let audit_file: Option<PathBuf> =
+                                match terminal_attributes.audit.enabled {
+                                    true => Some(
+                                        report
+                                            .action_log_dir
+                                            .join(format!("{}_commands.jsonl", output_name)),
+                                    ),
+                                    false => None,
+                                };
+
+                            // the session is documented in the operator audit log
+                            operator_audit::record(
+                                operator_audit::TERMINAL_STARTED,
+                                &terminal_attributes.shell,
+                                Some(action_name),
+                            );
+                            // a terminal in the background is not waited for
+                            let waits = terminal_attributes.wait;
+
+                            // check if we need to run in parallel
+                            if options.parallel {
+                                let cloned_workflow_item = workflow_item.clone();
+                                let cloned_log_entry = log_entry.clone();
+                                let terminal_action = action_name.to_string();
+                                let future: StepFuture = Box::pin(async move {
+                                    let result = terminal::Terminal::run(
+                                        terminal_attributes,
+                                        options,
+                                        out_file,
+                                        audit_file,
+                                    )
+                                    .await;
+                                    if waits {
+                                        operator_audit::terminal_stopped(&result, &terminal_action);
+                                    }
+                                    (cloned_workflow_item, cloned_log_entry, result)
+                                });
+                                futures.push(limit_step(
+                                    isolate_step(future, &workflow_item, &log_entry, action_name),
+                                    &parallel_limit,
+                                ));
+                                waiting_result!()
+                            } else {
+                                let result = block_on(terminal::Terminal::run(
+                                    terminal_attributes,
+                                    options,
+                                    out_file,
+                                    audit_file,
+                                ));
+                                if waits {
+                                    operator_audit::terminal_stopped(&result, action_name);
+                                }
+                                result
+                            }
+                        }
+                        ActionType::Yara => {
+                            // convert action attributes to yara attributes
+                            let yara_attributes: YaraAttributes = attributes.clone().into();
+                            info!("Running yara action: {}", action_name);
+
+                            // generate csv file name where the results will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            yara::Yara::run(
+                                yara_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                                &system_variables.custom_files_directory,
+                            )
+                        }
+                        ActionType::Registry => {
+                            // convert action attributes to registry attributes
+                            let registry_attributes: RegistryAttributes = attributes.clone().into();
+                            info!("Running registry action: {}", action_name);
+
+                            // generate csv file name where the values will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            registry::Registry::run(registry_attributes, options, out_file)
+                        }
+                        ActionType::RecycleBin => {
+                            // convert action attributes to recycle bin attributes
+                            let recycle_bin_attributes: RecycleBinAttributes =
+                                attributes.clone().into();
+                            info!("Running recycle bin action: {}", action_name);
+
+                            // generate csv file name where the deleted files will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            recycle_bin::RecycleBin::run(
+                                recycle_bin_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::ShellArtifacts => {
+                            // convert action attributes to shell artifacts attributes
+                            let shell_artifacts_attributes: ShellArtifactsAttributes =
+                                attributes.clone().into();
+                            info!("Running shell artifacts action: {}", action_name);
+
+                            // generate csv file name where the parsed records will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            shell_artifacts::ShellArtifacts::run(
+                                shell_artifacts_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::DnsIoc => {
+                            // convert action attributes to dns ioc attributes
+                            let dns_ioc_attributes: DnsIocAttributes = attributes.clone().into();
+                            info!("Running dns_ioc action: {}", action_name);
+
+                            // generate csv file name where the hits will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            dns_ioc::DnsIoc::run(
+                                dns_ioc_attributes,
+                                options,
+                                out_file,
+                                &system_variables.custom_files_directory,
+                            )
+                        }
+                        ActionType::Grep => {
+                            // convert action attributes to grep attributes
+                            let grep_attributes: GrepAttributes = attributes.clone().into();
+                            info!("Running grep action: {}", action_name);
+
+                            // generate csv file name where the hits will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            grep::Grep::run(
+                                grep_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                                &system_variables.custom_files_directory,
+                            )
+                        }
+                        ActionType::WmiPersistence => {
+                            // convert action attributes to wmi persistence attributes
+                            let wmi_attributes: WmiPersistenceAttributes =
+                                attributes.clone().into();
+                            info!("Running wmi_persistence action: {}", action_name);
+
+                            // generate json file name where the results will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+
+                            wmi_persistence::WmiPersistence::run(wmi_attributes, options, out_file)
+                        }
+                        ActionType::FsSnapshot => {
+                            // convert action attributes to fs snapshot attributes
+                            let snapshot_attributes: FsSnapshotAttributes =
+                                attributes.clone().into();
+                            info!("Running fs_snapshot action: {}", action_name);
+
+                            // the diff and the captured snapshot (usable as future baseline) are stored separately
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+                            let snapshot_file = report
+                                .action_log_dir
+                                .join(format!("{}_snapshot.json", output_name));
+
+                            fs_snapshot::FsSnapshot::run(
+                                snapshot_attributes,
+                                options,
+                                out_file,
+                                snapshot_file,
+                                &system_variables.custom_files_directory,
+                            )
+                        }
+                        ActionType::NetworkConfig => {
+                            // convert action attributes to network config attributes
+                            let network_attributes: NetworkConfigAttributes =
+                                attributes.clone().into();
+                            info!("Running network_config action: {}", action_name);
+
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+
+                            network_config::NetworkConfig::run(
+                                network_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::UsbHistory => {
+                            // convert action attributes to usb history attributes
+                            let usb_attributes: UsbHistoryAttributes = attributes.clone().into();
+                            info!("Running usb_history action: {}", action_name);
+
+                            // generate csv file name where the results will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            usb_history::UsbHistory::run(
+                                usb_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::EtwTrace => {
+                            // convert action attributes to etw trace attributes
+                            let etw_attributes: EtwTraceAttributes = attributes.clone().into();
+                            info!("Running etw_trace action: {}", action_name);
+
+                            // the trace file is named after the session, so a later stop step finds it
+                            let trace_file = report.loot_dir.join("etw").join(format!(
+                                "{}.etl",
+                                sanitize_dirname(&etw_attributes.session_name)
+                            ));
+
+                            etw_trace::EtwTrace::run(
+                                etw_attributes,
+                                options,
+                                trace_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::Pcap => {
+                            // convert action attributes to pcap attributes
+                            let pcap_attributes: PcapAttributes = attributes.clone().into();
+                            info!("Running pcap action: {}", action_name);
+
+                            // the capture files are stored in the loot directory, the summary as json
+                            let out_dir = report.loot_dir.join("pcap").join(&output_name);
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+
+                            pcap::Pcap::run(
+                                pcap_attributes,
+                                options,
+                                out_dir,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::MemoryFiles => {
+                            // convert action attributes to memory files attributes
+                            let memory_files_attributes: MemoryFilesAttributes =
+                                attributes.clone().into();
+                            info!("Running memory files action: {}", action_name);
+
+                            // the copies are stored in the loot directory, the summary as json
+                            let out_dir = report.loot_dir.join("memory_files").join(&output_name);
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+
+                            memory_files::MemoryFiles::run(
+                                memory_files_attributes,
+                                options,
+                                out_dir,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::MemoryImage => {
+                            // convert action attributes to memory image attributes
+                            let memory_image_attributes: MemoryImageAttributes =
+                                attributes.clone().into();
+                            info!("Running memory image action: {}", action_name);
+
+                            // the image is stored in the loot directory, the summary as json
+                            let out_dir = report.loot_dir.join("memory");
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+
+                            memory_image::MemoryImage::run(
+                                memory_image_attributes,
+                                options,
+                                out_dir,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::Defender => {
+                            // convert action attributes to defender attributes
+                            let defender_attributes: DefenderAttributes = attributes.clone().into();
+                            info!("Running defender action: {}", action_name);
+
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.json", output_name));
+
+                            defender::Defender::run(
+                                defender_attributes,
+                                options,
+                                out_file,
+                                &system_variables.custom_files_directory,
+                                &mut self.defender_exclusions,
+                            )
+                        }
+                        ActionType::SshArtifacts => {
+                            // convert action attributes to ssh artifacts attributes
+                            let ssh_attributes: SshArtifactsAttributes = attributes.clone().into();
+                            info!("Running ssh_artifacts action: {}", action_name);
+
+                            // generate csv file name where the parsed keys will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            ssh_artifacts::SshArtifacts::run(
+                                ssh_attributes,
+                                options,
+                                out_file,
+                                file_processor,
+                            )
+                        }
+                        ActionType::Netwatch => {
+                            // convert action attributes to netwatch attributes
+                            let netwatch_attributes: NetwatchAttributes = attributes.clone().into();
+                            info!("Running netwatch action: {}", action_name);
+
+                            // generate csv file name where the observed connections will be stored
+                            let out_file =
+                                report.action_log_dir.join(format!("{}.csv", output_name));
+
+                            netwatch::Netwatch::run(netwatch_attributes, options, out_file)
+                        }
+                    }));
+                let result = match run_action {
+                    Ok(result) => result,
+                    Err(payload) => panicked_result(action_name, payload.as_ref(), start_time),
+                };
+
+                // the metadata of the stored files survives a crash in a later action
+                if let Err(e) = file_processor.flush_metadata() {
+                    error!("Error flushing metadata: {}", e);
                 }
-                ActionType::Yara => {
-                    // convert action attributes to yara attributes
-                    let yara_attributes: YaraAttributes = action.attributes.clone().into();
-                    info!("Running yara action: {}", action_name);
-
-                    // generate csv file name where the results will be stored
-                    let out_file = report
-                        .action_log_dir
-                        .join(format!("{}.csv", sanitize_dirname(action_name)));
-
-                    yara::Yara::run(
-                        yara_attributes,
-                        options,
-                        out_file,
-                        file_processor,
-                        &system_variables.custom_files_directory,
-                    )
+
+                let stored = &file_processor.stored_files()[stored_before..];
+                if !stored.is_empty() {
+                    stored_files.insert(output_name.clone(), stored.to_vec());
                 }
-            };
+
+                // parallel steps are logged once they have finished
+                if result.finished {
+                    action_log.record(log_entry, &result);
+                }
+                results.push(result);
+            }
+            let result = ActionResult::combine(results, start_time);
 
             // handle
             match self.handle_result(&result, &workflow_item) {
@@ -240,7 +823,8 @@ impl Workflow {
         // join all futures
         if futures.len() > 0 {
             info!("Waiting for all remaining processes to finish");
-            while let Some((workflow_item, action_result)) = futures.next().await {
+            while let Some((workflow_item, log_entry, action_result)) = futures.next().await {
+                action_log.record(log_entry, &action_result);
                 match self.handle_result(&action_result, &workflow_item) {
                     Ok(_) => {}
                     Err(e) => {
@@ -265,10 +849,14 @@ impl Workflow {
             return Ok(());
         }
 
+        self.warnings += result.warnings.len();
+
+        // successful steps are listed in the summary at the end of the workflow
         if result.success {
-            info!("Action {:?} succeeded:\n{}", workflow_item.action, &result);
+            debug!("Action {:?} succeeded:\n{}", workflow_item.action, &result);
         } else {
             error!("Action {:?} failed:\n{}", workflow_item.action, &result);
+            self.failed_actions += 1;
         }
 
         // We don't need to handle the on_error if the action was run in parallel
@@ -282,12 +870,13 @@ impl Workflow {
         // 2. If an error occurred and on_error is set to goto, jump to the specified step
         // 3. If an error occurred and on_error is set to abort, stop the workflow
         // 4. If an error occurred and on_error is set to continue, continue to the next step
+        // A handler for the error category (e.g. on_timeout) takes precedence over on_error
         match result.success {
             true => {
                 self.current_step += 1;
             }
             false => {
-                match &workflow_item.on_error {
+                match workflow_item.on_error_for(result.error_category) {
                     OnError::Goto { goto } => {
                         info!("Action failed, jumping to step: {}", goto);
                         // search for the step with the specified name
@@ -310,6 +899,12 @@ impl Workflow {
                         error!("Action failed, aborting workflow");
                         return Err("Aborting workflow".into());
                     }
+                    // the remaining parallel steps are still awaited
+                    OnError::AbortAndFinalize => {
+                        error!("Action failed, stopping the workflow and finishing the report");
+                        self.aborted = Some(format!("Action {:?} failed", workflow_item.action));
+                        return Ok(());
+                    }
                     OnError::Continue => {
                         error!("Action failed, continuing to the next step");
                         self.current_step += 1;
@@ -320,9 +915,97 @@ impl Workflow {
 
         // Check if we have to wait for keypress to continue
         if workflow_item.continue_after_keypress {
-            wait_for_user_input("Press any key to continue with...");
+            let prompt = messages::text(PRESS_ANY_KEY_TO_CONTINUE);
+            let action = Some(workflow_item.action.as_str());
+            operator_audit::record(operator_audit::KEYPRESS_PROMPT, &prompt, action);
+            wait_for_user_input(&prompt);
+            operator_audit::record(operator_audit::KEYPRESS, "Continued", action);
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isolate_step() {
+        let workflow_item: WorkflowItem = serde_yaml::from_str("action: crashing").unwrap();
+        let log_entry: ActionLogEntry = serde_json::from_value(serde_json::json!({
+            "step": 1, "action": "crashing", "action_type": "command", "output_name": "crashing",
+            "started": "", "success": false, "exit_code": null, "execution_time": 0.0,
+            "error_message": null, "parallel": true, "schema_version": 1
+        }))
+        .unwrap();
+
+        let futures: FuturesUnordered<StepFuture> = FuturesUnordered::new();
+        let crashing: StepFuture = Box::pin(async { panic!("index out of bounds") });
+        futures.push(isolate_step(
+            crashing,
+            &workflow_item,
+            &log_entry,
+            "crashing",
+        ));
+        let item = workflow_item.clone();
+        let entry = log_entry.clone();
+        let working: StepFuture = Box::pin(async move { (item, entry, waiting_result!()) });
+        futures.push(isolate_step(working, &workflow_item, &log_entry, "working"));
+
+        // the panic fails its own step, the other step still finishes
+        let results: Vec<ActionResult> =
+            block_on(futures.map(|(_, _, result)| result).collect::<Vec<_>>());
+        assert_eq!(results.len(), 2);
+        let failed = results.iter().find(|result| !result.success).unwrap();
+        assert!(failed.parallel && failed.finished);
+        assert_eq!(
+            failed.error_message.as_deref(),
+            Some("Action crashing panicked: index out of bounds")
+        );
+        assert!(results.iter().any(|result| result.success));
+    }
+
+    #[test]
+    fn test_limit_step() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let workflow_item: WorkflowItem = serde_yaml::from_str("action: sleeping").unwrap();
+        let log_entry: ActionLogEntry = serde_json::from_value(serde_json::json!({
+            "step": 1, "action": "sleeping", "action_type": "command", "output_name": "sleeping",
+            "started": "", "success": false, "exit_code": null, "execution_time": 0.0,
+            "error_message": null, "parallel": true, "schema_version": 1
+        }))
+        .unwrap();
+
+        // the highest number of steps running at the same time
+        let running = Arc::new(AtomicUsize::new(0));
+        let highest = Arc::new(AtomicUsize::new(0));
+        let run = |limit: Option<Arc<Semaphore>>| {
+            let futures: FuturesUnordered<StepFuture> = FuturesUnordered::new();
+            for _ in 0..5 {
+                let (item, entry) = (workflow_item.clone(), log_entry.clone());
+                let (running, highest) = (running.clone(), highest.clone());
+                let step: StepFuture = Box::pin(async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    highest.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    (item, entry, waiting_result!())
+                });
+                futures.push(limit_step(step, &limit));
+            }
+            tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap()
+                .block_on(futures.collect::<Vec<_>>())
+                .len()
+        };
+
+        assert_eq!(run(Some(Arc::new(Semaphore::new(2)))), 5);
+        assert_eq!(highest.swap(0, Ordering::SeqCst), 2);
+        assert_eq!(run(None), 5);
+        assert_eq!(highest.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::action_log::{ActionLogEntry, ACTION_LOG_FILE};
+use crate::handler::TIME_DRIFT_FILE;
+use crate::operator_audit::OPERATOR_AUDIT_FILE;
+use crate::readme::read_action_log;
+use report::{Report, ACTION_LOG_DIR};
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+use storage::StoredFile;
+
+// Name of the run summary inside action_output
+pub const SUMMARY_FILE: &str = "summary.json";
+
+// files some actions write next to [output_name].[ext], e.g. whoami_transcript.log
+const OUTPUT_SUFFIXES: [&str; 3] = ["transcript", "commands", "snapshot"];
+
+#[derive(Debug, Serialize)]
+pub struct StepSummary {
+    pub step: usize,
+    pub action: String,
+    pub action_type: String,
+    pub output_name: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub error_category: Option<String>,
+    pub duration: f64,
+    // counts reported by the action, e.g. scanned files or observed connections
+    pub items_processed: Option<u64>,
+    pub bytes_processed: Option<u64>,
+    pub warnings: Vec<String>,
+    // size of the output files, before compression
+    pub bytes_collected: u64,
+    // paths inside the report, e.g. action_output/whoami.log or files/[checksum]
+    pub output_files: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkflowSummary {
+    pub workflow: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub warnings: usize,
+    pub bytes_collected: u64,
+    pub steps: Vec<StepSummary>,
+}
+
+fn is_output_of(file_name: &str, output_name: &str) -> bool {
+    let rest = match file_name.strip_prefix(output_name) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    if rest.starts_with('.') {
+        return true;
+    }
+    OUTPUT_SUFFIXES
+        .iter()
+        .any(|suffix| rest.starts_with(&format!("_{}.", suffix)))
+}
+
+/// Files in action_output and their size
+fn list_action_outputs(action_log_dir: &Path) -> Vec<(String, u64)> {
+    let entries = match fs::read_dir(action_log_dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            (entry.file_name().to_string_lossy().to_string(), size)
+        })
+        .filter(|(name, _)| {
+            ![
+                ACTION_LOG_FILE,
+                SUMMARY_FILE,
+                OPERATOR_AUDIT_FILE,
+                TIME_DRIFT_FILE,
+            ]
+            .contains(&name.as_str())
+        })
+        .collect()
+}
+
+/// Combines the action log with the files each step wrote to action_output and
+/// the files it stored through the file processor (by output name)
+pub fn build_summary(
+    workflow: &str,
+    entries: &[ActionLogEntry],
+    action_outputs: &[(String, u64)],
+    stored_files: &HashMap<String, Vec<StoredFile>>,
+) -> WorkflowSummary {
+    let steps: Vec<StepSummary> = entries
+        .iter()
+        .map(|entry| {
+            let mut output_files: Vec<(String, u64)> = action_outputs
+                .iter()
+                .filter(|(name, _)| is_output_of(name, &entry.output_name))
+                .map(|(name, size)| (format!("{}/{}", ACTION_LOG_DIR, name), *size))
+                .collect();
+            output_files.sort();
+            if let Some(stored) = stored_files.get(&entry.output_name) {
+                output_files.extend(stored.iter().map(|file| (file.name.clone(), file.size)));
+            }
+            StepSummary {
+                step: entry.step,
+                action: entry.action.clone(),
+                action_type: entry.action_type.clone(),
+                output_name: entry.output_name.clone(),
+                success: entry.success,
+                exit_code: entry.exit_code,
+                error_category: entry.error_category.clone(),
+                duration: entry.execution_time,
+                items_processed: entry.items_processed,
+                bytes_processed: entry.bytes,
+                warnings: entry.warnings.clone(),
+                bytes_collected: output_files.iter().map(|(_, size)| size).sum(),
+                output_files: output_files.into_iter().map(|(name, _)| name).collect(),
+            }
+        })
+        .collect();
+
+    WorkflowSummary {
+        workflow: workflow.to_string(),
+        succeeded: steps.iter().filter(|step| step.success).count(),
+        failed: steps.iter().filter(|step| !step.success).count(),
+        warnings: steps.iter().map(|step| step.warnings.len()).sum(),
+        bytes_collected: steps.iter().map(|step| step.bytes_collected).sum(),
+        steps,
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
+
+/// One line per step, aligned to be read in the log
+pub fn render_table(summary: &WorkflowSummary) -> Vec<String> {
+    let width = summary
+        .steps
+        .iter()
+        .map(|step| step.action.len())
+        .chain(std::iter::once("Action".len()))
+        .max()
+        .unwrap_or(0);
+    let mut lines = vec![format!(
+        "{:>4}  {:<width$}  {:<14}  {:<24}  {:>9}  {:>10}  {:>5}",
+        "Step", "Action", "Type", "Result", "Duration", "Collected", "Files"
+    )];
+    for step in &summary.steps {
+        let result = match (step.success, &step.error_category) {
+            (true, _) => "OK".to_string(),
+            (false, Some(category)) => format!("FAILED ({})", category),
+            (false, None) => "FAILED".to_string(),
+        };
+        lines.push(format!(
+            "{:>4}  {:<width$}  {:<14}  {:<24}  {:>8.1}s  {:>10}  {:>5}",
+            step.step,
+            step.action,
+            step.action_type,
+            result,
+            step.duration,
+            format_bytes(step.bytes_collected),
+            step.output_files.len()
+        ));
+    }
+    let mut totals = format!(
+        "{} succeeded, {} failed, {} collected",
+        summary.succeeded,
+        summary.failed,
+        format_bytes(summary.bytes_collected)
+    );
+    if summary.warnings > 0 {
+        totals.push_str(&format!(", {} warnings", summary.warnings));
+    }
+    lines.push(totals);
+    lines
+}
+
+/// Writes the summary of all finished steps into action_output
+pub fn write_summary(
+    report: &Report,
+    workflow: &str,
+    stored_files: &HashMap<String, Vec<StoredFile>>,
+) -> Result<WorkflowSummary, Box<dyn Error>> {
+    let entries = read_action_log(report);
+    let action_outputs = list_action_outputs(&report.action_log_dir);
+    let summary = build_summary(workflow, &entries, &action_outputs, stored_files);
+    fs::write(
+        report.action_log_dir.join(SUMMARY_FILE),
+        serde_json::to_string_pretty(&summary)?,
+    )?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actions::{error_result, ActionResult, ErrorCategory};
+    use config::workflow::{Action, WorkflowRunner};
+
+    #[test]
+    fn test_build_summary() {
+        let yaml = r#"
+        properties:
+          title: "Triage"
+        launch_conditions:
+          os: ["linux"]
+        actions:
+          - name: "shell"
+            type: "terminal"
+            attributes:
+              wait: true
+          - name: "collect"
+            type: "store"
+            attributes:
+              patterns: "/var/log/*.log"
+        workflow: []
+        reporting:
+          zip_archive:
+            enabled: false
+            encryption:
+              enabled: false
+              public_key: ""
+              algorithm: "None"
+            compression:
+              enabled: false
+              size_limit: "0 B"
+          metadata:
+            mac_times: true
+            checksums: true
+            paths: true
+        "#;
+        let runner: WorkflowRunner = serde_yaml::from_str(yaml).unwrap();
+        let terminal: &Action = &runner.actions[0];
+        let store: &Action = &runner.actions[1];
+        let timeout = error_result!("Process timed out").with_category(ErrorCategory::Timeout);
+        let success = ActionResult {
+            finished: true,
+            ..Default::default()
+        };
+        let entries = vec![
+            ActionLogEntry::new(1, terminal, "shell").finish(&timeout),
+            ActionLogEntry::new(2, store, "collect").finish(
+                &ActionResult {
+                    finished: true,
+                    ..Default::default()
+                }
+                .with_items(1)
+                .with_bytes(2048)
+                .with_warnings(vec!["/etc/shadow: permission denied".to_string()]),
+            ),
+            ActionLogEntry::new(3, terminal, "shell_step3").finish(&success),
+        ];
+        let action_outputs = vec![
+            ("shell_transcript.log".to_string(), 100),
+            ("shell_commands.jsonl".to_string(), 20),
+            ("shell_step3.log".to_string(), 5),
+            ("shell_step3_transcript.log".to_string(), 7),
+        ];
+        let mut stored_files = HashMap::new();
+        stored_files.insert(
+            "collect".to_string(),
+            vec![StoredFile {
+                name: "files/abc".to_string(),
+                size: 2048,
+            }],
+        );
+
+        let summary = build_summary("Triage", &entries, &action_outputs, &stored_files);
+        assert_eq!((summary.succeeded, summary.failed), (2, 1));
+        assert_eq!(summary.bytes_collected, 100 + 20 + 5 + 7 + 2048);
+        // the outputs of step 3 don't belong to step 1
+        assert_eq!(
+            summary.steps[0].output_files,
+            vec![
+                "action_output/shell_commands.jsonl",
+                "action_output/shell_transcript.log"
+            ]
+        );
+        assert_eq!(summary.steps[0].error_category, Some("timeout".to_string()));
+        assert_eq!(summary.steps[1].output_files, vec!["files/abc"]);
+        assert_eq!(summary.steps[1].items_processed, Some(1));
+        assert_eq!(summary.steps[1].bytes_processed, Some(2048));
+        assert_eq!(summary.warnings, 1);
+        assert_eq!(summary.steps[2].bytes_collected, 12);
+
+        let table = render_table(&summary);
+        assert_eq!(table.len(), 5);
+        assert!(table[1].contains("FAILED (timeout)"));
+        assert!(table[2].contains("2.0 KB"));
+        assert_eq!(
+            table[4],
+            "2 succeeded, 1 failed, 2.1 KB collected, 1 warnings"
+        );
+    }
+}
@@ -1,3 +1,13 @@
+pub mod action_log;
+pub mod endpoint_summary;
+pub mod failure;
 pub mod handler;
+pub mod heartbeat;
 pub mod launch_conditions;
+pub mod notifier;
+pub mod operator_audit;
+pub mod plan;
+pub mod readme;
 pub mod runner;
+pub mod summary;
+pub mod uploader;